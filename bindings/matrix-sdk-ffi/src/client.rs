@@ -1,6 +1,7 @@
 use std::sync::{Arc, RwLock};
 
 use anyhow::{anyhow, Context};
+use futures_util::{pin_mut, StreamExt};
 use matrix_sdk::{
     media::{MediaFileHandle as SdkMediaFileHandle, MediaFormat, MediaRequest, MediaThumbnailSize},
     oidc::{
@@ -16,7 +17,10 @@ use matrix_sdk::{
         api::client::{
             account::whoami,
             media::get_content_thumbnail::v3::Method,
-            push::{EmailPusherData, PusherIds, PusherInit, PusherKind as RumaPusherKind},
+            push::{
+                EmailPusherData, Pusher as RumaPusher, PusherIds, PusherInit,
+                PusherKind as RumaPusherKind,
+            },
             room::{create_room, Visibility},
             session::get_login_types,
             user_directory::search_users,
@@ -30,7 +34,7 @@ use matrix_sdk::{
         serde::Raw,
         EventEncryptionAlgorithm, TransactionId, UInt, UserId,
     },
-    AuthApi, AuthSession, Client as MatrixClient, SessionChange,
+    AuthApi, AuthSession, Client as MatrixClient, SessionCallbackError, SessionChange,
 };
 use ruma::{
     api::client::discovery::discover_homeserver::AuthenticationServerInfo,
@@ -45,7 +49,7 @@ use url::Url;
 use super::{room::Room, session_verification::SessionVerificationController, RUNTIME};
 use crate::{
     client, notification::NotificationClientBuilder, notification_settings::NotificationSettings,
-    sync_service::SyncServiceBuilder, ClientError,
+    sync_service::SyncServiceBuilder, ClientError, TaskHandle,
 };
 
 #[derive(Clone, uniffi::Record)]
@@ -60,6 +64,12 @@ impl From<PusherIdentifiers> for PusherIds {
     }
 }
 
+impl From<PusherIds> for PusherIdentifiers {
+    fn from(value: PusherIds) -> Self {
+        Self { pushkey: value.pushkey, app_id: value.app_id }
+    }
+}
+
 #[derive(Clone, uniffi::Record)]
 pub struct HttpPusherData {
     pub url: String,
@@ -95,6 +105,55 @@ impl TryFrom<PusherKind> for RumaPusherKind {
     }
 }
 
+impl TryFrom<RumaPusherKind> for PusherKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: RumaPusherKind) -> anyhow::Result<Self> {
+        match value {
+            RumaPusherKind::Http(data) => {
+                let default_payload = if data.default_payload.is_null() {
+                    None
+                } else {
+                    Some(serde_json::to_string(&data.default_payload)?)
+                };
+                let format = match data.format {
+                    Some(RumaPushFormat::EventIdOnly) => Some(PushFormat::EventIdOnly),
+                    _ => None,
+                };
+                Ok(Self::Http { data: HttpPusherData { url: data.url, format, default_payload } })
+            }
+            RumaPusherKind::Email(_) => Ok(Self::Email),
+            _ => Err(anyhow!("Unsupported pusher kind")),
+        }
+    }
+}
+
+/// A pusher registered for the account.
+#[derive(Clone, uniffi::Record)]
+pub struct Pusher {
+    pub identifiers: PusherIdentifiers,
+    pub kind: PusherKind,
+    pub app_display_name: String,
+    pub device_display_name: String,
+    pub profile_tag: Option<String>,
+    pub lang: String,
+}
+
+impl TryFrom<RumaPusher> for Pusher {
+    type Error = anyhow::Error;
+
+    fn try_from(value: RumaPusher) -> anyhow::Result<Self> {
+        Ok(Self {
+            identifiers: value.ids.into(),
+            kind: value.kind.try_into()?,
+            app_display_name: value.app_display_name,
+            device_display_name: value.device_display_name,
+            profile_tag: value.profile_tag,
+            lang: value.lang,
+        })
+    }
+}
+
 #[derive(Clone, uniffi::Enum)]
 pub enum PushFormat {
     EventIdOnly,
@@ -108,12 +167,43 @@ impl From<PushFormat> for RumaPushFormat {
     }
 }
 
+#[derive(Clone, uniffi::Enum)]
+pub enum AppState {
+    Foreground,
+    Background,
+}
+
+impl From<AppState> for matrix_sdk::AppState {
+    fn from(value: AppState) -> Self {
+        match value {
+            AppState::Foreground => Self::Foreground,
+            AppState::Background => Self::Background,
+        }
+    }
+}
+
 #[uniffi::export(callback_interface)]
 pub trait ClientDelegate: Sync + Send {
     fn did_receive_auth_error(&self, is_soft_logout: bool);
     fn did_refresh_tokens(&self);
 }
 
+/// Shares the session with other processes using the same session, see
+/// [`Client::set_session_delegate()`].
+#[uniffi::export(callback_interface)]
+pub trait ClientSessionDelegate: Sync + Send {
+    /// Load the latest session of the given user from the shared storage.
+    fn retrieve_session_from_keychain(&self, user_id: String) -> Result<Session, ClientError>;
+    /// Persist the session in the shared storage after its tokens were
+    /// refreshed.
+    fn save_session_in_keychain(&self, session: Session);
+}
+
+#[uniffi::export(callback_interface)]
+pub trait IgnoredUsersListener: Sync + Send {
+    fn call(&self, ignored_user_ids: Vec<String>);
+}
+
 #[uniffi::export(callback_interface)]
 pub trait ProgressWatcher: Send + Sync {
     fn transmission_progress(&self, progress: TransmissionProgress);
@@ -237,61 +327,10 @@ impl Client {
 
     /// Restores the client from a `Session`.
     pub fn restore_session(&self, session: Session) -> Result<(), ClientError> {
-        let Session {
-            access_token,
-            refresh_token,
-            user_id,
-            device_id,
-            homeserver_url: _,
-            oidc_data,
-            sliding_sync_proxy,
-        } = session;
+        let sliding_sync_proxy = session.sliding_sync_proxy.clone();
+        let auth_session: AuthSession = session.try_into()?;
 
-        if let Some(oidc_data) = oidc_data {
-            // Restore using an OIDC FullSession.
-            let oidc_data = serde_json::from_str::<OidcUnvalidatedSessionData>(&oidc_data)?
-                .validate()
-                .context("OIDC metadata validation failed.")?;
-            let latest_id_token = oidc_data
-                .latest_id_token
-                .map(TryInto::try_into)
-                .transpose()
-                .context("OIDC latest_id_token is invalid.")?;
-
-            let user_session = matrix_sdk::oidc::UserSession {
-                meta: matrix_sdk::SessionMeta {
-                    user_id: user_id.try_into()?,
-                    device_id: device_id.into(),
-                },
-                tokens: matrix_sdk::oidc::SessionTokens {
-                    access_token,
-                    refresh_token,
-                    latest_id_token,
-                },
-                issuer_info: oidc_data.issuer_info,
-            };
-
-            let session = FullSession {
-                client: RegisteredClientData {
-                    credentials: ClientCredentials::None { client_id: oidc_data.client_id },
-                    metadata: oidc_data.client_metadata,
-                },
-                user: user_session,
-            };
-
-            self.restore_session_inner(session)?;
-        } else {
-            // Restore using a regular Matrix Session.
-            let session = matrix_sdk::matrix_auth::Session {
-                meta: matrix_sdk::SessionMeta {
-                    user_id: user_id.try_into()?,
-                    device_id: device_id.into(),
-                },
-                tokens: matrix_sdk::matrix_auth::SessionTokens { access_token, refresh_token },
-            };
-
-            self.restore_session_inner(session)?;
-        }
+        self.restore_session_inner(auth_session)?;
 
         if let Some(sliding_sync_proxy) = sliding_sync_proxy {
             let sliding_sync_proxy = Url::parse(&sliding_sync_proxy)
@@ -316,6 +355,67 @@ impl Client {
         })
     }
 
+    pub(crate) async fn session_inner(client: MatrixClient) -> Result<Session, ClientError> {
+        let auth_api = client.auth_api().context("Missing authentication API")?;
+
+        let homeserver_url = client.homeserver().await.into();
+        let sliding_sync_proxy = client.sliding_sync_proxy().map(|url| url.to_string());
+
+        match auth_api {
+            // Build the session from the regular Matrix Auth Session.
+            AuthApi::Matrix(a) => {
+                let matrix_sdk::matrix_auth::Session {
+                    meta: matrix_sdk::SessionMeta { user_id, device_id },
+                    tokens: matrix_sdk::matrix_auth::SessionTokens { access_token, refresh_token },
+                } = a.session().context("Missing session")?;
+
+                Ok(Session {
+                    access_token,
+                    refresh_token,
+                    user_id: user_id.to_string(),
+                    device_id: device_id.to_string(),
+                    homeserver_url,
+                    oidc_data: None,
+                    sliding_sync_proxy,
+                })
+            }
+            // Build the session from the OIDC UserSession.
+            AuthApi::Oidc(api) => {
+                let matrix_sdk::oidc::UserSession {
+                    meta: matrix_sdk::SessionMeta { user_id, device_id },
+                    tokens:
+                        matrix_sdk::oidc::SessionTokens { access_token, refresh_token, latest_id_token },
+                    issuer_info,
+                } = api.user_session().context("Missing session")?;
+                let client_id = api
+                    .client_credentials()
+                    .context("OIDC client credentials are missing.")?
+                    .client_id()
+                    .to_owned();
+                let client_metadata =
+                    api.client_metadata().context("OIDC client metadata is missing.")?.clone();
+                let oidc_data = OidcSessionData {
+                    client_id,
+                    client_metadata,
+                    latest_id_token: latest_id_token.map(|t| t.to_string()),
+                    issuer_info,
+                };
+
+                let oidc_data = serde_json::to_string(&oidc_data).ok();
+                Ok(Session {
+                    access_token,
+                    refresh_token,
+                    user_id: user_id.to_string(),
+                    device_id: device_id.to_string(),
+                    homeserver_url,
+                    oidc_data,
+                    sliding_sync_proxy,
+                })
+            }
+            _ => Err(anyhow!("Unknown authentication API").into()),
+        }
+    }
+
     pub(crate) async fn async_homeserver(&self) -> String {
         self.inner.homeserver().await.to_string()
     }
@@ -360,72 +460,42 @@ impl Client {
     }
 
     pub fn session(&self) -> Result<Session, ClientError> {
-        RUNTIME.block_on(async move {
-            let auth_api = self.inner.auth_api().context("Missing authentication API")?;
-
-            let homeserver_url = self.inner.homeserver().await.into();
-            let sliding_sync_proxy =
-                self.discovered_sliding_sync_proxy().map(|url| url.to_string());
-
-            match auth_api {
-                // Build the session from the regular Matrix Auth Session.
-                AuthApi::Matrix(a) => {
-                    let matrix_sdk::matrix_auth::Session {
-                        meta: matrix_sdk::SessionMeta { user_id, device_id },
-                        tokens:
-                            matrix_sdk::matrix_auth::SessionTokens { access_token, refresh_token },
-                    } = a.session().context("Missing session")?;
-
-                    Ok(Session {
-                        access_token,
-                        refresh_token,
-                        user_id: user_id.to_string(),
-                        device_id: device_id.to_string(),
-                        homeserver_url,
-                        oidc_data: None,
-                        sliding_sync_proxy,
-                    })
-                }
-                // Build the session from the OIDC UserSession.
-                AuthApi::Oidc(api) => {
-                    let matrix_sdk::oidc::UserSession {
-                        meta: matrix_sdk::SessionMeta { user_id, device_id },
-                        tokens:
-                            matrix_sdk::oidc::SessionTokens {
-                                access_token,
-                                refresh_token,
-                                latest_id_token,
-                            },
-                        issuer_info,
-                    } = api.user_session().context("Missing session")?;
-                    let client_id = api
-                        .client_credentials()
-                        .context("OIDC client credentials are missing.")?
-                        .client_id()
-                        .to_owned();
-                    let client_metadata =
-                        api.client_metadata().context("OIDC client metadata is missing.")?.clone();
-                    let oidc_data = OidcSessionData {
-                        client_id,
-                        client_metadata,
-                        latest_id_token: latest_id_token.map(|t| t.to_string()),
-                        issuer_info,
-                    };
-
-                    let oidc_data = serde_json::to_string(&oidc_data).ok();
-                    Ok(Session {
-                        access_token,
-                        refresh_token,
-                        user_id: user_id.to_string(),
-                        device_id: device_id.to_string(),
-                        homeserver_url,
-                        oidc_data,
-                        sliding_sync_proxy,
-                    })
-                }
-                _ => Err(anyhow!("Unknown authentication API").into()),
-            }
-        })
+        RUNTIME.block_on(Self::session_inner(self.inner.clone()))
+    }
+
+    /// Set the delegate used to share the session with other processes.
+    ///
+    /// Before refreshing the access token, the session is reloaded from the
+    /// delegate, in case another process already refreshed it. The refreshed
+    /// session is then given back to the delegate to be persisted.
+    pub fn set_session_delegate(
+        &self,
+        delegate: Box<dyn ClientSessionDelegate>,
+    ) -> Result<(), ClientError> {
+        let delegate: Arc<dyn ClientSessionDelegate> = delegate.into();
+        let save_delegate = delegate.clone();
+
+        self.inner.set_session_callbacks(
+            Box::new(move |client: MatrixClient| -> Result<_, SessionCallbackError> {
+                let user_id = client.user_id().context("Missing user ID")?.to_string();
+                let session = delegate.retrieve_session_from_keychain(user_id)?;
+                Ok(AuthSession::try_from(session)?)
+            }),
+            Box::new(move |client: MatrixClient| -> Result<_, SessionCallbackError> {
+                // The callback is called from an async context, so the session
+                // can't be built by blocking on the runtime here.
+                let save_delegate = save_delegate.clone();
+                RUNTIME.spawn(async move {
+                    match Self::session_inner(client).await {
+                        Ok(session) => save_delegate.save_session_in_keychain(session),
+                        Err(error) => error!("Failed to build the session to save: {error}"),
+                    }
+                });
+                Ok(())
+            }),
+        )?;
+
+        Ok(())
     }
 
     pub fn account_url(&self) -> Option<String> {
@@ -547,6 +617,23 @@ impl Client {
         })
     }
 
+    /// Remove the media files that don't respect the media retention policy
+    /// of the store from the media cache.
+    pub fn clean_up_media_cache(&self) -> Result<(), ClientError> {
+        RUNTIME.block_on(async move { Ok(self.inner.clean_up_media_cache().await?) })
+    }
+
+    /// Change the passphrase used to encrypt the stores of the client.
+    pub fn rotate_store_cipher(
+        &self,
+        old_passphrase: String,
+        new_passphrase: String,
+    ) -> Result<(), ClientError> {
+        RUNTIME.block_on(async move {
+            Ok(self.inner.rotate_store_cipher(&old_passphrase, &new_passphrase).await?)
+        })
+    }
+
     pub fn get_media_thumbnail(
         &self,
         media_source: Arc<MediaSource>,
@@ -655,6 +742,34 @@ impl Client {
         })
     }
 
+    /// Get the pushers registered for the account.
+    ///
+    /// Pushers of a kind that isn't supported are skipped.
+    pub fn pushers(&self) -> Result<Vec<Pusher>, ClientError> {
+        RUNTIME.block_on(async move {
+            let pushers = self.inner.pushers().await?;
+
+            Ok(pushers
+                .into_iter()
+                .filter_map(|pusher| match pusher.try_into() {
+                    Ok(pusher) => Some(pusher),
+                    Err(error) => {
+                        debug!("Skipping a pusher: {error}");
+                        None
+                    }
+                })
+                .collect())
+        })
+    }
+
+    /// Deletes the pusher with the given identifiers.
+    pub fn delete_pusher(&self, identifiers: PusherIdentifiers) -> Result<(), ClientError> {
+        RUNTIME.block_on(async move {
+            self.inner.delete_pusher(identifiers.into()).await?;
+            Ok(())
+        })
+    }
+
     /// The homeserver this client is configured to use.
     pub fn homeserver(&self) -> String {
         RUNTIME.block_on(self.async_homeserver())
@@ -674,7 +789,7 @@ impl Client {
     pub fn ignore_user(&self, user_id: String) -> Result<(), ClientError> {
         RUNTIME.block_on(async move {
             let user_id = UserId::parse(user_id)?;
-            self.inner.account().ignore_user(&user_id).await?;
+            self.inner.ignore_user(&user_id).await?;
             Ok(())
         })
     }
@@ -682,11 +797,32 @@ impl Client {
     pub fn unignore_user(&self, user_id: String) -> Result<(), ClientError> {
         RUNTIME.block_on(async move {
             let user_id = UserId::parse(user_id)?;
-            self.inner.account().unignore_user(&user_id).await?;
+            self.inner.unignore_user(&user_id).await?;
             Ok(())
         })
     }
 
+    pub fn ignored_users(&self) -> Result<Vec<String>, ClientError> {
+        RUNTIME.block_on(async move {
+            let ignored_users = self.inner.ignored_users().await?;
+            Ok(ignored_users.into_iter().map(|user_id| user_id.to_string()).collect())
+        })
+    }
+
+    pub fn subscribe_to_ignored_users(
+        &self,
+        listener: Box<dyn IgnoredUsersListener>,
+    ) -> Arc<TaskHandle> {
+        let ignored_users_stream = self.inner.subscribe_to_ignored_users();
+        Arc::new(TaskHandle::new(RUNTIME.spawn(async move {
+            pin_mut!(ignored_users_stream);
+            while let Some(ignored_users) = ignored_users_stream.next().await {
+                listener
+                    .call(ignored_users.into_iter().map(|user_id| user_id.to_string()).collect());
+            }
+        })))
+    }
+
     pub fn search_users(
         &self,
         search_term: String,
@@ -721,6 +857,15 @@ impl Client {
         SyncServiceBuilder::new(self.inner.clone())
     }
 
+    /// Report whether the app is visible to the user, to reduce the battery
+    /// usage of the syncs while it's in the background.
+    pub fn set_app_state(&self, app_state: AppState) {
+        // The state is applied right away, so consecutive calls are applied in
+        // order, and the pending receipts are sent in a task on the runtime.
+        let _guard = RUNTIME.enter();
+        self.inner.set_app_state(app_state.into());
+    }
+
     pub fn get_notification_settings(&self) -> Arc<NotificationSettings> {
         RUNTIME.block_on(async move {
             Arc::new(NotificationSettings::new(
@@ -902,6 +1047,68 @@ pub struct Session {
     pub sliding_sync_proxy: Option<String>,
 }
 
+impl TryFrom<Session> for AuthSession {
+    type Error = ClientError;
+
+    fn try_from(value: Session) -> Result<Self, Self::Error> {
+        let Session {
+            access_token,
+            refresh_token,
+            user_id,
+            device_id,
+            homeserver_url: _,
+            oidc_data,
+            sliding_sync_proxy: _,
+        } = value;
+
+        if let Some(oidc_data) = oidc_data {
+            // Create an OIDC FullSession.
+            let oidc_data = serde_json::from_str::<OidcUnvalidatedSessionData>(&oidc_data)?
+                .validate()
+                .context("OIDC metadata validation failed.")?;
+            let latest_id_token = oidc_data
+                .latest_id_token
+                .map(TryInto::try_into)
+                .transpose()
+                .context("OIDC latest_id_token is invalid.")?;
+
+            let user_session = matrix_sdk::oidc::UserSession {
+                meta: matrix_sdk::SessionMeta {
+                    user_id: user_id.try_into()?,
+                    device_id: device_id.into(),
+                },
+                tokens: matrix_sdk::oidc::SessionTokens {
+                    access_token,
+                    refresh_token,
+                    latest_id_token,
+                },
+                issuer_info: oidc_data.issuer_info,
+            };
+
+            let session = FullSession {
+                client: RegisteredClientData {
+                    credentials: ClientCredentials::None { client_id: oidc_data.client_id },
+                    metadata: oidc_data.client_metadata,
+                },
+                user: user_session,
+            };
+
+            Ok(session.into())
+        } else {
+            // Create a regular Matrix Session.
+            let session = matrix_sdk::matrix_auth::Session {
+                meta: matrix_sdk::SessionMeta {
+                    user_id: user_id.try_into()?,
+                    device_id: device_id.into(),
+                },
+                tokens: matrix_sdk::matrix_auth::SessionTokens { access_token, refresh_token },
+            };
+
+            Ok(session.into())
+        }
+    }
+}
+
 /// Represents a client registration against an OpenID Connect authentication
 /// issuer.
 #[derive(Serialize)]
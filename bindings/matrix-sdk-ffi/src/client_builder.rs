@@ -1,6 +1,7 @@
 use std::{fs, path::PathBuf, sync::Arc};
 
 use matrix_sdk::{
+    crypto::TrustRequirement as RustTrustRequirement,
     ruma::{
         api::{error::UnknownVersionError, MatrixVersion},
         ServerName, UserId,
@@ -33,6 +34,8 @@ pub struct ClientBuilder {
     proxy: Option<String>,
     disable_ssl_verification: bool,
     disable_automatic_token_refresh: bool,
+    cache_room_events: bool,
+    room_key_trust_requirement: TrustRequirement,
     inner: MatrixClientBuilder,
 }
 
@@ -110,6 +113,18 @@ impl ClientBuilder {
         Arc::new(builder)
     }
 
+    pub fn cache_room_events(self: Arc<Self>) -> Arc<Self> {
+        let mut builder = unwrap_or_clone_arc(self);
+        builder.cache_room_events = true;
+        Arc::new(builder)
+    }
+
+    pub fn room_key_trust_requirement(self: Arc<Self>, requirement: TrustRequirement) -> Arc<Self> {
+        let mut builder = unwrap_or_clone_arc(self);
+        builder.room_key_trust_requirement = requirement;
+        Arc::new(builder)
+    }
+
     pub fn build(self: Arc<Self>) -> Result<Arc<Client>, ClientError> {
         Ok(self.build_inner()?)
     }
@@ -168,6 +183,13 @@ impl ClientBuilder {
             inner_builder = inner_builder.handle_refresh_tokens();
         }
 
+        if builder.cache_room_events {
+            inner_builder = inner_builder.cache_room_events();
+        }
+
+        inner_builder =
+            inner_builder.room_key_trust_requirement(builder.room_key_trust_requirement.into());
+
         if let Some(user_agent) = builder.user_agent {
             inner_builder = inner_builder.user_agent(user_agent);
         }
@@ -220,7 +242,36 @@ impl Default for ClientBuilder {
             proxy: None,
             disable_ssl_verification: false,
             disable_automatic_token_refresh: false,
+            cache_room_events: false,
+            room_key_trust_requirement: TrustRequirement::Untrusted,
             inner: MatrixClient::builder(),
         }
     }
 }
+
+/// The trust that devices need to receive the room keys we share.
+#[derive(Clone, Copy, uniffi::Enum)]
+pub enum TrustRequirement {
+    /// All the devices can receive the room keys.
+    Untrusted,
+    /// Only the devices that are cross-signed by their owner can receive the
+    /// room keys, except for the devices of users without a cross-signing
+    /// identity.
+    CrossSignedOrLegacy,
+    /// Only the devices that are cross-signed by their owner can receive the
+    /// room keys.
+    CrossSigned,
+    /// Only the devices that we verified can receive the room keys.
+    VerifiedOnly,
+}
+
+impl From<TrustRequirement> for RustTrustRequirement {
+    fn from(value: TrustRequirement) -> Self {
+        match value {
+            TrustRequirement::Untrusted => Self::Untrusted,
+            TrustRequirement::CrossSignedOrLegacy => Self::CrossSignedOrLegacy,
+            TrustRequirement::CrossSigned => Self::CrossSigned,
+            TrustRequirement::VerifiedOnly => Self::VerifiedOnly,
+        }
+    }
+}
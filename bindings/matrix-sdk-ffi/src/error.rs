@@ -18,6 +18,12 @@ impl ClientError {
     }
 }
 
+impl From<uniffi::UnexpectedUniFFICallbackError> for ClientError {
+    fn from(e: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        Self::new(e)
+    }
+}
+
 impl From<anyhow::Error> for ClientError {
     fn from(e: anyhow::Error) -> ClientError {
         ClientError::Generic { msg: format!("{e:#}") }
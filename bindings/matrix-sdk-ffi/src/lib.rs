@@ -44,7 +44,7 @@ use async_compat::TOKIO1 as RUNTIME;
 use matrix_sdk::ruma::events::room::{
     message::RoomMessageEventContentWithoutRelation, MediaSource,
 };
-use matrix_sdk_ui::timeline::{BackPaginationStatus, EventItemOrigin};
+use matrix_sdk_ui::timeline::{BackPaginationStatus, EventItemOrigin, ForwardPaginationStatus};
 
 use self::{error::ClientError, task_handle::TaskHandle, timeline::MediaSourceExt};
 
@@ -265,6 +265,26 @@ impl NotificationSettings {
         notification_settings.contains_keyword_rules().await
     }
 
+    /// Get the keywords that trigger a notification.
+    pub async fn get_keywords(&self) -> Vec<String> {
+        let notification_settings = self.sdk_notification_settings.read().await;
+        notification_settings.get_keywords().await
+    }
+
+    /// Add a keyword that triggers a notification.
+    pub async fn add_keyword(&self, keyword: String) -> Result<(), NotificationSettingsError> {
+        let notification_settings = self.sdk_notification_settings.read().await;
+        notification_settings.add_keyword(keyword).await?;
+        Ok(())
+    }
+
+    /// Remove a keyword that triggers a notification.
+    pub async fn remove_keyword(&self, keyword: String) -> Result<(), NotificationSettingsError> {
+        let notification_settings = self.sdk_notification_settings.read().await;
+        notification_settings.remove_keyword(&keyword).await?;
+        Ok(())
+    }
+
     /// Get whether room mentions are enabled.
     pub async fn is_room_mention_enabled(&self) -> Result<bool, NotificationSettingsError> {
         let notification_settings = self.sdk_notification_settings.read().await;
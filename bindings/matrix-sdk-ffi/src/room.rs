@@ -9,7 +9,7 @@ use matrix_sdk::{
     },
     room::{Receipts, Room as SdkRoom},
     ruma::{
-        api::client::{receipt::create_receipt::v3::ReceiptType, room::report_content},
+        api::client::receipt::create_receipt::v3::ReceiptType,
         events::{
             location::{AssetType as RumaAssetType, LocationContent, ZoomLevel},
             poll::unstable_start::{
@@ -26,9 +26,10 @@ use matrix_sdk::{
         },
         EventId, UserId,
     },
+    ComposerDraft as SdkComposerDraft, ComposerDraftType as SdkComposerDraftType, IdParseError,
     RoomMemberships, RoomState,
 };
-use matrix_sdk_ui::timeline::{BackPaginationStatus, RoomExt, Timeline};
+use matrix_sdk_ui::timeline::{BackPaginationStatus, ForwardPaginationStatus, RoomExt, Timeline};
 use mime::Mime;
 use tokio::{
     sync::{Mutex, RwLock},
@@ -55,6 +56,7 @@ pub enum Membership {
     Invited,
     Joined,
     Left,
+    Knocked,
 }
 
 impl From<RoomState> for Membership {
@@ -63,6 +65,9 @@ impl From<RoomState> for Membership {
             RoomState::Invited => Membership::Invited,
             RoomState::Joined => Membership::Joined,
             RoomState::Left => Membership::Left,
+            RoomState::Knocked => Membership::Knocked,
+            // We're not in a room whose state we don't know about.
+            _ => Membership::Left,
         }
     }
 }
@@ -340,6 +345,30 @@ impl Room {
         }))))
     }
 
+    pub fn subscribe_to_forward_pagination_status(
+        &self,
+        listener: Box<dyn ForwardPaginationStatusListener>,
+    ) -> Result<Arc<TaskHandle>, ClientError> {
+        let mut subscriber = match &*RUNTIME.block_on(self.timeline.read()) {
+            Some(t) => t.forward_pagination_status(),
+            None => {
+                return Err(anyhow!(
+                    "Timeline not set up, can't subscribe to forward-pagination status"
+                )
+                .into());
+            }
+        };
+
+        Ok(Arc::new(TaskHandle::new(RUNTIME.spawn(async move {
+            // Send the current state even if it hasn't changed right away.
+            listener.on_update(subscriber.next_now());
+
+            while let Some(status) = subscriber.next().await {
+                listener.on_update(status);
+            }
+        }))))
+    }
+
     /// Loads older messages into the timeline.
     ///
     /// Raises an exception if there are no timeline listeners.
@@ -355,6 +384,43 @@ impl Room {
         })
     }
 
+    /// Loads newer messages into the timeline, after it was focused on an
+    /// event with [`Self::focus_timeline_on_event`].
+    ///
+    /// Raises an exception if there are no timeline listeners.
+    pub fn paginate_forwards(&self, opts: PaginationOptions) -> Result<(), ClientError> {
+        RUNTIME.block_on(async move {
+            let timeline: Arc<_> = self
+                .timeline
+                .read()
+                .await
+                .clone()
+                .context("No timeline listeners registered, can't paginate")?;
+            Ok(timeline.paginate_forwards(opts.into()).await?)
+        })
+    }
+
+    /// Replaces the items of the timeline with the given event and the events
+    /// around it, to jump to it.
+    ///
+    /// Raises an exception if there are no timeline listeners.
+    pub fn focus_timeline_on_event(
+        &self,
+        event_id: String,
+        context_size: u16,
+    ) -> Result<(), ClientError> {
+        RUNTIME.block_on(async move {
+            let timeline: Arc<_> = self
+                .timeline
+                .read()
+                .await
+                .clone()
+                .context("No timeline listeners registered, can't focus on an event")?;
+            let event_id = EventId::parse(event_id)?;
+            Ok(timeline.focus_on_event(&event_id, context_size).await?)
+        })
+    }
+
     pub fn send_read_receipt(&self, event_id: String) -> Result<(), ClientError> {
         let event_id = EventId::parse(event_id)?;
 
@@ -366,6 +432,22 @@ impl Room {
         })
     }
 
+    /// Send a read receipt for the given event in the thread with the given
+    /// root event.
+    pub fn send_thread_read_receipt(
+        &self,
+        event_id: String,
+        thread_root_event_id: String,
+    ) -> Result<(), ClientError> {
+        let event_id = EventId::parse(event_id)?;
+        let thread = ReceiptThread::Thread(EventId::parse(thread_root_event_id)?);
+
+        RUNTIME.block_on(async move {
+            self.inner.send_single_receipt(ReceiptType::Read, thread, event_id).await?;
+            Ok(())
+        })
+    }
+
     pub fn send_read_marker(
         &self,
         fully_read_event_id: String,
@@ -449,6 +531,40 @@ impl Room {
         Ok(())
     }
 
+    pub fn send_poll_response(
+        &self,
+        poll_start_id: String,
+        answers: Vec<String>,
+    ) -> Result<(), ClientError> {
+        let timeline = match &*RUNTIME.block_on(self.timeline.read()) {
+            Some(t) => Arc::clone(t),
+            None => return Err(anyhow!("Timeline not set up, can't send the poll response").into()),
+        };
+
+        let poll_start_id = EventId::parse(poll_start_id)?;
+
+        RUNTIME.spawn(async move {
+            timeline.send_poll_response(&poll_start_id, answers).await;
+        });
+
+        Ok(())
+    }
+
+    pub fn end_poll(&self, poll_start_id: String, text: String) -> Result<(), ClientError> {
+        let timeline = match &*RUNTIME.block_on(self.timeline.read()) {
+            Some(t) => Arc::clone(t),
+            None => return Err(anyhow!("Timeline not set up, can't end the poll").into()),
+        };
+
+        let poll_start_id = EventId::parse(poll_start_id)?;
+
+        RUNTIME.spawn(async move {
+            timeline.end_poll(&poll_start_id, text).await;
+        });
+
+        Ok(())
+    }
+
     pub fn send_reply(
         &self,
         msg: Arc<RoomMessageEventContentWithoutRelation>,
@@ -596,18 +712,7 @@ impl Room {
         let int_score = score.map(|value| value.into());
         RUNTIME.block_on(async move {
             let event_id = EventId::parse(event_id)?;
-            self.inner
-                .client()
-                .send(
-                    report_content::v3::Request::new(
-                        self.inner.room_id().into(),
-                        event_id,
-                        int_score,
-                        reason,
-                    ),
-                    None,
-                )
-                .await?;
+            self.inner.report_content(event_id, int_score, reason).await?;
             Ok(())
         })
     }
@@ -645,6 +750,28 @@ impl Room {
         })
     }
 
+    /// Add or remove the favourite tag of this room, with the given order
+    /// among the favourites.
+    pub async fn set_is_favourite(
+        &self,
+        is_favourite: bool,
+        tag_order: Option<f64>,
+    ) -> Result<(), ClientError> {
+        self.inner.set_is_favourite(is_favourite, tag_order).await?;
+        Ok(())
+    }
+
+    /// Add or remove the low priority tag of this room, with the given order
+    /// among the low priority rooms.
+    pub async fn set_is_low_priority(
+        &self,
+        is_low_priority: bool,
+        tag_order: Option<f64>,
+    ) -> Result<(), ClientError> {
+        self.inner.set_is_low_priority(is_low_priority, tag_order).await?;
+        Ok(())
+    }
+
     /// Sets a new name to the room.
     pub fn set_name(&self, name: Option<String>) -> Result<(), ClientError> {
         RUNTIME.block_on(async move {
@@ -656,7 +783,7 @@ impl Room {
     /// Sets a new topic in the room.
     pub fn set_topic(&self, topic: String) -> Result<(), ClientError> {
         RUNTIME.block_on(async move {
-            self.inner.set_room_topic(&topic).await?;
+            self.inner.set_topic(&topic).await?;
             Ok(())
         })
     }
@@ -713,6 +840,23 @@ impl Room {
         })
     }
 
+    pub fn set_state_events_expanded(
+        &self,
+        event_id: String,
+        expanded: bool,
+    ) -> Result<bool, ClientError> {
+        let timeline = RUNTIME
+            .block_on(self.timeline.read())
+            .as_ref()
+            .context("Timeline not set up, can't expand state events")?
+            .clone();
+
+        RUNTIME.block_on(async move {
+            let event_id = <&EventId>::try_from(event_id.as_str())?;
+            Ok(timeline.set_state_events_expanded(event_id, expanded).await)
+        })
+    }
+
     pub fn send_image(
         self: Arc<Self>,
         url: String,
@@ -797,6 +941,30 @@ impl Room {
         }))
     }
 
+    pub fn send_voice_message(
+        self: Arc<Self>,
+        url: String,
+        audio_info: AudioInfo,
+        waveform: Vec<u16>,
+        progress_watcher: Option<Box<dyn ProgressWatcher>>,
+    ) -> Arc<SendAttachmentJoinHandle> {
+        SendAttachmentJoinHandle::new(RUNTIME.spawn(async move {
+            let mime_str =
+                audio_info.mimetype.as_ref().ok_or(RoomError::InvalidAttachmentMimeType)?;
+            let mime_type =
+                mime_str.parse::<Mime>().map_err(|_| RoomError::InvalidAttachmentMimeType)?;
+
+            let base_audio_info: BaseAudioInfo = BaseAudioInfo::try_from(&audio_info)
+                .map_err(|_| RoomError::InvalidAttachmentData)?;
+
+            let attachment_info =
+                AttachmentInfo::Voice { audio_info: base_audio_info, waveform: Some(waveform) };
+            let attachment_config = AttachmentConfig::new().info(attachment_info);
+
+            self.send_attachment(url, mime_type, attachment_config, progress_watcher).await
+        }))
+    }
+
     pub fn send_file(
         self: Arc<Self>,
         url: String,
@@ -958,6 +1126,18 @@ impl Room {
     pub fn own_user_id(&self) -> String {
         self.inner.own_user_id().to_string()
     }
+
+    pub async fn save_composer_draft(&self, draft: ComposerDraft) -> Result<(), ClientError> {
+        Ok(self.inner.save_composer_draft(draft.try_into()?).await?)
+    }
+
+    pub async fn load_composer_draft(&self) -> Result<Option<ComposerDraft>, ClientError> {
+        Ok(self.inner.load_composer_draft().await?.map(Into::into))
+    }
+
+    pub async fn clear_composer_draft(&self) -> Result<(), ClientError> {
+        Ok(self.inner.clear_composer_draft().await?)
+    }
 }
 
 impl Room {
@@ -1050,6 +1230,11 @@ pub trait BackPaginationStatusListener: Sync + Send {
     fn on_update(&self, status: BackPaginationStatus);
 }
 
+#[uniffi::export(callback_interface)]
+pub trait ForwardPaginationStatusListener: Sync + Send {
+    fn on_update(&self, status: ForwardPaginationStatus);
+}
+
 #[derive(uniffi::Enum)]
 pub enum PaginationOptions {
     SingleRequest { event_limit: u16, wait_for_token: bool },
@@ -1090,3 +1275,67 @@ impl From<AssetType> for RumaAssetType {
         }
     }
 }
+
+/// The current draft of the composer of a room.
+#[derive(uniffi::Record)]
+pub struct ComposerDraft {
+    /// The draft content in plain text.
+    pub plain_text: String,
+    /// The draft content in HTML, if it is formatted.
+    pub html_text: Option<String>,
+    /// The type of the draft.
+    pub draft_type: ComposerDraftType,
+}
+
+impl From<SdkComposerDraft> for ComposerDraft {
+    fn from(value: SdkComposerDraft) -> Self {
+        let SdkComposerDraft { plain_text, html_text, draft_type } = value;
+        Self { plain_text, html_text, draft_type: draft_type.into() }
+    }
+}
+
+impl TryFrom<ComposerDraft> for SdkComposerDraft {
+    type Error = IdParseError;
+
+    fn try_from(value: ComposerDraft) -> Result<Self, Self::Error> {
+        let ComposerDraft { plain_text, html_text, draft_type } = value;
+        Ok(Self { plain_text, html_text, draft_type: draft_type.try_into()? })
+    }
+}
+
+/// The type of a composer draft.
+#[derive(uniffi::Enum)]
+pub enum ComposerDraftType {
+    /// The draft is a new message.
+    NewMessage,
+    /// The draft is a reply to an event.
+    Reply { event_id: String },
+    /// The draft is an edit of an event.
+    Edit { event_id: String },
+}
+
+impl From<SdkComposerDraftType> for ComposerDraftType {
+    fn from(value: SdkComposerDraftType) -> Self {
+        match value {
+            SdkComposerDraftType::NewMessage => Self::NewMessage,
+            SdkComposerDraftType::Reply { event_id } => Self::Reply { event_id: event_id.into() },
+            SdkComposerDraftType::Edit { event_id } => Self::Edit { event_id: event_id.into() },
+        }
+    }
+}
+
+impl TryFrom<ComposerDraftType> for SdkComposerDraftType {
+    type Error = IdParseError;
+
+    fn try_from(value: ComposerDraftType) -> Result<Self, Self::Error> {
+        Ok(match value {
+            ComposerDraftType::NewMessage => Self::NewMessage,
+            ComposerDraftType::Reply { event_id } => {
+                Self::Reply { event_id: EventId::parse(event_id)? }
+            }
+            ComposerDraftType::Edit { event_id } => {
+                Self::Edit { event_id: EventId::parse(event_id)? }
+            }
+        })
+    }
+}
@@ -14,6 +14,8 @@ pub struct RoomInfo {
     is_public: bool,
     is_space: bool,
     is_tombstoned: bool,
+    is_favourite: bool,
+    is_low_priority: bool,
     canonical_alias: Option<String>,
     alternative_aliases: Vec<String>,
     membership: Membership,
@@ -42,6 +44,8 @@ impl RoomInfo {
             is_public: room.is_public(),
             is_space: room.is_space(),
             is_tombstoned: room.is_tombstoned(),
+            is_favourite: room.is_favourite(),
+            is_low_priority: room.is_low_priority(),
             canonical_alias: room.canonical_alias().map(Into::into),
             alternative_aliases: room.alt_aliases().into_iter().map(Into::into).collect(),
             membership: room.state().into(),
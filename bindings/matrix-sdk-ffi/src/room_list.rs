@@ -13,7 +13,8 @@ use matrix_sdk::{
     RoomListEntry as MatrixRoomListEntry,
 };
 use matrix_sdk_ui::room_list_service::filters::{
-    new_filter_all, new_filter_fuzzy_match_room_name, new_filter_normalized_match_room_name,
+    new_filter_all, new_filter_dm, new_filter_favourite, new_filter_fuzzy_match_room_name,
+    new_filter_normalized_match_room_name, new_filter_spaces, new_filter_unread,
 };
 use tokio::sync::RwLock;
 
@@ -71,6 +72,23 @@ impl From<RoomListInput> for matrix_sdk_ui::room_list_service::Input {
     }
 }
 
+#[derive(uniffi::Enum)]
+pub enum RoomListSort {
+    Recency,
+    Name,
+    UnreadFirst,
+}
+
+impl From<RoomListSort> for matrix_sdk_ui::room_list_service::RoomListSort {
+    fn from(value: RoomListSort) -> Self {
+        match value {
+            RoomListSort::Recency => Self::Recency,
+            RoomListSort::Name => Self::Name,
+            RoomListSort::UnreadFirst => Self::UnreadFirst,
+        }
+    }
+}
+
 #[derive(uniffi::Object)]
 pub struct RoomListService {
     pub(crate) inner: Arc<matrix_sdk_ui::RoomListService>,
@@ -115,6 +133,10 @@ impl RoomListService {
     async fn apply_input(&self, input: RoomListInput) -> Result<(), RoomListError> {
         self.inner.apply_input(input.into()).await.map(|_| ()).map_err(Into::into)
     }
+
+    async fn set_sort(&self, sort: RoomListSort) -> Result<(), RoomListError> {
+        self.inner.set_sort(sort.into()).await.map_err(Into::into)
+    }
 }
 
 #[derive(uniffi::Object)]
@@ -326,6 +348,10 @@ impl RoomListEntriesDynamicFilter {
             Kind::FuzzyMatchRoomName { pattern } => {
                 self.inner.set(new_filter_fuzzy_match_room_name(&self.client, &pattern))
             }
+            Kind::Favourite => self.inner.set(new_filter_favourite(&self.client)),
+            Kind::Dm => self.inner.set(new_filter_dm(&self.client)),
+            Kind::Unread => self.inner.set(new_filter_unread(&self.client)),
+            Kind::Spaces => self.inner.set(new_filter_spaces(&self.client)),
         }
     }
 }
@@ -335,6 +361,10 @@ pub enum RoomListEntriesDynamicFilterKind {
     All,
     NormalizedMatchRoomName { pattern: String },
     FuzzyMatchRoomName { pattern: String },
+    Favourite,
+    Dm,
+    Unread,
+    Spaces,
 }
 
 #[derive(uniffi::Object)]
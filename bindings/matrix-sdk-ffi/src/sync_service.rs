@@ -30,6 +30,7 @@ use crate::{
 pub enum SyncServiceState {
     Idle,
     Running,
+    Paused,
     Terminated,
     Error,
 }
@@ -39,6 +40,7 @@ impl From<MatrixSyncServiceState> for SyncServiceState {
         match value {
             MatrixSyncServiceState::Idle => Self::Idle,
             MatrixSyncServiceState::Running => Self::Running,
+            MatrixSyncServiceState::Paused => Self::Paused,
             MatrixSyncServiceState::Terminated => Self::Terminated,
             MatrixSyncServiceState::Error => Self::Error,
         }
@@ -69,6 +71,14 @@ impl SyncService {
         Ok(self.inner.stop().await?)
     }
 
+    pub async fn pause(&self) -> Result<(), ClientError> {
+        Ok(self.inner.pause().await?)
+    }
+
+    pub async fn resume(&self) {
+        self.inner.resume().await;
+    }
+
     pub fn state(&self, listener: Box<dyn SyncServiceStateObserver>) -> Arc<TaskHandle> {
         let state_stream = self.inner.state();
 
@@ -105,6 +115,12 @@ impl SyncServiceBuilder {
         Arc::new(Self { builder })
     }
 
+    pub fn with_automatic_recovery(self: Arc<Self>, max_attempts: u32) -> Arc<Self> {
+        let this = unwrap_or_clone_arc(self);
+        let builder = this.builder.with_automatic_recovery(max_attempts);
+        Arc::new(Self { builder })
+    }
+
     pub async fn finish(self: Arc<Self>) -> Result<Arc<SyncService>, ClientError> {
         let this = unwrap_or_clone_arc(self);
         Ok(Arc::new(SyncService { inner: this.builder.build().await? }))
@@ -5,6 +5,7 @@ use extension_trait::extension_trait;
 use eyeball_im::VectorDiff;
 use matrix_sdk::{
     attachment::{BaseAudioInfo, BaseFileInfo, BaseImageInfo, BaseThumbnailInfo, BaseVideoInfo},
+    deserialized_responses::ShieldState as RustShieldState,
     ruma::events::{
         location::AssetType as RumaAssetType,
         poll::start::PollKind as RumaPollKind,
@@ -20,18 +21,25 @@ use matrix_sdk::{
                 MessageType as RumaMessageType,
                 NoticeMessageEventContent as RumaNoticeMessageEventContent,
                 RoomMessageEventContentWithoutRelation,
-                TextMessageEventContent as RumaTextMessageEventContent, VideoInfo as RumaVideoInfo,
+                TextMessageEventContent as RumaTextMessageEventContent,
+                UnstableAmplitude as RumaUnstableAmplitude,
+                UnstableAudioDetailsContentBlock as RumaUnstableAudioDetailsContentBlock,
+                UnstableVoiceContentBlock as RumaUnstableVoiceContentBlock,
+                VideoInfo as RumaVideoInfo,
                 VideoMessageEventContent as RumaVideoMessageEventContent,
             },
             ImageInfo as RumaImageInfo, MediaSource, ThumbnailInfo as RumaThumbnailInfo,
         },
+        Mentions as RumaMentions,
     },
+    IdParseError,
 };
 use matrix_sdk_ui::timeline::{EventItemOrigin, PollResult, Profile, TimelineDetails};
-use ruma::{assign, UInt};
+use ruma::{assign, UInt, UserId};
 use tracing::{info, warn};
 
 use crate::{
+    client::TransmissionProgress,
     error::{ClientError, TimelineError},
     helpers::unwrap_or_clone_arc,
     room::AssetType,
@@ -66,6 +74,33 @@ pub fn message_event_content_from_html(
     )))
 }
 
+/// The intentional mentions of a message.
+#[derive(uniffi::Record)]
+pub struct Mentions {
+    /// The IDs of the users mentioned in the message.
+    pub user_ids: Vec<String>,
+    /// Whether the whole room is mentioned in the message.
+    pub room: bool,
+}
+
+impl TryFrom<Mentions> for RumaMentions {
+    type Error = IdParseError;
+
+    fn try_from(value: Mentions) -> Result<Self, Self::Error> {
+        let user_ids = value.user_ids.iter().map(UserId::parse).collect::<Result<Vec<_>, _>>()?;
+        Ok(assign!(RumaMentions::with_user_ids(user_ids), { room: value.room }))
+    }
+}
+
+#[uniffi::export]
+pub fn message_event_content_with_mentions(
+    content: Arc<RoomMessageEventContentWithoutRelation>,
+    mentions: Mentions,
+) -> Result<Arc<RoomMessageEventContentWithoutRelation>, ClientError> {
+    let content = unwrap_or_clone_arc(content);
+    Ok(Arc::new(content.add_mentions(mentions.try_into()?)))
+}
+
 #[uniffi::export(callback_interface)]
 pub trait TimelineListener: Sync + Send {
     fn on_update(&self, diff: Vec<Arc<TimelineDiff>>);
@@ -242,6 +277,9 @@ impl TimelineItem {
         match self.0.as_virtual()? {
             VItem::DayDivider(ts) => Some(VirtualTimelineItem::DayDivider { ts: ts.0.into() }),
             VItem::ReadMarker => Some(VirtualTimelineItem::ReadMarker),
+            VItem::TypingUsers(profiles) => Some(VirtualTimelineItem::TypingUsers {
+                profiles: profiles.iter().map(Into::into).collect(),
+            }),
         }
     }
 
@@ -259,6 +297,9 @@ impl TimelineItem {
 pub enum EventSendState {
     /// The local event has not been sent yet.
     NotSentYet,
+    /// The local event is being sent to the server, with the progress of the
+    /// upload of its media, if any.
+    Sending { progress: Option<TransmissionProgress> },
     /// The local event has been sent to the server, but unsuccessfully: The
     /// sending has failed.
     SendingFailed { error: String },
@@ -275,6 +316,7 @@ impl From<&matrix_sdk_ui::timeline::EventSendState> for EventSendState {
 
         match value {
             NotSentYet => Self::NotSentYet,
+            Sending { progress } => Self::Sending { progress: progress.map(Into::into) },
             SendingFailed { error } => Self::SendingFailed { error: error.to_string() },
             Cancelled => Self::Cancelled,
             Sent { event_id } => Self::Sent { event_id: event_id.to_string() },
@@ -319,6 +361,10 @@ impl EventTimelineItem {
         self.0.is_editable()
     }
 
+    pub fn is_user_mentioned(&self) -> bool {
+        self.0.is_user_mentioned()
+    }
+
     pub fn content(&self) -> Arc<TimelineItemContent> {
         Arc::new(TimelineItemContent(self.0.content().clone()))
     }
@@ -364,6 +410,31 @@ impl EventTimelineItem {
     pub fn origin(&self) -> Option<EventItemOrigin> {
         self.0.origin()
     }
+
+    pub fn get_shield(&self, strict: bool) -> Option<ShieldState> {
+        self.0.get_shield(strict).map(Into::into)
+    }
+}
+
+/// The shield that should decorate an event, warning about its authenticity.
+#[derive(uniffi::Enum)]
+pub enum ShieldState {
+    /// A red shield with a tooltip containing the given message.
+    Red { message: String },
+    /// A grey shield with a tooltip containing the given message.
+    Grey { message: String },
+    /// No shield.
+    None,
+}
+
+impl From<RustShieldState> for ShieldState {
+    fn from(value: RustShieldState) -> Self {
+        match value {
+            RustShieldState::Red { message } => Self::Red { message: message.to_owned() },
+            RustShieldState::Grey { message } => Self::Grey { message: message.to_owned() },
+            RustShieldState::None => Self::None,
+        }
+    }
 }
 
 #[derive(uniffi::Record)]
@@ -397,16 +468,22 @@ impl From<&TimelineDetails<Profile>> for ProfileDetails {
         match details {
             TimelineDetails::Unavailable => Self::Unavailable,
             TimelineDetails::Pending => Self::Pending,
-            TimelineDetails::Ready(profile) => Self::Ready {
-                display_name: profile.display_name.clone(),
-                display_name_ambiguous: profile.display_name_ambiguous,
-                avatar_url: profile.avatar_url.as_ref().map(ToString::to_string),
-            },
+            TimelineDetails::Ready(profile) => profile.into(),
             TimelineDetails::Error(e) => Self::Error { message: e.to_string() },
         }
     }
 }
 
+impl From<&Profile> for ProfileDetails {
+    fn from(profile: &Profile) -> Self {
+        Self::Ready {
+            display_name: profile.display_name.clone(),
+            display_name_ambiguous: profile.display_name_ambiguous,
+            avatar_url: profile.avatar_url.as_ref().map(ToString::to_string),
+        }
+    }
+}
+
 #[derive(Clone, uniffi::Object)]
 pub struct TimelineItemContent(matrix_sdk_ui::timeline::TimelineItemContent);
 
@@ -427,6 +504,17 @@ impl TimelineItemContent {
                 }
             }
             Content::Poll(poll_state) => TimelineItemContentKind::from(poll_state.results()),
+            Content::LiveLocation(live_location) => TimelineItemContentKind::LiveLocation {
+                description: live_location.description().map(ToOwned::to_owned),
+                start_ts: live_location.start_ts().0.into(),
+                timeout: live_location.timeout().as_millis() as u64,
+                is_live: live_location.is_live(),
+                last_location: live_location.last_location().map(|location| BeaconLocation {
+                    geo_uri: location.geo_uri.clone(),
+                    description: location.description.clone(),
+                    ts: location.ts.0.into(),
+                }),
+            },
             Content::UnableToDecrypt(msg) => {
                 TimelineItemContentKind::UnableToDecrypt { msg: EncryptedMessage::new(msg) }
             }
@@ -472,6 +560,14 @@ impl TimelineItemContent {
                     error: error.to_string(),
                 }
             }
+            Content::CollapsedStateEvents(group) => TimelineItemContentKind::CollapsedStateEvents {
+                events: group
+                    .events()
+                    .iter()
+                    .map(|event| Arc::new(EventTimelineItem(event.clone())))
+                    .collect(),
+                is_expanded: group.is_expanded(),
+            },
         }
     }
 
@@ -498,6 +594,14 @@ pub enum TimelineItemContentKind {
         votes: HashMap<String, Vec<String>>,
         end_time: Option<u64>,
     },
+    LiveLocation {
+        description: Option<String>,
+        start_ts: u64,
+        /// The duration of the share from `start_ts`, in milliseconds.
+        timeout: u64,
+        is_live: bool,
+        last_location: Option<BeaconLocation>,
+    },
     UnableToDecrypt {
         msg: EncryptedMessage,
     },
@@ -524,6 +628,10 @@ pub enum TimelineItemContentKind {
         state_key: String,
         error: String,
     },
+    CollapsedStateEvents {
+        events: Vec<Arc<EventTimelineItem>>,
+        is_expanded: bool,
+    },
 }
 
 #[derive(Clone, uniffi::Object)]
@@ -572,10 +680,14 @@ impl From<MessageType> for RumaMessageType {
                 RumaImageMessageEventContent::new(content.body, (*content.source).clone())
                     .info(content.info.map(Into::into).map(Box::new)),
             ),
-            MessageType::Audio { content } => Self::Audio(
+            MessageType::Audio { content } => Self::Audio(assign!(
                 RumaAudioMessageEventContent::new(content.body, (*content.source).clone())
                     .info(content.info.map(Into::into).map(Box::new)),
-            ),
+                {
+                    audio: content.audio.map(Into::into),
+                    voice: content.voice.map(Into::into),
+                }
+            )),
             MessageType::Video { content } => Self::Video(
                 RumaVideoMessageEventContent::new(content.body, (*content.source).clone())
                     .info(content.info.map(Into::into).map(Box::new)),
@@ -625,6 +737,8 @@ impl TryFrom<RumaMessageType> for MessageType {
                     body: c.body.clone(),
                     source: Arc::new(c.source.clone()),
                     info: c.info.as_deref().map(Into::into),
+                    audio: c.audio.as_ref().map(Into::into),
+                    voice: c.voice.as_ref().map(Into::into),
                 },
             },
             RumaMessageType::Video(c) => MessageType::Video {
@@ -695,6 +809,51 @@ pub struct AudioMessageContent {
     pub body: String,
     pub source: Arc<MediaSource>,
     pub info: Option<AudioInfo>,
+    pub audio: Option<UnstableAudioDetailsContent>,
+    pub voice: Option<UnstableVoiceContent>,
+}
+
+#[derive(Clone, uniffi::Record)]
+pub struct UnstableAudioDetailsContent {
+    pub duration: Duration,
+    pub waveform: Vec<u16>,
+}
+
+impl From<UnstableAudioDetailsContent> for RumaUnstableAudioDetailsContentBlock {
+    fn from(details: UnstableAudioDetailsContent) -> Self {
+        Self::new(
+            details.duration,
+            details.waveform.into_iter().map(RumaUnstableAmplitude::new).collect(),
+        )
+    }
+}
+
+impl From<&RumaUnstableAudioDetailsContentBlock> for UnstableAudioDetailsContent {
+    fn from(details: &RumaUnstableAudioDetailsContentBlock) -> Self {
+        Self {
+            duration: details.duration,
+            waveform: details
+                .waveform
+                .iter()
+                .map(|amplitude| u64::from(amplitude.get()).try_into().unwrap_or(u16::MAX))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, uniffi::Record)]
+pub struct UnstableVoiceContent {}
+
+impl From<UnstableVoiceContent> for RumaUnstableVoiceContentBlock {
+    fn from(_: UnstableVoiceContent) -> Self {
+        Self::new()
+    }
+}
+
+impl From<&RumaUnstableVoiceContentBlock> for UnstableVoiceContent {
+    fn from(_: &RumaUnstableVoiceContentBlock) -> Self {
+        Self {}
+    }
 }
 
 #[derive(Clone, uniffi::Record)]
@@ -1081,6 +1240,8 @@ pub enum EncryptedMessage {
     MegolmV1AesSha2 {
         /// The ID of the session used to encrypt the message.
         session_id: String,
+        /// Why the message couldn't be decrypted.
+        cause: UtdCause,
     },
     Unknown,
 }
@@ -1094,15 +1255,41 @@ impl EncryptedMessage {
                 let sender_key = sender_key.clone();
                 Self::OlmV1Curve25519AesSha2 { sender_key }
             }
-            Message::MegolmV1AesSha2 { session_id, .. } => {
+            Message::MegolmV1AesSha2 { session_id, cause, .. } => {
                 let session_id = session_id.clone();
-                Self::MegolmV1AesSha2 { session_id }
+                Self::MegolmV1AesSha2 { session_id, cause: cause.clone().into() }
             }
             Message::Unknown => Self::Unknown,
         }
     }
 }
 
+#[derive(Clone, uniffi::Enum)]
+pub enum UtdCause {
+    MissingRoomKey,
+    UnverifiedDevice,
+    Withheld {
+        /// The withheld code, like `m.blacklisted`.
+        code: String,
+    },
+    SentBeforeJoin,
+    BackupNotConnected,
+}
+
+impl From<matrix_sdk_ui::timeline::UtdCause> for UtdCause {
+    fn from(cause: matrix_sdk_ui::timeline::UtdCause) -> Self {
+        use matrix_sdk_ui::timeline::UtdCause as Cause;
+
+        match cause {
+            Cause::MissingRoomKey => Self::MissingRoomKey,
+            Cause::UnverifiedDevice => Self::UnverifiedDevice,
+            Cause::Withheld(code) => Self::Withheld { code: code.as_str().to_owned() },
+            Cause::SentBeforeJoin => Self::SentBeforeJoin,
+            Cause::BackupNotConnected => Self::BackupNotConnected,
+        }
+    }
+}
+
 #[derive(Clone, uniffi::Record)]
 pub struct Reaction {
     pub key: String,
@@ -1128,6 +1315,9 @@ pub enum VirtualTimelineItem {
 
     /// The user's own read marker.
     ReadMarker,
+
+    /// The other members of the room that are currently typing.
+    TypingUsers { profiles: Vec<ProfileDetails> },
 }
 
 #[extension_trait]
@@ -1311,6 +1501,14 @@ pub struct PollAnswer {
     pub text: String,
 }
 
+#[derive(uniffi::Record)]
+pub struct BeaconLocation {
+    pub geo_uri: String,
+    pub description: Option<String>,
+    /// When the user was at this location, in milliseconds since Unix Epoch.
+    pub ts: u64,
+}
+
 impl From<PollResult> for TimelineItemContentKind {
     fn from(value: PollResult) -> Self {
         TimelineItemContentKind::Poll {
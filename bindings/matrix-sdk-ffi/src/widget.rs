@@ -122,17 +122,22 @@ impl From<matrix_sdk::widget::EventFilter> for WidgetEventFilter {
 #[uniffi::export(callback_interface)]
 pub trait WidgetPermissionsProvider: Send + Sync {
     fn acquire_permissions(&self, permissions: WidgetPermissions) -> WidgetPermissions;
+
+    /// Whether the widget with the given ID is allowed to get OpenID
+    /// credentials, to verify the identity of the user.
+    fn acquire_openid(&self, widget_id: String) -> bool;
 }
 
 struct PermissionsProviderWrap(Arc<dyn WidgetPermissionsProvider>);
 
 #[async_trait]
-impl matrix_sdk::widget::PermissionsProvider for PermissionsProviderWrap {
-    async fn acquire_permissions(
+impl matrix_sdk::widget::CapabilitiesProvider for PermissionsProviderWrap {
+    async fn acquire_capabilities(
         &self,
-        permissions: matrix_sdk::widget::Permissions,
+        request: matrix_sdk::widget::CapabilitiesRequest,
     ) -> matrix_sdk::widget::Permissions {
         let this = self.0.clone();
+        let permissions = request.requested;
         // This could require a prompt to the user. Ideally the callback
         // interface would just be async, but that's not supported yet so use
         // one of tokio's blocking task threads instead.
@@ -141,6 +146,15 @@ impl matrix_sdk::widget::PermissionsProvider for PermissionsProviderWrap {
             // propagate panics from the blocking task
             .unwrap()
     }
+
+    async fn acquire_openid(&self, widget_id: &str) -> bool {
+        let this = self.0.clone();
+        let widget_id = widget_id.to_owned();
+        tokio::task::spawn_blocking(move || this.acquire_openid(widget_id))
+            .await
+            // propagate panics from the blocking task
+            .unwrap()
+    }
 }
 
 #[uniffi::export]
@@ -32,6 +32,9 @@ pub enum Error {
     #[error("no client for localpart found")]
     NoClientForLocalpart,
 
+    #[error("user {0} is not in the namespaces of the appservice")]
+    UserNotInNamespace(ruma::OwnedUserId),
+
     #[error("could not convert host:port to socket addr")]
     HostPortToSocketAddrs,
 
@@ -17,8 +17,17 @@ use std::{future::Future, pin::Pin, sync::Arc};
 use tokio::sync::Mutex;
 
 use crate::{
-    ruma::api::appservice::query::{
-        query_room_alias::v1 as query_room, query_user_id::v1 as query_user,
+    ruma::{
+        api::appservice::{
+            query::{query_room_alias::v1 as query_room, query_user_id::v1 as query_user},
+            thirdparty::{
+                get_location_for_protocol::v1 as query_location,
+                get_location_for_room_alias::v1 as query_location_alias,
+                get_protocol::v1 as query_protocol, get_user_for_protocol::v1 as query_tp_user,
+                get_user_for_user_id::v1 as query_tp_user_id,
+            },
+        },
+        thirdparty::{Location, Protocol, User},
     },
     AppService,
 };
@@ -27,23 +36,40 @@ pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 pub(crate) type AppserviceFn<A, R> =
     Box<dyn FnMut(AppService, A) -> BoxFuture<'static, R> + Send + Sync + 'static>;
 
+type Handler<A, R> = Arc<Mutex<Option<AppserviceFn<A, R>>>>;
+
 #[derive(Default, Clone)]
 pub struct EventHandler {
-    pub users: Arc<Mutex<Option<AppserviceFn<query_user::Request, bool>>>>,
-    pub rooms: Arc<Mutex<Option<AppserviceFn<query_room::Request, bool>>>>,
+    pub users: Handler<query_user::Request, bool>,
+    pub rooms: Handler<query_room::Request, bool>,
+    pub protocols: Handler<query_protocol::Request, Option<Protocol>>,
+    pub locations: Handler<query_location::Request, Vec<Location>>,
+    pub location_aliases: Handler<query_location_alias::Request, Vec<Location>>,
+    pub thirdparty_users: Handler<query_tp_user::Request, Vec<User>>,
+    pub thirdparty_user_ids: Handler<query_tp_user_id::Request, Vec<User>>,
 }
 
 impl std::fmt::Debug for EventHandler {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn field<T>(
+            debug: &mut std::fmt::DebugStruct<'_, '_>,
+            name: &str,
+            handler: &Mutex<Option<T>>,
+        ) {
+            match handler.try_lock() {
+                Ok(lock) => debug.field(name, &lock.is_some()),
+                Err(_) => debug.field(name, &format_args!("<locked>")),
+            };
+        }
+
         let mut debug = f.debug_struct("EventHandler");
-        match self.users.try_lock() {
-            Ok(lock) => debug.field("users", &lock.is_some()),
-            Err(_) => debug.field("users", &format_args!("<locked>")),
-        };
-        match self.rooms.try_lock() {
-            Ok(lock) => debug.field("rooms", &lock.is_some()),
-            Err(_) => debug.field("rooms", &format_args!("<locked>")),
-        };
+        field(&mut debug, "users", &self.users);
+        field(&mut debug, "rooms", &self.rooms);
+        field(&mut debug, "protocols", &self.protocols);
+        field(&mut debug, "locations", &self.locations);
+        field(&mut debug, "location_aliases", &self.location_aliases);
+        field(&mut debug, "thirdparty_users", &self.thirdparty_users);
+        field(&mut debug, "thirdparty_user_ids", &self.thirdparty_user_ids);
         debug.finish()
     }
 }
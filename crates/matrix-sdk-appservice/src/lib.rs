@@ -21,6 +21,7 @@
 //!   run the webserver for you
 //! - [x] receive and validate requests from the homeserver correctly
 //! - [x] allow calling the homeserver with proper user identity assertion
+//! - [x] answer the homeserver's third-party protocol lookups
 //! - [x] have consistent room state by leveraging matrix-sdk's state store
 //! - [ ] provide E2EE support by leveraging matrix-sdk's crypto store
 //!
@@ -93,11 +94,18 @@ use ruma::{
         appservice::{
             event::push_events,
             query::{query_room_alias::v1 as query_room, query_user_id::v1 as query_user},
+            thirdparty::{
+                get_location_for_protocol::v1 as query_location,
+                get_location_for_room_alias::v1 as query_location_alias,
+                get_protocol::v1 as query_protocol, get_user_for_protocol::v1 as query_tp_user,
+                get_user_for_user_id::v1 as query_tp_user_id,
+            },
         },
         client::{account::register, sync::sync_events},
     },
     assign,
     events::{room::member::MembershipState, AnyStateEvent, AnyTimelineEvent},
+    thirdparty::{Location, Protocol, User},
     DeviceId, OwnedRoomId, OwnedServerName,
 };
 use serde::Deserialize;
@@ -330,6 +338,74 @@ impl AppService {
         *self.event_handler.rooms.lock().await = Some(handler);
     }
 
+    /// Register a responder for queries about the metadata of a third-party
+    /// protocol the application service bridges.
+    ///
+    /// The handler returns `None` if the protocol is not known.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/protocol/{protocol}](https://spec.matrix.org/v1.10/application-service-api/#get_matrixappv1thirdpartyprotocolprotocol).
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use matrix_sdk_appservice::{AppService, ruma::thirdparty::Protocol};
+    /// # fn run(appservice: AppService, irc: Protocol) {
+    /// appservice.register_thirdparty_protocol_query(Box::new(move |appservice, req| {
+    ///     let irc = irc.clone();
+    ///     Box::pin(async move { (req.protocol == "irc").then_some(irc) })
+    /// }));
+    /// # }
+    /// ```
+    pub async fn register_thirdparty_protocol_query(
+        &self,
+        handler: AppserviceFn<query_protocol::Request, Option<Protocol>>,
+    ) {
+        *self.event_handler.protocols.lock().await = Some(handler);
+    }
+
+    /// Register a responder for queries about the Matrix portal rooms of
+    /// third-party locations, looked up by their fields.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/location/{protocol}](https://spec.matrix.org/v1.10/application-service-api/#get_matrixappv1thirdpartylocationprotocol).
+    pub async fn register_thirdparty_location_query(
+        &self,
+        handler: AppserviceFn<query_location::Request, Vec<Location>>,
+    ) {
+        *self.event_handler.locations.lock().await = Some(handler);
+    }
+
+    /// Register a responder for queries about the third-party locations of a
+    /// Matrix room alias.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/location](https://spec.matrix.org/v1.10/application-service-api/#get_matrixappv1thirdpartylocation).
+    pub async fn register_thirdparty_location_alias_query(
+        &self,
+        handler: AppserviceFn<query_location_alias::Request, Vec<Location>>,
+    ) {
+        *self.event_handler.location_aliases.lock().await = Some(handler);
+    }
+
+    /// Register a responder for queries about the Matrix IDs of third-party
+    /// users, looked up by their fields.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/user/{protocol}](https://spec.matrix.org/v1.10/application-service-api/#get_matrixappv1thirdpartyuserprotocol).
+    pub async fn register_thirdparty_user_query(
+        &self,
+        handler: AppserviceFn<query_tp_user::Request, Vec<User>>,
+    ) {
+        *self.event_handler.thirdparty_users.lock().await = Some(handler);
+    }
+
+    /// Register a responder for queries about the third-party users of a
+    /// Matrix user ID.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/user](https://spec.matrix.org/v1.10/application-service-api/#get_matrixappv1thirdpartyuser).
+    pub async fn register_thirdparty_user_id_query(
+        &self,
+        handler: AppserviceFn<query_tp_user_id::Request, Vec<User>>,
+    ) {
+        *self.event_handler.thirdparty_user_ids.lock().await = Some(handler);
+    }
+
     /// Register an appservice user by sending a [`register::v3::Request`] to
     /// the homeserver.
     ///
@@ -542,6 +618,7 @@ impl AppService {
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::BTreeMap,
         future,
         sync::{Arc, Mutex},
     };
@@ -559,15 +636,17 @@ mod tests {
         events::AnyTimelineEvent,
         room_id,
         serde::Raw,
+        thirdparty::User,
+        user_id,
     };
     use serde_json::json;
     use tower::{Service, ServiceExt};
     use wiremock::{
-        matchers::{body_json, header, method, path},
+        matchers::{body_json, header, method, path, path_regex, query_param},
         Mock, MockServer, ResponseTemplate,
     };
 
-    use crate::{AppService, AppServiceBuilder, AppServiceRegistration, Result};
+    use crate::{AppService, AppServiceBuilder, AppServiceRegistration, Error, Result};
 
     fn registration_string() -> String {
         include_str!("../tests/registration.yaml").to_owned()
@@ -998,6 +1077,97 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_get_thirdparty_protocol() -> Result<()> {
+        let appservice = appservice(None, None).await?;
+
+        let uri = "/_matrix/app/v1/thirdparty/protocol/irc?access_token=hs_token";
+
+        // Without a responder, no protocol is known.
+        let response = appservice
+            .service()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 404);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_get_thirdparty_user_id() -> Result<()> {
+        let appservice = appservice(None, None).await?;
+        appservice
+            .register_thirdparty_user_id_query(Box::new(|_, req| {
+                Box::pin(async move {
+                    let fields = BTreeMap::from([("nick".to_owned(), "alice".to_owned())]);
+                    vec![User::new(req.userid, "irc".to_owned(), fields)]
+                })
+            }))
+            .await;
+
+        // This endpoint only has query parameters.
+        let uri = "/_matrix/app/v1/thirdparty/user?userid=%40_irc_alice%3Alocalhost&access_token=hs_token";
+
+        let response = appservice
+            .service()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body)?,
+            json!([{
+                "userid": "@_irc_alice:localhost",
+                "protocol": "irc",
+                "fields": { "nick": "alice" },
+            }])
+        );
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_user_builder_virtual_user() -> Result<()> {
+        let server = MockServer::start().await;
+        let appservice = appservice(Some(server.uri()), None).await?;
+
+        // Users outside of the namespaces of the appservice are refused.
+        let error = appservice.user(Some("alice")).await.unwrap_err();
+        assert!(
+            matches!(error, Error::UserNotInNamespace(user_id) if user_id == "@alice:localhost")
+        );
+
+        let user_id = user_id!("@_appservice_alice:localhost");
+        let client = appservice.user(Some("_appservice_alice")).await?;
+        assert_eq!(client.user_id(), Some(user_id));
+        // The device ID doesn't change between runs.
+        assert_eq!(client.device_id().unwrap(), "appservice");
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/.*/account/whoami$"))
+            .and(header(
+                "authorization",
+                format!("Bearer {}", appservice.registration().as_token).as_str(),
+            ))
+            .and(query_param("user_id", user_id.as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "user_id": user_id,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // The requests of the virtual user assert its identity.
+        client.whoami().await?;
+
+        Ok(())
+    }
+
     mod registration {
         use ruma::api::appservice::Registration;
 
@@ -21,11 +21,10 @@ use matrix_sdk::{
 };
 use ruma::{
     api::client::{session::login, uiaa::UserIdentifier},
-    assign, DeviceId, OwnedDeviceId, UserId,
+    assign, OwnedDeviceId, UserId,
 };
-use tracing::warn;
 
-use crate::{AppService, Result};
+use crate::{AppService, Error, Result};
 
 /// Builder for an appservice user
 #[derive(Debug)]
@@ -55,6 +54,9 @@ impl<'a> UserBuilder<'a> {
     }
 
     /// Set the device ID of the appservice user
+    ///
+    /// If the user doesn't log in, this defaults to the ID of the appservice
+    /// registration, so the same device is used across restarts.
     pub fn device_id(mut self, device_id: Option<OwnedDeviceId>) -> Self {
         self.device_id = device_id;
         self
@@ -88,7 +90,8 @@ impl<'a> UserBuilder<'a> {
     /// Build the appservice user
     ///
     /// # Errors
-    /// This function returns an error if an invalid localpart is provided.
+    /// This function returns an error if an invalid localpart is provided, or
+    /// if the user is not in the namespaces of the appservice.
     pub async fn build(self) -> Result<Client> {
         if let Some(client) = self.appservice.clients.get(self.localpart) {
             return Ok(client.clone());
@@ -98,7 +101,7 @@ impl<'a> UserBuilder<'a> {
         if !(self.appservice.user_id_is_in_namespace(&user_id)
             || self.localpart == self.appservice.registration.sender_localpart)
         {
-            warn!("Client id '{user_id}' is not in the namespace")
+            return Err(Error::UserNotInNamespace(user_id));
         }
 
         let mut builder = self.client_builder;
@@ -136,7 +139,9 @@ impl<'a> UserBuilder<'a> {
             Session {
                 meta: SessionMeta {
                     user_id: user_id.clone(),
-                    device_id: self.device_id.unwrap_or_else(DeviceId::new),
+                    device_id: self
+                        .device_id
+                        .unwrap_or_else(|| self.appservice.registration.id.as_str().into()),
                 },
                 tokens: SessionTokens {
                     access_token: self.appservice.registration.as_token.clone(),
@@ -23,7 +23,7 @@ use std::{
 use axum::{
     async_trait,
     body::{Bytes, HttpBody},
-    extract::{FromRequest, FromRequestParts, Path},
+    extract::{rejection::PathRejection, FromRequest, FromRequestParts, Path},
     middleware::{self, Next},
     response::{ErrorResponse, IntoResponse, Response},
     routing::{future::RouteFuture, get, put},
@@ -64,6 +64,11 @@ where
             .route("/_matrix/app/v1/users/:user_id", get(handlers::user))
             .route("/_matrix/app/v1/rooms/:room_id", get(handlers::room))
             .route("/_matrix/app/v1/transactions/:txn_id", put(handlers::transaction))
+            .route("/_matrix/app/v1/thirdparty/protocol/:protocol", get(handlers::protocol))
+            .route("/_matrix/app/v1/thirdparty/location/:protocol", get(handlers::location))
+            .route("/_matrix/app/v1/thirdparty/location", get(handlers::location_alias))
+            .route("/_matrix/app/v1/thirdparty/user/:protocol", get(handlers::thirdparty_user))
+            .route("/_matrix/app/v1/thirdparty/user", get(handlers::thirdparty_user_id))
             .route("/users/:user_id", get(handlers::user))
             .route("/rooms/:room_id", get(handlers::room))
             .route("/transactions/:txn_id", put(handlers::transaction))
@@ -143,9 +148,12 @@ where
         state: &S,
     ) -> Result<Self, Self::Rejection> {
         let (mut parts, body) = req.into_parts();
-        let path_params = Path::<Vec<String>>::from_request_parts(&mut parts, state)
-            .await
-            .map_err(IntoResponse::into_response)?;
+        let path_params = match Path::<Vec<String>>::from_request_parts(&mut parts, state).await {
+            Ok(Path(path_params)) => path_params,
+            // Some endpoints only have query parameters.
+            Err(PathRejection::MissingPathParams(_)) => Vec::new(),
+            Err(e) => return Err(e.into_response()),
+        };
         let bytes = Bytes::from_request(http::Request::new(body), state)
             .await
             .map_err(IntoResponse::into_response)?;
@@ -166,6 +174,10 @@ mod handlers {
     use ruma::api::appservice::{
         event::push_events,
         query::{query_room_alias, query_user_id},
+        thirdparty::{
+            get_location_for_protocol, get_location_for_room_alias, get_protocol,
+            get_user_for_protocol, get_user_for_user_id,
+        },
     };
     use serde::Serialize;
 
@@ -205,6 +217,61 @@ mod handlers {
         }
     }
 
+    pub async fn protocol(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_protocol::v1::Request>,
+    ) -> impl IntoResponse {
+        if let Some(get_protocol) = appservice.event_handler.protocols.lock().await.as_mut() {
+            get_protocol(appservice.clone(), request).await.map(Json).ok_or(StatusCode::NOT_FOUND)
+        } else {
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
+
+    pub async fn location(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_location_for_protocol::v1::Request>,
+    ) -> impl IntoResponse {
+        let locations = match appservice.event_handler.locations.lock().await.as_mut() {
+            Some(get_locations) => get_locations(appservice.clone(), request).await,
+            None => Vec::new(),
+        };
+        Json(locations)
+    }
+
+    pub async fn location_alias(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_location_for_room_alias::v1::Request>,
+    ) -> impl IntoResponse {
+        let locations = match appservice.event_handler.location_aliases.lock().await.as_mut() {
+            Some(get_locations) => get_locations(appservice.clone(), request).await,
+            None => Vec::new(),
+        };
+        Json(locations)
+    }
+
+    pub async fn thirdparty_user(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_user_for_protocol::v1::Request>,
+    ) -> impl IntoResponse {
+        let users = match appservice.event_handler.thirdparty_users.lock().await.as_mut() {
+            Some(get_users) => get_users(appservice.clone(), request).await,
+            None => Vec::new(),
+        };
+        Json(users)
+    }
+
+    pub async fn thirdparty_user_id(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_user_for_user_id::v1::Request>,
+    ) -> impl IntoResponse {
+        let users = match appservice.event_handler.thirdparty_user_ids.lock().await.as_mut() {
+            Some(get_users) => get_users(appservice.clone(), request).await,
+            None => Vec::new(),
+        };
+        Json(users)
+    }
+
     pub async fn transaction(
         appservice: Extension<AppService>,
         MatrixRequest(request): MatrixRequest<push_events::v1::Request>,
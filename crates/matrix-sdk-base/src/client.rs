@@ -13,19 +13,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "e2e-encryption")]
+use std::ops::Deref;
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt, iter,
+    sync::Arc,
 };
-#[cfg(feature = "e2e-encryption")]
-use std::{ops::Deref, sync::Arc};
 
 use eyeball::{SharedObservable, Subscriber};
 use matrix_sdk_common::instant::Instant;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_crypto::{
-    store::DynCryptoStore, EncryptionSettings, EncryptionSyncChanges, OlmError, OlmMachine,
-    ToDeviceRequest,
+    store::DynCryptoStore, DecryptionSettings, EncryptionSettings, EncryptionSyncChanges, OlmError,
+    OlmMachine, ToDeviceRequest, TrustRequirement,
 };
 #[cfg(feature = "e2e-encryption")]
 use ruma::events::{
@@ -58,6 +59,7 @@ use crate::latest_event::{is_suitable_for_latest_event, PossibleLatestEvent};
 use crate::{
     deserialized_responses::{AmbiguityChanges, MembersResponse, SyncTimelineEvent},
     error::Result,
+    event_preprocessor::EventPreprocessors,
     rooms::{Room, RoomInfo, RoomState},
     store::{
         ambiguity_map::AmbiguityCache, DynStateStore, MemoryStore, Result as StoreResult,
@@ -88,8 +90,17 @@ pub struct BaseClient {
     /// [`BaseClient::set_session_meta`]
     #[cfg(feature = "e2e-encryption")]
     olm_machine: Arc<RwLock<Option<OlmMachine>>>,
+    /// The trust that the devices of the room members need to receive the
+    /// room keys we share.
+    #[cfg(feature = "e2e-encryption")]
+    room_key_trust_requirement: TrustRequirement,
+    /// The settings to use to decrypt room events.
+    #[cfg(feature = "e2e-encryption")]
+    decryption_settings: DecryptionSettings,
     /// Observable of when a user is ignored/unignored.
     pub(crate) ignore_user_list_changes: SharedObservable<()>,
+    /// The preprocessors of the room events.
+    event_preprocessors: Arc<EventPreprocessors>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -121,10 +132,43 @@ impl BaseClient {
             crypto_store: config.crypto_store,
             #[cfg(feature = "e2e-encryption")]
             olm_machine: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            room_key_trust_requirement: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            decryption_settings: Default::default(),
             ignore_user_list_changes: Default::default(),
+            event_preprocessors: Default::default(),
         }
     }
 
+    /// Set the trust that the devices of the room members need to receive the
+    /// room keys we share.
+    ///
+    /// If some devices don't satisfy it, sharing the room key fails with
+    /// [`OlmError::UntrustedDevices`]. Defaults to
+    /// [`TrustRequirement::Untrusted`].
+    #[cfg(feature = "e2e-encryption")]
+    pub fn with_room_key_trust_requirement(mut self, requirement: TrustRequirement) -> Self {
+        self.room_key_trust_requirement = requirement;
+        self
+    }
+
+    /// Set the settings to use to decrypt room events.
+    ///
+    /// This allows to reject events sent by devices that are not trusted
+    /// enough. By default, events from all the devices are decrypted.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn with_decryption_settings(mut self, settings: DecryptionSettings) -> Self {
+        self.decryption_settings = settings;
+        self
+    }
+
+    /// The settings used to decrypt room events.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn decryption_settings(&self) -> &DecryptionSettings {
+        &self.decryption_settings
+    }
+
     /// Clones the current base client to use the same crypto store but a
     /// different, in-memory store config, and resets transient state.
     pub fn clone_with_in_memory_state_store(&self) -> Self {
@@ -133,7 +177,24 @@ impl BaseClient {
         #[cfg(feature = "e2e-encryption")]
         let config = config.crypto_store(self.crypto_store.clone());
 
-        Self::with_store_config(config)
+        let mut client = Self::with_store_config(config);
+        client.event_preprocessors = self.event_preprocessors.clone();
+
+        #[cfg(feature = "e2e-encryption")]
+        let client = client
+            .with_room_key_trust_requirement(self.room_key_trust_requirement)
+            .with_decryption_settings(self.decryption_settings.clone());
+
+        client
+    }
+
+    /// The preprocessors of the room events received by this client.
+    ///
+    /// They are called with the room events after they are decrypted. They
+    /// must also be run on the events of a sync response before giving it to
+    /// this client.
+    pub fn event_preprocessors(&self) -> &EventPreprocessors {
+        &self.event_preprocessors
     }
 
     /// Get the session meta information.
@@ -252,8 +313,10 @@ impl BaseClient {
         let olm = self.olm_machine().await;
         let Some(olm) = olm.as_ref() else { return Ok(None) };
 
-        let event: SyncTimelineEvent =
-            olm.decrypt_room_event(event.cast_ref(), room_id).await?.into();
+        let event: SyncTimelineEvent = olm
+            .decrypt_room_event_with_settings(event.cast_ref(), room_id, &self.decryption_settings)
+            .await?
+            .into();
 
         if let Ok(AnySyncTimelineEvent::MessageLike(e)) = event.event.deserialize() {
             match &e {
@@ -357,11 +420,15 @@ impl BaseClient {
                             AnySyncMessageLikeEvent::RoomEncrypted(
                                 SyncMessageLikeEvent::Original(_),
                             ) => {
-                                if let Ok(Some(e)) = Box::pin(
+                                if let Ok(Some(mut e)) = Box::pin(
                                     self.decrypt_sync_room_event(&event.event, room.room_id()),
                                 )
                                 .await
                                 {
+                                    if !self.event_preprocessors.run(&mut e.event, room.room_id()) {
+                                        continue;
+                                    }
+
                                     event = e;
                                 }
                             }
@@ -513,10 +580,15 @@ impl BaseClient {
         &self,
         room_id: &RoomId,
         events: &[Raw<AnyRoomAccountDataEvent>],
+        room_info: &mut RoomInfo,
         changes: &mut StateChanges,
     ) {
         for raw_event in events {
             if let Ok(event) = raw_event.deserialize() {
+                if let AnyRoomAccountDataEvent::Tag(e) = &event {
+                    room_info.update_tags(&e.content.tags);
+                }
+
                 changes.add_room_account_data(room_id, event, raw_event.clone());
             }
         }
@@ -631,7 +703,13 @@ impl BaseClient {
 
         // Walk backwards through the encrypted events, looking for one we can decrypt
         for (i, event) in enc_events.iter().enumerate().rev() {
-            if let Ok(Some(decrypted)) = self.decrypt_sync_room_event(event, room.room_id()).await {
+            if let Ok(Some(mut decrypted)) =
+                self.decrypt_sync_room_event(event, room.room_id()).await
+            {
+                if !self.event_preprocessors.run(&mut decrypted.event, room.room_id()) {
+                    continue;
+                }
+
                 // We found an event we can decrypt
                 if let Ok(any_sync_event) = decrypted.event.deserialize() {
                     // We can deserialize it to find its type
@@ -689,6 +767,27 @@ impl BaseClient {
         Ok(())
     }
 
+    /// User has knocked on a room.
+    ///
+    /// Update the internal and cached state accordingly. Return the final Room.
+    pub async fn room_knocked(&self, room_id: &RoomId) -> Result<Room> {
+        let room = self.store.get_or_create_room(room_id, RoomState::Knocked);
+        if room.state() != RoomState::Knocked {
+            let _sync_lock = self.sync_lock().read().await;
+
+            let mut room_info = room.clone_info();
+            room_info.mark_as_knocked();
+            room_info.mark_state_partially_synced();
+            room_info.mark_members_missing(); // the own member event changed
+            let mut changes = StateChanges::default();
+            changes.add_room(room_info.clone());
+            self.store.save_changes(&changes).await?; // Update the store
+            room.update_summary(room_info); // Update the cached room handle
+        }
+
+        Ok(room)
+    }
+
     /// Get access to the store's sync lock.
     pub fn sync_lock(&self) -> &RwLock<()> {
         self.store.sync_lock()
@@ -797,8 +896,13 @@ impl BaseClient {
                 )
                 .await?;
 
-            self.handle_room_account_data(&room_id, &new_info.account_data.events, &mut changes)
-                .await;
+            self.handle_room_account_data(
+                &room_id,
+                &new_info.account_data.events,
+                &mut room_info,
+                &mut changes,
+            )
+            .await;
 
             #[cfg(feature = "e2e-encryption")]
             if room_info.is_encrypted() {
@@ -867,8 +971,13 @@ impl BaseClient {
                 )
                 .await?;
 
-            self.handle_room_account_data(&room_id, &new_info.account_data.events, &mut changes)
-                .await;
+            self.handle_room_account_data(
+                &room_id,
+                &new_info.account_data.events,
+                &mut room_info,
+                &mut changes,
+            )
+            .await;
 
             changes.add_room(room_info);
             new_rooms.leave.insert(
@@ -890,6 +999,17 @@ impl BaseClient {
             new_rooms.invite.insert(room_id, new_info);
         }
 
+        for (room_id, new_info) in response.rooms.knock {
+            let room = self.store.get_or_create_room(&room_id, RoomState::Knocked);
+            let mut room_info = room.clone_info();
+            room_info.mark_as_knocked();
+            room_info.mark_state_fully_synced();
+
+            self.handle_invited_state(&new_info.knock_state.events, &mut room_info, &mut changes);
+
+            changes.add_room(room_info);
+        }
+
         // TODO remove this, we're processing account data events here again
         // because we want to have the push rules in place before we process
         // rooms and their events, but we want to create the rooms before we
@@ -1110,7 +1230,10 @@ impl BaseClient {
                 let members = self.store.get_user_ids(room_id, filter).await?;
 
                 let settings = settings.ok_or(Error::EncryptionNotEnabled)?;
-                let settings = EncryptionSettings::new(settings, history_visibility, false);
+                let settings = EncryptionSettings {
+                    trust_requirement: self.room_key_trust_requirement,
+                    ..EncryptionSettings::new(settings, history_visibility, false)
+                };
 
                 Ok(o.share_room_key(room_id, members.iter().map(Deref::deref), settings).await?)
             }
@@ -1296,18 +1419,26 @@ impl Default for BaseClient {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use matrix_sdk_test::{
         async_test, response_from_file, InvitedRoomBuilder, JoinedRoomBuilder, LeftRoomBuilder,
         StrippedStateTestEvent, SyncResponseBuilder, TimelineTestEvent,
     };
     use ruma::{
         api::{client as api, IncomingResponse},
-        room_id, user_id, RoomId, UserId,
+        events::AnyRoomAccountDataEvent,
+        room_id,
+        serde::Raw,
+        user_id, RoomId, UserId,
     };
     use serde_json::json;
 
     use super::BaseClient;
-    use crate::{store::StateStoreExt, DisplayName, Room, RoomState, SessionMeta, StateChanges};
+    use crate::{
+        store::{MemoryStore, StateStore, StateStoreExt, StoreConfig},
+        DisplayName, Room, RoomInfo, RoomState, RoomStateFilter, SessionMeta, StateChanges,
+    };
 
     #[async_test]
     async fn invite_after_leaving() {
@@ -1471,6 +1602,90 @@ mod tests {
     // events. In the meantime, there are tests for the most difficult logic
     // inside Room.  --andyb
 
+    #[async_test]
+    async fn knocked_room() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = logged_in_client(user_id).await;
+
+        let response = api::sync::sync_events::v3::Response::try_from_http_response(
+            response_from_file(&json!({
+                "next_batch": "asdkl;fjasdkl;fj;asdkl;f",
+                "rooms": {
+                    "knock": {
+                        "!test:example.org": {
+                            "knock_state": {
+                                "events": [
+                                    {
+                                        "content": {
+                                            "name": "Knock knock",
+                                        },
+                                        "sender": "@bob:example.org",
+                                        "state_key": "",
+                                        "type": "m.room.name",
+                                    },
+                                    {
+                                        "content": {
+                                            "membership": "knock",
+                                        },
+                                        "sender": user_id,
+                                        "state_key": user_id,
+                                        "type": "m.room.member",
+                                    },
+                                ],
+                            },
+                        },
+                    },
+                },
+            })),
+        )
+        .unwrap();
+        client.receive_sync_response(response).await.unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.state(), RoomState::Knocked);
+        assert_eq!(room.name().as_deref(), Some("Knock knock"));
+        assert_eq!(client.get_rooms_filtered(RoomStateFilter::KNOCKED).len(), 1);
+        assert!(client.get_rooms_filtered(RoomStateFilter::LEFT).is_empty());
+    }
+
+    #[async_test]
+    async fn tags_are_backfilled_from_the_store() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        // A room that was stored before its tags were tracked in its info.
+        let store = Arc::new(MemoryStore::new());
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        let raw_tags: Raw<AnyRoomAccountDataEvent> = Raw::new(&json!({
+            "type": "m.tag",
+            "content": { "tags": { "m.favourite": { "order": 0.5 } } },
+        }))
+        .unwrap()
+        .cast();
+        changes.add_room_account_data(room_id, raw_tags.deserialize().unwrap(), raw_tags);
+        store.save_changes(&changes).await.unwrap();
+
+        let client = BaseClient::with_store_config(StoreConfig::new().state_store(store.clone()));
+        client
+            .set_session_meta(SessionMeta {
+                user_id: user_id.to_owned(),
+                device_id: "FOOBAR".into(),
+            })
+            .await
+            .unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        assert!(room.is_favourite());
+        assert!(!room.is_low_priority());
+
+        // The backfilled info was saved.
+        let infos = store.get_room_infos().await.unwrap();
+        assert!(infos[0].is_favourite());
+    }
+
     async fn logged_in_client(user_id: &UserId) -> BaseClient {
         let client = BaseClient::new();
         client
@@ -0,0 +1,124 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client-side filtering of the room events received from the server.
+//!
+//! The [`EventPreprocessors`] of a [`BaseClient`] are called with the room
+//! events after they are decrypted. They must also be run on the events
+//! received from the server before giving them to the [`BaseClient`].
+//!
+//! [`BaseClient`]: crate::BaseClient
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering::SeqCst},
+        RwLock as StdRwLock,
+    },
+};
+
+use matrix_sdk_common::{SendOutsideWasm, SyncOutsideWasm};
+use ruma::{events::AnySyncTimelineEvent, serde::Raw, RoomId};
+
+#[cfg(not(target_arch = "wasm32"))]
+type EventPreprocessorFn =
+    dyn Fn(&mut Raw<AnySyncTimelineEvent>, &RoomId) -> PreprocessingAction + Send + Sync;
+#[cfg(target_arch = "wasm32")]
+type EventPreprocessorFn = dyn Fn(&mut Raw<AnySyncTimelineEvent>, &RoomId) -> PreprocessingAction;
+
+/// What to do with an event, as decided by an event preprocessor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreprocessingAction {
+    /// Process the event, possibly modified by the preprocessor.
+    Keep,
+
+    /// Drop the event, as if it wasn't received from the server.
+    Drop,
+}
+
+/// Handle to remove an event preprocessor by passing it to
+/// [`EventPreprocessors::remove()`].
+#[derive(Clone, Debug)]
+pub struct EventPreprocessorHandle {
+    preprocessor_id: u64,
+}
+
+/// The event preprocessors of a client.
+#[derive(Default)]
+pub struct EventPreprocessors {
+    preprocessors: StdRwLock<Vec<(u64, Box<EventPreprocessorFn>)>>,
+    counter: AtomicU64,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for EventPreprocessors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventPreprocessors")
+            .field("len", &self.preprocessors.read().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl EventPreprocessors {
+    /// Add a preprocessor, called after the ones that were already added.
+    ///
+    /// The state events and the stripped state events of invites are given to
+    /// the preprocessor as timeline events. The preprocessor must not add or
+    /// remove preprocessors itself.
+    pub fn add<F>(&self, preprocessor: F) -> EventPreprocessorHandle
+    where
+        F: Fn(&mut Raw<AnySyncTimelineEvent>, &RoomId) -> PreprocessingAction
+            + SendOutsideWasm
+            + SyncOutsideWasm
+            + 'static,
+    {
+        let preprocessor_id = self.counter.fetch_add(1, SeqCst);
+        self.preprocessors.write().unwrap().push((preprocessor_id, Box::new(preprocessor)));
+
+        EventPreprocessorHandle { preprocessor_id }
+    }
+
+    /// Remove the preprocessor associated with the handle.
+    pub fn remove(&self, handle: EventPreprocessorHandle) {
+        self.preprocessors
+            .write()
+            .unwrap()
+            .retain(|(preprocessor_id, _)| *preprocessor_id != handle.preprocessor_id);
+    }
+
+    /// Run the preprocessors on the given event of a room.
+    ///
+    /// An event dropped by a preprocessor is not given to the next ones.
+    /// Returns `false` if the event must be dropped.
+    pub fn run<T>(&self, event: &mut Raw<T>, room_id: &RoomId) -> bool {
+        let preprocessors = self.preprocessors.read().unwrap();
+        if preprocessors.is_empty() {
+            return true;
+        }
+
+        let mut sync_event: Raw<AnySyncTimelineEvent> = event.clone().cast();
+        let keep = preprocessors.iter().all(|(_, preprocessor)| {
+            preprocessor(&mut sync_event, room_id) == PreprocessingAction::Keep
+        });
+        *event = sync_event.cast();
+
+        keep
+    }
+
+    /// Run the preprocessors on the given events of a room, removing the
+    /// dropped ones.
+    pub fn run_all<T>(&self, events: &mut Vec<Raw<T>>, room_id: &RoomId) {
+        events.retain_mut(|event| self.run(event, room_id));
+    }
+}
@@ -27,6 +27,7 @@ mod client;
 pub mod debug;
 pub mod deserialized_responses;
 mod error;
+pub mod event_preprocessor;
 pub mod latest_event;
 pub mod media;
 mod rooms;
@@ -45,7 +46,10 @@ pub use once_cell;
 pub use rooms::{
     DisplayName, Room, RoomInfo, RoomMember, RoomMemberships, RoomState, RoomStateFilter,
 };
-pub use store::{StateChanges, StateStore, StateStoreDataKey, StateStoreDataValue, StoreError};
+pub use store::{
+    ComposerDraft, ComposerDraftType, StateChanges, StateStore, StateStoreDataKey,
+    StateStoreDataValue, StoreError,
+};
 pub use utils::{
     MinimalRoomMemberEvent, MinimalStateEvent, OriginalMinimalStateEvent, RedactedMinimalStateEvent,
 };
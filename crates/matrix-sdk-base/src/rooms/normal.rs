@@ -35,6 +35,7 @@ use ruma::{
         ignored_user_list::IgnoredUserListEventContent,
         receipt::{Receipt, ReceiptThread, ReceiptType},
         room::{
+            avatar::{self, RoomAvatarEventContent},
             create::RoomCreateEventContent,
             encryption::RoomEncryptionEventContent,
             guest_access::GuestAccess,
@@ -44,10 +45,11 @@ use ruma::{
             name::RoomNameEventContent,
             redaction::SyncRoomRedactionEvent,
             tombstone::RoomTombstoneEventContent,
+            topic::RoomTopicEventContent,
         },
-        tag::Tags,
+        tag::{TagName, Tags},
         AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncStateEvent,
-        RoomAccountDataEventType,
+        RoomAccountDataEventType, StateEventType,
     },
     room::RoomType,
     serde::Raw,
@@ -64,7 +66,7 @@ use super::{
 use crate::{
     deserialized_responses::MemberEvent,
     store::{DynStateStore, Result as StoreResult, StateStoreExt},
-    sync::UnreadNotificationsCount,
+    sync::{UnreadCounts, UnreadNotificationsCount},
     MinimalStateEvent, OriginalMinimalStateEvent, RoomMemberships,
 };
 
@@ -104,8 +106,9 @@ pub struct RoomSummary {
 }
 
 /// Enum keeping track in which state the room is, e.g. if our own user is
-/// joined, invited, or has left the room.
+/// joined, invited, has knocked on, or has left the room.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum RoomState {
     /// The room is in a joined state.
     Joined,
@@ -113,17 +116,19 @@ pub enum RoomState {
     Left,
     /// The room is in a invited state.
     Invited,
+    /// The room is in a knocked state, our own user asked to join it.
+    Knocked,
 }
 
 impl From<&MembershipState> for RoomState {
     fn from(membership_state: &MembershipState) -> Self {
-        // We consider Ban, Knock and Leave to be Left, because they all mean we are not
-        // in the room.
+        // We consider Ban and Leave to be Left, because they both mean we are not in
+        // the room.
         match membership_state {
             MembershipState::Ban => Self::Left,
             MembershipState::Invite => Self::Invited,
             MembershipState::Join => Self::Joined,
-            MembershipState::Knock => Self::Left,
+            MembershipState::Knock => Self::Knocked,
             MembershipState::Leave => Self::Left,
             _ => panic!("Unexpected MembershipState: {}", membership_state),
         }
@@ -182,11 +187,34 @@ impl Room {
         self.inner.read().room_type().is_some_and(|t| *t == RoomType::Space)
     }
 
+    /// Whether this room is tagged as a favourite.
+    ///
+    /// Contrary to [`Room::tags()`], this doesn't need to access the store.
+    pub fn is_favourite(&self) -> bool {
+        self.inner.read().is_favourite
+    }
+
+    /// Whether this room is tagged as low priority.
+    ///
+    /// Contrary to [`Room::tags()`], this doesn't need to access the store.
+    pub fn is_low_priority(&self) -> bool {
+        self.inner.read().is_low_priority
+    }
+
     /// Get the unread notification counts.
     pub fn unread_notification_counts(&self) -> UnreadNotificationsCount {
         self.inner.read().notification_counts
     }
 
+    /// Get the unread counts computed by the SDK from the cached events and
+    /// the read receipts of the current user.
+    ///
+    /// They are only computed if the event cache is enabled, otherwise they
+    /// are always 0.
+    pub fn unread_counts(&self) -> UnreadCounts {
+        self.inner.read().unread_counts
+    }
+
     /// Check if the room has its members fully synced.
     ///
     /// Members might be missing if lazy member loading was enabled for the
@@ -273,7 +301,7 @@ impl Room {
     #[instrument(skip_all, fields(room_id = ?self.room_id))]
     pub async fn is_direct(&self) -> StoreResult<bool> {
         match self.state() {
-            RoomState::Joined | RoomState::Left => {
+            RoomState::Joined | RoomState::Left | RoomState::Knocked => {
                 Ok(!self.inner.read().base_info.dm_targets.is_empty())
             }
             RoomState::Invited => {
@@ -702,6 +730,9 @@ pub struct RoomInfo {
     room_state: RoomState,
     /// The unread notifications counts.
     notification_counts: UnreadNotificationsCount,
+    /// The unread counts computed by the SDK.
+    #[serde(default)]
+    unread_counts: UnreadCounts,
     /// The summary of this room.
     summary: RoomSummary,
     /// Flag remembering if the room members are synced.
@@ -714,6 +745,18 @@ pub struct RoomInfo {
     /// Whether or not the encryption info was been synced.
     #[serde(default = "encryption_state_default")] // see fn docs for why we use this default
     encryption_state_synced: bool,
+    /// Whether the room is tagged as a favourite.
+    #[serde(default)]
+    is_favourite: bool,
+    /// Whether the room is tagged as low priority.
+    #[serde(default)]
+    is_low_priority: bool,
+    /// Whether the flags above were computed from the tags of the room.
+    ///
+    /// This is false for the rooms that were stored before these flags were
+    /// added, whose tags need to be loaded from the store.
+    #[serde(default)]
+    tags_synced: bool,
     /// The last event send by sliding sync
     #[cfg(feature = "experimental-sliding-sync")]
     pub(crate) latest_event: Option<SyncTimelineEvent>,
@@ -764,11 +807,15 @@ impl RoomInfo {
             room_id: room_id.into(),
             room_state,
             notification_counts: Default::default(),
+            unread_counts: Default::default(),
             summary: Default::default(),
             members_synced: false,
             last_prev_batch: None,
             sync_info: SyncInfo::NoState,
             encryption_state_synced: false,
+            is_favourite: false,
+            is_low_priority: false,
+            tags_synced: false,
             #[cfg(feature = "experimental-sliding-sync")]
             latest_event: None,
             base_info: BaseRoomInfo::new(),
@@ -790,6 +837,11 @@ impl RoomInfo {
         self.room_state = RoomState::Invited;
     }
 
+    /// Mark this Room as knocked.
+    pub fn mark_as_knocked(&mut self) {
+        self.room_state = RoomState::Knocked;
+    }
+
     /// Set the membership RoomState of this Room
     pub fn set_state(&mut self, room_state: RoomState) {
         self.room_state = room_state;
@@ -914,11 +966,99 @@ impl RoomInfo {
         }));
     }
 
+    /// Remove the room name.
+    pub fn remove_name(&mut self) {
+        self.base_info.name = Some(MinimalStateEvent::Original(OriginalMinimalStateEvent {
+            content: RoomNameEventContent::new(None),
+            event_id: None,
+        }));
+    }
+
+    /// Update the room topic.
+    pub fn update_topic(&mut self, topic: Option<String>) {
+        self.base_info.topic = topic.map(|topic| {
+            MinimalStateEvent::Original(OriginalMinimalStateEvent {
+                content: RoomTopicEventContent::new(topic),
+                event_id: None,
+            })
+        });
+    }
+
+    /// Update the room avatar.
+    pub fn update_avatar(
+        &mut self,
+        url: Option<OwnedMxcUri>,
+        info: Option<Box<avatar::ImageInfo>>,
+    ) {
+        let mut content = RoomAvatarEventContent::new();
+        content.url = url;
+        content.info = info;
+
+        self.base_info.avatar = Some(MinimalStateEvent::Original(OriginalMinimalStateEvent {
+            content,
+            event_id: None,
+        }));
+    }
+
+    /// Restore the state of the given type from another info of this room,
+    /// for example to roll back a local update.
+    ///
+    /// Only the room name, topic and avatar are supported.
+    pub fn restore_state(&mut self, event_type: &StateEventType, previous: &RoomInfo) {
+        let previous = &previous.base_info;
+        match event_type {
+            StateEventType::RoomName => self.base_info.name = previous.name.clone(),
+            StateEventType::RoomTopic => self.base_info.topic = previous.topic.clone(),
+            StateEventType::RoomAvatar => self.base_info.avatar = previous.avatar.clone(),
+            _ => warn!(%event_type, "Restoring this state is not supported"),
+        }
+    }
+
+    /// Update the tags of the room.
+    pub fn update_tags(&mut self, tags: &Tags) {
+        self.is_favourite = tags.contains_key(&TagName::Favorite);
+        self.is_low_priority = tags.contains_key(&TagName::LowPriority);
+        self.tags_synced = true;
+    }
+
+    /// Whether the tags of the room were already taken into account with
+    /// [`RoomInfo::update_tags()`].
+    pub(crate) fn tags_synced(&self) -> bool {
+        self.tags_synced
+    }
+
+    /// Whether the room is tagged as a favourite.
+    pub fn is_favourite(&self) -> bool {
+        self.is_favourite
+    }
+
+    /// Whether the room is tagged as low priority.
+    pub fn is_low_priority(&self) -> bool {
+        self.is_low_priority
+    }
+
+    /// The unread counts computed by the SDK.
+    pub fn unread_counts(&self) -> UnreadCounts {
+        self.unread_counts
+    }
+
     /// Update the notifications count
     pub fn update_notification_count(&mut self, notification_counts: UnreadNotificationsCount) {
         self.notification_counts = notification_counts;
     }
 
+    /// Update the unread counts computed by the SDK.
+    ///
+    /// Returns true if the counts changed, false otherwise.
+    pub fn update_unread_counts(&mut self, unread_counts: UnreadCounts) -> bool {
+        if self.unread_counts == unread_counts {
+            return false;
+        }
+
+        self.unread_counts = unread_counts;
+        true
+    }
+
     /// Update the RoomSummary
     ///
     /// Returns true if the Summary modified the info, false otherwise.
@@ -1082,6 +1222,8 @@ bitflags! {
         const INVITED  = 0b00000010;
         /// The room is in a left state.
         const LEFT     = 0b00000100;
+        /// The room is in a knocked state.
+        const KNOCKED  = 0b00001000;
     }
 }
 
@@ -1096,6 +1238,7 @@ impl RoomStateFilter {
             RoomState::Joined => Self::JOINED,
             RoomState::Left => Self::LEFT,
             RoomState::Invited => Self::INVITED,
+            RoomState::Knocked => Self::KNOCKED,
         };
 
         self.contains(bit_state)
@@ -1114,6 +1257,9 @@ impl RoomStateFilter {
         if self.contains(Self::INVITED) {
             states.push(RoomState::Invited);
         }
+        if self.contains(Self::KNOCKED) {
+            states.push(RoomState::Knocked);
+        }
 
         states
     }
@@ -1129,6 +1275,7 @@ mod tests {
     use matrix_sdk_test::async_test;
     use ruma::{
         api::client::sync::sync_events::v3::RoomSummary as RumaSummary,
+        event_id,
         events::{
             room::{
                 canonical_alias::RoomCanonicalAliasEventContent,
@@ -1161,7 +1308,10 @@ mod tests {
         // serialized format for `RoomInfo`.
 
         use super::RoomSummary;
-        use crate::{rooms::BaseRoomInfo, sync::UnreadNotificationsCount};
+        use crate::{
+            rooms::BaseRoomInfo,
+            sync::{UnreadCounts, UnreadNotificationsCount},
+        };
 
         let info = RoomInfo {
             room_id: room_id!("!gda78o:server.tld").into(),
@@ -1170,6 +1320,11 @@ mod tests {
                 highlight_count: 1,
                 notification_count: 2,
             },
+            unread_counts: UnreadCounts {
+                num_unread_messages: 3,
+                num_unread_notifications: 2,
+                num_unread_mentions: 1,
+            },
             summary: RoomSummary {
                 heroes: vec!["Somebody".to_owned()],
                 joined_member_count: 5,
@@ -1179,6 +1334,9 @@ mod tests {
             last_prev_batch: Some("pb".to_owned()),
             sync_info: SyncInfo::FullySynced,
             encryption_state_synced: true,
+            is_favourite: false,
+            is_low_priority: false,
+            tags_synced: false,
             latest_event: Some(
                 Raw::from_json_string(json!({"sender": "@u:i.uk"}).to_string()).unwrap().into(),
             ),
@@ -1192,6 +1350,11 @@ mod tests {
                 "highlight_count": 1,
                 "notification_count": 2,
             },
+            "unread_counts": {
+                "num_unread_messages": 3,
+                "num_unread_notifications": 2,
+                "num_unread_mentions": 1,
+            },
             "summary": {
                 "heroes": ["Somebody"],
                 "joined_member_count": 5,
@@ -1201,6 +1364,9 @@ mod tests {
             "last_prev_batch": "pb",
             "sync_info": "FullySynced",
             "encryption_state_synced": true,
+            "is_favourite": false,
+            "is_low_priority": false,
+            "tags_synced": false,
             "latest_event": {"encryption_info": null, "event": {"sender": "@u:i.uk"}},
             "base_info": {
                 "avatar": null,
@@ -1549,6 +1715,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn restoring_the_name_on_room_info_keeps_the_original_event() {
+        // Given a room with a name set by an event
+        let mut room_info = RoomInfo::new(room_id!("!r:e.uk"), RoomState::Joined);
+        room_info.base_info.name = Some(MinimalStateEvent::Original(OriginalMinimalStateEvent {
+            content: RoomNameEventContent::new(Some("Test Room".to_owned())),
+            event_id: Some(event_id!("$name").to_owned()),
+        }));
+        let previous = room_info.clone();
+
+        // When I update its name and restore it
+        room_info.update_name("new name".to_owned());
+        room_info.restore_state(&StateEventType::RoomName, &previous);
+
+        // Then the original event is back
+        assert_eq!(room_info.name(), Some("Test Room"));
+        assert_eq!(room_info.base_info.name.as_ref().unwrap().event_id(), Some(event_id!("$name")));
+    }
+
     #[test]
     #[cfg(feature = "experimental-sliding-sync")]
     fn when_we_provide_a_newly_decrypted_event_it_replaces_latest_event() {
@@ -268,7 +268,7 @@ impl BaseClient {
         };
 
         let room_account_data = if let Some(events) = account_data.rooms.get(room_id) {
-            self.handle_room_account_data(room_id, events, changes).await;
+            self.handle_room_account_data(room_id, events, &mut room_info, changes).await;
             Some(events.to_vec())
         } else {
             None
@@ -343,6 +343,8 @@ impl BaseClient {
             )),
 
             RoomState::Invited => Ok((room_info, None, None, invited_room)),
+
+            RoomState::Knocked => Ok((room_info, None, None, None)),
         }
     }
 
@@ -24,7 +24,7 @@ use ruma::{
         AnyStrippedStateEvent, AnySyncEphemeralRoomEvent, AnySyncStateEvent,
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType, SyncStateEvent,
     },
-    mxc_uri, room_id,
+    mxc_uri, owned_room_id, room_id,
     serde::Raw,
     uint, user_id, EventId, OwnedEventId, OwnedUserId, RoomId, UserId,
 };
@@ -32,10 +32,11 @@ use serde_json::{json, value::Value as JsonValue};
 
 use super::DynStateStore;
 use crate::{
-    deserialized_responses::MemberEvent,
+    deserialized_responses::{MemberEvent, RawAnySyncOrStrippedState},
     media::{MediaFormat, MediaRequest, MediaThumbnailSize},
     store::{Result, StateStoreExt},
-    RoomInfo, RoomMemberships, RoomState, StateChanges, StateStoreDataKey, StateStoreDataValue,
+    ComposerDraft, ComposerDraftType, RoomInfo, RoomMemberships, RoomState, RoomStateFilter,
+    StateChanges, StateStoreDataKey, StateStoreDataValue,
 };
 
 /// `StateStore` integration tests.
@@ -53,12 +54,16 @@ pub trait StateStoreIntegrationTests {
     async fn test_topic_redaction(&self) -> Result<()>;
     /// Test populating the store.
     async fn test_populate_store(&self) -> Result<()>;
+    /// Test getting the state of multiple rooms at once.
+    async fn test_multiple_rooms_state(&self) -> Result<()>;
     /// Test room member saving.
     async fn test_member_saving(&self);
     /// Test filter saving.
     async fn test_filter_saving(&self);
     /// Test sync token saving.
     async fn test_sync_token_saving(&self);
+    /// Test composer draft saving.
+    async fn test_composer_draft_saving(&self);
     /// Test stripped room member saving.
     async fn test_stripped_member_saving(&self);
     /// Test room power levels saving.
@@ -375,6 +380,39 @@ impl StateStoreIntegrationTests for DynStateStore {
         Ok(())
     }
 
+    async fn test_multiple_rooms_state(&self) -> Result<()> {
+        let room_id = room_id();
+        let stripped_room_id = stripped_room_id();
+        self.populate().await?;
+
+        let room_infos = self.get_room_infos_filtered(RoomStateFilter::INVITED).await?;
+        assert_eq!(room_infos.len(), 1, "Expected to find 1 invited room info");
+        assert_eq!(room_infos[0].room_id, stripped_room_id);
+        let room_infos =
+            self.get_room_infos_filtered(RoomStateFilter::JOINED | RoomStateFilter::LEFT).await?;
+        assert_eq!(room_infos.len(), 1, "Expected to find 1 joined or left room info");
+        assert_eq!(room_infos[0].room_id, room_id);
+        assert_eq!(self.get_room_infos_filtered(RoomStateFilter::empty()).await?.len(), 2);
+
+        let room_ids =
+            [room_id.to_owned(), stripped_room_id.to_owned(), owned_room_id!("!unknown:localhost")];
+        let names = self.get_state_events_for_rooms(&room_ids, StateEventType::RoomName).await?;
+        assert_eq!(names.len(), 2, "Expected to find room names for 2 rooms");
+        assert_matches!(names[room_id].as_slice(), [RawAnySyncOrStrippedState::Sync(_)]);
+        assert_matches!(
+            names[stripped_room_id].as_slice(),
+            [RawAnySyncOrStrippedState::Stripped(_)]
+        );
+
+        let topics = self.get_state_events_for_rooms(&room_ids, StateEventType::RoomTopic).await?;
+        assert_eq!(topics.len(), 1, "Expected to find a room topic for 1 room");
+        assert_eq!(topics[room_id].len(), 1);
+
+        assert!(self.get_state_events_for_rooms(&[], StateEventType::RoomName).await?.is_empty());
+
+        Ok(())
+    }
+
     async fn test_member_saving(&self) {
         let room_id = room_id!("!test_member_saving:localhost");
         let user_id = user_id();
@@ -525,6 +563,40 @@ impl StateStoreIntegrationTests for DynStateStore {
         assert_matches!(self.get_kv_data(StateStoreDataKey::SyncToken).await, Ok(None));
     }
 
+    async fn test_composer_draft_saving(&self) {
+        let room_id = room_id!("!test_composer_draft:localhost");
+        let draft = ComposerDraft {
+            plain_text: "Hello, World!".to_owned(),
+            html_text: Some("<strong>Hello</strong>, World!".to_owned()),
+            draft_type: ComposerDraftType::Reply {
+                event_id: event_id!("$reply_to:localhost").to_owned(),
+            },
+        };
+
+        assert_matches!(
+            self.get_kv_data(StateStoreDataKey::ComposerDraft(room_id)).await,
+            Ok(None)
+        );
+
+        self.set_kv_data(
+            StateStoreDataKey::ComposerDraft(room_id),
+            StateStoreDataValue::ComposerDraft(draft.clone()),
+        )
+        .await
+        .unwrap();
+        let stored_draft = assert_matches!(
+            self.get_kv_data(StateStoreDataKey::ComposerDraft(room_id)).await,
+            Ok(Some(StateStoreDataValue::ComposerDraft(d))) => d
+        );
+        assert_eq!(stored_draft, draft);
+
+        self.remove_kv_data(StateStoreDataKey::ComposerDraft(room_id)).await.unwrap();
+        assert_matches!(
+            self.get_kv_data(StateStoreDataKey::ComposerDraft(room_id)).await,
+            Ok(None)
+        );
+    }
+
     async fn test_stripped_member_saving(&self) {
         let room_id = room_id!("!test_stripped_member_saving:localhost");
         let user_id = user_id();
@@ -1187,6 +1259,12 @@ macro_rules! statestore_integration_tests {
             store.test_populate_store().await
         }
 
+        #[async_test]
+        async fn test_multiple_rooms_state() -> StoreResult<()> {
+            let store = get_store().await?.into_state_store();
+            store.test_multiple_rooms_state().await
+        }
+
         #[async_test]
         async fn test_member_saving() {
             let store = get_store().await.unwrap().into_state_store();
@@ -1205,6 +1283,12 @@ macro_rules! statestore_integration_tests {
             store.test_sync_token_saving().await
         }
 
+        #[async_test]
+        async fn test_composer_draft_saving() {
+            let store = get_store().await.unwrap().into_state_store();
+            store.test_composer_draft_saving().await
+        }
+
         #[async_test]
         async fn test_stripped_member_saving() {
             let store = get_store().await.unwrap().into_state_store();
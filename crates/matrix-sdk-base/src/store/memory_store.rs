@@ -38,8 +38,9 @@ use tracing::{debug, warn};
 
 use super::{Result, RoomInfo, StateChanges, StateStore, StoreError};
 use crate::{
-    deserialized_responses::RawAnySyncOrStrippedState, media::MediaRequest, MinimalRoomMemberEvent,
-    RoomMemberships, RoomState, StateStoreDataKey, StateStoreDataValue,
+    deserialized_responses::RawAnySyncOrStrippedState, media::MediaRequest, ComposerDraft,
+    MinimalRoomMemberEvent, RoomMemberships, RoomState, RoomStateFilter, StateStoreDataKey,
+    StateStoreDataValue,
 };
 
 /// In-Memory, non-persistent implementation of the `StateStore`
@@ -51,6 +52,7 @@ pub struct MemoryStore {
     user_avatar_url: DashMap<String, String>,
     sync_token: RwLock<Option<String>>,
     filters: DashMap<String, String>,
+    composer_drafts: DashMap<OwnedRoomId, ComposerDraft>,
     account_data: DashMap<GlobalAccountDataEventType, Raw<AnyGlobalAccountDataEvent>>,
     profiles: DashMap<OwnedRoomId, DashMap<OwnedUserId, MinimalRoomMemberEvent>>,
     display_names: DashMap<OwnedRoomId, DashMap<String, BTreeSet<OwnedUserId>>>,
@@ -89,6 +91,7 @@ impl MemoryStore {
             user_avatar_url: Default::default(),
             sync_token: Default::default(),
             filters: Default::default(),
+            composer_drafts: Default::default(),
             account_data: Default::default(),
             profiles: Default::default(),
             display_names: Default::default(),
@@ -118,6 +121,10 @@ impl MemoryStore {
                 .user_avatar_url
                 .get(user_id.as_str())
                 .map(|u| StateStoreDataValue::UserAvatarUrl(u.value().clone()))),
+            StateStoreDataKey::ComposerDraft(room_id) => Ok(self
+                .composer_drafts
+                .get(room_id)
+                .map(|d| StateStoreDataValue::ComposerDraft(d.value().clone()))),
         }
     }
 
@@ -143,6 +150,12 @@ impl MemoryStore {
                     value.into_user_avatar_url().expect("Session data not a user avatar url"),
                 );
             }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                self.composer_drafts.insert(
+                    room_id.to_owned(),
+                    value.into_composer_draft().expect("Session data not a composer draft"),
+                );
+            }
         }
 
         Ok(())
@@ -157,6 +170,9 @@ impl MemoryStore {
             StateStoreDataKey::UserAvatarUrl(user_id) => {
                 self.filters.remove(user_id.as_str());
             }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                self.composer_drafts.remove(room_id);
+            }
         }
 
         Ok(())
@@ -469,11 +485,15 @@ impl MemoryStore {
         self.room_info.iter().map(|r| r.clone()).collect()
     }
 
+    fn get_room_infos_filtered(&self, filter: RoomStateFilter) -> Vec<RoomInfo> {
+        self.room_info.iter().filter(|r| filter.matches(r.state())).map(|r| r.clone()).collect()
+    }
+
     fn get_stripped_room_infos(&self) -> Vec<RoomInfo> {
         self.room_info
             .iter()
             .filter_map(|r| match r.state() {
-                RoomState::Invited => Some(r.clone()),
+                RoomState::Invited | RoomState::Knocked => Some(r.clone()),
                 _ => None,
             })
             .collect()
@@ -587,6 +607,7 @@ impl MemoryStore {
         self.stripped_members.remove(room_id);
         self.room_user_receipts.remove(room_id);
         self.room_event_receipts.remove(room_id);
+        self.composer_drafts.remove(room_id);
 
         Ok(())
     }
@@ -698,6 +719,10 @@ impl StateStore for MemoryStore {
         Ok(self.get_room_infos())
     }
 
+    async fn get_room_infos_filtered(&self, filter: RoomStateFilter) -> Result<Vec<RoomInfo>> {
+        Ok(self.get_room_infos_filtered(filter))
+    }
+
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
         Ok(self.get_stripped_room_infos())
     }
@@ -47,6 +47,7 @@ use ruma::{
         presence::PresenceEvent,
         receipt::ReceiptEventContent,
         room::{member::StrippedRoomMemberEvent, redaction::SyncRoomRedactionEvent},
+        tag::TagEvent,
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
         AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
@@ -71,8 +72,8 @@ pub use self::integration_tests::StateStoreIntegrationTests;
 pub use self::{
     memory_store::MemoryStore,
     traits::{
-        DynStateStore, IntoStateStore, StateStore, StateStoreDataKey, StateStoreDataValue,
-        StateStoreExt,
+        ComposerDraft, ComposerDraftType, DynStateStore, IntoStateStore, StateStore,
+        StateStoreDataKey, StateStoreDataValue, StateStoreExt,
     },
 };
 
@@ -176,11 +177,22 @@ impl Store {
     ///
     /// This method panics if it is called twice.
     pub async fn set_session_meta(&self, session_meta: SessionMeta) -> Result<()> {
-        for info in self.inner.get_room_infos().await? {
+        let mut changes = StateChanges::default();
+
+        for mut info in self.inner.get_room_infos().await? {
+            if !info.tags_synced() {
+                self.backfill_tags(&mut info).await?;
+                changes.add_room(info.clone());
+            }
+
             let room = Room::restore(&session_meta.user_id, self.inner.clone(), info);
             self.rooms.insert(room.room_id().to_owned(), room);
         }
 
+        if !changes.room_infos.is_empty() {
+            self.inner.save_changes(&changes).await?;
+        }
+
         let token =
             self.get_kv_data(StateStoreDataKey::SyncToken).await?.and_then(|s| s.into_sync_token());
         *self.sync_token.write().await = token;
@@ -190,6 +202,25 @@ impl Store {
         Ok(())
     }
 
+    /// Compute the flags of the given room info that depend on the tags of the
+    /// room, from the `m.tag` event in the store.
+    ///
+    /// This is needed for the rooms that were stored before these flags were
+    /// added to [`RoomInfo`], because the `m.tag` event is only received again
+    /// when it changes.
+    async fn backfill_tags(&self, info: &mut RoomInfo) -> Result<()> {
+        let tags = self
+            .inner
+            .get_room_account_data_event(info.room_id(), RoomAccountDataEventType::Tag)
+            .await?
+            .and_then(|raw| raw.deserialize_as::<TagEvent>().ok())
+            .map(|event| event.content.tags)
+            .unwrap_or_default();
+
+        info.update_tags(&tags);
+        Ok(())
+    }
+
     /// The current [`SessionMeta`] containing our user ID and device ID.
     pub fn session_meta(&self) -> Option<&SessionMeta> {
         self.session_meta.get()
@@ -31,14 +31,15 @@ use ruma::{
         RoomAccountDataEventType, StateEventType, StaticEventContent, StaticStateEventContent,
     },
     serde::Raw,
-    EventId, MxcUri, OwnedEventId, OwnedUserId, RoomId, UserId,
+    EventId, MxcUri, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
 };
+use serde::{Deserialize, Serialize};
 
 use super::{StateChanges, StoreError};
 use crate::{
     deserialized_responses::{RawAnySyncOrStrippedState, RawMemberEvent, RawSyncOrStrippedState},
     media::MediaRequest,
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships,
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomStateFilter,
 };
 
 /// An abstract state store trait that can be used to implement different stores
@@ -149,6 +150,37 @@ pub trait StateStore: AsyncTraitDeps {
         state_keys: &[&str],
     ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error>;
 
+    /// Get the state events of a given `StateEventType` for several rooms at
+    /// once.
+    ///
+    /// The rooms without any state event of this type are not in the returned
+    /// map.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_ids` - The IDs of the rooms to find events for.
+    ///
+    /// * `event_type` - The event type.
+    ///
+    /// The default implementation calls [`StateStore::get_state_events()`]
+    /// for each room.
+    async fn get_state_events_for_rooms<'a>(
+        &self,
+        room_ids: &'a [OwnedRoomId],
+        event_type: StateEventType,
+    ) -> Result<BTreeMap<&'a RoomId, Vec<RawAnySyncOrStrippedState>>, Self::Error> {
+        let mut events = BTreeMap::new();
+
+        for room_id in room_ids {
+            let room_events = self.get_state_events(room_id, event_type.clone()).await?;
+            if !room_events.is_empty() {
+                events.insert(room_id.as_ref(), room_events);
+            }
+        }
+
+        Ok(events)
+    }
+
     /// Get the current profile for the given user in the given room.
     ///
     /// # Arguments
@@ -197,6 +229,20 @@ pub trait StateStore: AsyncTraitDeps {
     /// Get all the pure `RoomInfo`s the store knows about.
     async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error>;
 
+    /// Get the `RoomInfo`s of the rooms in the states matching the given
+    /// filter.
+    ///
+    /// The default implementation filters the result of
+    /// [`StateStore::get_room_infos()`].
+    async fn get_room_infos_filtered(
+        &self,
+        filter: RoomStateFilter,
+    ) -> Result<Vec<RoomInfo>, Self::Error> {
+        let mut infos = self.get_room_infos().await?;
+        infos.retain(|info| filter.matches(info.state()));
+        Ok(infos)
+    }
+
     /// Get all the pure `RoomInfo`s the store knows about.
     #[deprecated = "Use get_room_infos instead and filter by RoomState"]
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error>;
@@ -359,6 +405,49 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `uri` - The `MxcUri` of the media files.
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<(), Self::Error>;
 
+    /// Removes the media files' content that don't respect the media
+    /// retention policy of the store.
+    ///
+    /// Stores that don't have a media retention policy don't do anything.
+    async fn clean_up_media_cache(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Export the key encrypting the data of the store, encrypted with a new
+    /// passphrase.
+    ///
+    /// Nothing is written to the store: the export must be saved with
+    /// [`StateStore::save_store_cipher()`] for the new passphrase to be used.
+    /// This allows to check that the passphrase of several stores can be
+    /// changed before changing any of them.
+    ///
+    /// Returns `None` for stores that are not persisted, so there is no
+    /// passphrase to change. Persisted stores that are not encrypted with a
+    /// passphrase return an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `old_passphrase` - The passphrase currently used to open the store.
+    ///
+    /// * `new_passphrase` - The passphrase to use from now on.
+    async fn export_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let _ = (old_passphrase, new_passphrase);
+        Ok(None)
+    }
+
+    /// Save a key exported with [`StateStore::export_store_cipher()`].
+    ///
+    /// The passphrase used for the export must be used to open the store from
+    /// now on.
+    async fn save_store_cipher(&self, export: Vec<u8>) -> Result<(), Self::Error> {
+        let _ = export;
+        Ok(())
+    }
+
     /// Removes a room and all elements associated from the state store.
     ///
     /// # Arguments
@@ -444,6 +533,14 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.get_state_events_for_keys(room_id, event_type, state_keys).await.map_err(Into::into)
     }
 
+    async fn get_state_events_for_rooms<'a>(
+        &self,
+        room_ids: &'a [OwnedRoomId],
+        event_type: StateEventType,
+    ) -> Result<BTreeMap<&'a RoomId, Vec<RawAnySyncOrStrippedState>>, Self::Error> {
+        self.0.get_state_events_for_rooms(room_ids, event_type).await.map_err(Into::into)
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -483,6 +580,13 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.get_room_infos().await.map_err(Into::into)
     }
 
+    async fn get_room_infos_filtered(
+        &self,
+        filter: RoomStateFilter,
+    ) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.0.get_room_infos_filtered(filter).await.map_err(Into::into)
+    }
+
     #[allow(deprecated)]
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
         self.0.get_stripped_room_infos().await.map_err(Into::into)
@@ -584,6 +688,22 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.remove_media_content_for_uri(uri).await.map_err(Into::into)
     }
 
+    async fn clean_up_media_cache(&self) -> Result<(), Self::Error> {
+        self.0.clean_up_media_cache().await.map_err(Into::into)
+    }
+
+    async fn export_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.0.export_store_cipher(old_passphrase, new_passphrase).await.map_err(Into::into)
+    }
+
+    async fn save_store_cipher(&self, export: Vec<u8>) -> Result<(), Self::Error> {
+        self.0.save_store_cipher(export).await.map_err(Into::into)
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
         self.0.remove_room(room_id).await.map_err(Into::into)
     }
@@ -781,6 +901,38 @@ pub enum StateStoreDataValue {
 
     /// The user avatar url
     UserAvatarUrl(String),
+
+    /// The current draft of the composer of a room.
+    ComposerDraft(ComposerDraft),
+}
+
+/// The current draft of the composer of a room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComposerDraft {
+    /// The draft content in plain text.
+    pub plain_text: String,
+    /// The draft content in HTML, if it is formatted.
+    pub html_text: Option<String>,
+    /// The type of the draft.
+    pub draft_type: ComposerDraftType,
+}
+
+/// The type of a [`ComposerDraft`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ComposerDraftType {
+    /// The draft is a new message.
+    NewMessage,
+    /// The draft is a reply to an event.
+    Reply {
+        /// The ID of the event being replied to.
+        event_id: OwnedEventId,
+    },
+    /// The draft is an edit of an event.
+    Edit {
+        /// The ID of the event being edited.
+        event_id: OwnedEventId,
+    },
 }
 
 impl StateStoreDataValue {
@@ -807,6 +959,14 @@ impl StateStoreDataValue {
             _ => None,
         }
     }
+
+    /// Get this value if it is a composer draft.
+    pub fn into_composer_draft(self) -> Option<ComposerDraft> {
+        match self {
+            Self::ComposerDraft(draft) => Some(draft),
+            _ => None,
+        }
+    }
 }
 
 /// A key for key-value data.
@@ -820,6 +980,9 @@ pub enum StateStoreDataKey<'a> {
 
     /// Avatar URL
     UserAvatarUrl(&'a UserId),
+
+    /// The current draft of the composer of the given room.
+    ComposerDraft(&'a RoomId),
 }
 
 impl StateStoreDataKey<'_> {
@@ -830,4 +993,7 @@ impl StateStoreDataKey<'_> {
     /// Key prefix to use for the [`UserAvatarUrl`][Self::UserAvatarUrl]
     /// variant.
     pub const USER_AVATAR_URL: &'static str = "user_avatar_url";
+    /// Key prefix to use for the [`ComposerDraft`][Self::ComposerDraft]
+    /// variant.
+    pub const COMPOSER_DRAFT: &'static str = "composer_draft";
 }
@@ -157,6 +157,24 @@ impl From<RumaUnreadNotificationsCount> for UnreadNotificationsCount {
     }
 }
 
+/// The number of unread events in a room, computed by the SDK from the cached
+/// events and the read receipts of the current user.
+///
+/// Contrary to the counts sent by the homeserver, these take the events of
+/// encrypted rooms into account, once they were decrypted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UnreadCounts {
+    /// The number of unread messages, ignoring edits and the events that
+    /// aren't displayed like state events.
+    pub num_unread_messages: u64,
+    /// The number of unread events that trigger a notification according to
+    /// the push rules.
+    pub num_unread_notifications: u64,
+    /// The number of unread events that are highlighted according to the
+    /// push rules, usually because they mention the user.
+    pub num_unread_mentions: u64,
+}
+
 /// Updates to left rooms.
 #[derive(Clone)]
 pub struct LeftRoom {
@@ -24,6 +24,7 @@ pub mod debug;
 pub mod deserialized_responses;
 pub mod executor;
 pub mod ring_buffer;
+pub mod sleep;
 pub mod timeout;
 pub mod tracing_timer;
 
@@ -12,12 +12,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{
-    io::{Cursor, Read},
-    ops::DerefMut,
-};
-
-use bs58;
 use thiserror::Error;
 use zeroize::Zeroizing;
 
@@ -25,7 +19,12 @@ use super::{
     compat::{Error as DecryptionError, Message, PkDecryption},
     MegolmV1BackupKey,
 };
-use crate::store::BackupDecryptionKey;
+use crate::{
+    store::BackupDecryptionKey,
+    utilities::{
+        decode_recovery_key, encode_recovery_key, format_recovery_key, RecoveryKeyDecodeError,
+    },
+};
 
 /// Error type for the decoding of a [`BackupDecryptionKey`].
 #[derive(Debug, Error)]
@@ -53,6 +52,17 @@ pub enum DecodeError {
     PublicKey(#[from] vodozemac::KeyError),
 }
 
+impl From<RecoveryKeyDecodeError> for DecodeError {
+    fn from(value: RecoveryKeyDecodeError) -> Self {
+        match value {
+            RecoveryKeyDecodeError::Prefix(expected, got) => Self::Prefix(expected, got),
+            RecoveryKeyDecodeError::Parity(expected, got) => Self::Parity(expected, got),
+            RecoveryKeyDecodeError::Base58(e) => Self::Base58(e),
+            RecoveryKeyDecodeError::Io(e) => Self::Io(e),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum UnpicklingError {
     #[error(transparent)]
@@ -73,31 +83,13 @@ impl TryFrom<String> for BackupDecryptionKey {
 
 impl std::fmt::Display for BackupDecryptionKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let string = Zeroizing::new(self.to_base58());
-
-        let string = Zeroizing::new(
-            string
-                .chars()
-                .collect::<Vec<char>>()
-                .chunks(Self::DISPLAY_CHUNK_SIZE)
-                .map(|c| c.iter().collect::<String>())
-                .collect::<Vec<_>>()
-                .join(" "),
-        );
+        let string = format_recovery_key(&encode_recovery_key(self.inner.as_ref()));
 
         write!(f, "{}", string.as_str())
     }
 }
 
 impl BackupDecryptionKey {
-    const PREFIX: [u8; 2] = [0x8b, 0x01];
-    const PREFIX_PARITY: u8 = Self::PREFIX[0] ^ Self::PREFIX[1];
-    const DISPLAY_CHUNK_SIZE: usize = 4;
-
-    fn parity_byte(bytes: &[u8]) -> u8 {
-        bytes.iter().fold(Self::PREFIX_PARITY, |acc, x| acc ^ x)
-    }
-
     /// Create a new decryption key from the given byte array.
     ///
     /// **Warning**: You need to make sure that the byte array contains correct
@@ -135,46 +127,13 @@ impl BackupDecryptionKey {
 
     /// Try to create a [`BackupDecryptionKey`] from a base58 export.
     pub fn from_base58(value: &str) -> Result<Self, DecodeError> {
-        // Remove any whitespace we might have
-        let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
-
-        let decoded = bs58::decode(value).with_alphabet(bs58::Alphabet::BITCOIN).into_vec()?;
-        let mut decoded = Cursor::new(decoded);
-
-        let mut prefix = [0u8; 2];
-        let mut key = Box::new([0u8; Self::KEY_SIZE]);
-        let mut expected_parity = [0u8; 1];
-
-        decoded.read_exact(&mut prefix)?;
-        decoded.read_exact(key.deref_mut())?;
-        decoded.read_exact(&mut expected_parity)?;
-
-        let expected_parity = expected_parity[0];
-        let parity = Self::parity_byte(key.as_ref());
-
-        let _ = Zeroizing::new(decoded.into_inner());
-
-        if prefix != Self::PREFIX {
-            Err(DecodeError::Prefix(Self::PREFIX, prefix))
-        } else if expected_parity != parity {
-            Err(DecodeError::Parity(expected_parity, parity))
-        } else {
-            Ok(Self::from_boxed_bytes(key))
-        }
+        let key = decode_recovery_key::<{ Self::KEY_SIZE }>(value)?;
+        Ok(Self::from_boxed_bytes(key))
     }
 
     /// Export the `[`BackupDecryptionKey`] as a base58 encoded string.
     pub fn to_base58(&self) -> String {
-        let bytes = Zeroizing::new(
-            [
-                Self::PREFIX.as_ref(),
-                self.inner.as_ref(),
-                [Self::parity_byte(self.inner.as_ref())].as_ref(),
-            ]
-            .concat(),
-        );
-
-        bs58::encode(bytes.as_slice()).with_alphabet(bs58::Alphabet::BITCOIN).into_string()
+        encode_recovery_key(self.inner.as_ref()).as_str().to_owned()
     }
 
     fn get_pk_decrytpion(&self) -> PkDecryption {
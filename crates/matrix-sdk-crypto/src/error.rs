@@ -12,6 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
+use matrix_sdk_common::deserialized_responses::VerificationLevel;
 use ruma::{CanonicalJsonError, IdParseError, OwnedDeviceId, OwnedRoomId, OwnedUserId};
 use serde_json::Error as SerdeError;
 use thiserror::Error;
@@ -73,6 +76,14 @@ pub enum OlmError {
             have a valid Olm session with us"
     )]
     MissingSession,
+
+    /// The room key can't be shared because some devices of the recipients
+    /// don't satisfy the trust requirement of the encryption settings.
+    ///
+    /// The devices are grouped by user. They need to be verified, or
+    /// blacklisted to be excluded from the room key sharing.
+    #[error("the room key can't be shared because some devices are not trusted: {0:?}")]
+    UntrustedDevices(BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>),
 }
 
 /// Error representing a failure during a group encryption operation.
@@ -119,6 +130,11 @@ pub enum MegolmError {
     /// The storage layer returned an error.
     #[error(transparent)]
     Store(#[from] CryptoStoreError),
+
+    /// The event was decrypted, but the device that sent it doesn't satisfy
+    /// the trust requirement of the decryption settings.
+    #[error("the sender of the event doesn't satisfy the trust requirement: {0:?}")]
+    SenderIdentityNotTrusted(VerificationLevel),
 }
 
 /// Error that occurs when decrypting an event that is malformed.
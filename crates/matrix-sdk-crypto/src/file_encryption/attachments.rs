@@ -42,16 +42,14 @@ type Aes256Ctr = ctr::Ctr128BE<Aes256>;
 /// Matrix attachment.
 pub struct AttachmentDecryptor<'a, R: Read> {
     inner: &'a mut R,
-    expected_hash: Vec<u8>,
-    sha: Sha256,
-    aes: Aes256Ctr,
+    decryptor: AttachmentChunkDecryptor,
 }
 
 impl<'a, R: 'a + Read + std::fmt::Debug> std::fmt::Debug for AttachmentDecryptor<'a, R> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("AttachmentDecryptor")
             .field("inner", &self.inner)
-            .field("expected_hash", &self.expected_hash)
+            .field("expected_hash", &self.decryptor.expected_hash)
             .finish()
     }
 }
@@ -61,22 +59,105 @@ impl<'a, R: Read> Read for AttachmentDecryptor<'a, R> {
         let read_bytes = self.inner.read(buf)?;
 
         if read_bytes == 0 {
-            let hash = self.sha.finalize_reset();
-
-            if hash.as_slice() == self.expected_hash.as_slice() {
-                Ok(0)
-            } else {
-                Err(IoError::new(ErrorKind::Other, "Hash mismatch while decrypting"))
-            }
+            self.decryptor.verify_hash()?;
+            Ok(0)
         } else {
-            self.sha.update(&buf[0..read_bytes]);
-            self.aes.apply_keystream(&mut buf[0..read_bytes]);
-
+            self.decryptor.decrypt_chunk(&mut buf[0..read_bytes]);
             Ok(read_bytes)
         }
     }
 }
 
+/// A decryptor for a Matrix attachment that is received in chunks, like a
+/// streamed download.
+///
+/// Contrary to [`AttachmentDecryptor`], it doesn't need a reader over the whole
+/// encrypted data.
+pub struct AttachmentChunkDecryptor {
+    expected_hash: Vec<u8>,
+    sha: Sha256,
+    aes: Aes256Ctr,
+}
+
+impl std::fmt::Debug for AttachmentChunkDecryptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AttachmentChunkDecryptor")
+            .field("expected_hash", &self.expected_hash)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AttachmentChunkDecryptor {
+    /// Create a decryptor for the attachment with the given encryption info.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::{Cursor, Read};
+    /// # use matrix_sdk_crypto::{AttachmentChunkDecryptor, AttachmentEncryptor};
+    /// let data = "Hello world".to_owned();
+    /// let mut cursor = Cursor::new(data.clone());
+    ///
+    /// let mut encryptor = AttachmentEncryptor::new(&mut cursor);
+    ///
+    /// let mut encrypted = Vec::new();
+    /// encryptor.read_to_end(&mut encrypted).unwrap();
+    /// let info = encryptor.finish();
+    ///
+    /// let mut decryptor = AttachmentChunkDecryptor::new(info).unwrap();
+    /// for chunk in encrypted.chunks_mut(4) {
+    ///     decryptor.decrypt_chunk(chunk);
+    /// }
+    /// decryptor.verify_hash().unwrap();
+    ///
+    /// assert_eq!(encrypted, data.as_bytes());
+    /// ```
+    pub fn new(info: MediaEncryptionInfo) -> Result<Self, DecryptorError> {
+        if info.version != VERSION {
+            return Err(DecryptorError::UnknownVersion);
+        }
+
+        let hash =
+            info.hashes.get("sha256").ok_or(DecryptorError::MissingHash)?.as_bytes().to_owned();
+        let mut key = info.key.k.into_inner();
+        let iv = info.iv.into_inner();
+
+        if key.len() != KEY_SIZE {
+            return Err(DecryptorError::KeyNonceLength);
+        }
+
+        let key_array = GenericArray::from_slice(&key);
+        let iv = GenericArray::from_exact_iter(iv).ok_or(DecryptorError::KeyNonceLength)?;
+
+        let sha = Sha256::default();
+
+        let aes = Aes256Ctr::new(key_array, &iv);
+        key.zeroize();
+
+        Ok(Self { expected_hash: hash, sha, aes })
+    }
+
+    /// Decrypt the next chunk of the attachment in place.
+    ///
+    /// The decrypted data must not be trusted before
+    /// [`verify_hash()`](Self::verify_hash) succeeds.
+    pub fn decrypt_chunk(&mut self, chunk: &mut [u8]) {
+        self.sha.update(&*chunk);
+        self.aes.apply_keystream(chunk);
+    }
+
+    /// Check that the hash of all the chunks decrypted since the last call
+    /// matches the one of the encryption info.
+    pub fn verify_hash(&mut self) -> std::io::Result<()> {
+        let hash = self.sha.finalize_reset();
+
+        if hash.as_slice() == self.expected_hash.as_slice() {
+            Ok(())
+        } else {
+            Err(IoError::new(ErrorKind::Other, "Hash mismatch while decrypting"))
+        }
+    }
+}
+
 /// Error type for attachment decryption.
 #[derive(Error, Debug)]
 pub enum DecryptorError {
@@ -130,28 +211,8 @@ impl<'a, R: Read + 'a> AttachmentDecryptor<'a, R> {
         input: &'a mut R,
         info: MediaEncryptionInfo,
     ) -> Result<AttachmentDecryptor<'a, R>, DecryptorError> {
-        if info.version != VERSION {
-            return Err(DecryptorError::UnknownVersion);
-        }
-
-        let hash =
-            info.hashes.get("sha256").ok_or(DecryptorError::MissingHash)?.as_bytes().to_owned();
-        let mut key = info.key.k.into_inner();
-        let iv = info.iv.into_inner();
-
-        if key.len() != KEY_SIZE {
-            return Err(DecryptorError::KeyNonceLength);
-        }
-
-        let key_array = GenericArray::from_slice(&key);
-        let iv = GenericArray::from_exact_iter(iv).ok_or(DecryptorError::KeyNonceLength)?;
-
-        let sha = Sha256::default();
-
-        let aes = Aes256Ctr::new(key_array, &iv);
-        key.zeroize();
-
-        Ok(AttachmentDecryptor { inner: input, expected_hash: hash, sha, aes })
+        let decryptor = AttachmentChunkDecryptor::new(info)?;
+        Ok(AttachmentDecryptor { inner: input, decryptor })
     }
 }
 
@@ -304,7 +365,9 @@ mod tests {
 
     use serde_json::json;
 
-    use super::{AttachmentDecryptor, AttachmentEncryptor, MediaEncryptionInfo};
+    use super::{
+        AttachmentChunkDecryptor, AttachmentDecryptor, AttachmentEncryptor, MediaEncryptionInfo,
+    };
 
     const EXAMPLE_DATA: &[u8] = &[
         179, 154, 118, 127, 186, 127, 110, 33, 203, 33, 33, 134, 67, 100, 173, 46, 235, 27, 215,
@@ -368,6 +431,19 @@ mod tests {
         assert_eq!("It's a secret to everybody", decrypted);
     }
 
+    #[test]
+    fn real_decrypt_chunks() {
+        let mut data = EXAMPLE_DATA.to_vec();
+        let mut decryptor = AttachmentChunkDecryptor::new(example_key()).unwrap();
+
+        for chunk in data.chunks_mut(5) {
+            decryptor.decrypt_chunk(chunk);
+        }
+        decryptor.verify_hash().unwrap();
+
+        assert_eq!(data, b"It's a secret to everybody");
+    }
+
     #[test]
     fn decrypt_invalid_hash() {
         let mut cursor = Cursor::new("fake message");
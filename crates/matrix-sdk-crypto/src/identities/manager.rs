@@ -556,6 +556,17 @@ impl IdentityManager {
         if master_key.user_id() != user_id || self_signing.user_id() != user_id {
             warn!(?user_id, "User ID mismatch in one of the cross signing keys",);
         } else if let Some(i) = self.store.get_user_identity(user_id).await? {
+            // Remember that we verified this identity before it gets updated, to
+            // be able to detect verification violations.
+            if let ReadOnlyUserIdentities::Other(identity) = &i {
+                let own_identity =
+                    self.store.get_user_identity(self.user_id()).await?.and_then(|i| i.into_own());
+
+                if own_identity.is_some_and(|own| own.is_identity_signed(identity).is_ok()) {
+                    identity.mark_as_previously_verified();
+                }
+            }
+
             match self.handle_changed_identity(response, master_key, self_signing, i).await {
                 Ok(c) => {
                     trace!(identity = ?c.public, "Updated a user identity");
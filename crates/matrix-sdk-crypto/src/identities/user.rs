@@ -17,7 +17,7 @@ use std::{
     ops::Deref,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, RwLock as StdRwLock,
     },
 };
 
@@ -34,7 +34,7 @@ use tracing::error;
 use super::{atomic_bool_deserializer, atomic_bool_serializer};
 use crate::{
     error::SignatureError,
-    store::{Changes, IdentityChanges},
+    store::{Changes, IdentityChanges, Store},
     types::{MasterPubkey, SelfSigningPubkey, UserSigningPubkey},
     verification::VerificationMachine,
     CryptoStoreError, OutgoingVerificationRequest, ReadOnlyDevice, VerificationRequest,
@@ -187,6 +187,7 @@ pub struct UserIdentity {
     pub(crate) inner: ReadOnlyUserIdentity,
     pub(crate) own_identity: Option<ReadOnlyOwnUserIdentity>,
     pub(crate) verification_machine: VerificationMachine,
+    pub(crate) store: Store,
 }
 
 impl Deref for UserIdentity {
@@ -203,6 +204,43 @@ impl UserIdentity {
         self.own_identity.as_ref().is_some_and(|o| o.is_identity_signed(&self.inner).is_ok())
     }
 
+    /// Whether this user identity was verified before, but isn't anymore.
+    ///
+    /// This happens when the user resets their cross-signing identity after we
+    /// verified them. It can be resolved by verifying the new identity, or by
+    /// accepting that it isn't verified anymore with
+    /// [`UserIdentity::withdraw_verification()`].
+    pub fn has_verification_violation(&self) -> bool {
+        self.inner.was_previously_verified() && !self.is_verified()
+    }
+
+    /// Accept the current master key of this user identity.
+    ///
+    /// After this, [`ReadOnlyUserIdentity::has_pin_violation()`] returns
+    /// `false` until the master key changes again.
+    pub async fn pin_current_master_key(&self) -> Result<(), CryptoStoreError> {
+        self.inner.pin_current_master_key();
+        self.save().await
+    }
+
+    /// Stop considering that this user identity was verified before.
+    ///
+    /// After this, [`UserIdentity::has_verification_violation()`] returns
+    /// `false` until the identity is verified again.
+    pub async fn withdraw_verification(&self) -> Result<(), CryptoStoreError> {
+        self.inner.withdraw_verification();
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<(), CryptoStoreError> {
+        let changes = Changes {
+            identities: IdentityChanges { changed: vec![self.inner.clone().into()], new: vec![] },
+            ..Default::default()
+        };
+
+        self.store.save_changes(changes).await
+    }
+
     /// Manually verify this user.
     ///
     /// This method will attempt to sign the user identity using our private
@@ -356,10 +394,57 @@ impl PartialEq for ReadOnlyUserIdentities {
 /// only contain a master key and a self signing key, meaning that only device
 /// signatures can be checked with this identity.
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(from = "ReadOnlyUserIdentitySerializer", into = "ReadOnlyUserIdentitySerializer")]
 pub struct ReadOnlyUserIdentity {
     user_id: OwnedUserId,
     pub(crate) master_key: MasterPubkey,
     self_signing_key: SelfSigningPubkey,
+    /// The master key that was approved for this user, either because it is
+    /// the first one we saw, or because it was pinned explicitly.
+    pinned_master_key: Arc<StdRwLock<MasterPubkey>>,
+    /// Whether we verified this user identity at some point, even if it has
+    /// changed since.
+    previously_verified: Arc<AtomicBool>,
+}
+
+/// The serialized form of a [`ReadOnlyUserIdentity`].
+#[derive(Deserialize, Serialize)]
+struct ReadOnlyUserIdentitySerializer {
+    user_id: OwnedUserId,
+    master_key: MasterPubkey,
+    self_signing_key: SelfSigningPubkey,
+    // Identities that were stored before pinning was introduced pin their
+    // current master key.
+    #[serde(default)]
+    pinned_master_key: Option<MasterPubkey>,
+    #[serde(default)]
+    previously_verified: bool,
+}
+
+impl From<ReadOnlyUserIdentitySerializer> for ReadOnlyUserIdentity {
+    fn from(value: ReadOnlyUserIdentitySerializer) -> Self {
+        let pinned_master_key = value.pinned_master_key.unwrap_or_else(|| value.master_key.clone());
+
+        Self {
+            user_id: value.user_id,
+            master_key: value.master_key,
+            self_signing_key: value.self_signing_key,
+            pinned_master_key: Arc::new(StdRwLock::new(pinned_master_key)),
+            previously_verified: Arc::new(AtomicBool::new(value.previously_verified)),
+        }
+    }
+}
+
+impl From<ReadOnlyUserIdentity> for ReadOnlyUserIdentitySerializer {
+    fn from(value: ReadOnlyUserIdentity) -> Self {
+        Self {
+            pinned_master_key: Some(value.pinned_master_key()),
+            previously_verified: value.was_previously_verified(),
+            user_id: value.user_id,
+            master_key: value.master_key,
+            self_signing_key: value.self_signing_key,
+        }
+    }
 }
 
 impl ReadOnlyUserIdentity {
@@ -379,7 +464,13 @@ impl ReadOnlyUserIdentity {
     ) -> Result<Self, SignatureError> {
         master_key.verify_subkey(&self_signing_key)?;
 
-        Ok(Self { user_id: master_key.user_id().into(), master_key, self_signing_key })
+        Ok(Self {
+            user_id: master_key.user_id().into(),
+            pinned_master_key: Arc::new(StdRwLock::new(master_key.clone())),
+            master_key,
+            self_signing_key,
+            previously_verified: Arc::new(AtomicBool::new(false)),
+        })
     }
 
     #[cfg(test)]
@@ -388,7 +479,13 @@ impl ReadOnlyUserIdentity {
         let self_signing_key =
             identity.self_signing_key.lock().await.as_ref().unwrap().public_key.clone();
 
-        Self { user_id: identity.user_id().into(), master_key, self_signing_key }
+        Self {
+            user_id: identity.user_id().into(),
+            pinned_master_key: Arc::new(StdRwLock::new(master_key.clone())),
+            master_key,
+            self_signing_key,
+            previously_verified: Arc::new(AtomicBool::new(false)),
+        }
     }
 
     /// Get the user id of this identity.
@@ -406,8 +503,42 @@ impl ReadOnlyUserIdentity {
         &self.self_signing_key
     }
 
+    /// Get the master key that was approved for this identity.
+    ///
+    /// This is the first master key we saw for this user, or the one that was
+    /// pinned with [`UserIdentity::pin_current_master_key()`].
+    pub fn pinned_master_key(&self) -> MasterPubkey {
+        self.pinned_master_key.read().unwrap().clone()
+    }
+
+    /// Whether the master key of this identity changed since it was pinned.
+    pub fn has_pin_violation(&self) -> bool {
+        *self.pinned_master_key.read().unwrap() != self.master_key
+    }
+
+    pub(crate) fn pin_current_master_key(&self) {
+        *self.pinned_master_key.write().unwrap() = self.master_key.clone();
+    }
+
+    /// Whether we verified this identity at some point, even if it has changed
+    /// since.
+    pub fn was_previously_verified(&self) -> bool {
+        self.previously_verified.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn mark_as_previously_verified(&self) {
+        self.previously_verified.store(true, Ordering::SeqCst)
+    }
+
+    pub(crate) fn withdraw_verification(&self) {
+        self.previously_verified.store(false, Ordering::SeqCst)
+    }
+
     /// Update the identity with a new master key and self signing key.
     ///
+    /// The pinned master key is kept, so a change of master key can be
+    /// detected with [`ReadOnlyUserIdentity::has_pin_violation()`].
+    ///
     /// # Arguments
     ///
     /// * `master_key` - The new master key of the user identity.
@@ -722,7 +853,7 @@ pub(crate) mod tests {
 
     use super::{
         testing::{device, get_other_identity, get_own_identity},
-        ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities,
+        ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities, ReadOnlyUserIdentity,
     };
     use crate::{
         identities::{manager::testing::own_key_query, Device},
@@ -757,6 +888,29 @@ pub(crate) mod tests {
         get_other_identity();
     }
 
+    #[test]
+    fn other_identity_pinning_serialization() {
+        let identity = get_other_identity();
+        assert!(!identity.has_pin_violation());
+        assert!(!identity.was_previously_verified());
+
+        identity.mark_as_previously_verified();
+        let json = serde_json::to_value(&identity).unwrap();
+        let deserialized: ReadOnlyUserIdentity = serde_json::from_value(json.clone()).unwrap();
+        assert!(deserialized.was_previously_verified());
+        assert_eq!(deserialized.pinned_master_key(), *identity.master_key());
+
+        // Identities stored before pinning was introduced pin their current
+        // master key.
+        let mut json = json;
+        let object = json.as_object_mut().unwrap();
+        object.remove("pinned_master_key");
+        object.remove("previously_verified");
+        let deserialized: ReadOnlyUserIdentity = serde_json::from_value(json).unwrap();
+        assert!(!deserialized.was_previously_verified());
+        assert!(!deserialized.has_pin_violation());
+    }
+
     #[test]
     fn own_identity_check_signatures() {
         let response = own_key_query();
@@ -64,7 +64,7 @@ use crate::{
     olm::{
         Account, CrossSigningStatus, EncryptionSettings, ExportedRoomKey, IdentityKeys,
         InboundGroupSession, OlmDecryptionInfo, PrivateCrossSigningIdentity, ReadOnlyAccount,
-        SessionType,
+        SessionType, TrustRequirement,
     },
     requests::{IncomingResponse, OutgoingRequest, UploadSigningKeysRequest},
     session_manager::{GroupSessionManager, SessionManager},
@@ -739,18 +739,46 @@ impl OlmMachine {
         }
     }
 
+    #[instrument(skip_all, fields(sender = ?event.sender, code = ?event.content.withheld_code()))]
     async fn add_withheld_info(&self, changes: &mut Changes, event: &RoomKeyWithheldEvent) {
-        if let RoomKeyWithheldContent::MegolmV1AesSha2(
+        let content = match &event.content {
+            RoomKeyWithheldContent::MegolmV1AesSha2(c) => c,
+            #[cfg(feature = "experimental-algorithms")]
+            RoomKeyWithheldContent::MegolmV2AesSha2(c) => c,
+            RoomKeyWithheldContent::Unknown(_) => {
+                debug!("Ignoring a room key withheld event with an unsupported algorithm");
+                return;
+            }
+        };
+
+        let c = match content {
             MegolmV1AesSha2WithheldContent::BlackListed(c)
-            | MegolmV1AesSha2WithheldContent::Unverified(c),
-        ) = &event.content
-        {
-            changes
-                .withheld_session_info
-                .entry(c.room_id.to_owned())
-                .or_default()
-                .insert(c.session_id.to_owned(), event.to_owned());
-        }
+            | MegolmV1AesSha2WithheldContent::Unverified(c) => c,
+            // Those codes are also sent by our own devices in response to room
+            // key requests, in which case they don't come from the owner of
+            // the session.
+            MegolmV1AesSha2WithheldContent::Unauthorised(c)
+            | MegolmV1AesSha2WithheldContent::Unavailable(c)
+                if &*event.sender != self.user_id() =>
+            {
+                c
+            }
+            MegolmV1AesSha2WithheldContent::Unauthorised(_)
+            | MegolmV1AesSha2WithheldContent::Unavailable(_) => {
+                debug!("Ignoring a room key withheld event in response to a room key request");
+                return;
+            }
+            // This isn't about a specific session.
+            MegolmV1AesSha2WithheldContent::NoOlm(_) => return,
+        };
+
+        debug!(room_id = ?c.room_id, session_id = c.session_id, "Received a room key withheld event");
+
+        changes
+            .withheld_session_info
+            .entry(c.room_id.to_owned())
+            .or_default()
+            .insert(c.session_id.to_owned(), event.to_owned());
     }
 
     #[cfg(test)]
@@ -867,6 +895,51 @@ impl OlmMachine {
         self.inner.group_session_manager.share_room_key(room_id, users, encryption_settings).await
     }
 
+    /// Get to-device requests to share the room keys of a room with a user
+    /// that was invited to it.
+    ///
+    /// Only the room keys that were created while the history visibility of
+    /// the room was `shared` or `world_readable` are shared, see [MSC3061].
+    ///
+    /// The devices of the user need to be known and Olm sessions need to be
+    /// established with them beforehand, using
+    /// [`OlmMachine::update_tracked_users()`] and
+    /// [`OlmMachine::get_missing_sessions()`].
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The room id of the room the user was invited to.
+    ///
+    /// `user_id` - The user that was invited to the room.
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    pub async fn share_room_key_history(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> OlmResult<Vec<Arc<ToDeviceRequest>>> {
+        self.inner.group_session_manager.share_room_key_history(room_id, user_id).await
+    }
+
+    /// Get the room keys of a room that can be shared with a newly invited or
+    /// joined user, with [`OlmMachine::share_room_key_history()`].
+    ///
+    /// Only the room keys that were created while the history visibility of
+    /// the room was `shared` or `world_readable` can be shared, see
+    /// [MSC3061].
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The room id of the room the user was invited to or joined.
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    pub async fn shareable_room_key_history(
+        &self,
+        room_id: &RoomId,
+    ) -> StoreResult<Vec<InboundGroupSession>> {
+        self.inner.group_session_manager.shareable_room_key_history(room_id).await
+    }
+
     /// Receive an unencrypted verification event.
     ///
     /// This method can be used to pass verification events that are happening
@@ -1380,18 +1453,76 @@ impl OlmMachine {
         }
     }
 
+    /// Check that the sender of an event decrypted with the given encryption
+    /// info satisfies the given trust requirement.
+    async fn check_sender_trust_requirement(
+        &self,
+        encryption_info: &EncryptionInfo,
+        trust_requirement: TrustRequirement,
+    ) -> MegolmResult<()> {
+        let VerificationState::Unverified(level) = &encryption_info.verification_state else {
+            return Ok(());
+        };
+
+        let is_trusted = match (trust_requirement, level) {
+            (TrustRequirement::Untrusted, _) => true,
+            (
+                TrustRequirement::CrossSignedOrLegacy | TrustRequirement::CrossSigned,
+                VerificationLevel::UnverifiedIdentity,
+            ) => true,
+            // The device isn't cross-signed, which is only accepted if its
+            // owner doesn't have a cross-signing identity.
+            (TrustRequirement::CrossSignedOrLegacy, VerificationLevel::UnsignedDevice) => {
+                self.store().get_identity(&encryption_info.sender).await?.is_none()
+            }
+            _ => false,
+        };
+
+        if is_trusted {
+            Ok(())
+        } else {
+            Err(MegolmError::SenderIdentityNotTrusted(level.clone()))
+        }
+    }
+
     /// Decrypt an event from a room timeline.
     ///
+    /// This accepts events from all the devices, use
+    /// [`OlmMachine::decrypt_room_event_with_settings()`] to reject events from
+    /// untrusted devices.
+    ///
     /// # Arguments
     ///
     /// * `event` - The event that should be decrypted.
     ///
     /// * `room_id` - The ID of the room where the event was sent to.
-    #[instrument(skip_all, fields(?room_id, event_id, sender, algorithm, session_id, sender_key))]
     pub async fn decrypt_room_event(
         &self,
         event: &Raw<EncryptedEvent>,
         room_id: &RoomId,
+    ) -> MegolmResult<TimelineEvent> {
+        self.decrypt_room_event_with_settings(event, room_id, &DecryptionSettings::default()).await
+    }
+
+    /// Decrypt an event from a room timeline, with the given settings.
+    ///
+    /// If the device that sent the event doesn't satisfy the trust requirement
+    /// of the settings, this fails with
+    /// [`MegolmError::SenderIdentityNotTrusted`].
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event that should be decrypted.
+    ///
+    /// * `room_id` - The ID of the room where the event was sent to.
+    ///
+    /// * `settings` - The settings to use for the decryption.
+    #[instrument(skip_all, fields(?room_id, event_id, sender, algorithm, session_id, sender_key))]
+    pub async fn decrypt_room_event_with_settings(
+        &self,
+        event: &Raw<EncryptedEvent>,
+        room_id: &RoomId,
+        settings: &DecryptionSettings,
     ) -> MegolmResult<TimelineEvent> {
         let event = event.deserialize()?;
 
@@ -1414,7 +1545,16 @@ impl OlmMachine {
         };
 
         tracing::Span::current().record("session_id", content.session_id());
-        let result = self.decrypt_megolm_events(room_id, &event, &content).await;
+        let mut result = self.decrypt_megolm_events(room_id, &event, &content).await;
+
+        if let Ok(TimelineEvent { encryption_info: Some(encryption_info), .. }) = &result {
+            let requirement = settings.sender_device_trust_requirement;
+
+            if let Err(e) = self.check_sender_trust_requirement(encryption_info, requirement).await
+            {
+                result = Err(e);
+            }
+        }
 
         if let Err(e) = &result {
             #[cfg(feature = "automatic-room-key-forwarding")]
@@ -1926,6 +2066,18 @@ impl OlmMachine {
     }
 }
 
+/// Settings for decrypting room events.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecryptionSettings {
+    /// The trust that the device that sent an event needs for the event to be
+    /// decrypted.
+    ///
+    /// Events sent by devices that don't satisfy it fail to decrypt with
+    /// [`MegolmError::SenderIdentityNotTrusted`]. Defaults to
+    /// [`TrustRequirement::Untrusted`].
+    pub sender_device_trust_requirement: TrustRequirement,
+}
+
 /// Data contained from a sync response and that needs to be processed by the
 /// OlmMachine.
 #[derive(Debug)]
@@ -2014,8 +2166,8 @@ pub(crate) mod tests {
         },
         utilities::json_convert,
         verification::tests::{outgoing_request_to_event, request_to_event},
-        EncryptionSettings, LocalTrust, MegolmError, OlmError, ReadOnlyDevice, ToDeviceRequest,
-        UserIdentities,
+        DecryptionSettings, EncryptionSettings, LocalTrust, MegolmError, OlmError, ReadOnlyDevice,
+        ToDeviceRequest, TrustRequirement, UserIdentities,
     };
 
     /// These keys need to be periodically uploaded to the server.
@@ -2722,6 +2874,45 @@ pub(crate) mod tests {
         assert_matches!(err, MegolmError::MissingRoomKey(Some(WithheldCode::Unverified)));
     }
 
+    #[async_test]
+    async fn test_withheld_unauthorised() {
+        let (alice, bob) = get_machine_pair_with_setup_sessions(alice_id(), user_id(), false).await;
+        let room_id = room_id!("!test:example.org");
+
+        let withheld_event = |sender: &UserId, session_id: &str| {
+            let content = RoomKeyWithheldContent::new(
+                EventEncryptionAlgorithm::MegolmV1AesSha2,
+                WithheldCode::Unauthorised,
+                room_id.to_owned(),
+                session_id.to_owned(),
+                alice.identity_keys().curve25519,
+                alice.device_id().to_owned(),
+            );
+            json_convert(&ToDeviceEvent::new(sender.to_owned(), content)).unwrap()
+        };
+
+        // The second event looks like a response to a room key request from one
+        // of our own devices.
+        bob.receive_sync_changes(EncryptionSyncChanges {
+            to_device_events: vec![
+                withheld_event(alice.user_id(), "alice_session"),
+                withheld_event(bob.user_id(), "own_session"),
+            ],
+            changed_devices: &Default::default(),
+            one_time_keys_counts: &Default::default(),
+            unused_fallback_keys: None,
+            next_batch_token: None,
+        })
+        .await
+        .unwrap();
+
+        let withheld = bob.store().get_withheld_info(room_id, "alice_session").await.unwrap();
+        assert_eq!(withheld.unwrap().content.withheld_code(), WithheldCode::Unauthorised);
+
+        let withheld = bob.store().get_withheld_info(room_id, "own_session").await.unwrap();
+        assert!(withheld.is_none());
+    }
+
     #[async_test]
     async fn test_decryption_verification_state() {
         macro_rules! assert_shield {
@@ -3049,6 +3240,79 @@ pub(crate) mod tests {
             .is_verified());
     }
 
+    #[async_test]
+    async fn test_decryption_sender_trust_requirement() {
+        let (alice, bob) = get_machine_pair_with_setup_sessions(alice_id(), user_id(), false).await;
+        let room_id = room_id!("!test:example.org");
+
+        let to_device_requests = alice
+            .share_room_key(room_id, iter::once(bob.user_id()), EncryptionSettings::default())
+            .await
+            .unwrap();
+
+        let event = ToDeviceEvent::new(
+            alice.user_id().to_owned(),
+            to_device_requests_to_content(to_device_requests),
+        );
+
+        let group_session = bob
+            .decrypt_to_device_event(&event, &mut Changes::default())
+            .await
+            .unwrap()
+            .inbound_group_session;
+        bob.store().save_inbound_group_sessions(&[group_session.unwrap()]).await.unwrap();
+
+        let content = RoomMessageEventContent::text_plain("It is a secret to everybody");
+        let encrypted_content = alice
+            .encrypt_room_event(room_id, AnyMessageLikeEventContent::RoomMessage(content))
+            .await
+            .unwrap();
+
+        let event = json!({
+            "event_id": "$xxxxx:example.org",
+            "origin_server_ts": MilliSecondsSinceUnixEpoch::now(),
+            "sender": alice.user_id(),
+            "type": "m.room.encrypted",
+            "content": encrypted_content,
+        });
+        let event = json_convert(&event).unwrap();
+
+        let settings = |sender_device_trust_requirement| DecryptionSettings {
+            sender_device_trust_requirement,
+        };
+
+        // Alice doesn't have a cross-signing identity, so her device is accepted
+        // as a legacy device.
+        bob.decrypt_room_event_with_settings(
+            &event,
+            room_id,
+            &settings(TrustRequirement::CrossSignedOrLegacy),
+        )
+        .await
+        .unwrap();
+
+        let result = bob
+            .decrypt_room_event_with_settings(
+                &event,
+                room_id,
+                &settings(TrustRequirement::CrossSigned),
+            )
+            .await;
+        assert_matches!(
+            result,
+            Err(MegolmError::SenderIdentityNotTrusted(VerificationLevel::UnsignedDevice))
+        );
+
+        let result = bob
+            .decrypt_room_event_with_settings(
+                &event,
+                room_id,
+                &settings(TrustRequirement::VerifiedOnly),
+            )
+            .await;
+        assert_matches!(result, Err(MegolmError::SenderIdentityNotTrusted(_)));
+    }
+
     #[async_test]
     async fn test_verification_states_multiple_device() {
         let (bob, _) = get_prepared_machine(user_id(), false).await;
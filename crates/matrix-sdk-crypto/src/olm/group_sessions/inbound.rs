@@ -362,6 +362,20 @@ impl InboundGroupSession {
         self.imported
     }
 
+    /// Was the room history visible to newly invited or joined members when
+    /// this session was created?
+    ///
+    /// Such sessions can be shared with users that are invited to the room
+    /// later on, as described in [MSC3061].
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    pub fn shared_history(&self) -> bool {
+        matches!(
+            self.history_visibility.as_ref(),
+            Some(HistoryVisibility::Shared | HistoryVisibility::WorldReadable)
+        )
+    }
+
     /// Check if the `InboundGroupSession` is better than the given other
     /// `InboundGroupSession`
     pub async fn compare(&self, other: &InboundGroupSession) -> SessionOrdering {
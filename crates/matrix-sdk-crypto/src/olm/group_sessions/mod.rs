@@ -22,10 +22,14 @@ pub use inbound::{InboundGroupSession, PickledInboundGroupSession};
 pub(crate) use outbound::ShareState;
 pub use outbound::{
     EncryptionSettings, GroupSession, OutboundGroupSession, PickledOutboundGroupSession, ShareInfo,
+    TrustRequirement,
 };
 use thiserror::Error;
 pub use vodozemac::megolm::{ExportedSessionKey, SessionKey};
-use vodozemac::{megolm::SessionKeyDecodeError, Curve25519PublicKey};
+use vodozemac::{
+    megolm::{InboundGroupSession as InnerSession, SessionConfig, SessionKeyDecodeError},
+    Curve25519PublicKey,
+};
 
 #[cfg(feature = "experimental-algorithms")]
 use crate::types::events::forwarded_room_key::ForwardedMegolmV2AesSha2Content;
@@ -121,6 +125,30 @@ pub struct BackedUpRoomKey {
     pub forwarding_curve25519_key_chain: Vec<Curve25519PublicKey>,
 }
 
+impl ExportedRoomKey {
+    /// Create an exported room key from a room key that was downloaded from
+    /// the key backup.
+    ///
+    /// The backed up room key doesn't contain the session ID, so it is
+    /// computed from the session key, instead of trusting the ID under which
+    /// the room key was stored in the backup.
+    pub fn from_backed_up_room_key(room_id: OwnedRoomId, room_key: BackedUpRoomKey) -> Self {
+        // The session config doesn't matter here, we only need the session ID.
+        let session_id =
+            InnerSession::import(&room_key.session_key, SessionConfig::default()).session_id();
+
+        Self {
+            algorithm: room_key.algorithm,
+            room_id,
+            sender_key: room_key.sender_key,
+            session_id,
+            session_key: room_key.session_key,
+            sender_claimed_keys: room_key.sender_claimed_keys,
+            forwarding_curve25519_key_chain: room_key.forwarding_curve25519_key_chain,
+        }
+    }
+}
+
 impl TryFrom<ExportedRoomKey> for ForwardedRoomKeyContent {
     type Error = SessionExportError;
 
@@ -85,6 +85,10 @@ pub struct EncryptionSettings {
     /// excluded from the conversation.
     #[serde(default)]
     pub only_allow_trusted_devices: bool,
+    /// The trust that the devices of the recipients need to receive the room
+    /// key.
+    #[serde(default)]
+    pub trust_requirement: TrustRequirement,
 }
 
 impl Default for EncryptionSettings {
@@ -95,6 +99,7 @@ impl Default for EncryptionSettings {
             rotation_period_msgs: ROTATION_MESSAGES,
             history_visibility: HistoryVisibility::Shared,
             only_allow_trusted_devices: false,
+            trust_requirement: TrustRequirement::default(),
         }
     }
 }
@@ -119,6 +124,51 @@ impl EncryptionSettings {
             rotation_period_msgs,
             history_visibility,
             only_allow_trusted_devices,
+            trust_requirement: TrustRequirement::default(),
+        }
+    }
+}
+
+/// The trust that devices need to receive a room key, or to be trusted as the
+/// senders of events.
+///
+/// When a room key is shared, if some devices of the recipients don't satisfy
+/// this requirement, the sharing fails with [`OlmError::UntrustedDevices`].
+/// Blacklisted devices never receive the room key, so they are not considered.
+///
+/// When an event is decrypted with [`DecryptionSettings`], if the device that
+/// sent it doesn't satisfy this requirement, the decryption fails with
+/// [`MegolmError::SenderIdentityNotTrusted`].
+///
+/// [`OlmError::UntrustedDevices`]: crate::OlmError::UntrustedDevices
+/// [`DecryptionSettings`]: crate::DecryptionSettings
+/// [`MegolmError::SenderIdentityNotTrusted`]: crate::MegolmError::SenderIdentityNotTrusted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TrustRequirement {
+    /// All the devices can receive the room key.
+    #[default]
+    Untrusted,
+    /// Only the devices that are cross-signed by their owner can receive the
+    /// room key, except for the devices of users who don't have a
+    /// cross-signing identity.
+    CrossSignedOrLegacy,
+    /// Only the devices that are cross-signed by their owner can receive the
+    /// room key.
+    CrossSigned,
+    /// Only the devices that we verified can receive the room key.
+    VerifiedOnly,
+}
+
+impl TrustRequirement {
+    /// Whether the given device satisfies this requirement.
+    pub(crate) fn is_satisfied_by(self, device: &Device) -> bool {
+        match self {
+            Self::Untrusted => true,
+            Self::CrossSignedOrLegacy => {
+                device.device_owner_identity.is_none() || device.is_cross_signed_by_owner()
+            }
+            Self::CrossSigned => device.is_cross_signed_by_owner(),
+            Self::VerifiedOnly => device.is_verified(),
         }
     }
 }
@@ -0,0 +1,477 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for the [secret storage] of the Matrix specification.
+//!
+//! Secrets, like the private cross-signing keys or the backup decryption key,
+//! are stored encrypted in the account data of the user. They are encrypted
+//! with a secret storage key, that the user either saves as a base58 encoded
+//! recovery key, or derives from a passphrase.
+//!
+//! [secret storage]: https://spec.matrix.org/v1.8/client-server-api/#storage
+
+use std::fmt;
+
+use aes::{
+    cipher::{generic_array::GenericArray, KeyIvInit, StreamCipher},
+    Aes256,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2;
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    thread_rng, RngCore,
+};
+use ruma::events::secret::request::SecretName;
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Sha512};
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::utilities::{
+    decode, decode_recovery_key, encode, encode_recovery_key, format_recovery_key,
+    DecodeError as Base64DecodeError, RecoveryKeyDecodeError,
+};
+
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+/// The only secret storage algorithm defined by the specification.
+pub const SECRET_STORAGE_ALGORITHM_V1_AES_HMAC_SHA2: &str = "m.secret_storage.v1.aes-hmac-sha2";
+
+/// The key derivation algorithm used for passphrases.
+const PBKDF2_ALGORITHM: &str = "m.pbkdf2";
+
+const KEY_SIZE: usize = 32;
+const IV_SIZE: usize = 16;
+const KEY_ID_SIZE: usize = 32;
+const SALT_SIZE: usize = 32;
+const PBKDF2_ITERATIONS: u32 = 500_000;
+
+/// Error type for the decoding and checking of a [`SecretStorageKey`].
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    /// The key uses an unsupported algorithm.
+    #[error("The secret storage key uses an unsupported algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    /// The passphrase uses an unsupported key derivation algorithm.
+    #[error("The passphrase uses an unsupported key derivation algorithm: {0}")]
+    UnsupportedKeyDerivation(String),
+    /// The decoded recovery key has an invalid prefix.
+    #[error("The decoded recovery key has an invalid prefix: expected {0:?}, got {1:?}")]
+    Prefix([u8; 2], [u8; 2]),
+    /// The parity byte of the recovery key didn't match.
+    #[error("The parity byte of the recovery key doesn't match: expected {0:?}, got {1:?}")]
+    Parity(u8, u8),
+    /// The recovery key isn't valid base58.
+    #[error(transparent)]
+    Base58(#[from] bs58::decode::Error),
+    /// Some data isn't valid base64.
+    #[error(transparent)]
+    Base64(#[from] Base64DecodeError),
+    /// The recovery key is too short or too long.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The recovery key or passphrase doesn't match the key description in the
+    /// account data.
+    #[error("The recovery key or passphrase doesn't match the secret storage key")]
+    KeyMismatch,
+}
+
+impl From<RecoveryKeyDecodeError> for DecodeError {
+    fn from(value: RecoveryKeyDecodeError) -> Self {
+        match value {
+            RecoveryKeyDecodeError::Prefix(expected, got) => Self::Prefix(expected, got),
+            RecoveryKeyDecodeError::Parity(expected, got) => Self::Parity(expected, got),
+            RecoveryKeyDecodeError::Base58(e) => Self::Base58(e),
+            RecoveryKeyDecodeError::Io(e) => Self::Io(e),
+        }
+    }
+}
+
+/// Error type for the decryption of a secret.
+#[derive(Debug, Error)]
+pub enum SecretDecryptionError {
+    /// The MAC of the encrypted secret doesn't match.
+    #[error("The MAC of the encrypted secret doesn't match")]
+    InvalidMac,
+    /// The IV of the encrypted secret has an invalid length.
+    #[error("The IV of the encrypted secret has an invalid length: expected {0}, got {1}")]
+    IvLength(usize, usize),
+    /// Some data isn't valid base64.
+    #[error(transparent)]
+    Base64(#[from] Base64DecodeError),
+    /// The decrypted secret isn't valid UTF-8.
+    #[error(transparent)]
+    Utf8(#[from] std::string::FromUtf8Error),
+}
+
+/// The parameters used to derive a [`SecretStorageKey`] from a passphrase.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PassPhrase {
+    /// The key derivation algorithm, only `m.pbkdf2` is supported.
+    pub algorithm: String,
+    /// The salt used for the key derivation.
+    pub salt: String,
+    /// The number of PBKDF2 iterations.
+    pub iterations: u32,
+    /// The number of bits to generate for the key, 256 if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bits: Option<u32>,
+}
+
+/// The content of an `m.secret_storage.key.*` account data event, describing a
+/// secret storage key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretStorageKeyEventContent {
+    /// The name of the key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The encryption algorithm used with this key.
+    pub algorithm: String,
+    /// The parameters used to derive the key from a passphrase, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<PassPhrase>,
+    /// The IV used to check the key, encoded as base64.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iv: Option<String>,
+    /// The MAC used to check the key, encoded as base64.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+}
+
+/// A secret encrypted with the `m.secret_storage.v1.aes-hmac-sha2` algorithm,
+/// as found in the account data.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AesHmacSha2EncryptedData {
+    /// The IV used for the encryption, encoded as base64.
+    pub iv: String,
+    /// The encrypted secret, encoded as base64.
+    pub ciphertext: String,
+    /// The MAC of the encrypted secret, encoded as base64.
+    pub mac: String,
+}
+
+/// A key used to encrypt and decrypt secrets in the secret storage.
+pub struct SecretStorageKey {
+    key_id: String,
+    key: Box<[u8; KEY_SIZE]>,
+    passphrase: Option<PassPhrase>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for SecretStorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretStorageKey").field("key_id", &self.key_id).finish_non_exhaustive()
+    }
+}
+
+impl Drop for SecretStorageKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl SecretStorageKey {
+    /// Create a new random secret storage key.
+    pub fn new() -> Self {
+        let mut key = Box::new([0u8; KEY_SIZE]);
+        thread_rng().fill_bytes(key.as_mut());
+
+        Self { key_id: Self::random_key_id(), key, passphrase: None }
+    }
+
+    /// Create a new secret storage key derived from the given passphrase.
+    pub fn new_from_passphrase(passphrase: &str) -> Self {
+        Self::new_from_passphrase_helper(passphrase, PBKDF2_ITERATIONS)
+    }
+
+    fn new_from_passphrase_helper(passphrase: &str, iterations: u32) -> Self {
+        let salt = Alphanumeric.sample_string(&mut thread_rng(), SALT_SIZE);
+        let passphrase_info =
+            PassPhrase { algorithm: PBKDF2_ALGORITHM.to_owned(), salt, iterations, bits: None };
+
+        let key = Self::derive_key(passphrase, &passphrase_info);
+
+        Self { key_id: Self::random_key_id(), key, passphrase: Some(passphrase_info) }
+    }
+
+    /// Restore the secret storage key with the given ID and description.
+    ///
+    /// The `input` is either the base58 encoded recovery key, or the
+    /// passphrase if the key was derived from one. The key is checked against
+    /// the IV and MAC of the description, if present.
+    pub fn from_account_data(
+        input: &str,
+        key_id: String,
+        content: &SecretStorageKeyEventContent,
+    ) -> Result<Self, DecodeError> {
+        if content.algorithm != SECRET_STORAGE_ALGORITHM_V1_AES_HMAC_SHA2 {
+            return Err(DecodeError::UnsupportedAlgorithm(content.algorithm.clone()));
+        }
+
+        let key = match decode_recovery_key::<KEY_SIZE>(input) {
+            Ok(key) => key,
+            // The input isn't a recovery key, try it as a passphrase.
+            Err(error) => match &content.passphrase {
+                Some(passphrase) => {
+                    if passphrase.algorithm != PBKDF2_ALGORITHM {
+                        return Err(DecodeError::UnsupportedKeyDerivation(
+                            passphrase.algorithm.clone(),
+                        ));
+                    }
+
+                    Self::derive_key(input, passphrase)
+                }
+                None => return Err(error.into()),
+            },
+        };
+
+        let key = Self { key_id, key, passphrase: content.passphrase.clone() };
+
+        if let (Some(iv), Some(mac)) = (&content.iv, &content.mac) {
+            if !key.check(iv, mac)? {
+                return Err(DecodeError::KeyMismatch);
+            }
+        }
+
+        Ok(key)
+    }
+
+    /// The ID of the key, used as a suffix of the type of its account data
+    /// event.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// The type of the account data event describing this key.
+    pub fn event_type(&self) -> String {
+        format!("m.secret_storage.key.{}", self.key_id)
+    }
+
+    /// The description of this key to store in the account data.
+    pub fn event_content(&self) -> SecretStorageKeyEventContent {
+        let (iv, mac) = self.check_values(Self::random_iv());
+
+        SecretStorageKeyEventContent {
+            name: None,
+            algorithm: SECRET_STORAGE_ALGORITHM_V1_AES_HMAC_SHA2.to_owned(),
+            passphrase: self.passphrase.clone(),
+            iv: Some(encode(iv)),
+            mac: Some(mac),
+        }
+    }
+
+    /// Export the key as a base58 encoded recovery key, with spaces every four
+    /// characters.
+    pub fn to_base58(&self) -> String {
+        let encoded = encode_recovery_key(self.key.as_ref());
+        format_recovery_key(&encoded).as_str().to_owned()
+    }
+
+    /// Encrypt the given secret to store it in the account data under the
+    /// given name.
+    pub fn encrypt(&self, secret: &str, secret_name: &SecretName) -> AesHmacSha2EncryptedData {
+        let iv = Self::random_iv();
+        let mut ciphertext = secret.as_bytes().to_vec();
+
+        let mac = self.encrypt_helper(&mut ciphertext, secret_name.as_str(), &iv);
+
+        AesHmacSha2EncryptedData { iv: encode(iv), ciphertext: encode(ciphertext), mac }
+    }
+
+    /// Decrypt the given secret, that was stored in the account data under the
+    /// given name.
+    pub fn decrypt(
+        &self,
+        data: &AesHmacSha2EncryptedData,
+        secret_name: &SecretName,
+    ) -> Result<String, SecretDecryptionError> {
+        let iv = decode(&data.iv)?;
+        let iv: [u8; IV_SIZE] = iv
+            .try_into()
+            .map_err(|iv: Vec<u8>| SecretDecryptionError::IvLength(IV_SIZE, iv.len()))?;
+        let mut ciphertext = decode(&data.ciphertext)?;
+        let mac = decode(&data.mac)?;
+
+        let (aes_key, hmac_key) = self.derive_keys(secret_name.as_str());
+
+        let mut hmac =
+            Hmac::<Sha256>::new_from_slice(hmac_key.as_ref()).expect("Can't create an HMAC object");
+        hmac.update(&ciphertext);
+        hmac.verify_slice(&mac).map_err(|_| SecretDecryptionError::InvalidMac)?;
+
+        let mut aes = Aes256Ctr::new(GenericArray::from_slice(aes_key.as_ref()), &iv.into());
+        aes.apply_keystream(&mut ciphertext);
+
+        let secret = String::from_utf8(ciphertext.clone());
+        ciphertext.zeroize();
+
+        Ok(secret?)
+    }
+
+    fn random_key_id() -> String {
+        Alphanumeric.sample_string(&mut thread_rng(), KEY_ID_SIZE)
+    }
+
+    fn random_iv() -> [u8; IV_SIZE] {
+        let mut iv = [0u8; IV_SIZE];
+        thread_rng().fill_bytes(&mut iv);
+
+        // Clear bit 63 of the IV, to avoid problems with implementations that
+        // use a 64 bit counter.
+        let mut iv = u128::from_be_bytes(iv);
+        iv &= !(1 << 63);
+        iv.to_be_bytes()
+    }
+
+    fn derive_key(passphrase: &str, info: &PassPhrase) -> Box<[u8; KEY_SIZE]> {
+        let mut key = Box::new([0u8; KEY_SIZE]);
+        pbkdf2::<Hmac<Sha512>>(
+            passphrase.as_bytes(),
+            info.salt.as_bytes(),
+            info.iterations,
+            key.as_mut(),
+        );
+
+        key
+    }
+
+    /// Derive the AES and HMAC keys for the secret with the given name.
+    fn derive_keys(&self, name: &str) -> (Zeroizing<[u8; KEY_SIZE]>, Zeroizing<[u8; KEY_SIZE]>) {
+        let hkdf: Hkdf<Sha256> = Hkdf::new(Some(&[0u8; KEY_SIZE]), self.key.as_ref());
+
+        let mut keys = Zeroizing::new([0u8; KEY_SIZE * 2]);
+        hkdf.expand(name.as_bytes(), keys.as_mut()).expect("Can't expand the secret storage key");
+
+        let mut aes_key = Zeroizing::new([0u8; KEY_SIZE]);
+        let mut hmac_key = Zeroizing::new([0u8; KEY_SIZE]);
+        aes_key.copy_from_slice(&keys[..KEY_SIZE]);
+        hmac_key.copy_from_slice(&keys[KEY_SIZE..]);
+
+        (aes_key, hmac_key)
+    }
+
+    /// Encrypt the plaintext in place and return the base64 encoded MAC.
+    fn encrypt_helper(&self, plaintext: &mut [u8], name: &str, iv: &[u8; IV_SIZE]) -> String {
+        let (aes_key, hmac_key) = self.derive_keys(name);
+
+        let mut aes = Aes256Ctr::new(GenericArray::from_slice(aes_key.as_ref()), &(*iv).into());
+        aes.apply_keystream(plaintext);
+
+        let mut hmac =
+            Hmac::<Sha256>::new_from_slice(hmac_key.as_ref()).expect("Can't create an HMAC object");
+        hmac.update(plaintext);
+
+        encode(hmac.finalize().into_bytes())
+    }
+
+    /// Compute the MAC used to check the key, by encrypting 32 zero bytes with
+    /// an empty name.
+    fn check_values(&self, iv: [u8; IV_SIZE]) -> ([u8; IV_SIZE], String) {
+        let mut zeroes = [0u8; KEY_SIZE];
+        let mac = self.encrypt_helper(&mut zeroes, "", &iv);
+
+        (iv, mac)
+    }
+
+    fn check(&self, iv: &str, mac: &str) -> Result<bool, DecodeError> {
+        let iv = decode(iv)?;
+        let Ok(iv) = <[u8; IV_SIZE]>::try_from(iv) else {
+            return Ok(false);
+        };
+
+        let (_, expected_mac) = self.check_values(iv);
+
+        // Compare the decoded MACs, as the padding might differ.
+        Ok(decode(mac)? == decode(expected_mac)?)
+    }
+}
+
+impl Default for SecretStorageKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use ruma::events::secret::request::SecretName;
+
+    use super::{DecodeError, SecretDecryptionError, SecretStorageKey};
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let key = SecretStorageKey::new();
+        let secret_name = SecretName::CrossSigningMasterKey;
+
+        let encrypted = key.encrypt("It's a secret to everybody", &secret_name);
+        let decrypted = key.decrypt(&encrypted, &secret_name).unwrap();
+        assert_eq!(decrypted, "It's a secret to everybody");
+
+        // The name of the secret is part of the key derivation.
+        assert_matches!(
+            key.decrypt(&encrypted, &SecretName::CrossSigningSelfSigningKey),
+            Err(SecretDecryptionError::InvalidMac)
+        );
+    }
+
+    #[test]
+    fn test_restore_from_recovery_key() {
+        let key = SecretStorageKey::new();
+        let content = key.event_content();
+        let recovery_key = key.to_base58();
+
+        let restored =
+            SecretStorageKey::from_account_data(&recovery_key, key.key_id().to_owned(), &content)
+                .unwrap();
+        assert_eq!(restored.key, key.key);
+
+        let other_key = SecretStorageKey::new();
+        assert_matches!(
+            SecretStorageKey::from_account_data(
+                &other_key.to_base58(),
+                key.key_id().to_owned(),
+                &content
+            ),
+            Err(DecodeError::KeyMismatch)
+        );
+    }
+
+    #[test]
+    fn test_restore_from_passphrase() {
+        // Use fewer iterations than the default to keep the test fast.
+        let key = SecretStorageKey::new_from_passphrase_helper("It's a secret to everybody", 10);
+        let content = key.event_content();
+        assert!(content.passphrase.is_some());
+
+        let restored = SecretStorageKey::from_account_data(
+            "It's a secret to everybody",
+            key.key_id().to_owned(),
+            &content,
+        )
+        .unwrap();
+        assert_eq!(restored.key, key.key);
+
+        assert_matches!(
+            SecretStorageKey::from_account_data(
+                "Wrong passphrase",
+                key.key_id().to_owned(),
+                &content
+            ),
+            Err(DecodeError::KeyMismatch)
+        );
+    }
+}
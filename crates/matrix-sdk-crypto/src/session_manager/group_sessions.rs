@@ -342,6 +342,7 @@ impl GroupSessionManager {
         let users: BTreeSet<&UserId> = users.collect();
         let mut devices: BTreeMap<OwnedUserId, Vec<Device>> = Default::default();
         let mut withheld_devices: Vec<(Device, WithheldCode)> = Default::default();
+        let mut untrusted_devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>> = Default::default();
 
         trace!(
             ?users,
@@ -422,10 +423,24 @@ impl GroupSessionManager {
                 };
             }
 
+            let untrusted: Vec<_> = recipients
+                .iter()
+                .filter(|d| !settings.trust_requirement.is_satisfied_by(d))
+                .map(|d| d.device_id().to_owned())
+                .collect();
+
+            if !untrusted.is_empty() {
+                untrusted_devices.insert(user_id.to_owned(), untrusted);
+            }
+
             devices.entry(user_id.to_owned()).or_default().extend(recipients);
             withheld_devices.extend(withheld_recipients);
         }
 
+        if !untrusted_devices.is_empty() {
+            return Err(OlmError::UntrustedDevices(untrusted_devices));
+        }
+
         trace!(
             should_rotate = should_rotate,
             session_id = outbound.session_id(),
@@ -844,12 +859,132 @@ impl GroupSessionManager {
 
         Ok(requests)
     }
+
+    /// Get the room keys of a room that can be shared with a newly invited or
+    /// joined user.
+    ///
+    /// These are the room keys that were created while the history of the
+    /// room was visible to new members.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The room id of the room the user was invited to or joined.
+    pub async fn shareable_room_key_history(
+        &self,
+        room_id: &RoomId,
+    ) -> StoreResult<Vec<InboundGroupSession>> {
+        Ok(self
+            .store
+            .get_inbound_group_sessions_for_room(room_id)
+            .await?
+            .into_iter()
+            .filter(|s| s.shared_history())
+            .collect())
+    }
+
+    /// Get to-device requests to share the room keys of a room that were
+    /// created while the room history was shared with a newly invited user.
+    ///
+    /// The room keys are sent as forwarded room keys to all the non-blacklisted
+    /// devices of the user we have an Olm session with. A to-device request
+    /// can only contain a single event per device, so every request contains
+    /// one room key for each of the devices, until all the room keys are sent.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The room id of the room the user was invited to.
+    ///
+    /// `user_id` - The user that was invited to the room.
+    #[instrument(skip(self))]
+    pub async fn share_room_key_history(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> OlmResult<Vec<Arc<ToDeviceRequest>>> {
+        let sessions = self.shareable_room_key_history(room_id).await?;
+
+        if sessions.is_empty() {
+            debug!("No room keys with a shared history to share");
+            return Ok(Vec::new());
+        }
+
+        let devices: Vec<_> = self
+            .store
+            .get_user_devices_filtered(user_id)
+            .await?
+            .devices()
+            .filter(|d| !d.is_blacklisted())
+            .collect();
+
+        let mut changes = Changes::default();
+        let mut device_contents = Vec::with_capacity(devices.len());
+
+        'devices: for device in &devices {
+            let mut contents = Vec::with_capacity(sessions.len());
+
+            for session in &sessions {
+                match device.encrypt_room_key_for_forwarding(session.clone(), None).await {
+                    Ok((used_session, content)) => {
+                        changes.sessions.push(used_session);
+                        contents.push(content.cast());
+                    }
+                    Err(
+                        OlmError::MissingSession
+                        | OlmError::EventError(EventError::MissingSenderKey),
+                    ) => {
+                        debug!(
+                            device_id = ?device.device_id(),
+                            "Can't share a room key with a device we don't have an Olm session with"
+                        );
+                        continue 'devices;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            device_contents.push((device.device_id().to_owned(), contents.into_iter()));
+        }
+
+        let mut requests = Vec::with_capacity(sessions.len());
+
+        loop {
+            let device_messages: BTreeMap<_, _> = device_contents
+                .iter_mut()
+                .filter_map(|(device_id, contents)| {
+                    Some((DeviceIdOrAllDevices::DeviceId(device_id.clone()), contents.next()?))
+                })
+                .collect();
+
+            if device_messages.is_empty() {
+                break;
+            }
+
+            requests.push(Arc::new(ToDeviceRequest {
+                event_type: ToDeviceEventType::RoomEncrypted,
+                txn_id: TransactionId::new(),
+                messages: BTreeMap::from([(user_id.to_owned(), device_messages)]),
+            }));
+        }
+
+        if !changes.is_empty() {
+            self.store.save_changes(changes).await?;
+        }
+
+        info!(
+            room_key_count = sessions.len(),
+            request_count = requests.len(),
+            "Shared the room key history with an invited user"
+        );
+
+        Ok(requests)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::{collections::BTreeSet, ops::Deref, sync::Arc};
 
+    use assert_matches::assert_matches;
     use matrix_sdk_test::{async_test, response_from_file};
     use ruma::{
         api::{
@@ -875,7 +1010,7 @@ mod tests {
             },
             EventEncryptionAlgorithm,
         },
-        EncryptionSettings, LocalTrust, OlmMachine, ToDeviceRequest,
+        EncryptionSettings, LocalTrust, OlmError, OlmMachine, ToDeviceRequest, TrustRequirement,
     };
 
     fn alice_id() -> &'static UserId {
@@ -1267,6 +1402,53 @@ mod tests {
         assert_eq!(149, withheld.len());
     }
 
+    #[async_test]
+    async fn test_key_recipient_collecting_trust_requirement() {
+        let user_id = user_id!("@example:localhost");
+        let device_id = device_id!("TESTDEVICE");
+        let room_id = room_id!("!test:localhost");
+
+        let machine = machine_with_user(user_id, device_id).await;
+
+        let (outbound, _) = machine
+            .inner
+            .group_session_manager
+            .get_or_create_outbound_session(room_id, EncryptionSettings::default())
+            .await
+            .expect("We should be able to create a new session");
+
+        let settings = EncryptionSettings {
+            trust_requirement: TrustRequirement::VerifiedOnly,
+            ..Default::default()
+        };
+
+        // None of the devices are verified, so the room key can't be shared.
+        let result = machine
+            .inner
+            .group_session_manager
+            .collect_session_recipients([user_id].into_iter(), &settings, &outbound)
+            .await;
+        let untrusted = assert_matches!(result, Err(OlmError::UntrustedDevices(d)) => d);
+        assert!(untrusted[user_id].iter().any(|d| d.as_str() == "AFGUOBTZWM"));
+        assert!(!untrusted[user_id].contains(&device_id.to_owned()));
+
+        // Verifying or blacklisting all the devices resolves the violation.
+        let devices = machine.get_user_devices(user_id, None).await.unwrap();
+        for (i, device) in devices.devices().filter(|d| d.device_id() != device_id).enumerate() {
+            let trust = if i % 2 == 0 { LocalTrust::Verified } else { LocalTrust::BlackListed };
+            device.set_local_trust(trust).await.unwrap();
+        }
+
+        let CollectRecipientsResult { devices: recipients, .. } = machine
+            .inner
+            .group_session_manager
+            .collect_session_recipients([user_id].into_iter(), &settings, &outbound)
+            .await
+            .expect("We should be able to collect the session recipients");
+        assert!(!recipients[user_id].is_empty());
+        assert!(recipients[user_id].iter().all(|d| d.is_verified()));
+    }
+
     #[async_test]
     async fn test_sharing_withheld_only_trusted() {
         let machine = machine().await;
@@ -1386,4 +1568,39 @@ mod tests {
 
         assert!(device.was_withheld_code_sent());
     }
+
+    #[async_test]
+    async fn test_sharing_room_key_history() {
+        let machine = machine().await;
+        let shared_room = room_id!("!shared:localhost");
+        let joined_room = room_id!("!joined:localhost");
+        let alice_id = alice_id();
+        let bob_id = user_id!("@bob:localhost");
+
+        let settings = EncryptionSettings::default();
+        assert_eq!(settings.history_visibility, HistoryVisibility::Shared);
+        machine.share_room_key(shared_room, [alice_id].into_iter(), settings).await.unwrap();
+
+        let settings = EncryptionSettings {
+            history_visibility: HistoryVisibility::Joined,
+            ..Default::default()
+        };
+        machine.share_room_key(joined_room, [alice_id].into_iter(), settings).await.unwrap();
+
+        let sessions = machine.shareable_room_key_history(shared_room).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(machine.shareable_room_key_history(joined_room).await.unwrap().is_empty());
+
+        // The room key of the room with a shared history is sent to Bob's device.
+        let requests = machine.share_room_key_history(shared_room, bob_id).await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].event_type, "m.room.encrypted".into());
+        assert_eq!(requests[0].message_count(), 1);
+        assert!(requests[0].messages[bob_id]
+            .contains_key(&DeviceIdOrAllDevices::DeviceId("BOBDEVICE".into())));
+
+        // The room key of the room where the history is restricted isn't.
+        let requests = machine.share_room_key_history(joined_room, bob_id).await.unwrap();
+        assert!(requests.is_empty());
+    }
 }
@@ -105,6 +105,11 @@ impl GroupSessionStore {
             .collect()
     }
 
+    /// Get all the group sessions of the given room the store knows about.
+    pub fn get_all_for_room(&self, room_id: &RoomId) -> Vec<InboundGroupSession> {
+        self.entries.get(room_id).map(|keys| keys.values().cloned().collect()).unwrap_or_default()
+    }
+
     /// Get the number of `InboundGroupSession`s we have.
     pub fn count(&self) -> usize {
         self.entries.iter().map(|d| d.value().len()).sum()
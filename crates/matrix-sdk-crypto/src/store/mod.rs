@@ -137,6 +137,10 @@ struct StoreInner {
     /// The sender side of a broadcast channel which sends out secrets we
     /// received as a `m.secret.send` event.
     secrets_broadcaster: broadcast::Sender<GossippedSecret>,
+
+    /// The sender side of a broadcast stream that is notified whenever user
+    /// identities are created or updated.
+    identities_changed_sender: broadcast::Sender<Vec<OwnedUserId>>,
 }
 
 /// Aggregated changes to be saved in the database.
@@ -403,6 +407,7 @@ impl Store {
     ) -> Self {
         let room_keys_received_sender = broadcast::Sender::new(10);
         let secrets_broadcaster = broadcast::Sender::new(10);
+        let identities_changed_sender = broadcast::Sender::new(10);
 
         let inner = Arc::new(StoreInner {
             user_id,
@@ -416,6 +421,7 @@ impl Store {
             tracked_user_loading_lock: Mutex::new(()),
             room_keys_received_sender,
             secrets_broadcaster,
+            identities_changed_sender,
         });
 
         Self { inner }
@@ -460,8 +466,20 @@ impl Store {
 
         let secrets = changes.secrets.to_owned();
 
+        let changed_identities: Vec<_> = changes
+            .identities
+            .new
+            .iter()
+            .chain(&changes.identities.changed)
+            .map(|identity| identity.user_id().to_owned())
+            .collect();
+
         self.inner.store.save_changes(changes).await?;
 
+        if !changed_identities.is_empty() {
+            let _ = self.inner.identities_changed_sender.send(changed_identities);
+        }
+
         if !room_key_updates.is_empty() {
             // Ignore the result. It can only fail if there are no listeners.
             let _ = self.inner.room_keys_received_sender.send(room_key_updates);
@@ -655,6 +673,7 @@ impl Store {
                         inner: i,
                         verification_machine: self.inner.verification_machine.clone(),
                         own_identity,
+                        store: self.clone(),
                     }
                     .into()
                 }
@@ -1035,6 +1054,30 @@ impl Store {
         })
     }
 
+    /// Receive notifications of user identities being created or updated as a
+    /// [`Stream`].
+    ///
+    /// Each time user identities are saved in the store, the IDs of their
+    /// users are sent to the stream. This includes changes of their keys
+    /// received from the homeserver, and changes of their local state like the
+    /// pinned master key.
+    ///
+    /// If the reader of the stream lags too far behind, a warning will be
+    /// logged and items will be dropped.
+    pub fn identities_changed_stream(&self) -> impl Stream<Item = Vec<OwnedUserId>> {
+        let stream = BroadcastStream::new(self.inner.identities_changed_sender.subscribe());
+
+        stream.filter_map(|result| async move {
+            match result {
+                Ok(r) => Some(r),
+                Err(BroadcastStreamRecvError::Lagged(lag)) => {
+                    warn!("identities_changed_stream missed {lag} updates");
+                    None
+                }
+            }
+        })
+    }
+
     /// Creates a `CryptoStoreLock` for this store, that will contain the given
     /// key and value when hold.
     pub fn create_store_lock(&self, lock_key: String, lock_value: String) -> CryptoStoreLock {
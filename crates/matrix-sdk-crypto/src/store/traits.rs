@@ -98,6 +98,19 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// Get all the inbound group sessions we have stored.
     async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>, Self::Error>;
 
+    /// Get all the inbound group sessions of the given room we have stored.
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        Ok(self
+            .get_inbound_group_sessions()
+            .await?
+            .into_iter()
+            .filter(|s| s.room_id() == room_id)
+            .collect())
+    }
+
     /// Get the number inbound group sessions we have and how many of them are
     /// backed up.
     async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts, Self::Error>;
@@ -260,6 +273,41 @@ pub trait CryptoStore: AsyncTraitDeps {
 
     /// Load the next-batch token for a to-device query, if any.
     async fn next_batch_token(&self) -> Result<Option<String>, Self::Error>;
+
+    /// Export the key encrypting the data of the store, encrypted with a new
+    /// passphrase.
+    ///
+    /// Nothing is written to the store: the export must be saved with
+    /// [`CryptoStore::save_store_cipher()`] for the new passphrase to be used.
+    /// This allows to check that the passphrase of several stores can be
+    /// changed before changing any of them.
+    ///
+    /// Returns `None` for stores that are not persisted, so there is no
+    /// passphrase to change. Persisted stores that are not encrypted with a
+    /// passphrase return an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `old_passphrase` - The passphrase currently used to open the store.
+    ///
+    /// * `new_passphrase` - The passphrase to use from now on.
+    async fn export_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let _ = (old_passphrase, new_passphrase);
+        Ok(None)
+    }
+
+    /// Save a key exported with [`CryptoStore::export_store_cipher()`].
+    ///
+    /// The passphrase used for the export must be used to open the store from
+    /// now on.
+    async fn save_store_cipher(&self, export: Vec<u8>) -> Result<(), Self::Error> {
+        let _ = export;
+        Ok(())
+    }
 }
 
 #[repr(transparent)]
@@ -308,6 +356,13 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.get_inbound_group_sessions().await.map_err(Into::into)
     }
 
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<InboundGroupSession>> {
+        self.0.get_inbound_group_sessions_for_room(room_id).await.map_err(Into::into)
+    }
+
     async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
         self.0.inbound_group_session_counts().await.map_err(Into::into)
     }
@@ -430,6 +485,18 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
     async fn next_batch_token(&self) -> Result<Option<String>, Self::Error> {
         self.0.next_batch_token().await.map_err(Into::into)
     }
+
+    async fn export_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.0.export_store_cipher(old_passphrase, new_passphrase).await.map_err(Into::into)
+    }
+
+    async fn save_store_cipher(&self, export: Vec<u8>) -> Result<(), Self::Error> {
+        self.0.save_store_cipher(export).await.map_err(Into::into)
+    }
 }
 
 /// A type-erased [`CryptoStore`].
@@ -16,6 +16,7 @@ use std::{
     borrow::Borrow,
     collections::HashMap,
     hash::Hash,
+    io::{Cursor, Read},
     sync::{Arc, RwLock},
     time::Duration,
 };
@@ -27,6 +28,8 @@ use base64::{
     Engine,
 };
 use matrix_sdk_common::instant::Instant;
+use thiserror::Error;
+use zeroize::Zeroizing;
 
 const STANDARD_NO_PAD: GeneralPurpose = GeneralPurpose::new(
     &alphabet::STANDARD,
@@ -44,6 +47,96 @@ pub fn encode(input: impl AsRef<[u8]>) -> String {
     STANDARD_NO_PAD.encode(input)
 }
 
+/// The prefix of the decoded recovery keys, used for the backup decryption key
+/// and the secret storage key.
+pub(crate) const RECOVERY_KEY_PREFIX: [u8; 2] = [0x8b, 0x01];
+const RECOVERY_KEY_DISPLAY_CHUNK_SIZE: usize = 4;
+
+/// Error type for the decoding of a recovery key.
+#[derive(Debug, Error)]
+pub enum RecoveryKeyDecodeError {
+    /// The decoded recovery key has an invalid prefix.
+    #[error("The decoded recovery key has an invalid prefix: expected {0:?}, got {1:?}")]
+    Prefix([u8; 2], [u8; 2]),
+    /// The parity byte of the recovery key didn't match.
+    #[error("The parity byte of the recovery key doesn't match: expected {0:?}, got {1:?}")]
+    Parity(u8, u8),
+    /// The recovery key isn't valid base58.
+    #[error(transparent)]
+    Base58(#[from] bs58::decode::Error),
+    /// The recovery key is too short or too long.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+fn recovery_key_parity_byte(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(RECOVERY_KEY_PREFIX[0] ^ RECOVERY_KEY_PREFIX[1], |acc, x| acc ^ x)
+}
+
+/// Encode the given key as a base58 recovery key, with its prefix and parity
+/// byte.
+pub(crate) fn encode_recovery_key(key: &[u8]) -> Zeroizing<String> {
+    let bytes = Zeroizing::new(
+        [RECOVERY_KEY_PREFIX.as_ref(), key, [recovery_key_parity_byte(key)].as_ref()].concat(),
+    );
+
+    Zeroizing::new(
+        bs58::encode(bytes.as_slice()).with_alphabet(bs58::Alphabet::BITCOIN).into_string(),
+    )
+}
+
+/// Split the given encoded recovery key in groups of four characters, to make
+/// it easier to read.
+pub(crate) fn format_recovery_key(encoded: &str) -> Zeroizing<String> {
+    Zeroizing::new(
+        encoded
+            .chars()
+            .collect::<Vec<char>>()
+            .chunks(RECOVERY_KEY_DISPLAY_CHUNK_SIZE)
+            .map(|c| c.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Decode a base58 recovery key, checking its prefix and parity byte.
+///
+/// Whitespace in the input is ignored.
+pub(crate) fn decode_recovery_key<const KEY_SIZE: usize>(
+    value: &str,
+) -> Result<Box<[u8; KEY_SIZE]>, RecoveryKeyDecodeError> {
+    let value: Zeroizing<String> =
+        Zeroizing::new(value.chars().filter(|c| !c.is_whitespace()).collect());
+
+    let decoded = Zeroizing::new(
+        bs58::decode(value.as_str()).with_alphabet(bs58::Alphabet::BITCOIN).into_vec()?,
+    );
+    let mut decoded = Cursor::new(decoded.as_slice());
+
+    let mut prefix = [0u8; 2];
+    let mut key = Box::new([0u8; KEY_SIZE]);
+    let mut expected_parity = [0u8; 1];
+
+    decoded.read_exact(&mut prefix)?;
+    decoded.read_exact(key.as_mut())?;
+    decoded.read_exact(&mut expected_parity)?;
+
+    if (decoded.position() as usize) != decoded.get_ref().len() {
+        return Err(RecoveryKeyDecodeError::Io(std::io::ErrorKind::InvalidData.into()));
+    }
+
+    let expected_parity = expected_parity[0];
+    let parity = recovery_key_parity_byte(key.as_ref());
+
+    if prefix != RECOVERY_KEY_PREFIX {
+        Err(RecoveryKeyDecodeError::Prefix(RECOVERY_KEY_PREFIX, prefix))
+    } else if expected_parity != parity {
+        Err(RecoveryKeyDecodeError::Parity(expected_parity, parity))
+    } else {
+        Ok(key)
+    }
+}
+
 #[cfg(test)]
 pub(crate) fn json_convert<T, U>(value: &T) -> serde_json::Result<U>
 where
@@ -122,6 +122,8 @@ pub enum IndexeddbCryptoStoreError {
     },
     #[error(transparent)]
     CryptoStoreError(#[from] CryptoStoreError),
+    #[error("The store is not encrypted with a passphrase")]
+    MissingStoreCipher,
 }
 
 impl From<indexed_db_futures::web_sys::DomException> for IndexeddbCryptoStoreError {
@@ -270,11 +272,9 @@ impl IndexeddbCryptoStore {
         })
     }
 
-    /// Open a new `IndexeddbCryptoStore` with given name and passphrase
-    pub async fn open_with_passphrase(prefix: &str, passphrase: &str) -> Result<Self> {
-        let name = format!("{prefix:0}::matrix-sdk-crypto-meta");
-
-        let mut db_req: OpenDbRequest = IdbDatabase::open_u32(&name, 1)?;
+    /// Open the database holding the store cipher, creating it if necessary.
+    async fn open_meta_db(name: &str) -> Result<IdbDatabase> {
+        let mut db_req: OpenDbRequest = IdbDatabase::open_u32(name, 1)?;
         db_req.set_on_upgrade_needed(Some(|evt: &IdbVersionChangeEvent| -> Result<(), JsValue> {
             let old_version = evt.old_version() as u32;
             if old_version < 1 {
@@ -286,7 +286,12 @@ impl IndexeddbCryptoStore {
             Ok(())
         }));
 
-        let db: IdbDatabase = db_req.into_future().await?;
+        Ok(db_req.into_future().await?)
+    }
+
+    /// Open a new `IndexeddbCryptoStore` with given name and passphrase
+    pub async fn open_with_passphrase(prefix: &str, passphrase: &str) -> Result<Self> {
+        let db = Self::open_meta_db(&format!("{prefix:0}::matrix-sdk-crypto-meta")).await?;
 
         let tx: IdbTransaction<'_> =
             db.transaction_on_one_with_mode("matrix-sdk-crypto", IdbTransactionMode::Readonly)?;
@@ -754,6 +759,63 @@ impl_crypto_store! {
         }
     }
 
+    async fn export_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let db = Self::open_meta_db(&format!("{}-meta", self.name)).await?;
+
+        let tx: IdbTransaction<'_> =
+            db.transaction_on_one_with_mode("matrix-sdk-crypto", IdbTransactionMode::Readonly)?;
+        let ob = tx.object_store("matrix-sdk-crypto")?;
+
+        let store_cipher: Option<Vec<u8>> = ob
+            .get(&JsValue::from_str(keys::STORE_CIPHER))?
+            .await?
+            .map(|k| k.into_serde())
+            .transpose()?;
+
+        // Must release the database access manually as it's not done when
+        // dropping it.
+        db.close();
+
+        let Some(store_cipher) = store_cipher else {
+            return Err(IndexeddbCryptoStoreError::MissingStoreCipher);
+        };
+
+        let cipher = StoreCipher::import(old_passphrase, &store_cipher)
+            .map_err(|_| CryptoStoreError::UnpicklingError)?;
+        #[cfg(not(test))]
+        let export = cipher.export(new_passphrase);
+        #[cfg(test)]
+        let export = cipher._insecure_export_fast_for_testing(new_passphrase);
+
+        Ok(Some(export.map_err(CryptoStoreError::backend)?))
+    }
+
+    async fn save_store_cipher(&self, export: Vec<u8>) -> Result<()> {
+        let db = Self::open_meta_db(&format!("{}-meta", self.name)).await?;
+
+        let tx: IdbTransaction<'_> =
+            db.transaction_on_one_with_mode("matrix-sdk-crypto", IdbTransactionMode::Readwrite)?;
+        let ob = tx.object_store("matrix-sdk-crypto")?;
+
+        let key = JsValue::from_str(keys::STORE_CIPHER);
+        let result = if ob.get(&key)?.await?.is_some() {
+            ob.put_key_val(&key, &JsValue::from_serde(&export)?)?;
+            tx.await.into_result().map_err(Into::into)
+        } else {
+            Err(IndexeddbCryptoStoreError::MissingStoreCipher)
+        };
+
+        // Must release the database access manually as it's not done when
+        // dropping it.
+        db.close();
+
+        result
+    }
+
     async fn save_account(&self, account: ReadOnlyAccount) -> Result<()> {
         self.save_changes(Changes { account: Some(account), ..Default::default() })
             .await
@@ -1220,7 +1282,8 @@ mod tests {
 #[rustfmt::skip]
 mod encrypted_tests {
     use super::IndexeddbCryptoStore;
-    use matrix_sdk_crypto::cryptostore_integration_tests;
+    use matrix_sdk_crypto::{cryptostore_integration_tests, store::CryptoStore};
+    use matrix_sdk_test::async_test;
 
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
@@ -1234,4 +1297,19 @@ mod encrypted_tests {
     // FIXME: the tests pass, if run one by one, but run all together locally,
     //        as well as CI fails... see matrix-org/matrix-rust-sdk#661
     //     cryptostore_integration_tests!();
+
+    #[async_test]
+    async fn test_change_passphrase() {
+        let name = "test_change_passphrase";
+
+        let store = get_store(name, Some("old")).await;
+        // The old passphrase must be right.
+        store.export_store_cipher("wrong", "new").await.unwrap_err();
+        let export = store.export_store_cipher("old", "new").await.unwrap().unwrap();
+        store.save_store_cipher(export).await.unwrap();
+        drop(store);
+
+        IndexeddbCryptoStore::open_with_passphrase(name, "old").await.unwrap_err();
+        IndexeddbCryptoStore::open_with_passphrase(name, "new").await.unwrap();
+    }
 }
@@ -130,6 +130,49 @@ pub async fn upgrade_meta_db(
     Ok((meta_db, store_cipher))
 }
 
+/// Export the store cipher saved in the meta database, encrypted with a new
+/// passphrase.
+pub async fn export_meta_db_store_cipher(
+    meta_db: &IdbDatabase,
+    old_passphrase: &str,
+    new_passphrase: &str,
+) -> Result<Vec<u8>> {
+    let tx: IdbTransaction<'_> =
+        meta_db.transaction_on_one_with_mode(keys::INTERNAL_STATE, IdbTransactionMode::Readonly)?;
+    let ob = tx.object_store(keys::INTERNAL_STATE)?;
+
+    let Some(StoreKeyWrapper(inner)) =
+        ob.get(&JsValue::from_str(keys::STORE_KEY))?.await?.map(|v| v.into_serde()).transpose()?
+    else {
+        return Err(IndexeddbStateStoreError::MissingStoreCipher);
+    };
+
+    let cipher = StoreCipher::import(old_passphrase, &inner)?;
+    #[cfg(not(test))]
+    let export = cipher.export(new_passphrase)?;
+    #[cfg(test)]
+    let export = cipher._insecure_export_fast_for_testing(new_passphrase)?;
+
+    Ok(export)
+}
+
+/// Replace the store cipher saved in the meta database.
+pub async fn save_meta_db_store_cipher(meta_db: &IdbDatabase, export: Vec<u8>) -> Result<()> {
+    let tx: IdbTransaction<'_> = meta_db
+        .transaction_on_one_with_mode(keys::INTERNAL_STATE, IdbTransactionMode::Readwrite)?;
+    let ob = tx.object_store(keys::INTERNAL_STATE)?;
+
+    let key = JsValue::from_str(keys::STORE_KEY);
+    if ob.get(&key)?.await?.is_none() {
+        return Err(IndexeddbStateStoreError::MissingStoreCipher);
+    }
+
+    ob.put_key_val(&key, &JsValue::from_serde(&StoreKeyWrapper(export))?)?;
+    tx.await.into_result()?;
+
+    Ok(())
+}
+
 // Helper struct for upgrading the inner DB.
 #[derive(Debug, Clone, Default)]
 pub struct OngoingMigration {
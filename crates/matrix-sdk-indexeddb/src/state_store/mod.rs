@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::{
+    cmp::Ordering,
     collections::{BTreeMap, BTreeSet, HashSet},
     sync::Arc,
 };
@@ -25,8 +26,8 @@ use matrix_sdk_base::{
     deserialized_responses::RawAnySyncOrStrippedState,
     media::{MediaRequest, UniqueKey},
     store::{StateChanges, StateStore, StoreError},
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateStoreDataKey,
-    StateStoreDataValue,
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, RoomStateFilter,
+    StateStoreDataKey, StateStoreDataValue,
 };
 use matrix_sdk_store_encryption::{Error as EncryptionError, StoreCipher};
 use ruma::{
@@ -41,7 +42,8 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType, SyncStateEvent,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId,
+    RoomVersionId, UserId,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{debug, warn};
@@ -51,8 +53,10 @@ use web_sys::IdbKeyRange;
 mod migrations;
 
 pub use self::migrations::MigrationConflictStrategy;
-use self::migrations::{upgrade_inner_db, upgrade_meta_db};
-use crate::safe_encode::SafeEncode;
+use self::migrations::{
+    export_meta_db_store_cipher, save_meta_db_store_cipher, upgrade_inner_db, upgrade_meta_db,
+};
+use crate::safe_encode::{SafeEncode, KEY_SEPARATOR, RANGE_END};
 
 #[derive(Debug, thiserror::Error)]
 pub enum IndexeddbStateStoreError {
@@ -66,6 +70,8 @@ pub enum IndexeddbStateStoreError {
     StoreError(#[from] StoreError),
     #[error("Can't migrate {name} from {old_version} to {new_version} without deleting data. See MigrationConflictStrategy for ways to configure.")]
     MigrationConflict { name: String, old_version: u32, new_version: u32 },
+    #[error("The store is not encrypted with a passphrase")]
+    MissingStoreCipher,
 }
 
 impl From<indexed_db_futures::web_sys::DomException> for IndexeddbStateStoreError {
@@ -383,6 +389,9 @@ impl IndexeddbStateStore {
             StateStoreDataKey::UserAvatarUrl(user_id) => {
                 self.encode_key(keys::KV, (StateStoreDataKey::USER_AVATAR_URL, user_id))
             }
+            StateStoreDataKey::ComposerDraft(room_id) => {
+                self.encode_key(keys::KV, (StateStoreDataKey::COMPOSER_DRAFT, room_id))
+            }
         }
     }
 }
@@ -425,17 +434,28 @@ impl_state_store!({
             .transaction_on_one_with_mode(keys::KV, IdbTransactionMode::Readonly)?
             .object_store(keys::KV)?
             .get(&encoded_key)?
-            .await?
-            .map(|f| self.deserialize_event::<String>(&f))
-            .transpose()?;
+            .await?;
+
+        let Some(value) = value else {
+            return Ok(None);
+        };
 
         let value = match key {
-            StateStoreDataKey::SyncToken => value.map(StateStoreDataValue::SyncToken),
-            StateStoreDataKey::Filter(_) => value.map(StateStoreDataValue::Filter),
-            StateStoreDataKey::UserAvatarUrl(_) => value.map(StateStoreDataValue::UserAvatarUrl),
+            StateStoreDataKey::SyncToken => {
+                StateStoreDataValue::SyncToken(self.deserialize_event(&value)?)
+            }
+            StateStoreDataKey::Filter(_) => {
+                StateStoreDataValue::Filter(self.deserialize_event(&value)?)
+            }
+            StateStoreDataKey::UserAvatarUrl(_) => {
+                StateStoreDataValue::UserAvatarUrl(self.deserialize_event(&value)?)
+            }
+            StateStoreDataKey::ComposerDraft(_) => {
+                StateStoreDataValue::ComposerDraft(self.deserialize_event(&value)?)
+            }
         };
 
-        Ok(value)
+        Ok(Some(value))
     }
 
     async fn set_kv_data(
@@ -445,14 +465,19 @@ impl_state_store!({
     ) -> Result<()> {
         let encoded_key = self.encode_kv_data_key(key);
 
-        let value = match key {
-            StateStoreDataKey::SyncToken => {
-                value.into_sync_token().expect("Session data not a sync token")
-            }
-            StateStoreDataKey::Filter(_) => value.into_filter().expect("Session data not a filter"),
-            StateStoreDataKey::UserAvatarUrl(_) => {
-                value.into_user_avatar_url().expect("Session data not an user avatar url")
+        let serialized_value = match key {
+            StateStoreDataKey::SyncToken => self.serialize_event(
+                &value.into_sync_token().expect("Session data not a sync token"),
+            )?,
+            StateStoreDataKey::Filter(_) => {
+                self.serialize_event(&value.into_filter().expect("Session data not a filter"))?
             }
+            StateStoreDataKey::UserAvatarUrl(_) => self.serialize_event(
+                &value.into_user_avatar_url().expect("Session data not an user avatar url"),
+            )?,
+            StateStoreDataKey::ComposerDraft(_) => self.serialize_event(
+                &value.into_composer_draft().expect("Session data not a composer draft"),
+            )?,
         };
 
         let tx =
@@ -460,7 +485,7 @@ impl_state_store!({
 
         let obj = tx.object_store(keys::KV)?;
 
-        obj.put_key_val(&encoded_key, &self.serialize_event(&value)?)?;
+        obj.put_key_val(&encoded_key, &serialized_value)?;
 
         tx.await.into_result()?;
 
@@ -931,6 +956,93 @@ impl_state_store!({
         Ok(events)
     }
 
+    async fn get_state_events_for_rooms<'a>(
+        &self,
+        room_ids: &'a [OwnedRoomId],
+        event_type: StateEventType,
+    ) -> Result<BTreeMap<&'a RoomId, Vec<RawAnySyncOrStrippedState>>> {
+        // Compare keys like IndexedDB does, by UTF-16 code units.
+        fn cmp_keys(a: &str, b: &str) -> Ordering {
+            a.encode_utf16().cmp(b.encode_utf16())
+        }
+
+        let mut events = BTreeMap::<_, Vec<_>>::new();
+        if room_ids.is_empty() {
+            return Ok(events);
+        }
+
+        let txn = self.inner.transaction_on_multi_with_mode(
+            &[keys::STRIPPED_ROOM_STATE, keys::ROOM_STATE],
+            IdbTransactionMode::Readonly,
+        )?;
+
+        // Like in `get_state_events`, the stripped state of a room takes precedence
+        // over its state, so the rooms found in the stripped state are skipped in
+        // the state.
+        for (store_name, stripped) in [(keys::STRIPPED_ROOM_STATE, true), (keys::ROOM_STATE, false)]
+        {
+            // The bounds of the keys of the events of each room, sorted so all the
+            // rooms are visited with a single cursor, jumping from one room to the
+            // next one.
+            let mut bounds = room_ids
+                .iter()
+                .filter(|room_id| !events.contains_key(room_id.as_ref() as &RoomId))
+                .filter_map(|room_id| {
+                    let prefix = self.encode_key(store_name, (room_id, &event_type)).as_string()?;
+                    Some((
+                        [prefix.as_str(), KEY_SEPARATOR].concat(),
+                        [prefix.as_str(), RANGE_END].concat(),
+                        room_id,
+                    ))
+                })
+                .collect::<Vec<_>>();
+            bounds.sort_by(|(a, ..), (b, ..)| cmp_keys(a, b));
+
+            let (Some((lower, ..)), Some((_, upper, _))) = (bounds.first(), bounds.last()) else {
+                continue;
+            };
+            let range = IdbKeyRange::bound(&JsValue::from(lower), &JsValue::from(upper))?;
+            let Some(cursor) =
+                txn.object_store(store_name)?.open_cursor_with_range(&range)?.await?
+            else {
+                continue;
+            };
+
+            let mut bounds = bounds.into_iter().peekable();
+            while let Some(key) = cursor.key().and_then(|key| key.as_string()) {
+                // Skip the rooms whose events are all before the cursor.
+                while bounds.next_if(|(_, upper, _)| cmp_keys(upper, &key).is_lt()).is_some() {}
+                let Some((lower, _, room_id)) = bounds.peek() else {
+                    break;
+                };
+
+                if cmp_keys(&key, lower).is_lt() {
+                    // Jump to the first event of the next room.
+                    if !cursor.continue_cursor_with_key(&JsValue::from(lower))?.await? {
+                        break;
+                    }
+                    continue;
+                }
+
+                let value = cursor.value();
+                let event = if stripped {
+                    self.deserialize_event(&value).ok().map(RawAnySyncOrStrippedState::Stripped)
+                } else {
+                    self.deserialize_event(&value).ok().map(RawAnySyncOrStrippedState::Sync)
+                };
+                if let Some(event) = event {
+                    events.entry(room_id.as_ref()).or_default().push(event);
+                }
+
+                if !cursor.continue_cursor()?.await? {
+                    break;
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -988,6 +1100,33 @@ impl_state_store!({
         Ok(entries)
     }
 
+    async fn get_room_infos_filtered(&self, filter: RoomStateFilter) -> Result<Vec<RoomInfo>> {
+        let txn = self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_INFOS, IdbTransactionMode::Readonly)?;
+        let store = txn.object_store(keys::ROOM_INFOS)?;
+
+        let mut infos = Vec::new();
+        let cursor = store.open_cursor()?.await?;
+
+        if let Some(cursor) = cursor {
+            loop {
+                let value = cursor.value();
+                let info = self.deserialize_event::<RoomInfo>(&value)?;
+
+                if filter.matches(info.state()) {
+                    infos.push(info);
+                }
+
+                if !cursor.continue_cursor()?.await? {
+                    break;
+                }
+            }
+        }
+
+        Ok(infos)
+    }
+
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
         let txn = self
             .inner
@@ -1002,7 +1141,7 @@ impl_state_store!({
                 let value = cursor.value();
                 let info = self.deserialize_event::<RoomInfo>(&value)?;
 
-                if info.state() == RoomState::Invited {
+                if matches!(info.state(), RoomState::Invited | RoomState::Knocked) {
                     infos.push(info);
                 }
 
@@ -1216,6 +1355,18 @@ impl_state_store!({
         tx.await.into_result().map_err(|e| e.into())
     }
 
+    async fn export_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(Some(export_meta_db_store_cipher(&self.meta, old_passphrase, new_passphrase).await?))
+    }
+
+    async fn save_store_cipher(&self, export: Vec<u8>) -> Result<()> {
+        save_meta_db_store_cipher(&self.meta, export).await
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         let direct_stores = [keys::ROOM_INFOS];
 
@@ -1235,6 +1386,7 @@ impl_state_store!({
             let mut v = Vec::new();
             v.extend(prefixed_stores);
             v.extend(direct_stores);
+            v.push(keys::KV);
             v
         };
 
@@ -1253,6 +1405,10 @@ impl_state_store!({
                 store.delete(&key)?;
             }
         }
+
+        tx.object_store(keys::KV)?
+            .delete(&self.encode_kv_data_key(StateStoreDataKey::ComposerDraft(room_id)))?;
+
         tx.await.into_result().map_err(|e| e.into())
     }
 
@@ -1319,7 +1475,10 @@ mod encrypted_tests {
     #[cfg(target_arch = "wasm32")]
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
-    use matrix_sdk_base::statestore_integration_tests;
+    use matrix_sdk_base::{
+        statestore_integration_tests, StateStore, StateStoreDataKey, StateStoreDataValue,
+    };
+    use matrix_sdk_test::async_test;
     use uuid::Uuid;
 
     use super::{IndexeddbStateStore, Result};
@@ -1331,4 +1490,36 @@ mod encrypted_tests {
     }
 
     statestore_integration_tests!(with_media_tests);
+
+    #[async_test]
+    async fn test_change_passphrase() {
+        let db_name = format!("test-state-passphrase-{}", Uuid::new_v4().as_hyphenated());
+        let open = |passphrase: &str| {
+            IndexeddbStateStore::builder()
+                .name(db_name.clone())
+                .passphrase(passphrase.to_owned())
+                .build()
+        };
+
+        let store = open("old").await.unwrap();
+        store
+            .set_kv_data(
+                StateStoreDataKey::SyncToken,
+                StateStoreDataValue::SyncToken("token".to_owned()),
+            )
+            .await
+            .unwrap();
+
+        // The old passphrase must be right.
+        store.export_store_cipher("wrong", "new").await.unwrap_err();
+        let export = store.export_store_cipher("old", "new").await.unwrap().unwrap();
+        store.save_store_cipher(export).await.unwrap();
+        drop(store);
+
+        open("old").await.unwrap_err();
+
+        let store = open("new").await.unwrap();
+        let token = store.get_kv_data(StateStoreDataKey::SyncToken).await.unwrap();
+        assert_eq!(token.and_then(|v| v.into_sync_token()).as_deref(), Some("token"));
+    }
 }
@@ -0,0 +1,4 @@
+ALTER TABLE "media" ADD COLUMN "last_access" INTEGER NOT NULL DEFAULT 0;
+UPDATE "media" SET "last_access" = CAST(strftime('%s', 'now') AS INTEGER);
+
+CREATE INDEX "media_last_access_idx" ON "media" ("last_access");
@@ -44,7 +44,7 @@ use tracing::{debug, instrument, warn};
 
 use crate::{
     error::{Error, Result},
-    get_or_create_store_cipher,
+    export_store_cipher, get_or_create_store_cipher, save_store_cipher,
     utils::{
         load_db_version, Key, SqliteConnectionExt as _, SqliteObjectExt, SqliteObjectStoreExt as _,
     },
@@ -478,6 +478,20 @@ trait SqliteObjectCryptoStoreExt: SqliteObjectExt {
             .await?)
     }
 
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: Key,
+    ) -> Result<Vec<(Vec<u8>, bool)>> {
+        Ok(self
+            .prepare(
+                "SELECT data, backed_up FROM inbound_group_session WHERE room_id = ?",
+                |mut stmt| {
+                    stmt.query((room_id,))?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+                },
+            )
+            .await?)
+    }
+
     async fn get_inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
         let total = self
             .query_row("SELECT count(*) FROM inbound_group_session", (), |row| row.get(0))
@@ -920,6 +934,24 @@ impl CryptoStore for SqliteCryptoStore {
             .collect()
     }
 
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<InboundGroupSession>> {
+        let room_id = self.encode_key("inbound_group_session", room_id.as_bytes());
+
+        self.acquire()
+            .await?
+            .get_inbound_group_sessions_for_room(room_id)
+            .await?
+            .into_iter()
+            .map(|(value, backed_up)| {
+                let pickle = self.deserialize_pickled_inbound_group_session(&value, backed_up)?;
+                Ok(InboundGroupSession::from_pickle(pickle)?)
+            })
+            .collect()
+    }
+
     async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
         Ok(self.acquire().await?.get_inbound_group_session_counts().await?)
     }
@@ -1224,6 +1256,20 @@ impl CryptoStore for SqliteCryptoStore {
             Ok(None)
         }
     }
+
+    async fn export_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let conn = self.acquire().await?;
+        Ok(Some(export_store_cipher(old_passphrase, new_passphrase, &conn).await?))
+    }
+
+    async fn save_store_cipher(&self, export: Vec<u8>) -> Result<()> {
+        let conn = self.acquire().await?;
+        save_store_cipher(export, &conn).await
+    }
 }
 
 #[cfg(test)]
@@ -99,6 +99,9 @@ pub enum Error {
 
     #[error("Redaction failed: {0}")]
     Redaction(#[source] ruma::canonical_json::RedactionError),
+
+    #[error("The store is not encrypted with a passphrase")]
+    MissingStoreCipher,
 }
 
 macro_rules! impl_from {
@@ -33,8 +33,8 @@ mod utils;
 pub use self::crypto_store::SqliteCryptoStore;
 pub use self::error::OpenStoreError;
 #[cfg(feature = "state-store")]
-pub use self::state_store::SqliteStateStore;
-use self::utils::SqliteObjectStoreExt;
+pub use self::state_store::{MediaRetentionPolicy, SqliteStateStore};
+use self::{error::Error, utils::SqliteObjectStoreExt};
 
 async fn get_or_create_store_cipher(
     passphrase: &str,
@@ -57,6 +57,35 @@ async fn get_or_create_store_cipher(
     Ok(cipher)
 }
 
+/// Export the store cipher saved in the database, encrypted with a new
+/// passphrase.
+async fn export_store_cipher(
+    old_passphrase: &str,
+    new_passphrase: &str,
+    conn: &SqliteConn,
+) -> Result<Vec<u8>, Error> {
+    let encrypted_cipher = conn.get_kv("cipher").await?.ok_or(Error::MissingStoreCipher)?;
+    let cipher = StoreCipher::import(old_passphrase, &encrypted_cipher)?;
+
+    #[cfg(not(test))]
+    let export = cipher.export(new_passphrase);
+    #[cfg(test)]
+    let export = cipher._insecure_export_fast_for_testing(new_passphrase);
+
+    Ok(export?)
+}
+
+/// Replace the store cipher saved in the database.
+async fn save_store_cipher(export: Vec<u8>, conn: &SqliteConn) -> Result<(), Error> {
+    if conn.get_kv("cipher").await?.is_none() {
+        return Err(Error::MissingStoreCipher);
+    }
+
+    conn.set_kv("cipher", export).await?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 #[ctor::ctor]
 fn init_logging() {
@@ -7,6 +7,7 @@ use std::{
     iter,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use async_trait::async_trait;
@@ -15,8 +16,8 @@ use itertools::Itertools;
 use matrix_sdk_base::{
     deserialized_responses::RawAnySyncOrStrippedState,
     media::{MediaRequest, UniqueKey},
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateChanges, StateStore,
-    StateStoreDataKey, StateStoreDataValue,
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, RoomStateFilter, StateChanges,
+    StateStore, StateStoreDataKey, StateStoreDataValue,
 };
 use matrix_sdk_store_encryption::StoreCipher;
 use ruma::{
@@ -29,7 +30,8 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId,
+    UserId,
 };
 use rusqlite::{limits::Limit, OptionalExtension, Transaction};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -38,7 +40,7 @@ use tracing::{debug, warn};
 
 use crate::{
     error::{Error, Result},
-    get_or_create_store_cipher,
+    export_store_cipher, get_or_create_store_cipher, save_store_cipher,
     utils::{load_db_version, Key, SqliteObjectExt},
     OpenStoreError, SqliteObjectStoreExt,
 };
@@ -57,7 +59,34 @@ mod keys {
     pub const MEDIA: &str = "media";
 }
 
-const DATABASE_VERSION: u8 = 2;
+const DATABASE_VERSION: u8 = 3;
+
+/// The precision with which the last access time of a media file is tracked.
+const MEDIA_LAST_ACCESS_RESOLUTION: Duration = Duration::from_secs(60);
+
+/// The retention policy of the media cache of a [`SqliteStateStore`].
+///
+/// The sizes are the ones of the media files as they are stored, i.e. after
+/// they have been encrypted if the store uses a passphrase.
+///
+/// The default policy doesn't set any limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MediaRetentionPolicy {
+    /// The maximum total size of the media cache, in bytes.
+    ///
+    /// When it is exceeded, the least recently accessed media files are
+    /// removed.
+    pub max_cache_size: Option<usize>,
+
+    /// The maximum size of a single media file, in bytes.
+    ///
+    /// Bigger media files are not cached.
+    pub max_file_size: Option<usize>,
+
+    /// The maximum duration a media file is kept in the cache since it was last
+    /// accessed.
+    pub max_age: Option<Duration>,
+}
 
 /// A sqlite based cryptostore.
 #[derive(Clone)]
@@ -65,6 +94,7 @@ pub struct SqliteStateStore {
     store_cipher: Option<Arc<StoreCipher>>,
     path: Option<PathBuf>,
     pool: SqlitePool,
+    media_retention_policy: MediaRetentionPolicy,
 }
 
 impl fmt::Debug for SqliteStateStore {
@@ -107,12 +137,31 @@ impl SqliteStateStore {
             Some(p) => Some(Arc::new(get_or_create_store_cipher(p, &conn).await?)),
             None => None,
         };
-        let this = Self { store_cipher, path: None, pool };
+        let this = Self {
+            store_cipher,
+            path: None,
+            pool,
+            media_retention_policy: MediaRetentionPolicy::default(),
+        };
         this.run_migrations(&conn, version, None).await?;
 
         Ok(this)
     }
 
+    /// Use the given retention policy for the media cache.
+    ///
+    /// It is applied every time a media file is added to the cache, and with
+    /// [`StateStore::clean_up_media_cache()`].
+    pub fn with_media_retention_policy(mut self, policy: MediaRetentionPolicy) -> Self {
+        self.media_retention_policy = policy;
+        self
+    }
+
+    /// The retention policy for the media cache.
+    pub fn media_retention_policy(&self) -> MediaRetentionPolicy {
+        self.media_retention_policy
+    }
+
     /// Run database migrations from the given `from` version to the given `to`
     /// version
     ///
@@ -162,6 +211,15 @@ impl SqliteStateStore {
             .await?;
         }
 
+        if from < 3 && to >= 3 {
+            conn.with_transaction(move |txn| {
+                txn.execute_batch(include_str!(
+                    "../migrations/state_store/003_media_last_access.sql"
+                ))
+            })
+            .await?;
+        }
+
         conn.set_kv("version", vec![to]).await?;
 
         Ok(())
@@ -224,6 +282,9 @@ impl SqliteStateStore {
             StateStoreDataKey::UserAvatarUrl(u) => {
                 Cow::Owned(format!("{}:{u}", StateStoreDataKey::USER_AVATAR_URL))
             }
+            StateStoreDataKey::ComposerDraft(r) => {
+                Cow::Owned(format!("{}:{r}", StateStoreDataKey::COMPOSER_DRAFT))
+            }
         };
 
         self.encode_key(keys::KV_BLOB, &*key_s)
@@ -280,6 +341,7 @@ async fn init(conn: &SqliteConn) -> Result<()> {
 
 trait SqliteConnectionStateStoreExt {
     fn set_kv_blob(&self, key: &[u8], value: &[u8]) -> rusqlite::Result<()>;
+    fn remove_kv_blob(&self, key: &[u8]) -> rusqlite::Result<()>;
 
     fn set_global_account_data(&self, event_type: &[u8], data: &[u8]) -> rusqlite::Result<()>;
 
@@ -350,6 +412,11 @@ impl SqliteConnectionStateStoreExt for rusqlite::Connection {
         Ok(())
     }
 
+    fn remove_kv_blob(&self, key: &[u8]) -> rusqlite::Result<()> {
+        self.execute("DELETE FROM kv_blob WHERE key = ?", (key,))?;
+        Ok(())
+    }
+
     fn set_global_account_data(&self, event_type: &[u8], data: &[u8]) -> rusqlite::Result<()> {
         self.prepare_cached(
             "INSERT OR REPLACE INTO global_account_data (event_type, data)
@@ -640,6 +707,29 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
             .await?)
     }
 
+    async fn get_maybe_stripped_state_events_for_rooms(
+        &self,
+        room_ids: Vec<Key>,
+        event_type: Key,
+    ) -> Result<Vec<(Vec<u8>, bool, Vec<u8>)>> {
+        chunk_large_query_over(room_ids, None, move |room_ids| {
+            let sql_params = repeat_vars(room_ids.len());
+            let sql = format!(
+                "SELECT room_id, stripped, data FROM state_event
+                 WHERE event_type = ? AND room_id IN ({sql_params})"
+            );
+
+            let params = rusqlite::params_from_iter(iter::once(event_type.clone()).chain(room_ids));
+
+            self.prepare(sql, move |mut stmt| {
+                stmt.query(params)?
+                    .mapped(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                    .collect()
+            })
+        })
+        .await
+    }
+
     async fn get_profiles(
         &self,
         room_id: Key,
@@ -776,22 +866,38 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
 
     async fn set_media(&self, uri: Key, format: Key, data: Vec<u8>) -> Result<()> {
         self.execute(
-            "INSERT OR REPLACE INTO media (uri, format, data) VALUES (?, ?, ?)",
-            (uri, format, data),
+            "INSERT OR REPLACE INTO media (uri, format, data, last_access) VALUES (?, ?, ?, ?)",
+            (uri, format, data, unix_timestamp_now()),
         )
         .await?;
         Ok(())
     }
 
     async fn get_media(&self, uri: Key, format: Key) -> Result<Option<Vec<u8>>> {
-        Ok(self
+        let Some((data, last_access)) = self
             .query_row(
-                "SELECT data FROM media WHERE uri = ? AND format = ?",
-                (uri, format),
-                |row| row.get(0),
+                "SELECT data, last_access FROM media WHERE uri = ? AND format = ?",
+                (uri.clone(), format.clone()),
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?)),
             )
             .await
-            .optional()?)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        // Only write the last access time when it is stale enough to matter for
+        // the retention policy, to avoid a write for every cache hit.
+        let now = unix_timestamp_now();
+        if now.saturating_sub(last_access) >= MEDIA_LAST_ACCESS_RESOLUTION.as_secs() as i64 {
+            self.execute(
+                "UPDATE media SET last_access = ? WHERE uri = ? AND format = ?",
+                (now, uri, format),
+            )
+            .await?;
+        }
+
+        Ok(Some(data))
     }
 
     async fn remove_media(&self, uri: Key, format: Key) -> Result<()> {
@@ -803,6 +909,34 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
         self.execute("DELETE FROM media WHERE uri = ?", (uri,)).await?;
         Ok(())
     }
+
+    async fn clean_up_media(&self, policy: MediaRetentionPolicy) -> Result<()> {
+        if let Some(max_age) = policy.max_age {
+            let max_age = i64::try_from(max_age.as_secs()).unwrap_or(i64::MAX);
+            let min_last_access = unix_timestamp_now().saturating_sub(max_age);
+            self.execute("DELETE FROM media WHERE last_access < ?", (min_last_access,)).await?;
+        }
+
+        if let Some(max_cache_size) = policy.max_cache_size {
+            let max_cache_size = i64::try_from(max_cache_size).unwrap_or(i64::MAX);
+            // Keep the most recently accessed media files that fit in the cache.
+            self.execute(
+                "DELETE FROM media WHERE rowid IN (
+                    SELECT rowid FROM (
+                        SELECT rowid, SUM(length(data)) OVER (
+                            ORDER BY last_access DESC, rowid DESC
+                        ) AS cumulated_size
+                        FROM media
+                    )
+                    WHERE cumulated_size > ?
+                )",
+                (max_cache_size,),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -822,12 +956,18 @@ impl StateStore for SqliteStateStore {
             .get_kv_blob(self.encode_state_store_data_key(key))
             .await?
             .map(|data| {
-                let string = self.deserialize_value(&data)?;
                 Ok(match key {
-                    StateStoreDataKey::SyncToken => StateStoreDataValue::SyncToken(string),
-                    StateStoreDataKey::Filter(_) => StateStoreDataValue::Filter(string),
+                    StateStoreDataKey::SyncToken => {
+                        StateStoreDataValue::SyncToken(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::Filter(_) => {
+                        StateStoreDataValue::Filter(self.deserialize_value(&data)?)
+                    }
                     StateStoreDataKey::UserAvatarUrl(_) => {
-                        StateStoreDataValue::UserAvatarUrl(string)
+                        StateStoreDataValue::UserAvatarUrl(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::ComposerDraft(_) => {
+                        StateStoreDataValue::ComposerDraft(self.deserialize_value(&data)?)
                     }
                 })
             })
@@ -839,19 +979,24 @@ impl StateStore for SqliteStateStore {
         key: StateStoreDataKey<'_>,
         value: StateStoreDataValue,
     ) -> Result<()> {
-        let value = match key {
-            StateStoreDataKey::SyncToken => {
-                value.into_sync_token().expect("Session data not a sync token")
-            }
-            StateStoreDataKey::Filter(_) => value.into_filter().expect("Session data not a filter"),
-            StateStoreDataKey::UserAvatarUrl(_) => {
-                value.into_user_avatar_url().expect("Session data not an user avatar url")
+        let serialized_value = match key {
+            StateStoreDataKey::SyncToken => self.serialize_value(
+                &value.into_sync_token().expect("Session data not a sync token"),
+            )?,
+            StateStoreDataKey::Filter(_) => {
+                self.serialize_value(&value.into_filter().expect("Session data not a filter"))?
             }
+            StateStoreDataKey::UserAvatarUrl(_) => self.serialize_value(
+                &value.into_user_avatar_url().expect("Session data not an user avatar url"),
+            )?,
+            StateStoreDataKey::ComposerDraft(_) => self.serialize_value(
+                &value.into_composer_draft().expect("Session data not a composer draft"),
+            )?,
         };
 
         self.acquire()
             .await?
-            .set_kv_blob(self.encode_state_store_data_key(key), self.serialize_value(&value)?)
+            .set_kv_blob(self.encode_state_store_data_key(key), serialized_value)
             .await
     }
 
@@ -910,7 +1055,8 @@ impl StateStore for SqliteStateStore {
                 }
 
                 for (room_id, room_info) in room_infos {
-                    let stripped = room_info.state() == RoomState::Invited;
+                    let stripped =
+                        matches!(room_info.state(), RoomState::Invited | RoomState::Knocked);
                     // Remove non-stripped data for stripped rooms and vice-versa.
                     this.remove_maybe_stripped_room_data(txn, &room_id, !stripped)?;
 
@@ -1234,6 +1380,43 @@ impl StateStore for SqliteStateStore {
             .collect()
     }
 
+    async fn get_state_events_for_rooms<'a>(
+        &self,
+        room_ids: &'a [OwnedRoomId],
+        event_type: StateEventType,
+    ) -> Result<BTreeMap<&'a RoomId, Vec<RawAnySyncOrStrippedState>>> {
+        if room_ids.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        let room_ids_map = room_ids
+            .iter()
+            .map(|r| (self.encode_key(keys::STATE_EVENT, r), r.as_ref()))
+            .collect::<BTreeMap<_, &RoomId>>();
+        let room_ids = room_ids_map.keys().cloned().collect();
+        let event_type = self.encode_key(keys::STATE_EVENT, event_type.to_string());
+
+        let mut events = BTreeMap::<_, Vec<_>>::new();
+        for (room_id, stripped, data) in self
+            .acquire()
+            .await?
+            .get_maybe_stripped_state_events_for_rooms(room_ids, event_type)
+            .await?
+        {
+            let ev = if stripped {
+                RawAnySyncOrStrippedState::Stripped(self.deserialize_json(&data)?)
+            } else {
+                RawAnySyncOrStrippedState::Sync(self.deserialize_json(&data)?)
+            };
+
+            let room_id =
+                room_ids_map.get(room_id.as_slice()).expect("returned room IDs were requested");
+            events.entry(*room_id).or_default().push(ev);
+        }
+
+        Ok(events)
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -1322,9 +1505,28 @@ impl StateStore for SqliteStateStore {
             .collect()
     }
 
+    async fn get_room_infos_filtered(&self, filter: RoomStateFilter) -> Result<Vec<RoomInfo>> {
+        let states = filter
+            .as_vec()
+            .into_iter()
+            .map(|state| {
+                serde_json::to_string(&state).map(|state| self.encode_key(keys::ROOM_INFO, state))
+            })
+            .collect::<Result<_, _>>()?;
+        self.acquire()
+            .await?
+            .get_room_infos(states)
+            .await?
+            .into_iter()
+            .map(|data| self.deserialize_json(&data))
+            .collect()
+    }
+
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
-        let states =
-            vec![self.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Invited)?)];
+        let states = vec![
+            self.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Invited)?),
+            self.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Knocked)?),
+        ];
         self.acquire()
             .await?
             .get_room_infos(states)
@@ -1489,7 +1691,16 @@ impl StateStore for SqliteStateStore {
         let uri = self.encode_key(keys::MEDIA, request.source.unique_key());
         let format = self.encode_key(keys::MEDIA, request.format.unique_key());
         let data = self.encode_value(content)?;
-        self.acquire().await?.set_media(uri, format, data).await
+
+        let policy = self.media_retention_policy;
+        if policy.max_file_size.is_some_and(|max_file_size| data.len() > max_file_size) {
+            debug!("Not caching media file that exceeds the maximum file size");
+            return Ok(());
+        }
+
+        let conn = self.acquire().await?;
+        conn.set_media(uri, format, data).await?;
+        conn.clean_up_media(policy).await
     }
 
     async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
@@ -1510,6 +1721,24 @@ impl StateStore for SqliteStateStore {
         self.acquire().await?.remove_uri_medias(uri).await
     }
 
+    async fn clean_up_media_cache(&self) -> Result<()> {
+        self.acquire().await?.clean_up_media(self.media_retention_policy).await
+    }
+
+    async fn export_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let conn = self.acquire().await?;
+        Ok(Some(export_store_cipher(old_passphrase, new_passphrase, &conn).await?))
+    }
+
+    async fn save_store_cipher(&self, export: Vec<u8>) -> Result<()> {
+        let conn = self.acquire().await?;
+        save_store_cipher(export, &conn).await
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         let this = self.clone();
         let room_id = room_id.to_owned();
@@ -1538,6 +1767,10 @@ impl StateStore for SqliteStateStore {
                 let display_name_room_id = this.encode_key(keys::DISPLAY_NAME, &room_id);
                 txn.remove_room_display_names(&display_name_room_id)?;
 
+                let composer_draft_key =
+                    this.encode_state_store_data_key(StateStoreDataKey::ComposerDraft(&room_id));
+                txn.remove_kv_blob(&composer_draft_key)?;
+
                 Ok(())
             })
             .await
@@ -1589,6 +1822,12 @@ where
     Ok(all_results)
 }
 
+/// The current time as a UNIX timestamp, in seconds.
+fn unix_timestamp_now() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    i64::try_from(now.as_secs()).unwrap_or(i64::MAX)
+}
+
 /// Repeat `?` n times, where n is defined by `count`. `?` are comma-separated.
 fn repeat_vars(count: usize) -> impl fmt::Display {
     assert_ne!(count, 0);
@@ -1623,7 +1862,11 @@ mod tests {
 mod encrypted_tests {
     use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
 
-    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+    use matrix_sdk_base::{
+        statestore_integration_tests, StateStore, StateStoreDataKey, StateStoreDataValue,
+        StoreError,
+    };
+    use matrix_sdk_test::async_test;
     use once_cell::sync::Lazy;
     use tempfile::{tempdir, TempDir};
 
@@ -1642,6 +1885,37 @@ mod encrypted_tests {
     }
 
     statestore_integration_tests!(with_media_tests);
+
+    #[async_test]
+    async fn test_change_passphrase() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+
+        let store = SqliteStateStore::open(&tmpdir_path, Some("old")).await.unwrap();
+        store
+            .set_kv_data(
+                StateStoreDataKey::SyncToken,
+                StateStoreDataValue::SyncToken("token".to_owned()),
+            )
+            .await
+            .unwrap();
+
+        // The old passphrase must be right.
+        store.export_store_cipher("wrong", "new").await.unwrap_err();
+        let export = store.export_store_cipher("old", "new").await.unwrap().unwrap();
+
+        // Exporting the store cipher doesn't change the passphrase.
+        drop(store);
+        let store = SqliteStateStore::open(&tmpdir_path, Some("old")).await.unwrap();
+        store.save_store_cipher(export).await.unwrap();
+        drop(store);
+
+        SqliteStateStore::open(&tmpdir_path, Some("old")).await.unwrap_err();
+
+        let store = SqliteStateStore::open(&tmpdir_path, Some("new")).await.unwrap();
+        let token = store.get_kv_data(StateStoreDataKey::SyncToken).await.unwrap();
+        assert_eq!(token.and_then(|v| v.into_sync_token()).as_deref(), Some("token"));
+    }
 }
 
 #[cfg(test)]
@@ -1683,7 +1957,12 @@ mod migration_tests {
         init(&conn).await?;
 
         let store_cipher = Some(Arc::new(get_or_create_store_cipher(SECRET, &conn).await.unwrap()));
-        let this = SqliteStateStore { store_cipher, path: None, pool };
+        let this = SqliteStateStore {
+            store_cipher,
+            path: None,
+            pool,
+            media_retention_policy: Default::default(),
+        };
         this.run_migrations(&conn, 1, Some(version)).await?;
 
         Ok(this)
@@ -1731,3 +2010,108 @@ mod migration_tests {
         assert_eq!(stripped_rooms.len(), 2);
     }
 }
+
+#[cfg(test)]
+mod media_retention_tests {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering::SeqCst},
+        time::Duration,
+    };
+
+    use matrix_sdk_base::{
+        media::{MediaFormat, MediaRequest},
+        StateStore,
+    };
+    use matrix_sdk_test::async_test;
+    use once_cell::sync::Lazy;
+    use ruma::{events::room::MediaSource, mxc_uri, MxcUri};
+    use tempfile::{tempdir, TempDir};
+
+    use super::{MediaRetentionPolicy, SqliteStateStore};
+    use crate::utils::SqliteObjectExt;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+    static NUM: AtomicU32 = AtomicU32::new(0);
+
+    async fn get_store(policy: MediaRetentionPolicy) -> SqliteStateStore {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+
+        SqliteStateStore::open(tmpdir_path.to_str().unwrap(), None)
+            .await
+            .unwrap()
+            .with_media_retention_policy(policy)
+    }
+
+    fn media_request(uri: &MxcUri) -> MediaRequest {
+        MediaRequest { source: MediaSource::Plain(uri.to_owned()), format: MediaFormat::File }
+    }
+
+    async fn set_last_access(store: &SqliteStateStore, last_access: i64) {
+        let conn = store.pool.get().await.unwrap();
+        conn.execute("UPDATE media SET last_access = ?", (last_access,)).await.unwrap();
+    }
+
+    #[async_test]
+    async fn test_max_file_size() {
+        let store =
+            get_store(MediaRetentionPolicy { max_file_size: Some(10), ..Default::default() }).await;
+
+        let small = media_request(mxc_uri!("mxc://localhost/small"));
+        let big = media_request(mxc_uri!("mxc://localhost/big"));
+
+        store.add_media_content(&small, vec![0; 10]).await.unwrap();
+        store.add_media_content(&big, vec![0; 11]).await.unwrap();
+
+        assert!(store.get_media_content(&small).await.unwrap().is_some());
+        assert!(store.get_media_content(&big).await.unwrap().is_none());
+    }
+
+    #[async_test]
+    async fn test_max_cache_size_evicts_least_recently_accessed() {
+        let store =
+            get_store(MediaRetentionPolicy { max_cache_size: Some(20), ..Default::default() })
+                .await;
+
+        let first = media_request(mxc_uri!("mxc://localhost/first"));
+        let second = media_request(mxc_uri!("mxc://localhost/second"));
+        let third = media_request(mxc_uri!("mxc://localhost/third"));
+
+        store.add_media_content(&first, vec![0; 10]).await.unwrap();
+        store.add_media_content(&second, vec![0; 10]).await.unwrap();
+        set_last_access(&store, 1).await;
+
+        // Accessing the first media file makes it the most recently accessed.
+        assert!(store.get_media_content(&first).await.unwrap().is_some());
+
+        store.add_media_content(&third, vec![0; 10]).await.unwrap();
+
+        assert!(store.get_media_content(&first).await.unwrap().is_some());
+        assert!(store.get_media_content(&second).await.unwrap().is_none());
+        assert!(store.get_media_content(&third).await.unwrap().is_some());
+    }
+
+    #[async_test]
+    async fn test_clean_up_media_cache_removes_expired_media() {
+        let store = get_store(MediaRetentionPolicy {
+            max_age: Some(Duration::from_secs(60)),
+            ..Default::default()
+        })
+        .await;
+
+        let old = media_request(mxc_uri!("mxc://localhost/old"));
+        let recent = media_request(mxc_uri!("mxc://localhost/recent"));
+
+        store.add_media_content(&old, vec![0; 10]).await.unwrap();
+        set_last_access(&store, 1).await;
+        store.add_media_content(&recent, vec![0; 10]).await.unwrap();
+
+        // The old media file was removed when the recent one was added.
+        assert!(store.get_media_content(&old).await.unwrap().is_none());
+        assert!(store.get_media_content(&recent).await.unwrap().is_some());
+
+        set_last_access(&store, 1).await;
+        store.clean_up_media_cache().await.unwrap();
+        assert!(store.get_media_content(&recent).await.unwrap().is_none());
+    }
+}
@@ -31,9 +31,8 @@ use std::time::Duration;
 use async_stream::stream;
 use futures_core::stream::Stream;
 use futures_util::{pin_mut, StreamExt};
-use matrix_sdk::{Client, SlidingSync};
+use matrix_sdk::{Client, SlidingSync, SlidingSyncExtension};
 use matrix_sdk_crypto::store::locks::CryptoStoreLock;
-use ruma::{api::client::sync::sync_events::v4, assign};
 use tracing::{debug, trace};
 
 /// Should the `EncryptionSync` make use of locking?
@@ -80,10 +79,8 @@ impl EncryptionSync {
         let mut builder = client
             .sliding_sync("encryption")
             .map_err(Error::SlidingSync)?
-            .with_to_device_extension(
-                assign!(v4::ToDeviceConfig::default(), { enabled: Some(true)}),
-            )
-            .with_e2ee_extension(assign!(v4::E2EEConfig::default(), { enabled: Some(true)}));
+            .with_extension(SlidingSyncExtension::ToDevice)
+            .with_extension(SlidingSyncExtension::E2ee);
 
         if let Some((poll_timeout, network_timeout)) = poll_and_network_timeouts {
             builder = builder.poll_timeout(poll_timeout).network_timeout(network_timeout);
@@ -111,6 +108,28 @@ impl EncryptionSync {
         Ok(Self { client, sliding_sync, with_locking })
     }
 
+    /// Run a single sync, only fetching the to-device events and the
+    /// end-to-end encryption data, without waiting for new events if there
+    /// are none.
+    ///
+    /// This is meant for notification extensions that only need to receive
+    /// the room keys to decrypt a notification, as quickly as possible. The
+    /// same sliding sync as [`EncryptionSync::new()`] is used, and the
+    /// cross-process lock is taken as in
+    /// [`EncryptionSync::run_fixed_iterations()`].
+    pub async fn sync_to_device_once(
+        process_id: String,
+        client: Client,
+        network_timeout: Duration,
+        with_locking: WithLocking,
+    ) -> Result<(), Error> {
+        let encryption_sync =
+            Self::new(process_id, client, Some((Duration::ZERO, network_timeout)), with_locking)
+                .await?;
+
+        encryption_sync.run_fixed_iterations(1).await
+    }
+
     pub async fn run_fixed_iterations(self, num_iterations: u8) -> Result<(), Error> {
         let sync = self.sliding_sync.sync();
 
@@ -0,0 +1,621 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Safe rendering of the HTML of messages.
+//!
+//! The `formatted_body` of a message is sanitized according to the
+//! [allow-list of the spec], with the reply fallback stripped, and converted
+//! into a [`RichText`]: a list of blocks, like paragraphs or list items,
+//! made of text spans with formatting attributes. This intermediate
+//! representation can be rendered by any UI toolkit without having to deal
+//! with HTML.
+//!
+//! [allow-list of the spec]: https://spec.matrix.org/v1.10/client-server-api/#mroommessage-msgtypes
+
+use html5ever::{
+    tendril::StrTendril,
+    tokenizer::{
+        BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+    },
+};
+use matrix_sdk::media::{MediaFormat, MediaRequest, MediaThumbnailSize};
+use ruma::{
+    api::client::media::get_content_thumbnail::v3::Method,
+    events::room::{
+        message::{
+            sanitize::{HtmlSanitizerMode, RemoveReplyFallback},
+            FormattedBody, MessageFormat,
+        },
+        MediaSource,
+    },
+    OwnedMxcUri, UInt,
+};
+
+/// The sanitized content of an HTML message, as blocks of formatted text.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RichText {
+    /// The blocks of the message, in order.
+    pub blocks: Vec<Block>,
+}
+
+impl RichText {
+    /// Sanitize and parse the given HTML.
+    ///
+    /// Only the elements and attributes allowed by the spec are kept, and the
+    /// `<mx-reply>` fallback of replies is removed.
+    pub fn from_html(html: &str) -> Self {
+        let mut formatted = FormattedBody::html(html);
+        formatted.sanitize_html(HtmlSanitizerMode::Strict, RemoveReplyFallback::Yes);
+
+        let mut queue = BufferQueue::new();
+        queue.push_back(StrTendril::from_slice(&formatted.body));
+
+        let mut tokenizer = Tokenizer::new(RichTextBuilder::default(), TokenizerOpts::default());
+        let _ = tokenizer.feed(&mut queue);
+        tokenizer.end();
+
+        tokenizer.sink.finish()
+    }
+
+    /// Sanitize and parse the given formatted body of a message.
+    ///
+    /// Returns `None` if the body is not in the HTML format.
+    pub fn from_formatted_body(formatted: &FormattedBody) -> Option<Self> {
+        (formatted.format == MessageFormat::Html).then(|| Self::from_html(&formatted.body))
+    }
+}
+
+/// A block of a [`RichText`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Block {
+    /// The kind of the block.
+    pub kind: BlockKind,
+
+    /// The number of quotes the block is nested in.
+    pub quote_depth: usize,
+
+    /// The list item the block starts, if any.
+    pub list_item: Option<ListItem>,
+
+    /// The content of the block.
+    pub spans: Vec<Span>,
+}
+
+/// The kind of a [`Block`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum BlockKind {
+    /// A paragraph.
+    #[default]
+    Paragraph,
+
+    /// A heading, with its level between 1 and 6.
+    Heading(u8),
+
+    /// Preformatted code, whose whitespace must be preserved.
+    CodeBlock {
+        /// The language of the code, if known.
+        language: Option<String>,
+    },
+
+    /// A horizontal rule, without content.
+    HorizontalRule,
+}
+
+/// The list item started by a [`Block`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListItem {
+    /// The number of lists the item is nested in, starting at 1.
+    pub depth: usize,
+
+    /// The marker of the item.
+    pub marker: ListMarker,
+}
+
+/// The marker of a [`ListItem`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListMarker {
+    /// A bullet, for unordered lists.
+    Bullet,
+
+    /// A number, for ordered lists.
+    Number(u64),
+}
+
+/// A span of the content of a [`Block`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Span {
+    /// Text with the given format.
+    Text {
+        /// The text.
+        text: String,
+
+        /// The format of the text.
+        format: TextFormat,
+    },
+
+    /// An inline image.
+    Image(Image),
+
+    /// A line break.
+    LineBreak,
+}
+
+/// The formatting attributes of a [`Span::Text`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextFormat {
+    /// Whether the text is bold.
+    pub bold: bool,
+
+    /// Whether the text is italic.
+    pub italic: bool,
+
+    /// Whether the text is underlined.
+    pub underline: bool,
+
+    /// Whether the text is struck through.
+    pub strikethrough: bool,
+
+    /// Whether the text is inline code.
+    pub code: bool,
+
+    /// Whether the text is a superscript.
+    pub superscript: bool,
+
+    /// Whether the text is a subscript.
+    pub subscript: bool,
+
+    /// Whether the text is hidden behind a spoiler.
+    pub spoiler: bool,
+
+    /// The URL the text links to, if any.
+    pub link: Option<String>,
+
+    /// The color of the text, as a `#rrggbb` hex string, if any.
+    pub color: Option<String>,
+
+    /// The background color of the text, as a `#rrggbb` hex string, if any.
+    pub background_color: Option<String>,
+}
+
+/// An inline image of a [`RichText`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    /// The `mxc://` URI of the image.
+    pub source: OwnedMxcUri,
+
+    /// The alternative text of the image, if any.
+    pub alt: Option<String>,
+
+    /// The title of the image, if any.
+    pub title: Option<String>,
+
+    /// The width the image should be displayed with, if any.
+    pub width: Option<UInt>,
+
+    /// The height the image should be displayed with, if any.
+    pub height: Option<UInt>,
+}
+
+impl Image {
+    /// The request to download the image with [`Media::get_media_content()`].
+    ///
+    /// If the image has a size, a thumbnail of that size is requested.
+    ///
+    /// [`Media::get_media_content()`]: matrix_sdk::Media::get_media_content
+    pub fn media_request(&self) -> MediaRequest {
+        let format = match (self.width, self.height) {
+            (Some(width), Some(height)) => {
+                MediaFormat::Thumbnail(MediaThumbnailSize { method: Method::Scale, width, height })
+            }
+            _ => MediaFormat::File,
+        };
+
+        MediaRequest { source: MediaSource::Plain(self.source.clone()), format }
+    }
+}
+
+/// An element that was opened and not closed yet.
+#[derive(Debug)]
+struct OpenElement {
+    name: String,
+    /// The format of the text inside the element.
+    format: TextFormat,
+    /// Whether the element is a quote or a list, whose end must be tracked.
+    is_quote: bool,
+    is_list: bool,
+}
+
+/// The state of an open list.
+#[derive(Debug)]
+struct OpenList {
+    /// The number of the next item, for ordered lists.
+    next_number: Option<u64>,
+}
+
+/// Builds a [`RichText`] from the tokens of sanitized HTML.
+#[derive(Debug, Default)]
+struct RichTextBuilder {
+    blocks: Vec<Block>,
+    current: Block,
+    elements: Vec<OpenElement>,
+    lists: Vec<OpenList>,
+    quote_depth: usize,
+}
+
+impl RichTextBuilder {
+    fn format(&self) -> TextFormat {
+        self.elements.last().map(|element| element.format.clone()).unwrap_or_default()
+    }
+
+    fn in_code_block(&self) -> bool {
+        matches!(self.current.kind, BlockKind::CodeBlock { .. })
+    }
+
+    /// Finish the current block, and start a new one of the given kind.
+    fn start_block(&mut self, kind: BlockKind, list_item: Option<ListItem>) {
+        // A list item starting with a block, like a paragraph, is rendered as
+        // a single block.
+        let list_item = list_item.or_else(|| {
+            self.current.spans.is_empty().then(|| self.current.list_item.take()).flatten()
+        });
+
+        self.end_block();
+        self.current = Block { kind, quote_depth: self.quote_depth, list_item, spans: Vec::new() };
+    }
+
+    /// Finish the current block, keeping it if it has content.
+    fn end_block(&mut self) {
+        let mut block = std::mem::replace(
+            &mut self.current,
+            Block { quote_depth: self.quote_depth, ..Default::default() },
+        );
+
+        if !matches!(block.kind, BlockKind::CodeBlock { .. }) {
+            if let Some(Span::Text { text, .. }) = block.spans.last_mut() {
+                text.truncate(text.trim_end().len());
+            }
+            block.spans.retain(|span| !matches!(span, Span::Text { text, .. } if text.is_empty()));
+        }
+
+        if !block.spans.is_empty()
+            || block.list_item.is_some()
+            || block.kind == BlockKind::HorizontalRule
+        {
+            self.blocks.push(block);
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let text = if self.in_code_block() {
+            text.to_owned()
+        } else {
+            // Outside of preformatted text, whitespace is collapsed, and
+            // ignored at the start of a line.
+            let at_line_start = match self.current.spans.last() {
+                None | Some(Span::LineBreak) => true,
+                Some(Span::Text { text, .. }) => text.ends_with(' '),
+                Some(Span::Image(_)) => false,
+            };
+
+            let mut collapsed = String::with_capacity(text.len());
+            let mut last_was_space = at_line_start;
+            for c in text.chars() {
+                if c.is_whitespace() {
+                    if !last_was_space {
+                        collapsed.push(' ');
+                    }
+                    last_was_space = true;
+                } else {
+                    collapsed.push(c);
+                    last_was_space = false;
+                }
+            }
+
+            collapsed
+        };
+
+        if text.is_empty() {
+            return;
+        }
+
+        let format = self.format();
+        match self.current.spans.last_mut() {
+            Some(Span::Text { text: last, format: last_format }) if *last_format == format => {
+                last.push_str(&text);
+            }
+            _ => self.current.spans.push(Span::Text { text, format }),
+        }
+    }
+
+    fn start_tag(&mut self, tag: Tag) {
+        let name = &*tag.name;
+        let attr = |name: &str| {
+            tag.attrs
+                .iter()
+                .find(|attr| &*attr.name.local == name)
+                .map(|attr| attr.value.to_string())
+        };
+
+        let mut format = self.format();
+        let mut is_quote = false;
+        let mut is_list = false;
+
+        match name {
+            // Void elements.
+            "br" => {
+                self.current.spans.push(Span::LineBreak);
+                return;
+            }
+            "hr" => {
+                self.start_block(BlockKind::HorizontalRule, None);
+                self.end_block();
+                return;
+            }
+            "img" => {
+                if let Some(source) = attr("src") {
+                    self.current.spans.push(Span::Image(Image {
+                        source: source.into(),
+                        alt: attr("alt"),
+                        title: attr("title"),
+                        width: attr("width").and_then(|width| width.parse().ok()),
+                        height: attr("height").and_then(|height| height.parse().ok()),
+                    }));
+                }
+                return;
+            }
+
+            // Blocks.
+            "p" | "div" | "table" | "tr" | "caption" | "details" | "summary" => {
+                self.start_block(BlockKind::Paragraph, None);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                self.start_block(BlockKind::Heading(level), None);
+                format.bold = true;
+            }
+            "pre" => {
+                self.start_block(BlockKind::CodeBlock { language: None }, None);
+            }
+            "blockquote" => {
+                self.quote_depth += 1;
+                self.end_block();
+                is_quote = true;
+            }
+            "ul" | "ol" => {
+                let next_number = (name == "ol")
+                    .then(|| attr("start").and_then(|start| start.parse().ok()).unwrap_or(1));
+                self.lists.push(OpenList { next_number });
+                self.end_block();
+                is_list = true;
+            }
+            "li" => {
+                let depth = self.lists.len();
+                let marker = match self.lists.last_mut() {
+                    Some(OpenList { next_number: Some(number) }) => {
+                        let marker = ListMarker::Number(*number);
+                        *number += 1;
+                        marker
+                    }
+                    _ => ListMarker::Bullet,
+                };
+                self.start_block(BlockKind::Paragraph, Some(ListItem { depth, marker }));
+            }
+
+            // Inline elements.
+            "b" | "strong" => format.bold = true,
+            "i" | "em" => format.italic = true,
+            "u" => format.underline = true,
+            "del" | "s" | "strike" => format.strikethrough = true,
+            "sup" => format.superscript = true,
+            "sub" => format.subscript = true,
+            "code" => {
+                if let BlockKind::CodeBlock { language } = &mut self.current.kind {
+                    *language = attr("class")
+                        .and_then(|class| class.strip_prefix("language-").map(ToOwned::to_owned));
+                } else {
+                    format.code = true;
+                }
+            }
+            "a" => {
+                if let Some(href) = attr("href") {
+                    format.link = Some(href);
+                }
+            }
+            "font" | "span" => {
+                if let Some(color) = attr("data-mx-color").or_else(|| attr("color")) {
+                    format.color = Some(color);
+                }
+                if let Some(color) = attr("data-mx-bg-color") {
+                    format.background_color = Some(color);
+                }
+                if attr("data-mx-spoiler").is_some() {
+                    format.spoiler = true;
+                }
+            }
+            _ => {}
+        }
+
+        if !tag.self_closing {
+            self.elements.push(OpenElement { name: name.to_owned(), format, is_quote, is_list });
+        }
+    }
+
+    fn end_tag(&mut self, tag: Tag) {
+        let name = &*tag.name;
+        let Some(index) = self.elements.iter().rposition(|element| element.name == name) else {
+            return;
+        };
+
+        for element in self.elements.drain(index..).rev().collect::<Vec<_>>() {
+            match element.name.as_str() {
+                "p" | "div" | "table" | "tr" | "caption" | "details" | "summary" | "h1" | "h2"
+                | "h3" | "h4" | "h5" | "h6" | "pre" | "li" => {
+                    self.end_block();
+                }
+                _ => {}
+            }
+
+            if element.is_quote {
+                self.end_block();
+                self.quote_depth -= 1;
+                self.current.quote_depth = self.quote_depth;
+            }
+            if element.is_list {
+                self.end_block();
+                self.lists.pop();
+            }
+        }
+    }
+
+    fn finish(mut self) -> RichText {
+        self.end_block();
+        RichText { blocks: self.blocks }
+    }
+}
+
+impl TokenSink for RichTextBuilder {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => match tag.kind {
+                TagKind::StartTag => self.start_tag(tag),
+                TagKind::EndTag => self.end_tag(tag),
+            },
+            Token::CharacterTokens(text) => self.push_text(&text),
+            _ => {}
+        }
+
+        TokenSinkResult::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{mxc_uri, uint};
+
+    use super::{Block, BlockKind, Image, ListItem, ListMarker, RichText, Span, TextFormat};
+
+    fn text(text: &str) -> Span {
+        Span::Text { text: text.to_owned(), format: TextFormat::default() }
+    }
+
+    fn paragraph(spans: Vec<Span>) -> Block {
+        Block { spans, ..Default::default() }
+    }
+
+    #[test]
+    fn inline_formatting() {
+        let rich_text = RichText::from_html(
+            "Hello <b>bold <i>and italic</i></b> <a href=\"https://matrix.org\">link</a>",
+        );
+
+        assert_eq!(
+            rich_text.blocks,
+            [paragraph(vec![
+                text("Hello "),
+                Span::Text {
+                    text: "bold ".to_owned(),
+                    format: TextFormat { bold: true, ..Default::default() }
+                },
+                Span::Text {
+                    text: "and italic".to_owned(),
+                    format: TextFormat { bold: true, italic: true, ..Default::default() }
+                },
+                text(" "),
+                Span::Text {
+                    text: "link".to_owned(),
+                    format: TextFormat {
+                        link: Some("https://matrix.org".to_owned()),
+                        ..Default::default()
+                    }
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn reply_fallback_and_unsafe_content_are_removed() {
+        let rich_text = RichText::from_html(
+            "<mx-reply><blockquote>In reply to</blockquote></mx-reply>\
+             <p><a href=\"javascript:alert(1)\">Hi</a> <span data-mx-spoiler>secret</span></p>",
+        );
+
+        assert_eq!(
+            rich_text.blocks,
+            [paragraph(vec![
+                text("Hi "),
+                Span::Text {
+                    text: "secret".to_owned(),
+                    format: TextFormat { spoiler: true, ..Default::default() }
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        let rich_text = RichText::from_html(
+            "<h2>Title</h2>\
+             <blockquote><p>Quoted</p></blockquote>\
+             <ol start=\"3\"><li>Three</li><li>Four<ul><li>Nested</li></ul></li></ol>\
+             <pre><code class=\"language-rust\">fn main() {\n    a();\n}</code></pre>\
+             <hr>\
+             <img src=\"mxc://localhost/image\" alt=\"An image\" width=\"32\" height=\"16\">",
+        );
+
+        assert_eq!(
+            rich_text.blocks,
+            [
+                Block {
+                    kind: BlockKind::Heading(2),
+                    spans: vec![Span::Text {
+                        text: "Title".to_owned(),
+                        format: TextFormat { bold: true, ..Default::default() }
+                    }],
+                    ..Default::default()
+                },
+                Block { quote_depth: 1, spans: vec![text("Quoted")], ..Default::default() },
+                Block {
+                    list_item: Some(ListItem { depth: 1, marker: ListMarker::Number(3) }),
+                    spans: vec![text("Three")],
+                    ..Default::default()
+                },
+                Block {
+                    list_item: Some(ListItem { depth: 1, marker: ListMarker::Number(4) }),
+                    spans: vec![text("Four")],
+                    ..Default::default()
+                },
+                Block {
+                    list_item: Some(ListItem { depth: 2, marker: ListMarker::Bullet }),
+                    spans: vec![text("Nested")],
+                    ..Default::default()
+                },
+                Block {
+                    kind: BlockKind::CodeBlock { language: Some("rust".to_owned()) },
+                    spans: vec![text("fn main() {\n    a();\n}")],
+                    ..Default::default()
+                },
+                Block { kind: BlockKind::HorizontalRule, ..Default::default() },
+                paragraph(vec![Span::Image(Image {
+                    source: mxc_uri!("mxc://localhost/image").to_owned(),
+                    alt: Some("An image".to_owned()),
+                    title: None,
+                    width: Some(uint!(32)),
+                    height: Some(uint!(16)),
+                })]),
+            ]
+        );
+    }
+}
@@ -15,8 +15,10 @@
 mod events;
 
 pub mod encryption_sync;
+pub mod html;
 pub mod notification_client;
 pub mod room_list_service;
+pub mod room_member_list;
 pub mod sync_service;
 pub mod timeline;
 
@@ -18,12 +18,12 @@ use std::{
 };
 
 use futures_util::{future::ready, pin_mut, StreamExt as _};
-use matrix_sdk::{room::Room, Client, ClientBuildError, SlidingSyncList, SlidingSyncMode};
+use matrix_sdk::{
+    room::Room, Client, ClientBuildError, SlidingSyncExtension, SlidingSyncList, SlidingSyncMode,
+};
 use matrix_sdk_base::{deserialized_responses::TimelineEvent, RoomState, StoreError};
 use ruma::{
-    api::client::sync::sync_events::v4::{
-        AccountDataConfig, RoomSubscription, SyncRequestListFilters,
-    },
+    api::client::sync::sync_events::v4::{RoomSubscription, SyncRequestListFilters},
     assign,
     events::{
         room::member::StrippedRoomMemberEvent, AnyFullStateEventContent, AnyStateEvent,
@@ -269,9 +269,7 @@ impl NotificationClient {
             .sliding_sync(Self::CONNECTION_ID)?
             .poll_timeout(Duration::from_secs(1))
             .network_timeout(Duration::from_secs(3))
-            .with_account_data_extension(
-                assign!(AccountDataConfig::default(), { enabled: Some(true) }),
-            )
+            .with_extension(SlidingSyncExtension::AccountData)
             .add_list(invites)
             .build()
             .await?;
@@ -0,0 +1,16 @@
+use matrix_sdk::{Client, RoomListEntry};
+
+/// Create a new filter that will accept all direct rooms, i.e. rooms with at
+/// least one DM target.
+///
+/// Rooms are fetched from the `Client`.
+pub fn new_filter(client: &Client) -> impl Fn(&RoomListEntry) -> bool {
+    let client = client.clone();
+
+    move |room_list_entry| -> bool {
+        let Some(room_id) = room_list_entry.as_room_id() else { return false };
+        let Some(room) = client.get_room(room_id) else { return false };
+
+        !room.direct_targets().is_empty()
+    }
+}
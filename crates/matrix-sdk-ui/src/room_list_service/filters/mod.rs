@@ -1,16 +1,24 @@
 mod all;
+mod dm;
+mod favourite;
 mod fuzzy_match_room_name;
 mod normalized_match_room_name;
+mod spaces;
+mod unread;
 
 pub use all::new_filter as new_filter_all;
+pub use dm::new_filter as new_filter_dm;
+pub use favourite::new_filter as new_filter_favourite;
 pub use fuzzy_match_room_name::new_filter as new_filter_fuzzy_match_room_name;
 pub use normalized_match_room_name::new_filter as new_filter_normalized_match_room_name;
+pub use spaces::new_filter as new_filter_spaces;
 use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+pub use unread::new_filter as new_filter_unread;
 
 /// Normalize a string, i.e. decompose it into NFD (Normalization Form D, i.e. a
 /// canonical decomposition, see http://www.unicode.org/reports/tr15/) and
 /// filter out the combining marks.
-fn normalize_string(str: &str) -> String {
+pub(crate) fn normalize_string(str: &str) -> String {
     str.nfd().filter(|c| !is_combining_mark(*c)).collect::<String>()
 }
 
@@ -0,0 +1,15 @@
+use matrix_sdk::{Client, RoomListEntry};
+
+/// Create a new filter that will accept all rooms with unread notifications.
+///
+/// Rooms are fetched from the `Client`.
+pub fn new_filter(client: &Client) -> impl Fn(&RoomListEntry) -> bool {
+    let client = client.clone();
+
+    move |room_list_entry| -> bool {
+        let Some(room_id) = room_list_entry.as_room_id() else { return false };
+        let Some(room) = client.get_room(room_id) else { return false };
+
+        room.unread_notification_counts().notification_count > 0
+    }
+}
@@ -74,16 +74,14 @@ use eyeball::{SharedObservable, Subscriber};
 use futures_util::{pin_mut, Stream, StreamExt};
 pub use matrix_sdk::RoomListEntry;
 use matrix_sdk::{
-    sliding_sync::Ranges, Client, Error as SlidingSyncError, SlidingSync, SlidingSyncList,
-    SlidingSyncListBuilder, SlidingSyncMode,
+    sliding_sync::Ranges, Client, Error as SlidingSyncError, SlidingSync, SlidingSyncExtension,
+    SlidingSyncList, SlidingSyncListBuilder, SlidingSyncMode,
 };
 use matrix_sdk_base::ring_buffer::RingBuffer;
 pub use room::*;
 pub use room_list::*;
 use ruma::{
-    api::client::sync::sync_events::v4::{
-        AccountDataConfig, E2EEConfig, SyncRequestListFilters, ToDeviceConfig,
-    },
+    api::client::sync::sync_events::v4::SyncRequestListFilters,
     assign,
     events::{StateEventType, TimelineEventType},
     OwnedRoomId, RoomId,
@@ -147,16 +145,12 @@ impl RoomListService {
         let mut builder = client
             .sliding_sync("room-list")
             .map_err(Error::SlidingSync)?
-            .with_account_data_extension(
-                assign!(AccountDataConfig::default(), { enabled: Some(true) }),
-            );
+            .with_extension(SlidingSyncExtension::AccountData);
 
         if with_encryption {
             builder = builder
-                .with_e2ee_extension(assign!(E2EEConfig::default(), { enabled: Some(true) }))
-                .with_to_device_extension(
-                    assign!(ToDeviceConfig::default(), { enabled: Some(true) }),
-                );
+                .with_extension(SlidingSyncExtension::E2ee)
+                .with_extension(SlidingSyncExtension::ToDevice);
         }
 
         let sliding_sync = builder
@@ -168,6 +162,8 @@ impl RoomListService {
                         (StateEventType::RoomAvatar, "".to_owned()),
                         (StateEventType::RoomEncryption, "".to_owned()),
                         (StateEventType::RoomPowerLevels, "".to_owned()),
+                        // Needed to know which rooms are spaces.
+                        (StateEventType::RoomCreate, "".to_owned()),
                     ]),
             ))
             .await
@@ -371,6 +367,36 @@ impl RoomListService {
         Ok(room)
     }
 
+    /// Change how the rooms are sorted.
+    ///
+    /// The new sort order applies to [`ALL_ROOMS_LIST_NAME`] and
+    /// [`VISIBLE_ROOMS_LIST_NAME`], and is sent to the server with the next
+    /// sync request.
+    pub async fn set_sort(&self, sort: RoomListSort) -> Result<(), Error> {
+        let sort = sort.sliding_sync_sort();
+
+        self.sliding_sync
+            .on_list(ALL_ROOMS_LIST_NAME, |list| {
+                list.set_sort(sort.clone());
+
+                ready(())
+            })
+            .await
+            .ok_or_else(|| Error::UnknownList(ALL_ROOMS_LIST_NAME.to_owned()))?;
+
+        // The `VISIBLE_ROOMS_LIST_NAME` list may not exist yet. In that case, it will
+        // copy the sort order of `ALL_ROOMS_LIST_NAME` when it's created.
+        self.sliding_sync
+            .on_list(VISIBLE_ROOMS_LIST_NAME, |list| {
+                list.set_sort(sort);
+
+                ready(())
+            })
+            .await;
+
+        Ok(())
+    }
+
     #[cfg(test)]
     pub fn sliding_sync(&self) -> &SlidingSync {
         &self.sliding_sync
@@ -382,15 +408,17 @@ impl RoomListService {
 ///
 /// This function configures the `sort`, the `filters` and the`bump_event_types`
 /// properties, so that they are exactly the same.
+///
+/// Spaces are not filtered out by the server, so that they can be filtered
+/// with [`filters::new_filter_spaces`].
 fn configure_all_or_visible_rooms_list(
     list_builder: SlidingSyncListBuilder,
 ) -> SlidingSyncListBuilder {
     list_builder
-        .sort(vec!["by_recency".to_owned(), "by_name".to_owned()])
+        .sort(RoomListSort::default().sliding_sync_sort())
         .filters(Some(assign!(SyncRequestListFilters::default(), {
             is_invite: Some(false),
             is_tombstoned: Some(false),
-            not_room_types: vec!["m.space".to_owned()],
         })))
         .bump_event_types(&[
             TimelineEventType::RoomMessage,
@@ -399,6 +427,37 @@ fn configure_all_or_visible_rooms_list(
         ])
 }
 
+/// How the rooms of [`ALL_ROOMS_LIST_NAME`] and [`VISIBLE_ROOMS_LIST_NAME`]
+/// are sorted.
+///
+/// Sorting is done by the server.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RoomListSort {
+    /// Most recently active rooms first, then by name.
+    #[default]
+    Recency,
+
+    /// By name only.
+    Name,
+
+    /// Rooms with unread notifications first, then the most recently active
+    /// rooms, then by name.
+    UnreadFirst,
+}
+
+impl RoomListSort {
+    /// The `sort` parameter of the Sliding Sync list.
+    fn sliding_sync_sort(self) -> Vec<String> {
+        let sort: &[&str] = match self {
+            Self::Recency => &["by_recency", "by_name"],
+            Self::Name => &["by_name"],
+            Self::UnreadFirst => &["by_notification_level", "by_recency", "by_name"],
+        };
+
+        sort.iter().map(|&s| s.to_owned()).collect()
+    }
+}
+
 /// [`RoomList`]'s errors.
 #[derive(Debug, Error)]
 pub enum Error {
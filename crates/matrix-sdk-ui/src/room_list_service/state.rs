@@ -110,20 +110,25 @@ pub const VISIBLE_ROOMS_DEFAULT_RANGE: Range = 0..=19;
 #[async_trait]
 impl Action for AddVisibleRoomsList {
     async fn run(&self, sliding_sync: &SlidingSync) -> Result<(), Error> {
-        sliding_sync
-            .add_list(super::configure_all_or_visible_rooms_list(
-                SlidingSyncList::builder(VISIBLE_ROOMS_LIST_NAME)
-                    .sync_mode(
-                        SlidingSyncMode::new_selective().add_range(VISIBLE_ROOMS_DEFAULT_RANGE),
-                    )
-                    .timeline_limit(20)
-                    .required_state(vec![
-                        (StateEventType::RoomEncryption, "".to_owned()),
-                        (StateEventType::RoomMember, "$LAZY".to_owned()),
-                    ]),
-            ))
-            .await
-            .map_err(Error::SlidingSync)?;
+        let mut list_builder = super::configure_all_or_visible_rooms_list(
+            SlidingSyncList::builder(VISIBLE_ROOMS_LIST_NAME)
+                .sync_mode(SlidingSyncMode::new_selective().add_range(VISIBLE_ROOMS_DEFAULT_RANGE))
+                .timeline_limit(20)
+                .required_state(vec![
+                    (StateEventType::RoomEncryption, "".to_owned()),
+                    (StateEventType::RoomMember, "$LAZY".to_owned()),
+                ]),
+        );
+
+        // Keep the sort order of `ALL_ROOMS_LIST_NAME`, it may have been changed by
+        // `RoomListService::set_sort`.
+        if let Some(sort) =
+            sliding_sync.on_list(ALL_ROOMS_LIST_NAME, |list| ready(list.sort())).await
+        {
+            list_builder = list_builder.sort(sort);
+        }
+
+        sliding_sync.add_list(list_builder).await.map_err(Error::SlidingSync)?;
 
         Ok(())
     }
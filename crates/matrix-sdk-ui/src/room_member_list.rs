@@ -0,0 +1,553 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An observable, sorted and searchable list of the members of a room.
+//!
+//! Nothing is loaded before [`RoomMemberList::load()`] is called. The members
+//! that are already in the store are shown first, and the full list is only
+//! requested from the homeserver with `/members` if it is missing. Only a
+//! lightweight summary of each member is kept in memory. The observable list
+//! only contains the first members matching the current search, more can be
+//! added with [`RoomMemberList::paginate()`], so large rooms can be rendered
+//! progressively.
+//!
+//! Once loaded, the list is updated with the member events received via sync,
+//! by reloading only the members they concern.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use eyeball_im::{ObservableVector, Vector, VectorSubscriber};
+use matrix_sdk::{
+    executor::{spawn, JoinHandle},
+    room::RoomMember,
+    sync::RoomUpdate,
+    Result, Room, RoomMemberships,
+};
+use ruma::{
+    events::{room::member::MembershipState, StateEventType},
+    serde::Raw,
+    OwnedMxcUri, OwnedUserId, UserId,
+};
+use tokio::sync::broadcast;
+use tracing::{debug, error, warn};
+
+use crate::room_list_service::filters::normalize_string;
+
+/// The number of members added to the observable list by default, by
+/// [`RoomMemberList::load()`] and [`RoomMemberList::paginate()`].
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// An observable, sorted and searchable list of the members of a room.
+///
+/// Members are grouped by [`RoomMemberRole`], and sorted by name inside each
+/// group. The list is kept up to date with the membership and power level
+/// changes received via sync, once it has been loaded.
+#[derive(Debug)]
+pub struct RoomMemberList {
+    room: Room,
+    state: Arc<StdMutex<RoomMemberListState>>,
+    room_update_join_handle: JoinHandle<()>,
+}
+
+impl RoomMemberList {
+    /// Create a new `RoomMemberList` for the joined and invited members of the
+    /// given room.
+    ///
+    /// Nothing is loaded until [`RoomMemberList::load()`] is called.
+    pub fn new(room: Room) -> Self {
+        Self::with_memberships(room, RoomMemberships::JOIN | RoomMemberships::INVITE)
+    }
+
+    /// Create a new `RoomMemberList` for the members of the given room with
+    /// the given memberships.
+    ///
+    /// Nothing is loaded until [`RoomMemberList::load()`] is called.
+    pub fn with_memberships(room: Room, memberships: RoomMemberships) -> Self {
+        let state = Arc::new(StdMutex::new(RoomMemberListState::new(memberships)));
+
+        let room_update_join_handle = spawn({
+            let room = room.clone();
+            let state = state.clone();
+            let mut room_update_rx = room.subscribe_to_updates();
+
+            async move {
+                loop {
+                    let mut changes = MemberListChanges::default();
+                    match room_update_rx.recv().await {
+                        Ok(RoomUpdate::Joined { updates, .. }) => {
+                            for event in &updates.state {
+                                changes.add_event(event);
+                            }
+                            for event in &updates.timeline.events {
+                                changes.add_event(&event.event);
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Closed) => break,
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            warn!("Lagged behind sync responses, reloading room members");
+                            changes.reload_all = true;
+                        }
+                    }
+
+                    if !state.lock().unwrap().loaded {
+                        continue;
+                    }
+
+                    if let Err(error) = apply_changes(&room, &state, changes).await {
+                        error!("Failed to update room members: {error}");
+                    }
+                }
+            }
+        });
+
+        Self { room, state, room_update_join_handle }
+    }
+
+    /// Load the members of the room.
+    ///
+    /// The members that are already in the store are added to the list
+    /// first. Then, if the full member list wasn't synced yet, it is
+    /// requested from the homeserver and the list is updated. The first
+    /// members matching the current search are added to the observable list.
+    pub async fn load(&self) -> Result<()> {
+        let memberships = self.state.lock().unwrap().memberships;
+
+        let entries = load_entries(&self.room, memberships).await?;
+        {
+            let mut state = self.state.lock().unwrap();
+            state.loaded = true;
+            state.set_members(entries);
+        }
+
+        if self.room.sync_members().await?.is_some() {
+            let entries = load_entries(&self.room, memberships).await?;
+            self.state.lock().unwrap().set_members(entries);
+        }
+
+        Ok(())
+    }
+
+    /// Get the members in the observable list, and a stream of updates to
+    /// them.
+    pub fn subscribe(
+        &self,
+    ) -> (Vector<RoomMemberListEntry>, VectorSubscriber<RoomMemberListEntry>) {
+        let state = self.state.lock().unwrap();
+        (Vector::clone(&state.entries), ObservableVector::subscribe(&state.entries))
+    }
+
+    /// The total number of members matching the current search, including
+    /// the ones that are not in the observable list yet.
+    pub fn matching_members_count(&self) -> usize {
+        self.state.lock().unwrap().matches.len()
+    }
+
+    /// Add the next `count` members matching the current search to the
+    /// observable list.
+    ///
+    /// Returns whether the end of the list was reached.
+    pub fn paginate(&self, count: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.limit = state.limit.saturating_add(count);
+        state.update_entries();
+        state.entries.len() == state.matches.len()
+    }
+
+    /// Only show the members whose name or user ID contains the given
+    /// pattern, or all members if `pattern` is `None`.
+    ///
+    /// The comparison ignores case and diacritics. When the new pattern
+    /// extends the previous one, only the members that matched the previous
+    /// pattern are searched again.
+    pub fn set_search(&self, pattern: Option<&str>) {
+        let pattern = pattern
+            .map(|pattern| normalize_string(&pattern.trim().to_lowercase()))
+            .filter(|pattern| !pattern.is_empty());
+
+        let mut state = self.state.lock().unwrap();
+        if state.search == pattern {
+            return;
+        }
+
+        let matches = match (&state.search, &pattern) {
+            (Some(previous), Some(pattern)) if pattern.contains(previous.as_str()) => state
+                .matches
+                .iter()
+                .copied()
+                .filter(|&idx| state.members[idx].matches(pattern))
+                .collect(),
+            _ => state.find_matches(pattern.as_deref()),
+        };
+
+        state.search = pattern;
+        state.matches = matches;
+        state.limit = DEFAULT_PAGE_SIZE;
+        state.update_entries();
+    }
+}
+
+impl Drop for RoomMemberList {
+    fn drop(&mut self) {
+        self.room_update_join_handle.abort();
+    }
+}
+
+#[derive(Debug)]
+struct RoomMemberListState {
+    memberships: RoomMemberships,
+    loaded: bool,
+    /// All the members, sorted.
+    members: Vec<RoomMemberListEntry>,
+    /// The normalized search pattern.
+    search: Option<String>,
+    /// The indices in `members` of the members matching `search`.
+    matches: Vec<usize>,
+    /// The maximum number of members in `entries`.
+    limit: usize,
+    /// The observable list.
+    entries: ObservableVector<RoomMemberListEntry>,
+}
+
+impl RoomMemberListState {
+    fn new(memberships: RoomMemberships) -> Self {
+        Self {
+            memberships,
+            loaded: false,
+            members: Vec::new(),
+            search: None,
+            matches: Vec::new(),
+            limit: DEFAULT_PAGE_SIZE,
+            entries: ObservableVector::new(),
+        }
+    }
+
+    fn set_members(&mut self, mut members: Vec<RoomMemberListEntry>) {
+        members.sort_by(RoomMemberListEntry::compare);
+        self.members = members;
+        self.matches = self.find_matches(self.search.as_deref());
+        self.update_entries();
+    }
+
+    /// Replace the members with the given user IDs, keeping the list sorted.
+    ///
+    /// A `None` entry removes the member from the list.
+    fn update_members(&mut self, updates: Vec<(OwnedUserId, Option<RoomMemberListEntry>)>) {
+        for (user_id, entry) in updates {
+            if let Some(idx) = self.members.iter().position(|m| m.user_id == user_id) {
+                self.members.remove(idx);
+            }
+
+            if let Some(entry) = entry {
+                let idx =
+                    self.members.binary_search_by(|m| m.compare(&entry)).unwrap_or_else(|idx| idx);
+                self.members.insert(idx, entry);
+            }
+        }
+
+        self.matches = self.find_matches(self.search.as_deref());
+        self.update_entries();
+    }
+
+    /// The user IDs of the members using one of the given display names.
+    fn members_named(&self, names: &BTreeSet<String>) -> BTreeSet<OwnedUserId> {
+        self.members
+            .iter()
+            .filter(|m| m.display_name.as_ref().is_some_and(|name| names.contains(name)))
+            .map(|m| m.user_id.clone())
+            .collect()
+    }
+
+    fn find_matches(&self, pattern: Option<&str>) -> Vec<usize> {
+        match pattern {
+            Some(pattern) => {
+                (0..self.members.len()).filter(|&idx| self.members[idx].matches(pattern)).collect()
+            }
+            None => (0..self.members.len()).collect(),
+        }
+    }
+
+    /// Update the observable list with the first matches.
+    ///
+    /// Only the entries that changed are updated, so observers get small
+    /// diffs when a few members change or when paginating.
+    fn update_entries(&mut self) {
+        let new_entries: Vector<_> =
+            self.matches.iter().take(self.limit).map(|&idx| self.members[idx].clone()).collect();
+
+        let old_len = self.entries.len();
+        let new_len = new_entries.len();
+        let prefix =
+            self.entries.iter().zip(new_entries.iter()).take_while(|(a, b)| a == b).count();
+        let suffix = self
+            .entries
+            .iter()
+            .rev()
+            .zip(new_entries.iter().rev())
+            .take(old_len.min(new_len) - prefix)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let removed = old_len - prefix - suffix;
+        let inserted = new_len - prefix - suffix;
+
+        if removed == 0 && suffix == 0 {
+            // Only new members at the end, e.g. when paginating.
+            if inserted > 0 {
+                self.entries.append(new_entries.skip(prefix));
+            }
+        } else if removed + inserted > old_len / 2 {
+            self.entries.clear();
+            self.entries.append(new_entries);
+        } else {
+            for _ in 0..removed {
+                self.entries.remove(prefix);
+            }
+            for (idx, entry) in new_entries.into_iter().enumerate().skip(prefix).take(inserted) {
+                self.entries.insert(idx, entry);
+            }
+        }
+    }
+}
+
+/// The role of a member in a room, according to their power level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RoomMemberRole {
+    /// The member has a power level of at least 100.
+    Administrator,
+    /// The member has a power level of at least 50.
+    Moderator,
+    /// The member has a power level lower than 50.
+    User,
+}
+
+impl RoomMemberRole {
+    fn from_power_level(power_level: i64) -> Self {
+        match power_level {
+            100.. => Self::Administrator,
+            50.. => Self::Moderator,
+            _ => Self::User,
+        }
+    }
+}
+
+/// A member in a [`RoomMemberList`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomMemberListEntry {
+    user_id: OwnedUserId,
+    display_name: Option<String>,
+    name_ambiguous: bool,
+    avatar_url: Option<OwnedMxcUri>,
+    power_level: i64,
+    membership: MembershipState,
+    /// The normalized name, used for sorting and searching.
+    normalized_name: String,
+}
+
+impl RoomMemberListEntry {
+    fn new(member: &RoomMember) -> Self {
+        Self {
+            user_id: member.user_id().to_owned(),
+            display_name: member.display_name().map(ToOwned::to_owned),
+            name_ambiguous: member.name_ambiguous(),
+            avatar_url: member.avatar_url().map(ToOwned::to_owned),
+            power_level: member.power_level(),
+            membership: member.membership().clone(),
+            normalized_name: normalize_string(&member.name().to_lowercase()),
+        }
+    }
+
+    /// The user ID of the member.
+    pub fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    /// The display name of the member, if they set one.
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    /// The name to show for the member.
+    ///
+    /// This is the display name of the member, followed by their user ID if
+    /// another member uses the same display name, or the local part of their
+    /// user ID if they didn't set a display name.
+    pub fn disambiguated_name(&self) -> String {
+        match &self.display_name {
+            Some(display_name) if self.name_ambiguous => {
+                format!("{display_name} ({})", self.user_id)
+            }
+            Some(display_name) => display_name.clone(),
+            None => self.user_id.localpart().to_owned(),
+        }
+    }
+
+    /// The avatar URL of the member, if they set one.
+    pub fn avatar_url(&self) -> Option<&OwnedMxcUri> {
+        self.avatar_url.as_ref()
+    }
+
+    /// The power level of the member.
+    pub fn power_level(&self) -> i64 {
+        self.power_level
+    }
+
+    /// The role of the member, according to their power level.
+    pub fn role(&self) -> RoomMemberRole {
+        RoomMemberRole::from_power_level(self.power_level)
+    }
+
+    /// The membership of the member.
+    pub fn membership(&self) -> &MembershipState {
+        &self.membership
+    }
+
+    fn matches(&self, pattern: &str) -> bool {
+        self.normalized_name.contains(pattern)
+            || self.user_id.as_str().to_lowercase().contains(pattern)
+    }
+
+    fn compare(&self, other: &Self) -> Ordering {
+        self.role()
+            .cmp(&other.role())
+            .then_with(|| other.power_level.cmp(&self.power_level))
+            .then_with(|| self.normalized_name.cmp(&other.normalized_name))
+            .then_with(|| self.user_id.cmp(&other.user_id))
+    }
+}
+
+async fn load_entries(
+    room: &Room,
+    memberships: RoomMemberships,
+) -> Result<Vec<RoomMemberListEntry>> {
+    let members = room.members_no_sync(memberships).await?;
+    Ok(members.iter().map(RoomMemberListEntry::new).collect())
+}
+
+/// The changes to the member list received via sync.
+#[derive(Debug, Default)]
+struct MemberListChanges {
+    /// The members whose member event changed.
+    user_ids: BTreeSet<OwnedUserId>,
+    /// Whether all the members must be reloaded, e.g. because the power levels
+    /// changed.
+    reload_all: bool,
+}
+
+impl MemberListChanges {
+    fn add_event<T>(&mut self, event: &Raw<T>) {
+        match event.get_field::<StateEventType>("type") {
+            Ok(Some(StateEventType::RoomMember)) => {
+                match event.get_field::<OwnedUserId>("state_key") {
+                    Ok(Some(user_id)) => {
+                        self.user_ids.insert(user_id);
+                    }
+                    _ => {
+                        warn!("Member event without a valid state key, reloading room members");
+                        self.reload_all = true;
+                    }
+                }
+            }
+            Ok(Some(StateEventType::RoomPowerLevels)) => self.reload_all = true,
+            _ => {}
+        }
+    }
+}
+
+/// Apply the given changes to the member list, by reloading the members they
+/// concern from the store.
+async fn apply_changes(
+    room: &Room,
+    state: &StdMutex<RoomMemberListState>,
+    changes: MemberListChanges,
+) -> Result<()> {
+    let memberships = state.lock().unwrap().memberships;
+
+    if changes.reload_all {
+        debug!("Reloading all room members");
+        let entries = load_entries(room, memberships).await?;
+        state.lock().unwrap().set_members(entries);
+        return Ok(());
+    }
+
+    if changes.user_ids.is_empty() {
+        return Ok(());
+    }
+
+    debug!(count = changes.user_ids.len(), "Updating changed room members");
+
+    // A display name change can make other members' names ambiguous, or not
+    // anymore, so the members sharing the old or new names are reloaded too.
+    let old_names: BTreeSet<_> = {
+        let state = state.lock().unwrap();
+        state
+            .members
+            .iter()
+            .filter(|m| changes.user_ids.contains(&m.user_id))
+            .filter_map(|m| m.display_name.clone())
+            .collect()
+    };
+
+    let mut updates = Vec::new();
+    let mut new_names = BTreeSet::new();
+    for user_id in &changes.user_ids {
+        let entry = load_entry(room, user_id, memberships).await?;
+        if let Some(name) = entry.as_ref().and_then(|e| e.display_name.clone()) {
+            new_names.insert(name);
+        }
+        updates.push((user_id.clone(), entry));
+    }
+
+    let names = &old_names | &new_names;
+    let same_name_user_ids = state.lock().unwrap().members_named(&names);
+    for user_id in same_name_user_ids.difference(&changes.user_ids) {
+        updates.push((user_id.clone(), load_entry(room, user_id, memberships).await?));
+    }
+
+    state.lock().unwrap().update_members(updates);
+
+    Ok(())
+}
+
+/// Load the entry of the member with the given user ID, if they have one of the
+/// given memberships.
+async fn load_entry(
+    room: &Room,
+    user_id: &UserId,
+    memberships: RoomMemberships,
+) -> Result<Option<RoomMemberListEntry>> {
+    let member = room.get_member_no_sync(user_id).await?;
+    Ok(member
+        .filter(|member| memberships.matches(member.membership()))
+        .map(|member| RoomMemberListEntry::new(&member)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoomMemberRole;
+
+    #[test]
+    fn test_role_from_power_level() {
+        assert_eq!(RoomMemberRole::from_power_level(100), RoomMemberRole::Administrator);
+        assert_eq!(RoomMemberRole::from_power_level(9000), RoomMemberRole::Administrator);
+        assert_eq!(RoomMemberRole::from_power_level(99), RoomMemberRole::Moderator);
+        assert_eq!(RoomMemberRole::from_power_level(50), RoomMemberRole::Moderator);
+        assert_eq!(RoomMemberRole::from_power_level(0), RoomMemberRole::User);
+        assert_eq!(RoomMemberRole::from_power_level(-10), RoomMemberRole::User);
+    }
+}
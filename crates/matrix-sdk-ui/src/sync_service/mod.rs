@@ -21,9 +21,14 @@
 //! The sync service will signal errors via its
 //! [`state`](SyncService::state) that the user
 //! MUST observe. Whenever an error/termination is observed, the user MUST call
-//! [`SyncService::start()`] again to restart the room list sync.
+//! [`SyncService::start()`] again to restart the room list sync, unless
+//! [automatic recovery](SyncServiceBuilder::with_automatic_recovery) is
+//! enabled.
 
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use eyeball::{SharedObservable, Subscriber};
 use futures_core::Future;
@@ -31,11 +36,13 @@ use futures_util::{pin_mut, StreamExt as _};
 use matrix_sdk::Client;
 use thiserror::Error;
 use tokio::{
+    select,
     sync::{
         mpsc::{Receiver, Sender},
         Mutex as AsyncMutex,
     },
     task::{spawn, JoinHandle},
+    time::sleep,
 };
 use tracing::{error, info, instrument, trace, warn, Instrument, Level};
 
@@ -44,6 +51,16 @@ use crate::{
     room_list_service::{self, RoomListService},
 };
 
+/// The delay before the first automatic restart of the syncs after a failure.
+const INITIAL_RECOVERY_DELAY: Duration = Duration::from_secs(1);
+
+/// The maximum delay between two automatic restarts of the syncs.
+const MAX_RECOVERY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long the syncs must run before failing for the automatic recovery to
+/// start a new series of attempts.
+const RECOVERY_RESET_DELAY: Duration = Duration::from_secs(60);
+
 /// Current state of the application.
 ///
 /// This is a high-level state indicating what's the status of the underlying
@@ -61,6 +78,9 @@ pub enum State {
     Idle,
     /// The underlying syncs are properly running in the background.
     Running,
+    /// The service has been paused with [`SyncService::pause`], and can be
+    /// resumed with [`SyncService::resume`].
+    Paused,
     /// Any of the underlying syncs has terminated gracefully (i.e. be stopped).
     Terminated,
     /// Any of the underlying syncs has ran into an error.
@@ -97,6 +117,10 @@ pub struct SyncService {
     /// This is set at the same time as the other two tasks.
     scheduler_task: Arc<Mutex<Option<JoinHandle<()>>>>,
 
+    /// How many times in a row the syncs are restarted after a failure, before
+    /// giving up with [`State::Error`].
+    max_recovery_attempts: u32,
+
     /// `TerminationReport` sender for the [`Self::stop()`] function.
     ///
     /// This is set at the same time as all the tasks in [`Self::start()`].
@@ -124,88 +148,151 @@ impl SyncService {
     /// (`TerminationReport`), sent either because we wanted to stop both
     /// syncs, or because one of the syncs failed (in which case we'll stop
     /// the other one too).
+    ///
+    /// If automatic recovery is enabled, the syncs are restarted after a
+    /// failure, and the scheduler waits for the next termination message.
     fn spawn_scheduler_task(
         &self,
         mut receiver: Receiver<TerminationReport>,
+        sender: Sender<TerminationReport>,
     ) -> impl Future<Output = ()> {
         let encryption_sync_task = self.encryption_sync_task.clone();
         let encryption_sync = self.encryption_sync.clone();
         let room_list_service = self.room_list_service.clone();
         let room_list_task = self.room_list_task.clone();
         let state = self.state.clone();
+        let max_recovery_attempts = self.max_recovery_attempts;
 
         async move {
-            let Some(report) = receiver.recv().await else {
-                info!("internal channel has been closed?");
-                return;
-            };
-
-            // If one service failed, make sure to request stopping the other one.
-            let (stop_room_list, stop_encryption) = match &report.origin {
-                TerminationOrigin::EncryptionSync => (true, false),
-                TerminationOrigin::RoomList => (false, true),
-                TerminationOrigin::Scheduler => (true, true),
-            };
-
-            // Stop both services, and wait for the streams to properly finish: at some
-            // point they'll return `None` and will exit their infinite loops,
-            // and their tasks will gracefully terminate.
-
-            if stop_room_list {
-                if let Err(err) = room_list_service.stop_sync() {
-                    error!("unable to stop room list service: {err:#}");
+            let mut recovery_attempts = 0;
+            let mut started_at = Instant::now();
+
+            loop {
+                let Some(report) = receiver.recv().await else {
+                    info!("internal channel has been closed?");
+                    return;
+                };
+
+                // If one service failed, make sure to request stopping the other one.
+                let (stop_room_list, stop_encryption) = match &report.origin {
+                    TerminationOrigin::EncryptionSync => (true, false),
+                    TerminationOrigin::RoomList => (false, true),
+                    TerminationOrigin::Scheduler | TerminationOrigin::Pause => (true, true),
+                };
+
+                // Stop both services, and wait for the streams to properly finish: at some
+                // point they'll return `None` and will exit their infinite loops,
+                // and their tasks will gracefully terminate.
+
+                if stop_room_list {
+                    if let Err(err) = room_list_service.stop_sync() {
+                        error!("unable to stop room list service: {err:#}");
+                    }
                 }
-            }
 
-            {
-                let task = room_list_task.lock().unwrap().take();
-                if let Some(task) = task {
-                    if let Err(err) = task.await {
-                        error!("when awaiting room list service: {err:#}");
+                {
+                    let task = room_list_task.lock().unwrap().take();
+                    if let Some(task) = task {
+                        if let Err(err) = task.await {
+                            error!("when awaiting room list service: {err:#}");
+                        }
                     }
                 }
-            }
 
-            if let Some(encryption_sync) = &encryption_sync {
-                if stop_encryption {
-                    if let Err(err) = encryption_sync.stop_sync() {
-                        warn!("unable to stop encryption sync: {err:#}");
+                if let Some(encryption_sync) = &encryption_sync {
+                    if stop_encryption {
+                        if let Err(err) = encryption_sync.stop_sync() {
+                            warn!("unable to stop encryption sync: {err:#}");
+                        }
                     }
-                }
 
-                let task = encryption_sync_task.lock().unwrap().take();
-                if let Some(task) = task {
-                    if let Err(err) = task.await {
-                        error!("when awaiting encryption sync: {err:#}");
+                    let task = encryption_sync_task.lock().unwrap().take();
+                    if let Some(task) = task {
+                        if let Err(err) = task.await {
+                            error!("when awaiting encryption sync: {err:#}");
+                        }
                     }
                 }
-            }
 
-            if report.is_error {
+                if !report.is_error {
+                    state.set(match report.origin {
+                        TerminationOrigin::Scheduler => State::Idle,
+                        TerminationOrigin::Pause => State::Paused,
+                        _ => State::Terminated,
+                    });
+                    return;
+                }
+
                 if report.has_expired {
                     if stop_room_list {
                         room_list_service.expire_sync_session().await;
                     }
                     if stop_encryption {
                         // Expire the encryption sync too.
-                        if let Some(encryption_sync) = encryption_sync {
+                        if let Some(encryption_sync) = &encryption_sync {
                             encryption_sync.expire_sync_session().await;
                         }
                     }
                 }
 
-                state.set(State::Error);
-            } else if matches!(report.origin, TerminationOrigin::Scheduler) {
-                state.set(State::Idle);
-            } else {
-                state.set(State::Terminated);
+                // Syncs that ran for a while before failing start a new series of
+                // attempts.
+                if started_at.elapsed() >= RECOVERY_RESET_DELAY {
+                    recovery_attempts = 0;
+                }
+
+                if recovery_attempts >= max_recovery_attempts {
+                    state.set(State::Error);
+                    return;
+                }
+
+                // The reports of the syncs stopped above are stale, but a request to
+                // stop the service must still be honored.
+                let mut stop_request = None;
+                while let Ok(report) = receiver.try_recv() {
+                    stop_request = stop_request.or(stopped_state(&report.origin));
+                }
+
+                if stop_request.is_none() {
+                    let delay = recovery_delay(recovery_attempts);
+                    recovery_attempts += 1;
+                    warn!(
+                        "restarting the syncs in {delay:?} \
+                         (attempt {recovery_attempts}/{max_recovery_attempts})"
+                    );
+
+                    stop_request = select! {
+                        () = sleep(delay) => None,
+                        // Only a request to stop the service can be received here.
+                        report = receiver.recv() => Some(
+                            report.and_then(|report| stopped_state(&report.origin))
+                                .unwrap_or(State::Idle),
+                        ),
+                    };
+                }
+
+                if let Some(stopped_state) = stop_request {
+                    state.set(stopped_state);
+                    return;
+                }
+
+                *room_list_task.lock().unwrap() = Some(spawn(Self::spawn_room_list_sync(
+                    room_list_service.clone(),
+                    sender.clone(),
+                )));
+
+                if let Some(encryption_sync) = encryption_sync.clone() {
+                    *encryption_sync_task.lock().unwrap() =
+                        Some(spawn(Self::spawn_encryption_sync(encryption_sync, sender.clone())));
+                }
+
+                started_at = Instant::now();
             }
         }
         .instrument(tracing::span!(Level::WARN, "scheduler task"))
     }
 
     fn spawn_encryption_sync(
-        &self,
         encryption_sync: Arc<EncryptionSync>,
         sender: Sender<TerminationReport>,
     ) -> impl Future<Output = ()> {
@@ -251,9 +338,10 @@ impl SyncService {
         }
     }
 
-    fn spawn_room_list_sync(&self, sender: Sender<TerminationReport>) -> impl Future<Output = ()> {
-        let room_list_service = self.room_list_service.clone();
-
+    fn spawn_room_list_sync(
+        room_list_service: Arc<RoomListService>,
+        sender: Sender<TerminationReport>,
+    ) -> impl Future<Output = ()> {
         async move {
             let room_list_stream = room_list_service.sync();
             pin_mut!(room_list_stream);
@@ -311,32 +399,57 @@ impl SyncService {
             return;
         }
 
+        self.start_tasks();
+    }
+
+    /// Resume the underlying sliding syncs after a call to [`Self::pause()`].
+    ///
+    /// This does nothing if the service isn't paused, for example if it was
+    /// stopped or restarted in the meantime.
+    pub async fn resume(&self) {
+        let _guard = self.modifying_state.lock().await;
+
+        if !matches!(self.state.get(), State::Paused) {
+            return;
+        }
+
+        self.start_tasks();
+    }
+
+    /// Spawn the tasks running the underlying sliding syncs.
+    ///
+    /// Must be called while holding the `modifying_state` lock.
+    fn start_tasks(&self) {
         trace!("starting sync service");
 
         let (sender, receiver) = tokio::sync::mpsc::channel(16);
 
         // First, take care of the room list.
         *self.room_list_task.lock().unwrap() =
-            Some(spawn(self.spawn_room_list_sync(sender.clone())));
+            Some(spawn(Self::spawn_room_list_sync(self.room_list_service.clone(), sender.clone())));
 
         // Then, take care of the encryption sync.
         if let Some(encryption_sync) = self.encryption_sync.clone() {
             *self.encryption_sync_task.lock().unwrap() =
-                Some(spawn(self.spawn_encryption_sync(encryption_sync, sender.clone())));
+                Some(spawn(Self::spawn_encryption_sync(encryption_sync, sender.clone())));
         }
 
         // Spawn the scheduler task.
-        *self.scheduler_sender.lock().unwrap() = Some(sender);
-        *self.scheduler_task.lock().unwrap() = Some(spawn(self.spawn_scheduler_task(receiver)));
+        *self.scheduler_sender.lock().unwrap() = Some(sender.clone());
+        *self.scheduler_task.lock().unwrap() =
+            Some(spawn(self.spawn_scheduler_task(receiver, sender)));
 
         self.state.set(State::Running);
     }
 
     /// Stop the underlying sliding syncs.
     ///
-    /// This must be called when the app goes into the background. It's better
-    /// to call this API when the application exits, although not strictly
-    /// necessary.
+    /// This must be called when the app is about to be suspended, unless
+    /// [`Self::pause()`] is used instead. It's better to call this API when
+    /// the application exits, although not strictly necessary.
+    ///
+    /// An app that keeps running in the background should rather report it
+    /// with [`Client::set_app_state()`], to sync less often without stopping.
     #[instrument(skip_all)]
     pub async fn stop(&self) -> Result<(), Error> {
         let _guard = self.modifying_state.lock().await;
@@ -346,11 +459,44 @@ impl SyncService {
                 // No need to stop if we were not running.
                 return Ok(());
             }
+            State::Paused => {
+                // The syncs are already stopped, only forget about resuming them.
+                self.state.set(State::Idle);
+                return Ok(());
+            }
             State::Running => {}
         };
 
+        trace!("stopping sync service");
+
+        self.terminate(TerminationOrigin::Scheduler).await
+    }
+
+    /// Pause the underlying sliding syncs, e.g. when the app is about to be
+    /// suspended.
+    ///
+    /// Like with [`Self::stop()`], the syncs are stopped, but the service ends
+    /// up in the [`State::Paused`] state, and can be restarted with
+    /// [`Self::resume()`].
+    #[instrument(skip_all)]
+    pub async fn pause(&self) -> Result<(), Error> {
+        let _guard = self.modifying_state.lock().await;
+
+        if !matches!(self.state.get(), State::Running) {
+            // No need to pause if we were not running.
+            return Ok(());
+        }
+
         trace!("pausing sync service");
 
+        self.terminate(TerminationOrigin::Pause).await
+    }
+
+    /// Request the scheduler task to stop the underlying syncs, and wait for
+    /// it to finish.
+    ///
+    /// Must be called while holding the `modifying_state` lock.
+    async fn terminate(&self, origin: TerminationOrigin) -> Result<(), Error> {
         // First, request to stop the two underlying syncs; we'll look at the results
         // later, so that we're in a clean state independently of the request to
         // stop.
@@ -361,11 +507,7 @@ impl SyncService {
                 error!("missing sender");
                 Error::InternalSchedulerError
             })?
-            .send(TerminationReport {
-                is_error: false,
-                has_expired: false,
-                origin: TerminationOrigin::Scheduler,
-            })
+            .send(TerminationReport { is_error: false, has_expired: false, origin })
             .await
             .map_err(|err| {
                 error!("when sending termination report: {err}");
@@ -388,10 +530,27 @@ impl SyncService {
     }
 }
 
+/// The state of the service after a request to stop it with the given
+/// origin, if it is one.
+fn stopped_state(origin: &TerminationOrigin) -> Option<State> {
+    match origin {
+        TerminationOrigin::Scheduler => Some(State::Idle),
+        TerminationOrigin::Pause => Some(State::Paused),
+        TerminationOrigin::EncryptionSync | TerminationOrigin::RoomList => None,
+    }
+}
+
+/// The delay before restarting the syncs after the given number of
+/// consecutive recovery attempts.
+fn recovery_delay(attempts: u32) -> Duration {
+    INITIAL_RECOVERY_DELAY.saturating_mul(2u32.saturating_pow(attempts)).min(MAX_RECOVERY_DELAY)
+}
+
 enum TerminationOrigin {
     EncryptionSync,
     RoomList,
     Scheduler,
+    Pause,
 }
 
 struct TerminationReport {
@@ -427,6 +586,9 @@ pub struct SyncServiceBuilder {
     /// Application identifier, used as the cross-process lock value, if
     /// applicable.
     identifier: String,
+
+    /// How many times in a row the syncs are restarted after a failure.
+    max_recovery_attempts: u32,
 }
 
 impl SyncServiceBuilder {
@@ -436,6 +598,7 @@ impl SyncServiceBuilder {
             with_cross_process_lock: false,
             with_encryption_sync: false,
             identifier: "app".to_owned(),
+            max_recovery_attempts: 0,
         }
     }
 
@@ -467,6 +630,20 @@ impl SyncServiceBuilder {
         self
     }
 
+    /// Restart the syncs automatically when one of them fails.
+    ///
+    /// The syncs are restarted after a delay growing exponentially from 1
+    /// second to 30 seconds, at most `max_attempts` times in a row. If the
+    /// syncs keep failing, the service ends up in the [`State::Error`] state.
+    /// The state stays [`State::Running`] while recovering.
+    ///
+    /// Disabled by default, in which case the caller must restart the service
+    /// with [`SyncService::start()`] after an error.
+    pub fn with_automatic_recovery(mut self, max_attempts: u32) -> Self {
+        self.max_recovery_attempts = max_attempts;
+        self
+    }
+
     /// Finish setting up the `SyncService`.
     ///
     /// This creates the underlying sliding syncs, and will *not* start them in
@@ -495,6 +672,7 @@ impl SyncServiceBuilder {
             room_list_task: Arc::new(Mutex::new(None)),
             scheduler_task: Arc::new(Mutex::new(None)),
             scheduler_sender: Mutex::new(None),
+            max_recovery_attempts: self.max_recovery_attempts,
             state: SharedObservable::new(State::Idle),
             modifying_state: AsyncMutex::new(()),
         })
@@ -12,10 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+#[cfg(feature = "e2e-encryption")]
+use std::collections::BTreeSet;
+use std::sync::{atomic::AtomicBool, Arc};
 
 use async_std::sync::Mutex;
 use eyeball::SharedObservable;
+use futures_util::StreamExt;
 use imbl::Vector;
 use matrix_sdk::{
     deserialized_responses::SyncTimelineEvent, executor::spawn, sync::RoomUpdate, Room,
@@ -24,17 +27,25 @@ use ruma::events::{
     receipt::{ReceiptThread, ReceiptType},
     AnySyncTimelineEvent,
 };
+#[cfg(feature = "e2e-encryption")]
+use ruma::{events::AnyToDeviceEvent, serde::Raw};
+#[cfg(feature = "e2e-encryption")]
+use serde::Deserialize;
 use tokio::sync::{broadcast, mpsc};
-use tracing::{error, info, info_span, trace, warn, Instrument};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 
-#[cfg(feature = "e2e-encryption")]
-use super::to_device::{handle_forwarded_room_key_event, handle_room_key_event};
 use super::{
     inner::{TimelineInner, TimelineInnerSettings},
-    queue::send_queued_messages,
-    BackPaginationStatus, Timeline, TimelineDropHandle,
+    pinned_events::PinnedEventIds,
+    queue::{restore_send_queue, send_queued_messages},
+    thread, BackPaginationStatus, ForwardPaginationStatus, Timeline, TimelineDropHandle,
+    TimelineFocus,
 };
 
+/// The number of cached events the live timeline starts with, if the event
+/// cache is enabled.
+const INITIAL_CACHED_EVENTS_COUNT: usize = 20;
+
 /// Builder that allows creating and configuring various parts of a
 /// [`Timeline`].
 #[must_use]
@@ -44,6 +55,7 @@ pub struct TimelineBuilder {
     prev_token: Option<String>,
     events: Vector<SyncTimelineEvent>,
     settings: TimelineInnerSettings,
+    focus: TimelineFocus,
 }
 
 impl TimelineBuilder {
@@ -53,6 +65,7 @@ impl TimelineBuilder {
             prev_token: None,
             events: Vector::new(),
             settings: TimelineInnerSettings::default(),
+            focus: TimelineFocus::default(),
         }
     }
 
@@ -106,6 +119,42 @@ impl TimelineBuilder {
         self
     }
 
+    /// Whether to collapse consecutive state events into a single item.
+    ///
+    /// If this is enabled, runs of state events on the same day, like
+    /// membership changes, are displayed as a single item with the
+    /// [`CollapsedStateEvents`][super::TimelineItemContent::CollapsedStateEvents]
+    /// content, that can be expanded with
+    /// [`Timeline::set_state_events_expanded`].
+    ///
+    /// Defaults to `false`.
+    pub fn collapse_state_events(mut self, collapse: bool) -> Self {
+        self.settings.collapse_state_events = collapse;
+        self
+    }
+
+    /// Whether to show the other members that are currently typing in a
+    /// [`VirtualTimelineItem::TypingUsers`][super::VirtualTimelineItem::TypingUsers]
+    /// item at the end of the timeline.
+    ///
+    /// Defaults to `false`.
+    pub fn show_typing_users(mut self, show: bool) -> Self {
+        self.settings.show_typing_users = show;
+        self
+    }
+
+    /// Choose what the timeline should focus on.
+    ///
+    /// Defaults to [`TimelineFocus::Live`].
+    ///
+    /// With a focus other than [`TimelineFocus::Live`], the initial events, the
+    /// tracking of the read marker and receipts, and the typing users are
+    /// ignored.
+    pub fn with_focus(mut self, focus: TimelineFocus) -> Self {
+        self.focus = focus;
+        self
+    }
+
     /// Create a [`Timeline`] with the options set on this builder.
     #[tracing::instrument(
         skip(self),
@@ -114,13 +163,61 @@ impl TimelineBuilder {
             events_length = self.events.len(),
             track_read_receipts = self.settings.track_read_receipts,
             prev_token = self.prev_token,
+            focus = ?self.focus,
         )
     )]
     pub async fn build(self) -> Timeline {
-        let Self { room, prev_token, events, settings } = self;
+        let Self { room, mut prev_token, mut events, mut settings, focus } = self;
+
+        let pinned_event_ids = match &focus {
+            TimelineFocus::Live => None,
+            TimelineFocus::PinnedEvents => {
+                let pinned_event_ids = PinnedEventIds::default();
+                prev_token = None;
+                events = pinned_event_ids.load_initial_events(&room).await;
+                settings.track_read_receipts = false;
+                settings.show_typing_users = false;
+                settings.event_filter = pinned_event_ids.wrap_filter(settings.event_filter);
+                Some(pinned_event_ids)
+            }
+            TimelineFocus::Thread { root_event_id } => {
+                prev_token = None;
+                events.clear();
+                settings.track_read_receipts = false;
+                settings.show_typing_users = false;
+                settings.event_filter =
+                    thread::wrap_filter(root_event_id.clone(), settings.event_filter);
+                None
+            }
+        };
+        let is_live = focus == TimelineFocus::Live;
+
+        // The live timeline starts with the cached events, and is paginated
+        // from the cache, which only makes requests to the homeserver for the
+        // events it doesn't have.
+        let event_cache = if is_live { room.event_cache() } else { None };
+        if let Some(event_cache) = &event_cache {
+            match event_cache.latest_events(INITIAL_CACHED_EVENTS_COUNT).await {
+                Ok(cached_events) => {
+                    prev_token = cached_events
+                        .first()
+                        .and_then(SyncTimelineEvent::event_id)
+                        .map(String::from);
+                    events = cached_events.into_iter().collect();
+                }
+                Err(e) => {
+                    error!("Failed to load the cached events: {e}");
+                }
+            }
+        }
+        let uses_event_cache = event_cache.is_some();
+
         let has_events = !events.is_empty();
         let track_read_marker_and_receipts = settings.track_read_receipts;
 
+        let (msg_sender, msg_receiver) = mpsc::channel(1);
+        settings.queue_sender = Some(msg_sender.downgrade());
+
         let mut inner = TimelineInner::new(room).with_settings(settings);
 
         if track_read_marker_and_receipts {
@@ -173,11 +270,19 @@ impl TimelineBuilder {
         let client = room.client();
 
         let start_token = Arc::new(Mutex::new(prev_token));
+        let end_token = Arc::new(Mutex::new(None));
+        let back_pagination_status = if pinned_event_ids.is_some() {
+            BackPaginationStatus::TimelineStartReached
+        } else {
+            BackPaginationStatus::Idle
+        };
 
         let mut room_update_rx = room.subscribe_to_updates();
         let room_update_join_handle = spawn({
             let inner = inner.clone();
             let start_token = start_token.clone();
+            let end_token = end_token.clone();
+            let pinned_event_ids = pinned_event_ids.clone();
             async move {
                 loop {
                     let update = match room_update_rx.recv().await {
@@ -185,7 +290,11 @@ impl TimelineBuilder {
                         Err(broadcast::error::RecvError::Closed) => break,
                         Err(broadcast::error::RecvError::Lagged(_)) => {
                             warn!("Lagged behind sync responses, resetting timeline");
-                            inner.clear().await;
+                            if let Some(pinned_event_ids) = &pinned_event_ids {
+                                pinned_event_ids.reload(&inner, true).await;
+                            } else {
+                                inner.clear().await;
+                            }
                             continue;
                         }
                     };
@@ -193,6 +302,12 @@ impl TimelineBuilder {
                     trace!("Handling a room update");
 
                     let update_start_token = |prev_batch: &Option<_>| {
+                        // Only the live timeline is paginated from the sync
+                        // tokens, unless it is paginated from the event cache.
+                        if !is_live || uses_event_cache {
+                            return;
+                        }
+
                         // Only update start_token if it's not currently locked.
                         // If it is locked, pagination is currently in progress.
                         if let Some(mut start_token) = start_token.try_lock() {
@@ -202,58 +317,246 @@ impl TimelineBuilder {
                         }
                     };
 
+                    // The events of the sync can't be added after the ones
+                    // loaded around a focused event, there is a gap between
+                    // them until the end of the timeline is reached.
+                    let is_detached_from_sync = end_token.lock().await.is_some();
+
+                    // All the changes made while handling the update are sent
+                    // at once to the subscribers of the batched stream.
+                    let batch_guard = inner.start_batch();
+
                     match update {
                         RoomUpdate::Left { updates, .. } => {
+                            if is_detached_from_sync {
+                                continue;
+                            }
+
                             update_start_token(&updates.timeline.prev_batch);
                             inner.handle_sync_timeline(updates.timeline).await;
                         }
-                        RoomUpdate::Joined { updates, .. } => {
+                        RoomUpdate::Joined { mut updates, .. } => {
+                            if is_detached_from_sync {
+                                trace!("Timeline is focused on an event, ignoring synced events");
+                                updates.timeline = Default::default();
+                            }
+
                             update_start_token(&updates.timeline.prev_batch);
                             inner.handle_joined_room_update(updates).await;
+
+                            if let Some(pinned_event_ids) = &pinned_event_ids {
+                                pinned_event_ids.reload(&inner, false).await;
+                            }
                         }
                         RoomUpdate::Invited { .. } => {
                             warn!("Room is in invited state, can't build or update its timeline");
                         }
                     }
+
+                    drop(batch_guard);
+
+                    #[cfg(feature = "backups")]
+                    inner.download_missing_room_keys_from_backup().await;
                 }
             }
             .instrument(info_span!("room_update_handler", room_id = ?room.room_id()))
         });
 
-        // Not using room.add_event_handler here because RoomKey events are
-        // to-device events that are not received in the context of a room.
-
+        // Retry to decrypt the events that couldn't be decrypted whenever room
+        // keys for this room are received, downloaded from the key backup or
+        // imported.
         #[cfg(feature = "e2e-encryption")]
-        let room_key_handle = client
-            .add_event_handler(handle_room_key_event(inner.clone(), room.room_id().to_owned()));
+        let room_keys_join_handle = match client.encryption().room_keys_received_stream().await {
+            Some(room_keys_stream) => Some(spawn({
+                let inner = inner.clone();
+                let client = client.clone();
+                let room_id = room.room_id().to_owned();
+                async move {
+                    let mut room_keys_stream = Box::pin(room_keys_stream);
+                    loop {
+                        while let Some(room_keys) = room_keys_stream.next().await {
+                            let session_ids: BTreeSet<_> = room_keys
+                                .into_iter()
+                                .filter(|room_key| room_key.room_id == room_id)
+                                .map(|room_key| room_key.session_id)
+                                .collect();
+
+                            if session_ids.is_empty() {
+                                continue;
+                            }
+
+                            trace!("Retrying decryption with the received room keys");
+                            inner.retry_event_decryption(inner.room(), Some(session_ids)).await;
+                        }
+
+                        // The stream ends when the OlmMachine is regenerated,
+                        // for example when another process used the crypto
+                        // store, so subscribe to the new one.
+                        let Some(new_stream) =
+                            client.encryption().room_keys_received_stream().await
+                        else {
+                            break;
+                        };
+                        debug!("The OlmMachine was regenerated, subscribing to its room keys");
+                        room_keys_stream = Box::pin(new_stream);
+
+                        // Room keys might have been received in the meantime.
+                        inner.retry_event_decryption(inner.room(), None).await;
+                    }
+                }
+                .instrument(info_span!("room_keys_received_handler", room_id = ?room.room_id()))
+            })),
+            None => {
+                warn!("The client is not logged in, decryption won't be retried automatically");
+                None
+            }
+        };
+
+        // Find out again why the events couldn't be decrypted when the sender
+        // of a room key tells us that they withheld it.
         #[cfg(feature = "e2e-encryption")]
-        let forwarded_room_key_handle = client.add_event_handler(handle_forwarded_room_key_event(
-            inner.clone(),
-            room.room_id().to_owned(),
-        ));
-
-        let handles = vec![
-            #[cfg(feature = "e2e-encryption")]
-            room_key_handle,
-            #[cfg(feature = "e2e-encryption")]
-            forwarded_room_key_handle,
-        ];
+        let room_key_withheld_handle = room.add_event_handler({
+            let inner = inner.clone();
+            move |event: Raw<AnyToDeviceEvent>| {
+                let inner = inner.clone();
+                async move {
+                    #[derive(Deserialize)]
+                    struct RoomKeyWithheldDetails {
+                        #[serde(rename = "type")]
+                        event_type: String,
+                        content: RoomKeyWithheldContentDetails,
+                    }
+
+                    #[derive(Deserialize)]
+                    struct RoomKeyWithheldContentDetails {
+                        session_id: Option<String>,
+                    }
+
+                    let Ok(details) = event.deserialize_as::<RoomKeyWithheldDetails>() else {
+                        return;
+                    };
+                    if details.event_type != "m.room_key.withheld" {
+                        return;
+                    }
+                    let Some(session_id) = details.content.session_id else {
+                        return;
+                    };
+
+                    trace!(session_id, "A room key was withheld, updating the UTD causes");
+                    inner.update_utd_causes(&session_id).await;
+                }
+            }
+        });
+
+        let back_pagination_status = SharedObservable::new(back_pagination_status);
+
+        // The homeserver doesn't send the events of ignored users, so remove the
+        // ones that are already in the timeline when a user is ignored, and fetch
+        // them again when a user is unignored.
+        let mut ignored_users_stream = Box::pin(client.subscribe_to_ignored_users());
+        let ignore_user_list_update_join_handle = spawn({
+            let inner = inner.clone();
+            let start_token = start_token.clone();
+            let back_pagination_status = back_pagination_status.clone();
+            let pinned_event_ids = pinned_event_ids.clone();
+            async move {
+                let mut ignored_users = client.ignored_users().await.unwrap_or_else(|error| {
+                    warn!("Failed to load the ignored users: {error}");
+                    Default::default()
+                });
+
+                while let Some(new_ignored_users) = ignored_users_stream.next().await {
+                    if ignored_users.is_subset(&new_ignored_users) {
+                        trace!("Removing the events of ignored users");
+                        inner.remove_events_from_senders(&new_ignored_users).await;
+                    } else if let Some(pinned_event_ids) = &pinned_event_ids {
+                        info!("Users were unignored, reloading timeline");
+                        pinned_event_ids.reload(&inner, true).await;
+                    } else {
+                        info!("Users were unignored, resetting timeline");
+                        *start_token.lock().await = None;
+                        inner.clear().await;
+                        back_pagination_status.set_if_not_eq(BackPaginationStatus::Idle);
+                    }
+
+                    ignored_users = new_ignored_users;
+                }
+            }
+            .instrument(info_span!("ignore_user_list_update_handler", room_id = ?room.room_id()))
+        });
+
+        // Remove the events that are older than what the retention policy of the
+        // room allows. The history cutoff is updated by the client after every
+        // sync.
+        let retention_join_handle = spawn({
+            let room = room.clone();
+            let inner = inner.clone();
+            let start_token = start_token.clone();
+            let back_pagination_status = back_pagination_status.clone();
+            let pinned_event_ids = pinned_event_ids.clone();
+            async move {
+                let mut cutoff_stream = room.subscribe_to_history_cutoff();
+                let mut cutoff = room.history_cutoff().await.unwrap_or_else(|error| {
+                    warn!("Failed to load the history cutoff: {error}");
+                    None
+                });
+                inner.set_history_cutoff(cutoff).await;
+
+                while let Some(new_cutoff) = cutoff_stream.next().await {
+                    // Events that were not kept before might be allowed now.
+                    let was_relaxed =
+                        cutoff.is_some_and(|old| new_cutoff.map_or(true, |new| new < old));
+                    cutoff = new_cutoff;
+                    inner.set_history_cutoff(cutoff).await;
+
+                    if was_relaxed {
+                        if let Some(pinned_event_ids) = &pinned_event_ids {
+                            info!("Retention policy was relaxed, reloading timeline");
+                            pinned_event_ids.reload(&inner, true).await;
+                        } else {
+                            info!("Retention policy was relaxed, resetting timeline");
+                            *start_token.lock().await = None;
+                            inner.clear().await;
+                            back_pagination_status.set_if_not_eq(BackPaginationStatus::Idle);
+                        }
+                    }
+                }
+            }
+            .instrument(info_span!("retention_handler", room_id = ?room.room_id()))
+        });
 
-        let (msg_sender, msg_receiver) = mpsc::channel(1);
         info!("Starting message-sending loop");
         spawn(send_queued_messages(inner.clone(), room.clone(), msg_receiver));
 
+        if is_live {
+            restore_send_queue(&inner, &room, &msg_sender).await;
+        }
+
         let timeline = Timeline {
             inner,
+            focus,
+            event_cache,
             start_token,
             start_token_condvar: Default::default(),
-            back_pagination_status: SharedObservable::new(BackPaginationStatus::Idle),
-            _end_token: Mutex::new(None),
+            back_pagination_status,
+            back_pagination_lock: Mutex::new(()),
+            end_token,
+            forward_pagination_status: SharedObservable::new(
+                ForwardPaginationStatus::LiveEdgeReached,
+            ),
+            forward_pagination_lock: Mutex::new(()),
+            is_focused_on_event: AtomicBool::new(false),
             msg_sender,
             drop_handle: Arc::new(TimelineDropHandle {
-                client,
-                event_handler_handles: handles,
                 room_update_join_handle,
+                ignore_user_list_update_join_handle,
+                retention_join_handle,
+                #[cfg(feature = "e2e-encryption")]
+                room_keys_join_handle,
+                #[cfg(feature = "e2e-encryption")]
+                room_key_withheld_handle,
+                #[cfg(feature = "e2e-encryption")]
+                client: client.clone(),
             }),
         };
 
@@ -263,6 +566,8 @@ impl TimelineBuilder {
             // have received the room key to decrypt them while nobody was listening to the
             // `m.room_key` event, let's retry now.
             timeline.retry_decryption_for_all_events().await;
+            #[cfg(feature = "backups")]
+            timeline.inner.download_missing_room_keys_from_backup().await;
         }
 
         timeline
@@ -0,0 +1,95 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use matrix_sdk::deserialized_responses::TimelineEvent;
+use ruma::{
+    events::{
+        room::message::{MessageType, Relation},
+        AnySyncMessageLikeEvent, AnySyncTimelineEvent, SyncMessageLikeEvent,
+    },
+    serde::Raw,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, UserId,
+};
+use tracing::warn;
+
+/// A version of a message, in its edit history.
+///
+/// To get it, use
+/// [`Timeline::fetch_edit_history()`][super::Timeline::fetch_edit_history].
+#[derive(Clone, Debug)]
+pub struct MessageVersion {
+    /// The ID of the event that introduced this version, i.e. the original
+    /// event or one of its edits.
+    pub event_id: OwnedEventId,
+    /// The sender of this version.
+    pub sender: OwnedUserId,
+    /// The time at which this version was sent.
+    pub timestamp: MilliSecondsSinceUnixEpoch,
+    /// The content of the message in this version.
+    pub msgtype: MessageType,
+}
+
+/// Get the version of the message introduced by the given original event.
+pub(super) fn original_version(event: &Raw<AnySyncTimelineEvent>) -> Option<MessageVersion> {
+    let AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+        SyncMessageLikeEvent::Original(event),
+    )) = event.deserialize().ok()?
+    else {
+        return None;
+    };
+
+    Some(MessageVersion {
+        event_id: event.event_id,
+        sender: event.sender,
+        timestamp: event.origin_server_ts,
+        msgtype: event.content.msgtype,
+    })
+}
+
+/// Get the version of the message introduced by the given edit.
+///
+/// Returns `None` if the event isn't a valid edit of the given original event,
+/// for example if it has a different sender.
+pub(super) fn edit_version(
+    edit: &TimelineEvent,
+    original_event_id: &EventId,
+    original_sender: &UserId,
+) -> Option<MessageVersion> {
+    let AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+        SyncMessageLikeEvent::Original(event),
+    )) = edit.event.deserialize_as().ok()?
+    else {
+        return None;
+    };
+
+    if event.sender != original_sender {
+        warn!(event_id = ?event.event_id, "Edit sent by another user, ignoring");
+        return None;
+    }
+
+    let Some(Relation::Replacement(replacement)) = event.content.relates_to else {
+        return None;
+    };
+
+    if replacement.event_id != original_event_id {
+        return None;
+    }
+
+    Some(MessageVersion {
+        event_id: event.event_id,
+        sender: event.sender,
+        timestamp: event.origin_server_ts,
+        msgtype: replacement.new_content.msgtype,
+    })
+}
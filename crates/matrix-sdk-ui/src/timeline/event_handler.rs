@@ -12,13 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::{mem, sync::Arc};
 
 use eyeball_im::{ObservableVector, ObservableVectorEntry};
 use indexmap::{map::Entry, IndexMap};
 use matrix_sdk::deserialized_responses::EncryptionInfo;
 use ruma::{
     events::{
+        beacon::BeaconEventContent,
+        beacon_info::BeaconInfoEventContent,
         poll::{
             unstable_end::UnstablePollEndEventContent,
             unstable_response::UnstablePollResponseEventContent,
@@ -27,41 +29,47 @@ use ruma::{
             },
         },
         reaction::ReactionEventContent,
-        receipt::Receipt,
+        receipt::{Receipt, ReceiptType},
         relation::Replacement,
         room::{
             encrypted::RoomEncryptedEventContent,
             member::RoomMemberEventContent,
             message::{
                 self, sanitize::RemoveReplyFallback, RoomMessageEventContent,
-                RoomMessageEventContentWithoutRelation,
+                RoomMessageEventContentWithoutRelation, SyncRoomMessageEvent,
             },
             redaction::{RoomRedactionEventContent, SyncRoomRedactionEvent},
         },
         AnyMessageLikeEventContent, AnySyncMessageLikeEvent, AnySyncStateEvent,
         AnySyncTimelineEvent, BundledMessageLikeRelations, EventContent, FullStateEventContent,
-        MessageLikeEventType, StateEventType, SyncStateEvent,
+        Mentions, MessageLikeEventType, StateEventType, SyncStateEvent,
     },
     serde::Raw,
     EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
     RoomVersionId,
 };
+use tokio::sync::mpsc::WeakSender;
 use tracing::{debug, error, field::debug, info, instrument, trace, warn};
 
 use super::{
     event_item::{
-        AnyOtherFullStateEventContent, BundledReactions, EventItemIdentifier, EventSendState,
-        EventTimelineItemKind, LocalEventTimelineItem, Profile, RemoteEventOrigin,
+        AnyOtherFullStateEventContent, BundledReactions, CollapsedStateEvents, EventItemIdentifier,
+        EventSendState, EventTimelineItemKind, LocalEventTimelineItem, Profile, RemoteEventOrigin,
         RemoteEventTimelineItem,
     },
+    inner::TimelineInnerSettings,
     item::timeline_item,
+    queue::QueueRequest,
     read_receipts::maybe_add_implicit_read_receipt,
     util::{find_read_marker, rfind_event_by_id, rfind_event_item, timestamp_to_date},
     EventTimelineItem, InReplyToDetails, Message, OtherState, ReactionGroup, ReactionSenderData,
-    Sticker, TimelineDetails, TimelineInnerState, TimelineItem, TimelineItemContent,
+    Sticker, TimelineDetails, TimelineInnerState, TimelineItem, TimelineItemContent, UtdCause,
     VirtualTimelineItem, DEFAULT_SANITIZER_MODE,
 };
-use crate::{events::SyncTimelineEventWithoutContent, timeline::polls::PollState};
+use crate::{
+    events::SyncTimelineEventWithoutContent,
+    timeline::{live_location::LiveLocationState, polls::PollState},
+};
 
 #[derive(Clone)]
 pub(super) enum Flow {
@@ -86,6 +94,7 @@ pub(super) struct TimelineEventContext {
     pub(super) encryption_info: Option<EncryptionInfo>,
     pub(super) read_receipts: IndexMap<OwnedUserId, Receipt>,
     pub(super) is_highlighted: bool,
+    pub(super) is_mentioned: bool,
     pub(super) flow: Flow,
 }
 
@@ -98,6 +107,10 @@ pub(super) enum TimelineEventKind {
     RedactedMessage {
         event_type: MessageLikeEventType,
     },
+    UnableToDecrypt {
+        content: RoomEncryptedEventContent,
+        cause: UtdCause,
+    },
     Redaction {
         redacts: OwnedEventId,
         content: RoomRedactionEventContent,
@@ -111,6 +124,10 @@ pub(super) enum TimelineEventKind {
         content: FullStateEventContent<RoomMemberEventContent>,
         sender: OwnedUserId,
     },
+    BeaconInfo {
+        user_id: OwnedUserId,
+        content: BeaconInfoEventContent,
+    },
     OtherState {
         state_key: String,
         content: AnyOtherFullStateEventContent,
@@ -162,6 +179,9 @@ impl TimelineEventKind {
                         sender: ev.sender,
                     },
                 },
+                AnySyncStateEvent::BeaconInfo(SyncStateEvent::Original(ev)) => {
+                    Self::BeaconInfo { user_id: ev.state_key, content: ev.content }
+                }
                 ev => Self::OtherState {
                     state_key: ev.state_key().to_owned(),
                     content: AnyOtherFullStateEventContent::with_event_content(ev.content()),
@@ -170,6 +190,35 @@ impl TimelineEventKind {
         }
     }
 
+    /// The intentional mentions of the message of this event, if any.
+    ///
+    /// For an edit, or a message with a bundled edit, these are the mentions
+    /// of the new content.
+    pub(super) fn mentions(&self) -> Option<&Mentions> {
+        let Self::Message { content: AnyMessageLikeEventContent::RoomMessage(content), relations } =
+            self
+        else {
+            return None;
+        };
+
+        let replacement = match (&content.relates_to, relations.replace.as_deref()) {
+            (Some(message::Relation::Replacement(replacement)), _) => Some(replacement),
+            (
+                _,
+                Some(AnySyncMessageLikeEvent::RoomMessage(SyncRoomMessageEvent::Original(edit))),
+            ) => match &edit.content.relates_to {
+                Some(message::Relation::Replacement(replacement)) => Some(replacement),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match replacement {
+            Some(replacement) => replacement.new_content.mentions.as_ref(),
+            None => content.mentions.as_ref(),
+        }
+    }
+
     pub(super) fn failed_to_parse(
         event: SyncTimelineEventWithoutContent,
         error: serde_json::Error,
@@ -200,8 +249,8 @@ impl TimelineEventKind {
 pub(super) enum TimelineItemPosition {
     Start,
     End {
-        /// Whether this event is coming from a local cache.
-        from_cache: bool,
+        /// Where this event is coming from.
+        origin: RemoteEventOrigin,
     },
     #[cfg(feature = "e2e-encryption")]
     Update(usize),
@@ -223,6 +272,8 @@ pub(super) struct TimelineEventHandler<'a> {
     state: &'a mut TimelineInnerState,
     ctx: TimelineEventContext,
     track_read_receipts: bool,
+    collapse_state_events: bool,
+    queue_sender: Option<WeakSender<QueueRequest>>,
     result: HandleEventResult,
 }
 
@@ -250,9 +301,16 @@ impl<'a> TimelineEventHandler<'a> {
     pub(super) fn new(
         state: &'a mut TimelineInnerState,
         ctx: TimelineEventContext,
-        track_read_receipts: bool,
+        settings: &TimelineInnerSettings,
     ) -> Self {
-        Self { state, ctx, track_read_receipts, result: HandleEventResult::default() }
+        Self {
+            state,
+            ctx,
+            track_read_receipts: settings.track_read_receipts,
+            collapse_state_events: settings.collapse_state_events,
+            queue_sender: settings.queue_sender.clone(),
+            result: HandleEventResult::default(),
+        }
     }
 
     /// Handle an event.
@@ -295,12 +353,18 @@ impl<'a> TimelineEventHandler<'a> {
                     self.handle_room_message_edit(re);
                 }
                 AnyMessageLikeEventContent::RoomMessage(c) => {
+                    if let Some(message::Relation::Thread(thread)) = &c.relates_to {
+                        self.add_implicit_thread_read_receipt(thread.event_id.clone());
+                    }
+
                     self.add(
                         should_add,
                         TimelineItemContent::message(c, relations, &self.state.items),
                     );
                 }
-                AnyMessageLikeEventContent::RoomEncrypted(c) => self.handle_room_encrypted(c),
+                AnyMessageLikeEventContent::RoomEncrypted(c) => {
+                    self.handle_room_encrypted(c, UtdCause::default());
+                }
                 AnyMessageLikeEventContent::Sticker(content) => {
                     self.add(should_add, TimelineItemContent::Sticker(Sticker { content }));
                 }
@@ -313,6 +377,7 @@ impl<'a> TimelineEventHandler<'a> {
                 }
                 AnyMessageLikeEventContent::UnstablePollResponse(c) => self.handle_poll_response(c),
                 AnyMessageLikeEventContent::UnstablePollEnd(c) => self.handle_poll_end(c),
+                AnyMessageLikeEventContent::Beacon(c) => self.handle_beacon(c),
                 // TODO
                 _ => {
                     debug!(
@@ -328,6 +393,10 @@ impl<'a> TimelineEventHandler<'a> {
                 }
             }
 
+            TimelineEventKind::UnableToDecrypt { content, cause } => {
+                self.handle_room_encrypted(content, cause);
+            }
+
             TimelineEventKind::Redaction { redacts, content } => {
                 self.handle_redaction(redacts, content);
             }
@@ -339,6 +408,10 @@ impl<'a> TimelineEventHandler<'a> {
                 self.add(should_add, TimelineItemContent::room_member(user_id, content, sender));
             }
 
+            TimelineEventKind::BeaconInfo { user_id, content } => {
+                self.handle_beacon_info(user_id, content, should_add);
+            }
+
             TimelineEventKind::OtherState { state_key, content } => {
                 self.add(
                     should_add,
@@ -414,7 +487,8 @@ impl<'a> TimelineEventHandler<'a> {
                 }
                 TimelineItemContent::MembershipChange(_)
                 | TimelineItemContent::ProfileChange(_)
-                | TimelineItemContent::OtherState { .. } => {
+                | TimelineItemContent::OtherState { .. }
+                | TimelineItemContent::CollapsedStateEvents(_) => {
                     info!("Edit event applies to a state event, discarding");
                     return None;
                 }
@@ -433,6 +507,7 @@ impl<'a> TimelineEventHandler<'a> {
                 msgtype,
                 in_reply_to: msg.in_reply_to.clone(),
                 edited: true,
+                mentions: replacement.new_content.mentions,
             });
 
             let edit_json = match &self.ctx.flow {
@@ -441,7 +516,12 @@ impl<'a> TimelineEventHandler<'a> {
             };
 
             trace!("Applying edit");
-            Some(event_item.with_content(new_content, edit_json))
+            let mut new_item = event_item.with_content(new_content, edit_json);
+            // The mentions of the edit replace the ones of the original message.
+            if let EventTimelineItemKind::Remote(remote) = &mut new_item.kind {
+                remote.is_mentioned = self.ctx.is_mentioned;
+            }
+            Some(new_item)
         });
     }
 
@@ -458,19 +538,16 @@ impl<'a> TimelineEventHandler<'a> {
             }
         };
 
+        // The event might still be a local echo, if it was sent but its remote
+        // echo wasn't received yet.
         if let Some((idx, event_item)) = rfind_event_by_id(&self.state.items, event_id) {
-            let Some(remote_event_item) = event_item.as_remote() else {
-                error!("inconsistent state: reaction received on a non-remote event item");
-                return;
-            };
-
             // Handling of reactions on redacted events is an open question.
             // For now, ignore reactions on redacted events like Element does.
             if let TimelineItemContent::RedactedMessage = event_item.content() {
                 debug!("Ignoring reaction on redacted event");
                 return;
             } else {
-                let mut reactions = remote_event_item.reactions.clone();
+                let mut reactions = event_item.reactions().clone();
                 let reaction_group = reactions.entry(c.relates_to.key.clone()).or_default();
 
                 if let Some(txn_id) = old_txn_id {
@@ -492,10 +569,8 @@ impl<'a> TimelineEventHandler<'a> {
                 );
 
                 trace!("Adding reaction");
-                self.state.items.set(
-                    idx,
-                    event_item.with_inner_kind(remote_event_item.with_reactions(reactions)),
-                );
+                let new_item = event_item.with_reactions(reactions);
+                self.state.items.set(idx, timeline_item(new_item, event_item.internal_id));
                 self.result.items_updated += 1;
             }
         } else {
@@ -628,10 +703,80 @@ impl<'a> TimelineEventHandler<'a> {
         );
     }
 
+    fn handle_beacon_info(
+        &mut self,
+        user_id: OwnedUserId,
+        c: BeaconInfoEventContent,
+        should_add: bool,
+    ) {
+        let pending_events = &mut self.state.live_location_pending_events;
+
+        if c.live {
+            let mut live_location_state = LiveLocationState::new(c, user_id);
+            if let Flow::Remote { event_id, .. } = self.ctx.flow.clone() {
+                pending_events.apply(&event_id, &mut live_location_state);
+            }
+            pending_events.apply_stop(self.ctx.timestamp, &mut live_location_state);
+            self.add(should_add, TimelineItemContent::LiveLocation(live_location_state));
+            return;
+        }
+
+        // A non-live beacon info stops the latest share with the same state key
+        // that was started before it, it is not shown as a separate item.
+        let stop_ts = self.ctx.timestamp;
+        let result = rfind_event_item(&self.state.items, |it| {
+            it.timestamp() <= stop_ts
+                && matches!(it.content(), TimelineItemContent::LiveLocation(s) if s.state_key == user_id)
+        });
+
+        let Some((idx, item)) = result else {
+            // The share might be loaded later, when paginating backwards.
+            debug!("Live location share not found, keeping stop for later");
+            pending_events.add_stop(&user_id, stop_ts);
+            return;
+        };
+
+        let TimelineItemContent::LiveLocation(live_location_state) = item.content() else {
+            return;
+        };
+        if live_location_state.is_stopped() {
+            debug!("Live location share already stopped, discarding stop");
+            return;
+        }
+
+        trace!("Stopping live location share");
+        let new_item =
+            item.with_content(TimelineItemContent::LiveLocation(live_location_state.stop()), None);
+        self.state.items.set(idx, timeline_item(new_item, item.internal_id));
+        self.result.items_updated += 1;
+    }
+
+    fn handle_beacon(&mut self, c: BeaconEventContent) {
+        update_timeline_item!(
+            self,
+            &c.relates_to.event_id,
+            found: |event_item| match event_item.content() {
+                TimelineItemContent::LiveLocation(live_location_state) => {
+                    let live_location_state = live_location_state.update_location((&c).into())?;
+                    Some(event_item.with_content(
+                        TimelineItemContent::LiveLocation(live_location_state),
+                        None,
+                    ))
+                }
+                _ => None,
+            },
+            not_found: || {
+                self.state
+                    .live_location_pending_events
+                    .add_location(&c.relates_to.event_id, (&c).into());
+            }
+        );
+    }
+
     #[instrument(skip_all)]
-    fn handle_room_encrypted(&mut self, c: RoomEncryptedEventContent) {
+    fn handle_room_encrypted(&mut self, c: RoomEncryptedEventContent, cause: UtdCause) {
         // TODO: Handle replacements if the replaced event is also UTD
-        self.add(true, TimelineItemContent::unable_to_decrypt(c));
+        self.add(true, TimelineItemContent::unable_to_decrypt(c, cause));
     }
 
     // Redacted redactions are no-ops (unfortunately)
@@ -694,12 +839,25 @@ impl<'a> TimelineEventHandler<'a> {
             // implemented) => no early return here.
         }
 
-        update_timeline_item!(self, &redacts, "redaction", |event_item| {
+        // Events in a group of state events are attached to the group.
+        let target = self.state.visible_event_id(&redacts).to_owned();
+        update_timeline_item!(self, &target, "redaction", |event_item| {
             if event_item.as_remote().is_none() {
                 error!("inconsistent state: redaction received on a non-remote event item");
                 return None;
             }
 
+            if let TimelineItemContent::CollapsedStateEvents(group) = &event_item.content {
+                let group = group.redact_event(&redacts, &self.state.room_version)?;
+                let content = TimelineItemContent::CollapsedStateEvents(group);
+                return Some(event_item.with_content(content, None));
+            }
+
+            if target != redacts {
+                // The event is hidden by the event filter.
+                return None;
+            }
+
             if let TimelineItemContent::RedactedMessage = &event_item.content {
                 debug!("event item is already redacted");
                 return None;
@@ -779,6 +937,7 @@ impl<'a> TimelineEventHandler<'a> {
     /// Add a new event item in the timeline.
     fn add(&mut self, should_add: bool, content: TimelineItemContent) {
         if !should_add {
+            self.hide();
             return;
         }
 
@@ -793,7 +952,13 @@ impl<'a> TimelineEventHandler<'a> {
             Flow::Local { txn_id } => {
                 let send_state = EventSendState::NotSentYet;
                 let transaction_id = txn_id.to_owned();
-                LocalEventTimelineItem { send_state, transaction_id }
+                let queue_sender = self.queue_sender.clone();
+                LocalEventTimelineItem {
+                    send_state,
+                    transaction_id,
+                    queue_sender,
+                    reactions: BundledReactions::default(),
+                }
             }
             .into(),
             Flow::Remote { event_id, raw_event, position, .. } => {
@@ -806,9 +971,7 @@ impl<'a> TimelineEventHandler<'a> {
 
                 let origin = match position {
                     TimelineItemPosition::Start => RemoteEventOrigin::Pagination,
-                    // We only paginate backwards for now, so End only happens for syncs
-                    TimelineItemPosition::End { from_cache: true } => RemoteEventOrigin::Cache,
-                    TimelineItemPosition::End { from_cache: false } => RemoteEventOrigin::Sync,
+                    TimelineItemPosition::End { origin } => *origin,
                     #[cfg(feature = "e2e-encryption")]
                     TimelineItemPosition::Update(idx) => self.state.items[*idx]
                         .as_event()
@@ -825,6 +988,7 @@ impl<'a> TimelineEventHandler<'a> {
                     read_receipts: self.ctx.read_receipts.clone(),
                     is_own: self.ctx.is_own_event,
                     is_highlighted: self.ctx.is_highlighted,
+                    is_mentioned: self.ctx.is_mentioned,
                     encryption_info: self.ctx.encryption_info.clone(),
                     original_json: Some(raw_event.clone()),
                     latest_edit_json: None,
@@ -836,6 +1000,14 @@ impl<'a> TimelineEventHandler<'a> {
 
         let mut item = EventTimelineItem::new(sender, sender_profile, timestamp, content, kind);
 
+        if self.collapse_state_events && item.content.is_state_change() {
+            if let Some(idx) = self.collapsible_item_idx(timestamp) {
+                self.collapse_state_event(idx, item);
+                self.maybe_update_read_marker();
+                return;
+            }
+        }
+
         match &self.ctx.flow {
             Flow::Local { .. } => {
                 trace!("Adding new local timeline item");
@@ -871,6 +1043,7 @@ impl<'a> TimelineEventHandler<'a> {
                     .iter()
                     .filter_map(|ev| ev.as_event()?.event_id())
                     .any(|id| id == event_id)
+                    || self.is_collapsed(event_id)
                 {
                     trace!("Skipping back-paginated event that has already been seen");
                     return;
@@ -901,16 +1074,27 @@ impl<'a> TimelineEventHandler<'a> {
                         self.ctx.is_own_event,
                         &mut self.state.items,
                         &mut self.state.users_read_receipts,
+                        &self.state.hidden_events,
                     );
                 }
 
                 let item = self.state.new_timeline_item(item);
                 self.state.items.insert(1, item);
+
+                // The hidden events at the start follow this event.
+                for hidden_event_id in mem::take(&mut self.state.hidden_events_at_start) {
+                    self.state.hidden_events.insert(hidden_event_id, event_id.clone());
+                }
             }
 
             Flow::Remote {
                 position: TimelineItemPosition::End { .. }, txn_id, event_id, ..
             } => {
+                if self.is_collapsed(event_id) {
+                    trace!("Skipping event that is already in a group of state events");
+                    return;
+                }
+
                 let result = rfind_event_item(&self.state.items, |it| {
                     txn_id.is_some() && it.transaction_id() == txn_id.as_deref()
                         || it.event_id() == Some(event_id)
@@ -935,6 +1119,23 @@ impl<'a> TimelineEventHandler<'a> {
                         }
                     }
 
+                    if let Some(local_item) = old_item.as_local() {
+                        // Keep the reactions to the local echo until their
+                        // own remote echoes are received.
+                        let is_redacted = item.content.is_redacted();
+                        if let Some(remote_item) = item.as_remote_mut().filter(|_| !is_redacted) {
+                            for (key, local_group) in &local_item.reactions {
+                                let group = remote_item.reactions.entry(key.clone()).or_default();
+                                for (id, sender_data) in &local_group.0 {
+                                    group
+                                        .0
+                                        .entry(id.clone())
+                                        .or_insert_with(|| sender_data.clone());
+                                }
+                            }
+                        }
+                    }
+
                     if txn_id.is_none() {
                         // The event was created by this client, but the server
                         // sent it back without a transaction ID.
@@ -959,6 +1160,7 @@ impl<'a> TimelineEventHandler<'a> {
                                 self.ctx.is_own_event,
                                 &mut self.state.items,
                                 &mut self.state.users_read_receipts,
+                                &self.state.hidden_events,
                             );
                         }
 
@@ -1019,7 +1221,11 @@ impl<'a> TimelineEventHandler<'a> {
                     .find(|(_, evt)| {
                         !matches!(
                             evt.send_state(),
-                            Some(EventSendState::NotSentYet | EventSendState::Sent { .. })
+                            Some(
+                                EventSendState::NotSentYet
+                                    | EventSendState::Sending { .. }
+                                    | EventSendState::Sent { .. }
+                            )
                         )
                     })
                     .unzip()
@@ -1076,6 +1282,7 @@ impl<'a> TimelineEventHandler<'a> {
                         self.ctx.is_own_event,
                         &mut self.state.items,
                         &mut self.state.users_read_receipts,
+                        &self.state.hidden_events,
                     );
                 }
 
@@ -1104,14 +1311,226 @@ impl<'a> TimelineEventHandler<'a> {
             }
         }
 
-        // See if we can update the read marker.
-        if self.state.event_should_update_fully_read_marker {
-            update_read_marker(
+        self.maybe_update_read_marker();
+    }
+
+    /// Add an implicit read receipt for the sender of the current event in the
+    /// given thread.
+    fn add_implicit_thread_read_receipt(&mut self, thread_root: OwnedEventId) {
+        if !self.track_read_receipts {
+            return;
+        }
+
+        // Back-paginated events are older than the current receipts.
+        let Flow::Remote { event_id, position: TimelineItemPosition::End { .. }, .. } =
+            &self.ctx.flow
+        else {
+            return;
+        };
+
+        self.state.maybe_update_thread_read_receipt(
+            self.ctx.sender.clone(),
+            thread_root,
+            ReceiptType::Read,
+            event_id.clone(),
+            Receipt::new(self.ctx.timestamp),
+        );
+    }
+
+    /// The index of the item that the current state event can be collapsed
+    /// into, if any.
+    ///
+    /// It is the remote state event or group of state events next to the
+    /// position of the current event, if it is on the same day.
+    fn collapsible_item_idx(&self, timestamp: MilliSecondsSinceUnixEpoch) -> Option<usize> {
+        let Flow::Remote { event_id, position, .. } = &self.ctx.flow else { return None };
+
+        let idx = match position {
+            // The first item is always a day divider.
+            TimelineItemPosition::Start => 1,
+            TimelineItemPosition::End { .. } => self.state.items.len().checked_sub(1)?,
+            #[cfg(feature = "e2e-encryption")]
+            TimelineItemPosition::Update(_) => return None,
+        };
+
+        // Duplicate events are handled like other events.
+        if rfind_event_by_id(&self.state.items, event_id).is_some() || self.is_collapsed(event_id) {
+            return None;
+        }
+
+        let item = self.state.items.get(idx)?.as_event()?;
+        let is_collapsible = item.is_remote_event()
+            && (item.content.is_state_change()
+                || matches!(item.content, TimelineItemContent::CollapsedStateEvents(_)));
+
+        (is_collapsible && timestamp_to_date(item.timestamp()) == timestamp_to_date(timestamp))
+            .then_some(idx)
+    }
+
+    /// Add the current state event to the item at the given index, which
+    /// becomes a group of state events if it isn't one already.
+    ///
+    /// The group keeps the identity of its first item, and the event is
+    /// attached to it for the fully-read marker and the read receipts.
+    fn collapse_state_event(&mut self, idx: usize, mut item: EventTimelineItem) {
+        let Flow::Remote { event_id, position, .. } = &self.ctx.flow else { return };
+        let (event_id, position) = (event_id.clone(), position.clone());
+
+        self.result.item_added = false;
+
+        if self.track_read_receipts {
+            // Back-paginated events are older than the items of the group.
+            let item_pos = match position {
+                TimelineItemPosition::Start => 0,
+                _ => idx,
+            };
+            maybe_add_implicit_read_receipt(
+                item_pos,
+                &mut item,
+                self.ctx.is_own_event,
                 &mut self.state.items,
-                self.state.fully_read_event.as_deref(),
-                &mut self.state.event_should_update_fully_read_marker,
+                &mut self.state.users_read_receipts,
+                &self.state.hidden_events,
             );
         }
+
+        // The read receipts are displayed on the group.
+        let read_receipts = item
+            .as_remote_mut()
+            .map(|remote| mem::take(&mut remote.read_receipts))
+            .unwrap_or_default();
+
+        let group_item = &self.state.items[idx];
+        let internal_id = group_item.internal_id;
+        let mut group_event = group_item.as_event().expect("collapsible item is an event").clone();
+        let group_event_id =
+            group_event.event_id().expect("collapsible item is a remote event").to_owned();
+
+        let mut group = match &group_event.content {
+            TimelineItemContent::CollapsedStateEvents(group) => group.clone(),
+            _ => {
+                let mut first_event = group_event.clone();
+                if let Some(remote) = first_event.as_remote_mut() {
+                    remote.read_receipts.clear();
+                }
+                CollapsedStateEvents::new(first_event)
+            }
+        };
+
+        match position {
+            TimelineItemPosition::Start => group.events.push_front(item),
+            _ => group.events.push_back(item),
+        }
+
+        group_event.set_content(TimelineItemContent::CollapsedStateEvents(group));
+        if let Some(remote) = group_event.as_remote_mut() {
+            remote.read_receipts.extend(read_receipts);
+        }
+
+        trace!(idx, "Collapsing state event into existing item");
+        self.state.items.set(idx, timeline_item(group_event, internal_id));
+        self.result.items_updated += 1;
+
+        self.state.hidden_events.insert(event_id, group_event_id.clone());
+        if matches!(position, TimelineItemPosition::Start) {
+            // The hidden events at the start follow this event.
+            for hidden_event_id in mem::take(&mut self.state.hidden_events_at_start) {
+                self.state.hidden_events.insert(hidden_event_id, group_event_id.clone());
+            }
+        }
+    }
+
+    /// Whether the event with the given ID is in a group of state events.
+    fn is_collapsed(&self, event_id: &EventId) -> bool {
+        self.state.items.iter().any(|item| {
+            item.as_event()
+                .and_then(|ev| ev.content.as_collapsed_state_events())
+                .is_some_and(|group| group.contains(event_id))
+        })
+    }
+
+    /// Handle a remote event that is hidden by the event filter.
+    ///
+    /// The event is attached to the visible event that precedes it, for the
+    /// fully-read marker and the read receipts that point to it. The local
+    /// echo of the event is removed.
+    fn hide(&mut self) {
+        let Flow::Remote { event_id, txn_id, position, .. } = &self.ctx.flow else { return };
+        let (event_id, txn_id, position) = (event_id.clone(), txn_id.clone(), position.clone());
+
+        match position {
+            TimelineItemPosition::Start => {
+                self.state.hidden_events_at_start.push(event_id.clone());
+            }
+
+            TimelineItemPosition::End { .. } => {
+                // Remove the local echo of the event, if any.
+                if let Some(txn_id) = &txn_id {
+                    if let Some((idx, _)) = rfind_event_item(&self.state.items, |it| {
+                        it.transaction_id() == Some(txn_id)
+                    }) {
+                        trace!("Removing local echo of hidden event");
+                        self.remove_item(idx);
+                    }
+                }
+
+                match latest_remote_event_id(&self.state.items, self.state.items.len()) {
+                    Some(visible_event_id) => {
+                        self.state.hidden_events.insert(event_id.clone(), visible_event_id);
+                    }
+                    None => self.state.hidden_events_at_start.push(event_id.clone()),
+                }
+            }
+
+            #[cfg(feature = "e2e-encryption")]
+            TimelineItemPosition::Update(idx) => {
+                // The event was decrypted and is now hidden, its item is removed
+                // in `handle_event`.
+                match latest_remote_event_id(&self.state.items, idx) {
+                    Some(visible_event_id) => {
+                        self.state.hidden_events.insert(event_id.clone(), visible_event_id);
+                    }
+                    None => self.state.hidden_events_at_start.push(event_id.clone()),
+                }
+            }
+        }
+
+        if self.state.fully_read_event.as_ref() == Some(&event_id) {
+            self.state.event_should_update_fully_read_marker = true;
+            self.maybe_update_read_marker();
+        }
+    }
+
+    /// Remove the item at the given index, and the day divider that precedes
+    /// it if it has no event item left.
+    fn remove_item(&mut self, idx: usize) {
+        self.state.items.remove(idx);
+
+        if idx > 0
+            && self.state.items[idx - 1].is_day_divider()
+            && self.state.items.get(idx).map_or(true, |item| item.is_day_divider())
+        {
+            trace!("Removing stray day divider");
+            self.state.items.remove(idx - 1);
+        }
+    }
+
+    /// Update the fully-read marker, if it couldn't be placed yet.
+    fn maybe_update_read_marker(&mut self) {
+        if !self.state.event_should_update_fully_read_marker {
+            return;
+        }
+
+        let fully_read_event = self
+            .state
+            .fully_read_event
+            .as_deref()
+            .map(|event_id| self.state.visible_event_id(event_id).to_owned());
+        update_read_marker(
+            &mut self.state.items,
+            fully_read_event.as_deref(),
+            &mut self.state.event_should_update_fully_read_marker,
+        );
     }
 
     fn pending_reactions(&mut self) -> Option<BundledReactions> {
@@ -1143,6 +1562,18 @@ impl<'a> TimelineEventHandler<'a> {
     }
 }
 
+/// The ID of the latest remote event item before the given index.
+fn latest_remote_event_id(
+    items: &ObservableVector<Arc<TimelineItem>>,
+    end: usize,
+) -> Option<OwnedEventId> {
+    items
+        .iter()
+        .take(end)
+        .rev()
+        .find_map(|item| Some(item.as_event()?.as_remote()?.event_id.clone()))
+}
+
 pub(crate) fn update_read_marker(
     items: &mut ObservableVector<Arc<TimelineItem>>,
     fully_read_event: Option<&EventId>,
@@ -19,6 +19,7 @@ use indexmap::IndexMap;
 use itertools::Itertools;
 use matrix_sdk::{deserialized_responses::TimelineEvent, Result};
 use matrix_sdk_base::latest_event::{is_suitable_for_latest_event, PossibleLatestEvent};
+use matrix_sdk_crypto::types::events::room_key_withheld::WithheldCode;
 use ruma::{
     assign,
     events::{
@@ -54,17 +55,17 @@ use ruma::{
         sticker::StickerEventContent,
         AnyFullStateEventContent, AnyMessageLikeEventContent, AnySyncMessageLikeEvent,
         AnySyncTimelineEvent, AnyTimelineEvent, BundledMessageLikeRelations, FullStateEventContent,
-        MessageLikeEventType, OriginalSyncMessageLikeEvent, StateEventType,
+        Mentions, MessageLikeEventType, OriginalSyncMessageLikeEvent, StateEventType,
     },
-    OwnedDeviceId, OwnedEventId, OwnedMxcUri, OwnedTransactionId, OwnedUserId, RoomVersionId,
-    UserId,
+    EventId, OwnedDeviceId, OwnedEventId, OwnedMxcUri, OwnedTransactionId, OwnedUserId,
+    RoomVersionId, UserId,
 };
 use tracing::{error, warn};
 
 use super::{EventItemIdentifier, EventTimelineItem, Profile, TimelineDetails};
 use crate::timeline::{
-    polls::PollState, traits::RoomDataProvider, Error as TimelineError, ReactionSenderData,
-    TimelineItem, DEFAULT_SANITIZER_MODE,
+    live_location::LiveLocationState, polls::PollState, traits::RoomDataProvider,
+    Error as TimelineError, ReactionSenderData, TimelineItem, DEFAULT_SANITIZER_MODE,
 };
 
 /// The content of an [`EventTimelineItem`][super::EventTimelineItem].
@@ -114,6 +115,16 @@ pub enum TimelineItemContent {
 
     /// An `m.poll.start` event.
     Poll(PollState),
+
+    /// A live `m.beacon_info` state event, with the last location that was
+    /// shared.
+    LiveLocation(LiveLocationState),
+
+    /// A group of consecutive state events.
+    ///
+    /// Only used if the timeline collapses state events, see
+    /// [`TimelineBuilder::collapse_state_events`][crate::timeline::TimelineBuilder::collapse_state_events].
+    CollapsedStateEvents(CollapsedStateEvents),
 }
 
 impl TimelineItemContent {
@@ -196,6 +207,21 @@ impl TimelineItemContent {
         }
     }
 
+    /// If `self` is of the [`CollapsedStateEvents`][Self::CollapsedStateEvents]
+    /// variant, return the inner [`CollapsedStateEvents`].
+    pub fn as_collapsed_state_events(&self) -> Option<&CollapsedStateEvents> {
+        match self {
+            Self::CollapsedStateEvents(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Whether this content is a state change that can be collapsed with
+    /// other state changes.
+    pub(in crate::timeline) fn is_state_change(&self) -> bool {
+        matches!(self, Self::MembershipChange(_) | Self::ProfileChange(_) | Self::OtherState(_))
+    }
+
     pub(crate) fn is_redacted(&self) -> bool {
         matches!(self, Self::RedactedMessage)
     }
@@ -210,8 +236,8 @@ impl TimelineItemContent {
         Self::Message(Message::from_event(c, relations, timeline_items))
     }
 
-    pub(crate) fn unable_to_decrypt(content: RoomEncryptedEventContent) -> Self {
-        TimelineItemContent::UnableToDecrypt(content.into())
+    pub(crate) fn unable_to_decrypt(content: RoomEncryptedEventContent, cause: UtdCause) -> Self {
+        TimelineItemContent::UnableToDecrypt(EncryptedMessage::new(content, cause))
     }
 
     pub(crate) fn room_member(
@@ -287,11 +313,15 @@ impl TimelineItemContent {
             | Self::RedactedMessage
             | Self::Sticker(_)
             | Self::Poll(_)
+            | Self::LiveLocation(_)
             | Self::UnableToDecrypt(_) => Self::RedactedMessage,
             Self::MembershipChange(ev) => Self::MembershipChange(ev.redact(room_version)),
             Self::ProfileChange(ev) => Self::ProfileChange(ev.redact()),
             Self::OtherState(ev) => Self::OtherState(ev.redact(room_version)),
-            Self::FailedToParseMessageLike { .. } | Self::FailedToParseState { .. } => self.clone(),
+            Self::FailedToParseMessageLike { .. }
+            | Self::FailedToParseState { .. }
+            // The events of the group are redacted individually.
+            | Self::CollapsedStateEvents(_) => self.clone(),
         }
     }
 }
@@ -302,6 +332,7 @@ pub struct Message {
     pub(in crate::timeline) msgtype: MessageType,
     pub(in crate::timeline) in_reply_to: Option<InReplyToDetails>,
     pub(in crate::timeline) edited: bool,
+    pub(in crate::timeline) mentions: Option<Mentions>,
 }
 
 impl Message {
@@ -340,11 +371,11 @@ impl Message {
             _ => None,
         });
 
-        let msgtype = match edit {
+        let (msgtype, mentions) = match edit {
             Some(mut e) => {
                 // Edit's content is never supposed to contain the reply fallback.
                 e.new_content.msgtype.sanitize(DEFAULT_SANITIZER_MODE, RemoveReplyFallback::No);
-                e.new_content.msgtype
+                (e.new_content.msgtype, e.new_content.mentions)
             }
             None => {
                 let remove_reply_fallback = if in_reply_to.is_some() {
@@ -355,11 +386,11 @@ impl Message {
 
                 let mut msgtype = c.msgtype;
                 msgtype.sanitize(DEFAULT_SANITIZER_MODE, remove_reply_fallback);
-                msgtype
+                (msgtype, c.mentions)
             }
         };
 
-        Self { msgtype, in_reply_to, edited }
+        Self { msgtype, in_reply_to, edited, mentions }
     }
 
     /// Get the `msgtype`-specific data of this message.
@@ -384,6 +415,13 @@ impl Message {
         self.edited
     }
 
+    /// Get the intentional mentions of this message, if any.
+    ///
+    /// If the message was edited, these are the mentions of the latest edit.
+    pub fn mentions(&self) -> Option<&Mentions> {
+        self.mentions.as_ref()
+    }
+
     pub(in crate::timeline) fn with_in_reply_to(&self, in_reply_to: InReplyToDetails) -> Self {
         Self { in_reply_to: Some(in_reply_to), ..self.clone() }
     }
@@ -394,14 +432,14 @@ impl From<Message> for RoomMessageEventContent {
         let relates_to = msg.in_reply_to.map(|details| message::Relation::Reply {
             in_reply_to: InReplyTo::new(details.event_id),
         });
-        assign!(Self::new(msg.msgtype), { relates_to })
+        assign!(Self::new(msg.msgtype), { relates_to, mentions: msg.mentions })
     }
 }
 
 #[cfg(not(tarpaulin_include))]
 impl fmt::Debug for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Self { msgtype: _, in_reply_to, edited } = self;
+        let Self { msgtype: _, in_reply_to, edited, mentions: _ } = self;
         // since timeline items are logged, don't include all fields here so
         // people don't leak personal data in bug reports
         f.debug_struct("Message")
@@ -529,13 +567,16 @@ pub enum EncryptedMessage {
 
         /// The ID of the session used to encrypt the message.
         session_id: String,
+
+        /// Why the message couldn't be decrypted.
+        cause: UtdCause,
     },
     /// No metadata because the event uses an unknown algorithm.
     Unknown,
 }
 
-impl From<RoomEncryptedEventContent> for EncryptedMessage {
-    fn from(c: RoomEncryptedEventContent) -> Self {
+impl EncryptedMessage {
+    fn new(c: RoomEncryptedEventContent, cause: UtdCause) -> Self {
         match c.scheme {
             EncryptedEventScheme::OlmV1Curve25519AesSha2(s) => {
                 Self::OlmV1Curve25519AesSha2 { sender_key: s.sender_key }
@@ -543,13 +584,51 @@ impl From<RoomEncryptedEventContent> for EncryptedMessage {
             #[allow(deprecated)]
             EncryptedEventScheme::MegolmV1AesSha2(s) => {
                 let MegolmV1AesSha2Content { sender_key, device_id, session_id, .. } = s;
-                Self::MegolmV1AesSha2 { sender_key, device_id, session_id }
+                Self::MegolmV1AesSha2 { sender_key, device_id, session_id, cause }
             }
             _ => Self::Unknown,
         }
     }
 }
 
+/// The reason why an `m.megolm.v1.aes-sha2` event could not be decrypted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum UtdCause {
+    /// The room key wasn't received, and no more specific reason is known.
+    ///
+    /// It might still arrive later, in which case the event is decrypted
+    /// automatically.
+    #[default]
+    MissingRoomKey,
+
+    /// The sender didn't share the room key because this device isn't
+    /// verified.
+    UnverifiedDevice,
+
+    /// The sender didn't share the room key, for the given reason.
+    Withheld(WithheldCode),
+
+    /// The event was sent before the user joined the room, so the room key
+    /// wasn't shared with them.
+    SentBeforeJoin,
+
+    /// A key backup exists, but the room key can't be downloaded from it
+    /// because its decryption key isn't known.
+    ///
+    /// The decryption key can be retrieved by recovering the secrets of the
+    /// account, with `Encryption::recover_secrets()`.
+    BackupNotConnected,
+}
+
+impl From<WithheldCode> for UtdCause {
+    fn from(code: WithheldCode) -> Self {
+        match code {
+            WithheldCode::Unverified => Self::UnverifiedDevice,
+            code => Self::Withheld(code),
+        }
+    }
+}
+
 /// The reactions grouped by key.
 ///
 /// Key: The reaction, usually an emoji.\
@@ -594,6 +673,50 @@ impl Deref for ReactionGroup {
     }
 }
 
+/// A group of consecutive state events, displayed as a single timeline item.
+#[derive(Clone, Debug)]
+pub struct CollapsedStateEvents {
+    pub(in crate::timeline) events: Vector<EventTimelineItem>,
+    pub(in crate::timeline) expanded: bool,
+}
+
+impl CollapsedStateEvents {
+    /// Create a collapsed group with the given state event.
+    pub(in crate::timeline) fn new(event: EventTimelineItem) -> Self {
+        Self { events: vector![event], expanded: false }
+    }
+
+    /// The state events of the group, from the oldest to the newest.
+    pub fn events(&self) -> &Vector<EventTimelineItem> {
+        &self.events
+    }
+
+    /// Whether the group should be displayed expanded, with all its events,
+    /// rather than as a summary.
+    pub fn is_expanded(&self) -> bool {
+        self.expanded
+    }
+
+    /// Whether the group contains the event with the given ID.
+    pub(in crate::timeline) fn contains(&self, event_id: &EventId) -> bool {
+        self.events.iter().any(|ev| ev.event_id() == Some(event_id))
+    }
+
+    /// Redact the event with the given ID in this group.
+    ///
+    /// Returns `None` if the group doesn't contain this event.
+    pub(in crate::timeline) fn redact_event(
+        &self,
+        event_id: &EventId,
+        room_version: &RoomVersionId,
+    ) -> Option<Self> {
+        let idx = self.events.iter().position(|ev| ev.event_id() == Some(event_id))?;
+        let mut events = self.events.clone();
+        events.set(idx, self.events[idx].redact(room_version));
+        Some(Self { events, expanded: self.expanded })
+    }
+}
+
 /// An `m.sticker` event.
 #[derive(Clone, Debug)]
 pub struct Sticker {
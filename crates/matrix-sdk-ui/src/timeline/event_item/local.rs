@@ -14,8 +14,12 @@
 
 use std::sync::Arc;
 
-use matrix_sdk::Error;
+use matrix_sdk::{Error, TransmissionProgress};
 use ruma::{EventId, OwnedEventId, OwnedTransactionId};
+use tokio::sync::mpsc::WeakSender;
+
+use super::BundledReactions;
+use crate::timeline::queue::QueueRequest;
 
 /// An item for an event that was created locally and not yet echoed back by
 /// the homeserver.
@@ -25,6 +29,10 @@ pub(in crate::timeline) struct LocalEventTimelineItem {
     pub send_state: EventSendState,
     /// The transaction ID.
     pub transaction_id: OwnedTransactionId,
+    /// The sender of the message-sending loop of the timeline, if it has one.
+    pub queue_sender: Option<WeakSender<QueueRequest>>,
+    /// The reactions to this local echo, that are sent once it is sent.
+    pub reactions: BundledReactions,
 }
 
 impl LocalEventTimelineItem {
@@ -43,6 +51,11 @@ impl LocalEventTimelineItem {
     pub fn with_send_state(&self, send_state: EventSendState) -> Self {
         Self { send_state, ..self.clone() }
     }
+
+    /// Clone the current event item, and update its `reactions`.
+    pub fn with_reactions(&self, reactions: BundledReactions) -> Self {
+        Self { reactions, ..self.clone() }
+    }
 }
 
 /// This type represents the "send state" of a local event timeline item.
@@ -50,6 +63,14 @@ impl LocalEventTimelineItem {
 pub enum EventSendState {
     /// The local event has not been sent yet.
     NotSentYet,
+    /// The local event is being sent to the server.
+    ///
+    /// This is only used for attachments, that are sent outside of the
+    /// message-sending queue.
+    Sending {
+        /// The progress of the upload of the media, if it has started.
+        progress: Option<TransmissionProgress>,
+    },
     /// The local event has been sent to the server, but unsuccessfully: The
     /// sending has failed.
     SendingFailed {
@@ -15,7 +15,10 @@
 use std::sync::Arc;
 
 use indexmap::IndexMap;
-use matrix_sdk::{deserialized_responses::EncryptionInfo, Client, Error};
+use matrix_sdk::{
+    deserialized_responses::{EncryptionInfo, ShieldState},
+    Client, Error,
+};
 use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
 use once_cell::sync::Lazy;
 use ruma::{
@@ -26,15 +29,18 @@ use ruma::{
 };
 use tracing::warn;
 
+use super::{queue::LocalEchoHandle, util::is_user_mentioned};
+
 mod content;
 mod local;
 mod remote;
 
 pub use self::{
     content::{
-        AnyOtherFullStateEventContent, BundledReactions, EncryptedMessage, InReplyToDetails,
-        MemberProfileChange, MembershipChange, Message, OtherState, ReactionGroup, RepliedToEvent,
-        RoomMembershipChange, Sticker, TimelineItemContent,
+        AnyOtherFullStateEventContent, BundledReactions, CollapsedStateEvents, EncryptedMessage,
+        InReplyToDetails, MemberProfileChange, MembershipChange, Message, OtherState,
+        ReactionGroup, RepliedToEvent, RoomMembershipChange, Sticker, TimelineItemContent,
+        UtdCause,
     },
     local::EventSendState,
 };
@@ -73,7 +79,9 @@ pub(super) enum EventTimelineItemKind {
 /// A wrapper that can contain either a transaction id, or an event id.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum EventItemIdentifier {
+    /// The transaction ID of a local echo.
     TransactionId(OwnedTransactionId),
+    /// The ID of an event received from the server.
     EventId(OwnedEventId),
 }
 
@@ -124,6 +132,17 @@ impl EventTimelineItem {
         // Being highlighted is _probably_ not relevant to the message preview.
         let is_highlighted = false;
 
+        let room = client.get_room(room_id);
+
+        // Mentions are useful in the message preview though, to show that the
+        // room needs the user's attention.
+        let is_mentioned = match (&room, &item_content) {
+            (Some(room), TimelineItemContent::Message(message)) => {
+                is_user_mentioned(room, &sender, message.mentions()).await
+            }
+            _ => false,
+        };
+
         // We may need this, depending on how we are going to display edited messages in
         // previews.
         let latest_edit_json = None;
@@ -137,6 +156,7 @@ impl EventTimelineItem {
             read_receipts,
             is_own,
             is_highlighted,
+            is_mentioned,
             encryption_info,
             original_json: Some(raw_sync_event),
             latest_edit_json,
@@ -144,7 +164,6 @@ impl EventTimelineItem {
         }
         .into();
 
-        let room = client.get_room(room_id);
         let sender_profile = if let Some(room) = room {
             room.profile(&sender)
                 .await
@@ -200,6 +219,14 @@ impl EventTimelineItem {
         }
     }
 
+    /// Get a handle to abort sending this item, edit it before it's sent, or
+    /// retry sending it after it failed, if it is a local echo.
+    pub fn local_echo_handle(&self) -> Option<LocalEchoHandle> {
+        let local = self.as_local()?;
+        let queue_sender = local.queue_sender.clone()?;
+        Some(LocalEchoHandle::new(local.transaction_id.clone(), queue_sender))
+    }
+
     /// Get the transaction ID of this item.
     ///
     /// The transaction ID is currently only kept until the remote echo for a
@@ -226,6 +253,22 @@ impl EventTimelineItem {
         }
     }
 
+    /// Get the identifier of this item, to refer to it in
+    /// [`Timeline::toggle_reaction_on_item()`][super::Timeline::toggle_reaction_on_item].
+    ///
+    /// This is the transaction ID of a local echo, or the ID of an event
+    /// received from the server.
+    pub fn identifier(&self) -> EventItemIdentifier {
+        match &self.kind {
+            EventTimelineItemKind::Local(local) => {
+                EventItemIdentifier::TransactionId(local.transaction_id.clone())
+            }
+            EventTimelineItemKind::Remote(remote) => {
+                EventItemIdentifier::EventId(remote.event_id.clone())
+            }
+        }
+    }
+
     /// Get the sender of this item.
     pub fn sender(&self) -> &UserId {
         &self.sender
@@ -242,11 +285,12 @@ impl EventTimelineItem {
     }
 
     /// Get the reactions of this item.
+    ///
+    /// The reactions to a local echo are local echoes too, that are sent once
+    /// the event is sent.
     pub fn reactions(&self) -> &BundledReactions {
-        // There's not much of a point in allowing reactions to local echoes.
-        static EMPTY_REACTIONS: Lazy<BundledReactions> = Lazy::new(Default::default);
         match &self.kind {
-            EventTimelineItemKind::Local(_) => &EMPTY_REACTIONS,
+            EventTimelineItemKind::Local(local_event) => &local_event.reactions,
             EventTimelineItemKind::Remote(remote_event) => &remote_event.reactions,
         }
     }
@@ -301,6 +345,18 @@ impl EventTimelineItem {
         }
     }
 
+    /// Whether the logged-in user is mentioned in this event.
+    ///
+    /// This only uses the intentional mentions of the event, i.e. its
+    /// `m.mentions` field, and is independent from the push rules that decide
+    /// whether the event [is highlighted](Self::is_highlighted).
+    pub fn is_user_mentioned(&self) -> bool {
+        match &self.kind {
+            EventTimelineItemKind::Local(_) => false,
+            EventTimelineItemKind::Remote(remote_event) => remote_event.is_mentioned,
+        }
+    }
+
     /// Get the encryption information for the event, if any.
     pub fn encryption_info(&self) -> Option<&EncryptionInfo> {
         match &self.kind {
@@ -309,6 +365,22 @@ impl EventTimelineItem {
         }
     }
 
+    /// Get the shield that should decorate this event, if any.
+    ///
+    /// The shield warns about the authenticity of the event, for example when
+    /// it was sent from a device that isn't verified by its owner. This is
+    /// `None` for events that were not encrypted.
+    ///
+    /// # Arguments
+    ///
+    /// * `strict` - Whether to use the strict ruleset, which also warns about
+    ///   events sent by users that we didn't verify.
+    pub fn get_shield(&self, strict: bool) -> Option<ShieldState> {
+        let state = &self.encryption_info()?.verification_state;
+
+        Some(if strict { state.to_shield_state_strict() } else { state.to_shield_state_lax() })
+    }
+
     /// Get the raw JSON representation of the initial event (the one that
     /// caused this timeline item to be created).
     ///
@@ -370,6 +442,15 @@ impl EventTimelineItem {
         new
     }
 
+    /// Clone the current event item, and update its reactions.
+    pub(super) fn with_reactions(&self, reactions: BundledReactions) -> Self {
+        let kind = match &self.kind {
+            EventTimelineItemKind::Local(l) => l.with_reactions(reactions).into(),
+            EventTimelineItemKind::Remote(r) => r.with_reactions(reactions).into(),
+        };
+        self.with_kind(kind)
+    }
+
     /// Clone the current event item, and update its `sender_profile`.
     pub(super) fn with_sender_profile(&self, sender_profile: TimelineDetails<Profile>) -> Self {
         Self { sender_profile, ..self.clone() }
@@ -378,7 +459,9 @@ impl EventTimelineItem {
     pub(super) fn redact(&self, room_version: &RoomVersionId) -> Self {
         let content = self.content.redact(room_version);
         let kind = match &self.kind {
-            EventTimelineItemKind::Local(l) => EventTimelineItemKind::Local(l.clone()),
+            EventTimelineItemKind::Local(l) => {
+                EventTimelineItemKind::Local(l.with_reactions(BundledReactions::default()))
+            }
             EventTimelineItemKind::Remote(r) => EventTimelineItemKind::Remote(r.redact()),
         };
         Self {
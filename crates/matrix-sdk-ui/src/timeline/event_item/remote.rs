@@ -42,6 +42,9 @@ pub(in crate::timeline) struct RemoteEventTimelineItem {
     pub is_own: bool,
     /// Whether the item should be highlighted in the timeline.
     pub is_highlighted: bool,
+    /// Whether the logged-in user is mentioned in the intentional mentions of
+    /// the event.
+    pub is_mentioned: bool,
     /// Encryption information.
     pub encryption_info: Option<EncryptionInfo>,
     /// JSON of the original event.
@@ -117,6 +120,7 @@ impl fmt::Debug for RemoteEventTimelineItem {
             original_json: _,
             latest_edit_json: _,
             is_highlighted,
+            is_mentioned,
             origin,
         } = self;
 
@@ -126,6 +130,7 @@ impl fmt::Debug for RemoteEventTimelineItem {
             .field("read_receipts", read_receipts)
             .field("is_own", is_own)
             .field("is_highlighted", is_highlighted)
+            .field("is_mentioned", is_mentioned)
             .field("encryption_info", encryption_info)
             .field("origin", origin)
             .finish_non_exhaustive()
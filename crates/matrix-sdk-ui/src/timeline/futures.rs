@@ -3,13 +3,33 @@ use std::{
     future::{Future, IntoFuture},
     path::Path,
     pin::Pin,
+    sync::Arc,
 };
 
 use eyeball::{SharedObservable, Subscriber};
+use futures_util::pin_mut;
 use matrix_sdk::{attachment::AttachmentConfig, TransmissionProgress};
 use mime::Mime;
+use ruma::{
+    events::{
+        room::{
+            message::{
+                AudioMessageEventContent, FileMessageEventContent, ImageMessageEventContent,
+                MessageType, RoomMessageEventContent, VideoMessageEventContent,
+            },
+            MediaSource,
+        },
+        AnyMessageLikeEventContent,
+    },
+    OwnedMxcUri, OwnedTransactionId, TransactionId,
+};
+use tokio::select;
+use tracing::warn;
+
+use super::{inner::TimelineInner, queue::forget_local_message, Error, EventSendState, Timeline};
 
-use super::{Error, Timeline};
+/// The server name of the placeholder URIs of the media of local echoes.
+const LOCAL_MEDIA_SERVER_NAME: &str = "send-queue.localhost";
 
 pub struct SendAttachment<'a> {
     timeline: &'a Timeline,
@@ -46,22 +66,158 @@ impl<'a> IntoFuture for SendAttachment<'a> {
 
     fn into_future(self) -> Self::IntoFuture {
         let Self { timeline, url, mime_type, config, send_progress } = self;
-        Box::pin(async move {
-            let body = Path::new(&url)
-                .file_name()
-                .ok_or(Error::InvalidAttachmentFileName)?
-                .to_str()
-                .expect("path was created from UTF-8 string, hence filename part is UTF-8 too");
-            let data = fs::read(&url).map_err(|_| Error::InvalidAttachmentData)?;
-
-            timeline
-                .room()
-                .send_attachment(body, &mime_type, data, config)
-                .with_send_progress_observable(send_progress)
-                .await
-                .map_err(|_| Error::FailedSendingAttachment)?;
+        Box::pin(send_local_attachment(
+            timeline.inner.clone(),
+            TransactionId::new(),
+            url,
+            mime_type,
+            config,
+            send_progress,
+        ))
+    }
+}
+
+/// Add a local echo for the attachment at the given URL, upload it and send it.
+///
+/// The attachment is kept in the persistent send queue of the room until it
+/// was sent, or until sending it failed for another reason than the homeserver
+/// being unreachable.
+pub(super) async fn send_local_attachment(
+    inner: TimelineInner,
+    txn_id: OwnedTransactionId,
+    url: String,
+    mime_type: Mime,
+    config: AttachmentConfig,
+    send_progress: SharedObservable<TransmissionProgress>,
+) -> Result<(), Error> {
+    let result =
+        send_attachment_with_local_echo(&inner, &txn_id, url, &mime_type, config, send_progress)
+            .await;
+
+    if matches!(result, Err(Error::InvalidAttachmentFileName | Error::InvalidAttachmentData)) {
+        // The file can't be read, there is no point in trying again later.
+        forget_local_message(inner.room(), &txn_id).await;
+    }
 
+    result
+}
+
+async fn send_attachment_with_local_echo(
+    inner: &TimelineInner,
+    txn_id: &TransactionId,
+    url: String,
+    mime_type: &Mime,
+    config: AttachmentConfig,
+    send_progress: SharedObservable<TransmissionProgress>,
+) -> Result<(), Error> {
+    let body = Path::new(&url)
+        .file_name()
+        .ok_or(Error::InvalidAttachmentFileName)?
+        .to_str()
+        .expect("path was created from UTF-8 string, hence filename part is UTF-8 too");
+    let data = fs::read(&url).map_err(|_| Error::InvalidAttachmentData)?;
+
+    let content = local_echo_content(body, mime_type, txn_id);
+    inner.handle_local_event(txn_id.to_owned(), content).await;
+    inner.update_event_send_state(txn_id, EventSendState::Sending { progress: None }).await;
+
+    let room = inner.room();
+    let send_queue = match room.send_queue().await {
+        Ok(send_queue) => {
+            if let Err(error) =
+                send_queue.push_attachment(txn_id.to_owned(), url.clone(), mime_type).await
+            {
+                warn!("Failed to persist attachment: {error}");
+            }
+            Some(send_queue)
+        }
+        Err(error) => {
+            warn!("Failed to load the send queue: {error}");
+            None
+        }
+    };
+
+    let mut progress = send_progress.subscribe();
+    let config = config.txn_id(txn_id);
+    let send_once = || {
+        room.send_attachment(body, mime_type, data.clone(), config.clone())
+            .with_send_progress_observable(send_progress.clone())
+            .into_future()
+    };
+
+    // Like messages, attachments are retried while the homeserver can't be
+    // reached, and removed from the persistent send queue once they were sent
+    // or sending them failed for another reason.
+    let send = async {
+        match &send_queue {
+            Some(send_queue) => send_queue.send_with_retry(txn_id, send_once).await,
+            None => send_once().await,
+        }
+    };
+    pin_mut!(send);
+
+    // Report the progress until the attachment is sent.
+    let result = loop {
+        select! {
+            result = &mut send => break result,
+            Some(progress) = progress.next() => {
+                let send_state = EventSendState::Sending { progress: Some(progress) };
+                inner.update_event_send_state(txn_id, send_state).await;
+            }
+        }
+    };
+
+    match result {
+        Ok(response) => {
+            let send_state = EventSendState::Sent { event_id: response.event_id };
+            inner.update_event_send_state(txn_id, send_state).await;
             Ok(())
-        })
+        }
+        // The local echo was discarded.
+        Err(matrix_sdk::Error::SendAborted) => Err(Error::FailedSendingAttachment),
+        Err(error) => {
+            let send_state = EventSendState::SendingFailed { error: Arc::new(error) };
+            inner.update_event_send_state(txn_id, send_state).await;
+            Err(Error::FailedSendingAttachment)
+        }
     }
 }
+
+/// The content of the local echo of an attachment.
+///
+/// The media is not uploaded yet, so it uses a placeholder URI.
+fn local_echo_content(
+    body: &str,
+    mime_type: &Mime,
+    txn_id: &TransactionId,
+) -> AnyMessageLikeEventContent {
+    let url = OwnedMxcUri::from(format!("mxc://{LOCAL_MEDIA_SERVER_NAME}/{txn_id}"));
+    let body = body.to_owned();
+
+    let msgtype = match mime_type.type_() {
+        mime::IMAGE => MessageType::Image(ImageMessageEventContent::plain(body, url)),
+        mime::AUDIO => MessageType::Audio(AudioMessageEventContent::plain(body, url)),
+        mime::VIDEO => MessageType::Video(VideoMessageEventContent::plain(body, url)),
+        _ => MessageType::File(FileMessageEventContent::plain(body, url)),
+    };
+
+    RoomMessageEventContent::new(msgtype).into()
+}
+
+/// Whether the given message is the local echo of an attachment, whose media
+/// is not uploaded.
+pub(super) fn is_local_echo_attachment(msgtype: &MessageType) -> bool {
+    let source = match msgtype {
+        MessageType::Image(content) => &content.source,
+        MessageType::Audio(content) => &content.source,
+        MessageType::Video(content) => &content.source,
+        MessageType::File(content) => &content.source,
+        _ => return false,
+    };
+
+    matches!(
+        source,
+        MediaSource::Plain(uri)
+            if uri.server_name().map_or(false, |s| s.as_str() == LOCAL_MEDIA_SERVER_NAME)
+    )
+}
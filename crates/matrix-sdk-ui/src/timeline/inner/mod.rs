@@ -12,14 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(feature = "e2e-encryption")]
-use std::collections::BTreeSet;
-use std::{fmt, sync::Arc};
+#[cfg(feature = "backups")]
+use std::sync::Mutex as StdMutex;
+use std::{collections::BTreeSet, fmt, sync::Arc};
 
 use async_rx::StreamExt as _;
 use eyeball_im::{ObservableVectorEntry, VectorDiff, VectorSubscriber};
 use eyeball_im_util::{FilterMapVectorSubscriber, VectorExt};
 use futures_core::Stream;
+use futures_util::StreamExt as _;
 use imbl::Vector;
 use itertools::Itertools;
 #[cfg(all(test, feature = "e2e-encryption"))]
@@ -40,12 +41,17 @@ use ruma::{
         reaction::ReactionEventContent,
         receipt::{Receipt, ReceiptThread, ReceiptType},
         relation::Annotation,
-        room::redaction::RoomRedactionEventContent,
+        room::{
+            message::RoomMessageEventContentWithoutRelation, redaction::RoomRedactionEventContent,
+        },
+        typing::TypingEventContent,
         AnyMessageLikeEventContent, AnyRoomAccountDataEvent, AnySyncEphemeralRoomEvent,
         AnySyncTimelineEvent,
     },
-    EventId, OwnedEventId, OwnedTransactionId, TransactionId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
+    TransactionId, UserId,
 };
+use tokio::sync::mpsc::WeakSender;
 use tracing::{debug, error, field::debug, info, instrument, trace, warn};
 #[cfg(feature = "e2e-encryption")]
 use tracing::{field, info_span, Instrument as _};
@@ -54,25 +60,36 @@ use tracing::{field, info_span, Instrument as _};
 use super::traits::Decryptor;
 use super::{
     event_handler::TimelineItemPosition,
-    event_item::EventItemIdentifier,
+    event_item::{EventItemIdentifier, RemoteEventOrigin},
+    futures::is_local_echo_attachment,
     item::timeline_item,
-    reactions::ReactionToggleResult,
+    queue::QueueRequest,
+    reactions::{ReactionSenderData, ReactionToggleResult},
     traits::RoomDataProvider,
     util::{compare_events_positions, rfind_event_by_id, rfind_event_item, RelativePosition},
-    AnnotationKey, EventSendState, EventTimelineItem, InReplyToDetails, Message, Profile,
-    RepliedToEvent, TimelineDetails, TimelineItem, TimelineItemContent, TimelineItemKind,
+    AnnotationKey, CollapsedStateEvents, EventSendState, EventTimelineItem, InReplyToDetails,
+    Message, Profile, RepliedToEvent, TimelineDetails, TimelineItem, TimelineItemContent,
+    TimelineItemKind,
 };
 
 mod state;
 
 pub(super) use self::state::TimelineInnerState;
-use self::state::{TimelineInnerStateLock, TimelineInnerStateLockGuard};
+#[cfg(feature = "e2e-encryption")]
+use self::state::UtdCauseResolver;
+use self::state::{
+    TimelineInnerStateBatchGuard, TimelineInnerStateLock, TimelineInnerStateLockGuard, UtdCauses,
+};
 
 #[derive(Clone, Debug)]
 pub(super) struct TimelineInner<P: RoomDataProvider = Room> {
     state: TimelineInnerStateLock,
     room_data_provider: P,
     settings: TimelineInnerSettings,
+    /// The session IDs of the room keys that were already requested from the
+    /// key backup.
+    #[cfg(feature = "backups")]
+    backup_requested_sessions: Arc<StdMutex<BTreeSet<String>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -98,6 +115,11 @@ pub(super) struct TimelineInnerSettings {
     pub(super) track_read_receipts: bool,
     pub(super) event_filter: Arc<TimelineEventFilterFn>,
     pub(super) add_failed_to_parse: bool,
+    pub(super) collapse_state_events: bool,
+    pub(super) show_typing_users: bool,
+    /// The sender of the message-sending loop, given to local echoes so they
+    /// can be acted upon without keeping the loop alive.
+    pub(super) queue_sender: Option<WeakSender<QueueRequest>>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -106,6 +128,8 @@ impl fmt::Debug for TimelineInnerSettings {
         f.debug_struct("TimelineInnerSettings")
             .field("track_read_receipts", &self.track_read_receipts)
             .field("add_failed_to_parse", &self.add_failed_to_parse)
+            .field("collapse_state_events", &self.collapse_state_events)
+            .field("show_typing_users", &self.show_typing_users)
             .finish_non_exhaustive()
     }
 }
@@ -116,6 +140,9 @@ impl Default for TimelineInnerSettings {
             track_read_receipts: false,
             event_filter: Arc::new(|_| true),
             add_failed_to_parse: true,
+            collapse_state_events: false,
+            show_typing_users: false,
+            queue_sender: None,
         }
     }
 }
@@ -129,6 +156,8 @@ impl<P: RoomDataProvider> TimelineInner<P> {
             state: TimelineInnerStateLock::new(state),
             room_data_provider,
             settings: TimelineInnerSettings::default(),
+            #[cfg(feature = "backups")]
+            backup_requested_sessions: Default::default(),
         }
     }
 
@@ -163,6 +192,13 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         (items, stream)
     }
 
+    /// Start a batch of updates, whose changes are sent at once to the
+    /// subscribers of [`Self::subscribe_batched()`] when the returned guard
+    /// is dropped.
+    pub(super) fn start_batch(&self) -> TimelineInnerStateBatchGuard {
+        self.state.start_batch()
+    }
+
     pub(super) async fn subscribe_filter_map<U, F>(
         &self,
         f: F,
@@ -317,14 +353,19 @@ impl<P: RoomDataProvider> TimelineInner<P> {
 
         debug!("Adding {} initial events", events.len());
 
+        let utd_causes =
+            UtdCauses::load(&self.room_data_provider, events.iter().map(|event| &event.event))
+                .await;
+
         let mut state = self.state.lock().await;
         for event in events {
             state
                 .handle_remote_event(
                     event,
-                    TimelineItemPosition::End { from_cache: true },
+                    TimelineItemPosition::End { origin: RemoteEventOrigin::Cache },
                     &self.room_data_provider,
                     &self.settings,
+                    &utd_causes,
                 )
                 .await;
         }
@@ -337,8 +378,31 @@ impl<P: RoomDataProvider> TimelineInner<P> {
 
     #[instrument(skip_all)]
     pub(super) async fn handle_joined_room_update(&self, update: JoinedRoom) {
+        // Load the profiles before locking the state, to not block the timeline
+        // while they are fetched.
+        let mut typing_users = None;
+        if self.settings.show_typing_users {
+            let content =
+                update.ephemeral.iter().rev().find_map(|raw_event| match raw_event.deserialize() {
+                    Ok(AnySyncEphemeralRoomEvent::Typing(ev)) => Some(ev.content),
+                    _ => None,
+                });
+            if let Some(content) = content {
+                typing_users = Some(self.load_typing_users(content).await);
+            }
+        }
+
+        let utd_causes = self.load_utd_causes(&update.timeline.events).await;
+
         let mut state = self.state.lock().await;
-        state.handle_sync_timeline(update.timeline, &self.room_data_provider, &self.settings).await;
+        state
+            .handle_sync_timeline(
+                update.timeline,
+                &self.room_data_provider,
+                &self.settings,
+                &utd_causes,
+            )
+            .await;
 
         trace!("Handling account data");
         for raw_event in update.account_data {
@@ -368,25 +432,61 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                 }
             }
         }
+
+        if let Some(typing_users) = typing_users {
+            state.handle_typing(typing_users);
+        }
+    }
+
+    /// Load the profiles of the other members that are typing.
+    async fn load_typing_users(&self, content: TypingEventContent) -> Vec<Profile> {
+        let own_user_id = self.room_data_provider.own_user_id();
+        let mut typing_users = Vec::with_capacity(content.user_ids.len());
+
+        for user_id in content.user_ids.iter().filter(|id| *id != own_user_id) {
+            // Keep users without a known profile, so the number of typing users
+            // is still right.
+            let profile = self.room_data_provider.profile(user_id).await.unwrap_or(Profile {
+                display_name: None,
+                display_name_ambiguous: false,
+                avatar_url: None,
+            });
+            typing_users.push(profile);
+        }
+
+        typing_users
     }
 
     pub(super) async fn handle_sync_timeline(&self, timeline: Timeline) {
+        let utd_causes = self.load_utd_causes(&timeline.events).await;
         self.state
             .lock()
             .await
-            .handle_sync_timeline(timeline, &self.room_data_provider, &self.settings)
+            .handle_sync_timeline(timeline, &self.room_data_provider, &self.settings, &utd_causes)
             .await;
     }
 
     #[cfg(test)]
     pub(super) async fn handle_live_event(&self, event: SyncTimelineEvent) {
+        let utd_causes = self.load_utd_causes(std::slice::from_ref(&event)).await;
         self.state
             .lock()
             .await
-            .handle_live_event(event, &self.room_data_provider, &self.settings)
+            .handle_live_event(event, &self.room_data_provider, &self.settings, &utd_causes)
             .await;
     }
 
+    /// Load the reasons why the given events couldn't be decrypted, before
+    /// locking the state.
+    async fn load_utd_causes(&self, events: &[SyncTimelineEvent]) -> UtdCauses {
+        UtdCauses::load(&self.room_data_provider, events.iter().map(|event| &event.event)).await
+    }
+
+    async fn load_paginated_utd_causes(&self, events: &[TimelineEvent]) -> UtdCauses {
+        UtdCauses::load(&self.room_data_provider, events.iter().map(|event| event.event.cast_ref()))
+            .await
+    }
+
     /// Handle the creation of a new local event.
     #[instrument(skip_all)]
     pub(super) async fn handle_local_event(
@@ -457,7 +557,9 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                     return;
                 }
 
-                if idx == state.items.len() && state.items[idx - 1].is_day_divider() {
+                let is_last_event =
+                    state.items.get(idx).map_or(true, |item| item.is_typing_users());
+                if is_last_event && state.items[idx - 1].is_day_divider() {
                     // The day divider may have been added for this local echo, remove it and let
                     // the next message decide whether it's required or not.
                     state.items.remove(idx - 1);
@@ -494,11 +596,14 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         }
 
         let is_error = matches!(send_state, EventSendState::SendingFailed { .. });
+        // Attachments are sent outside of the message-sending queue, so their
+        // failure doesn't pause it.
+        let is_queued = !matches!(local_item.send_state, EventSendState::Sending { .. });
 
         let new_item = item.with_inner_kind(local_item.with_send_state(send_state));
         state.items.set(idx, new_item);
 
-        if is_error {
+        if is_error && is_queued {
             // When there is an error, sending further messages is paused. This
             // should be reflected in the timeline, so we set all other pending
             // events to cancelled.
@@ -564,33 +669,240 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         Ok(follow_up_action)
     }
 
+    /// Wait for the local echo with the given transaction ID to be sent.
+    ///
+    /// Returns the ID of the event, or `None` if the local echo failed to be
+    /// sent or isn't in the timeline.
+    pub(super) async fn wait_for_local_echo_sent(
+        &self,
+        txn_id: &TransactionId,
+    ) -> Option<OwnedEventId> {
+        let unique_id = {
+            let state = self.state.lock().await;
+            let (_, item) =
+                rfind_event_item(&state.items, |it| it.transaction_id() == Some(txn_id))?;
+            item.internal_id
+        };
+
+        self.wait_for_event_id(unique_id).await
+    }
+
+    /// Wait for the event of the item with the given unique ID to get an ID,
+    /// either because the local echo was sent or because it was replaced by
+    /// its remote echo.
+    ///
+    /// Returns `None` if the local echo failed to be sent or was removed.
+    async fn wait_for_event_id(&self, unique_id: u64) -> Option<OwnedEventId> {
+        let (mut items, mut stream) = self.subscribe().await;
+
+        loop {
+            let item = items.iter().find(|it| it.unique_id() == unique_id)?.as_event()?;
+            match item.send_state() {
+                None => return item.event_id().map(ToOwned::to_owned),
+                Some(EventSendState::Sent { event_id }) => return Some(event_id.clone()),
+                Some(EventSendState::SendingFailed { .. } | EventSendState::Cancelled) => {
+                    return None;
+                }
+                Some(_) => {}
+            }
+
+            // Stop if the timeline was dropped.
+            stream.next().await?;
+            items = self.items().await;
+        }
+    }
+
+    /// Replace the content of the local echo with the given transaction ID,
+    /// unless it was already sent.
+    ///
+    /// `edited` is whether the new content is sent as an edit, rather than
+    /// being sent in place of the original content.
+    ///
+    /// Returns the send state of the local echo.
+    pub(super) async fn edit_local_echo(
+        &self,
+        txn_id: &TransactionId,
+        new_content: &RoomMessageEventContentWithoutRelation,
+        edited: bool,
+    ) -> Result<EventSendState, super::Error> {
+        let mut state = self.state.lock().await;
+
+        let (idx, item) = rfind_event_item(&state.items, |it| it.transaction_id() == Some(txn_id))
+            .ok_or(super::Error::LocalEchoNotInTimeline)?;
+        let send_state = item.send_state().cloned().ok_or(super::Error::LocalEchoNotInTimeline)?;
+
+        if let EventSendState::Sent { .. } = send_state {
+            return Ok(send_state);
+        }
+
+        let TimelineItemContent::Message(msg) = item.content() else {
+            return Err(super::Error::UnsupportedEvent);
+        };
+        if is_local_echo_attachment(msg.msgtype()) {
+            return Err(super::Error::UnsupportedEvent);
+        }
+
+        let new_content = TimelineItemContent::Message(Message {
+            msgtype: new_content.msgtype.clone(),
+            in_reply_to: msg.in_reply_to.clone(),
+            edited,
+            mentions: new_content.mentions.clone(),
+        });
+
+        trace!("Applying edit to local echo");
+        let new_item = timeline_item(item.with_content(new_content, None), item.internal_id);
+        state.items.set(idx, new_item);
+
+        Ok(send_state)
+    }
+
+    /// Redact the local echo with the given transaction ID, unless it was
+    /// already sent or failed to be sent.
+    ///
+    /// Returns the send state of the local echo.
+    pub(super) async fn redact_local_echo(
+        &self,
+        txn_id: &TransactionId,
+    ) -> Result<EventSendState, super::Error> {
+        let mut state = self.state.lock().await;
+
+        let (idx, item) = rfind_event_item(&state.items, |it| it.transaction_id() == Some(txn_id))
+            .ok_or(super::Error::LocalEchoNotInTimeline)?;
+        let send_state = item.send_state().cloned().ok_or(super::Error::LocalEchoNotInTimeline)?;
+
+        if let EventSendState::NotSentYet | EventSendState::Sending { .. } = send_state {
+            trace!("Redacting local echo");
+            let new_item = timeline_item(item.redact(&state.room_version), item.internal_id);
+            state.items.set(idx, new_item);
+        }
+
+        Ok(send_state)
+    }
+
+    /// Add a local echo of the reaction with the given transaction ID and key
+    /// to the local echo with the given transaction ID, unless it was already
+    /// sent or failed to be sent.
+    ///
+    /// Returns the send state of the local echo.
+    pub(super) async fn add_local_echo_reaction(
+        &self,
+        txn_id: &TransactionId,
+        reaction_txn_id: &TransactionId,
+        key: &str,
+    ) -> Result<EventSendState, super::Error> {
+        let mut state = self.state.lock().await;
+
+        let (idx, item) = rfind_event_item(&state.items, |it| it.transaction_id() == Some(txn_id))
+            .ok_or(super::Error::LocalEchoNotInTimeline)?;
+        let send_state = item.send_state().cloned().ok_or(super::Error::LocalEchoNotInTimeline)?;
+
+        if let EventSendState::NotSentYet | EventSendState::Sending { .. } = send_state {
+            // Reactions on redacted events are ignored.
+            if item.content().is_redacted() {
+                return Err(super::Error::FailedToToggleReaction);
+            }
+
+            let mut reactions = item.reactions().clone();
+            reactions.entry(key.to_owned()).or_default().0.insert(
+                EventItemIdentifier::TransactionId(reaction_txn_id.to_owned()),
+                ReactionSenderData {
+                    sender_id: self.room_data_provider.own_user_id().to_owned(),
+                    timestamp: MilliSecondsSinceUnixEpoch::now(),
+                },
+            );
+
+            trace!("Adding reaction to local echo");
+            let new_item = timeline_item(item.with_reactions(reactions), item.internal_id);
+            state.items.set(idx, new_item);
+        }
+
+        Ok(send_state)
+    }
+
+    /// Remove the local echo of the reaction with the given transaction ID and
+    /// key from the event with the given transaction ID or event ID.
+    ///
+    /// This is a no-op if the reaction isn't in the timeline.
+    pub(super) async fn remove_local_echo_reaction(
+        &self,
+        txn_id: &TransactionId,
+        event_id: Option<&EventId>,
+        reaction_txn_id: &TransactionId,
+        key: &str,
+    ) {
+        let mut state = self.state.lock().await;
+
+        let Some((idx, item)) = rfind_event_item(&state.items, |it| {
+            it.transaction_id() == Some(txn_id) || event_id.is_some() && it.event_id() == event_id
+        }) else {
+            return;
+        };
+
+        let mut reactions = item.reactions().clone();
+        let Some(group) = reactions.get_mut(key) else {
+            return;
+        };
+        let reaction_id = EventItemIdentifier::TransactionId(reaction_txn_id.to_owned());
+        if group.0.shift_remove(&reaction_id).is_none() {
+            return;
+        }
+        if group.0.is_empty() {
+            reactions.shift_remove(key);
+        }
+
+        trace!("Removing reaction from local echo");
+        let new_item = timeline_item(item.with_reactions(reactions), item.internal_id);
+        state.items.set(idx, new_item);
+    }
+
+    /// Prepare the local echo with the given transaction ID to be sent again,
+    /// after sending it failed.
+    ///
+    /// The content to send is computed from the content of the local echo with
+    /// `retry_content`. The local echo is only moved to the end of the
+    /// timeline and marked as not sent yet if that succeeds.
     pub(super) async fn prepare_retry(
         &self,
         txn_id: &TransactionId,
-    ) -> Option<TimelineItemContent> {
+        retry_content: impl FnOnce(
+            &TimelineItemContent,
+        ) -> Result<AnyMessageLikeEventContent, super::Error>,
+    ) -> Result<AnyMessageLikeEventContent, super::Error> {
         let mut state = self.state.lock().await;
 
-        let (idx, item) = rfind_event_item(&state.items, |it| it.transaction_id() == Some(txn_id))?;
-        let local_item = item.as_local()?;
+        let (idx, item) = rfind_event_item(&state.items, |it| it.transaction_id() == Some(txn_id))
+            .ok_or(super::Error::RetryEventNotInTimeline)?;
+        let local_item = item.as_local().ok_or(super::Error::RetryEventNotInTimeline)?;
 
         match &local_item.send_state {
-            EventSendState::NotSentYet => {
+            EventSendState::NotSentYet | EventSendState::Sending { .. } => {
                 warn!("Attempted to retry the sending of an item that is already pending");
-                return None;
+                return Err(super::Error::RetryEventNotInTimeline);
             }
             EventSendState::Sent { .. } => {
                 warn!("Attempted to retry the sending of an item that has already succeeded");
-                return None;
+                return Err(super::Error::RetryEventNotInTimeline);
             }
             EventSendState::SendingFailed { .. } | EventSendState::Cancelled => {}
         }
 
+        let content = retry_content(&item.content)?;
+
         let new_item = item.with_inner_kind(local_item.with_send_state(EventSendState::NotSentYet));
-        let content = item.content.clone();
         state.items.remove(idx);
-        state.items.push_back(new_item);
+        state.with_typing_users_item_detached(|state| state.items.push_back(new_item));
 
-        Some(content)
+        Ok(content)
+    }
+
+    /// Get the send state of the local echo with the given transaction ID.
+    pub(super) async fn local_echo_send_state(
+        &self,
+        txn_id: &TransactionId,
+    ) -> Option<EventSendState> {
+        let state = self.state.lock().await;
+        let (_, item) = rfind_event_item(&state.items, |it| it.transaction_id() == Some(txn_id))?;
+        item.send_state().cloned()
     }
 
     pub(super) async fn discard_local_echo(&self, txn_id: &TransactionId) -> bool {
@@ -605,6 +917,67 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         }
     }
 
+    /// Remove the items of the events sent by the given users, along with the
+    /// day dividers that are left without any item after them.
+    pub(super) async fn remove_events_from_senders(&self, senders: &BTreeSet<OwnedUserId>) {
+        let mut state = self.state.lock().await;
+        remove_event_items(&mut state, |event| senders.contains(event.sender()));
+    }
+
+    /// Set the point in time before which remote events are not kept in the
+    /// timeline, and remove the ones that are already in the timeline.
+    pub(super) async fn set_history_cutoff(&self, cutoff: Option<MilliSecondsSinceUnixEpoch>) {
+        let mut state = self.state.lock().await;
+        state.history_cutoff = cutoff;
+
+        if let Some(cutoff) = cutoff {
+            remove_event_items(&mut state, |event| {
+                !event.is_local_echo() && event.timestamp() < cutoff
+            });
+        }
+    }
+
+    /// Whether any of the given events is older than the current history
+    /// cutoff, meaning that there is no point in paginating further.
+    pub(super) async fn is_past_history_cutoff(&self, events: &[TimelineEvent]) -> bool {
+        let Some(cutoff) = self.state.lock().await.history_cutoff else {
+            return false;
+        };
+
+        events.iter().any(|event| {
+            event
+                .event
+                .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                .ok()
+                .flatten()
+                .is_some_and(|ts| ts < cutoff)
+        })
+    }
+
+    pub(super) async fn set_state_events_expanded(
+        &self,
+        event_id: &EventId,
+        expanded: bool,
+    ) -> bool {
+        let mut state = self.state.lock().await;
+        let Some((idx, item)) = rfind_event_by_id(&state.items, event_id) else {
+            return false;
+        };
+        let Some(group) = item.content().as_collapsed_state_events() else {
+            return false;
+        };
+        if group.expanded == expanded {
+            return true;
+        }
+
+        let group = CollapsedStateEvents { expanded, ..group.clone() };
+        let new_item = item.with_content(TimelineItemContent::CollapsedStateEvents(group), None);
+        let internal_id = item.internal_id;
+        state.items.set(idx, timeline_item(new_item, internal_id));
+
+        true
+    }
+
     /// Handle a list of back-paginated events.
     ///
     /// Returns the number of timeline updates that were made. Short-circuits
@@ -615,6 +988,7 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         &self,
         events: Vec<TimelineEvent>,
     ) -> Option<HandleManyEventsResult> {
+        let utd_causes = self.load_paginated_utd_causes(&events).await;
         let mut state = self.state.lock().await;
 
         let mut total = HandleManyEventsResult::default();
@@ -625,6 +999,38 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                     TimelineItemPosition::Start,
                     &self.room_data_provider,
                     &self.settings,
+                    &utd_causes,
+                )
+                .await;
+
+            total.items_added = total.items_added.checked_add(res.item_added as u16)?;
+            total.items_updated = total.items_updated.checked_add(res.items_updated)?;
+        }
+
+        Some(total)
+    }
+
+    /// Handle a list of forward-paginated events.
+    ///
+    /// Like [`Self::handle_back_paginated_events`], but the events are added
+    /// to the end of the timeline, in the order in which they are given.
+    #[instrument(skip_all)]
+    pub(super) async fn handle_forward_paginated_events(
+        &self,
+        events: Vec<TimelineEvent>,
+    ) -> Option<HandleManyEventsResult> {
+        let utd_causes = self.load_paginated_utd_causes(&events).await;
+        let mut state = self.state.lock().await;
+
+        let mut total = HandleManyEventsResult::default();
+        for event in events {
+            let res = state
+                .handle_remote_event(
+                    event.into(),
+                    TimelineItemPosition::End { origin: RemoteEventOrigin::Pagination },
+                    &self.room_data_provider,
+                    &self.settings,
+                    &utd_causes,
                 )
                 .await;
 
@@ -635,6 +1041,29 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         Some(total)
     }
 
+    /// Replace the items of the timeline with the given events, in
+    /// chronological order.
+    #[instrument(skip_all)]
+    pub(super) async fn replace_with_events(&self, events: Vec<TimelineEvent>) {
+        let utd_causes = self.load_paginated_utd_causes(&events).await;
+        let mut state = self.state.lock().await;
+
+        trace!("Replacing the timeline with {} events", events.len());
+        state.clear();
+
+        for event in events {
+            state
+                .handle_remote_event(
+                    event.into(),
+                    TimelineItemPosition::End { origin: RemoteEventOrigin::Pagination },
+                    &self.room_data_provider,
+                    &self.settings,
+                    &utd_causes,
+                )
+                .await;
+        }
+    }
+
     pub(super) async fn set_fully_read_event(&self, fully_read_event_id: OwnedEventId) {
         self.state.lock().await.set_fully_read_event(fully_read_event_id)
     }
@@ -776,6 +1205,8 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                         TimelineItemPosition::Update(idx),
                         &room_data_provider,
                         &settings,
+                        // Only the events that could be decrypted are handled.
+                        &UtdCauses::default(),
                     )
                     .await;
 
@@ -788,6 +1219,71 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         });
     }
 
+    /// Find out again why the events encrypted with the given session
+    /// couldn't be decrypted, after the sender told us that they withheld the
+    /// room key.
+    #[cfg(feature = "e2e-encryption")]
+    pub(super) async fn update_utd_causes(&self, session_id: &str) {
+        use super::EncryptedMessage;
+
+        // The stores are accessed to find the causes, so don't keep the state
+        // locked in the meantime.
+        let utds: Vec<_> = self
+            .state
+            .lock()
+            .await
+            .items
+            .iter()
+            .filter_map(|item| {
+                let event_item = item.as_event()?;
+                match event_item.content().as_unable_to_decrypt()? {
+                    EncryptedMessage::MegolmV1AesSha2 { session_id: utd_session_id, .. }
+                        if utd_session_id == session_id =>
+                    {
+                        Some((event_item.event_id()?.to_owned(), event_item.timestamp()))
+                    }
+                    EncryptedMessage::MegolmV1AesSha2 { .. }
+                    | EncryptedMessage::OlmV1Curve25519AesSha2 { .. }
+                    | EncryptedMessage::Unknown => None,
+                }
+            })
+            .collect();
+
+        if utds.is_empty() {
+            return;
+        }
+
+        let mut resolver = UtdCauseResolver::new(&self.room_data_provider).await;
+        let mut causes = Vec::with_capacity(utds.len());
+        for (event_id, timestamp) in utds {
+            causes.push((event_id, resolver.resolve(session_id, timestamp).await));
+        }
+
+        let mut state = self.state.lock().await;
+        for (event_id, cause) in causes {
+            let Some((idx, item)) = rfind_event_by_id(&state.items, &event_id) else {
+                continue;
+            };
+            let Some(mut message) = item.content().as_unable_to_decrypt().cloned() else {
+                // The event was decrypted in the meantime.
+                continue;
+            };
+            let EncryptedMessage::MegolmV1AesSha2 { cause: old_cause, .. } = &mut message else {
+                continue;
+            };
+            if *old_cause == cause {
+                continue;
+            }
+
+            trace!(?event_id, ?cause, "Updating the cause of the UTD");
+            *old_cause = cause;
+
+            let new_item = item.with_content(TimelineItemContent::UnableToDecrypt(message), None);
+            let internal_id = item.internal_id;
+            state.items.set(idx, timeline_item(new_item, internal_id));
+        }
+    }
+
     pub(super) async fn set_sender_profiles_pending(&self) {
         self.set_non_ready_sender_profiles(TimelineDetails::Pending).await;
     }
@@ -853,6 +1349,27 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         let own_user_id = self.room_data_provider.own_user_id();
         self.state.lock().await.handle_explicit_read_receipts(receipt_event_content, own_user_id);
     }
+
+    #[cfg(test)]
+    pub(super) async fn handle_typing(&self, typing_event_content: TypingEventContent) {
+        let typing_users = self.load_typing_users(typing_event_content).await;
+        self.state.lock().await.handle_typing(typing_users);
+    }
+
+    #[cfg(test)]
+    pub(super) async fn thread_read_receipt_event_id(
+        &self,
+        user_id: &UserId,
+        thread_root: &EventId,
+    ) -> Option<OwnedEventId> {
+        let state = self.state.lock().await;
+        let (event_id, _) = state
+            .users_thread_read_receipts
+            .get(user_id)?
+            .get(thread_root)?
+            .get(&ReceiptType::Read)?;
+        Some(event_id.clone())
+    }
 }
 
 impl TimelineInner {
@@ -860,6 +1377,68 @@ impl TimelineInner {
         &self.room_data_provider
     }
 
+    /// Download the room keys of the events that couldn't be decrypted from
+    /// the key backup, and retry to decrypt those events once the keys are
+    /// imported.
+    ///
+    /// The room key of a given session is only requested once.
+    #[cfg(feature = "backups")]
+    pub(super) async fn download_missing_room_keys_from_backup(&self) {
+        use super::EncryptedMessage;
+
+        let utd_session_ids: BTreeSet<String> = self
+            .state
+            .lock()
+            .await
+            .items
+            .iter()
+            .filter_map(|item| match item.as_event()?.content().as_unable_to_decrypt()? {
+                EncryptedMessage::MegolmV1AesSha2 { session_id, .. } => Some(session_id.clone()),
+                EncryptedMessage::OlmV1Curve25519AesSha2 { .. } | EncryptedMessage::Unknown => None,
+            })
+            .collect();
+
+        let session_ids: Vec<String> = {
+            let mut requested_sessions = self.backup_requested_sessions.lock().unwrap();
+            utd_session_ids
+                .into_iter()
+                .filter(|session_id| requested_sessions.insert(session_id.clone()))
+                .collect()
+        };
+
+        if session_ids.is_empty() {
+            return;
+        }
+
+        let inner = self.clone();
+        matrix_sdk::executor::spawn(
+            async move {
+                let room = inner.room();
+                let encryption = room.client().encryption();
+
+                // The decryption of the events is also retried when the room
+                // keys are received, but the stream of received room keys
+                // might miss them if the OlmMachine was regenerated.
+                for session_id in session_ids {
+                    match encryption.backup_download_session(room.room_id(), &session_id).await {
+                        Ok(true) => {
+                            let session_ids = BTreeSet::from([session_id]);
+                            inner.retry_event_decryption(room, Some(session_ids)).await;
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            warn!(%session_id, "Failed to download room key from the backup: {e}");
+
+                            // Allow the room key to be requested again later.
+                            inner.backup_requested_sessions.lock().unwrap().remove(&session_id);
+                        }
+                    }
+                }
+            }
+            .instrument(info_span!("download_room_keys_from_backup")),
+        );
+    }
+
     /// Get the current fully-read event.
     pub(super) async fn fully_read_event(&self) -> Option<FullyReadEvent> {
         match self.room().account_data_static().await {
@@ -967,6 +1546,52 @@ impl TimelineInner {
         state.latest_user_read_receipt(user_id, room).await
     }
 
+    pub(super) async fn user_receipt(
+        &self,
+        user_id: &UserId,
+        receipt_type: ReceiptType,
+    ) -> Option<(OwnedEventId, Receipt)> {
+        let state = self.state.lock().await;
+        let room = self.room();
+
+        state.user_receipt(user_id, receipt_type, room).await
+    }
+
+    pub(super) async fn latest_user_read_receipt_timeline_index(
+        &self,
+        user_id: &UserId,
+    ) -> Option<usize> {
+        let state = self.state.lock().await;
+        let room = self.room();
+
+        let (event_id, _) = state.latest_user_read_receipt(user_id, room).await?;
+        let visible_event_id = state.visible_event_id(&event_id);
+
+        rfind_event_by_id(&state.items, visible_event_id).map(|(pos, _)| pos)
+    }
+
+    pub(super) async fn latest_user_thread_read_receipt(
+        &self,
+        user_id: &UserId,
+        thread_root: &EventId,
+    ) -> Option<(OwnedEventId, Receipt)> {
+        let state = self.state.lock().await;
+        let room = self.room();
+
+        state.latest_user_thread_read_receipt(user_id, thread_root, room).await
+    }
+
+    pub(super) async fn thread_unread_count(
+        &self,
+        user_id: &UserId,
+        thread_root: &EventId,
+    ) -> usize {
+        let state = self.state.lock().await;
+        let room = self.room();
+
+        state.thread_unread_count(user_id, thread_root, room).await
+    }
+
     /// Check whether the given receipt should be sent.
     ///
     /// Returns `false` if the given receipt is older than the current one.
@@ -976,7 +1601,11 @@ impl TimelineInner {
         thread: &ReceiptThread,
         event_id: &EventId,
     ) -> bool {
-        // We don't support threaded receipts yet.
+        if let ReceiptThread::Thread(thread_root) = thread {
+            return self.should_send_thread_receipt(receipt_type, thread_root, event_id).await;
+        }
+
+        // We don't support receipts for the main timeline yet.
         if *thread != ReceiptThread::Unthreaded {
             return true;
         }
@@ -1027,6 +1656,42 @@ impl TimelineInner {
         // Let the server handle unknown receipts.
         true
     }
+
+    /// Check whether the given receipt in the given thread should be sent.
+    ///
+    /// Returns `false` if the given receipt is older than the current one in
+    /// the thread.
+    async fn should_send_thread_receipt(
+        &self,
+        receipt_type: &SendReceiptType,
+        thread_root: &EventId,
+        event_id: &EventId,
+    ) -> bool {
+        let own_user_id = self.room().own_user_id();
+        let state = self.state.lock().await;
+        let room = self.room();
+
+        let old_receipt = match receipt_type {
+            SendReceiptType::Read => {
+                state.user_thread_receipt(own_user_id, ReceiptType::Read, thread_root, room).await
+            }
+            SendReceiptType::ReadPrivate => {
+                state.latest_user_thread_read_receipt(own_user_id, thread_root, room).await
+            }
+            // Let the server handle unknown receipts.
+            _ => return true,
+        };
+
+        if let Some((old_event_id, _)) = old_receipt {
+            if let Some(relative_pos) =
+                compare_events_positions(&old_event_id, event_id, &state.items)
+            {
+                return relative_pos == RelativePosition::After;
+            }
+        }
+
+        true
+    }
 }
 
 #[derive(Default)]
@@ -1076,3 +1741,31 @@ async fn fetch_replied_to_event(
     };
     Ok(res)
 }
+
+/// Remove the event items matching the given predicate from the timeline, and
+/// the day dividers that are left without events.
+fn remove_event_items(
+    state: &mut TimelineInnerState,
+    mut predicate: impl FnMut(&EventTimelineItem) -> bool,
+) {
+    let mut idx = state.items.len();
+    while idx > 0 {
+        idx -= 1;
+
+        let item = &state.items[idx];
+        let remove = match item.as_event() {
+            Some(event) => predicate(event),
+            None => {
+                item.is_day_divider()
+                    && state
+                        .items
+                        .get(idx + 1)
+                        .map_or(true, |next| next.is_day_divider() || next.is_typing_users())
+            }
+        };
+
+        if remove {
+            state.items.remove(idx);
+        }
+    }
+}
@@ -16,7 +16,10 @@ use std::{
     collections::HashMap,
     fmt,
     ops::{Deref, DerefMut},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use eyeball::{SharedObservable, Subscriber};
@@ -27,12 +30,16 @@ use ruma::{
     events::{
         receipt::{Receipt, ReceiptType},
         relation::Annotation,
-        room::redaction::RoomRedactionEventContent,
-        AnyMessageLikeEventContent,
+        room::{
+            encrypted::{EncryptedEventScheme, OriginalSyncRoomEncryptedEvent},
+            redaction::RoomRedactionEventContent,
+        },
+        AnyMessageLikeEventContent, AnySyncTimelineEvent, MessageLikeEventType,
     },
     push::Action,
-    MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId, RoomVersionId,
-    UserId,
+    serde::Raw,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
+    RoomVersionId, UserId,
 };
 use tokio::sync::{Mutex, MutexGuard, OwnedMutexGuard};
 use tracing::{debug, error, instrument, trace, warn};
@@ -45,21 +52,118 @@ use crate::{
             update_read_marker, Flow, HandleEventResult, TimelineEventContext,
             TimelineEventHandler, TimelineEventKind, TimelineItemPosition,
         },
-        event_item::EventItemIdentifier,
+        event_item::{EventItemIdentifier, RemoteEventOrigin},
         item::timeline_item,
+        live_location::LiveLocationPendingEvents,
         polls::PollPendingEvents,
         reactions::{ReactionToggleResult, Reactions},
         traits::RoomDataProvider,
-        util::{rfind_event_item, timestamp_to_date},
+        util::{is_user_mentioned, rfind_event_item, timestamp_to_date},
         AnnotationKey, Error as TimelineError, Profile, ReactionSenderData, TimelineItem,
-        TimelineItemKind, VirtualTimelineItem,
+        TimelineItemKind, UtdCause, VirtualTimelineItem,
     },
 };
 
+/// The reasons why the encrypted events of a batch couldn't be decrypted.
+///
+/// Finding them requires to access the stores, so they are loaded before the
+/// timeline state is locked.
+#[derive(Debug, Default)]
+pub(super) struct UtdCauses(HashMap<OwnedEventId, UtdCause>);
+
+impl UtdCauses {
+    /// Load the reasons why the given events couldn't be decrypted, if they
+    /// are encrypted.
+    pub(super) async fn load<'a, P: RoomDataProvider>(
+        room_data_provider: &P,
+        events: impl IntoIterator<Item = &'a Raw<AnySyncTimelineEvent>>,
+    ) -> Self {
+        let mut utds = Vec::new();
+
+        for raw in events {
+            let event_type = raw.get_field::<MessageLikeEventType>("type").ok().flatten();
+            if event_type != Some(MessageLikeEventType::RoomEncrypted) {
+                continue;
+            }
+
+            let Ok(event) = raw.deserialize_as::<OriginalSyncRoomEncryptedEvent>() else {
+                continue;
+            };
+
+            if let EncryptedEventScheme::MegolmV1AesSha2(c) = event.content.scheme {
+                utds.push((event.event_id, c.session_id, event.origin_server_ts));
+            }
+        }
+
+        if utds.is_empty() {
+            return Self::default();
+        }
+
+        let mut resolver = UtdCauseResolver::new(room_data_provider).await;
+        let mut causes = HashMap::with_capacity(utds.len());
+
+        for (event_id, session_id, timestamp) in utds {
+            causes.insert(event_id, resolver.resolve(&session_id, timestamp).await);
+        }
+
+        Self(causes)
+    }
+
+    fn get(&self, event_id: &EventId) -> UtdCause {
+        self.0.get(event_id).cloned().unwrap_or_default()
+    }
+}
+
+/// Finds out why `m.megolm.v1.aes-sha2` events couldn't be decrypted.
+///
+/// The information that is the same for all the events of a batch is only
+/// loaded once.
+pub(super) struct UtdCauseResolver<'a, P: RoomDataProvider> {
+    room_data_provider: &'a P,
+    own_join_ts: Option<MilliSecondsSinceUnixEpoch>,
+    is_backup_missing_decryption_key: Option<bool>,
+}
+
+impl<'a, P: RoomDataProvider> UtdCauseResolver<'a, P> {
+    pub(super) async fn new(room_data_provider: &'a P) -> Self {
+        let own_join_ts = room_data_provider.own_join_timestamp().await;
+        Self { room_data_provider, own_join_ts, is_backup_missing_decryption_key: None }
+    }
+
+    /// Find out why an event encrypted with the given session and sent at the
+    /// given time couldn't be decrypted.
+    pub(super) async fn resolve(
+        &mut self,
+        session_id: &str,
+        timestamp: MilliSecondsSinceUnixEpoch,
+    ) -> UtdCause {
+        if let Some(code) = self.room_data_provider.room_key_withheld_code(session_id).await {
+            return code.into();
+        }
+
+        if self.own_join_ts.is_some_and(|join_ts| timestamp < join_ts) {
+            return UtdCause::SentBeforeJoin;
+        }
+
+        let is_backup_missing_decryption_key = match self.is_backup_missing_decryption_key {
+            Some(missing) => missing,
+            None => {
+                let missing = self.room_data_provider.is_backup_missing_decryption_key().await;
+                *self.is_backup_missing_decryption_key.insert(missing)
+            }
+        };
+        if is_backup_missing_decryption_key {
+            return UtdCause::BackupNotConnected;
+        }
+
+        UtdCause::MissingRoomKey
+    }
+}
+
 #[derive(Clone)]
 pub(in crate::timeline) struct TimelineInnerStateLock {
     inner: Arc<Mutex<TimelineInnerState>>,
-    lock_release_ob: SharedObservable<()>,
+    lock_release_ob: LockReleaseObservable,
 }
 
 impl TimelineInnerStateLock {
@@ -68,7 +172,17 @@ impl TimelineInnerStateLock {
     }
 
     pub(super) fn subscribe_lock_release(&self) -> Subscriber<()> {
-        self.lock_release_ob.subscribe()
+        self.lock_release_ob.inner.subscribe()
+    }
+
+    /// Start a batch of updates.
+    ///
+    /// Until the returned guard is dropped, releasing the lock doesn't notify
+    /// the subscribers, so that all the changes made while handling one
+    /// update, like a sync response, are sent to them at once.
+    pub(super) fn start_batch(&self) -> TimelineInnerStateBatchGuard {
+        self.lock_release_ob.batch_depth.fetch_add(1, Ordering::SeqCst);
+        TimelineInnerStateBatchGuard { lock_release_ob: self.lock_release_ob.clone() }
     }
 
     pub async fn lock(&self) -> TimelineInnerStateLockGuard<'_> {
@@ -99,6 +213,7 @@ pub(in crate::timeline) struct TimelineInnerState {
     next_internal_id: u64,
     pub reactions: Reactions,
     pub poll_pending_events: PollPendingEvents,
+    pub live_location_pending_events: LiveLocationPendingEvents,
     pub fully_read_event: Option<OwnedEventId>,
     /// Whether the fully-read marker item should try to be updated when an
     /// event is added.
@@ -109,11 +224,37 @@ pub(in crate::timeline) struct TimelineInnerState {
     /// User ID => Receipt type => Read receipt of the user of the given
     /// type.
     pub users_read_receipts: HashMap<OwnedUserId, HashMap<ReceiptType, (OwnedEventId, Receipt)>>,
+    /// User ID => Thread root event ID => Receipt type => Read receipt of the
+    /// user of the given type in the given thread.
+    pub users_thread_read_receipts:
+        HashMap<OwnedUserId, HashMap<OwnedEventId, HashMap<ReceiptType, (OwnedEventId, Receipt)>>>,
     /// the local reaction request state that is queued next
     pub reaction_state: IndexMap<AnnotationKey, ReactionState>,
     /// the in flight reaction request state that is ongoing
     pub in_flight_reaction: IndexMap<AnnotationKey, ReactionState>,
     pub room_version: RoomVersionId,
+    /// Event ID of an event hidden by the event filter => Event ID of the
+    /// visible event that precedes it.
+    ///
+    /// Events in a group of state events are mapped to the event of the
+    /// group's item.
+    ///
+    /// Used to place the fully-read marker and read receipts that point to a
+    /// hidden event.
+    pub hidden_events: HashMap<OwnedEventId, OwnedEventId>,
+    /// Events hidden by the event filter that precede all the visible events.
+    ///
+    /// They are added to `hidden_events` when a visible event is added at the
+    /// start of the timeline.
+    pub hidden_events_at_start: Vec<OwnedEventId>,
+    /// The profiles of the other members that are currently typing.
+    ///
+    /// When not empty, they are shown in a virtual item at the end of the
+    /// timeline.
+    typing_users: Vec<Profile>,
+    /// The point in time before which remote events are not kept in the
+    /// timeline, according to the retention policy of the room.
+    pub history_cutoff: Option<MilliSecondsSinceUnixEpoch>,
 }
 
 impl TimelineInnerState {
@@ -126,12 +267,18 @@ impl TimelineInnerState {
             next_internal_id: Default::default(),
             reactions: Default::default(),
             poll_pending_events: Default::default(),
+            live_location_pending_events: Default::default(),
             fully_read_event: Default::default(),
             event_should_update_fully_read_marker: Default::default(),
             users_read_receipts: Default::default(),
+            users_thread_read_receipts: Default::default(),
             reaction_state: Default::default(),
             in_flight_reaction: Default::default(),
             room_version,
+            hidden_events: Default::default(),
+            hidden_events_at_start: Default::default(),
+            typing_users: Default::default(),
+            history_cutoff: Default::default(),
         }
     }
 
@@ -161,34 +308,47 @@ impl TimelineInnerState {
         timeline: Timeline,
         room_data_provider: &P,
         settings: &TimelineInnerSettings,
+        utd_causes: &UtdCauses,
     ) {
         if timeline.limited {
             debug!("Got limited sync response, resetting timeline");
             self.clear();
         }
 
+        // Detach the typing users item only once for all the events.
+        let detached = self.items.last().is_some_and(|item| item.is_typing_users());
+        if detached {
+            self.items.pop_back();
+        }
+
         let num_events = timeline.events.len();
         for (i, event) in timeline.events.into_iter().enumerate() {
             trace!("Handling event {i} out of {num_events}");
-            self.handle_live_event(event, room_data_provider, settings).await;
+            self.handle_live_event(event, room_data_provider, settings, utd_causes).await;
+        }
+
+        if detached {
+            self.items.push_back(TimelineItem::typing_users(self.typing_users.clone()));
         }
     }
 
     /// Handle a live remote event.
     ///
     /// Shorthand for `handle_remote_event` with a `position` of
-    /// `TimelineItemPosition::End { from_cache: false }`.
+    /// `TimelineItemPosition::End { origin: RemoteEventOrigin::Sync }`.
     pub(super) async fn handle_live_event<P: RoomDataProvider>(
         &mut self,
         event: SyncTimelineEvent,
         room_data_provider: &P,
         settings: &TimelineInnerSettings,
+        utd_causes: &UtdCauses,
     ) -> HandleEventResult {
         self.handle_remote_event(
             event,
-            TimelineItemPosition::End { from_cache: false },
+            TimelineItemPosition::End { origin: RemoteEventOrigin::Sync },
             room_data_provider,
             settings,
+            utd_causes,
         )
         .await
     }
@@ -202,6 +362,7 @@ impl TimelineInnerState {
         position: TimelineItemPosition,
         room_data_provider: &P,
         settings: &TimelineInnerSettings,
+        utd_causes: &UtdCauses,
     ) -> HandleEventResult {
         let should_add_event = &*settings.event_filter;
         let raw = event.event;
@@ -243,8 +404,27 @@ impl TimelineInnerState {
             },
         };
 
+        if self.history_cutoff.is_some_and(|cutoff| timestamp < cutoff) {
+            debug!(?event_id, "Ignoring event older than the retention policy of the room");
+            return HandleEventResult::default();
+        }
+
+        let event_kind = match event_kind {
+            TimelineEventKind::Message {
+                content: AnyMessageLikeEventContent::RoomEncrypted(content),
+                ..
+            } => {
+                let cause = utd_causes.get(&event_id);
+                TimelineEventKind::UnableToDecrypt { content, cause }
+            }
+            event_kind => event_kind,
+        };
+
         let is_own_event = sender == room_data_provider.own_user_id();
+        let is_mentioned =
+            is_user_mentioned(room_data_provider, &sender, event_kind.mentions()).await;
         let sender_profile = room_data_provider.profile(&sender).await;
+        let is_at_end = matches!(position, TimelineItemPosition::End { .. });
         let ctx = TimelineEventContext {
             sender,
             sender_profile,
@@ -257,10 +437,17 @@ impl TimelineInnerState {
                 Default::default()
             },
             is_highlighted: event.push_actions.iter().any(Action::is_highlight),
+            is_mentioned,
             flow: Flow::Remote { event_id, raw_event: raw, txn_id, position, should_add },
         };
 
-        TimelineEventHandler::new(self, ctx, settings.track_read_receipts).handle_event(event_kind)
+        if is_at_end {
+            self.with_typing_users_item_detached(|state| {
+                TimelineEventHandler::new(state, ctx, settings).handle_event(event_kind)
+            })
+        } else {
+            TimelineEventHandler::new(self, ctx, settings).handle_event(event_kind)
+        }
     }
 
     /// Handle the creation of a new local event.
@@ -282,11 +469,15 @@ impl TimelineInnerState {
             read_receipts: Default::default(),
             // An event sent by ourself is never matched against push rules.
             is_highlighted: false,
+            is_mentioned: false,
             flow: Flow::Local { txn_id },
         };
 
-        TimelineEventHandler::new(self, ctx, settings.track_read_receipts)
-            .handle_event(TimelineEventKind::Message { content, relations: Default::default() });
+        self.with_typing_users_item_detached(|state| {
+            TimelineEventHandler::new(state, ctx, settings).handle_event(
+                TimelineEventKind::Message { content, relations: Default::default() },
+            );
+        });
     }
 
     /// Handle the local redaction of an event.
@@ -309,10 +500,10 @@ impl TimelineInnerState {
             read_receipts: Default::default(),
             // An event sent by ourself is never matched against push rules.
             is_highlighted: false,
+            is_mentioned: false,
             flow: Flow::Local { txn_id: txn_id.clone() },
         };
-        let timeline_event_handler =
-            TimelineEventHandler::new(self, ctx, settings.track_read_receipts);
+        let timeline_event_handler = TimelineEventHandler::new(self, ctx, settings);
 
         match to_redact {
             EventItemIdentifier::TransactionId(txn_id) => {
@@ -346,7 +537,10 @@ impl TimelineInnerState {
             let mut idx = 0;
             while idx < self.items.len() {
                 if self.items[idx].is_day_divider()
-                    && self.items.get(idx + 1).map_or(true, |item| item.is_day_divider())
+                    && self
+                        .items
+                        .get(idx + 1)
+                        .map_or(true, |item| item.is_day_divider() || item.is_typing_users())
                 {
                     self.items.remove(idx);
                     // don't increment idx because all elements have shifted
@@ -356,11 +550,77 @@ impl TimelineInnerState {
             }
         } else {
             self.items.clear();
+
+            // The typing users are not part of the history that is cleared.
+            if !self.typing_users.is_empty() {
+                self.items.push_back(TimelineItem::typing_users(self.typing_users.clone()));
+            }
         }
 
         self.reactions.clear();
         self.fully_read_event = None;
         self.event_should_update_fully_read_marker = false;
+        self.hidden_events.clear();
+        self.hidden_events_at_start.clear();
+    }
+
+    /// Update the other members that are currently typing.
+    ///
+    /// The item at the end of the timeline is only updated if the typing users
+    /// changed.
+    pub(super) fn handle_typing(&mut self, typing_users: Vec<Profile>) {
+        if typing_users == self.typing_users {
+            return;
+        }
+
+        let has_item = self.items.last().is_some_and(|item| item.is_typing_users());
+        match (has_item, typing_users.is_empty()) {
+            (true, true) => {
+                self.items.pop_back();
+            }
+            (true, false) => {
+                let idx = self.items.len() - 1;
+                self.items.set(idx, TimelineItem::typing_users(typing_users.clone()));
+            }
+            (false, false) => {
+                self.items.push_back(TimelineItem::typing_users(typing_users.clone()));
+            }
+            (false, true) => {}
+        }
+
+        self.typing_users = typing_users;
+    }
+
+    /// Call the given function with the typing users item removed from the end
+    /// of the timeline, and add it back afterwards.
+    ///
+    /// This allows the code adding items at the end of the timeline to not
+    /// care about it.
+    pub(super) fn with_typing_users_item_detached<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let detached = self.items.last().is_some_and(|item| item.is_typing_users());
+        if detached {
+            self.items.pop_back();
+        }
+
+        let result = f(self);
+
+        if detached {
+            self.items.push_back(TimelineItem::typing_users(self.typing_users.clone()));
+        }
+
+        result
+    }
+
+    /// The ID of the event that is visible in the timeline in place of the
+    /// given one.
+    ///
+    /// This is the given event ID, unless the event is hidden by the event
+    /// filter or is in a group of state events.
+    pub fn visible_event_id<'a>(&'a self, event_id: &'a EventId) -> &'a EventId {
+        self.hidden_events.get(event_id).map_or(event_id, |id| id)
     }
 
     #[instrument(skip_all)]
@@ -370,11 +630,12 @@ impl TimelineInnerState {
             return;
         }
 
+        let visible_event_id = self.visible_event_id(&fully_read_event_id).to_owned();
         self.fully_read_event = Some(fully_read_event_id);
 
         update_read_marker(
             &mut self.items,
-            self.fully_read_event.as_deref(),
+            Some(&visible_event_id),
             &mut self.event_should_update_fully_read_marker,
         );
     }
@@ -479,7 +740,7 @@ impl TimelineInnerState {
 
 pub(in crate::timeline) struct TimelineInnerStateLockGuard<'a> {
     inner: MutexGuard<'a, TimelineInnerState>,
-    lock_release_ob: SharedObservable<()>,
+    lock_release_ob: LockReleaseObservable,
 }
 
 impl Deref for TimelineInnerStateLockGuard<'_> {
@@ -498,13 +759,13 @@ impl DerefMut for TimelineInnerStateLockGuard<'_> {
 
 impl Drop for TimelineInnerStateLockGuard<'_> {
     fn drop(&mut self) {
-        self.lock_release_ob.set(());
+        self.lock_release_ob.notify();
     }
 }
 
 pub(in crate::timeline) struct TimelineInnerStateOwnedLockGuard {
     inner: OwnedMutexGuard<TimelineInnerState>,
-    lock_release_ob: SharedObservable<()>,
+    lock_release_ob: LockReleaseObservable,
 }
 
 impl Deref for TimelineInnerStateOwnedLockGuard {
@@ -523,6 +784,38 @@ impl DerefMut for TimelineInnerStateOwnedLockGuard {
 
 impl Drop for TimelineInnerStateOwnedLockGuard {
     fn drop(&mut self) {
-        self.lock_release_ob.set(());
+        self.lock_release_ob.notify();
+    }
+}
+
+/// Observable notified when the lock of the state is released, unless a batch
+/// of updates is running.
+#[derive(Clone, Default)]
+struct LockReleaseObservable {
+    inner: SharedObservable<()>,
+    /// The number of batches of updates that are running.
+    batch_depth: Arc<AtomicUsize>,
+}
+
+impl LockReleaseObservable {
+    fn notify(&self) {
+        if self.batch_depth.load(Ordering::SeqCst) == 0 {
+            self.inner.set(());
+        }
+    }
+}
+
+/// Guard of a batch of updates of the state, started with
+/// [`TimelineInnerStateLock::start_batch()`].
+///
+/// The subscribers are notified when it is dropped.
+pub(in crate::timeline) struct TimelineInnerStateBatchGuard {
+    lock_release_ob: LockReleaseObservable,
+}
+
+impl Drop for TimelineInnerStateBatchGuard {
+    fn drop(&mut self) {
+        self.lock_release_ob.batch_depth.fetch_sub(1, Ordering::SeqCst);
+        self.lock_release_ob.notify();
     }
 }
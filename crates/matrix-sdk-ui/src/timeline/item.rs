@@ -14,7 +14,7 @@
 
 use std::{ops::Deref, sync::Arc};
 
-use super::{EventTimelineItem, VirtualTimelineItem};
+use super::{EventTimelineItem, Profile, VirtualTimelineItem};
 
 #[derive(Clone, Debug)]
 #[allow(clippy::large_enum_variant)]
@@ -80,6 +80,13 @@ impl TimelineItem {
         })
     }
 
+    pub(crate) fn typing_users(profiles: Vec<Profile>) -> Arc<TimelineItem> {
+        Arc::new(Self {
+            kind: TimelineItemKind::Virtual(VirtualTimelineItem::TypingUsers(profiles)),
+            internal_id: u64::MAX - 1,
+        })
+    }
+
     pub(crate) fn is_local_echo(&self) -> bool {
         matches!(&self.kind, TimelineItemKind::Event(ev) if ev.is_local_echo())
     }
@@ -99,6 +106,10 @@ impl TimelineItem {
     pub(crate) fn is_read_marker(&self) -> bool {
         matches!(self.kind, TimelineItemKind::Virtual(VirtualTimelineItem::ReadMarker))
     }
+
+    pub(crate) fn is_typing_users(&self) -> bool {
+        matches!(self.kind, TimelineItemKind::Virtual(VirtualTimelineItem::TypingUsers(_)))
+    }
 }
 
 impl Deref for TimelineItem {
@@ -0,0 +1,166 @@
+//! This module handles rendering of MSC3489 live location shares in the
+//! timeline.
+
+use std::{collections::HashMap, time::Duration};
+
+use ruma::{
+    events::{beacon::BeaconEventContent, beacon_info::BeaconInfoEventContent},
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, UserId,
+};
+
+/// Holds the state of a live location share.
+///
+/// This struct should be created for each live `m.beacon_info` state event
+/// handled and then updated whenever handling an `m.beacon` event that relates
+/// to it, or a non-live `m.beacon_info` state event that stops it.
+#[derive(Clone, Debug)]
+pub struct LiveLocationState {
+    pub(super) beacon_info: BeaconInfoEventContent,
+    /// The state key of the `m.beacon_info` event, i.e. the user sharing
+    /// their location.
+    pub(super) state_key: OwnedUserId,
+    pub(super) last_location: Option<BeaconLocation>,
+}
+
+/// A location shared during a live location share.
+#[derive(Clone, Debug)]
+pub struct BeaconLocation {
+    /// The location, as a `geo:` URI.
+    pub geo_uri: String,
+    /// The description of the location, if any.
+    pub description: Option<String>,
+    /// When the user was at this location.
+    pub ts: MilliSecondsSinceUnixEpoch,
+}
+
+impl From<&BeaconEventContent> for BeaconLocation {
+    fn from(content: &BeaconEventContent) -> Self {
+        Self {
+            geo_uri: content.location.uri.clone(),
+            description: content.location.description.clone(),
+            ts: content.ts,
+        }
+    }
+}
+
+impl LiveLocationState {
+    pub(super) fn new(beacon_info: BeaconInfoEventContent, state_key: OwnedUserId) -> Self {
+        Self { beacon_info, state_key, last_location: None }
+    }
+
+    /// Update the last location of the share.
+    ///
+    /// If the location is older than the current one, returns `None`.
+    pub(super) fn update_location(&self, location: BeaconLocation) -> Option<Self> {
+        if self.last_location.as_ref().is_some_and(|last| last.ts >= location.ts) {
+            return None;
+        }
+
+        let mut clone = self.clone();
+        clone.last_location = Some(location);
+        Some(clone)
+    }
+
+    /// Marks the share as stopped.
+    pub(super) fn stop(&self) -> Self {
+        let mut clone = self.clone();
+        clone.beacon_info.live = false;
+        clone
+    }
+
+    /// The description of the share, if any.
+    pub fn description(&self) -> Option<&str> {
+        self.beacon_info.description.as_deref()
+    }
+
+    /// When the share was started.
+    pub fn start_ts(&self) -> MilliSecondsSinceUnixEpoch {
+        self.beacon_info.ts
+    }
+
+    /// How long the location is shared, from [`Self::start_ts()`].
+    pub fn timeout(&self) -> Duration {
+        self.beacon_info.timeout
+    }
+
+    /// Whether the share is still live, i.e. it was not stopped and it has
+    /// not timed out yet.
+    pub fn is_live(&self) -> bool {
+        self.beacon_info.is_live()
+    }
+
+    /// Whether the share was explicitly stopped by its sender.
+    pub(super) fn is_stopped(&self) -> bool {
+        !self.beacon_info.live
+    }
+
+    /// The last location that was shared, if any.
+    pub fn last_location(&self) -> Option<&BeaconLocation> {
+        self.last_location.as_ref()
+    }
+}
+
+/// Acts as a cache for the locations and the stops of live location shares
+/// handled before their `m.beacon_info` event has been handled, e.g. when
+/// paginating backwards.
+#[derive(Debug, Default)]
+pub(super) struct LiveLocationPendingEvents {
+    pending_locations: HashMap<OwnedEventId, BeaconLocation>,
+    /// The oldest stop of the shares of each state key.
+    pending_stops: HashMap<OwnedUserId, MilliSecondsSinceUnixEpoch>,
+}
+
+impl LiveLocationPendingEvents {
+    /// Keeps the given location if it's the most recent one for the share.
+    pub(super) fn add_location(
+        &mut self,
+        beacon_info_event_id: &EventId,
+        location: BeaconLocation,
+    ) {
+        match self.pending_locations.get(beacon_info_event_id) {
+            Some(pending) if pending.ts >= location.ts => {}
+            _ => {
+                self.pending_locations.insert(beacon_info_event_id.to_owned(), location);
+            }
+        }
+    }
+
+    /// Keeps the given stop of the shares with the given state key, if it's
+    /// the oldest one.
+    pub(super) fn add_stop(&mut self, state_key: &UserId, ts: MilliSecondsSinceUnixEpoch) {
+        match self.pending_stops.get(state_key) {
+            Some(pending) if *pending <= ts => {}
+            _ => {
+                self.pending_stops.insert(state_key.to_owned(), ts);
+            }
+        }
+    }
+
+    /// Stops the given live_location_state started at the given timestamp, if
+    /// the cache has a stop for its state key that happened after it.
+    pub(super) fn apply_stop(
+        &mut self,
+        start_ts: MilliSecondsSinceUnixEpoch,
+        live_location_state: &mut LiveLocationState,
+    ) {
+        let state_key = &live_location_state.state_key;
+        if self.pending_stops.get(state_key).is_some_and(|stop_ts| *stop_ts >= start_ts) {
+            self.pending_stops.remove(state_key);
+            *live_location_state = live_location_state.stop();
+        }
+    }
+
+    /// Moves the location present in the cache for the share started by the
+    /// given beacon_info_event_id to the given live_location_state.
+    pub(super) fn apply(
+        &mut self,
+        beacon_info_event_id: &EventId,
+        live_location_state: &mut LiveLocationState,
+    ) {
+        if let Some(location) = self.pending_locations.remove(beacon_info_event_id) {
+            if let Some(updated) = live_location_state.update_location(location) {
+                *live_location_state = updated;
+            }
+        }
+    }
+}
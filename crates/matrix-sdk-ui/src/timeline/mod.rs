@@ -16,46 +16,69 @@
 //!
 //! See [`Timeline`] for details.
 
-use std::{pin::Pin, sync::Arc, task::Poll, time::Duration};
+use std::{
+    fs, iter,
+    ops::Range,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::Duration,
+};
 
 use async_std::sync::{Condvar, Mutex};
 use eyeball::{SharedObservable, Subscriber};
 use eyeball_im::VectorDiff;
 use futures_core::Stream;
+use futures_util::{future::Either, StreamExt as _};
 use imbl::Vector;
 use matrix_sdk::{
-    attachment::AttachmentConfig,
-    event_handler::EventHandlerHandle,
+    attachment::{AttachmentConfig, AttachmentInfo, BaseAudioInfo},
+    event_cache::RoomEventCache,
     executor::JoinHandle,
     room::{MessagesOptions, Receipts, Room},
-    Client, Result,
+    Result,
 };
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk::{event_handler::EventHandlerHandle, Client};
 use matrix_sdk_base::RoomState;
 use mime::Mime;
 use pin_project_lite::pin_project;
 use ruma::{
-    api::client::receipt::create_receipt::v3::ReceiptType,
+    api::client::receipt::create_receipt::v3::ReceiptType as SendReceiptType,
     assign,
     events::{
+        poll::{
+            unstable_end::UnstablePollEndEventContent,
+            unstable_response::UnstablePollResponseEventContent,
+        },
         reaction::ReactionEventContent,
-        receipt::{Receipt, ReceiptThread},
+        receipt::{Receipt, ReceiptThread, ReceiptType},
         relation::Annotation,
-        room::{message::sanitize::HtmlSanitizerMode, redaction::RoomRedactionEventContent},
+        room::{
+            message::{sanitize::HtmlSanitizerMode, RoomMessageEventContentWithoutRelation},
+            redaction::RoomRedactionEventContent,
+        },
         AnyMessageLikeEventContent,
     },
-    EventId, OwnedEventId, OwnedTransactionId, TransactionId, UserId,
+    EventId, Int, OwnedEventId, OwnedTransactionId, TransactionId, UInt, UserId,
 };
 use thiserror::Error;
-use tokio::sync::mpsc::Sender;
+use tokio::{select, sync::mpsc::Sender};
 use tracing::{debug, error, info, instrument, warn};
 
 mod builder;
+mod edit_history;
 mod event_handler;
 mod event_item;
 mod futures;
 mod inner;
 mod item;
+mod live_location;
 mod pagination;
+mod pinned_events;
 mod polls;
 mod queue;
 mod reactions;
@@ -63,34 +86,44 @@ mod read_receipts;
 mod sliding_sync_ext;
 #[cfg(test)]
 mod tests;
-#[cfg(feature = "e2e-encryption")]
-mod to_device;
+mod thread;
 mod traits;
 mod util;
 mod virtual_item;
+mod window;
 
 pub use self::{
     builder::TimelineBuilder,
+    edit_history::MessageVersion,
     event_item::{
-        AnyOtherFullStateEventContent, BundledReactions, EncryptedMessage, EventItemOrigin,
-        EventSendState, EventTimelineItem, InReplyToDetails, MemberProfileChange, MembershipChange,
-        Message, OtherState, Profile, ReactionGroup, RepliedToEvent, RoomMembershipChange, Sticker,
-        TimelineDetails, TimelineItemContent,
+        AnyOtherFullStateEventContent, BundledReactions, CollapsedStateEvents, EncryptedMessage,
+        EventItemIdentifier, EventItemOrigin, EventSendState, EventTimelineItem, InReplyToDetails,
+        MemberProfileChange, MembershipChange, Message, OtherState, Profile, ReactionGroup,
+        RepliedToEvent, RoomMembershipChange, Sticker, TimelineDetails, TimelineItemContent,
+        UtdCause,
     },
     futures::SendAttachment,
     item::{TimelineItem, TimelineItemKind},
+    live_location::{BeaconLocation, LiveLocationState},
     pagination::{PaginationOptions, PaginationOutcome},
     polls::PollResult,
+    queue::LocalEchoHandle,
     reactions::ReactionSenderData,
     sliding_sync_ext::SlidingSyncRoomExt,
     traits::RoomExt,
     virtual_item::VirtualTimelineItem,
+    window::TimelineWindow,
 };
 use self::{
+    edit_history::{edit_version, original_version},
     inner::{ReactionAction, TimelineInner, TimelineInnerState},
-    queue::LocalMessage,
+    queue::{
+        edit_content, persist_local_message, request_local_echo_action, LocalEchoAction,
+        LocalMessage, LocalMessageContent, QueueRequest,
+    },
     reactions::ReactionToggleResult,
     util::rfind_event_by_id,
+    window::Window,
 };
 
 /// The default sanitizer mode used when sanitizing HTML.
@@ -104,14 +137,35 @@ const DEFAULT_SANITIZER_MODE: HtmlSanitizerMode = HtmlSanitizerMode::Compat;
 #[derive(Debug)]
 pub struct Timeline {
     inner: TimelineInner,
+    focus: TimelineFocus,
+    /// The cache of the events of the room, to paginate the live timeline
+    /// from, if it is enabled.
+    event_cache: Option<RoomEventCache>,
 
     start_token: Arc<Mutex<Option<String>>>,
     start_token_condvar: Arc<Condvar>,
     /// Observable for whether a pagination is currently running
     back_pagination_status: SharedObservable<BackPaginationStatus>,
+    /// Lock held while a back-pagination is running, so concurrent calls wait
+    /// for it instead of starting another one.
+    back_pagination_lock: Mutex<()>,
 
-    _end_token: Mutex<Option<String>>,
-    msg_sender: Sender<LocalMessage>,
+    /// The token to paginate forwards from, if the timeline is focused on an
+    /// event and the end of the timeline wasn't reached yet.
+    ///
+    /// Events received via sync are ignored while it is set.
+    end_token: Arc<Mutex<Option<String>>>,
+    /// Observable for whether a forward-pagination is currently running, or
+    /// whether the timeline follows the live events of the room.
+    forward_pagination_status: SharedObservable<ForwardPaginationStatus>,
+    /// Lock held while a forward-pagination is running, so concurrent calls
+    /// wait for it instead of starting another one.
+    forward_pagination_lock: Mutex<()>,
+    /// Whether the timeline was loaded around an event with
+    /// [`Self::focus_on_event()`], in which case it is paginated with the
+    /// tokens of the homeserver rather than from the event cache.
+    is_focused_on_event: AtomicBool,
+    msg_sender: Sender<QueueRequest>,
     drop_handle: Arc<TimelineDropHandle>,
 }
 
@@ -138,24 +192,43 @@ impl Timeline {
     }
 
     /// Clear all timeline items, and reset pagination parameters.
+    ///
+    /// If the timeline was focused on an event, this goes back to the live
+    /// timeline.
     pub async fn clear(&self) {
         let mut start_lock = self.start_token.lock().await;
-        let mut end_lock = self._end_token.lock().await;
+        let mut end_lock = self.end_token.lock().await;
 
         *start_lock = None;
         *end_lock = None;
+        self.is_focused_on_event.store(false, Ordering::SeqCst);
+        self.forward_pagination_status.set_if_not_eq(ForwardPaginationStatus::LiveEdgeReached);
 
         self.inner.clear().await;
     }
 
     /// Subscribe to the back-pagination status of the timeline.
+    ///
+    /// This allows to show that more events are being loaded, or that the
+    /// start of the timeline was reached, without tracking the calls to
+    /// [`Self::paginate_backwards()`].
     pub fn back_pagination_status(&self) -> Subscriber<BackPaginationStatus> {
         self.back_pagination_status.subscribe()
     }
 
     /// Add more events to the start of the timeline.
+    ///
+    /// If a back-pagination is already running, this waits for it to finish
+    /// instead of starting another one, so it is safe to call repeatedly, like
+    /// from a scroll handler.
     #[instrument(skip_all, fields(room_id = ?self.room().room_id(), ?options))]
     pub async fn paginate_backwards(&self, mut options: PaginationOptions<'_>) -> Result<()> {
+        let Some(_back_pagination_guard) = self.back_pagination_lock.try_lock() else {
+            debug!("Back-pagination already running, waiting for it to finish");
+            drop(self.back_pagination_lock.lock().await);
+            return Ok(());
+        };
+
         let mut start_lock = self.start_token.lock().await;
         if start_lock.is_none()
             && self.back_pagination_status.get() == BackPaginationStatus::TimelineStartReached
@@ -166,7 +239,17 @@ impl Timeline {
 
         self.back_pagination_status.set(BackPaginationStatus::Paginating);
 
-        if start_lock.is_none() && options.wait_for_token {
+        // The events of a thread are paginated with their own tokens, that
+        // don't come from sync.
+        let is_thread = matches!(self.focus, TimelineFocus::Thread { .. });
+
+        // The event cache doesn't need a token to start paginating, but it
+        // doesn't know the tokens of the events loaded around a focused event.
+        let event_cache =
+            self.event_cache.as_ref().filter(|_| !self.is_focused_on_event.load(Ordering::SeqCst));
+        let uses_event_cache = event_cache.is_some();
+
+        if start_lock.is_none() && options.wait_for_token && !is_thread && !uses_event_cache {
             info!("No prev_batch token, waiting");
             (start_lock, _) = self
                 .start_token_condvar
@@ -181,25 +264,138 @@ impl Timeline {
         let mut from = start_lock.clone();
         let mut outcome = PaginationOutcome::new();
 
+        while let Some(limit) = options.next_event_limit(outcome) {
+            let messages = match (&self.focus, event_cache) {
+                (TimelineFocus::Thread { root_event_id }, _) => {
+                    thread::thread_messages(self.room(), root_event_id, from, limit).await
+                }
+                (_, Some(event_cache)) => {
+                    event_cache.paginate_backwards(from.as_deref(), limit).await
+                }
+                _ => {
+                    self.room()
+                        .messages(assign!(MessagesOptions::backward(), {
+                            from,
+                            limit: limit.into(),
+                        }))
+                        .await
+                }
+            }
+            .map_err(|e| {
+                self.back_pagination_status.set(BackPaginationStatus::Idle);
+                e
+            })?;
+
+            // Events older than the retention policy of the room are not shown, so
+            // there is no point in paginating further once they are reached.
+            let past_history_cutoff = self.inner.is_past_history_cutoff(&messages.chunk).await;
+
+            let process_events_result = async {
+                outcome.events_received = messages.chunk.len().try_into().ok()?;
+                outcome.total_events_received =
+                    outcome.total_events_received.checked_add(outcome.events_received)?;
+
+                let res = self.inner.handle_back_paginated_events(messages.chunk).await?;
+
+                outcome.items_added = res.items_added;
+                outcome.items_updated = res.items_updated;
+                outcome.total_items_added =
+                    outcome.total_items_added.checked_add(outcome.items_added)?;
+                outcome.total_items_updated =
+                    outcome.total_items_updated.checked_add(outcome.items_updated)?;
+
+                Some(())
+            }
+            .await;
+
+            from = if past_history_cutoff { None } else { messages.end };
+
+            if from.is_none() {
+                break;
+            }
+
+            if process_events_result.is_none() {
+                error!("Received an excessive number of events, ending pagination (u16 overflow)");
+                break;
+            }
+        }
+
+        let status = if from.is_some() {
+            BackPaginationStatus::Idle
+        } else {
+            BackPaginationStatus::TimelineStartReached
+        };
+        self.back_pagination_status.set(status);
+        *start_lock = from;
+
+        #[cfg(feature = "backups")]
+        self.inner.download_missing_room_keys_from_backup().await;
+
+        Ok(())
+    }
+
+    /// Subscribe to the forward-pagination status of the timeline.
+    ///
+    /// This allows to show that newer events are being loaded, or whether the
+    /// timeline follows the live events of the room again after it was
+    /// focused on an event with [`Self::focus_on_event()`].
+    pub fn forward_pagination_status(&self) -> Subscriber<ForwardPaginationStatus> {
+        self.forward_pagination_status.subscribe()
+    }
+
+    /// Add more events to the end of the timeline, after it was focused on an
+    /// event with [`Self::focus_on_event()`].
+    ///
+    /// The newer events are fetched from the homeserver and added after the
+    /// ones already in the timeline until the live edge of the room is
+    /// reached, at which point the events received via sync are added to the
+    /// timeline again.
+    ///
+    /// Does nothing if the timeline already follows the live events of the
+    /// room. If a forward-pagination is already running, this waits for it to
+    /// finish instead of starting another one.
+    #[instrument(skip_all, fields(room_id = ?self.room().room_id(), ?options))]
+    pub async fn paginate_forwards(&self, mut options: PaginationOptions<'_>) -> Result<()> {
+        let Some(_forward_pagination_guard) = self.forward_pagination_lock.try_lock() else {
+            debug!("Forward-pagination already running, waiting for it to finish");
+            drop(self.forward_pagination_lock.lock().await);
+            return Ok(());
+        };
+
+        let mut end_lock = self.end_token.lock().await;
+        let Some(mut from) = end_lock.clone() else {
+            debug!("Live edge reached, ignoring forwards-pagination request");
+            return Ok(());
+        };
+
+        self.forward_pagination_status.set(ForwardPaginationStatus::Paginating);
+
+        let mut outcome = PaginationOutcome::new();
+        let mut live_edge_reached = false;
+
         while let Some(limit) = options.next_event_limit(outcome) {
             let messages = self
                 .room()
-                .messages(assign!(MessagesOptions::backward(), {
-                    from,
+                .messages(assign!(MessagesOptions::forward(), {
+                    from: Some(from.clone()),
                     limit: limit.into(),
                 }))
                 .await
                 .map_err(|e| {
-                    self.back_pagination_status.set(BackPaginationStatus::Idle);
+                    self.forward_pagination_status.set(ForwardPaginationStatus::Idle);
                     e
                 })?;
 
+            // The live edge is reached when there are no more events, whether
+            // the homeserver returns a token or not.
+            let end = messages.end.filter(|_| !messages.chunk.is_empty());
+
             let process_events_result = async {
                 outcome.events_received = messages.chunk.len().try_into().ok()?;
                 outcome.total_events_received =
                     outcome.total_events_received.checked_add(outcome.events_received)?;
 
-                let res = self.inner.handle_back_paginated_events(messages.chunk).await?;
+                let res = self.inner.handle_forward_paginated_events(messages.chunk).await?;
 
                 outcome.items_added = res.items_added;
                 outcome.items_updated = res.items_updated;
@@ -212,11 +408,11 @@ impl Timeline {
             }
             .await;
 
-            from = messages.end;
-
-            if from.is_none() {
+            let Some(end) = end else {
+                live_edge_reached = true;
                 break;
-            }
+            };
+            from = end;
 
             if process_events_result.is_none() {
                 error!("Received an excessive number of events, ending pagination (u16 overflow)");
@@ -224,13 +420,93 @@ impl Timeline {
             }
         }
 
-        let status = if from.is_some() {
+        if live_edge_reached {
+            // The events received via sync while the lock was held are
+            // handled once it is released, the ones that were already added
+            // by the pagination are deduplicated.
+            debug!("Live edge reached, adding the events received via sync again");
+            *end_lock = None;
+            self.forward_pagination_status.set(ForwardPaginationStatus::LiveEdgeReached);
+        } else {
+            *end_lock = Some(from);
+            self.forward_pagination_status.set(ForwardPaginationStatus::Idle);
+        }
+
+        #[cfg(feature = "backups")]
+        self.inner.download_missing_room_keys_from_backup().await;
+
+        Ok(())
+    }
+
+    /// Replace the items of the timeline with the given event and the events
+    /// around it, for example to jump to an event from a search result or a
+    /// permalink.
+    ///
+    /// The events are loaded with the `/context` endpoint of the homeserver.
+    /// From there, the timeline can be paginated in both directions with
+    /// [`Self::paginate_backwards()`] and [`Self::paginate_forwards()`].
+    ///
+    /// The events received via sync are ignored until the end of the timeline
+    /// is reached with [`Self::paginate_forwards()`]. To go back to the live
+    /// timeline directly, use [`Self::clear()`].
+    ///
+    /// This is only supported by the timelines with the
+    /// [`TimelineFocus::Live`] focus, it returns an error for the other ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event to focus on.
+    ///
+    /// * `context_size` - The maximum number of events to load around the
+    ///   event.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn focus_on_event(&self, event_id: &EventId, context_size: u16) -> Result<(), Error> {
+        if !matches!(self.focus, TimelineFocus::Live) {
+            return Err(Error::UnsupportedFocus);
+        }
+
+        let _back_pagination_guard = self.back_pagination_lock.lock().await;
+        let _forward_pagination_guard = self.forward_pagination_lock.lock().await;
+        let mut start_lock = self.start_token.lock().await;
+        let mut end_lock = self.end_token.lock().await;
+
+        let context =
+            self.room().event_context(event_id, context_size.into()).await.map_err(|error| {
+                error!("Failed to load the event context: {error}");
+                Error::FailedToLoadEventContext
+            })?;
+
+        let Some(event) = context.event else {
+            error!("The homeserver didn't return the event to focus on");
+            return Err(Error::FailedToLoadEventContext);
+        };
+
+        let events = context
+            .events_before
+            .into_iter()
+            .rev()
+            .chain(iter::once(event))
+            .chain(context.events_after)
+            .collect();
+        self.inner.replace_with_events(events).await;
+
+        self.is_focused_on_event.store(true, Ordering::SeqCst);
+
+        let status = if context.next_batch_token.is_some() {
+            ForwardPaginationStatus::Idle
+        } else {
+            ForwardPaginationStatus::LiveEdgeReached
+        };
+        self.forward_pagination_status.set(status);
+        *end_lock = context.next_batch_token;
+
+        let status = if context.prev_batch_token.is_some() {
             BackPaginationStatus::Idle
         } else {
             BackPaginationStatus::TimelineStartReached
         };
         self.back_pagination_status.set(status);
-        *start_lock = from;
+        *start_lock = context.prev_batch_token;
 
         Ok(())
     }
@@ -238,6 +514,10 @@ impl Timeline {
     /// Retry decryption of previously un-decryptable events given a list of
     /// session IDs whose keys have been imported.
     ///
+    /// This is done automatically when room keys are received, downloaded from
+    /// the key backup or imported with the [`Client`][matrix_sdk::Client] of
+    /// the room, so this is only useful if they were imported by other means.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -310,7 +590,8 @@ impl Timeline {
     ///
     /// In contrast to [`subscribe`](Self::subscribe), this stream can yield
     /// multiple diffs at once. The batching is done such that no arbitrary
-    /// delays are added.
+    /// delays are added: all the diffs produced while handling one sync
+    /// response for the room are yielded as a single batch.
     pub async fn subscribe_batched(
         &self,
     ) -> (Vector<Arc<TimelineItem>>, impl Stream<Item = Vec<VectorDiff<Arc<TimelineItem>>>>) {
@@ -319,6 +600,81 @@ impl Timeline {
         (items, stream)
     }
 
+    /// Get the timeline items in the given window of indices, and a batched
+    /// stream of changes limited to that window.
+    ///
+    /// In contrast to [`subscribe_batched`](Self::subscribe_batched), only the
+    /// items in the window are kept and sent to the subscriber, which reduces
+    /// the memory and the cost of processing the changes of very long
+    /// timelines. The window can be moved with the returned
+    /// [`TimelineWindow`], for example when the user scrolls, in which case
+    /// the stream yields a [`VectorDiff::Reset`] with the items in the new
+    /// window.
+    ///
+    /// The stream also yields a [`VectorDiff::Reset`] when an item that is
+    /// outside of the window enters it, for example when an item is added
+    /// before the window or removed from it.
+    pub async fn subscribe_window(
+        &self,
+        range: Range<usize>,
+    ) -> (
+        Vector<Arc<TimelineItem>>,
+        impl Stream<Item = Vec<VectorDiff<Arc<TimelineItem>>>>,
+        TimelineWindow,
+    ) {
+        let (items, updates) = self.inner.subscribe_batched().await;
+        let mut window = Window::new(&items, range.clone());
+        let values = window.values();
+
+        let range_observable = SharedObservable::new(range.clone());
+        let mut ranges = range_observable.subscribe();
+        let inner = self.inner.clone();
+
+        let stream = async_stream::stream! {
+            let mut range = range;
+            let mut updates = Box::pin(updates);
+
+            loop {
+                let update = select! {
+                    Some(batch) = updates.next() => Either::Left(batch),
+                    Some(new_range) = ranges.next() => Either::Right(new_range),
+                    else => break,
+                };
+
+                let needs_reset = match update {
+                    Either::Left(batch) => {
+                        let mut diffs = Vec::new();
+                        let is_applied =
+                            batch.into_iter().all(|diff| window.apply(diff, &mut diffs));
+
+                        if is_applied && !diffs.is_empty() {
+                            yield diffs;
+                        }
+                        !is_applied
+                    }
+                    Either::Right(new_range) => {
+                        let has_changed = new_range != range;
+                        range = new_range;
+                        has_changed
+                    }
+                };
+
+                if needs_reset {
+                    // Subscribe again to get all the items in a state that is
+                    // consistent with the following diffs.
+                    let (items, new_updates) = inner.subscribe_batched().await;
+                    window = Window::new(&items, range.clone());
+                    updates = Box::pin(new_updates);
+
+                    yield vec![VectorDiff::Reset { values: window.values() }];
+                }
+            }
+        };
+        let stream = TimelineStream::new(stream, self.drop_handle.clone());
+
+        (values, stream, TimelineWindow::new(range_observable))
+    }
+
     /// Send a message to the room, and add it to the timeline as a local echo.
     ///
     /// For simplicity, this method doesn't currently allow custom message
@@ -327,8 +683,12 @@ impl Timeline {
     /// If the encryption feature is enabled, this method will transparently
     /// encrypt the room message if the room is encrypted.
     ///
-    /// If sending the message fails, the local echo item will change its
-    /// `send_state` to [`EventSendState::SendingFailed`].
+    /// The message is kept in the [send queue] of the room until it is sent,
+    /// so that it is restored by the next live timeline of the room if the
+    /// application is restarted in the meantime. Sending is retried while the
+    /// homeserver can't be reached. If sending the message fails for another
+    /// reason, the local echo item will change its `send_state` to
+    /// [`EventSendState::SendingFailed`].
     ///
     /// # Arguments
     ///
@@ -346,17 +706,119 @@ impl Timeline {
     ///       corresponding [`SyncMessageLikeEvent`], but only for the *sending*
     ///       device. Other devices will not see it.
     ///
+    /// [send queue]: matrix_sdk::Room::send_queue
     /// [`MessageLikeUnsigned`]: ruma::events::MessageLikeUnsigned
     /// [`SyncMessageLikeEvent`]: ruma::events::SyncMessageLikeEvent
     #[instrument(skip(self, content), fields(room_id = ?self.room().room_id()))]
     pub async fn send(&self, content: AnyMessageLikeEventContent, txn_id: Option<&TransactionId>) {
         let txn_id = txn_id.map_or_else(TransactionId::new, ToOwned::to_owned);
         self.inner.handle_local_event(txn_id.clone(), content.clone()).await;
-        if self.msg_sender.send(LocalMessage { content, txn_id }).await.is_err() {
+        persist_local_message(self.room(), &txn_id, &content).await;
+        let content = LocalMessageContent::Event(content);
+        let msg = LocalMessage { content, txn_id };
+        if self.msg_sender.send(QueueRequest::Send(msg)).await.is_err() {
             error!("Internal error: timeline message receiver is closed");
         }
     }
 
+    /// Edit a message.
+    ///
+    /// If the message is a local echo, this is the same as
+    /// [`LocalEchoHandle::edit()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `item_id` - The transaction ID of the local echo, or the event ID of
+    ///   the event to edit.
+    ///
+    /// * `new_content` - The new content of the message.
+    #[instrument(skip(self, new_content), fields(room_id = ?self.room().room_id()))]
+    pub async fn edit(
+        &self,
+        item_id: &EventItemIdentifier,
+        new_content: RoomMessageEventContentWithoutRelation,
+    ) -> Result<(), Error> {
+        match item_id {
+            EventItemIdentifier::EventId(event_id) => {
+                self.send(edit_content(event_id.clone(), new_content), None).await;
+            }
+            EventItemIdentifier::TransactionId(txn_id) => {
+                let action = LocalEchoAction::Edit(new_content);
+                request_local_echo_action(&self.msg_sender, txn_id, action).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Redact an event.
+    ///
+    /// If the event is a local echo, it is shown as redacted right away, and
+    /// the redaction is sent after the local echo, because it needs the ID of
+    /// the event. If the local echo failed to be sent, it is discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `item_id` - The transaction ID of the local echo, or the event ID of
+    ///   the event to redact.
+    ///
+    /// * `reason` - The reason for the redaction.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn redact(
+        &self,
+        item_id: &EventItemIdentifier,
+        reason: Option<&str>,
+    ) -> Result<(), Error> {
+        let event_id = match item_id {
+            EventItemIdentifier::EventId(event_id) => event_id,
+            EventItemIdentifier::TransactionId(txn_id) => {
+                let action = LocalEchoAction::Redact(reason.map(ToOwned::to_owned));
+                request_local_echo_action(&self.msg_sender, txn_id, action).await?;
+                return Ok(());
+            }
+        };
+
+        self.room().redact(event_id, reason, None).await.map_err(|error| {
+            error!("Failed to redact event: {error}");
+            Error::FailedToRedact
+        })?;
+
+        Ok(())
+    }
+
+    /// Vote in a poll, and add the vote to the poll's item as a local echo.
+    ///
+    /// A new vote replaces the previous vote of the user in the poll. Sending
+    /// an empty list of answers removes the vote.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_start_id` - The ID of the `m.poll.start` event of the poll.
+    ///
+    /// * `answers` - The IDs of the selected answers.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn send_poll_response(&self, poll_start_id: &EventId, answers: Vec<String>) {
+        let content = UnstablePollResponseEventContent::new(answers, poll_start_id.to_owned());
+        self.send(AnyMessageLikeEventContent::UnstablePollResponse(content), None).await;
+    }
+
+    /// End a poll, so no more votes are counted.
+    ///
+    /// Only the sender of the poll, or users with the permission to redact
+    /// events, can end a poll.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_start_id` - The ID of the `m.poll.start` event of the poll.
+    ///
+    /// * `text` - The fallback text of the `m.poll.end` event, for clients that
+    ///   don't support polls.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn end_poll(&self, poll_start_id: &EventId, text: String) {
+        let content = UnstablePollEndEventContent::new(text, poll_start_id.to_owned());
+        self.send(AnyMessageLikeEventContent::UnstablePollEnd(content), None).await;
+    }
+
     /// Toggle a reaction on an event
     ///
     /// Adds or redacts a reaction based on the state of the reaction at the
@@ -393,6 +855,46 @@ impl Timeline {
         Ok(())
     }
 
+    /// Toggle a reaction on the given timeline item.
+    ///
+    /// This is like [`Timeline::toggle_reaction()`], but the item can also be
+    /// a local echo. In that case, the reaction is added to the send queue of
+    /// the room and only sent once the local echo is sent, because reactions
+    /// need the ID of the event. Toggling the reaction again until then
+    /// removes it from the queue. This fails if the local echo couldn't be
+    /// sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `item_id` - The transaction ID of the local echo, or the event ID of
+    ///   the event to react to.
+    ///
+    /// * `key` - The key of the reaction, usually an emoji.
+    pub async fn toggle_reaction_on_item(
+        &self,
+        item_id: &EventItemIdentifier,
+        key: &str,
+    ) -> Result<(), Error> {
+        let event_id = match item_id {
+            EventItemIdentifier::EventId(event_id) => event_id.clone(),
+            EventItemIdentifier::TransactionId(txn_id) => {
+                let action = LocalEchoAction::React(key.to_owned());
+                if request_local_echo_action(&self.msg_sender, txn_id, action).await? {
+                    // The reaction is sent after the local echo.
+                    return Ok(());
+                }
+
+                // The local echo was already sent, react to its event.
+                self.inner
+                    .wait_for_local_echo_sent(txn_id)
+                    .await
+                    .ok_or(Error::FailedToToggleReaction)?
+            }
+        };
+
+        self.toggle_reaction(&Annotation::new(event_id, key.to_owned())).await
+    }
+
     /// Redact a reaction event from the homeserver
     async fn redact_reaction(&self, event_id: &EventId) -> ReactionToggleResult {
         let room = self.room();
@@ -434,8 +936,12 @@ impl Timeline {
         }
     }
 
-    /// Sends an attachment to the room. It does not currently support local
-    /// echoes
+    /// Sends an attachment to the room.
+    ///
+    /// A local echo is added to the timeline, with a
+    /// [`EventSendState::Sending`] send state that reports the progress of
+    /// the upload. The transaction ID of the `config` is replaced by the one
+    /// of the local echo.
     ///
     /// If the encryption feature is enabled, this method will transparently
     /// encrypt the room message if the room is encrypted.
@@ -458,69 +964,80 @@ impl Timeline {
         SendAttachment::new(self, url, mime_type, config)
     }
 
+    /// Sends a voice message to the room, as defined in [MSC3245].
+    ///
+    /// This works like [`Self::send_attachment()`], with the audio message
+    /// marked as a voice message and containing the given waveform, so clients
+    /// can render it with a voice message UI.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The url for the audio file to be sent
+    ///
+    /// * `mime_type` - The audio file's mime type
+    ///
+    /// * `waveform` - The amplitudes of the audio clip, between 0 and 1024
+    ///
+    /// * `duration` - The duration of the audio clip
+    ///
+    /// [MSC3245]: https://github.com/matrix-org/matrix-spec-proposals/pull/3245
+    pub fn send_voice_message(
+        &self,
+        url: String,
+        mime_type: Mime,
+        waveform: Vec<u16>,
+        duration: Duration,
+    ) -> SendAttachment<'_> {
+        let size = fs::metadata(&url).ok().and_then(|metadata| UInt::new(metadata.len()));
+        let info = AttachmentInfo::Voice {
+            audio_info: BaseAudioInfo { duration: Some(duration), size },
+            waveform: Some(waveform),
+        };
+
+        self.send_attachment(url, mime_type, AttachmentConfig::new().info(info))
+    }
+
     /// Retry sending a message that previously failed to send.
     ///
+    /// This is the same as [`LocalEchoHandle::retry()`].
+    ///
     /// # Arguments
     ///
     /// * `txn_id` - The transaction ID of a local echo timeline item that has a
     ///   `send_state()` of `SendState::FailedToSend { .. }`
     pub async fn retry_send(&self, txn_id: &TransactionId) -> Result<(), Error> {
-        macro_rules! error_return {
-            ($msg:literal) => {{
-                error!($msg);
-                return Ok(());
-            }};
-        }
-
-        let item = self.inner.prepare_retry(txn_id).await.ok_or(Error::RetryEventNotInTimeline)?;
-        let content = match item {
-            TimelineItemContent::Message(msg) => {
-                AnyMessageLikeEventContent::RoomMessage(msg.into())
-            }
-            TimelineItemContent::RedactedMessage => {
-                error_return!("Invalid state: attempting to retry a redacted message");
-            }
-            TimelineItemContent::Sticker(sticker) => {
-                AnyMessageLikeEventContent::Sticker(sticker.content)
-            }
-            TimelineItemContent::UnableToDecrypt(_) => {
-                error_return!("Invalid state: attempting to retry a UTD item");
-            }
-            TimelineItemContent::MembershipChange(_)
-            | TimelineItemContent::ProfileChange(_)
-            | TimelineItemContent::OtherState(_) => {
-                error_return!("Retrying state events is not currently supported");
-            }
-            TimelineItemContent::FailedToParseMessageLike { .. }
-            | TimelineItemContent::FailedToParseState { .. } => {
-                error_return!("Invalid state: attempting to retry a failed-to-parse item");
-            }
-            TimelineItemContent::Poll(poll_state) => {
-                AnyMessageLikeEventContent::UnstablePollStart(poll_state.into())
-            }
-        };
-
-        let txn_id = txn_id.to_owned();
-        if self.msg_sender.send(LocalMessage { content, txn_id }).await.is_err() {
-            error!("Internal error: timeline message receiver is closed");
-        }
-
+        request_local_echo_action(&self.msg_sender, txn_id, LocalEchoAction::Retry).await?;
         Ok(())
     }
 
-    /// Discard a local echo for a message that failed to send.
+    /// Abort sending a message and discard its local echo.
+    ///
+    /// This is the same as [`LocalEchoHandle::abort()`].
     ///
-    /// Returns whether the local echo with the given transaction ID was found.
+    /// Returns whether the local echo with the given transaction ID was
+    /// discarded.
     ///
     /// # Argument
     ///
-    /// * `txn_id` - The transaction ID of a local echo timeline item that has a
-    ///   `send_state()` of `SendState::FailedToSend { .. }`. *Note:* A send
-    ///   state of `SendState::NotYetSent` might be supported in the future as
-    ///   well, but there can be no guarantee for that actually stopping the
-    ///   event from reaching the server.
+    /// * `txn_id` - The transaction ID of a local echo timeline item.
     pub async fn cancel_send(&self, txn_id: &TransactionId) -> bool {
-        self.inner.discard_local_echo(txn_id).await
+        request_local_echo_action(&self.msg_sender, txn_id, LocalEchoAction::Abort)
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Expand or collapse a group of state events.
+    ///
+    /// Returns whether the group was found.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The event ID of the [`EventTimelineItem`] whose content
+    ///   is [`TimelineItemContent::CollapsedStateEvents`].
+    ///
+    /// * `expanded` - Whether the group should be expanded.
+    pub async fn set_state_events_expanded(&self, event_id: &EventId, expanded: bool) -> bool {
+        self.inner.set_state_events_expanded(event_id, expanded).await
     }
 
     /// Fetch unavailable details about the event with the given ID.
@@ -547,6 +1064,64 @@ impl Timeline {
         self.inner.fetch_in_reply_to_details(event_id).await
     }
 
+    /// Fetch the edit history of the message of the given timeline item.
+    ///
+    /// Returns the versions of the message from the original one to the most
+    /// recent edit. The edits that were not sent by the sender of the message
+    /// are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - The item of the message. It must have a remote echo.
+    #[instrument(skip(self, item), fields(room_id = ?self.room().room_id()))]
+    pub async fn fetch_edit_history(
+        &self,
+        item: &EventTimelineItem,
+    ) -> Result<Vec<MessageVersion>, Error> {
+        let event_id = item.event_id().ok_or(Error::RemoteEventNotInTimeline)?;
+        let original =
+            item.original_json().and_then(original_version).ok_or(Error::UnsupportedEvent)?;
+
+        let edits = self.room().event_edits(event_id).await.map_err(|error| {
+            error!("Failed to fetch the edits of the event: {error}");
+            Error::FailedToFetchEditHistory
+        })?;
+
+        let original_sender = original.sender.clone();
+        let edit_versions =
+            edits.iter().filter_map(|edit| edit_version(edit, event_id, &original_sender));
+
+        Ok(iter::once(original).chain(edit_versions).collect())
+    }
+
+    /// Report the event of the given timeline item to the homeserver
+    /// administrators.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - The item to report. It must have a remote echo.
+    ///
+    /// * `score` - The score to rate this content as, where -100 is most
+    ///   offensive and 0 is inoffensive.
+    ///
+    /// * `reason` - The reason for the event being reported.
+    #[instrument(skip(self, item), fields(room_id = ?self.room().room_id()))]
+    pub async fn report_content(
+        &self,
+        item: &EventTimelineItem,
+        score: Option<Int>,
+        reason: Option<String>,
+    ) -> Result<(), Error> {
+        let event_id = item.event_id().ok_or(Error::RemoteEventNotInTimeline)?;
+
+        self.room().report_content(event_id.to_owned(), score, reason).await.map_err(|error| {
+            error!("Failed to report content: {error}");
+            Error::FailedToReportContent
+        })?;
+
+        Ok(())
+    }
+
     /// Fetch all member events for the room this timeline is displaying.
     ///
     /// If the full member list is not known, sender profiles are currently
@@ -580,15 +1155,71 @@ impl Timeline {
         self.inner.latest_user_read_receipt(user_id).await
     }
 
+    /// Get the receipt of the given type for the given user.
+    ///
+    /// Like [`Timeline::latest_user_read_receipt()`], this also keeps track of
+    /// implicit read receipts.
+    #[instrument(skip(self))]
+    pub async fn user_receipt(
+        &self,
+        user_id: &UserId,
+        receipt_type: ReceiptType,
+    ) -> Option<(OwnedEventId, Receipt)> {
+        self.inner.user_receipt(user_id, receipt_type).await
+    }
+
+    /// Get the index of the timeline item where the latest read receipt of
+    /// the given user should be displayed.
+    ///
+    /// If the receipt points to an event that isn't displayed, the index of
+    /// the closest previous item is returned. Returns `None` if the user has
+    /// no read receipt or if the item isn't loaded in this timeline.
+    ///
+    /// The index is only valid until the next update of the timeline items.
+    #[instrument(skip(self))]
+    pub async fn latest_user_read_receipt_timeline_index(&self, user_id: &UserId) -> Option<usize> {
+        self.inner.latest_user_read_receipt_timeline_index(user_id).await
+    }
+
+    /// Get the latest read receipt for the given user in the thread with the
+    /// given root event.
+    ///
+    /// Like [`Timeline::latest_user_read_receipt()`], this also keeps track of
+    /// implicit read receipts, i.e. when a room member sends an event in the
+    /// thread.
+    #[instrument(skip(self))]
+    pub async fn latest_user_thread_read_receipt(
+        &self,
+        user_id: &UserId,
+        thread_root: &EventId,
+    ) -> Option<(OwnedEventId, Receipt)> {
+        self.inner.latest_user_thread_read_receipt(user_id, thread_root).await
+    }
+
+    /// Get the number of messages in the thread with the given root event
+    /// that the current user hasn't read yet.
+    ///
+    /// The messages are counted from the latest read receipt of the user in
+    /// the thread, or from their latest message in the thread. Only the
+    /// messages that are loaded in the timeline are counted.
+    #[instrument(skip(self))]
+    pub async fn thread_unread_count(&self, thread_root: &EventId) -> usize {
+        let own_user_id = self.room().own_user_id();
+        self.inner.thread_unread_count(own_user_id, thread_root).await
+    }
+
     /// Send the given receipt.
     ///
     /// This uses [`Room::send_single_receipt`] internally, but checks
     /// first if the receipt points to an event in this timeline that is more
     /// recent than the current ones, to avoid unnecessary requests.
+    ///
+    /// Read receipts can be sent for a thread by using
+    /// [`ReceiptThread::Thread`] with the ID of the thread's root event.
     #[instrument(skip(self))]
     pub async fn send_single_receipt(
         &self,
-        receipt_type: ReceiptType,
+        receipt_type: SendReceiptType,
         thread: ReceiptThread,
         event_id: OwnedEventId,
     ) -> Result<()> {
@@ -611,7 +1242,7 @@ impl Timeline {
             if !self
                 .inner
                 .should_send_receipt(
-                    &ReceiptType::FullyRead,
+                    &SendReceiptType::FullyRead,
                     &ReceiptThread::Unthreaded,
                     fully_read,
                 )
@@ -624,7 +1255,11 @@ impl Timeline {
         if let Some(read_receipt) = &receipts.public_read_receipt {
             if !self
                 .inner
-                .should_send_receipt(&ReceiptType::Read, &ReceiptThread::Unthreaded, read_receipt)
+                .should_send_receipt(
+                    &SendReceiptType::Read,
+                    &ReceiptThread::Unthreaded,
+                    read_receipt,
+                )
                 .await
             {
                 receipts.public_read_receipt = None;
@@ -635,7 +1270,7 @@ impl Timeline {
             if !self
                 .inner
                 .should_send_receipt(
-                    &ReceiptType::ReadPrivate,
+                    &SendReceiptType::ReadPrivate,
                     &ReceiptThread::Unthreaded,
                     private_read_receipt,
                 )
@@ -669,17 +1304,28 @@ impl Timeline {
 
 #[derive(Debug)]
 struct TimelineDropHandle {
-    client: Client,
-    event_handler_handles: Vec<EventHandlerHandle>,
     room_update_join_handle: JoinHandle<()>,
+    ignore_user_list_update_join_handle: JoinHandle<()>,
+    retention_join_handle: JoinHandle<()>,
+    #[cfg(feature = "e2e-encryption")]
+    room_keys_join_handle: Option<JoinHandle<()>>,
+    #[cfg(feature = "e2e-encryption")]
+    room_key_withheld_handle: EventHandlerHandle,
+    #[cfg(feature = "e2e-encryption")]
+    client: Client,
 }
 
 impl Drop for TimelineDropHandle {
     fn drop(&mut self) {
-        for handle in self.event_handler_handles.drain(..) {
-            self.client.remove_event_handler(handle);
-        }
         self.room_update_join_handle.abort();
+        self.ignore_user_list_update_join_handle.abort();
+        self.retention_join_handle.abort();
+        #[cfg(feature = "e2e-encryption")]
+        if let Some(handle) = &self.room_keys_join_handle {
+            handle.abort();
+        }
+        #[cfg(feature = "e2e-encryption")]
+        self.client.remove_event_handler(self.room_key_withheld_handle.clone());
     }
 }
 
@@ -708,13 +1354,63 @@ impl<S: Stream> Stream for TimelineStream<S> {
     }
 }
 
+/// What a [`Timeline`] is focused on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimelineFocus {
+    /// The live timeline of the room, i.e. the events received via sync and
+    /// back-pagination.
+    #[default]
+    Live,
+
+    /// The events pinned in the room, as listed in its `m.room.pinned_events`
+    /// state event.
+    ///
+    /// The events are fetched from the homeserver, and the timeline is
+    /// reloaded every time the list of pinned events changes.
+    PinnedEvents,
+
+    /// The events of a thread: its root event and the events that have an
+    /// `m.thread` relation to it.
+    ///
+    /// The timeline starts empty, the events of the thread are loaded with
+    /// [`Timeline::paginate_backwards`], and the root event is added once the
+    /// start of the thread is reached.
+    Thread {
+        /// The ID of the root event of the thread.
+        root_event_id: OwnedEventId,
+    },
+}
+
+/// The status of the back-pagination of a [`Timeline`].
+///
+/// To observe it, use [`Timeline::back_pagination_status()`].
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BackPaginationStatus {
+    /// No back-pagination is running, and there are more events to load.
     Idle,
+    /// A back-pagination is running.
     Paginating,
+    /// The start of the timeline was reached, there are no more events to
+    /// load.
     TimelineStartReached,
 }
 
+/// The status of the forward-pagination of a [`Timeline`].
+///
+/// To observe it, use [`Timeline::forward_pagination_status()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForwardPaginationStatus {
+    /// No forward-pagination is running, and there are more events to load
+    /// before the live edge of the room is reached.
+    Idle,
+    /// A forward-pagination is running.
+    Paginating,
+    /// The live edge of the room was reached, the timeline follows the events
+    /// received via sync.
+    LiveEdgeReached,
+}
+
 /// Errors specific to the timeline.
 #[derive(Error, Debug)]
 #[non_exhaustive]
@@ -747,6 +1443,10 @@ pub enum Error {
     #[error("Failed toggling reaction")]
     FailedToToggleReaction,
 
+    /// The content could not be reported
+    #[error("Failed reporting content")]
+    FailedToReportContent,
+
     /// The room is not in a joined state.
     #[error("Room is not joined")]
     RoomNotJoined,
@@ -754,4 +1454,29 @@ pub enum Error {
     /// Could not get user
     #[error("User ID is not available")]
     UserIdNotAvailable,
+
+    /// The event to focus on or its context could not be loaded
+    #[error("Failed loading the event context")]
+    FailedToLoadEventContext,
+
+    /// The operation is not supported by the focus of the timeline
+    #[error("Unsupported operation for the focus of the timeline")]
+    UnsupportedFocus,
+
+    /// The edit history of the message could not be fetched
+    #[error("Failed fetching the edit history")]
+    FailedToFetchEditHistory,
+
+    /// The local echo is not in the timeline.
+    #[error("Local echo not found in timeline")]
+    LocalEchoNotInTimeline,
+
+    /// The event could not be redacted
+    #[error("Failed redacting event")]
+    FailedToRedact,
+
+    /// The timeline was dropped, so the local echo can't be acted upon
+    /// anymore.
+    #[error("The timeline was dropped")]
+    TimelineDropped,
 }
@@ -0,0 +1,136 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, RwLock};
+
+use imbl::Vector;
+use matrix_sdk::{
+    deserialized_responses::{SyncOrStrippedState, SyncTimelineEvent},
+    Room,
+};
+use ruma::{
+    events::{room::pinned_events::RoomPinnedEventsEventContent, AnySyncTimelineEvent},
+    EventId, OwnedEventId,
+};
+use tracing::{debug, error, instrument, warn};
+
+use super::inner::{TimelineEventFilterFn, TimelineInner};
+
+/// The list of event IDs pinned in a room, shared between the timeline's
+/// event filter and the task that keeps it up-to-date.
+#[derive(Clone, Debug, Default)]
+pub(super) struct PinnedEventIds(Arc<RwLock<Vec<OwnedEventId>>>);
+
+impl PinnedEventIds {
+    fn contains(&self, event_id: &EventId) -> bool {
+        self.0.read().unwrap().iter().any(|id| id == event_id)
+    }
+
+    /// Replace the list of pinned event IDs.
+    ///
+    /// Returns `true` if the list changed.
+    fn replace(&self, event_ids: Vec<OwnedEventId>) -> bool {
+        let mut guard = self.0.write().unwrap();
+        if *guard == event_ids {
+            return false;
+        }
+
+        *guard = event_ids;
+        true
+    }
+
+    /// Wrap the given event filter so that it only accepts pinned events.
+    ///
+    /// Events relating to a pinned event (edits, reactions, redactions, …) are
+    /// still applied to the existing items since they are not subject to the
+    /// filter.
+    pub(super) fn wrap_filter(
+        &self,
+        filter: Arc<TimelineEventFilterFn>,
+    ) -> Arc<TimelineEventFilterFn> {
+        let this = self.clone();
+        Arc::new(move |ev: &AnySyncTimelineEvent| this.contains(ev.event_id()) && filter(ev))
+    }
+
+    /// Load the pinned events of the room for the first time.
+    pub(super) async fn load_initial_events(&self, room: &Room) -> Vector<SyncTimelineEvent> {
+        let event_ids = load_pinned_event_ids(room).await;
+        self.replace(event_ids.clone());
+        fetch_events(room, &event_ids).await
+    }
+
+    /// Check whether the `m.room.pinned_events` state of the room changed, and
+    /// if it did, replace the items of the timeline with the new pinned
+    /// events.
+    ///
+    /// If `force` is `true`, the items are replaced even if the list of pinned
+    /// events didn't change.
+    #[instrument(skip(self, inner))]
+    pub(super) async fn reload(&self, inner: &TimelineInner, force: bool) {
+        let room = inner.room();
+        let event_ids = load_pinned_event_ids(room).await;
+        if !self.replace(event_ids.clone()) && !force {
+            return;
+        }
+
+        debug!("Pinned events changed, reloading the timeline");
+        let events = fetch_events(room, &event_ids).await;
+        inner.clear().await;
+        inner.clone().add_initial_events(events).await;
+    }
+}
+
+/// Get the IDs of the events pinned in the given room, from the store.
+async fn load_pinned_event_ids(room: &Room) -> Vec<OwnedEventId> {
+    let raw_event = match room.get_state_event_static::<RoomPinnedEventsEventContent>().await {
+        Ok(Some(raw_event)) => raw_event,
+        Ok(None) => return Vec::new(),
+        Err(e) => {
+            error!("Failed to load the pinned events from the store: {e}");
+            return Vec::new();
+        }
+    };
+
+    match raw_event.deserialize() {
+        Ok(SyncOrStrippedState::Sync(ev)) => {
+            ev.as_original().map(|ev| ev.content.pinned.clone()).unwrap_or_default()
+        }
+        Ok(SyncOrStrippedState::Stripped(_)) => {
+            debug!("Room is in invited state, it has no pinned events");
+            Vec::new()
+        }
+        Err(e) => {
+            warn!("Failed to deserialize the pinned events state event: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Fetch the events with the given IDs from the homeserver, in the same order.
+///
+/// Events that can't be fetched are skipped.
+async fn fetch_events(room: &Room, event_ids: &[OwnedEventId]) -> Vector<SyncTimelineEvent> {
+    let mut events = Vector::new();
+
+    for event_id in event_ids {
+        match room.event(event_id).await {
+            Ok(event) => events.push_back(event.into()),
+            Err(e) => {
+                warn!(?event_id, "Failed to fetch pinned event: {e}");
+            }
+        }
+    }
+
+    events
+}
@@ -180,6 +180,24 @@ pub struct PollResult {
     pub end_time: Option<u64>,
 }
 
+impl PollResult {
+    /// The number of votes for the answer with the given ID.
+    pub fn vote_count(&self, answer_id: &str) -> usize {
+        self.votes.get(answer_id).map_or(0, Vec::len)
+    }
+
+    /// The IDs of the answers the given user voted for.
+    ///
+    /// This is usually used with the own user ID, to show their vote.
+    pub fn votes_of(&self, user_id: &UserId) -> Vec<String> {
+        self.votes
+            .iter()
+            .filter(|(_, voters)| voters.iter().any(|voter| voter.as_str() == user_id.as_str()))
+            .map(|(answer_id, _)| answer_id.clone())
+            .collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct PollResultAnswer {
     pub id: String,
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     future::{pending, Future, Pending},
     mem,
     pin::Pin,
@@ -21,17 +21,151 @@ use std::{
     task::{Context, Poll},
 };
 
-use futures_util::future::Either;
+use futures_util::future::{AbortHandle, Abortable, Aborted, Either};
 use matrix_sdk::{
+    attachment::AttachmentConfig,
     executor::{spawn, JoinError, JoinHandle},
+    room::{DependentRequest, QueuedDependentRequest, QueuedEventContent},
     Room,
 };
 use matrix_sdk_base::RoomState;
-use ruma::{events::AnyMessageLikeEventContent, OwnedTransactionId};
-use tokio::{select, sync::mpsc::Receiver};
+use ruma::{
+    events::{
+        reaction::ReactionEventContent,
+        relation::{Annotation, Replacement},
+        room::message::{Relation, RoomMessageEventContentWithoutRelation},
+        AnyMessageLikeEventContent,
+    },
+    EventId, OwnedEventId, OwnedTransactionId, TransactionId,
+};
+use tokio::{
+    select,
+    sync::{
+        mpsc::{Receiver, Sender, WeakSender},
+        oneshot,
+    },
+};
 use tracing::{debug, error, info, instrument, trace, warn};
 
-use super::{inner::TimelineInner, EventSendState};
+use super::{
+    futures::{is_local_echo_attachment, send_local_attachment},
+    inner::TimelineInner,
+    Error, EventSendState, TimelineItemContent,
+};
+
+/// A request to the message-sending loop.
+pub(super) enum QueueRequest {
+    /// Send a message after the ones that are already queued.
+    Send(LocalMessage),
+    /// Act on a local echo.
+    LocalEcho(LocalEchoRequest),
+}
+
+/// A request to act on the local echo with the given transaction ID.
+pub(super) struct LocalEchoRequest {
+    /// The transaction ID of the local echo.
+    pub txn_id: OwnedTransactionId,
+    /// The action to perform.
+    pub action: LocalEchoAction,
+    /// The sender for the result of the action.
+    pub result_sender: oneshot::Sender<Result<bool, Error>>,
+}
+
+/// An action on a local echo.
+pub(super) enum LocalEchoAction {
+    /// Abort sending the event and discard the local echo.
+    ///
+    /// The result is whether the local echo was discarded.
+    Abort,
+    /// Replace the content of the message.
+    Edit(RoomMessageEventContentWithoutRelation),
+    /// Redact the message, with the given reason.
+    Redact(Option<String>),
+    /// Toggle the reaction with the given key on the message.
+    ///
+    /// The result is whether the reaction was queued or cancelled. It is
+    /// `false` if the message was already sent, in which case the reaction
+    /// must be toggled on the event.
+    React(String),
+    /// Retry sending the event after it failed.
+    Retry,
+}
+
+/// Send a request to act on a local echo to the message-sending loop, and wait
+/// for its result.
+pub(super) async fn request_local_echo_action(
+    queue_sender: &Sender<QueueRequest>,
+    txn_id: &TransactionId,
+    action: LocalEchoAction,
+) -> Result<bool, Error> {
+    let (result_sender, result_receiver) = oneshot::channel();
+    let request = LocalEchoRequest { txn_id: txn_id.to_owned(), action, result_sender };
+
+    if queue_sender.send(QueueRequest::LocalEcho(request)).await.is_err() {
+        error!("Internal error: timeline message receiver is closed");
+        return Err(Error::TimelineDropped);
+    }
+
+    result_receiver.await.map_err(|_| Error::TimelineDropped)?
+}
+
+/// A handle to act on a local echo in the timeline.
+///
+/// To get it, use
+/// [`EventTimelineItem::local_echo_handle()`][super::EventTimelineItem::local_echo_handle].
+///
+/// The handle doesn't keep the timeline alive: once the timeline is dropped,
+/// the methods of the handle fail with [`Error::TimelineDropped`].
+#[derive(Clone, Debug)]
+pub struct LocalEchoHandle {
+    txn_id: OwnedTransactionId,
+    queue_sender: WeakSender<QueueRequest>,
+}
+
+impl LocalEchoHandle {
+    pub(super) fn new(txn_id: OwnedTransactionId, queue_sender: WeakSender<QueueRequest>) -> Self {
+        Self { txn_id, queue_sender }
+    }
+
+    /// The transaction ID of the local echo.
+    pub fn transaction_id(&self) -> &TransactionId {
+        &self.txn_id
+    }
+
+    /// Abort sending the event, and discard its local echo.
+    ///
+    /// If the event is being sent, the request is cancelled, but if it already
+    /// reached the homeserver the event might have been sent anyway, and its
+    /// remote echo is then added to the timeline. Attachments can't be
+    /// aborted. Returns whether the local echo was discarded.
+    pub async fn abort(&self) -> bool {
+        self.request(LocalEchoAction::Abort).await.unwrap_or(false)
+    }
+
+    /// Edit the message.
+    ///
+    /// If the message wasn't sent yet, the new content is sent instead of the
+    /// original one. If it is being sent or was already sent, an edit is sent
+    /// after it. If sending it failed, the new content is sent when retrying.
+    pub async fn edit(
+        &self,
+        new_content: RoomMessageEventContentWithoutRelation,
+    ) -> Result<(), Error> {
+        self.request(LocalEchoAction::Edit(new_content)).await?;
+        Ok(())
+    }
+
+    /// Retry sending the event, after sending it failed.
+    pub async fn retry(&self) -> Result<(), Error> {
+        self.request(LocalEchoAction::Retry).await?;
+        Ok(())
+    }
+
+    async fn request(&self, action: LocalEchoAction) -> Result<bool, Error> {
+        let queue_sender = self.queue_sender.upgrade().ok_or(Error::TimelineDropped)?;
+        request_local_echo_action(&queue_sender, &self.txn_id, action).await
+    }
+}
 
 /// A locally-created message that is supposed to be sent.
 pub(super) struct LocalMessage {
@@ -40,18 +174,253 @@ pub(super) struct LocalMessage {
     /// Used for finding the corresponding local echo in the timeline.
     pub txn_id: OwnedTransactionId,
     /// The message contents.
-    pub content: AnyMessageLikeEventContent,
+    pub content: LocalMessageContent,
+}
+
+/// The content of a [`LocalMessage`].
+pub(super) enum LocalMessageContent {
+    /// A message-like event that is sent as-is.
+    Event(AnyMessageLikeEventContent),
+    /// A request targeting a local echo, that can only be sent once the local
+    /// echo was sent because it needs its event ID.
+    Dependent {
+        /// The transaction ID of the targeted local echo.
+        parent_txn_id: OwnedTransactionId,
+        /// The event ID of the targeted local echo, if it was already sent.
+        parent_event_id: Option<OwnedEventId>,
+        /// The request to send.
+        request: DependentRequest,
+    },
+}
+
+/// Add a message to the persistent send queue of the room, so that it can be
+/// restored if the application is restarted before it was sent.
+pub(super) async fn persist_local_message(
+    room: &Room,
+    txn_id: &TransactionId,
+    content: &AnyMessageLikeEventContent,
+) {
+    let result = match room.send_queue().await {
+        Ok(send_queue) => send_queue.push_event(txn_id.to_owned(), content).await,
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = result {
+        warn!("Failed to persist local message: {error}");
+    }
+}
+
+/// Add a request targeting a local echo to the persistent send queue of the
+/// room.
+async fn persist_dependent_request(
+    room: &Room,
+    txn_id: &TransactionId,
+    parent_txn_id: &TransactionId,
+    parent_event_id: Option<&EventId>,
+    request: &DependentRequest,
+) {
+    let result = match room.send_queue().await {
+        Ok(send_queue) => {
+            let result = send_queue
+                .push_dependent(txn_id.to_owned(), parent_txn_id.to_owned(), request.clone())
+                .await;
+            match (result, parent_event_id) {
+                (Ok(()), Some(event_id)) => {
+                    send_queue.resolve_dependents(parent_txn_id, event_id).await
+                }
+                (result, _) => result,
+            }
+        }
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = result {
+        warn!("Failed to persist request targeting a local echo: {error}");
+    }
+}
+
+/// Replace the content of a message in the persistent send queue of the room.
+async fn replace_local_message(
+    room: &Room,
+    txn_id: &TransactionId,
+    content: &AnyMessageLikeEventContent,
+) {
+    let result = match room.send_queue().await {
+        Ok(send_queue) => send_queue.replace_event(txn_id, content).await,
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = result {
+        warn!("Failed to replace local message in the send queue: {error}");
+    }
+}
+
+/// Remove a message that is being sent from the persistent send queue of the
+/// room, and stop retrying to send it.
+async fn abort_local_message(room: &Room, txn_id: &TransactionId) {
+    let result = match room.send_queue().await {
+        Ok(send_queue) => send_queue.abort(txn_id).await,
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = result {
+        warn!("Failed to remove local message from the send queue: {error}");
+    }
+}
+
+/// Remove a message from the persistent send queue of the room.
+pub(super) async fn forget_local_message(room: &Room, txn_id: &TransactionId) {
+    let result = match room.send_queue().await {
+        Ok(send_queue) => send_queue.remove(txn_id).await,
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = result {
+        warn!("Failed to remove local message from the send queue: {error}");
+    }
+}
+
+/// Remove a request targeting a local echo from the persistent send queue of
+/// the room.
+async fn forget_dependent_request(room: &Room, txn_id: &TransactionId) {
+    let result = match room.send_queue().await {
+        Ok(send_queue) => send_queue.remove_dependent(txn_id).await,
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = result {
+        warn!("Failed to remove request targeting a local echo from the send queue: {error}");
+    }
+}
+
+/// Add local echoes for the events that were left in the persistent send
+/// queue of the room, e.g. by a previous run of the application, and send them
+/// again.
+#[instrument(skip_all, fields(room_id = ?room.room_id()))]
+pub(super) async fn restore_send_queue(
+    timeline_inner: &TimelineInner,
+    room: &Room,
+    msg_sender: &Sender<QueueRequest>,
+) {
+    let send_queue = match room.send_queue().await {
+        Ok(send_queue) => send_queue,
+        Err(error) => {
+            warn!("Failed to load the send queue: {error}");
+            return;
+        }
+    };
+
+    // The transaction IDs of the restored local echoes.
+    let mut restored = HashSet::new();
+
+    for event in send_queue.events().await {
+        let txn_id = event.transaction_id;
+
+        match event.content {
+            QueuedEventContent::Event { event_type, content } => {
+                let content = match content.deserialize_with_type(event_type) {
+                    Ok(content) => content,
+                    Err(error) => {
+                        warn!(?txn_id, "Dropping queued event with invalid content: {error}");
+                        forget_local_message(room, &txn_id).await;
+                        continue;
+                    }
+                };
+
+                debug!(?txn_id, "Restoring queued event");
+                timeline_inner.handle_local_event(txn_id.clone(), content.clone()).await;
+                restored.insert(txn_id.clone());
+
+                let content = LocalMessageContent::Event(content);
+                let msg = LocalMessage { content, txn_id };
+                if msg_sender.send(QueueRequest::Send(msg)).await.is_err() {
+                    error!("Internal error: timeline message receiver is closed");
+                }
+            }
+            QueuedEventContent::Attachment { path, mime_type } => {
+                debug!(?txn_id, "Restoring queued attachment");
+                restored.insert(txn_id.clone());
+                let mime_type = mime_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+                spawn(send_local_attachment(
+                    timeline_inner.clone(),
+                    txn_id,
+                    path,
+                    mime_type,
+                    AttachmentConfig::new(),
+                    Default::default(),
+                ));
+            }
+        }
+    }
+
+    for dependent in send_queue.dependent_requests().await {
+        let QueuedDependentRequest {
+            transaction_id: txn_id,
+            parent_transaction_id,
+            parent_event_id,
+            request,
+        } = dependent;
+
+        if parent_event_id.is_none() {
+            if !restored.contains(&parent_transaction_id) {
+                warn!(?txn_id, "Dropping queued request whose target was never sent");
+                forget_dependent_request(room, &txn_id).await;
+                continue;
+            }
+
+            // Show the request on the local echo again.
+            let result = match &request {
+                DependentRequest::Edit { new_content } => timeline_inner
+                    .edit_local_echo(&parent_transaction_id, new_content, true)
+                    .await
+                    .map(|_| ()),
+                DependentRequest::Redact { .. } => {
+                    timeline_inner.redact_local_echo(&parent_transaction_id).await.map(|_| ())
+                }
+                DependentRequest::React { key } => timeline_inner
+                    .add_local_echo_reaction(&parent_transaction_id, &txn_id, key)
+                    .await
+                    .map(|_| ()),
+            };
+            if let Err(error) = result {
+                warn!(?txn_id, "Failed to apply queued request to its local echo: {error}");
+            }
+        }
+
+        debug!(?txn_id, "Restoring queued request targeting a local echo");
+        let content = LocalMessageContent::Dependent {
+            parent_txn_id: parent_transaction_id,
+            parent_event_id,
+            request,
+        };
+        let msg = LocalMessage { content, txn_id };
+        if msg_sender.send(QueueRequest::Send(msg)).await.is_err() {
+            error!("Internal error: timeline message receiver is closed");
+        }
+    }
+}
+
+/// The content of an event replacing the content of the event with the given
+/// ID.
+pub(super) fn edit_content(
+    event_id: OwnedEventId,
+    new_content: RoomMessageEventContentWithoutRelation,
+) -> AnyMessageLikeEventContent {
+    let replacement = Replacement::new(event_id, new_content.clone());
+    let mut content = new_content.with_relation(None);
+    content.relates_to = Some(Relation::Replacement(replacement));
+    content.into()
 }
 
 #[instrument(skip_all, fields(room_id = ?room.room_id()))]
 pub(super) async fn send_queued_messages(
     timeline_inner: TimelineInner,
     room: Room,
-    mut msg_receiver: Receiver<LocalMessage>,
+    mut msg_receiver: Receiver<QueueRequest>,
 ) {
     let mut queue = VecDeque::new();
     let mut send_task: SendMessageTask = SendMessageTask::Idle;
-    let mut recv_fut: Either<_, Pending<Option<LocalMessage>>> =
+    let mut recv_fut: Either<_, Pending<Option<QueueRequest>>> =
         Either::Left(Box::pin(msg_receiver.recv()));
 
     loop {
@@ -67,15 +436,29 @@ pub(super) async fn send_queued_messages(
                 ).await;
             }
             recv_res = &mut recv_fut => {
-                recv_fut = if let Some(msg) = recv_res {
-                    trace!("Got a LocalMessage");
-                    handle_message(
-                        msg,
-                        room.clone(),
-                        &mut send_task,
-                        &mut queue,
-                        &timeline_inner,
-                    ).await;
+                recv_fut = if let Some(request) = recv_res {
+                    match request {
+                        QueueRequest::Send(msg) => {
+                            trace!("Got a LocalMessage");
+                            handle_message(
+                                msg,
+                                room.clone(),
+                                &mut send_task,
+                                &mut queue,
+                                &timeline_inner,
+                            ).await;
+                        }
+                        QueueRequest::LocalEcho(request) => {
+                            trace!("Got a LocalEchoRequest");
+                            handle_local_echo_request(
+                                request,
+                                room.clone(),
+                                &mut send_task,
+                                &mut queue,
+                                &timeline_inner,
+                            ).await;
+                        }
+                    }
 
                     // appease the borrow checker
                     drop(recv_fut);
@@ -106,6 +489,9 @@ async fn handle_message(
     if queue.is_empty() && send_task.is_idle() {
         if room.state() == RoomState::Joined {
             send_task.start(room, timeline_inner.clone(), msg);
+        } else if let LocalMessageContent::Dependent { .. } = msg.content {
+            info!("Refusing to send request, room is not joined");
+            forget_dependent_request(&room, &msg.txn_id).await;
         } else {
             info!("Refusing to send message, room is not joined");
             timeline_inner
@@ -123,6 +509,361 @@ async fn handle_message(
     }
 }
 
+async fn handle_local_echo_request(
+    request: LocalEchoRequest,
+    room: Room,
+    send_task: &mut SendMessageTask,
+    queue: &mut VecDeque<LocalMessage>,
+    timeline_inner: &TimelineInner,
+) {
+    let LocalEchoRequest { txn_id, action, result_sender } = request;
+
+    let result = match action {
+        LocalEchoAction::Abort => {
+            Ok(abort_sending(&room, &txn_id, send_task, queue, timeline_inner).await)
+        }
+        LocalEchoAction::Edit(new_content) => {
+            edit_message(room, &txn_id, new_content, send_task, queue, timeline_inner)
+                .await
+                .map(|()| true)
+        }
+        LocalEchoAction::Redact(reason) => {
+            redact_message(room, &txn_id, reason, send_task, queue, timeline_inner)
+                .await
+                .map(|()| true)
+        }
+        LocalEchoAction::Retry => {
+            retry_sending(room, &txn_id, send_task, queue, timeline_inner).await.map(|()| true)
+        }
+        LocalEchoAction::React(key) => {
+            react_to_message(room, &txn_id, key, send_task, queue, timeline_inner).await
+        }
+    };
+
+    // The requester might not wait for the result anymore.
+    _ = result_sender.send(result);
+}
+
+/// Abort sending the message with the given transaction ID, and discard its
+/// local echo.
+///
+/// Returns `false` if the message was already sent, or if it is an attachment.
+async fn abort_sending(
+    room: &Room,
+    txn_id: &TransactionId,
+    send_task: &SendMessageTask,
+    queue: &mut VecDeque<LocalMessage>,
+    timeline_inner: &TimelineInner,
+) -> bool {
+    if let Some(index) = queue.iter().position(|msg| msg.txn_id == txn_id) {
+        queue.remove(index);
+    } else if send_task.abort(txn_id) {
+        // The next message is sent once the task has stopped.
+        debug!(?txn_id, "Cancelled the request of the message");
+    } else {
+        match timeline_inner.local_echo_send_state(txn_id).await {
+            Some(EventSendState::SendingFailed { .. } | EventSendState::Cancelled) => {}
+            // Attachments are sent outside of the queue, they can't be
+            // aborted either.
+            _ => return false,
+        }
+    }
+
+    // The requests targeting the message can't be sent anymore.
+    let mut dependents = Vec::new();
+    queue.retain(|msg| match &msg.content {
+        LocalMessageContent::Dependent { parent_txn_id, .. } if parent_txn_id == txn_id => {
+            dependents.push(msg.txn_id.clone());
+            false
+        }
+        _ => true,
+    });
+    for dependent_txn_id in dependents {
+        forget_dependent_request(room, &dependent_txn_id).await;
+    }
+
+    debug!(?txn_id, "Aborting sending of message");
+    timeline_inner.discard_local_echo(txn_id).await;
+    abort_local_message(room, txn_id).await;
+    true
+}
+
+/// Replace the content of the message with the given transaction ID.
+///
+/// If the message is still in the queue, the new content is sent instead of
+/// the original one. Otherwise, it is sent as an edit once the message was
+/// sent, unless sending the message failed, in which case the new content is
+/// sent when retrying.
+async fn edit_message(
+    room: Room,
+    txn_id: &TransactionId,
+    new_content: RoomMessageEventContentWithoutRelation,
+    send_task: &mut SendMessageTask,
+    queue: &mut VecDeque<LocalMessage>,
+    timeline_inner: &TimelineInner,
+) -> Result<(), Error> {
+    if let Some(msg) = queue.iter_mut().find(|msg| msg.txn_id == txn_id) {
+        let LocalMessageContent::Event(AnyMessageLikeEventContent::RoomMessage(content)) =
+            &mut msg.content
+        else {
+            return Err(Error::UnsupportedEvent);
+        };
+
+        timeline_inner.edit_local_echo(txn_id, &new_content, false).await?;
+
+        debug!(?txn_id, "Replacing content of queued message");
+        *content = new_content.with_relation(content.relates_to.take());
+        let content = AnyMessageLikeEventContent::RoomMessage(content.clone());
+        replace_local_message(&room, txn_id, &content).await;
+
+        return Ok(());
+    }
+
+    let is_sending = send_task.is_sending(txn_id);
+    match timeline_inner.edit_local_echo(txn_id, &new_content, is_sending).await? {
+        EventSendState::Sent { event_id } => {
+            let content = edit_content(event_id, new_content);
+            let txn_id = TransactionId::new();
+            timeline_inner.handle_local_event(txn_id.clone(), content.clone()).await;
+            persist_local_message(&room, &txn_id, &content).await;
+
+            let content = LocalMessageContent::Event(content);
+            handle_message(
+                LocalMessage { txn_id, content },
+                room,
+                send_task,
+                queue,
+                timeline_inner,
+            )
+            .await;
+        }
+        EventSendState::NotSentYet | EventSendState::Sending { .. } => {
+            let request = DependentRequest::Edit { new_content };
+            queue_dependent_request(
+                room,
+                TransactionId::new(),
+                txn_id,
+                None,
+                request,
+                send_task,
+                queue,
+                timeline_inner,
+            )
+            .await;
+        }
+        EventSendState::SendingFailed { .. } | EventSendState::Cancelled => {}
+    }
+
+    Ok(())
+}
+
+/// Redact the message with the given transaction ID.
+///
+/// The local echo is shown as redacted right away, and the redaction is sent
+/// after the message, because it needs the ID of the event. If sending the
+/// message failed, the local echo is discarded instead.
+async fn redact_message(
+    room: Room,
+    txn_id: &TransactionId,
+    reason: Option<String>,
+    send_task: &mut SendMessageTask,
+    queue: &mut VecDeque<LocalMessage>,
+    timeline_inner: &TimelineInner,
+) -> Result<(), Error> {
+    let request = DependentRequest::Redact { reason };
+
+    match timeline_inner.redact_local_echo(txn_id).await? {
+        EventSendState::Sent { event_id } => {
+            queue_dependent_request(
+                room,
+                TransactionId::new(),
+                txn_id,
+                Some(event_id),
+                request,
+                send_task,
+                queue,
+                timeline_inner,
+            )
+            .await;
+        }
+        EventSendState::NotSentYet | EventSendState::Sending { .. } => {
+            queue_dependent_request(
+                room,
+                TransactionId::new(),
+                txn_id,
+                None,
+                request,
+                send_task,
+                queue,
+                timeline_inner,
+            )
+            .await;
+        }
+        EventSendState::SendingFailed { .. } | EventSendState::Cancelled => {
+            abort_sending(&room, txn_id, send_task, queue, timeline_inner).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Toggle the reaction with the given key on the message with the given
+/// transaction ID.
+///
+/// The reaction is sent after the message, because it needs the ID of the
+/// event. Toggling it again before it was sent cancels it. Returns `false` if
+/// the message was already sent, in which case the reaction must be toggled on
+/// the event instead.
+async fn react_to_message(
+    room: Room,
+    txn_id: &TransactionId,
+    key: String,
+    send_task: &mut SendMessageTask,
+    queue: &mut VecDeque<LocalMessage>,
+    timeline_inner: &TimelineInner,
+) -> Result<bool, Error> {
+    let queued_reaction = queue.iter().position(|msg| {
+        matches!(
+            &msg.content,
+            LocalMessageContent::Dependent {
+                parent_txn_id,
+                request: DependentRequest::React { key: queued_key },
+                ..
+            } if parent_txn_id == txn_id && *queued_key == key
+        )
+    });
+    if let Some(msg) = queued_reaction.and_then(|index| queue.remove(index)) {
+        debug!(txn_id = ?msg.txn_id, "Cancelling queued reaction");
+        if let LocalMessageContent::Dependent { parent_event_id, .. } = &msg.content {
+            timeline_inner
+                .remove_local_echo_reaction(txn_id, parent_event_id.as_deref(), &msg.txn_id, &key)
+                .await;
+        }
+        forget_dependent_request(&room, &msg.txn_id).await;
+        return Ok(true);
+    }
+
+    let reaction_txn_id = TransactionId::new();
+    let send_state = timeline_inner
+        .add_local_echo_reaction(txn_id, &reaction_txn_id, &key)
+        .await
+        .map_err(|_| Error::FailedToToggleReaction)?;
+
+    match send_state {
+        EventSendState::Sent { .. } => Ok(false),
+        EventSendState::NotSentYet | EventSendState::Sending { .. } => {
+            let request = DependentRequest::React { key };
+            queue_dependent_request(
+                room,
+                reaction_txn_id,
+                txn_id,
+                None,
+                request,
+                send_task,
+                queue,
+                timeline_inner,
+            )
+            .await;
+            Ok(true)
+        }
+        EventSendState::SendingFailed { .. } | EventSendState::Cancelled => {
+            warn!("Can't react to a local echo that failed to be sent");
+            Err(Error::FailedToToggleReaction)
+        }
+    }
+}
+
+/// Queue the request with the given transaction ID, targeting the local echo
+/// with the given parent transaction ID, to send it after the local echo.
+#[allow(clippy::too_many_arguments)]
+async fn queue_dependent_request(
+    room: Room,
+    txn_id: OwnedTransactionId,
+    parent_txn_id: &TransactionId,
+    parent_event_id: Option<OwnedEventId>,
+    request: DependentRequest,
+    send_task: &mut SendMessageTask,
+    queue: &mut VecDeque<LocalMessage>,
+    timeline_inner: &TimelineInner,
+) {
+    persist_dependent_request(&room, &txn_id, parent_txn_id, parent_event_id.as_deref(), &request)
+        .await;
+
+    let content = LocalMessageContent::Dependent {
+        parent_txn_id: parent_txn_id.to_owned(),
+        parent_event_id,
+        request,
+    };
+    handle_message(LocalMessage { txn_id, content }, room, send_task, queue, timeline_inner).await;
+}
+
+/// Retry sending the message with the given transaction ID, after it failed.
+///
+/// Returns an error without changing the local echo if it can't be sent again.
+async fn retry_sending(
+    room: Room,
+    txn_id: &TransactionId,
+    send_task: &mut SendMessageTask,
+    queue: &mut VecDeque<LocalMessage>,
+    timeline_inner: &TimelineInner,
+) -> Result<(), Error> {
+    let content = timeline_inner.prepare_retry(txn_id, retry_content).await?;
+
+    persist_local_message(&room, txn_id, &content).await;
+    let msg =
+        LocalMessage { txn_id: txn_id.to_owned(), content: LocalMessageContent::Event(content) };
+    handle_message(msg, room, send_task, queue, timeline_inner).await;
+
+    Ok(())
+}
+
+/// The content to send when retrying to send a local echo with the given
+/// content.
+///
+/// Returns an error if it can't be sent again.
+fn retry_content(content: &TimelineItemContent) -> Result<AnyMessageLikeEventContent, Error> {
+    macro_rules! error_return {
+        ($msg:literal) => {{
+            error!($msg);
+            return Err(Error::UnsupportedEvent);
+        }};
+    }
+
+    let content = match content {
+        TimelineItemContent::Message(msg) if is_local_echo_attachment(msg.msgtype()) => {
+            error_return!("Retrying attachments is not currently supported");
+        }
+        TimelineItemContent::Message(msg) => {
+            AnyMessageLikeEventContent::RoomMessage(msg.clone().into())
+        }
+        TimelineItemContent::RedactedMessage => {
+            error_return!("Invalid state: attempting to retry a redacted message");
+        }
+        TimelineItemContent::Sticker(sticker) => {
+            AnyMessageLikeEventContent::Sticker(sticker.content.clone())
+        }
+        TimelineItemContent::UnableToDecrypt(_) => {
+            error_return!("Invalid state: attempting to retry a UTD item");
+        }
+        TimelineItemContent::MembershipChange(_)
+        | TimelineItemContent::ProfileChange(_)
+        | TimelineItemContent::OtherState(_)
+        | TimelineItemContent::LiveLocation(_)
+        | TimelineItemContent::CollapsedStateEvents(_) => {
+            error_return!("Retrying state events is not currently supported");
+        }
+        TimelineItemContent::FailedToParseMessageLike { .. }
+        | TimelineItemContent::FailedToParseState { .. } => {
+            error_return!("Invalid state: attempting to retry a failed-to-parse item");
+        }
+        TimelineItemContent::Poll(poll_state) => {
+            AnyMessageLikeEventContent::UnstablePollStart(poll_state.clone().into())
+        }
+    };
+
+    Ok(content)
+}
+
 async fn handle_task_ready(
     result: SendMessageResult,
     send_task: &mut SendMessageTask,
@@ -130,19 +871,28 @@ async fn handle_task_ready(
     timeline_inner: &TimelineInner,
 ) {
     match result {
-        SendMessageResult::Success { room } => {
+        SendMessageResult::Success { room, sent_event } => {
+            if let Some((txn_id, event_id)) = sent_event {
+                resolve_dependent_requests(queue, &txn_id, &event_id);
+            }
+
             if let Some(msg) = queue.pop_front() {
                 send_task.start(room, timeline_inner.clone(), msg);
             }
         }
+        SendMessageResult::Aborted => {
+            if let Some(msg) = queue.pop_front() {
+                send_task.start(timeline_inner.room().clone(), timeline_inner.clone(), msg);
+            }
+        }
         SendMessageResult::SendingFailed => {
             // Timeline items are marked as failed / cancelled in this case.
             // Clear the timeline and wait for the user to explicitly retry.
-            queue.clear();
+            clear_queue(queue, timeline_inner).await;
         }
         SendMessageResult::TaskError { join_error, txn_id } => {
             error!("Message-sending task failed: {join_error}");
-            queue.clear();
+            clear_queue(queue, timeline_inner).await;
 
             let send_state = EventSendState::SendingFailed {
                 // FIXME: Probably not exactly right
@@ -153,6 +903,51 @@ async fn handle_task_ready(
     }
 }
 
+/// Set the event ID of the local echo with the given transaction ID in the
+/// queued requests that target it.
+fn resolve_dependent_requests(
+    queue: &mut VecDeque<LocalMessage>,
+    txn_id: &TransactionId,
+    event_id: &OwnedEventId,
+) {
+    for msg in queue {
+        if let LocalMessageContent::Dependent { parent_txn_id, parent_event_id, .. } =
+            &mut msg.content
+        {
+            if parent_txn_id == txn_id {
+                *parent_event_id = Some(event_id.clone());
+            }
+        }
+    }
+}
+
+/// Clear the queue after a message failed to be sent.
+///
+/// The queued messages can be sent again by retrying, but the requests
+/// targeting local echoes are dropped: edits were applied to the content of
+/// their local echo, that is sent when retrying, and reactions are removed
+/// from their local echo.
+async fn clear_queue(queue: &mut VecDeque<LocalMessage>, timeline_inner: &TimelineInner) {
+    for msg in queue.drain(..) {
+        if let LocalMessageContent::Dependent { parent_txn_id, parent_event_id, request } =
+            msg.content
+        {
+            debug!(txn_id = ?msg.txn_id, "Dropping queued request targeting a local echo");
+            if let DependentRequest::React { key } = &request {
+                timeline_inner
+                    .remove_local_echo_reaction(
+                        &parent_txn_id,
+                        parent_event_id.as_deref(),
+                        &msg.txn_id,
+                        key,
+                    )
+                    .await;
+            }
+            forget_dependent_request(timeline_inner.room(), &msg.txn_id).await;
+        }
+    }
+}
+
 /// Result of [`SendMessageTask`].
 enum SendMessageResult {
     /// The message was sent successfully, and the local echo was updated to
@@ -161,7 +956,12 @@ enum SendMessageResult {
         /// The joined room object, used to start sending of the next message
         /// in the queue, if it isn't empty.
         room: Room,
+        /// The transaction ID and event ID of the sent message, if it has a
+        /// local echo.
+        sent_event: Option<(OwnedTransactionId, OwnedEventId)>,
     },
+    /// Sending was aborted, and the local echo was discarded.
+    Aborted,
     /// Sending failed, and the local echo was updated to indicate this.
     SendingFailed,
     /// The [`SendMessageTask`] failed, likely due to a panic.
@@ -186,7 +986,13 @@ enum SendMessageTask {
         /// The transaction ID of the message that is being sent.
         txn_id: OwnedTransactionId,
         /// Handle to the task itself.
-        join_handle: JoinHandle<Option<Room>>,
+        ///
+        /// It returns the room to send the next message, and the event ID of
+        /// the sent message if it has a local echo, or `None` if sending
+        /// failed.
+        join_handle: JoinHandle<Result<Option<(Room, Option<OwnedEventId>)>, Aborted>>,
+        /// Handle to abort sending the message.
+        abort_handle: AbortHandle,
     },
 }
 
@@ -196,20 +1002,73 @@ impl SendMessageTask {
         matches!(self, Self::Idle)
     }
 
+    /// Whether the message with the given transaction ID is being sent.
+    fn is_sending(&self, txn_id: &TransactionId) -> bool {
+        matches!(self, Self::Running { txn_id: sending_txn_id, .. } if sending_txn_id == txn_id)
+    }
+
+    /// Abort sending the message with the given transaction ID, if it is being
+    /// sent.
+    ///
+    /// Returns whether the message was being sent.
+    fn abort(&self, txn_id: &TransactionId) -> bool {
+        match self {
+            Self::Running { txn_id: sending_txn_id, abort_handle, .. }
+                if sending_txn_id == txn_id =>
+            {
+                abort_handle.abort();
+                true
+            }
+            _ => false,
+        }
+    }
+
     fn start(&mut self, room: Room, timeline_inner: TimelineInner, msg: LocalMessage) {
         debug!("Spawning message-sending task");
         let txn_id = msg.txn_id.clone();
-        let join_handle = spawn(async move {
-            let result = room.send(msg.content, Some(&msg.txn_id)).await;
-            let (room, send_state) = match result {
-                Ok(response) => (Some(room), EventSendState::Sent { event_id: response.event_id }),
-                Err(error) => (None, EventSendState::SendingFailed { error: Arc::new(error) }),
-            };
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let send = async move {
+            match msg.content {
+                LocalMessageContent::Event(content) => {
+                    send_event(room, timeline_inner, msg.txn_id, content).await
+                }
+                LocalMessageContent::Dependent { parent_txn_id, parent_event_id, request } => {
+                    let parent_event_id = match parent_event_id {
+                        Some(event_id) => Some(event_id),
+                        // The local echo is not sent by the queue, like attachments.
+                        None => timeline_inner.wait_for_local_echo_sent(&parent_txn_id).await,
+                    };
 
-            timeline_inner.update_event_send_state(&msg.txn_id, send_state).await;
-            room
-        });
-        *self = Self::Running { txn_id, join_handle };
+                    let sent = if let Some(parent_event_id) = &parent_event_id {
+                        send_dependent_request(&room, &msg.txn_id, parent_event_id, &request).await
+                    } else {
+                        warn!(txn_id = ?msg.txn_id, "Target of the request wasn't sent, dropping it");
+                        forget_dependent_request(&room, &msg.txn_id).await;
+                        false
+                    };
+
+                    // Remove the local echo of a reaction that won't be sent.
+                    match &request {
+                        DependentRequest::React { key } if !sent => {
+                            timeline_inner
+                                .remove_local_echo_reaction(
+                                    &parent_txn_id,
+                                    parent_event_id.as_deref(),
+                                    &msg.txn_id,
+                                    key,
+                                )
+                                .await;
+                        }
+                        _ => {}
+                    }
+
+                    // Failing to send the request doesn't affect the next messages.
+                    Some((room, None))
+                }
+            }
+        };
+        let join_handle = spawn(Abortable::new(send, abort_registration));
+        *self = Self::Running { txn_id, join_handle, abort_handle };
     }
 
     fn reset(&mut self) {
@@ -223,7 +1082,7 @@ impl Future for SendMessageTask {
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match &mut *self {
             SendMessageTask::Idle => Poll::Pending,
-            SendMessageTask::Running { txn_id, join_handle } => {
+            SendMessageTask::Running { txn_id, join_handle, .. } => {
                 Pin::new(join_handle).poll(cx).map(|result| {
                     let txn_id = mem::replace(txn_id, OwnedTransactionId::from(""));
                     if txn_id.as_str().is_empty() {
@@ -231,8 +1090,12 @@ impl Future for SendMessageTask {
                     }
 
                     match result {
-                        Ok(Some(room)) => SendMessageResult::Success { room },
-                        Ok(None) => SendMessageResult::SendingFailed,
+                        Ok(Ok(Some((room, event_id)))) => SendMessageResult::Success {
+                            room,
+                            sent_event: event_id.map(|event_id| (txn_id, event_id)),
+                        },
+                        Ok(Ok(None)) => SendMessageResult::SendingFailed,
+                        Ok(Err(Aborted)) => SendMessageResult::Aborted,
                         Err(join_error) => SendMessageResult::TaskError { join_error, txn_id },
                     }
                 })
@@ -240,3 +1103,83 @@ impl Future for SendMessageTask {
         }
     }
 }
+
+/// Send a message-like event with a local echo, and update the send state of
+/// the local echo.
+async fn send_event(
+    room: Room,
+    timeline_inner: TimelineInner,
+    txn_id: OwnedTransactionId,
+    content: AnyMessageLikeEventContent,
+) -> Option<(Room, Option<OwnedEventId>)> {
+    // Messages are retried while the homeserver can't be reached, and
+    // removed from the persistent send queue once they were sent.
+    let send_queue = room.send_queue().await;
+    let send = || room.send(content.clone(), Some(&txn_id));
+    let result = match &send_queue {
+        Ok(send_queue) => send_queue.send_with_retry(&txn_id, send).await,
+        Err(error) => {
+            warn!("Failed to load the send queue: {error}");
+            send().await
+        }
+    };
+
+    match result {
+        Ok(response) => {
+            let event_id = response.event_id;
+
+            // Persist the event ID for the requests targeting this event, in
+            // case the application is restarted before they are sent.
+            if let Ok(send_queue) = &send_queue {
+                if let Err(error) = send_queue.resolve_dependents(&txn_id, &event_id).await {
+                    warn!("Failed to update the requests targeting the event: {error}");
+                }
+            }
+
+            let send_state = EventSendState::Sent { event_id: event_id.clone() };
+            timeline_inner.update_event_send_state(&txn_id, send_state).await;
+            Some((room, Some(event_id)))
+        }
+        // The local echo was discarded, the next messages can be sent.
+        Err(matrix_sdk::Error::SendAborted) => Some((room, None)),
+        Err(error) => {
+            let send_state = EventSendState::SendingFailed { error: Arc::new(error) };
+            timeline_inner.update_event_send_state(&txn_id, send_state).await;
+            None
+        }
+    }
+}
+
+/// Send a request targeting the event with the given ID.
+///
+/// The request is only sent once, and removed from the persistent send queue
+/// of the room whether it succeeded or not. Returns whether it succeeded.
+async fn send_dependent_request(
+    room: &Room,
+    txn_id: &TransactionId,
+    parent_event_id: &EventId,
+    request: &DependentRequest,
+) -> bool {
+    let result = match request {
+        DependentRequest::Edit { new_content } => {
+            let content = edit_content(parent_event_id.to_owned(), new_content.clone());
+            room.send(content, Some(txn_id)).await.map(|_| ())
+        }
+        DependentRequest::Redact { reason } => room
+            .redact(parent_event_id, reason.as_deref(), Some(txn_id.to_owned()))
+            .await
+            .map(|_| ())
+            .map_err(Into::into),
+        DependentRequest::React { key } => {
+            let annotation = Annotation::new(parent_event_id.to_owned(), key.clone());
+            room.send(ReactionEventContent::new(annotation), Some(txn_id)).await.map(|_| ())
+        }
+    };
+
+    if let Err(error) = &result {
+        warn!(?txn_id, "Failed to send request targeting {parent_event_id}: {error}");
+    }
+
+    forget_dependent_request(room, txn_id).await;
+    result.is_ok()
+}
@@ -21,6 +21,7 @@ use ruma::{
     events::receipt::{Receipt, ReceiptEventContent, ReceiptThread, ReceiptType},
     EventId, OwnedEventId, OwnedUserId, UserId,
 };
+use serde::Deserialize;
 use tracing::{error, warn};
 
 use super::{
@@ -29,7 +30,7 @@ use super::{
     item::timeline_item,
     traits::RoomDataProvider,
     util::{compare_events_positions, rfind_event_by_id, RelativePosition},
-    EventTimelineItem, TimelineItem,
+    EventTimelineItem, TimelineItem, TimelineItemContent,
 };
 
 struct FullReceipt<'a> {
@@ -68,12 +69,26 @@ impl TimelineInnerState {
                 }
 
                 for (user_id, receipt) in receipts {
+                    if let ReceiptThread::Thread(thread_root) = &receipt.thread {
+                        let thread_root = thread_root.clone();
+                        self.maybe_update_thread_read_receipt(
+                            user_id,
+                            thread_root,
+                            receipt_type.clone(),
+                            event_id.clone(),
+                            receipt,
+                        );
+                        continue;
+                    }
+
+                    // We don't support receipts for the main timeline yet.
                     if receipt.thread != ReceiptThread::Unthreaded {
                         continue;
                     }
 
+                    let visible_event_id = self.visible_event_id(&event_id);
                     let receipt_item_pos =
-                        rfind_event_by_id(&self.items, &event_id).map(|(pos, _)| pos);
+                        rfind_event_by_id(&self.items, visible_event_id).map(|(pos, _)| pos);
                     let is_own_user_id = user_id == own_user_id;
                     let full_receipt = FullReceipt {
                         event_id: &event_id,
@@ -88,6 +103,7 @@ impl TimelineInnerState {
                         is_own_user_id,
                         &mut self.items,
                         &mut self.users_read_receipts,
+                        &self.hidden_events,
                     );
 
                     if read_receipt_updated && !is_own_user_id {
@@ -98,6 +114,47 @@ impl TimelineInnerState {
         }
     }
 
+    /// Update the read receipt of the given type of the given user in the
+    /// given thread, if it is more recent than the current one.
+    ///
+    /// Returns true if the read receipt was saved.
+    pub(super) fn maybe_update_thread_read_receipt(
+        &mut self,
+        user_id: OwnedUserId,
+        thread_root: OwnedEventId,
+        receipt_type: ReceiptType,
+        event_id: OwnedEventId,
+        receipt: Receipt,
+    ) -> bool {
+        let thread_receipts = self
+            .users_thread_read_receipts
+            .entry(user_id)
+            .or_default()
+            .entry(thread_root)
+            .or_default();
+
+        if let Some((old_event_id, _)) = thread_receipts.get(&receipt_type) {
+            if *old_event_id == event_id {
+                // Nothing to do.
+                return false;
+            }
+
+            // If we can't compare the positions of the events, the new receipt
+            // is deemed more recent, like for unthreaded receipts.
+            let old_event_id = self.hidden_events.get(old_event_id).unwrap_or(old_event_id);
+            let new_event_id = self.hidden_events.get(&event_id).unwrap_or(&event_id);
+            if compare_events_positions(old_event_id, new_event_id, &self.items)
+                .is_some_and(|pos| pos == RelativePosition::Before)
+            {
+                // The old receipt is more recent than the new one.
+                return false;
+            }
+        }
+
+        thread_receipts.insert(receipt_type, (event_id, receipt));
+        true
+    }
+
     /// Load the read receipts from the store for the given event ID.
     pub(super) async fn load_read_receipts_for_event<P: RoomDataProvider>(
         &mut self,
@@ -151,6 +208,32 @@ impl TimelineInnerState {
             })
     }
 
+    /// Get the receipt of the given type for the given user in the given
+    /// thread.
+    pub(super) async fn user_thread_receipt(
+        &self,
+        user_id: &UserId,
+        receipt_type: ReceiptType,
+        thread_root: &EventId,
+        room: &Room,
+    ) -> Option<(OwnedEventId, Receipt)> {
+        if let Some(receipt) = self
+            .users_thread_read_receipts
+            .get(user_id)
+            .and_then(|threads| threads.get(thread_root))
+            .and_then(|thread_map| thread_map.get(&receipt_type))
+            .cloned()
+        {
+            return Some(receipt);
+        }
+
+        let thread = ReceiptThread::Thread(thread_root.to_owned());
+        room.user_receipt(receipt_type.clone(), thread, user_id).await.unwrap_or_else(|e| {
+            error!("Could not get user thread receipt of type {receipt_type:?}: {e}");
+            None
+        })
+    }
+
     /// Get the latest read receipt for the given user.
     ///
     /// Useful to get the latest read receipt, whether it's private or public.
@@ -162,6 +245,77 @@ impl TimelineInnerState {
         let public_read_receipt = self.user_receipt(user_id, ReceiptType::Read, room).await;
         let private_read_receipt = self.user_receipt(user_id, ReceiptType::ReadPrivate, room).await;
 
+        self.latest_read_receipt(public_read_receipt, private_read_receipt)
+    }
+
+    /// Get the latest read receipt for the given user in the given thread.
+    ///
+    /// Useful to get the latest read receipt, whether it's private or public.
+    pub(super) async fn latest_user_thread_read_receipt(
+        &self,
+        user_id: &UserId,
+        thread_root: &EventId,
+        room: &Room,
+    ) -> Option<(OwnedEventId, Receipt)> {
+        let public_read_receipt =
+            self.user_thread_receipt(user_id, ReceiptType::Read, thread_root, room).await;
+        let private_read_receipt =
+            self.user_thread_receipt(user_id, ReceiptType::ReadPrivate, thread_root, room).await;
+
+        self.latest_read_receipt(public_read_receipt, private_read_receipt)
+    }
+
+    /// Get the number of messages in the given thread that are more recent
+    /// than the latest read receipt of the given user in the thread.
+    ///
+    /// Only the messages in the timeline are counted, and the count stops at
+    /// the latest message of the user in the thread, which is an implicit
+    /// read receipt.
+    pub(super) async fn thread_unread_count(
+        &self,
+        user_id: &UserId,
+        thread_root: &EventId,
+        room: &Room,
+    ) -> usize {
+        let receipt_event_id = self
+            .latest_user_thread_read_receipt(user_id, thread_root, room)
+            .await
+            .map(|(event_id, _)| event_id);
+        let receipt_event_id = receipt_event_id.as_deref().map(|id| self.visible_event_id(id));
+
+        let mut count = 0;
+        for item in self.items.iter().rev() {
+            let Some(event) = item.as_event() else {
+                continue;
+            };
+
+            if receipt_event_id.is_some() && event.event_id() == receipt_event_id {
+                break;
+            }
+            if event_thread_root(event).as_deref() != Some(thread_root) {
+                continue;
+            }
+            if event.sender() == user_id {
+                break;
+            }
+
+            if let TimelineItemContent::Message(_)
+            | TimelineItemContent::Sticker(_)
+            | TimelineItemContent::Poll(_) = event.content()
+            {
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    /// Get the latest of the given public and private read receipts.
+    fn latest_read_receipt(
+        &self,
+        public_read_receipt: Option<(OwnedEventId, Receipt)>,
+        private_read_receipt: Option<(OwnedEventId, Receipt)>,
+    ) -> Option<(OwnedEventId, Receipt)> {
         // If we only have one, return it.
         let Some((pub_event_id, pub_receipt)) = &public_read_receipt else {
             return private_read_receipt;
@@ -210,6 +364,7 @@ pub(super) fn maybe_add_implicit_read_receipt(
     is_own_event: bool,
     timeline_items: &mut ObservableVector<Arc<TimelineItem>>,
     users_read_receipts: &mut HashMap<OwnedUserId, HashMap<ReceiptType, (OwnedEventId, Receipt)>>,
+    hidden_events: &HashMap<OwnedEventId, OwnedEventId>,
 ) {
     let EventTimelineItemKind::Remote(remote_event_item) = &mut event_item.kind else {
         return;
@@ -229,6 +384,7 @@ pub(super) fn maybe_add_implicit_read_receipt(
         is_own_event,
         timeline_items,
         users_read_receipts,
+        hidden_events,
     );
     if read_receipt_updated && !is_own_event {
         remote_event_item.add_read_receipt(event_item.sender.clone(), receipt);
@@ -252,6 +408,7 @@ fn maybe_update_read_receipt(
     is_own_user_id: bool,
     timeline_items: &mut ObservableVector<Arc<TimelineItem>>,
     users_read_receipts: &mut HashMap<OwnedUserId, HashMap<ReceiptType, (OwnedEventId, Receipt)>>,
+    hidden_events: &HashMap<OwnedEventId, OwnedEventId>,
 ) -> bool {
     let old_event_id = users_read_receipts
         .get(receipt.user_id)
@@ -262,7 +419,10 @@ fn maybe_update_read_receipt(
         return false;
     }
 
-    let old_item_and_pos = old_event_id.and_then(|e| rfind_event_by_id(timeline_items, e));
+    // The old receipt may point to an event hidden by the event filter.
+    let old_item_and_pos = old_event_id
+        .map(|e| hidden_events.get(e).unwrap_or(e))
+        .and_then(|e| rfind_event_by_id(timeline_items, e));
     if let Some((old_receipt_pos, old_event_item)) = old_item_and_pos {
         let Some(new_receipt_pos) = new_item_pos else {
             // The old receipt is likely more recent since we can't find the
@@ -310,3 +470,26 @@ fn maybe_update_read_receipt(
 
     true
 }
+
+/// Get the root event of the thread that the given event is part of, if any.
+fn event_thread_root(event: &EventTimelineItem) -> Option<OwnedEventId> {
+    #[derive(Deserialize)]
+    struct RelatesTo {
+        rel_type: Option<String>,
+        event_id: Option<OwnedEventId>,
+    }
+
+    #[derive(Deserialize)]
+    struct Content {
+        #[serde(rename = "m.relates_to")]
+        relates_to: Option<RelatesTo>,
+    }
+
+    let content = event.original_json()?.get_field::<Content>("content").ok()??;
+    let relates_to = content.relates_to?;
+    if relates_to.rel_type.as_deref() != Some("m.thread") {
+        return None;
+    }
+
+    relates_to.event_id
+}
@@ -19,18 +19,22 @@ use matrix_sdk_test::async_test;
 use ruma::{
     assign,
     events::{
-        relation::{InReplyTo, Thread},
+        relation::{InReplyTo, Replacement, Thread},
         room::{
             member::{MembershipState, RedactedRoomMemberEventContent, RoomMemberEventContent},
-            message::{MessageType, Relation, RoomMessageEventContent},
+            message::{
+                MessageType, Relation, RoomMessageEventContent,
+                RoomMessageEventContentWithoutRelation,
+            },
             name::RoomNameEventContent,
             topic::RedactedRoomTopicEventContent,
         },
-        FullStateEventContent,
+        FullStateEventContent, Mentions,
     },
+    MilliSecondsSinceUnixEpoch,
 };
 use serde_json::json;
-use stream_assert::assert_next_matches;
+use stream_assert::{assert_next_matches, assert_pending};
 
 use super::{sync_timeline_event, TestTimeline, ALICE, BOB};
 use crate::timeline::{
@@ -391,3 +395,131 @@ async fn thread() {
     let replied_to_event = assert_matches!(&in_reply_to.event, TimelineDetails::Ready(msg) => msg);
     assert_eq!(replied_to_event.sender(), *ALICE);
 }
+
+#[async_test]
+async fn user_mentions() {
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_message_event(
+            &BOB,
+            RoomMessageEventContent::text_plain("Hi Alice")
+                .add_mentions(Mentions::with_user_ids([ALICE.to_owned()])),
+        )
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert!(item.is_user_mentioned());
+    let message = assert_matches!(item.content(), TimelineItemContent::Message(msg) => msg);
+    assert!(message.mentions().unwrap().user_ids.contains(*ALICE));
+
+    timeline
+        .handle_live_message_event(
+            &BOB,
+            RoomMessageEventContent::text_plain("Hi everyone")
+                .add_mentions(Mentions::with_room_mention()),
+        )
+        .await;
+
+    // Bob doesn't have the power level to notify the whole room.
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert!(!item.is_user_mentioned());
+
+    timeline
+        .handle_live_message_event(
+            &CAROL,
+            RoomMessageEventContent::text_plain("Hi everyone")
+                .add_mentions(Mentions::with_room_mention()),
+        )
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert!(item.is_user_mentioned());
+
+    timeline
+        .handle_live_message_event(
+            &BOB,
+            RoomMessageEventContent::text_plain("Hi Carol")
+                .add_mentions(Mentions::with_user_ids([CAROL.to_owned()])),
+        )
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert!(!item.is_user_mentioned());
+    let event_id = item.event_id().unwrap().to_owned();
+
+    // An edit that adds a mention of the own user highlights the message.
+    let edit = assign!(RoomMessageEventContent::text_plain(" * Hi Alice"), {
+        relates_to: Some(Relation::Replacement(Replacement::new(
+            event_id,
+            assign!(
+                RoomMessageEventContentWithoutRelation::from(MessageType::text_plain("Hi Alice")),
+                { mentions: Some(Mentions::with_user_ids([ALICE.to_owned()])) }
+            ),
+        ))),
+    });
+    timeline.handle_live_message_event(&BOB, edit).await;
+
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 3, value } => value);
+    assert!(item.is_user_mentioned());
+
+    // Mentions of the own user in own messages don't count.
+    timeline
+        .handle_live_message_event(
+            &ALICE,
+            RoomMessageEventContent::text_plain("Note to self")
+                .add_mentions(Mentions::with_user_ids([ALICE.to_owned()])),
+        )
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert!(!item.is_user_mentioned());
+}
+
+#[async_test]
+async fn history_cutoff() {
+    let timeline = TestTimeline::new();
+
+    timeline.set_next_ts(1000);
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("Old")).await;
+    timeline.set_next_ts(2000);
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("New")).await;
+    assert_eq!(timeline.inner.items().await.len(), 3);
+
+    // Events older than the cutoff are removed.
+    timeline.inner.set_history_cutoff(Some(MilliSecondsSinceUnixEpoch(1500_u32.into()))).await;
+
+    let items = timeline.inner.items().await;
+    assert_eq!(items.len(), 2);
+    assert!(items[0].is_day_divider());
+    let message = assert_matches!(
+        items[1].as_event().unwrap().content(),
+        TimelineItemContent::Message(msg) => msg
+    );
+    assert_eq!(message.body(), "New");
+
+    // New events older than the cutoff are ignored.
+    timeline.set_next_ts(1200);
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("Late")).await;
+    assert_eq!(timeline.inner.items().await.len(), 2);
+}
+
+#[async_test]
+async fn batched_updates() {
+    let timeline = TestTimeline::new();
+    let (_, stream) = timeline.inner.subscribe_batched().await;
+    let mut stream = Box::pin(stream);
+
+    let batch_guard = timeline.inner.start_batch();
+    timeline.handle_live_message_event(&ALICE, RoomMessageEventContent::text_plain("A")).await;
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("B")).await;
+    assert_pending!(stream);
+
+    drop(batch_guard);
+
+    // One day divider, two event items.
+    let batch = assert_next_matches!(stream, batch => batch);
+    assert_eq!(batch.len(), 3);
+    assert_pending!(stream);
+}
@@ -0,0 +1,132 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use assert_matches::assert_matches;
+use eyeball_im::VectorDiff;
+use matrix_sdk_test::async_test;
+use ruma::events::{
+    room::{
+        member::{MembershipState, RoomMemberEventContent},
+        message::RoomMessageEventContent,
+        name::RoomNameEventContent,
+    },
+    FullStateEventContent,
+};
+use stream_assert::assert_next_matches;
+
+use super::{TestTimeline, ALICE, BOB};
+use crate::timeline::{inner::TimelineInnerSettings, TimelineItemContent};
+
+fn collapsing_timeline() -> TestTimeline {
+    TestTimeline::new()
+        .with_settings(TimelineInnerSettings { collapse_state_events: true, ..Default::default() })
+}
+
+#[async_test]
+async fn consecutive_state_events_are_collapsed() {
+    let timeline = collapsing_timeline();
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_state_event_with_state_key(
+            &BOB,
+            BOB.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_matches!(item.content(), TimelineItemContent::MembershipChange(_));
+    let group_event_id = item.event_id().unwrap().to_owned();
+
+    timeline
+        .handle_live_state_event(
+            &ALICE,
+            RoomNameEventContent::new(Some("Alice's room".to_owned())),
+            None,
+        )
+        .await;
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 0, value } => value);
+    assert_eq!(item.event_id(), Some(&*group_event_id));
+    let group = assert_matches!(item.content(), TimelineItemContent::CollapsedStateEvents(g) => g);
+    assert_eq!(group.events().len(), 2);
+    assert!(!group.is_expanded());
+    assert_matches!(group.events()[0].content(), TimelineItemContent::MembershipChange(_));
+    assert_matches!(group.events()[1].content(), TimelineItemContent::OtherState(_));
+
+    // A message interrupts the group.
+    timeline.handle_live_message_event(&ALICE, RoomMessageEventContent::text_plain("hi")).await;
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_matches!(item.content(), TimelineItemContent::Message(_));
+
+    timeline
+        .handle_live_state_event(&ALICE, RoomNameEventContent::new(Some("Room".to_owned())), None)
+        .await;
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_matches!(item.content(), TimelineItemContent::OtherState(_));
+
+    // Expand the group.
+    assert!(timeline.inner.set_state_events_expanded(&group_event_id, true).await);
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 0, value } => value);
+    let group = assert_matches!(item.content(), TimelineItemContent::CollapsedStateEvents(g) => g);
+    assert!(group.is_expanded());
+
+    // The other items are not groups.
+    let message_event_id = timeline.inner.items().await[2].as_event().unwrap().event_id();
+    let message_event_id = message_event_id.unwrap().to_owned();
+    assert!(!timeline.inner.set_state_events_expanded(&message_event_id, true).await);
+}
+
+#[async_test]
+async fn redact_collapsed_state_event() {
+    let timeline = collapsing_timeline();
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_state_event_with_state_key(
+            &BOB,
+            BOB.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+    let _item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+
+    timeline
+        .handle_live_state_event_with_state_key(
+            &ALICE,
+            ALICE.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 0, value } => value);
+    let group = assert_matches!(item.content(), TimelineItemContent::CollapsedStateEvents(g) => g);
+    let redacted_event_id = group.events()[1].event_id().unwrap().to_owned();
+
+    timeline.handle_live_redaction(&ALICE, &redacted_event_id).await;
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 0, value } => value);
+    let group = assert_matches!(item.content(), TimelineItemContent::CollapsedStateEvents(g) => g);
+
+    let membership = assert_matches!(
+        group.events()[0].content(),
+        TimelineItemContent::MembershipChange(ev) => ev
+    );
+    assert_matches!(membership.content(), FullStateEventContent::Original { .. });
+    let membership = assert_matches!(
+        group.events()[1].content(),
+        TimelineItemContent::MembershipChange(ev) => ev
+    );
+    assert_matches!(membership.content(), FullStateEventContent::Redacted(_));
+}
@@ -19,6 +19,7 @@ use std::{io::Cursor, iter};
 use assert_matches::assert_matches;
 use eyeball_im::VectorDiff;
 use matrix_sdk::crypto::{decrypt_room_key_export, OlmMachine};
+use matrix_sdk_crypto::types::events::room_key_withheld::WithheldCode;
 use matrix_sdk_test::async_test;
 use ruma::{
     assign,
@@ -26,12 +27,12 @@ use ruma::{
         EncryptedEventScheme, MegolmV1AesSha2ContentInit, Relation, Replacement,
         RoomEncryptedEventContent,
     },
-    room_id, user_id,
+    room_id, uint, user_id, MilliSecondsSinceUnixEpoch,
 };
 use stream_assert::assert_next_matches;
 
-use super::{TestTimeline, BOB};
-use crate::timeline::{EncryptedMessage, TimelineItemContent};
+use super::{assert_no_more_updates, TestRoomDataProvider, TestTimeline, BOB};
+use crate::timeline::{EncryptedMessage, TimelineItem, TimelineItemContent, UtdCause};
 
 #[async_test]
 async fn retry_message_decryption() {
@@ -83,13 +84,14 @@ async fn retry_message_decryption() {
     let _day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
     let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
     let event = item.as_event().unwrap();
-    let session_id = assert_matches!(
+    let (session_id, cause) = assert_matches!(
         event.content(),
         TimelineItemContent::UnableToDecrypt(
-            EncryptedMessage::MegolmV1AesSha2 { session_id, .. },
-        ) => session_id
+            EncryptedMessage::MegolmV1AesSha2 { session_id, cause, .. },
+        ) => (session_id, cause)
     );
     assert_eq!(session_id, SESSION_ID);
+    assert_eq!(*cause, UtdCause::MissingRoomKey);
 
     let own_user_id = user_id!("@example:morheus.localhost");
     let exported_keys = decrypt_room_key_export(Cursor::new(SESSION_KEY), "1234").unwrap();
@@ -408,3 +410,115 @@ async fn retry_message_decryption_highlighted() {
     assert_eq!(text, "A secret to everybody but Alice");
     assert!(event.is_highlighted());
 }
+
+fn utd_content(session_id: &str) -> RoomEncryptedEventContent {
+    RoomEncryptedEventContent::new(
+        EncryptedEventScheme::MegolmV1AesSha2(
+            MegolmV1AesSha2ContentInit {
+                ciphertext: "ciphertext".to_owned(),
+                sender_key: "sender_key".to_owned(),
+                device_id: "DEVICEID".into(),
+                session_id: session_id.to_owned(),
+            }
+            .into(),
+        ),
+        None,
+    )
+}
+
+fn utd_cause(item: &TimelineItem) -> UtdCause {
+    assert_matches!(
+        item.as_event().unwrap().content(),
+        TimelineItemContent::UnableToDecrypt(
+            EncryptedMessage::MegolmV1AesSha2 { cause, .. },
+        ) => cause.clone()
+    )
+}
+
+#[async_test]
+async fn utd_cause_withheld() {
+    let room_data_provider = TestRoomDataProvider::default();
+    room_data_provider.withhold_room_key("blacklisted", WithheldCode::Blacklisted);
+    room_data_provider.withhold_room_key("unverified", WithheldCode::Unverified);
+
+    let timeline = TestTimeline::with_room_data_provider(room_data_provider);
+    let mut stream = timeline.subscribe().await;
+
+    timeline.handle_live_message_event(&BOB, utd_content("blacklisted")).await;
+    timeline.handle_live_message_event(&BOB, utd_content("unverified")).await;
+
+    let _day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::Withheld(WithheldCode::Blacklisted));
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::UnverifiedDevice);
+}
+
+#[async_test]
+async fn utd_cause_sent_before_join() {
+    // The first event is sent at 0, the second one at 1.
+    let room_data_provider =
+        TestRoomDataProvider::default().with_own_join_ts(MilliSecondsSinceUnixEpoch(uint!(1)));
+
+    let timeline = TestTimeline::with_room_data_provider(room_data_provider);
+    let mut stream = timeline.subscribe().await;
+
+    timeline.handle_live_message_event(&BOB, utd_content("before_join")).await;
+    timeline.handle_live_message_event(&BOB, utd_content("after_join")).await;
+
+    let _day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::SentBeforeJoin);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::MissingRoomKey);
+}
+
+#[async_test]
+async fn utd_cause_backup_not_connected() {
+    let room_data_provider = TestRoomDataProvider::default()
+        .with_own_join_ts(MilliSecondsSinceUnixEpoch(uint!(1)))
+        .with_backup_missing_decryption_key();
+    room_data_provider.withhold_room_key("unverified", WithheldCode::Unverified);
+
+    let timeline = TestTimeline::with_room_data_provider(room_data_provider);
+    let mut stream = timeline.subscribe().await;
+
+    // The more specific causes take precedence.
+    timeline.handle_live_message_event(&BOB, utd_content("before_join")).await;
+    timeline.handle_live_message_event(&BOB, utd_content("unverified")).await;
+    timeline.handle_live_message_event(&BOB, utd_content("not_backed_up")).await;
+
+    let _day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::SentBeforeJoin);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::UnverifiedDevice);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::BackupNotConnected);
+}
+
+#[async_test]
+async fn utd_cause_is_updated_when_room_key_is_withheld() {
+    let room_data_provider = TestRoomDataProvider::default();
+    let timeline = TestTimeline::with_room_data_provider(room_data_provider.clone());
+    let mut stream = timeline.subscribe().await;
+
+    timeline.handle_live_message_event(&BOB, utd_content("withheld_later")).await;
+    timeline.handle_live_message_event(&BOB, utd_content("other")).await;
+
+    let _day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::MissingRoomKey);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::MissingRoomKey);
+
+    room_data_provider.withhold_room_key("withheld_later", WithheldCode::Unverified);
+    timeline.inner.update_utd_causes("withheld_later").await;
+
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    assert_eq!(utd_cause(&item), UtdCause::UnverifiedDevice);
+
+    // Nothing changes if the cause is the same.
+    timeline.inner.update_utd_causes("withheld_later").await;
+    assert_no_more_updates(&mut stream).await;
+}
@@ -18,7 +18,7 @@ use assert_matches::assert_matches;
 use eyeball_im::VectorDiff;
 use matrix_sdk_test::async_test;
 use ruma::{
-    assign,
+    assign, event_id,
     events::{
         reaction::ReactionEventContent,
         relation::{Annotation, Replacement},
@@ -29,14 +29,14 @@ use ruma::{
             },
             name::RoomNameEventContent,
         },
-        AnySyncTimelineEvent,
+        AnyMessageLikeEventContent, AnySyncTimelineEvent,
     },
 };
 use serde_json::json;
 use stream_assert::assert_next_matches;
 
 use super::{TestTimeline, ALICE, BOB};
-use crate::timeline::{inner::TimelineInnerSettings, TimelineItemContent};
+use crate::timeline::{inner::TimelineInnerSettings, TimelineItemContent, VirtualTimelineItem};
 
 #[async_test]
 async fn default_filter() {
@@ -208,3 +208,80 @@ async fn hide_failed_to_parse() {
 
     assert_eq!(timeline.inner.items().await.len(), 0);
 }
+
+#[async_test]
+async fn hidden_remote_echo_removes_local_echo() {
+    let timeline = TestTimeline::new().with_settings(TimelineInnerSettings {
+        event_filter: Arc::new(|_| false),
+        ..Default::default()
+    });
+    let mut stream = timeline.subscribe().await;
+
+    // Local echoes are never filtered.
+    let txn_id = timeline
+        .handle_local_event(AnyMessageLikeEventContent::RoomMessage(
+            RoomMessageEventContent::text_plain("echo"),
+        ))
+        .await;
+    let _day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let _local_echo = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+
+    // When the hidden remote echo comes in…
+    timeline
+        .handle_live_custom_event(json!({
+            "content": {
+                "body": "echo",
+                "msgtype": "m.text",
+            },
+            "sender": &*ALICE,
+            "event_id": "$eeG0HA0FAZ37wP8kXlNkxx3I",
+            "origin_server_ts": 6,
+            "type": "m.room.message",
+            "unsigned": {
+                "transaction_id": txn_id,
+            },
+        }))
+        .await;
+
+    // … the local echo is removed, along with its day divider.
+    assert_next_matches!(stream, VectorDiff::Remove { index: 1 });
+    assert_next_matches!(stream, VectorDiff::Remove { index: 0 });
+    assert_eq!(timeline.inner.items().await.len(), 0);
+}
+
+#[async_test]
+async fn read_marker_on_hidden_event() {
+    // Filter out all state events.
+    let timeline = TestTimeline::new().with_settings(TimelineInnerSettings {
+        event_filter: Arc::new(|ev| matches!(ev, AnySyncTimelineEvent::MessageLike(_))),
+        ..Default::default()
+    });
+    let mut stream = timeline.subscribe().await;
+
+    timeline.handle_live_message_event(&ALICE, RoomMessageEventContent::text_plain("A")).await;
+    let _day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let _item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+
+    let hidden_event_id = event_id!("$hidden_name_event");
+    timeline
+        .handle_live_custom_event(json!({
+            "content": { "name": "Alice's room" },
+            "event_id": hidden_event_id,
+            "origin_server_ts": 10,
+            "sender": &*ALICE,
+            "state_key": "",
+            "type": "m.room.name",
+        }))
+        .await;
+
+    // The hidden event stands for the first message, which is the last item,
+    // so the marker is not added yet.
+    timeline.inner.set_fully_read_event(hidden_event_id.to_owned()).await;
+
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("B")).await;
+    let _item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+
+    // Now the read marker appears after the first message.
+    let item = assert_next_matches!(stream, VectorDiff::Insert { index: 2, value } => value);
+    assert_matches!(item.as_virtual(), Some(VirtualTimelineItem::ReadMarker));
+}
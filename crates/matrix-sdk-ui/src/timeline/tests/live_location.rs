@@ -0,0 +1,153 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{sync::Arc, time::Duration};
+
+use assert_matches::assert_matches;
+use matrix_sdk_test::async_test;
+use ruma::{
+    events::{beacon::BeaconEventContent, beacon_info::BeaconInfoEventContent},
+    MilliSecondsSinceUnixEpoch, OwnedEventId, UInt,
+};
+use serde_json::json;
+
+use super::{TestTimeline, ALICE};
+use crate::timeline::{live_location::LiveLocationState, TimelineItem, TimelineItemContent};
+
+impl TestTimeline {
+    async fn start_live_location_share(&self) -> OwnedEventId {
+        let content = BeaconInfoEventContent::new(None, Duration::from_secs(60), true, None);
+        self.handle_live_state_event_with_state_key(&ALICE, ALICE.to_owned(), content, None).await;
+
+        let items = self.inner.items().await;
+        items.last().unwrap().as_event().unwrap().event_id().unwrap().to_owned()
+    }
+
+    async fn send_beacon(&self, beacon_info_event_id: OwnedEventId, geo_uri: &str, ts: u32) {
+        let ts = MilliSecondsSinceUnixEpoch(UInt::from(ts));
+        let content = BeaconEventContent::new(beacon_info_event_id, geo_uri.to_owned(), Some(ts));
+        self.handle_live_message_event(&ALICE, content).await;
+    }
+
+    async fn live_location_state(&self) -> LiveLocationState {
+        let items = self.inner.items().await;
+        let event = items.last().unwrap().as_event().unwrap();
+        assert_matches!(event.content(), TimelineItemContent::LiveLocation(state) => state.clone())
+    }
+}
+
+#[async_test]
+async fn beacons_update_the_live_location() {
+    let timeline = TestTimeline::new();
+    let beacon_info_event_id = timeline.start_live_location_share().await;
+
+    let state = timeline.live_location_state().await;
+    assert!(state.is_live());
+    assert!(state.last_location().is_none());
+
+    timeline.send_beacon(beacon_info_event_id.clone(), "geo:51.5008,0.1247", 2000).await;
+    let state = timeline.live_location_state().await;
+    assert_eq!(state.last_location().unwrap().geo_uri, "geo:51.5008,0.1247");
+
+    // An older location is ignored.
+    timeline.send_beacon(beacon_info_event_id, "geo:48.8567,2.3508", 1000).await;
+    let state = timeline.live_location_state().await;
+    assert_eq!(state.last_location().unwrap().geo_uri, "geo:51.5008,0.1247");
+
+    // Beacons are not shown as separate items.
+    assert_eq!(timeline.len().await, 2);
+}
+
+#[async_test]
+async fn stopping_the_share_updates_the_item() {
+    let timeline = TestTimeline::new();
+    timeline.start_live_location_share().await;
+
+    let content = BeaconInfoEventContent::new(None, Duration::from_secs(60), false, None);
+    timeline.handle_live_state_event_with_state_key(&ALICE, ALICE.to_owned(), content, None).await;
+
+    let state = timeline.live_location_state().await;
+    assert!(!state.is_live());
+    assert_eq!(timeline.len().await, 2);
+}
+
+#[async_test]
+async fn beacon_before_beacon_info_is_applied() {
+    let timeline = TestTimeline::new();
+    let beacon_info_event_id = OwnedEventId::try_from("$beacon_info").unwrap();
+
+    timeline.send_beacon(beacon_info_event_id, "geo:51.5008,0.1247", 2000).await;
+    assert_eq!(timeline.len().await, 0);
+
+    timeline
+        .handle_live_custom_event(json!({
+            "content": {
+                "live": true,
+                "timeout": 60000,
+                "org.matrix.msc3488.ts": 1000,
+            },
+            "event_id": "$beacon_info",
+            "origin_server_ts": 1000,
+            "sender": *ALICE,
+            "state_key": *ALICE,
+            "type": "org.matrix.msc3672.beacon_info",
+        }))
+        .await;
+
+    let state = timeline.live_location_state().await;
+    assert_eq!(state.last_location().unwrap().geo_uri, "geo:51.5008,0.1247");
+}
+
+#[async_test]
+async fn back_paginated_stop_only_stops_older_shares() {
+    let timeline = TestTimeline::new();
+
+    let beacon_info = |event_id: &str, live: bool, ts: u64| {
+        json!({
+            "content": {
+                "live": live,
+                "timeout": 60000,
+                "org.matrix.msc3488.ts": ts,
+            },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": *ALICE,
+            "state_key": *ALICE,
+            "type": "org.matrix.msc3672.beacon_info",
+        })
+    };
+    let is_stopped = |items: &[Arc<TimelineItem>], event_id: &str| {
+        let event = items
+            .iter()
+            .filter_map(|item| item.as_event())
+            .find(|event| event.event_id().is_some_and(|id| id.as_str() == event_id))
+            .unwrap();
+        assert_matches!(event.content(), TimelineItemContent::LiveLocation(state) => state.is_stopped())
+    };
+
+    timeline.handle_live_custom_event(beacon_info("$new_share", true, 3000)).await;
+
+    // The stop of an older share is loaded, it doesn't stop the newer one.
+    timeline.handle_back_paginated_custom_event(beacon_info("$old_stop", false, 2000)).await;
+    let items = timeline.inner.items().await.iter().cloned().collect::<Vec<_>>();
+    assert_eq!(items.len(), 2);
+    assert!(!is_stopped(&items, "$new_share"));
+
+    // The older share is loaded afterwards, it is stopped.
+    timeline.handle_back_paginated_custom_event(beacon_info("$old_share", true, 1000)).await;
+    let items = timeline.inner.items().await.iter().cloned().collect::<Vec<_>>();
+    assert_eq!(items.len(), 3);
+    assert!(is_stopped(&items, "$old_share"));
+    assert!(!is_stopped(&items, "$new_share"));
+}
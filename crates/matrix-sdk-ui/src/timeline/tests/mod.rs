@@ -15,10 +15,10 @@
 //! Unit tests (based on private methods) for the timeline API.
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     sync::{
         atomic::{AtomicU64, Ordering::SeqCst},
-        Arc,
+        Arc, Mutex as StdMutex,
     },
 };
 
@@ -29,12 +29,14 @@ use futures_core::Stream;
 use futures_util::{FutureExt, StreamExt};
 use indexmap::IndexMap;
 use matrix_sdk::deserialized_responses::{SyncTimelineEvent, TimelineEvent};
+use matrix_sdk_crypto::types::events::room_key_withheld::WithheldCode;
 use once_cell::sync::Lazy;
 use ruma::{
     events::{
         receipt::{Receipt, ReceiptEventContent, ReceiptThread, ReceiptType},
         relation::Annotation,
         room::redaction::RoomRedactionEventContent,
+        typing::TypingEventContent,
         AnyMessageLikeEventContent, AnySyncTimelineEvent, EmptyStateKey, MessageLikeEventContent,
         RedactedMessageLikeEventContent, RedactedStateEventContent, StateEventContent,
         StaticStateEventContent,
@@ -58,12 +60,14 @@ use super::{
 };
 
 mod basic;
+mod collapsed_state;
 mod echo;
 mod edit;
 #[cfg(feature = "e2e-encryption")]
 mod encryption;
 mod event_filter;
 mod invalid;
+mod live_location;
 mod polls;
 mod reaction_group;
 mod reactions;
@@ -87,7 +91,11 @@ struct TestTimeline {
 
 impl TestTimeline {
     fn new() -> Self {
-        Self { inner: TimelineInner::new(TestRoomDataProvider), next_ts: AtomicU64::new(0) }
+        Self::with_room_data_provider(TestRoomDataProvider::default())
+    }
+
+    fn with_room_data_provider(room_data_provider: TestRoomDataProvider) -> Self {
+        Self { inner: TimelineInner::new(room_data_provider), next_ts: AtomicU64::new(0) }
     }
 
     fn with_settings(mut self, settings: TimelineInnerSettings) -> Self {
@@ -241,6 +249,10 @@ impl TestTimeline {
         self.inner.handle_read_receipts(ev_content).await;
     }
 
+    async fn handle_typing(&self, user_ids: Vec<OwnedUserId>) {
+        self.inner.handle_typing(TypingEventContent::new(user_ids)).await;
+    }
+
     async fn toggle_reaction_local(
         &self,
         annotation: &Annotation,
@@ -403,8 +415,31 @@ impl TestTimeline {
     }
 }
 
-#[derive(Clone)]
-struct TestRoomDataProvider;
+#[derive(Clone, Default)]
+struct TestRoomDataProvider {
+    /// The codes of the room keys that were withheld, by session ID.
+    withheld_codes: Arc<StdMutex<HashMap<String, WithheldCode>>>,
+    /// The time when Alice joined the room.
+    own_join_ts: Option<MilliSecondsSinceUnixEpoch>,
+    /// Whether a key backup exists, but its decryption key isn't known.
+    backup_missing_decryption_key: bool,
+}
+
+impl TestRoomDataProvider {
+    fn with_own_join_ts(mut self, ts: MilliSecondsSinceUnixEpoch) -> Self {
+        self.own_join_ts = Some(ts);
+        self
+    }
+
+    fn with_backup_missing_decryption_key(mut self) -> Self {
+        self.backup_missing_decryption_key = true;
+        self
+    }
+
+    fn withhold_room_key(&self, session_id: &str, code: WithheldCode) {
+        self.withheld_codes.lock().unwrap().insert(session_id.to_owned(), code);
+    }
+}
 
 #[async_trait]
 impl RoomDataProvider for TestRoomDataProvider {
@@ -431,13 +466,25 @@ impl RoomDataProvider for TestRoomDataProvider {
             member_count: uint!(2),
             user_id: ALICE.to_owned(),
             user_display_name: "Alice".to_owned(),
-            users_power_levels: BTreeMap::new(),
+            users_power_levels: BTreeMap::from([(CAROL.to_owned(), int!(50))]),
             default_power_level: int!(0),
             notification_power_levels: NotificationPowerLevels::new(),
         };
 
         Some((push_rules, push_context))
     }
+
+    async fn room_key_withheld_code(&self, session_id: &str) -> Option<WithheldCode> {
+        self.withheld_codes.lock().unwrap().get(session_id).cloned()
+    }
+
+    async fn own_join_timestamp(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.own_join_ts
+    }
+
+    async fn is_backup_missing_decryption_key(&self) -> bool {
+        self.backup_missing_decryption_key
+    }
 }
 
 pub(super) async fn assert_event_is_updated(
@@ -158,6 +158,21 @@ async fn a_somewhat_complex_voting_session_yields_the_expected_outcome() {
     assert_eq!(results.votes["id_down"], vec![ALICE.to_string()]);
 }
 
+#[async_test]
+async fn vote_counts_and_own_votes_are_computed() {
+    let timeline = TestTimeline::new();
+    timeline.send_poll_start(&ALICE, fakes::poll_a()).await;
+    let poll_id = timeline.poll_event().await.event_id().unwrap().to_owned();
+
+    timeline.send_poll_response(&ALICE, vec!["id_up"], &poll_id).await;
+    timeline.send_poll_response(&BOB, vec!["id_up"], &poll_id).await;
+    let results = timeline.poll_state().await.results();
+
+    assert_eq!(results.vote_count("id_up"), 2);
+    assert_eq!(results.vote_count("id_down"), 0);
+    assert_eq!(results.votes_of(&ALICE), vec!["id_up".to_owned()]);
+}
+
 #[async_test]
 async fn events_received_before_start_are_not_lost() {
     let timeline = TestTimeline::new();
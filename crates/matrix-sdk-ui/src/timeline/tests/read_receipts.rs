@@ -14,9 +14,13 @@
 
 use eyeball_im::VectorDiff;
 use matrix_sdk_test::async_test;
-use ruma::events::{
-    receipt::{ReceiptThread, ReceiptType},
-    room::message::RoomMessageEventContent,
+use ruma::{
+    assign,
+    events::{
+        receipt::{ReceiptThread, ReceiptType},
+        relation::Thread,
+        room::message::{Relation, RoomMessageEventContent},
+    },
 };
 use stream_assert::assert_next_matches;
 
@@ -82,3 +86,66 @@ async fn read_receipts_updates() {
     assert_eq!(event_d.read_receipts().len(), 1);
     assert!(event_d.read_receipts().get(*BOB).is_some());
 }
+
+#[async_test]
+async fn thread_read_receipts() {
+    let timeline = TestTimeline::new()
+        .with_settings(TimelineInnerSettings { track_read_receipts: true, ..Default::default() });
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline.handle_live_message_event(*ALICE, RoomMessageEventContent::text_plain("A")).await;
+    let item_a = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let thread_root = item_a.event_id().unwrap().to_owned();
+
+    // Implicit read receipt of Bob in the thread.
+    let content = assign!(RoomMessageEventContent::text_plain("B"), {
+        relates_to: Some(Relation::Thread(Thread::plain(thread_root.clone(), thread_root.clone()))),
+    });
+    timeline.handle_live_message_event(*BOB, content).await;
+    let item_b = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let event_b = item_b.event_id().unwrap().to_owned();
+    assert_eq!(
+        timeline.inner.thread_read_receipt_event_id(*BOB, &thread_root).await.as_deref(),
+        Some(&*event_b)
+    );
+
+    let content = assign!(RoomMessageEventContent::text_plain("C"), {
+        relates_to: Some(Relation::Thread(Thread::plain(thread_root.clone(), event_b.clone()))),
+    });
+    timeline.handle_live_message_event(*ALICE, content).await;
+    let item_c = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let event_c = item_c.event_id().unwrap().to_owned();
+
+    // An older explicit read receipt in the thread is ignored.
+    timeline
+        .handle_read_receipts([(
+            thread_root.clone(),
+            ReceiptType::Read,
+            BOB.to_owned(),
+            ReceiptThread::Thread(thread_root.clone()),
+        )])
+        .await;
+    assert_eq!(
+        timeline.inner.thread_read_receipt_event_id(*BOB, &thread_root).await.as_deref(),
+        Some(&*event_b)
+    );
+
+    // A newer explicit read receipt in the thread is saved.
+    timeline
+        .handle_read_receipts([(
+            event_c.clone(),
+            ReceiptType::Read,
+            BOB.to_owned(),
+            ReceiptThread::Thread(thread_root.clone()),
+        )])
+        .await;
+    assert_eq!(
+        timeline.inner.thread_read_receipt_event_id(*BOB, &thread_root).await.as_deref(),
+        Some(&*event_c)
+    );
+
+    // Threaded read receipts are not displayed on the items.
+    let items = timeline.inner.items().await;
+    let item_c = items.last().unwrap().as_event().unwrap();
+    assert!(item_c.read_receipts().get(*BOB).is_none());
+}
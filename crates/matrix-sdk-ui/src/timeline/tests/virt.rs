@@ -20,9 +20,9 @@ use ruma::{
     event_id,
     events::{room::message::RoomMessageEventContent, AnyMessageLikeEventContent},
 };
-use stream_assert::assert_next_matches;
+use stream_assert::{assert_next_matches, assert_pending};
 
-use super::{TestTimeline, ALICE, BOB};
+use super::{TestTimeline, ALICE, BOB, CAROL};
 use crate::timeline::{TimelineItemKind, VirtualTimelineItem};
 
 #[async_test]
@@ -155,3 +155,49 @@ async fn update_read_marker() {
     let marker = assert_next_matches!(stream, VectorDiff::Insert { index: 4, value } => value);
     assert_matches!(marker.kind, TimelineItemKind::Virtual(VirtualTimelineItem::ReadMarker));
 }
+
+#[async_test]
+async fn typing_users() {
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe().await;
+
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("A")).await;
+    let day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert!(day_divider.is_day_divider());
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    item.as_event().unwrap();
+
+    // The own user is never shown as typing.
+    timeline.handle_typing(vec![ALICE.to_owned(), BOB.to_owned()]).await;
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let profiles = assert_matches!(
+        item.as_virtual(),
+        Some(VirtualTimelineItem::TypingUsers(profiles)) => profiles
+    );
+    assert_eq!(profiles.len(), 1);
+
+    // The item isn't updated if the typing users didn't change.
+    timeline.handle_typing(vec![BOB.to_owned()]).await;
+    assert_pending!(stream);
+
+    timeline.handle_typing(vec![BOB.to_owned(), CAROL.to_owned()]).await;
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 2, value } => value);
+    let profiles = assert_matches!(
+        item.as_virtual(),
+        Some(VirtualTimelineItem::TypingUsers(profiles)) => profiles
+    );
+    assert_eq!(profiles.len(), 2);
+
+    // New events are added before the typing users.
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("B")).await;
+    assert_next_matches!(stream, VectorDiff::PopBack);
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    item.as_event().unwrap();
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert!(item.is_typing_users());
+
+    // The item is removed when nobody is typing anymore.
+    timeline.handle_typing(vec![]).await;
+    assert_next_matches!(stream, VectorDiff::PopBack);
+    assert_eq!(timeline.len().await, 3);
+}
@@ -0,0 +1,95 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use matrix_sdk::{
+    room::{Messages, RelationsOptions, Room},
+    Result,
+};
+use ruma::{
+    assign,
+    events::{
+        relation::RelationType,
+        room::{encrypted, message},
+        AnyMessageLikeEventContent, AnySyncTimelineEvent,
+    },
+    EventId, OwnedEventId, UInt,
+};
+
+use super::inner::TimelineEventFilterFn;
+
+/// Whether the given event is the root of the thread or one of its replies.
+fn is_in_thread(event: &AnySyncTimelineEvent, root_event_id: &EventId) -> bool {
+    if event.event_id() == root_event_id {
+        return true;
+    }
+
+    let AnySyncTimelineEvent::MessageLike(event) = event else {
+        return false;
+    };
+
+    match event.original_content() {
+        Some(AnyMessageLikeEventContent::RoomMessage(c)) => matches!(
+            c.relates_to,
+            Some(message::Relation::Thread(thread)) if thread.event_id == root_event_id
+        ),
+        // The relation of encrypted events is sent in clear, so they can be
+        // filtered before being decrypted.
+        Some(AnyMessageLikeEventContent::RoomEncrypted(c)) => matches!(
+            c.relates_to,
+            Some(encrypted::Relation::Thread(thread)) if thread.event_id == root_event_id
+        ),
+        _ => false,
+    }
+}
+
+/// Wrap the given event filter so that it only accepts the events of the
+/// thread with the given root.
+pub(super) fn wrap_filter(
+    root_event_id: OwnedEventId,
+    filter: Arc<TimelineEventFilterFn>,
+) -> Arc<TimelineEventFilterFn> {
+    Arc::new(move |ev: &AnySyncTimelineEvent| is_in_thread(ev, &root_event_id) && filter(ev))
+}
+
+/// Get a batch of events of the thread with the given root, from the most
+/// recent to the oldest, in the same form as a `/messages` response.
+///
+/// Once the start of the thread is reached, the root event is added at the
+/// end of the chunk.
+pub(super) async fn thread_messages(
+    room: &Room,
+    root_event_id: &EventId,
+    from: Option<String>,
+    limit: u16,
+) -> Result<Messages> {
+    let options = assign!(RelationsOptions::with_rel_type(RelationType::Thread), {
+        from: from.clone(),
+        limit: Some(UInt::from(limit)),
+    });
+    let relations = room.relations(root_event_id, options).await?;
+
+    let mut chunk = relations.chunk;
+    if relations.next_batch_token.is_none() {
+        chunk.push(room.event(root_event_id).await?);
+    }
+
+    Ok(Messages {
+        start: from.unwrap_or_default(),
+        end: relations.next_batch_token,
+        chunk,
+        state: Vec::new(),
+    })
+}
@@ -17,13 +17,17 @@ use indexmap::IndexMap;
 use matrix_sdk::Room;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk::{deserialized_responses::TimelineEvent, Result};
+use matrix_sdk_crypto::types::events::room_key_withheld::WithheldCode;
+#[cfg(feature = "e2e-encryption")]
+use ruma::{events::AnySyncTimelineEvent, serde::Raw};
 use ruma::{
-    events::receipt::{Receipt, ReceiptThread, ReceiptType},
+    events::{
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        room::member::MembershipState,
+    },
     push::{PushConditionRoomCtx, Ruleset},
-    EventId, OwnedUserId, RoomVersionId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedUserId, RoomVersionId, UserId,
 };
-#[cfg(feature = "e2e-encryption")]
-use ruma::{events::AnySyncTimelineEvent, serde::Raw};
 use tracing::{debug, error, warn};
 
 use super::{Profile, TimelineBuilder};
@@ -69,6 +73,25 @@ pub(super) trait RoomDataProvider: Clone + Send + Sync + 'static {
     async fn profile(&self, user_id: &UserId) -> Option<Profile>;
     async fn read_receipts_for_event(&self, event_id: &EventId) -> IndexMap<OwnedUserId, Receipt>;
     async fn push_rules_and_context(&self) -> Option<(Ruleset, PushConditionRoomCtx)>;
+    /// Get the reason why the sender withheld the room key of the given
+    /// session, if they told us so.
+    ///
+    /// By default, no room key is withheld.
+    async fn room_key_withheld_code(&self, _session_id: &str) -> Option<WithheldCode> {
+        None
+    }
+    /// Get the time when the current user joined the room, if it is known.
+    ///
+    /// By default, it isn't known.
+    async fn own_join_timestamp(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        None
+    }
+    /// Whether a key backup exists, but its decryption key isn't known.
+    ///
+    /// By default, there is no key backup.
+    async fn is_backup_missing_decryption_key(&self) -> bool {
+        false
+    }
 }
 
 #[async_trait]
@@ -133,6 +156,51 @@ impl RoomDataProvider for Room {
             }
         }
     }
+
+    #[cfg(feature = "e2e-encryption")]
+    async fn room_key_withheld_code(&self, session_id: &str) -> Option<WithheldCode> {
+        match self.client().encryption().get_withheld_info(self.room_id(), session_id).await {
+            Ok(code) => code,
+            Err(e) => {
+                error!(session_id, "Failed to get the withheld info of the room key: {e}");
+                None
+            }
+        }
+    }
+
+    async fn own_join_timestamp(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        match self.get_member_no_sync(self.own_user_id()).await {
+            // Only trust the timestamp of our member event if it is the one
+            // where we joined, and not a later profile change.
+            Ok(Some(member)) => {
+                member.event().as_sync().and_then(|ev| ev.as_original()).and_then(|ev| {
+                    let was_joined = ev
+                        .unsigned
+                        .prev_content
+                        .as_ref()
+                        .is_some_and(|c| c.membership == MembershipState::Join);
+                    (ev.content.membership == MembershipState::Join && !was_joined)
+                        .then_some(ev.origin_server_ts)
+                })
+            }
+            Ok(None) => None,
+            Err(e) => {
+                error!("Failed to fetch own room member information: {e}");
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "backups")]
+    async fn is_backup_missing_decryption_key(&self) -> bool {
+        match self.client().encryption().backups().is_missing_decryption_key().await {
+            Ok(missing) => missing,
+            Err(e) => {
+                warn!("Failed to check whether the backup decryption key is known: {e}");
+                false
+            }
+        }
+    }
 }
 
 // Internal helper to make most of retry_event_decryption independent of a room
@@ -16,9 +16,11 @@ use std::{ops::Deref, sync::Arc};
 
 use chrono::{Datelike, Local, TimeZone};
 use imbl::Vector;
-use ruma::{EventId, MilliSecondsSinceUnixEpoch};
+use ruma::{events::Mentions, EventId, MilliSecondsSinceUnixEpoch, UserId};
 
-use super::{event_item::EventTimelineItemKind, EventTimelineItem, TimelineItem};
+use super::{
+    event_item::EventTimelineItemKind, traits::RoomDataProvider, EventTimelineItem, TimelineItem,
+};
 
 pub(super) struct EventTimelineItemWithId<'a> {
     pub inner: &'a EventTimelineItem,
@@ -120,3 +122,32 @@ pub(super) fn timestamp_to_date(ts: MilliSecondsSinceUnixEpoch) -> Date {
 
     Date { year: datetime.year(), month: datetime.month(), day: datetime.day() }
 }
+
+/// Whether the own user is mentioned in the given intentional mentions of an
+/// event sent by `sender`.
+///
+/// A room mention only counts if the sender has the power level to notify the
+/// whole room.
+pub(super) async fn is_user_mentioned<P: RoomDataProvider>(
+    room_data_provider: &P,
+    sender: &UserId,
+    mentions: Option<&Mentions>,
+) -> bool {
+    let own_user_id = room_data_provider.own_user_id();
+    let Some(mentions) = mentions.filter(|_| sender != own_user_id) else {
+        return false;
+    };
+
+    if mentions.user_ids.contains(own_user_id) {
+        return true;
+    }
+    if !mentions.room {
+        return false;
+    }
+
+    room_data_provider.push_rules_and_context().await.is_some_and(|(_, context)| {
+        let sender_power_level =
+            context.users_power_levels.get(sender).copied().unwrap_or(context.default_power_level);
+        sender_power_level >= context.notification_power_levels.room
+    })
+}
@@ -14,6 +14,8 @@
 
 use ruma::MilliSecondsSinceUnixEpoch;
 
+use super::Profile;
+
 /// A [`TimelineItem`](super::TimelineItem) that doesn't correspond to an event.
 #[derive(Clone, Debug)]
 pub enum VirtualTimelineItem {
@@ -25,4 +27,12 @@ pub enum VirtualTimelineItem {
 
     /// The user's own read marker.
     ReadMarker,
+
+    /// The other members of the room that are currently typing.
+    ///
+    /// This item is only added if it was enabled with
+    /// [`TimelineBuilder::show_typing_users`][super::TimelineBuilder::show_typing_users].
+    /// It is always the last item of the timeline, and it is removed when
+    /// nobody is typing anymore.
+    TypingUsers(Vec<Profile>),
 }
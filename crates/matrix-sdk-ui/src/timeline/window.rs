@@ -0,0 +1,258 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Subscription to a movable window of the timeline items.
+
+use std::ops::Range;
+
+use eyeball::SharedObservable;
+use eyeball_im::VectorDiff;
+use imbl::Vector;
+
+/// A handle to move the window of a subscription created with
+/// [`Timeline::subscribe_window()`][super::Timeline::subscribe_window].
+#[derive(Clone, Debug)]
+pub struct TimelineWindow {
+    range: SharedObservable<Range<usize>>,
+}
+
+impl TimelineWindow {
+    pub(super) fn new(range: SharedObservable<Range<usize>>) -> Self {
+        Self { range }
+    }
+
+    /// Get the range of indices of the timeline items in the window.
+    pub fn range(&self) -> Range<usize> {
+        self.range.get()
+    }
+
+    /// Move the window to the given range of indices of the timeline items.
+    ///
+    /// The subscription yields a [`VectorDiff::Reset`] with the items in the
+    /// new window, unless the range didn't change.
+    pub fn set_range(&self, range: Range<usize>) {
+        self.range.set_if_not_eq(range);
+    }
+}
+
+/// The items of a window of the timeline, and the translation of the diffs of
+/// all the timeline items to diffs of the items in the window.
+///
+/// Only the items in the window are kept, with the number of items in the
+/// whole timeline. When an item that is outside of the window enters it, for
+/// example because an item of the window was removed, the window can't be
+/// updated from the diff alone and must be rebuilt from all the items.
+pub(super) struct Window<T: Clone> {
+    items: Vector<T>,
+    len: usize,
+    range: Range<usize>,
+}
+
+impl<T: Clone> Window<T> {
+    /// Create a window with the given range of the given items.
+    pub(super) fn new(all_items: &Vector<T>, range: Range<usize>) -> Self {
+        let items = all_items.iter().skip(range.start).take(range.len()).cloned().collect();
+        Self { items, len: all_items.len(), range }
+    }
+
+    /// The items in the window.
+    pub(super) fn values(&self) -> Vector<T> {
+        self.items.clone()
+    }
+
+    /// Apply the given diff of all the items, and push the resulting diffs of
+    /// the items in the window to `out`.
+    ///
+    /// Returns `false` if the window needs an item that is outside of it, in
+    /// which case it must be rebuilt from all the items.
+    pub(super) fn apply(&mut self, diff: VectorDiff<T>, out: &mut Vec<VectorDiff<T>>) -> bool {
+        match diff {
+            VectorDiff::Append { values } => {
+                values.into_iter().all(|value| self.insert(self.len, value, out))
+            }
+            VectorDiff::Clear => {
+                self.len = 0;
+                if !self.items.is_empty() {
+                    self.items.clear();
+                    out.push(VectorDiff::Clear);
+                }
+                true
+            }
+            VectorDiff::PushFront { value } => self.insert(0, value, out),
+            VectorDiff::PushBack { value } => self.insert(self.len, value, out),
+            VectorDiff::PopFront => self.len == 0 || self.remove(0, out),
+            VectorDiff::PopBack => self.len == 0 || self.remove(self.len - 1, out),
+            VectorDiff::Insert { index, value } => self.insert(index, value, out),
+            VectorDiff::Set { index, value } => {
+                if self.range.contains(&index) {
+                    let index = index - self.range.start;
+                    self.items.set(index, value.clone());
+                    out.push(VectorDiff::Set { index, value });
+                }
+                true
+            }
+            VectorDiff::Remove { index } => self.remove(index, out),
+            VectorDiff::Reset { values } => {
+                *self = Self::new(&values, self.range.clone());
+                out.push(VectorDiff::Reset { values: self.values() });
+                true
+            }
+        }
+    }
+
+    fn insert(&mut self, index: usize, value: T, out: &mut Vec<VectorDiff<T>>) -> bool {
+        self.len += 1;
+
+        if index >= self.range.end {
+            return true;
+        }
+
+        if index < self.range.start {
+            // The items shift to the right, the one before the window enters
+            // it, unless the window is empty.
+            return self.len <= self.range.start;
+        }
+
+        let index = index - self.range.start;
+        self.items.insert(index, value.clone());
+        out.push(VectorDiff::Insert { index, value });
+
+        if self.items.len() > self.range.len() {
+            self.items.pop_back();
+            out.push(VectorDiff::PopBack);
+        }
+
+        true
+    }
+
+    fn remove(&mut self, index: usize, out: &mut Vec<VectorDiff<T>>) -> bool {
+        let previous_len = self.len;
+        self.len -= 1;
+
+        if index >= self.range.end {
+            return true;
+        }
+
+        // The items shift to the left, the one after the window enters it.
+        if previous_len > self.range.end {
+            return false;
+        }
+
+        if index < self.range.start {
+            if !self.items.is_empty() {
+                self.items.pop_front();
+                out.push(VectorDiff::PopFront);
+            }
+        } else {
+            let index = index - self.range.start;
+            self.items.remove(index);
+            out.push(VectorDiff::Remove { index });
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyeball_im::VectorDiff;
+    use imbl::{vector, Vector};
+
+    use super::Window;
+
+    /// Apply the diffs of the window to a copy of its items, and check that
+    /// they match the items in the window.
+    fn apply_and_check(window: &mut Window<u32>, seen: &mut Vector<u32>, diff: VectorDiff<u32>) {
+        let mut out = Vec::new();
+        assert!(window.apply(diff, &mut out));
+        for diff in out {
+            match diff {
+                VectorDiff::Append { values } => seen.append(values),
+                VectorDiff::Clear => seen.clear(),
+                VectorDiff::PushFront { value } => seen.push_front(value),
+                VectorDiff::PushBack { value } => seen.push_back(value),
+                VectorDiff::PopFront => {
+                    seen.pop_front();
+                }
+                VectorDiff::PopBack => {
+                    seen.pop_back();
+                }
+                VectorDiff::Insert { index, value } => seen.insert(index, value),
+                VectorDiff::Set { index, value } => {
+                    seen.set(index, value);
+                }
+                VectorDiff::Remove { index } => {
+                    seen.remove(index);
+                }
+                VectorDiff::Reset { values } => *seen = values,
+            }
+        }
+        assert_eq!(*seen, window.values());
+    }
+
+    #[test]
+    fn diffs_are_limited_to_the_window() {
+        let mut window = Window::new(&vector![0, 1, 2, 3, 4, 5], 1..4);
+        let mut seen = window.values();
+        assert_eq!(seen, vector![1, 2, 3]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::Insert { index: 2, value: 11 });
+        assert_eq!(seen, vector![1, 11, 2]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::PushBack { value: 12 });
+        assert_eq!(seen, vector![1, 11, 2]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::Set { index: 3, value: 13 });
+        assert_eq!(seen, vector![1, 11, 13]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::Set { index: 5, value: 14 });
+        assert_eq!(seen, vector![1, 11, 13]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::Reset { values: vector![0, 1] });
+        assert_eq!(seen, vector![1]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::PopBack);
+        assert_eq!(seen, vector![]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::PopBack);
+        apply_and_check(&mut window, &mut seen, VectorDiff::Append { values: vector![0, 1, 2] });
+        assert_eq!(seen, vector![1, 2]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::Remove { index: 1 });
+        assert_eq!(seen, vector![2]);
+
+        apply_and_check(&mut window, &mut seen, VectorDiff::Clear);
+        assert_eq!(seen, vector![]);
+    }
+
+    #[test]
+    fn items_outside_of_the_window_are_needed() {
+        let mut window = Window::new(&vector![0, 1, 2, 3, 4, 5], 1..4);
+        let mut out = Vec::new();
+
+        // The item before the window would enter it.
+        assert!(!window.apply(VectorDiff::PushFront { value: 10 }, &mut out));
+
+        let mut window = Window::new(&vector![0, 1, 2, 3, 4, 5], 1..4);
+
+        // The item after the window would enter it.
+        assert!(!window.apply(VectorDiff::Remove { index: 2 }, &mut out));
+
+        let mut window = Window::new(&vector![0, 1, 2, 3, 4, 5], 1..4);
+
+        // So does the item after the window when the first one is removed.
+        assert!(!window.apply(VectorDiff::PopFront, &mut out));
+        assert!(out.is_empty());
+    }
+}
@@ -1,10 +1,12 @@
-use std::sync::Mutex;
+use std::{sync::Mutex, time::Duration};
 
 use futures_util::{pin_mut, StreamExt as _};
 use matrix_sdk_test::async_test;
 use matrix_sdk_ui::encryption_sync::{EncryptionSync, WithLocking};
 use serde_json::json;
-use wiremock::{Match as _, Mock, MockGuard, MockServer, Request, ResponseTemplate};
+use wiremock::{
+    matchers::query_param, Match as _, Mock, MockGuard, MockServer, Request, ResponseTemplate,
+};
 
 use crate::{
     logged_in_client,
@@ -82,54 +84,38 @@ async fn test_smoke_encryption_sync_works() -> anyhow::Result<()> {
         },
     };
 
-    // The to-device since token is passed from the previous request.
-    // The extensions haven't changed, so they're not updated (sticky parameters
-    // ftw)... in the first request. Then, the sliding sync instance will retry
-    // those requests, so it will include them again; as a matter of fact, the
-    // last request that we assert against will contain those.
-    sliding_sync_then_assert_request_and_fake_response! {
-        [server, stream]
-        sync matches Some(Err(_)),
-        assert request = {
-            "conn_id": "encryption",
-            "extensions": {
-                "to_device": {
-                    "since": "nb1"
-                }
-            }
-        },
-        respond with = (code 400) {
-            "error": "foo",
-            "errcode": "M_UNKNOWN_POS",
-        },
-    };
-
-    // The stream will stop, as it ran into an error.
-    assert!(stream.next().await.is_none());
-
-    // Start a new sync.
-    let stream = encryption_sync.sync();
-    pin_mut!(stream);
-
-    // The next request will contain sticky parameters again.
-    sliding_sync_then_assert_request_and_fake_response! {
-        [server, stream]
-        assert request = {
-            "conn_id": "encryption",
-            "extensions": {
-                "e2ee": {
-                    "enabled": true
-                },
-                "to_device": {
-                    "enabled": true,
-                    "since": "nb1"
+    // The session expires: the sliding sync instance resets it and restarts
+    // automatically, so the stream doesn't stop. The next request will contain
+    // sticky parameters again.
+    {
+        let _expired_mock_guard = Mock::given(SlidingSyncMatcher)
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": "foo",
+                "errcode": "M_UNKNOWN_POS",
+            })))
+            .up_to_n_times(1)
+            .mount_as_scoped(&server)
+            .await;
+
+        sliding_sync_then_assert_request_and_fake_response! {
+            [server, stream]
+            assert request = {
+                "conn_id": "encryption",
+                "extensions": {
+                    "e2ee": {
+                        "enabled": true
+                    },
+                    "to_device": {
+                        "enabled": true,
+                        "since": "nb1"
+                    }
                 }
-            }
-        },
-        respond with = {
-            "pos": "a"
-        },
-    };
+            },
+            respond with = {
+                "pos": "a"
+            },
+        };
+    }
 
     Ok(())
 }
@@ -344,3 +330,56 @@ async fn test_encryption_sync_always_reloads_todevice_token() -> anyhow::Result<
 
     Ok(())
 }
+
+#[async_test]
+async fn test_encryption_sync_to_device_once() -> anyhow::Result<()> {
+    let (client, server) = logged_in_client().await;
+
+    let _guard = Mock::given(SlidingSyncMatcher)
+        .and(query_param("timeout", "0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "pos": "0",
+            "extensions": {
+                "to_device": {
+                    "next_batch": "nb0"
+                },
+            }
+        })))
+        .expect(1)
+        .mount_as_scoped(&server)
+        .await;
+
+    EncryptionSync::sync_to_device_once(
+        "tests".to_owned(),
+        client.clone(),
+        Duration::from_secs(30),
+        WithLocking::Yes,
+    )
+    .await?;
+
+    // A single request was sent by the encryption sync, with the to-device and
+    // e2ee extensions enabled.
+    check_requests(
+        server,
+        &[json!({
+            "conn_id": "encryption",
+            "extensions": {
+                "e2ee": {
+                    "enabled": true
+                },
+                "to_device": {
+                    "enabled": true
+                }
+            }
+        })],
+    )
+    .await;
+
+    // The token of the to-device events was saved.
+    #[cfg(feature = "e2e-encryption")]
+    if let Some(olm_machine) = &*client.olm_machine_for_testing().await {
+        assert_eq!(olm_machine.store().next_batch_token().await?.as_deref(), Some("nb0"));
+    }
+
+    Ok(())
+}
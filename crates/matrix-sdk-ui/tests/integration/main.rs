@@ -29,6 +29,7 @@ use wiremock::{
 mod encryption_sync;
 mod notification_client;
 mod room_list_service;
+mod room_member_list;
 mod sliding_sync;
 mod sync_service;
 mod timeline;
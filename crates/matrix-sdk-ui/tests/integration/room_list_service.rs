@@ -8,8 +8,10 @@ use matrix_sdk::Client;
 use matrix_sdk_test::async_test;
 use matrix_sdk_ui::{
     room_list_service::{
-        filters::{new_filter_all, new_filter_fuzzy_match_room_name},
-        Error, Input, InputResult, RoomListEntry, RoomListLoadingState, State,
+        filters::{
+            new_filter_all, new_filter_fuzzy_match_room_name, new_filter_spaces, new_filter_unread,
+        },
+        Error, Input, InputResult, RoomListEntry, RoomListLoadingState, RoomListSort, State,
         ALL_ROOMS_LIST_NAME as ALL_ROOMS, INVITES_LIST_NAME as INVITES,
         VISIBLE_ROOMS_LIST_NAME as VISIBLE_ROOMS,
     },
@@ -252,11 +254,11 @@ async fn test_sync_all_states() -> Result<(), Error> {
                         ["m.room.avatar", ""],
                         ["m.room.encryption", ""],
                         ["m.room.power_levels", ""],
+                        ["m.room.create", ""],
                     ],
                     "filters": {
                         "is_invite": false,
                         "is_tombstoned": false,
-                        "not_room_types": ["m.space"],
                     },
                     "bump_event_types": [
                         "m.room.message",
@@ -308,7 +310,6 @@ async fn test_sync_all_states() -> Result<(), Error> {
                     "filters": {
                         "is_invite": false,
                         "is_tombstoned": false,
-                        "not_room_types": ["m.space"],
                     },
                     "bump_event_types": [
                         "m.room.message",
@@ -2444,3 +2445,210 @@ async fn test_input_viewport() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[async_test]
+async fn test_sort() -> Result<(), Error> {
+    let (_, server, room_list) = new_room_list_service().await?;
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Init => SettingUp,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "sort": ["by_recency", "by_name"],
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 1,
+                    "ops": [],
+                },
+            },
+            "rooms": {},
+        },
+    };
+
+    room_list.set_sort(RoomListSort::UnreadFirst).await?;
+
+    // The new sort order is sent for `ALL_ROOMS`, and `VISIBLE_ROOMS` is created
+    // with it.
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = SettingUp => Running,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "sort": ["by_notification_level", "by_recency", "by_name"],
+                },
+                VISIBLE_ROOMS: {
+                    "sort": ["by_notification_level", "by_recency", "by_name"],
+                },
+            },
+        },
+        respond with = {
+            "pos": "1",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    room_list.set_sort(RoomListSort::Name).await?;
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Running => Running,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "sort": ["by_name"],
+                },
+                VISIBLE_ROOMS: {
+                    "sort": ["by_name"],
+                },
+            },
+        },
+        respond with = {
+            "pos": "2",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_entries_stream_with_unread_filter() -> Result<(), Error> {
+    let (client, server, room_list) = new_room_list_service().await?;
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    let all_rooms = room_list.all_rooms().await?;
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Init => SettingUp,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 2,
+                    "ops": [
+                        {
+                            "op": "SYNC",
+                            "range": [0, 1],
+                            "room_ids": [
+                                "!r0:bar.org",
+                                "!r1:bar.org",
+                            ],
+                        },
+                    ],
+                },
+            },
+            "rooms": {
+                "!r0:bar.org": {
+                    "name": "Room #0",
+                    "initial": true,
+                    "timeline": [],
+                },
+                "!r1:bar.org": {
+                    "name": "Room #1",
+                    "initial": true,
+                    "timeline": [],
+                    "notification_count": 2,
+                },
+            },
+        },
+    };
+
+    let (previous_entries, _) = all_rooms.entries_with_static_filter(new_filter_unread(&client));
+    assert_eq!(previous_entries, entries![F("!r1:bar.org")]);
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_entries_stream_with_spaces_filter() -> Result<(), Error> {
+    let (client, server, room_list) = new_room_list_service().await?;
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    let all_rooms = room_list.all_rooms().await?;
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Init => SettingUp,
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 2,
+                    "ops": [
+                        {
+                            "op": "SYNC",
+                            "range": [0, 1],
+                            "room_ids": [
+                                "!r0:bar.org",
+                                "!r1:bar.org",
+                            ],
+                        },
+                    ],
+                },
+            },
+            "rooms": {
+                "!r0:bar.org": {
+                    "name": "Room #0",
+                    "initial": true,
+                    "timeline": [],
+                },
+                "!r1:bar.org": {
+                    "name": "Space #1",
+                    "initial": true,
+                    "timeline": [],
+                    "required_state": [
+                        {
+                            "content": {
+                                "creator": "@example:bar.org",
+                                "room_version": "10",
+                                "type": "m.space",
+                            },
+                            "event_id": "$create:bar.org",
+                            "origin_server_ts": 1,
+                            "sender": "@example:bar.org",
+                            "state_key": "",
+                            "type": "m.room.create",
+                        },
+                    ],
+                },
+            },
+        },
+    };
+
+    let (previous_entries, _) = all_rooms.entries_with_static_filter(new_filter_spaces(&client));
+    assert_eq!(previous_entries, entries![F("!r1:bar.org")]);
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use futures_util::{pin_mut, StreamExt};
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk_test::{async_test, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder};
+use matrix_sdk_ui::room_member_list::{RoomMemberList, RoomMemberRole};
+use ruma::{room_id, user_id};
+use serde_json::{json, Value as JsonValue};
+use tokio::time::timeout;
+use wiremock::{
+    matchers::{method, path_regex},
+    Mock, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync};
+
+fn member_event(user_id: &str, display_name: &str) -> JsonValue {
+    json!({
+        "content": {
+            "displayname": display_name,
+            "membership": "join",
+        },
+        "event_id": format!("$member_{user_id}"),
+        "origin_server_ts": 151800140,
+        "sender": user_id,
+        "state_key": user_id,
+        "type": "m.room.member",
+    })
+}
+
+#[async_test]
+async fn test_room_member_list() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
+        StateTestEvent::Custom(json!({
+            "content": {
+                "users": {
+                    "@zed:localhost": 100,
+                },
+            },
+            "event_id": "$power_levels",
+            "origin_server_ts": 151800140,
+            "sender": "@zed:localhost",
+            "state_key": "",
+            "type": "m.room.power_levels",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                member_event("@bob2:localhost", "Bob"),
+                member_event("@alice:localhost", "Alice"),
+                member_event("@zed:localhost", "Zed"),
+                member_event("@bob:localhost", "Bob"),
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let member_list = RoomMemberList::new(room);
+
+    // Nothing is loaded before calling `load()`.
+    let (entries, _) = member_list.subscribe();
+    assert!(entries.is_empty());
+
+    member_list.load().await.unwrap();
+
+    // Members are grouped by role, then sorted by name.
+    let (entries, _) = member_list.subscribe();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0].user_id(), user_id!("@zed:localhost"));
+    assert_eq!(entries[0].role(), RoomMemberRole::Administrator);
+    assert_eq!(entries[1].disambiguated_name(), "Alice");
+    assert_eq!(entries[1].role(), RoomMemberRole::User);
+    assert_eq!(entries[2].disambiguated_name(), "Bob (@bob:localhost)");
+    assert_eq!(entries[3].disambiguated_name(), "Bob (@bob2:localhost)");
+
+    // Search by display name.
+    member_list.set_search(Some("bo"));
+    let (entries, _) = member_list.subscribe();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(member_list.matching_members_count(), 2);
+
+    // Search by user ID, narrowing down the previous search.
+    member_list.set_search(Some("bob2"));
+    let (entries, _) = member_list.subscribe();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].user_id(), user_id!("@bob2:localhost"));
+
+    member_list.set_search(None);
+    let (entries, _) = member_list.subscribe();
+    assert_eq!(entries.len(), 4);
+
+    // Loading again doesn't request the members from the homeserver again.
+    member_list.load().await.unwrap();
+}
+
+#[async_test]
+async fn test_room_member_list_applies_member_events() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                member_event("@alice:localhost", "Alice"),
+                member_event("@bob:localhost", "Bob"),
+                member_event("@bob2:localhost", "Bob"),
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let member_list = RoomMemberList::new(room);
+    member_list.load().await.unwrap();
+
+    let (entries, stream) = member_list.subscribe();
+    pin_mut!(stream);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[1].disambiguated_name(), "Bob (@bob:localhost)");
+    assert_eq!(entries[2].disambiguated_name(), "Bob (@bob2:localhost)");
+
+    // A member changes their display name, and another member joins.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "displayname": "Robert",
+                    "membership": "join",
+                },
+                "event_id": "$bob2_rename",
+                "origin_server_ts": 151800150,
+                "sender": "@bob2:localhost",
+                "state_key": "@bob2:localhost",
+                "type": "m.room.member",
+            })))
+            .add_state_event(StateTestEvent::Custom(member_event("@carol:localhost", "Carol"))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    // Only the changed members are reloaded, without requesting the full member
+    // list again.
+    timeout(Duration::from_secs(1), stream.next()).await.unwrap().unwrap();
+    let (entries, _) = member_list.subscribe();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0].disambiguated_name(), "Alice");
+    // The name of the other member isn't ambiguous anymore.
+    assert_eq!(entries[1].disambiguated_name(), "Bob");
+    assert_eq!(entries[2].disambiguated_name(), "Carol");
+    assert_eq!(entries[3].disambiguated_name(), "Robert");
+}
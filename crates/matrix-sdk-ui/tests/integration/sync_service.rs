@@ -21,6 +21,7 @@ use matrix_sdk_test::async_test;
 use matrix_sdk_ui::sync_service::{State, SyncService};
 use serde_json::json;
 use stream_assert::{assert_next_matches, assert_pending};
+use tokio::{task::yield_now, time::sleep};
 use wiremock::{Match as _, Mock, MockGuard, MockServer, Request, ResponseTemplate};
 
 use crate::{
@@ -260,3 +261,96 @@ async fn test_sync_service_no_encryption_state() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[async_test]
+async fn test_sync_service_automatic_recovery() -> anyhow::Result<()> {
+    let (client, server) = logged_in_client().await;
+
+    // The first request fails, the next ones succeed.
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(ResponseTemplate::new(404))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    let encryption_pos = Arc::new(Mutex::new(0));
+    let room_pos = Arc::new(Mutex::new(0));
+    let _guard = setup_mocking_sliding_sync_server(&server, encryption_pos, room_pos.clone()).await;
+
+    let sync_service =
+        SyncService::builder(client).with_automatic_recovery(3).build().await.unwrap();
+    let mut state_stream = sync_service.state();
+
+    // Time only advances when the test sleeps while all the tasks are waiting
+    // for a timer, so the test doesn't depend on how long the requests take.
+    tokio::time::pause();
+
+    sync_service.start().await;
+    assert_next_matches!(state_stream, State::Running);
+
+    // The underlying sync fails, but the service keeps running while waiting to
+    // restart it.
+    while sync_service.task_states() != (false, false) {
+        yield_now().await;
+    }
+    sleep(Duration::from_millis(999)).await;
+    assert_pending!(state_stream);
+    assert_eq!(sync_service.task_states(), (false, false));
+    assert_eq!(*room_pos.lock().unwrap(), 0);
+
+    // After 1 second, the room list sync is restarted successfully.
+    sleep(Duration::from_millis(1)).await;
+    while *room_pos.lock().unwrap() == 0 {
+        yield_now().await;
+    }
+    assert_pending!(state_stream);
+    assert_eq!(sync_service.task_states(), (false, true));
+
+    tokio::time::resume();
+    sync_service.stop().await?;
+    assert_next_matches!(state_stream, State::Idle);
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_sync_service_pause_and_resume() -> anyhow::Result<()> {
+    let (client, server) = logged_in_client().await;
+
+    let encryption_pos = Arc::new(Mutex::new(0));
+    let room_pos = Arc::new(Mutex::new(0));
+    let _guard = setup_mocking_sliding_sync_server(&server, encryption_pos, room_pos).await;
+
+    let sync_service =
+        SyncService::builder(client).with_encryption_sync(false, None).build().await.unwrap();
+    let mut state_stream = sync_service.state();
+
+    // Resuming a service that isn't paused doesn't start it.
+    sync_service.resume().await;
+    assert_pending!(state_stream);
+    assert_eq!(sync_service.task_states(), (false, false));
+
+    sync_service.start().await;
+    assert_next_matches!(state_stream, State::Running);
+
+    // Pausing stops both syncs.
+    sync_service.pause().await?;
+    assert_next_matches!(state_stream, State::Paused);
+    assert_eq!(sync_service.task_states(), (false, false));
+
+    // Resuming restarts them.
+    sync_service.resume().await;
+    assert_next_matches!(state_stream, State::Running);
+    assert_eq!(sync_service.task_states(), (true, true));
+
+    // Stopping a paused service prevents resuming it.
+    sync_service.pause().await?;
+    assert_next_matches!(state_stream, State::Paused);
+    sync_service.stop().await?;
+    assert_next_matches!(state_stream, State::Idle);
+
+    sync_service.resume().await;
+    assert_pending!(state_stream);
+    assert_eq!(sync_service.task_states(), (false, false));
+
+    Ok(())
+}
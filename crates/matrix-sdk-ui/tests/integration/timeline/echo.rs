@@ -12,25 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{sync::Arc, time::Duration};
+use std::{env, fs, sync::Arc, time::Duration};
 
 use assert_matches::assert_matches;
 use eyeball_im::VectorDiff;
-use futures_util::StreamExt;
-use matrix_sdk::{config::SyncSettings, executor::spawn, ruma::MilliSecondsSinceUnixEpoch};
+use futures_util::{future, StreamExt};
+use matrix_sdk::{
+    attachment::AttachmentConfig, config::SyncSettings, executor::spawn, room::DependentRequest,
+    ruma::MilliSecondsSinceUnixEpoch, TransmissionProgress,
+};
 use matrix_sdk_test::{async_test, JoinedRoomBuilder, SyncResponseBuilder, TimelineTestEvent};
 use matrix_sdk_ui::timeline::{
-    EventSendState, RoomExt, TimelineItemContent, TimelineItemKind, VirtualTimelineItem,
+    EventItemIdentifier, EventSendState, RoomExt, TimelineItemContent, TimelineItemKind,
+    VirtualTimelineItem,
 };
 use ruma::{
     event_id,
-    events::room::message::{MessageType, RoomMessageEventContent},
+    events::room::{
+        message::{MessageType, RoomMessageEventContent},
+        MediaSource,
+    },
     room_id, uint, TransactionId,
 };
 use serde_json::json;
 use stream_assert::assert_next_matches;
+use tokio::time::sleep;
 use wiremock::{
-    matchers::{header, method, path_regex},
+    matchers::{body_string_contains, header, method, path, path_regex},
     Mock, ResponseTemplate,
 };
 
@@ -296,3 +304,252 @@ async fn cancel_failed() {
     // Observable local echo being removed
     assert_matches!(timeline_stream.next().await, Some(VectorDiff::Remove { index: 0 }));
 }
+
+#[async_test]
+async fn reaction_on_local_echo() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let (_, mut timeline_stream) =
+        timeline.subscribe_filter_map(|item| item.as_event().cloned()).await;
+
+    let event_id = event_id!("$wWgymRfo7ri1uQx0NXO40vLJ");
+    let reaction_event_id = event_id!("$reaction");
+    let txn_id: &TransactionId = "my-txn-id".into();
+
+    mock_encryption_state(&server, false).await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m\.room\.message/.*"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": event_id }))
+                .set_delay(Duration::from_millis(200)),
+        )
+        .mount(&server)
+        .await;
+
+    // Only the final state of the reaction is sent, on the event of the local
+    // echo.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m\.reaction/.*"))
+        .and(body_string_contains(event_id.as_str()))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(&json!({ "event_id": reaction_event_id })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    timeline.send(RoomMessageEventContent::text_plain("Hello, World!").into(), Some(txn_id)).await;
+
+    let local_echo = assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => value);
+    let item_id = local_echo.identifier();
+    assert_eq!(item_id, EventItemIdentifier::TransactionId(txn_id.to_owned()));
+
+    // Add, redact, add the reaction before the local echo is sent. The
+    // reaction doesn't wait for the remote echo, which might never come, for
+    // example if the timeline is focused on an event.
+    let (first, second, third) = future::join3(
+        timeline.toggle_reaction_on_item(&item_id, "👍"),
+        timeline.toggle_reaction_on_item(&item_id, "👍"),
+        timeline.toggle_reaction_on_item(&item_id, "👍"),
+    )
+    .await;
+    first.unwrap();
+    second.unwrap();
+    third.unwrap();
+
+    // The reaction is shown on the local echo while it is being sent.
+    let items = timeline.items().await;
+    let item = items.last().unwrap().as_event().unwrap();
+    assert_matches!(item.send_state(), Some(EventSendState::NotSentYet));
+    let senders = item.reactions()["👍"].senders().collect::<Vec<_>>();
+    assert_eq!(senders.len(), 1);
+    assert_eq!(senders[0].sender_id, client.user_id().unwrap());
+
+    // The reaction waits in the persistent send queue for the local echo.
+    let send_queue = room.send_queue().await.unwrap();
+    let dependents = send_queue.dependent_requests().await;
+    assert_eq!(dependents.len(), 1);
+    assert_eq!(&*dependents[0].parent_transaction_id, txn_id);
+    assert_matches!(&dependents[0].request, DependentRequest::React { key } => {
+        assert_eq!(key, "👍");
+    });
+
+    // Wait 200ms for the local echo and 200ms for the reaction.
+    sleep(Duration::from_millis(400)).await;
+
+    let items = timeline.items().await;
+    let item = items.last().unwrap().as_event().unwrap();
+    assert_eq!(item.event_id(), Some(event_id));
+    assert_matches!(item.send_state(), Some(EventSendState::Sent { .. }));
+    assert_eq!(item.reactions()["👍"].len(), 1);
+    assert!(send_queue.dependent_requests().await.is_empty());
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": "Hello, World!",
+                "msgtype": "m.text",
+            },
+            "event_id": event_id,
+            "origin_server_ts": 152037280,
+            "sender": "@example:localhost",
+            "type": "m.room.message",
+            "unsigned": { "transaction_id": txn_id, },
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(sync_settings.clone()).await.unwrap();
+
+    // The reaction is kept on the remote echo until its own remote echo is
+    // received.
+    let items = timeline.items().await;
+    let item = items.last().unwrap().as_event().unwrap();
+    assert!(!item.is_local_echo());
+    assert_eq!(item.reactions()["👍"].len(), 1);
+
+    // The local echo doesn't exist anymore.
+    timeline.toggle_reaction_on_item(&item_id, "👍").await.unwrap_err();
+}
+
+#[async_test]
+async fn attachment_echo() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    // Delay the response, so the progress of the upload is reported before it
+    // is done.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(
+                    &json!({ "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw" }),
+                )
+                .set_delay(Duration::from_millis(200)),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let event_id = event_id!("$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP");
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("mxc://example.com/AQwafuaFswefuhsfAFAgsw"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&json!({ "event_id": event_id })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = Arc::new(room.timeline().await);
+    let (_, mut timeline_stream) =
+        timeline.subscribe_filter_map(|item| item.as_event().cloned()).await;
+
+    let file_name = "matrix-sdk-ui-attachment-echo.txt";
+    let file_path = env::temp_dir().join(file_name);
+    fs::write(&file_path, "Hello world").unwrap();
+    let url = file_path.to_str().unwrap().to_owned();
+
+    // Don't move the original timeline, it must live until the end of the test
+    let send_hdl = spawn({
+        let timeline = timeline.clone();
+        async move { timeline.send_attachment(url, mime::TEXT_PLAIN, AttachmentConfig::new()).await }
+    });
+
+    // The local echo uses a placeholder URI until the media is uploaded.
+    let local_echo = assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => value);
+    assert_matches!(local_echo.send_state(), Some(EventSendState::NotSentYet));
+    let txn_id = local_echo.transaction_id().unwrap().to_owned();
+
+    let msg = assert_matches!(local_echo.content(), TimelineItemContent::Message(msg) => msg);
+    let file = assert_matches!(msg.msgtype(), MessageType::File(file) => file);
+    assert_eq!(file.body, file_name);
+    let uri = assert_matches!(&file.source, MediaSource::Plain(uri) => uri);
+    assert_eq!(uri.as_str(), format!("mxc://send-queue.localhost/{txn_id}"));
+
+    let item = assert_next_matches!(timeline_stream, VectorDiff::Set { index: 0, value } => value);
+    assert_matches!(item.send_state(), Some(EventSendState::Sending { progress: None }));
+
+    // The progress is reported until the attachment is sent.
+    let mut last_progress: Option<TransmissionProgress> = None;
+    loop {
+        let item = assert_matches!(
+            timeline_stream.next().await,
+            Some(VectorDiff::Set { index: 0, value }) => value
+        );
+        assert_eq!(item.transaction_id(), Some(&*txn_id));
+
+        match item.send_state() {
+            Some(EventSendState::Sending { progress: Some(progress) }) => {
+                assert!(progress.total > 0);
+                assert!(progress.current <= progress.total);
+                if let Some(last_progress) = last_progress {
+                    assert!(progress.current >= last_progress.current);
+                }
+                last_progress = Some(*progress);
+            }
+            Some(EventSendState::Sent { event_id: sent_event_id }) => {
+                assert_eq!(*sent_event_id, event_id);
+                break;
+            }
+            state => panic!("unexpected send state: {state:?}"),
+        }
+    }
+    assert!(last_progress.is_some(), "the progress of the upload should have been reported");
+
+    send_hdl.await.unwrap().unwrap();
+    fs::remove_file(file_path).unwrap();
+
+    // The remote echo replaces the local echo.
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": file_name,
+                "msgtype": "m.file",
+                "url": "mxc://example.com/AQwafuaFswefuhsfAFAgsw",
+            },
+            "event_id": event_id,
+            "origin_server_ts": 152038280,
+            "sender": "@example:localhost",
+            "type": "m.room.message",
+            "unsigned": { "transaction_id": txn_id },
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+
+    assert_next_matches!(timeline_stream, VectorDiff::Remove { index: 0 });
+    let remote_echo =
+        assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => value);
+    assert!(!remote_echo.is_local_echo());
+    assert_eq!(remote_echo.event_id(), Some(event_id));
+
+    let msg = assert_matches!(remote_echo.content(), TimelineItemContent::Message(msg) => msg);
+    let file = assert_matches!(msg.msgtype(), MessageType::File(file) => file);
+    let uri = assert_matches!(&file.source, MediaSource::Plain(uri) => uri);
+    assert_eq!(uri.as_str(), "mxc://example.com/AQwafuaFswefuhsfAFAgsw");
+}
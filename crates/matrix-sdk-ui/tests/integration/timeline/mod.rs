@@ -19,8 +19,8 @@ use eyeball_im::VectorDiff;
 use futures_util::StreamExt;
 use matrix_sdk::{config::SyncSettings, ruma::MilliSecondsSinceUnixEpoch};
 use matrix_sdk_test::{
-    async_test, JoinedRoomBuilder, RoomAccountDataTestEvent, StateTestEvent, SyncResponseBuilder,
-    TimelineTestEvent,
+    async_test, GlobalAccountDataTestEvent, JoinedRoomBuilder, RoomAccountDataTestEvent,
+    StateTestEvent, SyncResponseBuilder, TimelineTestEvent,
 };
 use matrix_sdk_ui::timeline::{
     Error as TimelineError, RoomExt, TimelineDetails, TimelineItemContent, VirtualTimelineItem,
@@ -33,10 +33,13 @@ use wiremock::{
 };
 
 mod echo;
+mod encryption;
 mod pagination;
+mod pinned_events;
 mod queue;
 mod read_receipts;
 mod subscribe;
+mod thread;
 
 pub(crate) mod sliding_sync;
 
@@ -392,6 +395,90 @@ async fn read_marker() {
     assert_matches!(marker.as_virtual().unwrap(), VirtualTimelineItem::ReadMarker);
 }
 
+#[async_test]
+async fn ignored_users() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let (_, mut timeline_stream) = timeline.subscribe().await;
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_bulk([
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": "hello",
+                "msgtype": "m.text",
+            },
+            "event_id": "$someplace:example.org",
+            "origin_server_ts": 152037280,
+            "sender": "@alice:example.org",
+            "type": "m.room.message",
+        })),
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": "buy my stuff!",
+                "msgtype": "m.text",
+            },
+            "event_id": "$someotherplace:example.org",
+            "origin_server_ts": 152037290,
+            "sender": "@spammer:example.org",
+            "type": "m.room.message",
+        })),
+    ]));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let _day_divider = assert_matches!(timeline_stream.next().await, Some(VectorDiff::PushBack { value }) => value);
+    let _message = assert_matches!(timeline_stream.next().await, Some(VectorDiff::PushBack { value }) => value);
+    let _spam = assert_matches!(timeline_stream.next().await, Some(VectorDiff::PushBack { value }) => value);
+
+    // The spammer is ignored, their event is removed from the timeline.
+    ev_builder.add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+        "content": {
+            "ignored_users": {
+                "@spammer:example.org": {},
+            },
+        },
+        "type": "m.ignored_user_list",
+    })));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    assert_matches!(timeline_stream.next().await, Some(VectorDiff::Remove { index: 2 }));
+    let items = timeline.items().await;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1].as_event().unwrap().sender(), "@alice:example.org");
+
+    // The spammer is unignored, the timeline is reset so their events can be
+    // fetched again.
+    ev_builder.add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+        "content": {
+            "ignored_users": {},
+        },
+        "type": "m.ignored_user_list",
+    })));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    assert_matches!(timeline_stream.next().await, Some(VectorDiff::Clear));
+    assert!(timeline.items().await.is_empty());
+}
+
 #[async_test]
 async fn in_reply_to_details() {
     let room_id = room_id!("!a98sd12bjh:example.org");
@@ -625,3 +712,94 @@ async fn sync_highlighted() {
     // `m.room.tombstone` should be highlighted by default.
     assert!(remote_event.is_highlighted());
 }
+
+#[async_test]
+async fn edit_history() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let (_, mut timeline_stream) = timeline.subscribe().await;
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        TimelineTestEvent::Custom(json!({
+            "content": {
+                "body": "hello",
+                "msgtype": "m.text",
+            },
+            "event_id": "$original",
+            "origin_server_ts": 152037280,
+            "sender": "@alice:example.org",
+            "type": "m.room.message",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let _day_divider = assert_matches!(
+        timeline_stream.next().await,
+        Some(VectorDiff::PushBack { value }) => value
+    );
+    let item = assert_matches!(
+        timeline_stream.next().await,
+        Some(VectorDiff::PushBack { value }) => value
+    );
+    let item = item.as_event().unwrap();
+
+    let edit = |event_id: &str, sender: &str, body: &str, ts: u64| {
+        json!({
+            "content": {
+                "body": format!("* {body}"),
+                "msgtype": "m.text",
+                "m.new_content": {
+                    "body": body,
+                    "msgtype": "m.text",
+                },
+                "m.relates_to": {
+                    "event_id": "$original",
+                    "rel_type": "m.replace",
+                },
+            },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": sender,
+            "type": "m.room.message",
+        })
+    };
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/v1/rooms/.*/relations/\$original/m.replace$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                edit("$edit1", "@alice:example.org", "hello there", 152037281),
+                edit("$edit2", "@bob:example.org", "hijacked", 152037282),
+                edit("$edit3", "@alice:example.org", "hello world", 152037283),
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let versions = timeline.fetch_edit_history(item).await.unwrap();
+
+    let event_ids: Vec<_> = versions.iter().map(|version| version.event_id.as_str()).collect();
+    assert_eq!(event_ids, ["$original", "$edit1", "$edit3"]);
+
+    let bodies: Vec<_> = versions.iter().map(|version| version.msgtype.body()).collect();
+    assert_eq!(bodies, ["hello", "hello there", "hello world"]);
+
+    assert_eq!(versions[2].sender, "@alice:example.org");
+    assert_eq!(versions[2].timestamp, MilliSecondsSinceUnixEpoch(uint!(152037283)));
+}
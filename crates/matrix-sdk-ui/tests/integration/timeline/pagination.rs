@@ -17,22 +17,25 @@ use std::{sync::Arc, time::Duration};
 use assert_matches::assert_matches;
 use eyeball_im::VectorDiff;
 use futures_util::future::join;
+use imbl::Vector;
 use matrix_sdk::config::SyncSettings;
 use matrix_sdk_test::{
     async_test, test_json, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder,
+    TimelineTestEvent,
 };
 use matrix_sdk_ui::timeline::{
-    AnyOtherFullStateEventContent, BackPaginationStatus, PaginationOptions, RoomExt,
-    TimelineItemContent, VirtualTimelineItem,
+    AnyOtherFullStateEventContent, BackPaginationStatus, ForwardPaginationStatus,
+    PaginationOptions, RoomExt, TimelineItem, TimelineItemContent, VirtualTimelineItem,
 };
 use ruma::{
+    event_id,
     events::{room::message::MessageType, FullStateEventContent},
     room_id,
 };
 use serde_json::json;
 use stream_assert::{assert_next_eq, assert_next_matches};
 use wiremock::{
-    matchers::{header, method, path_regex},
+    matchers::{header, method, path_regex, query_param},
     Mock, ResponseTemplate,
 };
 
@@ -224,3 +227,156 @@ async fn back_pagination_highlighted() {
     // `m.room.tombstone` should be highlighted by default.
     assert!(remote_event.is_highlighted());
 }
+
+#[async_test]
+async fn concurrent_back_pagination() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = Arc::new(room.timeline().await);
+
+    // Only one request is made while a back-pagination is already running.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&*test_json::ROOM_MESSAGES_BATCH_1)
+                .set_delay(Duration::from_millis(100)),
+        )
+        .expect(1)
+        .named("messages_batch_1")
+        .mount(&server)
+        .await;
+
+    let (first, second) = join(
+        timeline.paginate_backwards(PaginationOptions::single_request(10)),
+        timeline.paginate_backwards(PaginationOptions::single_request(10)),
+    )
+    .await;
+    first.unwrap();
+    second.unwrap();
+    server.verify().await;
+
+    assert_eq!(timeline.back_pagination_status().get(), BackPaginationStatus::Idle);
+}
+
+fn text_message(event_id: &str, body: &str, ts: u64) -> serde_json::Value {
+    json!({
+        "content": {
+            "body": body,
+            "msgtype": "m.text",
+        },
+        "event_id": event_id,
+        "origin_server_ts": ts,
+        "sender": "@alice:example.org",
+        "type": "m.room.message",
+    })
+}
+
+#[async_test]
+async fn focus_on_event() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/context/\$2"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "events_before": [text_message("$1", "first", 152037280)],
+            "event": text_message("$2", "focused", 152037281),
+            "events_after": [text_message("$3", "third", 152037282)],
+            "start": "before_1",
+            "end": "after_3",
+            "state": [],
+        })))
+        .expect(1)
+        .named("context")
+        .mount(&server)
+        .await;
+
+    timeline.focus_on_event(event_id!("$2"), 2).await.unwrap();
+    server.reset().await;
+
+    let bodies = |items: Vector<Arc<TimelineItem>>| -> Vec<String> {
+        items
+            .iter()
+            .filter_map(|item| Some(item.as_event()?.content().as_message()?.body().to_owned()))
+            .collect()
+    };
+    assert_eq!(bodies(timeline.items().await), ["first", "focused", "third"]);
+    assert_eq!(timeline.back_pagination_status().get(), BackPaginationStatus::Idle);
+    assert_eq!(timeline.forward_pagination_status().get(), ForwardPaginationStatus::Idle);
+
+    // Events received via sync are not added after the focused window.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(text_message("$5", "live", 152037284))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("dir", "f"))
+        .and(query_param("from", "after_3"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                text_message("$4", "fourth", 152037283),
+                text_message("$5", "live", 152037284),
+            ],
+            "start": "after_3",
+        })))
+        .expect(1)
+        .named("messages_forwards")
+        .mount(&server)
+        .await;
+
+    timeline.paginate_forwards(PaginationOptions::single_request(10)).await.unwrap();
+    server.reset().await;
+
+    assert_eq!(bodies(timeline.items().await), ["first", "focused", "third", "fourth", "live"]);
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("dir", "b"))
+        .and(query_param("from", "before_1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [text_message("$0", "zeroth", 152037279)],
+            "start": "before_1",
+        })))
+        .expect(1)
+        .named("messages_backwards")
+        .mount(&server)
+        .await;
+
+    timeline.paginate_backwards(PaginationOptions::single_request(10)).await.unwrap();
+    server.reset().await;
+
+    assert_eq!(
+        bodies(timeline.items().await),
+        ["zeroth", "first", "focused", "third", "fourth", "live"]
+    );
+    assert_eq!(timeline.back_pagination_status().get(), BackPaginationStatus::TimelineStartReached);
+}
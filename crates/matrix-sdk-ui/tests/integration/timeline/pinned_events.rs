@@ -0,0 +1,139 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use assert_matches::assert_matches;
+use eyeball_im::VectorDiff;
+use futures_util::StreamExt;
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk_test::{
+    async_test, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder, TimelineTestEvent,
+};
+use matrix_sdk_ui::timeline::{RoomExt, TimelineFocus};
+use ruma::{event_id, room_id, EventId, RoomId};
+use serde_json::json;
+use wiremock::{
+    matchers::{header, method, path_regex},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync};
+
+fn pinned_events_state(event_ids: &[&EventId]) -> StateTestEvent {
+    StateTestEvent::Custom(json!({
+        "content": {
+            "pinned": event_ids,
+        },
+        "event_id": "$pinned_state",
+        "origin_server_ts": 152037000,
+        "sender": "@alice:example.org",
+        "state_key": "",
+        "type": "m.room.pinned_events",
+    }))
+}
+
+async fn mock_event(server: &MockServer, room_id: &RoomId, event_id: &EventId, body: &str) {
+    let event_path = format!(r"^/_matrix/client/r0/rooms/.*/event/{}", regex_escape(event_id));
+    Mock::given(method("GET"))
+        .and(path_regex(event_path))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "content": {
+                "body": body,
+                "msgtype": "m.text",
+            },
+            "room_id": room_id,
+            "event_id": event_id,
+            "origin_server_ts": 152037280,
+            "sender": "@alice:example.org",
+            "type": "m.room.message",
+        })))
+        .mount(server)
+        .await;
+}
+
+fn regex_escape(event_id: &EventId) -> String {
+    event_id.as_str().replace('$', r"\$")
+}
+
+#[async_test]
+async fn pinned_events() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let first_event_id = event_id!("$pinned1");
+    let second_event_id = event_id!("$pinned2");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id).add_state_event(pinned_events_state(&[first_event_id])),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_event(&server, room_id, first_event_id, "first pinned").await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline_builder().with_focus(TimelineFocus::PinnedEvents).build().await;
+    let (items, mut timeline_stream) = timeline.subscribe().await;
+
+    // A day divider and the pinned event.
+    assert_eq!(items.len(), 2);
+    assert!(items[0].as_virtual().is_some());
+    let first = items[1].as_event().unwrap();
+    assert_eq!(first.event_id(), Some(first_event_id));
+    assert_eq!(first.content().as_message().unwrap().body(), "first pinned");
+
+    // Events that are not pinned are not added to the timeline.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id).add_timeline_event(TimelineTestEvent::MessageText),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    // A new event is pinned, the timeline is reloaded.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(pinned_events_state(&[first_event_id, second_event_id])),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    mock_event(&server, room_id, first_event_id, "first pinned").await;
+    mock_event(&server, room_id, second_event_id, "second pinned").await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+
+    // The message that isn't pinned was not added, so the first update is the
+    // reload.
+    assert_matches!(timeline_stream.next().await, Some(VectorDiff::Clear));
+    let _day_divider = assert_matches!(
+        timeline_stream.next().await,
+        Some(VectorDiff::PushBack { value }) => value
+    );
+    let first = assert_matches!(
+        timeline_stream.next().await,
+        Some(VectorDiff::PushBack { value }) => value
+    );
+    assert_eq!(first.as_event().unwrap().event_id(), Some(first_event_id));
+    let second = assert_matches!(
+        timeline_stream.next().await,
+        Some(VectorDiff::PushBack { value }) => value
+    );
+    assert_eq!(second.as_event().unwrap().event_id(), Some(second_event_id));
+}
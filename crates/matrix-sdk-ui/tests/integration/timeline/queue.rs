@@ -12,20 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{sync::Arc, time::Duration};
+use std::{env, fs, sync::Arc, time::Duration};
 
 use assert_matches::assert_matches;
 use eyeball_im::VectorDiff;
 use futures_util::StreamExt;
-use matrix_sdk::config::SyncSettings;
+use matrix_sdk::{attachment::AttachmentConfig, config::SyncSettings, room::QueuedEventContent};
 use matrix_sdk_test::{async_test, JoinedRoomBuilder, SyncResponseBuilder, TimelineTestEvent};
-use matrix_sdk_ui::timeline::{EventItemOrigin, EventSendState, RoomExt};
-use ruma::{events::room::message::RoomMessageEventContent, room_id};
+use matrix_sdk_ui::timeline::{
+    Error, EventItemIdentifier, EventItemOrigin, EventSendState, RoomExt, TimelineItemContent,
+};
+use ruma::{
+    events::room::message::{RoomMessageEventContent, RoomMessageEventContentWithoutRelation},
+    room_id, TransactionId,
+};
 use serde_json::json;
 use stream_assert::{assert_next_matches, assert_pending};
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
 use wiremock::{
-    matchers::{body_string_contains, method, path_regex},
+    matchers::{body_string_contains, method, path, path_regex},
     Mock, ResponseTemplate,
 };
 
@@ -103,6 +108,199 @@ async fn message_order() {
     assert_pending!(timeline_stream);
 }
 
+#[async_test]
+async fn dependent_requests_on_local_echoes() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = Arc::new(room.timeline().await);
+    let (_, mut timeline_stream) =
+        timeline.subscribe_filter_map(|item| item.as_event().cloned()).await;
+
+    // The edit is only sent once, after the message it replaces.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP"))
+        .and(body_string_contains("m.replace"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&json!({ "event_id": "$edit" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("First!"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP" }))
+                .set_delay(Duration::from_millis(200)),
+        )
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("Second."))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$5E2kLK/Sg342bgBU9ceEIEPYpbFaqJpZ" })),
+        )
+        .mount(&server)
+        .await;
+
+    // The redaction is only sent once, after the message it redacts.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/.*5E2kLK.*/.*"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(&json!({ "event_id": "$redaction" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let first_txn_id: &TransactionId = "first-txn-id".into();
+    let second_txn_id: &TransactionId = "second-txn-id".into();
+    timeline.send(RoomMessageEventContent::text_plain("First!").into(), Some(first_txn_id)).await;
+    timeline.send(RoomMessageEventContent::text_plain("Second.").into(), Some(second_txn_id)).await;
+
+    assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => {
+        assert_eq!(value.content().as_message().unwrap().body(), "First!");
+    });
+    assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => {
+        assert_eq!(value.content().as_message().unwrap().body(), "Second.");
+    });
+
+    // Edit and redact the local echoes before they are sent.
+    let first_id = EventItemIdentifier::TransactionId(first_txn_id.to_owned());
+    let new_content = RoomMessageEventContentWithoutRelation::text_plain("Edited");
+    timeline.edit(&first_id, new_content).await.unwrap();
+
+    let second_id = EventItemIdentifier::TransactionId(second_txn_id.to_owned());
+    timeline.redact(&second_id, None).await.unwrap();
+
+    // The requests are applied to the local echoes right away.
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 0, value } => {
+        assert_eq!(value.content().as_message().unwrap().body(), "Edited");
+        assert_matches!(value.send_state(), Some(EventSendState::NotSentYet));
+    });
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 1, value } => {
+        assert_matches!(value.content(), TimelineItemContent::RedactedMessage);
+        assert_matches!(value.send_state(), Some(EventSendState::NotSentYet));
+    });
+
+    // Wait 200ms for the first msg and 200ms for the other requests.
+    sleep(Duration::from_millis(400)).await;
+
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 0, value } => {
+        assert_eq!(value.event_id().unwrap(), "$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP");
+    });
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 1, value } => {
+        assert_eq!(value.event_id().unwrap(), "$5E2kLK/Sg342bgBU9ceEIEPYpbFaqJpZ");
+    });
+    assert_pending!(timeline_stream);
+
+    // The requests were removed from the persistent send queue once sent.
+    assert!(room.send_queue().await.unwrap().dependent_requests().await.is_empty());
+}
+
+#[async_test]
+async fn local_echo_handles() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = Arc::new(room.timeline().await);
+    let (_, mut timeline_stream) =
+        timeline.subscribe_filter_map(|item| item.as_event().cloned()).await;
+
+    // The first message is still being sent when it is aborted.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("First!"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP" }))
+                .set_delay(Duration::from_secs(60)),
+        )
+        .mount(&server)
+        .await;
+
+    // The second message is sent with its new content, not as an edit.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("Second, edited."))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$5E2kLK/Sg342bgBU9ceEIEPYpbFaqJpZ" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The third message is never sent.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("Third?"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&json!({ "event_id": "$third" })))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    timeline.send(RoomMessageEventContent::text_plain("First!").into(), None).await;
+    timeline.send(RoomMessageEventContent::text_plain("Second.").into(), None).await;
+    timeline.send(RoomMessageEventContent::text_plain("Third?").into(), None).await;
+
+    let first = assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => value);
+    let second = assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => value);
+    let third = assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => value);
+
+    // The other messages are still queued.
+    let new_content = RoomMessageEventContentWithoutRelation::text_plain("Second, edited.");
+    second.local_echo_handle().unwrap().edit(new_content).await.unwrap();
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 1, value } => {
+        let message = value.content().as_message().unwrap();
+        assert_eq!(message.body(), "Second, edited.");
+        assert!(!message.is_edited());
+    });
+
+    assert!(third.local_echo_handle().unwrap().abort().await);
+    assert_next_matches!(timeline_stream, VectorDiff::Remove { index: 2 });
+
+    // The first message is being sent, aborting it cancels the request.
+    assert!(first.local_echo_handle().unwrap().abort().await);
+    assert_next_matches!(timeline_stream, VectorDiff::Remove { index: 0 });
+
+    // Then the second message is sent.
+    assert_matches!(timeline_stream.next().await, Some(VectorDiff::Set { index: 0, value }) => {
+        assert_eq!(value.event_id().unwrap(), "$5E2kLK/Sg342bgBU9ceEIEPYpbFaqJpZ");
+    });
+    assert_pending!(timeline_stream);
+
+    // Nothing is left in the persistent send queue.
+    assert!(room.send_queue().await.unwrap().events().await.is_empty());
+}
+
 #[async_test]
 async fn retry_order() {
     let room_id = room_id!("!a98sd12bjh:example.org");
@@ -282,3 +480,163 @@ async fn clear_with_echoes() {
     assert_matches!(event_items[0].send_state(), Some(EventSendState::SendingFailed { .. }));
     assert_matches!(event_items[1].send_state(), Some(EventSendState::NotSentYet));
 }
+
+#[async_test]
+async fn restore_send_queue() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP" })),
+        )
+        .mount(&server)
+        .await;
+
+    // A message was left in the send queue, e.g. by a previous run of the app.
+    let room = client.get_room(room_id).unwrap();
+    let send_queue = room.send_queue().await.unwrap();
+    send_queue
+        .push_event("my-txn-id".into(), &RoomMessageEventContent::text_plain("Offline").into())
+        .await
+        .unwrap();
+
+    let timeline = Arc::new(room.timeline().await);
+    let (items, mut timeline_stream) =
+        timeline.subscribe_filter_map(|item| item.as_event().cloned()).await;
+
+    // The local echo is restored when the timeline is built…
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].content().as_message().unwrap().body(), "Offline");
+    assert_eq!(items[0].transaction_id().unwrap(), "my-txn-id");
+
+    // … and the message is sent again.
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 0, value } => {
+        assert_matches!(value.send_state(), Some(EventSendState::Sent { .. }));
+    });
+
+    // Once sent, it is removed from the send queue.
+    assert!(send_queue.events().await.is_empty());
+    assert!(room.send_queue().await.unwrap().events().await.is_empty());
+}
+
+#[async_test]
+async fn restore_failed_attachment() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    // The homeserver never answers the upload in time.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(60)))
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+
+    let file_name = "matrix-sdk-ui-restore-failed-attachment.txt";
+    let file_path = env::temp_dir().join(file_name);
+    fs::write(&file_path, "Hello world").unwrap();
+    let url = file_path.to_str().unwrap().to_owned();
+
+    // Skip the timeouts and the delays between the retries.
+    tokio::time::pause();
+    let result =
+        timeline.send_attachment(url.clone(), mime::TEXT_PLAIN, AttachmentConfig::new()).await;
+    assert_matches!(result, Err(Error::FailedSendingAttachment));
+    tokio::time::resume();
+
+    let items = timeline.items().await;
+    let local_echo = items.last().unwrap().as_event().unwrap();
+    assert_matches!(local_echo.send_state(), Some(EventSendState::SendingFailed { .. }));
+    let txn_id = local_echo.transaction_id().unwrap().to_owned();
+    drop(timeline);
+
+    // The attachment is kept in the send queue, marked as failed.
+    let send_queue = room.send_queue().await.unwrap();
+    let events = send_queue.events().await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].transaction_id, txn_id);
+    assert!(events[0].sending_failed);
+    assert_matches!(&events[0].content, QueuedEventContent::Attachment { path, .. } => {
+        assert_eq!(*path, url);
+    });
+
+    // The homeserver can be reached again when the application is restarted.
+    server.reset().await;
+    mock_encryption_state(&server, false).await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&json!({
+            "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let timeline = room.timeline().await;
+    let (mut items, mut timeline_stream) =
+        timeline.subscribe_filter_map(|item| item.as_event().cloned()).await;
+
+    // The local echo of the attachment is restored, and the attachment is sent
+    // again.
+    while !items
+        .front()
+        .is_some_and(|item| matches!(item.send_state(), Some(EventSendState::Sent { .. })))
+    {
+        let diff = timeout(Duration::from_secs(5), timeline_stream.next())
+            .await
+            .expect("the attachment should be sent")
+            .unwrap();
+        match diff {
+            VectorDiff::PushBack { value } => items.push_back(value),
+            VectorDiff::Set { index, value } => {
+                items.set(index, value);
+            }
+            diff => panic!("Unexpected diff: {diff:?}"),
+        }
+    }
+
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].transaction_id().unwrap(), txn_id);
+    assert_eq!(items[0].content().as_message().unwrap().body(), file_name);
+
+    // Once sent, it is removed from the send queue.
+    assert!(send_queue.events().await.is_empty());
+
+    fs::remove_file(file_path).unwrap();
+}
@@ -24,8 +24,10 @@ use matrix_sdk_test::{
 };
 use matrix_sdk_ui::timeline::RoomExt;
 use ruma::{
-    api::client::receipt::create_receipt::v3::ReceiptType, event_id,
-    events::receipt::ReceiptThread, room_id, user_id,
+    api::client::receipt::create_receipt::v3::ReceiptType,
+    event_id,
+    events::receipt::{ReceiptThread, ReceiptType as EventReceiptType},
+    room_id, user_id,
 };
 use serde_json::json;
 use wiremock::{
@@ -237,6 +239,80 @@ async fn read_receipts_updates() {
     assert_eq!(own_user_receipt_event_id, second_event_id);
 }
 
+#[async_test]
+async fn user_receipt_and_timeline_index() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let own_user_id = client.user_id().unwrap();
+    let alice = user_id!("@alice:localhost");
+    let bob = user_id!("@bob:localhost");
+
+    let second_event_id = event_id!("$e32037280er453l:localhost");
+    let third_event_id = event_id!("$Sg2037280074GZr34:localhost");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let (_, mut timeline_stream) = timeline.subscribe().await;
+
+    assert_matches!(timeline.user_receipt(alice, EventReceiptType::Read).await, None);
+    assert_eq!(timeline.latest_user_read_receipt_timeline_index(alice).await, None);
+
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::MessageText)
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {
+                    "body": "I'm dancing too",
+                    "msgtype": "m.text"
+                },
+                "event_id": second_event_id,
+                "origin_server_ts": 152039280,
+                "sender": alice,
+                "type": "m.room.message",
+            })))
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {
+                    "body": "Viva la macarena!",
+                    "msgtype": "m.text"
+                },
+                "event_id": third_event_id,
+                "origin_server_ts": 152045280,
+                "sender": alice,
+                "type": "m.room.message",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    // Wait for the day divider, the 3 events and the move of the receipt of
+    // @alice:localhost.
+    for _ in 0..5 {
+        timeline_stream.next().await.unwrap();
+    }
+
+    // The implicit read receipt of @alice:localhost is on the third event.
+    let (alice_receipt_event_id, _) =
+        timeline.user_receipt(alice, EventReceiptType::Read).await.unwrap();
+    assert_eq!(alice_receipt_event_id, third_event_id);
+    assert_matches!(timeline.user_receipt(alice, EventReceiptType::ReadPrivate).await, None);
+
+    // The indexes of the items with the receipts, after the day divider.
+    assert_eq!(timeline.latest_user_read_receipt_timeline_index(own_user_id).await, Some(1));
+    assert_eq!(timeline.latest_user_read_receipt_timeline_index(alice).await, Some(3));
+    assert_eq!(timeline.latest_user_read_receipt_timeline_index(bob).await, None);
+}
+
 #[async_test]
 async fn send_single_receipt() {
     let room_id = room_id!("!a98sd12bjh:example.org");
@@ -12,14 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use assert_matches::assert_matches;
 use eyeball_im::VectorDiff;
-use futures_util::StreamExt;
+use futures_util::{pin_mut, StreamExt};
 use matrix_sdk::config::SyncSettings;
 use matrix_sdk_test::{async_test, JoinedRoomBuilder, SyncResponseBuilder, TimelineTestEvent};
-use matrix_sdk_ui::timeline::{RoomExt, TimelineItemContent};
+use matrix_sdk_ui::timeline::{RoomExt, TimelineItem, TimelineItemContent};
 use ruma::{event_id, events::room::message::MessageType, room_id};
 use serde_json::json;
 
@@ -172,3 +172,82 @@ async fn event_filter() {
     assert_eq!(text.body, "hi");
     assert!(msg.is_edited());
 }
+
+#[async_test]
+async fn window() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+    let (items, timeline_stream, window) = timeline.subscribe_window(0..2).await;
+    pin_mut!(timeline_stream);
+    assert!(items.is_empty());
+
+    let message = |event_id: &str, body: &str, sender: &str| {
+        TimelineTestEvent::Custom(json!({
+            "content": { "body": body, "msgtype": "m.text" },
+            "event_id": event_id,
+            "origin_server_ts": 152037280,
+            "sender": sender,
+            "type": "m.room.message",
+        }))
+    };
+    let body = |item: &Arc<TimelineItem>| {
+        item.as_event().unwrap().content().as_message().unwrap().body().to_owned()
+    };
+
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(message("$a", "a", "@alice:localhost"))
+            .add_timeline_event(message("$b", "b", "@bob:localhost"))
+            .add_timeline_event(message("$c", "c", "@carol:localhost")),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    // Only the day divider and the first message are in the window.
+    let mut seen = items;
+    for diff in timeline_stream.next().await.unwrap() {
+        match diff {
+            VectorDiff::Insert { index, value } => seen.insert(index, value),
+            VectorDiff::Set { index, value } => {
+                seen.set(index, value);
+            }
+            diff => panic!("unexpected diff: {diff:?}"),
+        }
+    }
+    assert_eq!(seen.len(), 2);
+    assert!(seen[0].as_virtual().is_some());
+    assert_eq!(body(&seen[1]), "a");
+
+    // Moving the window resets it with the new items.
+    window.set_range(2..4);
+    let batch = timeline_stream.next().await.unwrap();
+    let values = assert_matches!(&batch[..], [VectorDiff::Reset { values }] => values);
+    assert_eq!(values.iter().map(body).collect::<Vec<_>>(), ["b", "c"]);
+
+    // The items added after the window are not sent.
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(message(
+        "$d",
+        "d",
+        "@dave:localhost",
+    )));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    window.set_range(3..5);
+    let batch = timeline_stream.next().await.unwrap();
+    let values = assert_matches!(&batch[..], [VectorDiff::Reset { values }] => values);
+    assert_eq!(values.iter().map(body).collect::<Vec<_>>(), ["c", "d"]);
+}
@@ -0,0 +1,176 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use assert_matches::assert_matches;
+use eyeball_im::VectorDiff;
+use futures_util::StreamExt;
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk_test::{async_test, JoinedRoomBuilder, SyncResponseBuilder, TimelineTestEvent};
+use matrix_sdk_ui::timeline::{
+    BackPaginationStatus, Error as TimelineError, PaginationOptions, RoomExt, TimelineFocus,
+};
+use ruma::{event_id, owned_event_id, room_id};
+use serde_json::json;
+use wiremock::{
+    matchers::{header, method, path_regex},
+    Mock, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync};
+
+#[async_test]
+async fn thread_timeline() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let root_event_id = event_id!("$threadroot");
+    let reply_event_id = event_id!("$threadreply");
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room
+        .timeline_builder()
+        .with_focus(TimelineFocus::Thread { root_event_id: root_event_id.to_owned() })
+        .build()
+        .await;
+
+    assert!(timeline.items().await.is_empty());
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/v1/rooms/.*/relations/\$threadroot/m\.thread"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [{
+                "content": {
+                    "body": "reply",
+                    "msgtype": "m.text",
+                    "m.relates_to": {
+                        "rel_type": "m.thread",
+                        "event_id": root_event_id,
+                    },
+                },
+                "room_id": room_id,
+                "event_id": reply_event_id,
+                "origin_server_ts": 152037290,
+                "sender": "@bob:example.org",
+                "type": "m.room.message",
+            }],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/event/\$threadroot"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "content": {
+                "body": "root",
+                "msgtype": "m.text",
+            },
+            "room_id": room_id,
+            "event_id": root_event_id,
+            "origin_server_ts": 152037280,
+            "sender": "@alice:example.org",
+            "type": "m.room.message",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    timeline.paginate_backwards(PaginationOptions::single_request(10)).await.unwrap();
+    server.reset().await;
+
+    // The thread is fully loaded, with the root first.
+    assert_eq!(timeline.back_pagination_status().get(), BackPaginationStatus::TimelineStartReached);
+    let items = timeline.items().await;
+    assert_eq!(items.len(), 3);
+    assert!(items[0].as_virtual().is_some());
+    assert_eq!(items[1].as_event().unwrap().event_id(), Some(root_event_id));
+    assert_eq!(items[2].as_event().unwrap().event_id(), Some(reply_event_id));
+
+    let (_, mut timeline_stream) = timeline.subscribe().await;
+
+    // Events from sync that are not in the thread are ignored.
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::MessageText)
+            .add_timeline_event(TimelineTestEvent::Custom(json!({
+                "content": {
+                    "body": "another reply",
+                    "msgtype": "m.text",
+                    "m.relates_to": {
+                        "rel_type": "m.thread",
+                        "event_id": root_event_id,
+                    },
+                },
+                "event_id": "$threadreply2",
+                "origin_server_ts": 152037300,
+                "sender": "@alice:example.org",
+                "type": "m.room.message",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let reply = assert_matches!(
+        timeline_stream.next().await,
+        Some(VectorDiff::PushBack { value }) => value
+    );
+    assert_eq!(reply.as_event().unwrap().event_id(), Some(event_id!("$threadreply2")));
+}
+
+#[async_test]
+async fn focus_on_event_in_thread_timeline() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/context/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room
+        .timeline_builder()
+        .with_focus(TimelineFocus::Thread { root_event_id: owned_event_id!("$threadroot") })
+        .build()
+        .await;
+
+    // The events of the thread can't be replaced by the context of an event.
+    let error = timeline.focus_on_event(event_id!("$threadreply"), 2).await.unwrap_err();
+    assert_matches!(error, TimelineError::UnsupportedFocus);
+    assert!(timeline.items().await.is_empty());
+}
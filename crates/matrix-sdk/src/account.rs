@@ -14,6 +14,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use futures_core::Stream;
 use matrix_sdk_base::{
     media::{MediaFormat, MediaRequest},
     store::StateStoreExt,
@@ -23,10 +24,12 @@ use mime::Mime;
 use ruma::{
     api::client::{
         account::{
-            add_3pid, change_password, deactivate, delete_3pid, get_3pids,
+            add_3pid, bind_3pid, change_password, deactivate, delete_3pid, get_3pids,
             request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
+            unbind_3pid, IdentityServerInfo,
         },
-        config::set_global_account_data,
+        config::{get_global_account_data, set_global_account_data},
+        error::ErrorKind,
         profile::{
             get_avatar_url, get_display_name, get_profile, set_avatar_url, set_display_name,
         },
@@ -37,7 +40,7 @@ use ruma::{
         ignored_user_list::{IgnoredUser, IgnoredUserListEventContent},
         push_rules::PushRulesEventContent,
         room::MediaSource,
-        AnyGlobalAccountDataEventContent, GlobalAccountDataEventContent,
+        AnyGlobalAccountDataEventContent, GlobalAccountDataEvent, GlobalAccountDataEventContent,
         GlobalAccountDataEventType, StaticEventContent,
     },
     push::Ruleset,
@@ -45,10 +48,15 @@ use ruma::{
     thirdparty::Medium,
     ClientSecret, MxcUri, OwnedMxcUri, OwnedUserId, RoomId, SessionId, UInt, UserId,
 };
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
 use tracing::error;
 
-use crate::{config::RequestConfig, Client, Error, HttpError, Result};
+use crate::{
+    config::RequestConfig,
+    identity_server::IdentityServerClient,
+    uiaa::{UiaaFlow, UiaaOutcome},
+    Client, Error, HttpError, Result,
+};
 
 /// A high-level API to manage the client owner's account.
 ///
@@ -338,7 +346,8 @@ impl Account {
     /// API][uiaa]. The first request needs to set this to `None` and will
     /// always fail with an [`UiaaResponse`]. The response will contain
     /// information for the interactive auth and the same request needs to be
-    /// made but this time with some `auth_data` provided.
+    /// made but this time with some `auth_data` provided. This can be done
+    /// with [`Client::send_with_uiaa()`].
     ///
     /// # Examples
     ///
@@ -368,10 +377,32 @@ impl Account {
         &self,
         id_server: Option<&str>,
         auth_data: Option<AuthData>,
+    ) -> Result<deactivate::v3::Response> {
+        self.send_deactivate(id_server, auth_data, false).await
+    }
+
+    /// Deactivate this account definitively, and ask the homeserver to erase
+    /// its data, like the messages sent by the user, as much as possible.
+    ///
+    /// See [`Account::deactivate()`] for the arguments.
+    pub async fn deactivate_and_erase(
+        &self,
+        id_server: Option<&str>,
+        auth_data: Option<AuthData>,
+    ) -> Result<deactivate::v3::Response> {
+        self.send_deactivate(id_server, auth_data, true).await
+    }
+
+    async fn send_deactivate(
+        &self,
+        id_server: Option<&str>,
+        auth_data: Option<AuthData>,
+        erase: bool,
     ) -> Result<deactivate::v3::Response> {
         let request = assign!(deactivate::v3::Request::new(), {
             id_server: id_server.map(ToOwned::to_owned),
             auth: auth_data,
+            erase,
         });
         Ok(self.client.send(request, None).await?)
     }
@@ -658,6 +689,66 @@ impl Account {
         Ok(self.client.send(request, None).await?)
     }
 
+    /// Bind a [Third Party Identifier][3pid] of this account on an identity
+    /// server, so other users can discover the account with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_secret` - The client secret used when requesting the
+    /// validation token of the 3PID.
+    ///
+    /// * `sid` - The session ID of the validated token.
+    ///
+    /// * `identity_server` - The identity server to bind the 3PID on.
+    ///
+    /// [3pid]: https://spec.matrix.org/v1.2/appendices/#3pid-types
+    pub async fn bind_3pid(
+        &self,
+        client_secret: &ClientSecret,
+        sid: &SessionId,
+        identity_server: &IdentityServerClient,
+    ) -> Result<bind_3pid::v3::Response> {
+        let identity_server_info = IdentityServerInfo::new(
+            identity_server.server_name().to_owned(),
+            identity_server.access_token().to_owned(),
+        );
+        let request = bind_3pid::v3::Request::new(
+            client_secret.to_owned(),
+            identity_server_info,
+            sid.to_owned(),
+        );
+        Ok(self.client.send(request, None).await?)
+    }
+
+    /// Unbind a [Third Party Identifier][3pid] of this account from an
+    /// identity server, so other users can't discover the account with it
+    /// anymore.
+    ///
+    /// The 3PID stays associated with the account on the homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The 3PID being unbound.
+    ///
+    /// * `medium` - The type of the 3PID.
+    ///
+    /// * `id_server` - The identity server to unbind from. If not provided,
+    /// the homeserver should unbind the 3PID from the identity server it was
+    /// bound to previously.
+    ///
+    /// [3pid]: https://spec.matrix.org/v1.2/appendices/#3pid-types
+    pub async fn unbind_3pid(
+        &self,
+        address: &str,
+        medium: Medium,
+        id_server: Option<&str>,
+    ) -> Result<unbind_3pid::v3::Response> {
+        let request = assign!(unbind_3pid::v3::Request::new(medium, address.to_owned()), {
+            id_server: id_server.map(ToOwned::to_owned),
+        });
+        Ok(self.client.send(request, None).await?)
+    }
+
     /// Get the content of an account data event of statically-known type.
     ///
     /// # Examples
@@ -686,6 +777,50 @@ impl Account {
         get_raw_content(self.client.store().get_account_data_event_static::<C>().await?)
     }
 
+    /// Get a stream of the content of an account data event of
+    /// statically-known type.
+    ///
+    /// A new value is yielded every time an event of this type is received in
+    /// a sync response. Use [`Account::account_data()`] to get the current
+    /// value.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # async {
+    /// # let client = Client::new("http://localhost:8080".parse()?).await?;
+    /// use futures_util::{pin_mut, StreamExt};
+    /// use matrix_sdk::ruma::events::ignored_user_list::IgnoredUserListEventContent;
+    ///
+    /// let stream =
+    ///     client.account().subscribe_to_account_data::<IgnoredUserListEventContent>();
+    /// pin_mut!(stream);
+    ///
+    /// while let Some(content) = stream.next().await {
+    ///     println!("Ignored users: {:?}", content.ignored_users.keys());
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn subscribe_to_account_data<C>(&self) -> impl Stream<Item = C>
+    where
+        C: GlobalAccountDataEventContent + StaticEventContent + Send + 'static,
+        GlobalAccountDataEvent<C>: DeserializeOwned,
+    {
+        let (sender, receiver) = async_channel::unbounded();
+
+        let handle = self.client.add_event_handler(move |event: GlobalAccountDataEvent<C>| {
+            let sender = sender.clone();
+            async move {
+                // The receiver is only dropped with the stream, which also
+                // removes this event handler.
+                let _ = sender.send(event.content).await;
+            }
+        });
+
+        self.client.event_handler_stream(handle, receiver)
+    }
+
     /// Get the content of an account data event of a given type.
     pub async fn account_data_raw(
         &self,
@@ -694,6 +829,32 @@ impl Account {
         get_raw_content(self.client.store().get_account_data_event(event_type).await?)
     }
 
+    /// Fetch the content of an account data event of a given type from the
+    /// homeserver.
+    ///
+    /// Unlike [`Account::account_data_raw()`], this doesn't use the local
+    /// store, so the result is up-to-date even if the client isn't syncing.
+    ///
+    /// Returns `None` if the homeserver doesn't have an account data event of
+    /// this type.
+    pub async fn fetch_account_data(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEventContent>>> {
+        let own_user =
+            self.client.user_id().ok_or_else(|| Error::from(HttpError::AuthenticationRequired))?;
+
+        let request = get_global_account_data::v3::Request::new(own_user.to_owned(), event_type);
+
+        match self.client.send(request, None).await {
+            Ok(response) => Ok(Some(response.account_data)),
+            Err(error) if matches!(error.client_api_error_kind(), Some(ErrorKind::NotFound)) => {
+                Ok(None)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
     /// Set the given account data event.
     ///
     /// # Examples
@@ -769,16 +930,22 @@ impl Account {
 
         // Now we need to mark the room as a DM for ourselves, we fetch the
         // existing `m.direct` event and append the room to the list of DMs we
-        // have with this user.
+        // have with this user. The event is fetched from the homeserver rather
+        // than the store, to avoid overwriting the DMs that were added by other
+        // devices and not received via sync yet.
         let mut content = self
-            .account_data::<DirectEventContent>()
+            .fetch_account_data(GlobalAccountDataEventType::Direct)
             .await?
-            .map(|c| c.deserialize())
+            .map(|c| c.deserialize_as::<DirectEventContent>())
             .transpose()?
             .unwrap_or_default();
 
         for user_id in user_ids {
-            content.entry(user_id.to_owned()).or_default().push(room_id.to_owned());
+            let room_ids = content.entry(user_id.to_owned()).or_default();
+
+            if !room_ids.iter().any(|id| *id == *room_id) {
+                room_ids.push(room_id.to_owned());
+            }
         }
 
         // TODO We should probably save the fact that we need to send this out
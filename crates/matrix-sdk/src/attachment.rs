@@ -32,6 +32,15 @@ use ruma::{
 #[cfg(feature = "image-proc")]
 use crate::ImageError;
 
+/// The number of horizontal and vertical components of the BlurHashes we
+/// compute.
+#[cfg(feature = "image-proc")]
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// The maximum width and height of the image used to compute a BlurHash.
+#[cfg(feature = "image-proc")]
+const BLURHASH_SOURCE_SIZE: u32 = 64;
+
 /// Base metadata about an image.
 #[derive(Debug, Clone)]
 pub struct BaseImageInfo {
@@ -77,7 +86,7 @@ pub struct BaseFileInfo {
 }
 
 /// Types of metadata for an attachment.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AttachmentInfo {
     /// The metadata of an image.
     Image(BaseImageInfo),
@@ -87,6 +96,15 @@ pub enum AttachmentInfo {
     Audio(BaseAudioInfo),
     /// The metadata of a file.
     File(BaseFileInfo),
+    /// The metadata of a voice message, as defined in [MSC3245].
+    ///
+    /// [MSC3245]: https://github.com/matrix-org/matrix-spec-proposals/pull/3245
+    Voice {
+        /// The metadata of the audio clip.
+        audio_info: BaseAudioInfo,
+        /// The amplitudes of the audio clip, between 0 and 1024.
+        waveform: Option<Vec<u16>>,
+    },
 }
 
 impl From<AttachmentInfo> for ImageInfo {
@@ -121,10 +139,12 @@ impl From<AttachmentInfo> for VideoInfo {
 impl From<AttachmentInfo> for AudioInfo {
     fn from(info: AttachmentInfo) -> Self {
         match info {
-            AttachmentInfo::Audio(info) => assign!(AudioInfo::new(), {
-                duration: info.duration,
-                size: info.size,
-            }),
+            AttachmentInfo::Audio(info) | AttachmentInfo::Voice { audio_info: info, .. } => {
+                assign!(AudioInfo::new(), {
+                    duration: info.duration,
+                    size: info.size,
+                })
+            }
             _ => AudioInfo::new(),
         }
     }
@@ -163,7 +183,7 @@ impl From<BaseThumbnailInfo> for ThumbnailInfo {
 }
 
 /// A thumbnail to upload and send for an attachment.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Thumbnail {
     /// The raw bytes of the thumbnail.
     pub data: Vec<u8>,
@@ -174,7 +194,7 @@ pub struct Thumbnail {
 }
 
 /// Configuration for sending an attachment.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AttachmentConfig {
     pub(crate) txn_id: Option<OwnedTransactionId>,
     pub(crate) info: Option<AttachmentInfo>,
@@ -201,13 +221,18 @@ impl AttachmentConfig {
         }
     }
 
-    /// Generate the thumbnail to send for this media.
+    /// Generate the thumbnail and the BlurHash to send for this media.
     ///
-    /// Uses [`generate_image_thumbnail()`].
+    /// Uses [`generate_image_thumbnail()`] and [`generate_blurhash()`].
     ///
     /// Thumbnails can only be generated for supported image attachments. For
     /// more information, see the [image](https://github.com/image-rs/image)
-    /// crate.
+    /// crate. If a thumbnail was already provided, it is used instead.
+    ///
+    /// The BlurHash is computed from the thumbnail if there is one, or from
+    /// the image itself, so it is also computed for videos if a thumbnail was
+    /// provided. It is added to the [`AttachmentInfo`], unless one was
+    /// already set.
     ///
     /// # Arguments
     ///
@@ -369,3 +394,64 @@ pub fn generate_image_thumbnail<R: BufRead + Seek>(
         },
     ))
 }
+
+/// Compute the [BlurHash](https://blurha.sh/) of an image.
+///
+/// The image is scaled down before computing the BlurHash, so this is cheap
+/// even for big images.
+///
+/// # Arguments
+/// * `content_type` - The type of the image.
+///
+/// * `reader` - A `Reader` that will be used to fetch the raw bytes of the
+/// image.
+#[cfg(feature = "image-proc")]
+pub fn generate_blurhash<R: BufRead + Seek>(
+    content_type: &mime::Mime,
+    reader: R,
+) -> Result<String, ImageError> {
+    let image_format =
+        image::ImageFormat::from_mime_type(content_type).ok_or(ImageError::FormatNotSupported)?;
+
+    let image =
+        image::load(reader, image_format)?.thumbnail(BLURHASH_SOURCE_SIZE, BLURHASH_SOURCE_SIZE);
+    let (width, height) = image.dimensions();
+
+    blurhash::encode(
+        BLURHASH_COMPONENTS.0,
+        BLURHASH_COMPONENTS.1,
+        width,
+        height,
+        &image.to_rgba8().into_raw(),
+    )
+    .map_err(|_| ImageError::BlurhashFailed)
+}
+
+#[cfg(all(test, feature = "image-proc"))]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, Rgb, RgbImage};
+
+    use super::generate_blurhash;
+
+    #[test]
+    fn test_generate_blurhash() {
+        let image =
+            RgbImage::from_fn(320, 240, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 128]));
+        let mut data = Vec::new();
+        image.write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png).unwrap();
+
+        let blurhash = generate_blurhash(&mime::IMAGE_PNG, Cursor::new(&data)).unwrap();
+
+        // 1 character for the number of components, 1 for the maximum AC
+        // component value, 4 for the DC component and 2 for each of the 11 AC
+        // components.
+        assert_eq!(blurhash.len(), 28);
+    }
+
+    #[test]
+    fn test_generate_blurhash_unsupported_format() {
+        generate_blurhash(&mime::TEXT_PLAIN, Cursor::new(b"Hello world")).unwrap_err();
+    }
+}
@@ -12,11 +12,40 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::fmt;
+
 use matrix_sdk_base::SessionMeta;
 
-use crate::matrix_auth::{self, MatrixAuth, MatrixAuthData};
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::{self, Oidc, OidcAuthData};
+use crate::{
+    matrix_auth::{self, MatrixAuth, MatrixAuthData},
+    Client,
+};
+
+/// The error type returned by the session callbacks.
+pub type SessionCallbackError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Callback used to load the latest session persisted by the application, see
+/// [`Client::set_session_callbacks()`].
+pub type ReloadSessionCallback =
+    dyn Fn(Client) -> Result<AuthSession, SessionCallbackError> + Send + Sync;
+
+/// Callback used to persist the session after its tokens were refreshed, see
+/// [`Client::set_session_callbacks()`].
+pub type SaveSessionCallback = dyn Fn(Client) -> Result<(), SessionCallbackError> + Send + Sync;
+
+/// The callbacks set with [`Client::set_session_callbacks()`].
+pub(crate) struct SessionCallbacks {
+    pub(crate) reload_session: Box<ReloadSessionCallback>,
+    pub(crate) save_session: Box<SaveSessionCallback>,
+}
+
+impl fmt::Debug for SessionCallbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionCallbacks").finish_non_exhaustive()
+    }
+}
 
 /// An enum over all the possible authentication APIs.
 #[derive(Debug, Clone)]
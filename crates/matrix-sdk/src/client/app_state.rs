@@ -0,0 +1,336 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::BTreeMap,
+    mem,
+    sync::{atomic::Ordering, Arc, Weak},
+    time::Duration,
+};
+
+use eyeball::Subscriber;
+use matrix_sdk_common::{executor::spawn, sleep::sleep};
+use ruma::{
+    api::client::{
+        read_marker::set_read_marker,
+        receipt::create_receipt::{self, v3::ReceiptType},
+    },
+    assign,
+    events::receipt::ReceiptThread,
+    OwnedEventId, OwnedRoomId, RoomId,
+};
+use thiserror::Error;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use super::{Client, ClientInner};
+use crate::{room::Receipts, Error, Result};
+
+/// The minimum timeout of the long-polling sync requests while the app is in
+/// the background.
+const BACKGROUND_SYNC_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often the receipts kept while the app is in the background are sent.
+const BACKGROUND_RECEIPTS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Whether the app using the client is visible to the user.
+///
+/// Set it with [`Client::set_app_state()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AppState {
+    /// The app is visible to the user.
+    #[default]
+    Foreground,
+
+    /// The app is in the background.
+    ///
+    /// To save battery, the long-polling sync requests use a longer timeout,
+    /// and the receipts are sent in batches.
+    Background,
+}
+
+/// An error when sending receipts that were kept while the app was in the
+/// background.
+#[derive(Debug, Error)]
+enum DelayedReceiptsError {
+    /// The receipts were dropped before being sent, because the room or the
+    /// client are gone.
+    #[error("the receipts were dropped before being sent")]
+    Dropped,
+
+    /// The request sending the receipts failed.
+    #[error("failed to send the receipts: {0}")]
+    Failed(Arc<Error>),
+}
+
+/// The receipts whose sending is delayed while the app is in the background.
+#[derive(Debug, Default)]
+pub(crate) struct PendingReceipts {
+    rooms: BTreeMap<OwnedRoomId, RoomPendingReceipts>,
+}
+
+/// The receipts of a room whose sending is delayed.
+#[derive(Debug)]
+struct RoomPendingReceipts {
+    /// The receipts of the main timeline, sent with a single request.
+    unthreaded: Receipts,
+
+    /// The other receipts, only the latest one for a given receipt type and
+    /// thread.
+    threaded: Vec<(ReceiptType, ReceiptThread, OwnedEventId)>,
+
+    /// Notifies the callers that added the receipts once they were sent.
+    sent: watch::Sender<Option<Result<(), Arc<Error>>>>,
+}
+
+impl PendingReceipts {
+    pub(crate) fn add(
+        &mut self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: OwnedEventId,
+    ) -> ReceiptsSent {
+        let pending = self.room(room_id);
+
+        if thread == ReceiptThread::Unthreaded {
+            let receipts = &mut pending.unthreaded;
+            let receipt = match receipt_type {
+                ReceiptType::FullyRead => Some(&mut receipts.fully_read),
+                ReceiptType::Read => Some(&mut receipts.public_read_receipt),
+                ReceiptType::ReadPrivate => Some(&mut receipts.private_read_receipt),
+                _ => None,
+            };
+
+            if let Some(receipt) = receipt {
+                *receipt = Some(event_id);
+                return ReceiptsSent(pending.sent.subscribe());
+            }
+        }
+
+        let existing = pending.threaded.iter_mut().find(|(existing_type, existing_thread, _)| {
+            *existing_type == receipt_type && *existing_thread == thread
+        });
+        match existing {
+            Some((.., existing_event_id)) => *existing_event_id = event_id,
+            None => pending.threaded.push((receipt_type, thread, event_id)),
+        }
+
+        ReceiptsSent(pending.sent.subscribe())
+    }
+
+    pub(crate) fn add_multiple(&mut self, room_id: &RoomId, receipts: Receipts) -> ReceiptsSent {
+        let pending = self.room(room_id);
+        let unthreaded = &mut pending.unthreaded;
+        unthreaded.fully_read = receipts.fully_read.or(unthreaded.fully_read.take());
+        unthreaded.public_read_receipt =
+            receipts.public_read_receipt.or(unthreaded.public_read_receipt.take());
+        unthreaded.private_read_receipt =
+            receipts.private_read_receipt.or(unthreaded.private_read_receipt.take());
+
+        ReceiptsSent(pending.sent.subscribe())
+    }
+
+    fn room(&mut self, room_id: &RoomId) -> &mut RoomPendingReceipts {
+        self.rooms.entry(room_id.to_owned()).or_insert_with(|| RoomPendingReceipts {
+            unthreaded: Receipts::new(),
+            threaded: Vec::new(),
+            sent: watch::channel(None).0,
+        })
+    }
+}
+
+/// The notification that receipts kept while the app is in the background
+/// were sent.
+pub(crate) struct ReceiptsSent(watch::Receiver<Option<Result<(), Arc<Error>>>>);
+
+impl ReceiptsSent {
+    /// Wait until the receipts are sent, and get the result of the request.
+    pub(crate) async fn wait(mut self) -> Result<()> {
+        // The result is only sent once, so it doesn't matter whether the
+        // sender was dropped since.
+        let _ = self.0.changed().await;
+
+        let error = match self.0.borrow().clone() {
+            Some(Ok(())) => return Ok(()),
+            Some(Err(error)) => DelayedReceiptsError::Failed(error),
+            None => DelayedReceiptsError::Dropped,
+        };
+        Err(Error::UnknownError(Box::new(error)))
+    }
+}
+
+impl Client {
+    /// Whether the app using this client is visible to the user.
+    pub fn app_state(&self) -> AppState {
+        self.inner.app_state.get()
+    }
+
+    /// Subscribe to the changes of the [`AppState`].
+    pub fn subscribe_to_app_state(&self) -> Subscriber<AppState> {
+        self.inner.app_state.subscribe()
+    }
+
+    /// Report whether the app using this client is visible to the user.
+    ///
+    /// While the app is in the [background](AppState::Background), the client
+    /// reduces its battery usage without stopping to sync:
+    ///
+    /// * the long-polling sync requests use a timeout of at least 2 minutes,
+    /// * the receipts sent with [`Room::send_single_receipt()`] and
+    ///   [`Room::send_multiple_receipts()`] are sent every 30 seconds, only the
+    ///   latest ones of each room. Those methods return once the receipts were
+    ///   actually sent.
+    ///
+    /// The receipts that are still kept when the app is back in the
+    /// foreground are sent right away.
+    ///
+    /// The new state is applied before this method returns, so consecutive
+    /// calls are applied in order. This must be called from within a tokio
+    /// runtime.
+    ///
+    /// [`Room::send_single_receipt()`]: crate::Room::send_single_receipt
+    /// [`Room::send_multiple_receipts()`]: crate::Room::send_multiple_receipts
+    pub fn set_app_state(&self, app_state: AppState) {
+        if self.inner.app_state.set_if_not_eq(app_state).is_none() {
+            return;
+        }
+
+        debug!(?app_state, "The app state changed");
+
+        match app_state {
+            AppState::Background => {
+                if !self.inner.background_receipts_task_started.swap(true, Ordering::SeqCst) {
+                    spawn(send_receipts_periodically(Arc::downgrade(&self.inner)));
+                }
+            }
+            AppState::Foreground => {
+                let client = self.clone();
+                spawn(async move { client.send_pending_receipts().await });
+            }
+        }
+    }
+
+    /// Send the receipts that were kept while the app was in the background.
+    async fn send_pending_receipts(&self) {
+        let rooms = mem::take(&mut self.inner.pending_receipts.lock().unwrap().rooms);
+
+        for (room_id, pending) in rooms {
+            // If the room is gone, dropping the receipts notifies the callers.
+            if self.get_room(&room_id).is_none() {
+                continue;
+            }
+
+            let result =
+                self.send_room_receipts(&room_id, pending.unthreaded, pending.threaded).await;
+            if let Err(error) = &result {
+                warn!(%room_id, "Failed to send pending receipts: {error}");
+            }
+
+            pending.sent.send_replace(Some(result.map_err(Arc::new)));
+        }
+    }
+
+    /// Send the given receipts of the given room.
+    async fn send_room_receipts(
+        &self,
+        room_id: &RoomId,
+        unthreaded: Receipts,
+        threaded: Vec<(ReceiptType, ReceiptThread, OwnedEventId)>,
+    ) -> Result<()> {
+        if !unthreaded.is_empty() {
+            let Receipts { fully_read, public_read_receipt, private_read_receipt } = unthreaded;
+            let request = assign!(set_read_marker::v3::Request::new(room_id.to_owned()), {
+                fully_read,
+                read_receipt: public_read_receipt,
+                private_read_receipt,
+            });
+            self.send(request, None).await?;
+        }
+
+        for (receipt_type, thread, event_id) in threaded {
+            let mut request =
+                create_receipt::v3::Request::new(room_id.to_owned(), receipt_type, event_id);
+            request.thread = thread;
+            self.send(request, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the receipts should be kept instead of being sent right away.
+    pub(crate) fn should_delay_receipts(&self) -> bool {
+        self.app_state() == AppState::Background
+    }
+
+    /// The timeout to use for a long-polling sync request, given the timeout
+    /// that was asked for.
+    pub(crate) fn sync_timeout(&self, timeout: Duration) -> Duration {
+        // A timeout of zero is used to get the latest changes right away.
+        if self.app_state() == AppState::Background && !timeout.is_zero() {
+            timeout.max(BACKGROUND_SYNC_TIMEOUT)
+        } else {
+            timeout
+        }
+    }
+}
+
+/// Send the receipts kept while the app is in the background periodically,
+/// until the client is dropped.
+async fn send_receipts_periodically(client: Weak<ClientInner>) {
+    loop {
+        sleep(BACKGROUND_RECEIPTS_INTERVAL).await;
+
+        let Some(inner) = client.upgrade() else {
+            return;
+        };
+        let client = Client { inner };
+
+        if client.app_state() == AppState::Background {
+            client.send_pending_receipts().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        api::client::receipt::create_receipt::v3::ReceiptType, events::receipt::ReceiptThread,
+        owned_event_id, room_id,
+    };
+
+    use super::PendingReceipts;
+    use crate::room::Receipts;
+
+    #[test]
+    fn pending_receipts_keep_the_latest() {
+        let room_id = room_id!("!room:localhost");
+        let mut pending = PendingReceipts::default();
+
+        pending.add(room_id, ReceiptType::Read, ReceiptThread::Unthreaded, owned_event_id!("$1"));
+        pending.add(room_id, ReceiptType::Read, ReceiptThread::Unthreaded, owned_event_id!("$2"));
+        pending.add_multiple(room_id, Receipts::new().fully_read_marker(owned_event_id!("$2")));
+        pending.add(room_id, ReceiptType::Read, ReceiptThread::Main, owned_event_id!("$3"));
+        pending.add(room_id, ReceiptType::Read, ReceiptThread::Main, owned_event_id!("$4"));
+
+        let receipts = &pending.rooms[room_id].unthreaded;
+        assert_eq!(receipts.public_read_receipt, Some(owned_event_id!("$2")));
+        assert_eq!(receipts.fully_read, Some(owned_event_id!("$2")));
+        assert_eq!(receipts.private_read_receipt, None);
+
+        let threaded = &pending.rooms[room_id].threaded;
+        assert_eq!(threaded.len(), 1);
+        assert_eq!(threaded[0].2, owned_event_id!("$4"));
+    }
+}
@@ -13,21 +13,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{fmt, sync::Arc};
+use std::{fmt, sync::Arc, time::Duration};
 
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_base::crypto::{DecryptionSettings, TrustRequirement};
 use matrix_sdk_base::{store::StoreConfig, BaseClient};
 use ruma::{
-    api::{client::discovery::discover_homeserver, error::FromHttpResponseError, MatrixVersion},
+    api::{error::FromHttpResponseError, MatrixVersion},
     OwnedServerName, ServerName,
 };
 use thiserror::Error;
 use tracing::{debug, field::debug, instrument, Span};
 use url::Url;
 
-use super::{Client, ClientInner};
+use super::{Client, ClientInner, ClientWellKnown};
 #[cfg(not(target_arch = "wasm32"))]
 use crate::http_client::HttpSettings;
-use crate::{config::RequestConfig, error::RumaApiError, http_client::HttpClient, HttpError};
+use crate::{
+    config::RequestConfig, error::RumaApiError, formatting::MessageFormatter,
+    http_client::HttpClient, HttpError,
+};
 
 /// Builder that allows creating and configuring various parts of a [`Client`].
 ///
@@ -80,6 +85,18 @@ pub struct ClientBuilder {
     appservice_mode: bool,
     server_versions: Option<Box<[MatrixVersion]>>,
     handle_refresh_tokens: bool,
+    index_encrypted_messages: bool,
+    follow_room_upgrades: bool,
+    max_local_history_age: Option<Duration>,
+    cache_room_events: bool,
+    message_formatter: Option<Arc<dyn MessageFormatter>>,
+    read_only: bool,
+    #[cfg(feature = "e2e-encryption")]
+    room_key_trust_requirement: TrustRequirement,
+    #[cfg(feature = "e2e-encryption")]
+    decryption_settings: DecryptionSettings,
+    #[cfg(feature = "e2e-encryption")]
+    keys_upload_request_config: Option<RequestConfig>,
     base_client: Option<BaseClient>,
 }
 
@@ -96,6 +113,21 @@ impl ClientBuilder {
             appservice_mode: false,
             server_versions: None,
             handle_refresh_tokens: false,
+            index_encrypted_messages: false,
+            follow_room_upgrades: false,
+            max_local_history_age: None,
+            cache_room_events: false,
+            #[cfg(feature = "markdown")]
+            message_formatter: Some(Arc::new(crate::formatting::MarkdownFormatter::new())),
+            #[cfg(not(feature = "markdown"))]
+            message_formatter: None,
+            read_only: false,
+            #[cfg(feature = "e2e-encryption")]
+            room_key_trust_requirement: TrustRequirement::default(),
+            #[cfg(feature = "e2e-encryption")]
+            decryption_settings: DecryptionSettings::default(),
+            #[cfg(feature = "e2e-encryption")]
+            keys_upload_request_config: None,
             base_client: None,
         }
     }
@@ -112,10 +144,9 @@ impl ClientBuilder {
 
     /// Set the sliding-sync proxy URL to use.
     ///
-    /// This is used only if the homeserver URL was defined with
-    /// [`Self::homeserver_url`]. If the homeserver address was defined with
-    /// [`Self::server_name`], then auto-discovery via the `.well-known`
-    /// endpoint will be performed.
+    /// If the homeserver address was defined with [`Self::server_name`], this
+    /// takes precedence over the proxy advertised in the `.well-known` file
+    /// found by auto-discovery.
     #[cfg(feature = "experimental-sliding-sync")]
     pub fn sliding_sync_proxy(mut self, url: impl AsRef<str>) -> Self {
         self.sliding_sync_proxy = Some(url.as_ref().to_owned());
@@ -337,6 +368,147 @@ impl ClientBuilder {
         self
     }
 
+    /// Add the messages of encrypted rooms to a local search index as they are
+    /// received via sync and decrypted.
+    ///
+    /// The homeserver can't search the messages of encrypted rooms, so this is
+    /// necessary for [`Room::search_messages()`] to find messages in encrypted
+    /// rooms.
+    ///
+    /// Only the messages received via sync are indexed: the messages loaded by
+    /// paginating backwards, for example with [`Room::messages()`], are not,
+    /// so messages sent before this was enabled can't be found.
+    ///
+    /// Note that the index contains the plain text of the messages, and is
+    /// stored in the state store. It is only encrypted if the state store is.
+    ///
+    /// [`Room::search_messages()`]: crate::Room::search_messages
+    /// [`Room::messages()`]: crate::Room::messages
+    pub fn index_encrypted_messages(mut self) -> Self {
+        self.index_encrypted_messages = true;
+        self
+    }
+
+    /// Join the replacement room automatically when a joined room is upgraded.
+    ///
+    /// When a `m.room.tombstone` event is received via sync, the replacement
+    /// room is joined with [`Room::follow_upgrade()`], which also copies the
+    /// tags and notification settings of the old room.
+    ///
+    /// Upgrades are reported with [`Client::subscribe_to_room_upgrades()`]
+    /// whether this is enabled or not.
+    ///
+    /// [`Room::follow_upgrade()`]: crate::Room::follow_upgrade
+    pub fn follow_room_upgrades(mut self) -> Self {
+        self.follow_room_upgrades = true;
+        self
+    }
+
+    /// Set how long the events of every room should be kept locally at most.
+    ///
+    /// This applies on top of the retention policies of the rooms, the
+    /// shortest of both limits is used, see [`Room::max_history_age()`].
+    ///
+    /// [`Room::max_history_age()`]: crate::Room::max_history_age
+    pub fn max_local_history_age(mut self, max_age: Duration) -> Self {
+        self.max_local_history_age = Some(max_age);
+        self
+    }
+
+    /// Keep the timeline events of the rooms received via sync in the state
+    /// store, with the [event cache].
+    ///
+    /// This allows to show the latest events of a room right away after a
+    /// restart, and to paginate backwards without making requests to the
+    /// homeserver for the events that are already cached.
+    ///
+    /// Note that the events are stored like they were received, so the
+    /// decrypted events are only encrypted at rest if the state store is.
+    ///
+    /// [event cache]: crate::event_cache
+    pub fn cache_room_events(mut self) -> Self {
+        self.cache_room_events = true;
+        self
+    }
+
+    /// Set the converter of the plain text body of outgoing messages to HTML,
+    /// used by [`Client::text_markdown()`] and similar methods.
+    ///
+    /// With the `markdown` feature, defaults to a [`MarkdownFormatter`] with
+    /// the default options. Otherwise, messages are sent as plain text unless
+    /// a formatter is set.
+    ///
+    /// [`MarkdownFormatter`]: crate::formatting::MarkdownFormatter
+    pub fn message_formatter(mut self, formatter: impl MessageFormatter + 'static) -> Self {
+        self.message_formatter = Some(Arc::new(formatter));
+        self
+    }
+
+    /// Put the client in read-only mode.
+    ///
+    /// In this mode, the requests that would change anything visible to other
+    /// users, like sending events, read receipts or typing notices, joining
+    /// rooms or changing the profile, fail with [`HttpError::ReadOnly`] before
+    /// reaching the homeserver. Logging in, syncing and uploading or querying
+    /// device keys are still allowed, but claiming one-time keys and sending
+    /// to-device messages aren't, since they affect the devices of other
+    /// users.
+    ///
+    /// This is useful for archive viewers or audit tools, that must not leave
+    /// any trace in the rooms they browse.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Set the trust that the devices of the room members need to receive the
+    /// room keys we share.
+    ///
+    /// When sending a message in an encrypted room, if some devices of the
+    /// members don't satisfy this requirement, the room key isn't shared and
+    /// sending fails with [`OlmError::UntrustedDevices`], which lists the
+    /// offending devices. They can then be verified, or blacklisted to be
+    /// excluded.
+    ///
+    /// Defaults to [`TrustRequirement::Untrusted`], which shares room keys
+    /// with all the devices that are not blacklisted.
+    ///
+    /// [`OlmError::UntrustedDevices`]: matrix_sdk_base::crypto::OlmError::UntrustedDevices
+    #[cfg(feature = "e2e-encryption")]
+    pub fn room_key_trust_requirement(mut self, requirement: TrustRequirement) -> Self {
+        self.room_key_trust_requirement = requirement;
+        self
+    }
+
+    /// Set the settings to use to decrypt room events.
+    ///
+    /// With a [`DecryptionSettings::sender_device_trust_requirement`] other
+    /// than [`TrustRequirement::Untrusted`], the events sent by devices that
+    /// don't satisfy the requirement are not decrypted, and stay encrypted in
+    /// the timeline.
+    ///
+    /// The events that are decrypted carry the verification state of their
+    /// sender device in their encryption info, which can be used to warn about
+    /// messages sent from unverified devices.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn decryption_settings(mut self, settings: DecryptionSettings) -> Self {
+        self.decryption_settings = settings;
+        self
+    }
+
+    /// Set the config to use for the requests uploading the encryption keys of
+    /// this device.
+    ///
+    /// These requests are critical for other users to be able to send
+    /// encrypted messages to this device, so it can make sense to retry them
+    /// more aggressively than other requests. Defaults to the config set with
+    /// [`ClientBuilder::request_config()`].
+    #[cfg(feature = "e2e-encryption")]
+    pub fn keys_upload_request_config(mut self, request_config: RequestConfig) -> Self {
+        self.keys_upload_request_config = Some(request_config);
+        self
+    }
+
     /// Public for test only
     #[doc(hidden)]
     pub fn base_client(mut self, base_client: BaseClient) -> Self {
@@ -391,59 +563,49 @@ impl ClientBuilder {
             BaseClient::with_store_config(store_config)
         };
 
-        let http_client = HttpClient::new(inner_http_client.clone(), self.request_config);
+        #[cfg(feature = "e2e-encryption")]
+        let base_client = base_client
+            .with_room_key_trust_requirement(self.room_key_trust_requirement)
+            .with_decryption_settings(self.decryption_settings);
+
+        let mut http_client = HttpClient::new(inner_http_client.clone(), self.request_config);
+        http_client.read_only = self.read_only;
 
-        let mut authentication_server_info = None;
+        let mut well_known = None;
 
         #[cfg(feature = "experimental-sliding-sync")]
-        let mut sliding_sync_proxy: Option<Url> = None;
+        let mut sliding_sync_proxy =
+            self.sliding_sync_proxy.as_ref().map(|url| Url::parse(url)).transpose()?;
 
         let homeserver = match homeserver_cfg {
-            HomeserverConfig::Url(url) => {
-                #[cfg(feature = "experimental-sliding-sync")]
-                {
-                    sliding_sync_proxy =
-                        self.sliding_sync_proxy.as_ref().map(|url| Url::parse(url)).transpose()?;
-                }
-                url
-            }
+            HomeserverConfig::Url(url) => url,
             HomeserverConfig::ServerName { server: server_name, protocol } => {
                 debug!("Trying to discover the homeserver");
 
-                let homeserver = match protocol {
+                let discovery_url = match protocol {
                     UrlScheme::Http => format!("http://{server_name}"),
                     UrlScheme::Https => format!("https://{server_name}"),
                 };
 
-                let well_known = http_client
-                    .send(
-                        discover_homeserver::Request::new(),
-                        Some(RequestConfig::short_retry()),
-                        homeserver,
-                        None,
-                        None,
-                        &[MatrixVersion::V1_0],
-                        Default::default(),
-                    )
+                let response = ClientWellKnown::fetch(&http_client, discovery_url.clone())
                     .await
                     .map_err(|e| match e {
                         HttpError::Api(err) => ClientBuildError::AutoDiscovery(err),
                         err => ClientBuildError::Http(err),
                     })?;
+                let discovered = ClientWellKnown::new(discovery_url, response)?;
 
-                authentication_server_info = well_known.authentication;
-
+                // A sliding sync proxy set explicitly takes precedence over the discovered one.
                 #[cfg(feature = "experimental-sliding-sync")]
-                if let Some(proxy) = well_known.sliding_sync_proxy.map(|p| p.url) {
-                    sliding_sync_proxy = Url::parse(&proxy).ok();
+                if sliding_sync_proxy.is_none() {
+                    sliding_sync_proxy = discovered.sliding_sync_proxy.clone();
                 }
 
-                debug!(
-                    homeserver_url = well_known.homeserver.base_url,
-                    "Discovered the homeserver"
-                );
+                debug!(homeserver_url = %discovered.homeserver, "Discovered the homeserver");
 
-                well_known.homeserver.base_url
+                let homeserver = discovered.homeserver.to_string();
+                well_known = Some(discovered);
+                homeserver
             }
         };
 
@@ -451,7 +613,7 @@ impl ClientBuilder {
 
         let inner = Arc::new(ClientInner::new(
             homeserver,
-            authentication_server_info,
+            well_known,
             #[cfg(feature = "experimental-sliding-sync")]
             sliding_sync_proxy,
             http_client,
@@ -460,6 +622,13 @@ impl ClientBuilder {
             self.appservice_mode,
             self.respect_login_well_known,
             self.handle_refresh_tokens,
+            self.index_encrypted_messages,
+            self.follow_room_upgrades,
+            self.max_local_history_age,
+            self.cache_room_events,
+            self.message_formatter,
+            #[cfg(feature = "e2e-encryption")]
+            self.keys_upload_request_config,
         ));
 
         debug!("Done building the Client");
@@ -0,0 +1,177 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use matrix_sdk_common::instant::Instant;
+use ruma::api::{
+    client::{
+        discovery::{
+            get_capabilities::{self, Capabilities},
+            get_supported_versions,
+        },
+        media::get_media_config,
+    },
+    MatrixVersion,
+};
+use tracing::{debug, warn};
+
+use super::Client;
+use crate::HttpResult;
+
+/// How long the capabilities of the homeserver are cached before they are
+/// fetched again.
+const SERVER_CAPABILITIES_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The versions, features and capabilities supported by the homeserver.
+///
+/// Get it with [`Client::server_capabilities()`].
+#[derive(Clone, Debug)]
+pub struct ServerCapabilities {
+    versions: Vec<MatrixVersion>,
+    unstable_features: BTreeMap<String, bool>,
+    capabilities: Capabilities,
+    max_upload_size: Option<u64>,
+    fetched_at: Instant,
+}
+
+impl ServerCapabilities {
+    /// The versions of the Matrix specification supported by the homeserver,
+    /// among the ones known by this SDK.
+    pub fn versions(&self) -> &[MatrixVersion] {
+        &self.versions
+    }
+
+    /// Whether the homeserver supports the given version of the Matrix
+    /// specification.
+    pub fn supports_version(&self, version: MatrixVersion) -> bool {
+        self.versions.contains(&version)
+    }
+
+    /// Whether the homeserver advertises the given unstable feature as
+    /// enabled, like `org.matrix.msc3440.stable`.
+    pub fn supports_unstable_feature(&self, feature: &str) -> bool {
+        self.unstable_features.get(feature).copied().unwrap_or(false)
+    }
+
+    /// Whether the homeserver advertises an enabled unstable feature for the
+    /// Matrix Spec Change with the given number.
+    ///
+    /// For example, `supports_msc(3440)` is `true` if the homeserver enables
+    /// `org.matrix.msc3440` or `org.matrix.msc3440.stable`.
+    pub fn supports_msc(&self, msc: u32) -> bool {
+        let msc = format!("msc{msc}");
+        self.unstable_features
+            .iter()
+            .any(|(feature, enabled)| *enabled && feature.split('.').any(|part| part == msc))
+    }
+
+    /// The capabilities of the homeserver, as returned by the `/capabilities`
+    /// endpoint.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Whether the user can change their password.
+    pub fn can_change_password(&self) -> bool {
+        self.capabilities.change_password.enabled
+    }
+
+    /// The maximum size of a file that can be uploaded to the media
+    /// repository, in bytes, if the homeserver advertises it.
+    pub fn max_upload_size(&self) -> Option<u64> {
+        self.max_upload_size
+    }
+
+    /// Whether these capabilities were fetched long enough ago that they should
+    /// be fetched again.
+    fn is_expired(&self) -> bool {
+        self.fetched_at.elapsed() > SERVER_CAPABILITIES_TTL
+    }
+}
+
+impl Client {
+    /// Get the versions, features and capabilities supported by the
+    /// homeserver.
+    ///
+    /// They are fetched from the `/versions`, `/capabilities` and media
+    /// `/config` endpoints the first time this is called, and are then cached
+    /// for an hour. Use [`Client::refresh_server_capabilities()`] to fetch
+    /// them again sooner.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let capabilities = client.server_capabilities().await?;
+    ///
+    /// if capabilities.can_change_password() {
+    ///     // Show the option to change the password.
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn server_capabilities(&self) -> HttpResult<ServerCapabilities> {
+        let mut cached = self.inner.server_capabilities.lock().await;
+
+        if let Some(capabilities) = cached.as_ref().filter(|c| !c.is_expired()) {
+            return Ok(capabilities.clone());
+        }
+
+        let capabilities = self.fetch_server_capabilities().await?;
+        *cached = Some(capabilities.clone());
+
+        Ok(capabilities)
+    }
+
+    /// Fetch the versions, features and capabilities supported by the
+    /// homeserver again, and update the cache used by
+    /// [`Client::server_capabilities()`].
+    pub async fn refresh_server_capabilities(&self) -> HttpResult<ServerCapabilities> {
+        let mut cached = self.inner.server_capabilities.lock().await;
+
+        let capabilities = self.fetch_server_capabilities().await?;
+        *cached = Some(capabilities.clone());
+
+        Ok(capabilities)
+    }
+
+    async fn fetch_server_capabilities(&self) -> HttpResult<ServerCapabilities> {
+        debug!("Fetching the capabilities of the homeserver");
+
+        let versions = self.send(get_supported_versions::Request::new(), None).await?;
+        let capabilities = self.send(get_capabilities::v3::Request::new(), None).await?;
+
+        // Not all homeservers have a media repository, so don't fail if the
+        // configuration can't be fetched.
+        let max_upload_size = match self.send(get_media_config::v3::Request::new(), None).await {
+            Ok(config) => Some(config.upload_size.into()),
+            Err(error) => {
+                warn!("Failed to fetch the media configuration of the homeserver: {error}");
+                None
+            }
+        };
+
+        Ok(ServerCapabilities {
+            versions: versions.known_versions().collect(),
+            unstable_features: versions.unstable_features,
+            capabilities: capabilities.capabilities,
+            max_upload_size,
+            fetched_at: Instant::now(),
+        })
+    }
+}
@@ -16,19 +16,19 @@
 
 #[cfg(feature = "experimental-oidc")]
 use std::ops::Deref;
-#[cfg(feature = "experimental-sliding-sync")]
-use std::sync::RwLock as StdRwLock;
 use std::{
-    collections::{btree_map, BTreeMap},
+    collections::{btree_map, BTreeMap, BTreeSet},
     fmt::{self, Debug},
     future::Future,
     pin::Pin,
-    sync::{Arc, Mutex as StdMutex},
+    sync::{atomic::AtomicBool, Arc, Mutex as StdMutex, RwLock as StdRwLock, Weak},
+    time::Duration,
 };
 
 use dashmap::DashMap;
 use eyeball::{Observable, SharedObservable, Subscriber};
 use futures_core::Stream;
+use futures_util::{stream, StreamExt};
 #[cfg(feature = "experimental-oidc")]
 use mas_oidc_client::{
     error::{
@@ -38,14 +38,13 @@ use mas_oidc_client::{
     types::errors::ClientErrorCode,
 };
 #[cfg(feature = "e2e-encryption")]
-use matrix_sdk_base::crypto::store::locks::CryptoStoreLock;
+use matrix_sdk_base::crypto::store::locks::{CryptoStoreLock, CryptoStoreLockGuard};
 use matrix_sdk_base::{
-    store::DynStateStore, BaseClient, RoomState, RoomStateFilter, SendOutsideWasm, SessionMeta,
-    SyncOutsideWasm,
+    store::DynStateStore,
+    sync::{UnreadCounts, UnreadNotificationsCount},
+    BaseClient, RoomState, RoomStateFilter, SendOutsideWasm, SessionMeta, SyncOutsideWasm,
 };
-use matrix_sdk_common::instant::Instant;
-#[cfg(feature = "experimental-sliding-sync")]
-use ruma::api::client::error::ErrorKind;
+use matrix_sdk_common::{executor::spawn, instant::Instant, sleep::sleep};
 #[cfg(feature = "appservice")]
 use ruma::TransactionId;
 use ruma::{
@@ -53,19 +52,25 @@ use ruma::{
         client::{
             account::whoami,
             alias::get_alias,
-            device::{delete_devices, get_devices, update_device},
+            device::{delete_devices, get_devices, update_device, Device},
             directory::{get_public_rooms, get_public_rooms_filtered},
             discovery::{
                 discover_homeserver::AuthenticationServerInfo,
                 get_capabilities::{self, Capabilities},
                 get_supported_versions,
             },
+            error::ErrorKind,
             filter::{create_filter::v3::Request as FilterUploadRequest, FilterDefinition},
+            knock::knock_room,
             membership::{join_room_by_id, join_room_by_id_or_alias},
             profile::get_profile,
-            push::{get_notifications::v3::Notification, set_pusher, Pusher},
+            push::{
+                get_notifications::v3::Notification, get_pushers, set_pusher, Pusher, PusherIds,
+            },
+            read_marker::set_read_marker,
             room::create_room,
             session::login::v3::DiscoveryInfo,
+            state::get_state_events_for_key,
             sync::sync_events,
             uiaa,
             user_directory::search_users,
@@ -74,42 +79,75 @@ use ruma::{
         MatrixVersion, OutgoingRequest,
     },
     assign,
+    events::{
+        direct::DirectEventContent,
+        ignored_user_list::IgnoredUserListEventContent,
+        receipt::{ReceiptThread, ReceiptType},
+        room::{
+            encryption::RoomEncryptionEventContent,
+            member::{MembershipState, RoomMemberEventContent},
+        },
+        AnySyncTimelineEvent, GlobalAccountDataEventType, InitialStateEvent, StateEventType,
+    },
     push::Ruleset,
-    DeviceId, OwnedDeviceId, OwnedRoomId, OwnedServerName, RoomAliasId, RoomId, RoomOrAliasId,
-    ServerName, UInt, UserId,
+    serde::Raw,
+    DeviceId, OwnedDeviceId, OwnedEventId, OwnedRoomId, OwnedServerName, OwnedUserId, RoomAliasId,
+    RoomId, RoomOrAliasId, ServerName, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
 use tokio::sync::{broadcast, Mutex, OnceCell, RwLock, RwLockReadGuard};
-use tracing::{debug, error, info, instrument, trace, Instrument, Span};
+use tracing::{debug, error, info, instrument, trace, warn, Instrument, Span};
 use url::Url;
 
+#[cfg(feature = "backups")]
+use crate::encryption::backups::BackupClientState;
 #[cfg(feature = "e2e-encryption")]
 use crate::encryption::Encryption;
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::{Oidc, OidcError};
 use crate::{
-    authentication::AuthData,
+    authentication::{AuthData, ReloadSessionCallback, SaveSessionCallback, SessionCallbacks},
     config::RequestConfig,
     error::{HttpError, HttpResult},
+    event_cache::EventCache,
     event_handler::{
-        EventHandler, EventHandlerDropGuard, EventHandlerHandle, EventHandlerStore, SyncEvent,
+        EventHandler, EventHandlerDropGuard, EventHandlerHandle, EventHandlerStore, HandlerKind,
+        SyncEvent,
     },
+    formatting::MessageFormatter,
     http_client::HttpClient,
+    identity_server::IdentityServerSession,
     matrix_auth::MatrixAuth,
     notification_settings::NotificationSettings,
-    sync::{RoomUpdate, SyncResponse},
-    Account, AuthApi, AuthSession, Error, Media, RefreshTokenError, Result, Room,
-    TransmissionProgress,
+    room::{retention::HistoryCutoffs, send_queue::SharedSendQueue, PolicyListSubscription},
+    sync::{RoomUpdate, RoomUpgrade, SyncResponse},
+    uiaa::{UiaaFlow, UiaaOutcome},
+    Account, AuthApi, AuthSession, Error, Media, NetworkState, RefreshTokenError, RequestInfo,
+    Result, Room, TransmissionProgress,
 };
 
+mod app_state;
 mod builder;
+mod capabilities;
 mod futures;
+mod well_known;
 
 pub use self::{
+    app_state::AppState,
     builder::{ClientBuildError, ClientBuilder},
+    capabilities::ServerCapabilities,
     futures::SendRequest,
+    well_known::ClientWellKnown,
 };
 
+/// The delay before checking whether the homeserver can be reached again, after
+/// it couldn't be reached.
+const CONNECTIVITY_PROBE_INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// The maximum delay between the checks of whether the homeserver can be
+/// reached again.
+const CONNECTIVITY_PROBE_MAX_DELAY: Duration = Duration::from_secs(60);
+
 #[cfg(not(target_arch = "wasm32"))]
 type NotificationHandlerFut = Pin<Box<dyn Future<Output = ()> + Send>>;
 #[cfg(target_arch = "wasm32")]
@@ -160,6 +198,8 @@ pub(crate) struct ClientInner {
     homeserver: RwLock<Url>,
     /// The authentication server info discovered from the homeserver.
     pub(crate) authentication_server_info: Option<AuthenticationServerInfo>,
+    /// The content of the well-known file of the server, if it was fetched.
+    well_known: StdRwLock<Option<ClientWellKnown>>,
     /// The sliding sync proxy that is trusted by the homeserver.
     #[cfg(feature = "experimental-sliding-sync")]
     sliding_sync_proxy: StdRwLock<Option<Url>>,
@@ -169,6 +209,8 @@ pub(crate) struct ClientInner {
     base_client: BaseClient,
     /// The Matrix versions the server supports (well-known ones only)
     server_versions: OnceCell<Box<[MatrixVersion]>>,
+    /// The cached capabilities of the server. See `server_capabilities`.
+    server_capabilities: Mutex<Option<ServerCapabilities>>,
     /// Locks making sure we only have one group session sharing request in
     /// flight per room.
     #[cfg(feature = "e2e-encryption")]
@@ -176,6 +218,9 @@ pub(crate) struct ClientInner {
     /// Lock making sure we're only doing one key claim request at a time.
     #[cfg(feature = "e2e-encryption")]
     pub(crate) key_claim_lock: Mutex<()>,
+    /// Lock making sure we're only looking for or creating one DM at a time in
+    /// this process. See `find_or_create_dm`.
+    dm_creation_lock: Mutex<()>,
     pub(crate) members_request_locks: Mutex<BTreeMap<OwnedRoomId, Arc<Mutex<()>>>>,
     /// Locks for requests on the encryption state of rooms.
     pub(crate) encryption_state_request_locks: Mutex<BTreeMap<OwnedRoomId, Arc<Mutex<()>>>>,
@@ -186,6 +231,29 @@ pub(crate) struct ClientInner {
     notification_handlers: RwLock<Vec<NotificationHandlerFn>>,
     pub(crate) room_update_channels: StdMutex<BTreeMap<OwnedRoomId, broadcast::Sender<RoomUpdate>>>,
     pub(crate) sync_gap_broadcast_txs: StdMutex<BTreeMap<OwnedRoomId, Observable<()>>>,
+    /// The state of the connectivity to the homeserver, updated after every
+    /// request.
+    network_state: SharedObservable<NetworkState>,
+    /// The devices of the current user, if they were fetched with
+    /// [`Client::devices()`].
+    own_devices: SharedObservable<Option<Vec<Device>>>,
+    /// Whether the app using the client is visible to the user.
+    pub(crate) app_state: SharedObservable<AppState>,
+    /// The receipts kept while the app is in the background.
+    pub(crate) pending_receipts: StdMutex<app_state::PendingReceipts>,
+    /// Whether the task sending the receipts kept while the app is in the
+    /// background was spawned.
+    pub(crate) background_receipts_task_started: AtomicBool,
+    /// The session with the identity server, if one was set with
+    /// [`Client::set_identity_server()`].
+    ///
+    /// It is `None` until it was loaded from the store.
+    pub(crate) identity_server: Mutex<Option<Option<Arc<IdentityServerSession>>>>,
+    /// The send queues of the rooms that were loaded from the store.
+    pub(crate) send_queues: Mutex<BTreeMap<OwnedRoomId, SharedSendQueue>>,
+    /// The OpenID tokens that were requested for widgets, by widget ID.
+    #[cfg(feature = "experimental-widgets")]
+    pub(crate) widget_openid_tokens: crate::widget::OpenIdTokenCache,
     /// Whether the client should operate in application service style mode.
     /// This is low-level functionality. For an high-level API check the
     /// `matrix_sdk_appservice` crate.
@@ -196,6 +264,25 @@ pub(crate) struct ClientInner {
     /// Whether to try to refresh the access token automatically when an
     /// `M_UNKNOWN_TOKEN` error is encountered.
     handle_refresh_tokens: bool,
+    /// Whether to add the decrypted messages received via sync to the local
+    /// search index of their room.
+    pub(crate) index_encrypted_messages: bool,
+    /// Whether to join the replacement room automatically when a room is
+    /// upgraded.
+    pub(crate) follow_room_upgrades: bool,
+    /// How long the events of every room should be kept locally at most.
+    pub(crate) max_local_history_age: Option<Duration>,
+    /// The history cutoffs of the rooms, updated after every sync.
+    pub(crate) history_cutoffs: HistoryCutoffs,
+    /// The cache of the timeline events of the rooms, if enabled.
+    pub(crate) event_cache: Option<EventCache>,
+    /// The converter of the body of outgoing messages to HTML, if any.
+    pub(crate) message_formatter: Option<Arc<dyn MessageFormatter>>,
+    /// The commands of the bot. See `add_command`.
+    #[cfg(feature = "bot-commands")]
+    pub(crate) commands: crate::commands::CommandRegistry,
+    /// Room upgrades publisher. See `subscribe_to_room_upgrades`.
+    pub(crate) room_upgrade_sender: broadcast::Sender<RoomUpgrade>,
     /// Lock making sure we're only doing one token refresh at a time.
     pub(crate) refresh_token_lock: Mutex<Result<(), RefreshTokenError>>,
     /// An event that can be listened on to wait for a successful sync. The
@@ -210,6 +297,8 @@ pub(crate) struct ClientInner {
     pub(crate) session_change_sender: broadcast::Sender<SessionChange>,
     /// Authentication data to keep in memory.
     pub(crate) auth_data: OnceCell<AuthData>,
+    /// Callbacks to reload and save the session, shared with other processes.
+    pub(crate) session_callbacks: OnceCell<SessionCallbacks>,
 
     #[cfg(feature = "e2e-encryption")]
     pub(crate) cross_process_crypto_store_lock: OnceCell<CryptoStoreLock>,
@@ -233,13 +322,22 @@ pub(crate) struct ClientInner {
     /// outside the `OlmMachine`.
     #[cfg(feature = "e2e-encryption")]
     pub(crate) crypto_store_generation: Arc<Mutex<Option<u64>>>,
+
+    /// The state of the room key backup.
+    #[cfg(feature = "backups")]
+    pub(crate) backup_state: BackupClientState,
+
+    /// The config to use for the requests uploading the encryption keys, if
+    /// it differs from the default one.
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) keys_upload_request_config: Option<RequestConfig>,
 }
 
 impl ClientInner {
     #[allow(clippy::too_many_arguments)]
     fn new(
         homeserver: Url,
-        authentication_server_info: Option<AuthenticationServerInfo>,
+        well_known: Option<ClientWellKnown>,
         #[cfg(feature = "experimental-sliding-sync")] sliding_sync_proxy: Option<Url>,
         http_client: HttpClient,
         base_client: BaseClient,
@@ -247,21 +345,33 @@ impl ClientInner {
         appservice_mode: bool,
         respect_login_well_known: bool,
         handle_refresh_tokens: bool,
+        index_encrypted_messages: bool,
+        follow_room_upgrades: bool,
+        max_local_history_age: Option<Duration>,
+        cache_room_events: bool,
+        message_formatter: Option<Arc<dyn MessageFormatter>>,
+        #[cfg(feature = "e2e-encryption")] keys_upload_request_config: Option<RequestConfig>,
     ) -> Self {
         let session_change_sender = broadcast::Sender::new(1);
+        let room_upgrade_sender = broadcast::Sender::new(16);
 
         Self {
             homeserver: RwLock::new(homeserver),
-            authentication_server_info,
+            authentication_server_info: well_known
+                .as_ref()
+                .and_then(|well_known| well_known.authentication.clone()),
+            well_known: StdRwLock::new(well_known),
             #[cfg(feature = "experimental-sliding-sync")]
             sliding_sync_proxy: StdRwLock::new(sliding_sync_proxy),
             http_client,
             base_client,
             server_versions: OnceCell::new_with(server_versions),
+            server_capabilities: Default::default(),
             #[cfg(feature = "e2e-encryption")]
             group_session_locks: Default::default(),
             #[cfg(feature = "e2e-encryption")]
             key_claim_lock: Default::default(),
+            dm_creation_lock: Default::default(),
             members_request_locks: Default::default(),
             encryption_state_request_locks: Default::default(),
             typing_notice_times: Default::default(),
@@ -269,17 +379,40 @@ impl ClientInner {
             notification_handlers: Default::default(),
             room_update_channels: Default::default(),
             sync_gap_broadcast_txs: Default::default(),
+            network_state: Default::default(),
+            own_devices: Default::default(),
+            app_state: Default::default(),
+            pending_receipts: Default::default(),
+            background_receipts_task_started: Default::default(),
+            identity_server: Default::default(),
+            send_queues: Default::default(),
+            #[cfg(feature = "experimental-widgets")]
+            widget_openid_tokens: Default::default(),
             appservice_mode,
             respect_login_well_known,
             sync_beat: event_listener::Event::new(),
             handle_refresh_tokens,
+            index_encrypted_messages,
+            follow_room_upgrades,
+            max_local_history_age,
+            history_cutoffs: HistoryCutoffs::default(),
+            event_cache: cache_room_events.then(EventCache::default),
+            message_formatter,
+            #[cfg(feature = "bot-commands")]
+            commands: Default::default(),
+            room_upgrade_sender,
             refresh_token_lock: Mutex::new(Ok(())),
             session_change_sender,
             auth_data: Default::default(),
+            session_callbacks: OnceCell::new(),
             #[cfg(feature = "e2e-encryption")]
             cross_process_crypto_store_lock: OnceCell::new(),
             #[cfg(feature = "e2e-encryption")]
             crypto_store_generation: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "backups")]
+            backup_state: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            keys_upload_request_config,
         }
     }
 }
@@ -311,6 +444,47 @@ impl Client {
         self.inner.base_client.subscribe_to_ignore_user_list_changes()
     }
 
+    /// Add the given user ID to the account's ignore list.
+    ///
+    /// The homeserver won't send the events of this user to the client
+    /// anymore.
+    pub async fn ignore_user(&self, user_id: &UserId) -> Result<()> {
+        self.account().ignore_user(user_id).await
+    }
+
+    /// Remove the given user ID from the account's ignore list.
+    pub async fn unignore_user(&self, user_id: &UserId) -> Result<()> {
+        self.account().unignore_user(user_id).await
+    }
+
+    /// Get the IDs of the users that are currently ignored, according to the
+    /// `m.ignored_user_list` account data in the store.
+    pub async fn ignored_users(&self) -> Result<BTreeSet<OwnedUserId>> {
+        let Some(raw) = self.account().account_data::<IgnoredUserListEventContent>().await? else {
+            return Ok(BTreeSet::new());
+        };
+
+        Ok(raw.deserialize()?.ignored_users.into_keys().collect())
+    }
+
+    /// Get a stream of the IDs of the ignored users.
+    ///
+    /// A new set is yielded every time the `m.ignored_user_list` account data
+    /// changes. Use [`Client::ignored_users()`] to get the current set.
+    pub fn subscribe_to_ignored_users(&self) -> impl Stream<Item = BTreeSet<OwnedUserId>> {
+        let client = self.clone();
+        let mut changes = self.subscribe_to_ignore_user_list_changes();
+
+        async_stream::stream! {
+            while changes.next().await.is_some() {
+                match client.ignored_users().await {
+                    Ok(ignored_users) => yield ignored_users,
+                    Err(error) => error!("Failed to load the ignored users: {error}"),
+                }
+            }
+        }
+    }
+
     /// Create a new [`ClientBuilder`].
     pub fn builder() -> ClientBuilder {
         ClientBuilder::new()
@@ -333,7 +507,8 @@ impl Client {
     /// Get the capabilities of the homeserver.
     ///
     /// This method should be used to check what features are supported by the
-    /// homeserver.
+    /// homeserver. [`Client::server_capabilities()`] caches the capabilities
+    /// along with the supported versions and features.
     ///
     /// # Examples
     ///
@@ -413,6 +588,13 @@ impl Client {
         self.inner.http_client.request_config
     }
 
+    /// Whether this client is in read-only mode.
+    ///
+    /// See [`ClientBuilder::read_only()`] for more details.
+    pub fn is_read_only(&self) -> bool {
+        self.inner.http_client.read_only
+    }
+
     /// Is the client logged in.
     pub fn logged_in(&self) -> bool {
         self.inner.base_client.logged_in()
@@ -550,6 +732,83 @@ impl Client {
         Media::new(self.clone())
     }
 
+    /// Remove the media files' content that don't respect the media retention
+    /// policy of the state store from the media cache.
+    ///
+    /// The retention policy is usually also applied when media files are added
+    /// to the cache, this can be used to apply it at other times, e.g. on
+    /// startup. State stores without a media retention policy don't remove
+    /// anything.
+    pub async fn clean_up_media_cache(&self) -> Result<()> {
+        Ok(self.store().clean_up_media_cache().await?)
+    }
+
+    /// Change the passphrase used to encrypt the stores of the client.
+    ///
+    /// The state store and, if the client is logged in, the crypto store are
+    /// encrypted again with the new passphrase. It must be used to open them
+    /// from now on.
+    ///
+    /// # Arguments
+    ///
+    /// * `old_passphrase` - The passphrase currently used to open the stores.
+    ///
+    /// * `new_passphrase` - The passphrase to use from now on.
+    pub async fn rotate_store_cipher(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<()> {
+        #[cfg(feature = "e2e-encryption")]
+        let olm_machine = self.olm_machine().await;
+
+        // Encrypt the keys of all the stores with the new passphrase before
+        // saving any of them, so a wrong passphrase or an unencrypted store
+        // doesn't leave the stores with different passphrases.
+        #[cfg(feature = "e2e-encryption")]
+        let crypto_store_export = match olm_machine.as_ref() {
+            Some(olm_machine) => {
+                olm_machine.store().export_store_cipher(old_passphrase, new_passphrase).await?
+            }
+            None => None,
+        };
+        let state_store_export =
+            self.store().export_store_cipher(old_passphrase, new_passphrase).await?;
+
+        #[cfg(feature = "e2e-encryption")]
+        if let (Some(olm_machine), Some(export)) = (olm_machine.as_ref(), crypto_store_export) {
+            olm_machine.store().save_store_cipher(export).await?;
+        }
+
+        if let Some(export) = state_store_export {
+            if let Err(error) = self.store().save_store_cipher(export).await {
+                // Put back the old passphrase of the crypto store.
+                #[cfg(feature = "e2e-encryption")]
+                if let Some(olm_machine) = olm_machine.as_ref() {
+                    let store = olm_machine.store();
+                    let rollback = async {
+                        if let Some(export) =
+                            store.export_store_cipher(new_passphrase, old_passphrase).await?
+                        {
+                            store.save_store_cipher(export).await?;
+                        }
+                        Ok::<_, matrix_sdk_base::crypto::CryptoStoreError>(())
+                    };
+
+                    if let Err(rollback_error) = rollback.await {
+                        error!(
+                            "Couldn't restore the passphrase of the crypto store: {rollback_error}"
+                        );
+                    }
+                }
+
+                return Err(error.into());
+            }
+        }
+
+        Ok(())
+    }
+
     /// Access the OpenID Connect API of the client.
     #[cfg(feature = "experimental-oidc")]
     pub fn oidc(&self) -> Oidc {
@@ -714,6 +973,88 @@ impl Client {
         self.add_event_handler_impl(handler, Some(room_id.to_owned()))
     }
 
+    /// Register a handler for a specific to-device event type.
+    ///
+    /// This method works the same way as
+    /// [`add_event_handler`][Self::add_event_handler], except that it only
+    /// accepts to-device events. See that method for more details on event
+    /// handler functions.
+    ///
+    /// Some to-device events, like room keys, are related to a room. If that
+    /// room is known, the handler can get it with a `Room` or `Option<Room>`
+    /// context argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `Ev` is not a to-device event type.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::{ruma::events::room_key::ToDeviceRoomKeyEvent, Room};
+    /// # use url::Url;
+    /// # futures_executor::block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080").unwrap();
+    /// # let client = matrix_sdk::Client::new(homeserver).await.unwrap();
+    ///
+    /// client.add_to_device_event_handler(
+    ///     |ev: ToDeviceRoomKeyEvent, room: Option<Room>| async move {
+    ///         if let Some(room) = room {
+    ///             println!("Received a room key for {}", room.room_id());
+    ///         }
+    ///     },
+    /// );
+    /// # });
+    /// ```
+    pub fn add_to_device_event_handler<Ev, Ctx, H>(&self, handler: H) -> EventHandlerHandle
+    where
+        Ev: SyncEvent + DeserializeOwned + Send + 'static,
+        H: EventHandler<Ev, Ctx>,
+    {
+        assert_eq!(
+            Ev::KIND,
+            HandlerKind::ToDevice,
+            "the event handler must be for a to-device event"
+        );
+        self.add_event_handler_impl(handler, None)
+    }
+
+    /// Register a handler for timeline events with the given type, received
+    /// as raw JSON.
+    ///
+    /// This allows to handle custom or unstable event types that have no Rust
+    /// type. Otherwise, this method works the same way as
+    /// [`add_event_handler`][Self::add_event_handler], and the handler can be
+    /// removed the same way with the returned handle. See that method for more
+    /// details on event handler functions.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::{
+    ///     ruma::{events::AnySyncTimelineEvent, serde::Raw},
+    ///     Room,
+    /// };
+    /// # use url::Url;
+    /// # futures_executor::block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080").unwrap();
+    /// # let client = matrix_sdk::Client::new(homeserver).await.unwrap();
+    ///
+    /// client.add_raw_event_handler(
+    ///     "org.example.custom",
+    ///     |ev: Raw<AnySyncTimelineEvent>, room: Room| async move {
+    ///         println!("Received {} in {}", ev.json(), room.room_id());
+    ///     },
+    /// );
+    /// # });
+    /// ```
+    pub fn add_raw_event_handler<Ctx, H>(&self, event_type: &str, handler: H) -> EventHandlerHandle
+    where
+        H: EventHandler<Raw<AnySyncTimelineEvent>, Ctx>,
+    {
+        self.add_raw_event_handler_impl(event_type, handler, None)
+    }
+
     /// Remove the event handler associated with the handle.
     ///
     /// Note that you **must not** call `remove_event_handler` from the
@@ -783,6 +1124,27 @@ impl Client {
         EventHandlerDropGuard::new(handle, self.clone())
     }
 
+    /// Get a stream of the values that the event handler identified by the
+    /// given handle sends through the channel of the given receiver.
+    ///
+    /// The event handler is removed when the stream is dropped.
+    pub(crate) fn event_handler_stream<T>(
+        &self,
+        handle: EventHandlerHandle,
+        receiver: async_channel::Receiver<T>,
+    ) -> impl Stream<Item = T> {
+        let guard = self.event_handler_drop_guard(handle);
+
+        async_stream::stream! {
+            // Keep the event handler alive as long as the stream is.
+            let _guard = guard;
+
+            while let Ok(value) = receiver.recv().await {
+                yield value;
+            }
+        }
+    }
+
     /// Add an arbitrary value for use as event handler context.
     ///
     /// The value can be obtained in an event handler by adding an argument of
@@ -924,6 +1286,40 @@ impl Client {
         self.base_client().get_room(room_id).map(|room| Room::new(self.clone(), room))
     }
 
+    /// Subscribe to the rules of the given moderation policy lists.
+    ///
+    /// The returned [`PolicyListSubscription`] is initialized with the rules
+    /// in the store, and is kept up to date with the policy rule events
+    /// received in the sync until it is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_ids` - The IDs of the policy lists to subscribe to.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, ruma::{room_id, user_id}};
+    /// # async {
+    /// # let client: Client = todo!();
+    /// let subscription = client
+    ///     .subscribe_to_policy_lists(&[room_id!("!bans:example.org")])
+    ///     .await?;
+    ///
+    /// if let Some(rule) =
+    ///     subscription.match_user(user_id!("@spammer:example.org"))
+    /// {
+    ///     println!("{} is banned: {}", rule.entity, rule.reason);
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn subscribe_to_policy_lists(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<PolicyListSubscription> {
+        PolicyListSubscription::new(self, room_ids).await
+    }
+
     /// Resolve a room alias to a room id and a list of servers which know
     /// about it.
     ///
@@ -976,6 +1372,84 @@ impl Client {
         }
     }
 
+    /// Set the callbacks used to share the session with other processes.
+    ///
+    /// This is useful when several processes use the same session, like an
+    /// application and its notification service extension on iOS. Without
+    /// this, a process refreshing the access token would invalidate the tokens
+    /// of the other processes, which would then try to refresh them too and
+    /// invalidate the tokens of the first one.
+    ///
+    /// # Arguments
+    ///
+    /// * `reload_session` - Called before refreshing the access token, to load
+    ///   the session persisted by the application. If its access token differs
+    ///   from the current one, another process refreshed the tokens, so they
+    ///   are used instead of refreshing them again.
+    ///
+    /// * `save_session` - Called after the access token was refreshed, to
+    ///   persist the session so other processes can load it.
+    ///
+    /// Returns an error if the callbacks were already set.
+    pub fn set_session_callbacks(
+        &self,
+        reload_session: Box<ReloadSessionCallback>,
+        save_session: Box<SaveSessionCallback>,
+    ) -> Result<()> {
+        self.inner
+            .session_callbacks
+            .set(SessionCallbacks { reload_session, save_session })
+            .map_err(|_| Error::MultipleSessionCallbacks)
+    }
+
+    /// Reload the session with the callback set with
+    /// [`Client::set_session_callbacks()`], if any.
+    ///
+    /// Returns `true` if the access token was changed by another process, in
+    /// which case the reloaded tokens are now used by this client.
+    pub(crate) fn reload_session_tokens(&self) -> bool {
+        let Some(callbacks) = self.inner.session_callbacks.get() else {
+            return false;
+        };
+
+        let session = match (callbacks.reload_session)(self.clone()) {
+            Ok(session) => session,
+            Err(error) => {
+                error!("Token refresh: Failed to reload the session: {error}");
+                return false;
+            }
+        };
+
+        if self.user_id() != Some(&*session.meta().user_id) {
+            error!("Token refresh: The reloaded session is for another user");
+            return false;
+        }
+
+        if Some(session.access_token()) == self.access_token().as_deref() {
+            return false;
+        }
+
+        trace!("Token refresh: Using the tokens refreshed by another process.");
+
+        match session {
+            AuthSession::Matrix(session) => self.matrix_auth().set_session_tokens(session.tokens),
+            #[cfg(feature = "experimental-oidc")]
+            AuthSession::Oidc(session) => self.oidc().set_session_tokens(session.user.tokens),
+        }
+
+        true
+    }
+
+    /// Save the session with the callback set with
+    /// [`Client::set_session_callbacks()`], if any.
+    pub(crate) fn save_session(&self) {
+        if let Some(callbacks) = self.inner.session_callbacks.get() {
+            if let Err(error) = (callbacks.save_session)(self.clone()) {
+                error!("Token refresh: Failed to save the session: {error}");
+            }
+        }
+    }
+
     /// Refresh the access token using the authentication API used to log into
     /// this session.
     ///
@@ -1104,6 +1578,39 @@ impl Client {
         Ok(Room::new(self.clone(), base_room))
     }
 
+    /// Knock on a room to ask to join it.
+    ///
+    /// The room needs to have a join rule allowing knocking. Once a moderator
+    /// approves the request, the room will be in the [`RoomState::Invited`]
+    /// state and can be joined. Until then, it is in the
+    /// [`RoomState::Knocked`] state, which can be observed with
+    /// [`Room::subscribe_info()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id_or_alias` - The `RoomId` or `RoomAliasId` of the room to
+    ///   knock on.
+    ///
+    /// * `reason` - Optional reason to give to the moderators of the room.
+    ///
+    /// * `server_names` - The servers to attempt to knock on the room through.
+    ///
+    /// [`Room::subscribe_info()`]: crate::BaseRoom::subscribe_info
+    pub async fn knock(
+        &self,
+        room_id_or_alias: &RoomOrAliasId,
+        reason: Option<String>,
+        server_names: &[OwnedServerName],
+    ) -> Result<Room> {
+        let request = assign!(knock_room::v3::Request::new(room_id_or_alias.to_owned()), {
+            reason,
+            server_name: server_names.to_owned(),
+        });
+        let response = self.send(request, None).await?;
+        let base_room = self.base_client().room_knocked(&response.room_id).await?;
+        Ok(Room::new(self.clone(), base_room))
+    }
+
     /// Search the homeserver's directory of public rooms.
     ///
     /// Sends a request to "_matrix/client/r0/publicRooms", returns
@@ -1183,11 +1690,7 @@ impl Client {
     pub async fn create_room(&self, request: create_room::v3::Request) -> Result<Room> {
         let invite = request.invite.clone();
         let is_direct_room = request.is_direct;
-        let response = self.send(request, None).await?;
-
-        let base_room = self.base_client().get_or_create_room(&response.room_id, RoomState::Joined);
-
-        let joined_room = Room::new(self.clone(), base_room);
+        let joined_room = self.send_create_room(request).await?;
 
         if is_direct_room && !invite.is_empty() {
             if let Err(error) =
@@ -1201,80 +1704,355 @@ impl Client {
         Ok(joined_room)
     }
 
+    /// Send the given request to create a room, without marking it as a DM.
+    async fn send_create_room(&self, request: create_room::v3::Request) -> Result<Room> {
+        let response = self.send(request, None).await?;
+
+        let base_room = self.base_client().get_or_create_room(&response.room_id, RoomState::Joined);
+
+        Ok(Room::new(self.clone(), base_room))
+    }
+
     /// Create a DM room.
     ///
     /// Convenience shorthand for [`create_room`][Self::create_room] with the
     /// given user being invited, the room marked `is_direct` and both the
     /// creator and invitee getting the default maximum power level.
     pub async fn create_dm(&self, user_id: &UserId) -> Result<Room> {
-        self.create_room(assign!(create_room::v3::Request::new(), {
+        self.create_room(Self::create_dm_request(user_id)).await
+    }
+
+    /// The request to create a DM with the given user, shared by the methods
+    /// creating DMs.
+    fn create_dm_request(user_id: &UserId) -> create_room::v3::Request {
+        assign!(create_room::v3::Request::new(), {
             invite: vec![user_id.to_owned()],
             is_direct: true,
             preset: Some(create_room::v3::RoomPreset::TrustedPrivateChat),
-        }))
-        .await
+        })
     }
 
-    /// Search the homeserver's directory for public rooms with a filter.
+    /// Get the DM room with the given user, or create an encrypted one if
+    /// there is none.
     ///
-    /// # Arguments
+    /// An existing room is used if it is listed as a DM with the user in the
+    /// `m.direct` account data, the current user has joined it and the other
+    /// user has joined or is invited. The account data is fetched from the
+    /// homeserver, so the DMs created by other devices or that were not
+    /// received via sync yet are found too.
     ///
-    /// * `room_search` - The easiest way to create this request is using the
-    /// `get_public_rooms_filtered::Request` itself.
+    /// Concurrent calls are handled one at a time, so calling this method
+    /// several times for the same user doesn't create duplicate DMs. If the
+    /// cross-process store lock is enabled with
+    /// [`Encryption::enable_cross_process_store_lock()`], this is also the
+    /// case for calls from other processes using the same store.
+    ///
+    /// [`Encryption::enable_cross_process_store_lock()`]: crate::encryption::Encryption::enable_cross_process_store_lock
     ///
     /// # Examples
     ///
     /// ```no_run
+    /// # use matrix_sdk::{Client, ruma::user_id};
     /// # use url::Url;
-    /// # use matrix_sdk::Client;
     /// # async {
     /// # let homeserver = Url::parse("http://example.com")?;
-    /// use matrix_sdk::ruma::{
-    ///     api::client::directory::get_public_rooms_filtered, directory::Filter,
-    /// };
-    /// # let mut client = Client::new(homeserver).await?;
-    ///
-    /// let mut filter = Filter::new();
-    /// filter.generic_search_term = Some("rust".to_owned());
-    /// let mut request = get_public_rooms_filtered::v3::Request::new();
-    /// request.filter = filter;
-    ///
-    /// let response = client.public_rooms_filtered(request).await?;
-    ///
-    /// for room in response.chunk {
-    ///     println!("Found room {room:?}");
-    /// }
+    /// # let client = Client::new(homeserver).await?;
+    /// let room = client.find_or_create_dm(user_id!("@alice:example.org")).await?;
     /// # anyhow::Ok(()) };
     /// ```
-    pub async fn public_rooms_filtered(
-        &self,
-        request: get_public_rooms_filtered::v3::Request,
-    ) -> HttpResult<get_public_rooms_filtered::v3::Response> {
-        self.send(request, None).await
+    #[instrument(skip(self))]
+    pub async fn find_or_create_dm(&self, user_id: &UserId) -> Result<Room> {
+        let _guard = self.inner.dm_creation_lock.lock().await;
+        #[cfg(feature = "e2e-encryption")]
+        let _cross_process_guard = self.lock_dm_creation_across_processes().await?;
+
+        if let Some(room) = self.find_usable_dm(user_id).await? {
+            debug!(room_id = ?room.room_id(), "Found an existing DM");
+            return Ok(room);
+        }
+
+        debug!("No usable DM found, creating one");
+
+        let encryption = RoomEncryptionEventContent::with_recommended_defaults();
+        let request = assign!(Self::create_dm_request(user_id), {
+            initial_state: vec![InitialStateEvent::new(encryption).to_raw_any()],
+        });
+        let room = self.send_create_room(request).await?;
+
+        // If the room is not marked as a DM, it won't be found next time and
+        // another one would be created.
+        self.account().mark_as_dm(room.room_id(), &[user_id.to_owned()]).await?;
+
+        Ok(room)
     }
 
-    /// Send an arbitrary request to the server, without updating client state.
-    ///
-    /// **Warning:** Because this method *does not* update the client state, it
-    /// is important to make sure that you account for this yourself, and
-    /// use wrapper methods where available.  This method should *only* be
-    /// used if a wrapper method for the endpoint you'd like to use is not
-    /// available.
-    ///
-    /// # Arguments
-    ///
-    /// * `request` - A filled out and valid request for the endpoint to be hit
-    ///
-    /// * `timeout` - An optional request timeout setting, this overrides the
-    /// default request setting if one was set.
-    ///
-    /// # Examples
+    /// Take the lock on the creation of DMs shared with the other processes
+    /// using the same crypto store, if the cross-process store lock is
+    /// enabled.
+    #[cfg(feature = "e2e-encryption")]
+    async fn lock_dm_creation_across_processes(&self) -> Result<Option<CryptoStoreLockGuard>> {
+        const DM_CREATION_LOCK_KEY: &str = "dm_creation_lock";
+
+        let Some(store_lock) = self.inner.cross_process_crypto_store_lock.get() else {
+            return Ok(None);
+        };
+
+        let olm_machine = self.olm_machine().await;
+        let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+        let lock = olm_machine.store().create_store_lock(
+            DM_CREATION_LOCK_KEY.to_owned(),
+            store_lock.lock_holder().to_owned(),
+        );
+
+        Ok(Some(lock.spin_lock(None).await?))
+    }
+
+    /// Find a DM with the given user in the `m.direct` account data on the
+    /// homeserver, that the current user has joined and where the other user
+    /// has joined or is invited.
     ///
-    /// ```no_run
-    /// # use matrix_sdk::{Client, config::SyncSettings};
-    /// # use url::Url;
-    /// # async {
-    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// The most recent DMs are tried first. The memberships in the DMs that
+    /// were not received via sync yet are fetched from the homeserver.
+    async fn find_usable_dm(&self, user_id: &UserId) -> Result<Option<Room>> {
+        let own_user_id = self.user_id().ok_or(Error::AuthenticationRequired)?;
+
+        let Some(raw_content) =
+            self.account().fetch_account_data(GlobalAccountDataEventType::Direct).await?
+        else {
+            return Ok(None);
+        };
+
+        let content = raw_content.deserialize_as::<DirectEventContent>()?;
+        let Some(room_ids) = content.get(user_id) else {
+            return Ok(None);
+        };
+
+        for room_id in room_ids.iter().rev() {
+            let Some(room) = self.get_room(room_id) else {
+                let own_membership = self.fetch_membership(room_id, own_user_id).await?;
+                if own_membership != Some(MembershipState::Join) {
+                    continue;
+                }
+
+                let other_membership = self.fetch_membership(room_id, user_id).await?;
+                if matches!(other_membership, Some(MembershipState::Join | MembershipState::Invite))
+                {
+                    let base_room =
+                        self.base_client().get_or_create_room(room_id, RoomState::Joined);
+                    return Ok(Some(Room::new(self.clone(), base_room)));
+                }
+
+                continue;
+            };
+
+            if room.state() != RoomState::Joined {
+                continue;
+            }
+
+            let is_other_user_in_room = room.get_member(user_id).await?.is_some_and(|member| {
+                matches!(member.membership(), MembershipState::Join | MembershipState::Invite)
+            });
+
+            if is_other_user_in_room {
+                return Ok(Some(room));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Fetch the membership of the given user in the given room from the
+    /// homeserver.
+    ///
+    /// Returns `None` if the user has no membership in the room, or if the
+    /// current user can't see it.
+    async fn fetch_membership(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MembershipState>> {
+        let request = get_state_events_for_key::v3::Request::new(
+            room_id.to_owned(),
+            StateEventType::RoomMember,
+            user_id.to_string(),
+        );
+
+        match self.send(request, None).await {
+            Ok(response) => {
+                Ok(Some(response.content.deserialize_as::<RoomMemberEventContent>()?.membership))
+            }
+            Err(error)
+                if matches!(
+                    error.client_api_error_kind(),
+                    Some(ErrorKind::NotFound | ErrorKind::Forbidden)
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Mark the given rooms as read, for a "mark all as read" button for
+    /// example.
+    ///
+    /// In every room, the fully-read marker and the public read receipt are
+    /// moved to the latest event with a single request. The requests for the
+    /// different rooms are sent concurrently, but only a few at a time, and
+    /// they are retried after the delay asked by the homeserver if they hit
+    /// its rate limit.
+    ///
+    /// The latest event of a room is only looked up locally, in the event
+    /// cache enabled with [`ClientBuilder::cache_room_events()`], so no
+    /// request is sent to find it. The rooms with no known events are
+    /// skipped.
+    ///
+    /// The unread counts of the rooms are reset locally right away, without
+    /// waiting for the next sync.
+    ///
+    /// Returns the errors of the rooms that couldn't be marked as read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let room_ids: Vec<_> = client
+    ///     .joined_rooms()
+    ///     .iter()
+    ///     .map(|room| room.room_id().to_owned())
+    ///     .collect();
+    ///
+    /// for (room_id, error) in client.mark_all_as_read(&room_ids).await {
+    ///     eprintln!("Couldn't mark {room_id} as read: {error}");
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip_all, fields(count = room_ids.len()))]
+    pub async fn mark_all_as_read(&self, room_ids: &[OwnedRoomId]) -> BTreeMap<OwnedRoomId, Error> {
+        const MAX_CONCURRENT_REQUESTS: usize = 5;
+
+        stream::iter(room_ids)
+            .map(|room_id| async move { (room_id, self.mark_room_as_read(room_id).await) })
+            .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+            .filter_map(|(room_id, result)| async move {
+                result.err().map(|error| (room_id.clone(), error))
+            })
+            .collect()
+            .await
+    }
+
+    /// Move the fully-read marker and the public read receipt of the given
+    /// room to its latest event.
+    async fn mark_room_as_read(&self, room_id: &RoomId) -> Result<()> {
+        let Some(room) = self.get_room(room_id) else {
+            warn!(?room_id, "Can't mark an unknown room as read");
+            return Ok(());
+        };
+
+        let Some(latest_event_id) = self.latest_event_id(&room).await? else {
+            debug!(?room_id, "The latest event of the room is unknown, can't mark it as read");
+            return Ok(());
+        };
+
+        // Update the local unread state first, so the change is visible right
+        // away. The next sync fixes it if the request fails.
+        let mut room_info = room.clone_info();
+        room_info.update_notification_count(UnreadNotificationsCount::default());
+        room_info.update_unread_counts(UnreadCounts::default());
+        room.update_summary(room_info);
+
+        let own_user_id = room.own_user_id();
+        let current_receipt =
+            room.user_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, own_user_id).await?;
+
+        if current_receipt.is_some_and(|(event_id, _)| event_id == latest_event_id) {
+            debug!(?room_id, "The room is already read");
+            return Ok(());
+        }
+
+        let request = assign!(set_read_marker::v3::Request::new(room_id.to_owned()), {
+            fully_read: Some(latest_event_id.clone()),
+            read_receipt: Some(latest_event_id),
+        });
+
+        self.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Get the ID of the latest event of the given room from the event cache,
+    /// if it's enabled.
+    async fn latest_event_id(&self, room: &Room) -> Result<Option<OwnedEventId>> {
+        let Some(event_cache) = room.event_cache() else {
+            return Ok(None);
+        };
+
+        Ok(event_cache.latest_events(1).await?.pop().and_then(|event| event.event_id()))
+    }
+
+    /// Search the homeserver's directory for public rooms with a filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_search` - The easiest way to create this request is using the
+    /// `get_public_rooms_filtered::Request` itself.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use url::Url;
+    /// # use matrix_sdk::Client;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// use matrix_sdk::ruma::{
+    ///     api::client::directory::get_public_rooms_filtered, directory::Filter,
+    /// };
+    /// # let mut client = Client::new(homeserver).await?;
+    ///
+    /// let mut filter = Filter::new();
+    /// filter.generic_search_term = Some("rust".to_owned());
+    /// let mut request = get_public_rooms_filtered::v3::Request::new();
+    /// request.filter = filter;
+    ///
+    /// let response = client.public_rooms_filtered(request).await?;
+    ///
+    /// for room in response.chunk {
+    ///     println!("Found room {room:?}");
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn public_rooms_filtered(
+        &self,
+        request: get_public_rooms_filtered::v3::Request,
+    ) -> HttpResult<get_public_rooms_filtered::v3::Response> {
+        self.send(request, None).await
+    }
+
+    /// Send an arbitrary request to the server, without updating client state.
+    ///
+    /// **Warning:** Because this method *does not* update the client state, it
+    /// is important to make sure that you account for this yourself, and
+    /// use wrapper methods where available.  This method should *only* be
+    /// used if a wrapper method for the endpoint you'd like to use is not
+    /// available.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - A filled out and valid request for the endpoint to be hit
+    ///
+    /// * `timeout` - An optional request timeout setting, this overrides the
+    /// default request setting if one was set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, config::SyncSettings};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
     /// # let mut client = Client::new(homeserver).await?;
     /// use matrix_sdk::ruma::{api::client::profile, user_id};
     ///
@@ -1408,7 +2186,8 @@ impl Client {
             None => self.homeserver().await.to_string(),
         };
 
-        self.inner
+        let response = self
+            .inner
             .http_client
             .send(
                 request,
@@ -1419,7 +2198,139 @@ impl Client {
                 self.server_versions().await?,
                 send_progress,
             )
-            .await
+            .await;
+
+        self.update_network_state(&response);
+
+        response
+    }
+
+    /// Update the network state according to the result of a request.
+    fn update_network_state<T>(&self, result: &HttpResult<T>) {
+        let state = match result {
+            Err(error) if error.is_network_error() => NetworkState::Offline,
+            _ => NetworkState::Online,
+        };
+
+        if self.inner.network_state.set_if_not_eq(state).is_some() {
+            info!(?state, "The network state changed");
+
+            // Without a sync loop, there might not be another request to tell
+            // that the homeserver can be reached again, so check it ourselves.
+            if state == NetworkState::Offline {
+                spawn(probe_connectivity(Arc::downgrade(&self.inner)));
+            }
+        }
+    }
+
+    /// Send a request to the homeserver only to find out whether it can be
+    /// reached, and update the network state accordingly.
+    async fn probe_homeserver(&self) {
+        let response = self
+            .inner
+            .http_client
+            .send(
+                get_supported_versions::Request::new(),
+                Some(RequestConfig::new().disable_retry()),
+                self.homeserver().await.to_string(),
+                None,
+                None,
+                &[MatrixVersion::V1_0],
+                Default::default(),
+            )
+            .await;
+
+        self.update_network_state(&response);
+    }
+
+    /// Add an observer that is called after every attempt at sending a request
+    /// to the homeserver.
+    ///
+    /// The observer receives the metadata of the request, without its
+    /// secrets, and the outcome of the attempt. This can be used to collect
+    /// network-quality metrics, or to include the recent requests in bug
+    /// reports.
+    ///
+    /// The observer is called synchronously on the task that sent the
+    /// request, so it should return quickly.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// client.add_request_observer(|info| {
+    ///     if info.error.is_some() {
+    ///         println!(
+    ///             "{} {} failed after {:?}",
+    ///             info.method, info.path, info.duration
+    ///         );
+    ///     }
+    /// });
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn add_request_observer(&self, observer: impl Fn(&RequestInfo) + Send + Sync + 'static) {
+        self.inner.http_client.add_request_observer(Arc::new(observer));
+    }
+
+    /// The state of the connectivity to the homeserver.
+    ///
+    /// The client is considered offline when the last request couldn't reach
+    /// the homeserver, and back online as soon as a request reaches it again.
+    /// Only failures to connect to the homeserver and timeouts are considered
+    /// as the homeserver not being reachable. While offline, the client checks
+    /// periodically whether the homeserver can be reached again, so it comes
+    /// back online even if no other request is sent.
+    ///
+    /// While offline, typing notices and receipts are not sent and
+    /// [`Error::Offline`] is returned instead, and the events in the send
+    /// queues are retried as soon as the client is back online.
+    pub fn network_state(&self) -> NetworkState {
+        self.inner.network_state.get()
+    }
+
+    /// Subscribe to the changes of the state of the connectivity to the
+    /// homeserver.
+    ///
+    /// See [`Client::network_state()`] for more details.
+    pub fn subscribe_to_network_state(&self) -> Subscriber<NetworkState> {
+        self.inner.network_state.subscribe()
+    }
+
+    /// Send the given request and return the response as soon as its headers
+    /// are received, so its body can be streamed.
+    ///
+    /// See [`HttpClient::send_streaming()`] for the meaning of `range_start`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn send_streaming<Request>(
+        &self,
+        request: Request,
+        config: Option<RequestConfig>,
+        range_start: Option<u64>,
+    ) -> HttpResult<reqwest::Response>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        let response = self
+            .inner
+            .http_client
+            .send_streaming(
+                request,
+                config.unwrap_or_else(|| self.request_config()),
+                self.homeserver().await.to_string(),
+                self.access_token().as_deref(),
+                self.server_versions().await?,
+                range_start,
+            )
+            .await;
+
+        self.update_network_state(&response);
+
+        response
     }
 
     fn broadcast_unknown_token(&self, soft_logout: &bool) {
@@ -1431,7 +2342,7 @@ impl Client {
     }
 
     async fn request_server_versions(&self) -> HttpResult<Box<[MatrixVersion]>> {
-        let server_versions: Box<[MatrixVersion]> = self
+        let response = self
             .inner
             .http_client
             .send(
@@ -1443,9 +2354,11 @@ impl Client {
                 &[MatrixVersion::V1_0],
                 Default::default(),
             )
-            .await?
-            .known_versions()
-            .collect();
+            .await;
+
+        self.update_network_state(&response);
+
+        let server_versions: Box<[MatrixVersion]> = response?.known_versions().collect();
 
         if server_versions.is_empty() {
             Ok(vec![MatrixVersion::V1_0].into())
@@ -1463,6 +2376,12 @@ impl Client {
 
     /// Get information of all our own devices.
     ///
+    /// Each [`Device`] contains its display name, and the IP address and time
+    /// it was last seen at, if the homeserver exposes them.
+    ///
+    /// The devices are also cached, and can be observed with
+    /// [`Client::subscribe_to_own_devices()`].
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -1485,7 +2404,44 @@ impl Client {
     pub async fn devices(&self) -> HttpResult<get_devices::v3::Response> {
         let request = get_devices::v3::Request::new();
 
-        self.send(request, None).await
+        let response = self.send(request, None).await?;
+        self.inner.own_devices.set(Some(response.devices.clone()));
+
+        Ok(response)
+    }
+
+    /// Subscribe to the changes of the list of our own devices.
+    ///
+    /// The list is `None` until it is fetched with [`Client::devices()`]. It
+    /// is then updated when a device is renamed or deleted with this client,
+    /// and fetched again when a sync reports that the list changed.
+    pub fn subscribe_to_own_devices(&self) -> Subscriber<Option<Vec<Device>>> {
+        self.inner.own_devices.subscribe()
+    }
+
+    /// Fetch our own devices again in a background task, if the list was
+    /// fetched before and the given users whose devices changed, from a sync,
+    /// include our own user.
+    ///
+    /// This is not awaited by the sync, so a slow request doesn't stall it.
+    pub(crate) fn refresh_own_devices_if_changed(&self, changed: &[OwnedUserId]) {
+        let Some(user_id) = self.user_id() else { return };
+
+        if self.inner.own_devices.read().is_none() || !changed.iter().any(|u| **u == *user_id) {
+            return;
+        }
+
+        debug!("Our own devices changed, fetching them again");
+
+        let client = self.clone();
+        spawn(async move {
+            let request = get_devices::v3::Request::new();
+
+            match client.send(request, Some(RequestConfig::short_retry())).await {
+                Ok(response) => client.inner.own_devices.set(Some(response.devices)),
+                Err(error) => warn!("Failed to fetch our own devices: {error}"),
+            }
+        });
     }
 
     /// Delete the given devices from the server.
@@ -1536,7 +2492,104 @@ impl Client {
         let mut request = delete_devices::v3::Request::new(devices.to_owned());
         request.auth = auth_data;
 
-        self.send(request, None).await
+        let response = self.send(request, None).await?;
+        self.inner.own_devices.update(|own_devices| {
+            if let Some(own_devices) = own_devices {
+                own_devices.retain(|device| !devices.contains(&device.device_id));
+            }
+        });
+
+        Ok(response)
+    }
+
+    /// Send a request that might require to go through the [User-Interactive
+    /// Authentication API][uiaa].
+    ///
+    /// The request is first sent without authentication data. If the
+    /// homeserver requires authentication, a [`UiaaFlow`] is returned to
+    /// submit the stages, which sends the request again with the
+    /// authentication data of each stage.
+    ///
+    /// # Arguments
+    ///
+    /// * `send` - A function sending the request with the given
+    /// authentication data.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, UiaaOutcome};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let account = client.account();
+    ///
+    /// let outcome = client
+    ///     .send_with_uiaa(move |auth_data| {
+    ///         let account = account.clone();
+    ///         async move { account.deactivate(None, auth_data).await }
+    ///     })
+    ///     .await?;
+    ///
+    /// if let UiaaOutcome::AuthRequired(mut flow) = outcome {
+    ///     flow.submit_password("wordpass").await?;
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    /// [uiaa]: https://spec.matrix.org/v1.2/client-server-api/#user-interactive-authentication-api
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn send_with_uiaa<T, F, Fut>(&self, send: F) -> Result<UiaaOutcome<T>>
+    where
+        F: Fn(Option<uiaa::AuthData>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+    {
+        UiaaFlow::start(self.user_id().map(ToOwned::to_owned), send).await
+    }
+
+    /// Send a request that might require to go through the [User-Interactive
+    /// Authentication API][uiaa].
+    ///
+    /// The request is first sent without authentication data. If the
+    /// homeserver requires authentication, a [`UiaaFlow`] is returned to
+    /// submit the stages, which sends the request again with the
+    /// authentication data of each stage.
+    ///
+    /// # Arguments
+    ///
+    /// * `send` - A function sending the request with the given
+    /// authentication data.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, UiaaOutcome};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let account = client.account();
+    ///
+    /// let outcome = client
+    ///     .send_with_uiaa(move |auth_data| {
+    ///         let account = account.clone();
+    ///         async move { account.deactivate(None, auth_data).await }
+    ///     })
+    ///     .await?;
+    ///
+    /// if let UiaaOutcome::AuthRequired(mut flow) = outcome {
+    ///     flow.submit_password("wordpass").await?;
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    /// [uiaa]: https://spec.matrix.org/v1.2/client-server-api/#user-interactive-authentication-api
+    #[cfg(target_arch = "wasm32")]
+    pub async fn send_with_uiaa<T, F, Fut>(&self, send: F) -> Result<UiaaOutcome<T>>
+    where
+        F: Fn(Option<uiaa::AuthData>) -> Fut + 'static,
+        Fut: Future<Output = Result<T>> + 'static,
+    {
+        UiaaFlow::start(self.user_id().map(ToOwned::to_owned), send).await
     }
 
     /// Change the display name of a device owned by the current user.
@@ -1556,7 +2609,17 @@ impl Client {
         let mut request = update_device::v3::Request::new(device_id.to_owned());
         request.display_name = Some(display_name.to_owned());
 
-        self.send(request, None).await
+        let response = self.send(request, None).await?;
+        self.inner.own_devices.update(|own_devices| {
+            let device =
+                own_devices.iter_mut().flatten().find(|device| &*device.device_id == device_id);
+
+            if let Some(device) = device {
+                device.display_name = Some(display_name.to_owned());
+            }
+        });
+
+        Ok(response)
     }
 
     /// Synchronize the client's state with the latest state on the server.
@@ -1668,15 +2731,18 @@ impl Client {
             error!(error = ?e, "Error while sending outgoing E2EE requests");
         }
 
+        // Poll for longer while the app is in the background.
+        let timeout = sync_settings.timeout.map(|timeout| self.sync_timeout(timeout));
+
         let request = assign!(sync_events::v3::Request::new(), {
             filter: sync_settings.filter.map(|f| *f),
             since: sync_settings.token,
             full_state: sync_settings.full_state,
             set_presence: sync_settings.set_presence,
-            timeout: sync_settings.timeout,
+            timeout,
         });
         let mut request_config = self.request_config();
-        if let Some(timeout) = sync_settings.timeout {
+        if let Some(timeout) = timeout {
             request_config.timeout += timeout;
         }
 
@@ -2000,18 +3066,77 @@ impl Client {
         self.send(request, None).await
     }
 
+    /// How long the events of every room should be kept locally at most, if
+    /// there is a limit.
+    ///
+    /// This is set with [`ClientBuilder::max_local_history_age()`].
+    pub fn max_local_history_age(&self) -> Option<Duration> {
+        self.inner.max_local_history_age
+    }
+
+    /// Subscribe to the upgrades of the rooms the user is in.
+    ///
+    /// A [`RoomUpgrade`] is received every time a `m.room.tombstone` event is
+    /// received via sync for a joined room.
+    pub fn subscribe_to_room_upgrades(&self) -> broadcast::Receiver<RoomUpgrade> {
+        self.inner.room_upgrade_sender.subscribe()
+    }
+
     /// Subscribes a new receiver to client SessionChange broadcasts.
     pub fn subscribe_to_session_changes(&self) -> broadcast::Receiver<SessionChange> {
         let broadcast = &self.inner.session_change_sender;
         broadcast.subscribe()
     }
 
+    /// Get the pushers registered for the account.
+    pub async fn pushers(&self) -> HttpResult<Vec<Pusher>> {
+        let request = get_pushers::v3::Request::new();
+        Ok(self.send(request, None).await?.pushers)
+    }
+
     /// Sets a given pusher
+    ///
+    /// This creates a new pusher, or replaces the existing pusher with the
+    /// same [`PusherIds`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # async {
+    /// # let client = Client::new("http://localhost:8080".parse()?).await?;
+    /// use matrix_sdk::ruma::{
+    ///     api::client::push::{PusherIds, PusherInit, PusherKind},
+    ///     push::HttpPusherData,
+    /// };
+    ///
+    /// let pusher = PusherInit {
+    ///     ids: PusherIds::new(
+    ///         "device-push-token".to_owned(),
+    ///         "org.example.app".to_owned(),
+    ///     ),
+    ///     kind: PusherKind::Http(HttpPusherData::new(
+    ///         "https://push.example.org/_matrix/push/v1/notify".to_owned(),
+    ///     )),
+    ///     app_display_name: "Example".to_owned(),
+    ///     device_display_name: "My phone".to_owned(),
+    ///     profile_tag: None,
+    ///     lang: "en".to_owned(),
+    /// };
+    /// client.set_pusher(pusher.into()).await?;
+    /// # anyhow::Ok(()) };
+    /// ```
     pub async fn set_pusher(&self, pusher: Pusher) -> HttpResult<set_pusher::v3::Response> {
         let request = set_pusher::v3::Request::post(pusher);
         self.send(request, None).await
     }
 
+    /// Delete the pusher with the given identifiers.
+    pub async fn delete_pusher(&self, ids: PusherIds) -> HttpResult<set_pusher::v3::Response> {
+        let request = set_pusher::v3::Request::delete(ids);
+        self.send(request, None).await
+    }
+
     /// Subscribe to sync gaps for the given room.
     ///
     /// This method is meant to be removed in favor of making event handlers
@@ -2043,7 +3168,7 @@ impl Client {
         let client = Client {
             inner: Arc::new(ClientInner::new(
                 self.inner.homeserver.read().await.clone(),
-                self.inner.authentication_server_info.clone(),
+                self.well_known(),
                 #[cfg(feature = "experimental-sliding-sync")]
                 self.inner.sliding_sync_proxy.read().unwrap().clone(),
                 self.inner.http_client.clone(),
@@ -2052,6 +3177,17 @@ impl Client {
                 self.inner.appservice_mode,
                 self.inner.respect_login_well_known,
                 self.inner.handle_refresh_tokens,
+                // The notification client uses an in-memory store, there's no
+                // point in indexing messages.
+                false,
+                // Nor in joining rooms.
+                false,
+                self.inner.max_local_history_age,
+                // Nor in caching events.
+                false,
+                self.inner.message_formatter.clone(),
+                #[cfg(feature = "e2e-encryption")]
+                self.inner.keys_upload_request_config,
             )),
         };
 
@@ -2064,19 +3200,50 @@ impl Client {
     }
 }
 
+/// Check whether the homeserver can be reached again, with an increasing delay
+/// between the attempts, until the client is back online or dropped.
+async fn probe_connectivity(client: Weak<ClientInner>) {
+    let mut delay = CONNECTIVITY_PROBE_INITIAL_DELAY;
+
+    loop {
+        sleep(delay).await;
+
+        let Some(inner) = client.upgrade() else {
+            return;
+        };
+        let client = Client { inner };
+
+        // Another request might have reached the homeserver in the meantime.
+        if client.network_state() == NetworkState::Online {
+            return;
+        }
+
+        debug!("Checking whether the homeserver can be reached again");
+        client.probe_homeserver().await;
+
+        if client.network_state() == NetworkState::Online {
+            return;
+        }
+
+        delay = (delay * 2).min(CONNECTIVITY_PROBE_MAX_DELAY);
+    }
+}
+
 // The http mocking library is not supported for wasm32
 #[cfg(all(test, not(target_arch = "wasm32")))]
 pub(crate) mod tests {
     use std::time::Duration;
 
-    use matrix_sdk_base::RoomState;
+    use futures_util::StreamExt;
+    use matrix_sdk_base::{RoomState, SessionMeta};
     use matrix_sdk_test::{
         async_test, test_json, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder,
     };
     #[cfg(target_arch = "wasm32")]
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
-    use ruma::{events::ignored_user_list::IgnoredUserListEventContent, UserId};
+    use ruma::{events::ignored_user_list::IgnoredUserListEventContent, user_id, UserId};
+    use serde_json::json;
     use url::Url;
     use wiremock::{
         matchers::{body_json, header, method, path},
@@ -2086,6 +3253,7 @@ pub(crate) mod tests {
     use super::Client;
     use crate::{
         config::{RequestConfig, SyncSettings},
+        matrix_auth::{Session, SessionTokens},
         test_utils::{logged_in_client, no_retry_test_client, test_client_builder},
     };
 
@@ -2116,6 +3284,30 @@ pub(crate) mod tests {
         assert_eq!(content.ignored_users.len(), 1);
     }
 
+    #[async_test]
+    async fn ignored_users() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        assert!(client.ignored_users().await.unwrap().is_empty());
+
+        let mut ignored_users_stream = Box::pin(client.subscribe_to_ignored_users());
+
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/sync".to_owned()))
+            .and(header("authorization", "Bearer 1234"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::SYNC))
+            .mount(&server)
+            .await;
+
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+        let _response = client.sync_once(sync_settings).await.unwrap();
+
+        let ignored_users = ignored_users_stream.next().await.unwrap();
+        assert_eq!(ignored_users.len(), 1);
+        assert!(ignored_users.contains(user_id!("@someone:example.org")));
+        assert_eq!(client.ignored_users().await.unwrap(), ignored_users);
+    }
+
     #[async_test]
     async fn successful_discovery() {
         let server = MockServer::start().await;
@@ -2169,6 +3361,115 @@ pub(crate) mod tests {
         );
     }
 
+    #[async_test]
+    async fn refresh_well_known() {
+        let server = MockServer::start().await;
+        let server_url = server.uri();
+        let domain = server_url.strip_prefix("http://").unwrap();
+        let alice = UserId::parse("@alice:".to_owned() + domain).unwrap();
+
+        Mock::given(method("GET"))
+            .and(path("/.well-known/matrix/client"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                test_json::WELL_KNOWN.to_string().replace("HOMESERVER_URL", server_url.as_ref()),
+                "application/json",
+            ))
+            .mount(&server)
+            .await;
+
+        let client = Client::builder()
+            .insecure_server_name_no_tls(alice.server_name())
+            .build()
+            .await
+            .unwrap();
+
+        let well_known = client.well_known().unwrap();
+        assert_eq!(well_known.homeserver, Url::parse(server_url.as_ref()).unwrap());
+        assert!(well_known.identity_server.is_none());
+
+        // The server changes its configuration.
+        server.reset().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/matrix/client"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "m.homeserver": { "base_url": server_url },
+                "m.identity_server": { "base_url": "https://identity.localhost" },
+                "org.matrix.msc3575.proxy": { "url": "https://proxy.localhost" },
+            })))
+            .mount(&server)
+            .await;
+
+        let well_known = client.refresh_well_known().await.unwrap().unwrap();
+        assert_eq!(
+            well_known.identity_server,
+            Some(Url::parse("https://identity.localhost").unwrap())
+        );
+        assert_eq!(
+            client.well_known().unwrap().identity_server,
+            Some(Url::parse("https://identity.localhost").unwrap())
+        );
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        assert_eq!(
+            client.sliding_sync_proxy(),
+            Some(Url::parse("https://proxy.localhost").unwrap())
+        );
+    }
+
+    #[async_test]
+    async fn refresh_well_known_keeps_configured_values() {
+        let server = MockServer::start().await;
+        let server_url = server.uri();
+        let domain = server_url.strip_prefix("http://").unwrap();
+        let alice = UserId::parse("@alice:".to_owned() + domain).unwrap();
+
+        Mock::given(method("GET"))
+            .and(path("/.well-known/matrix/client"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                test_json::WELL_KNOWN.to_string().replace("HOMESERVER_URL", server_url.as_ref()),
+                "application/json",
+            ))
+            .mount(&server)
+            .await;
+
+        let builder = Client::builder().insecure_server_name_no_tls(alice.server_name());
+        #[cfg(feature = "experimental-sliding-sync")]
+        let builder = builder.sliding_sync_proxy("https://configured.localhost");
+        let client = builder.build().await.unwrap();
+        client
+            .matrix_auth()
+            .restore_session(Session {
+                meta: SessionMeta { user_id: alice.clone(), device_id: "DEVICEID".into() },
+                tokens: SessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            })
+            .await
+            .unwrap();
+
+        // The server changes its configuration.
+        server.reset().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/matrix/client"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "m.homeserver": { "base_url": "https://other.localhost" },
+                "org.matrix.msc3575.proxy": { "url": "https://proxy.localhost" },
+            })))
+            .mount(&server)
+            .await;
+
+        let well_known = client.refresh_well_known().await.unwrap().unwrap();
+        assert_eq!(well_known.homeserver, Url::parse("https://other.localhost").unwrap());
+
+        // The homeserver of the session doesn't change.
+        assert_eq!(client.homeserver().await, Url::parse(server_url.as_ref()).unwrap());
+
+        // The configured sliding sync proxy is kept.
+        #[cfg(feature = "experimental-sliding-sync")]
+        assert_eq!(
+            client.sliding_sync_proxy(),
+            Some(Url::parse("https://configured.localhost").unwrap())
+        );
+    }
+
     #[async_test]
     async fn room_creation() {
         let server = MockServer::start().await;
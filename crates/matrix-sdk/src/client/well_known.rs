@@ -0,0 +1,176 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ruma::api::{
+    client::discovery::discover_homeserver::{self, AuthenticationServerInfo},
+    MatrixVersion,
+};
+use tracing::{debug, warn};
+use url::Url;
+
+use super::Client;
+use crate::{config::RequestConfig, http_client::HttpClient, HttpResult, Result};
+
+/// The content of the `/.well-known/matrix/client` file of a server, used to
+/// discover the homeserver and the services around it.
+///
+/// Get it with [`Client::well_known()`].
+#[derive(Clone, Debug)]
+pub struct ClientWellKnown {
+    /// The URL the file was fetched from, without the path.
+    discovery_url: String,
+    /// The URL of the homeserver.
+    pub homeserver: Url,
+    /// The URL of the identity server, if any.
+    pub identity_server: Option<Url>,
+    /// The URL of the sliding sync proxy trusted by the homeserver, if any.
+    ///
+    /// This is advertised with the `org.matrix.msc3575.proxy` entry.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub sliding_sync_proxy: Option<Url>,
+    /// The authentication server to use, if the homeserver supports
+    /// authenticating via OpenID Connect ([MSC2965]).
+    ///
+    /// [MSC2965]: https://github.com/matrix-org/matrix-spec-proposals/pull/2965
+    pub authentication: Option<AuthenticationServerInfo>,
+}
+
+impl ClientWellKnown {
+    /// Fetch the well-known file from the given server.
+    ///
+    /// `discovery_url` is the scheme and host of the server, like
+    /// `https://example.org`.
+    pub(crate) async fn fetch(
+        http_client: &HttpClient,
+        discovery_url: String,
+    ) -> HttpResult<discover_homeserver::Response> {
+        http_client
+            .send(
+                discover_homeserver::Request::new(),
+                Some(RequestConfig::short_retry()),
+                discovery_url,
+                None,
+                None,
+                &[MatrixVersion::V1_0],
+                Default::default(),
+            )
+            .await
+    }
+
+    /// Parse the URLs in the given well-known response.
+    ///
+    /// Fails if the homeserver URL is invalid. The other invalid URLs are
+    /// ignored.
+    pub(crate) fn new(
+        discovery_url: String,
+        response: discover_homeserver::Response,
+    ) -> Result<Self, url::ParseError> {
+        let homeserver = Url::parse(&response.homeserver.base_url)?;
+        let identity_server = response.identity_server.and_then(|info| {
+            Url::parse(&info.base_url)
+                .map_err(|error| warn!("Invalid identity server URL in the well-known: {error}"))
+                .ok()
+        });
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        let sliding_sync_proxy = response.sliding_sync_proxy.and_then(|info| {
+            Url::parse(&info.url)
+                .map_err(|error| warn!("Invalid sliding sync proxy URL in the well-known: {error}"))
+                .ok()
+        });
+
+        Ok(Self {
+            discovery_url,
+            homeserver,
+            identity_server,
+            #[cfg(feature = "experimental-sliding-sync")]
+            sliding_sync_proxy,
+            authentication: response.authentication,
+        })
+    }
+}
+
+impl Client {
+    /// The content of the `/.well-known/matrix/client` file of the server, if
+    /// it was fetched.
+    ///
+    /// It is fetched when this `Client` was constructed using auto-discovery
+    /// by setting the homeserver with [`ClientBuilder::server_name()`], or
+    /// with [`Client::refresh_well_known()`].
+    ///
+    /// [`ClientBuilder::server_name()`]: crate::ClientBuilder::server_name
+    pub fn well_known(&self) -> Option<ClientWellKnown> {
+        self.inner.well_known.read().unwrap().clone()
+    }
+
+    /// Fetch the `/.well-known/matrix/client` file of the server again, to
+    /// pick up changes to its configuration.
+    ///
+    /// The file is fetched from the server that was used for auto-discovery,
+    /// or from the server of the logged-in user otherwise. Returns `None` if
+    /// there is no server to fetch it from.
+    ///
+    /// The values that were not configured explicitly are updated with the
+    /// new ones:
+    ///
+    /// - The homeserver URL is only updated if the client is not logged in, as
+    ///   the session belongs to the homeserver it was created on.
+    /// - The sliding sync proxy is only updated if none is set, or if the
+    ///   current one was discovered with the previous well-known.
+    ///
+    /// The authentication server stays the one that was discovered when the
+    /// client was built, as changing it would require to log in again.
+    pub async fn refresh_well_known(&self) -> Result<Option<ClientWellKnown>> {
+        let previous = self.well_known();
+        let discovery_url = match &previous {
+            Some(well_known) => well_known.discovery_url.clone(),
+            None => match self.user_id() {
+                Some(user_id) => format!("https://{}", user_id.server_name()),
+                None => return Ok(None),
+            },
+        };
+
+        debug!(discovery_url, "Refreshing the well-known");
+
+        let response =
+            ClientWellKnown::fetch(&self.inner.http_client, discovery_url.clone()).await?;
+        let well_known = ClientWellKnown::new(discovery_url, response)?;
+
+        if well_known.homeserver != self.homeserver().await {
+            if self.logged_in() {
+                warn!(
+                    homeserver = %well_known.homeserver,
+                    "The homeserver URL changed, but the client is already logged in"
+                );
+            } else {
+                debug!(homeserver = %well_known.homeserver, "The homeserver URL changed");
+                self.set_homeserver(well_known.homeserver.clone()).await;
+            }
+        }
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        {
+            let current_proxy = self.sliding_sync_proxy();
+            let previous_proxy = previous.and_then(|well_known| well_known.sliding_sync_proxy);
+
+            if current_proxy.is_none() || current_proxy == previous_proxy {
+                self.set_sliding_sync_proxy(well_known.sliding_sync_proxy.clone());
+            }
+        }
+
+        *self.inner.well_known.write().unwrap() = Some(well_known.clone());
+
+        Ok(Some(well_known))
+    }
+}
@@ -0,0 +1,515 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Management of the clients of several accounts.
+//!
+//! A [`ClientManager`] owns one logged-in [`Client`] per account, and syncs
+//! all of them from tasks spawned on a single runtime. The accounts that are
+//! not in the foreground can be synced less often, and the start of the syncs
+//! is staggered to avoid a burst of requests when an app launches.
+//!
+//! The unread counts of all the accounts are merged, to be displayed in a
+//! badge for example. The accounts that are logged out by the homeserver stop
+//! syncing, and are reported with
+//! [`ClientManager::subscribe_to_logged_out_accounts()`].
+
+use std::{
+    sync::{Arc, Mutex as StdMutex, Weak},
+    time::Duration,
+};
+
+use eyeball::{SharedObservable, Subscriber};
+use futures_util::future::{AbortHandle, Abortable};
+#[cfg(target_arch = "wasm32")]
+use matrix_sdk_common::executor::spawn;
+use matrix_sdk_common::{instant::Instant, sleep::sleep};
+use ruma::{api::client::error::ErrorKind, OwnedUserId, UserId};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{config::SyncSettings, Client};
+
+/// The default delay between the start of the syncs of two accounts.
+const DEFAULT_SYNC_STAGGER: Duration = Duration::from_secs(1);
+
+/// The default delay between two syncs of an account in the background.
+const DEFAULT_BACKGROUND_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The delay before syncing again after a failed sync.
+const SYNC_ERROR_DELAY: Duration = Duration::from_secs(5);
+
+/// How often the account of a [`ClientManager`] is synced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SyncPriority {
+    /// The account is synced continuously, with long polling, so new events
+    /// are received right away.
+    #[default]
+    Foreground,
+
+    /// The account is synced periodically, without long polling.
+    ///
+    /// See [`ClientManager::set_background_sync_interval()`].
+    Background,
+}
+
+/// An error when adding a client to a [`ClientManager`].
+#[derive(Debug, Error)]
+pub enum ClientManagerError {
+    /// The client is not logged in.
+    #[error("the client is not logged in")]
+    NotLoggedIn,
+
+    /// A client for the same account was already added.
+    #[error("a client for {0} was already added")]
+    AlreadyAdded(OwnedUserId),
+}
+
+/// A manager of the clients of several accounts.
+///
+/// The sync tasks are stopped when the last clone of the manager is dropped.
+#[derive(Clone, Debug)]
+pub struct ClientManager {
+    inner: Arc<ClientManagerInner>,
+}
+
+#[derive(Debug)]
+struct ClientManagerInner {
+    state: StdMutex<ManagerState>,
+    total_unread_count: SharedObservable<u64>,
+    logged_out_accounts: SharedObservable<Vec<OwnedUserId>>,
+    /// The runtime on which the sync tasks of all the accounts are spawned.
+    #[cfg(not(target_arch = "wasm32"))]
+    runtime: tokio::runtime::Handle,
+}
+
+#[derive(Debug)]
+struct ManagerState {
+    accounts: Vec<ManagedAccount>,
+    syncing: bool,
+    sync_stagger: Duration,
+    background_sync_interval: Duration,
+}
+
+impl ManagerState {
+    fn new() -> Self {
+        Self {
+            accounts: Vec::new(),
+            syncing: false,
+            sync_stagger: DEFAULT_SYNC_STAGGER,
+            background_sync_interval: DEFAULT_BACKGROUND_SYNC_INTERVAL,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ManagedAccount {
+    user_id: OwnedUserId,
+    client: Client,
+    priority: SyncPriority,
+    unread_count: u64,
+    sync_task: Option<AbortHandle>,
+    /// Whether the homeserver rejected the access token of the client.
+    logged_out: bool,
+}
+
+impl ManagedAccount {
+    fn stop_sync(&mut self) {
+        if let Some(sync_task) = self.sync_task.take() {
+            sync_task.abort();
+        }
+    }
+}
+
+impl ClientManager {
+    /// Create a new `ClientManager` without any client, that spawns the sync
+    /// tasks of all the accounts on the current tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime. Use
+    /// [`ClientManager::with_runtime()`] in that case.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new() -> Self {
+        Self::with_runtime(tokio::runtime::Handle::current())
+    }
+
+    /// Create a new `ClientManager` without any client, that spawns the sync
+    /// tasks of all the accounts on the given tokio runtime.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_runtime(runtime: tokio::runtime::Handle) -> Self {
+        Self {
+            inner: Arc::new(ClientManagerInner {
+                state: StdMutex::new(ManagerState::new()),
+                total_unread_count: SharedObservable::new(0),
+                logged_out_accounts: SharedObservable::new(Vec::new()),
+                runtime,
+            }),
+        }
+    }
+
+    /// Create a new `ClientManager` without any client.
+    #[cfg(target_arch = "wasm32")]
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(ClientManagerInner {
+                state: StdMutex::new(ManagerState::new()),
+                total_unread_count: SharedObservable::new(0),
+                logged_out_accounts: SharedObservable::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Add the given logged-in client, synced with the given priority.
+    ///
+    /// If the manager is syncing, the client starts syncing right away.
+    pub fn add_client(
+        &self,
+        client: Client,
+        priority: SyncPriority,
+    ) -> Result<(), ClientManagerError> {
+        let user_id = client.user_id().ok_or(ClientManagerError::NotLoggedIn)?.to_owned();
+
+        let mut state = self.inner.state.lock().unwrap();
+        if state.accounts.iter().any(|account| account.user_id == user_id) {
+            return Err(ClientManagerError::AlreadyAdded(user_id));
+        }
+
+        let mut account = ManagedAccount {
+            user_id,
+            client,
+            priority,
+            unread_count: 0,
+            sync_task: None,
+            logged_out: false,
+        };
+        if state.syncing {
+            account.sync_task = Some(spawn_sync(
+                &self.inner,
+                &account,
+                Duration::ZERO,
+                state.background_sync_interval,
+            ));
+        }
+        state.accounts.push(account);
+
+        Ok(())
+    }
+
+    /// Remove the client of the given account, and stop syncing it.
+    ///
+    /// Returns the removed client, if any.
+    pub fn remove_client(&self, user_id: &UserId) -> Option<Client> {
+        let mut state = self.inner.state.lock().unwrap();
+        let index = state.accounts.iter().position(|account| account.user_id == user_id)?;
+
+        let mut account = state.accounts.remove(index);
+        account.stop_sync();
+        self.inner.update_total_unread_count(&state);
+        self.inner.update_logged_out_accounts(&state);
+
+        Some(account.client)
+    }
+
+    /// Get the client of the given account.
+    pub fn client(&self, user_id: &UserId) -> Option<Client> {
+        let state = self.inner.state.lock().unwrap();
+        state
+            .accounts
+            .iter()
+            .find(|account| account.user_id == user_id)
+            .map(|account| account.client.clone())
+    }
+
+    /// Get the clients of all the accounts, in the order they were added.
+    pub fn clients(&self) -> Vec<Client> {
+        let state = self.inner.state.lock().unwrap();
+        state.accounts.iter().map(|account| account.client.clone()).collect()
+    }
+
+    /// Change the priority of the given account.
+    ///
+    /// Returns `false` if there is no client for this account.
+    pub fn set_priority(&self, user_id: &UserId, priority: SyncPriority) -> bool {
+        let mut guard = self.inner.state.lock().unwrap();
+        let state = &mut *guard;
+        let Some(account) = state.accounts.iter_mut().find(|account| account.user_id == user_id)
+        else {
+            return false;
+        };
+
+        if account.priority != priority {
+            account.priority = priority;
+
+            if account.sync_task.is_some() {
+                account.stop_sync();
+                account.sync_task = Some(spawn_sync(
+                    &self.inner,
+                    account,
+                    Duration::ZERO,
+                    state.background_sync_interval,
+                ));
+            }
+        }
+
+        true
+    }
+
+    /// Set the delay between the start of the syncs of two accounts, when
+    /// calling [`ClientManager::start_sync()`].
+    ///
+    /// Defaults to 1 second.
+    pub fn set_sync_stagger(&self, stagger: Duration) {
+        self.inner.state.lock().unwrap().sync_stagger = stagger;
+    }
+
+    /// Set the delay between two syncs of an account with the
+    /// [`SyncPriority::Background`] priority.
+    ///
+    /// This is used for the syncs started after the call. Defaults to 1
+    /// minute.
+    pub fn set_background_sync_interval(&self, interval: Duration) {
+        self.inner.state.lock().unwrap().background_sync_interval = interval;
+    }
+
+    /// Start syncing all the accounts.
+    ///
+    /// The accounts in the foreground start syncing first, then the ones in
+    /// the background, with the delay set with
+    /// [`ClientManager::set_sync_stagger()`] between each of them.
+    ///
+    /// The accounts that were logged out by the homeserver don't start
+    /// syncing.
+    pub fn start_sync(&self) {
+        let mut guard = self.inner.state.lock().unwrap();
+        let state = &mut *guard;
+        if state.syncing {
+            return;
+        }
+        state.syncing = true;
+
+        let mut accounts: Vec<_> =
+            state.accounts.iter_mut().filter(|account| !account.logged_out).collect();
+        accounts.sort_by_key(|account| account.priority);
+
+        let mut delay = Duration::ZERO;
+        for account in accounts {
+            account.sync_task =
+                Some(spawn_sync(&self.inner, account, delay, state.background_sync_interval));
+            delay += state.sync_stagger;
+        }
+    }
+
+    /// Stop syncing all the accounts.
+    pub fn stop_sync(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.syncing = false;
+        for account in &mut state.accounts {
+            account.stop_sync();
+        }
+    }
+
+    /// Whether the accounts are being synced.
+    pub fn is_syncing(&self) -> bool {
+        self.inner.state.lock().unwrap().syncing
+    }
+
+    /// The number of unread notifications in the joined rooms of the given
+    /// account, as of its latest sync.
+    pub fn unread_count(&self, user_id: &UserId) -> Option<u64> {
+        let state = self.inner.state.lock().unwrap();
+        state
+            .accounts
+            .iter()
+            .find(|account| account.user_id == user_id)
+            .map(|account| account.unread_count)
+    }
+
+    /// The number of unread notifications across all the accounts.
+    pub fn total_unread_count(&self) -> u64 {
+        self.inner.total_unread_count.get()
+    }
+
+    /// Subscribe to the changes of the number of unread notifications across
+    /// all the accounts.
+    pub fn subscribe_to_total_unread_count(&self) -> Subscriber<u64> {
+        self.inner.total_unread_count.subscribe()
+    }
+
+    /// The accounts whose access token was rejected by the homeserver, and
+    /// that stopped syncing.
+    ///
+    /// Their clients need to log in again, and to be added again after being
+    /// removed with [`ClientManager::remove_client()`].
+    pub fn logged_out_accounts(&self) -> Vec<OwnedUserId> {
+        self.inner.logged_out_accounts.get()
+    }
+
+    /// Subscribe to the changes of the accounts whose access token was
+    /// rejected by the homeserver.
+    ///
+    /// See [`ClientManager::logged_out_accounts()`] for more details.
+    pub fn subscribe_to_logged_out_accounts(&self) -> Subscriber<Vec<OwnedUserId>> {
+        self.inner.logged_out_accounts.subscribe()
+    }
+}
+
+impl Default for ClientManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientManagerInner {
+    /// Update the unread count of the given account, after a sync.
+    fn update_unread_count(&self, user_id: &UserId, client: &Client) {
+        let unread_count: u64 = client
+            .joined_rooms()
+            .iter()
+            .map(|room| room.unread_notification_counts().notification_count)
+            .sum();
+
+        let mut state = self.state.lock().unwrap();
+        let Some(account) = state.accounts.iter_mut().find(|account| account.user_id == user_id)
+        else {
+            return;
+        };
+
+        account.unread_count = unread_count;
+        self.update_total_unread_count(&state);
+    }
+
+    /// Mark the given account as logged out, after its sync stopped.
+    fn set_logged_out(&self, user_id: &UserId) {
+        let mut state = self.state.lock().unwrap();
+        let Some(account) = state.accounts.iter_mut().find(|account| account.user_id == user_id)
+        else {
+            return;
+        };
+
+        account.sync_task = None;
+        account.logged_out = true;
+        self.update_logged_out_accounts(&state);
+    }
+
+    fn update_total_unread_count(&self, state: &ManagerState) {
+        let total: u64 = state.accounts.iter().map(|account| account.unread_count).sum();
+        self.total_unread_count.set_if_not_eq(total);
+    }
+
+    fn update_logged_out_accounts(&self, state: &ManagerState) {
+        let logged_out = state
+            .accounts
+            .iter()
+            .filter(|account| account.logged_out)
+            .map(|account| account.user_id.clone())
+            .collect();
+        self.logged_out_accounts.set_if_not_eq(logged_out);
+    }
+}
+
+impl Drop for ClientManagerInner {
+    fn drop(&mut self) {
+        for account in &mut self.state.get_mut().unwrap().accounts {
+            account.stop_sync();
+        }
+    }
+}
+
+/// Spawn a task syncing the given account after the given delay.
+fn spawn_sync(
+    manager: &Arc<ClientManagerInner>,
+    account: &ManagedAccount,
+    delay: Duration,
+    background_sync_interval: Duration,
+) -> AbortHandle {
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+
+    let future = sync_account(
+        Arc::downgrade(manager),
+        account.user_id.clone(),
+        account.client.clone(),
+        account.priority,
+        delay,
+        background_sync_interval,
+    );
+
+    #[cfg(not(target_arch = "wasm32"))]
+    manager.runtime.spawn(Abortable::new(future, abort_registration));
+    #[cfg(target_arch = "wasm32")]
+    spawn(Abortable::new(future, abort_registration));
+
+    abort_handle
+}
+
+/// Sync the given account until the task is aborted, the manager is dropped,
+/// or the homeserver rejects the access token of the client.
+async fn sync_account(
+    manager: Weak<ClientManagerInner>,
+    user_id: OwnedUserId,
+    client: Client,
+    priority: SyncPriority,
+    delay: Duration,
+    background_sync_interval: Duration,
+) {
+    sleep(delay).await;
+
+    debug!(?user_id, ?priority, "Starting to sync");
+
+    let mut token = client.sync_token().await;
+    let mut last_sync_time: Option<Instant> = None;
+
+    loop {
+        let mut settings = SyncSettings::new();
+        if let Some(token) = token.clone() {
+            settings = settings.token(token);
+        }
+        if priority == SyncPriority::Background {
+            settings = settings.timeout(Duration::ZERO);
+        }
+
+        match client.sync_once(settings).await {
+            Ok(response) => {
+                token = Some(response.next_batch);
+
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                manager.update_unread_count(&user_id, &client);
+            }
+            Err(error)
+                if matches!(
+                    error.client_api_error_kind(),
+                    Some(ErrorKind::UnknownToken { .. } | ErrorKind::MissingToken)
+                ) =>
+            {
+                warn!(?user_id, "Stopping to sync after an authentication error: {error}");
+
+                if let Some(manager) = manager.upgrade() {
+                    manager.set_logged_out(&user_id);
+                }
+                return;
+            }
+            Err(error) => {
+                warn!(?user_id, "Sync failed: {error}");
+                sleep(SYNC_ERROR_DELAY).await;
+                continue;
+            }
+        }
+
+        match priority {
+            SyncPriority::Foreground => Client::delay_sync(&mut last_sync_time).await,
+            SyncPriority::Background => sleep(background_sync_interval).await,
+        }
+    }
+}
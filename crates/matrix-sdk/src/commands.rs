@@ -0,0 +1,588 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Text commands for bots, built on top of the [event handlers].
+//!
+//! A command is registered with [`Client::add_command()`], with a name like
+//! `!echo` and a handler that receives the typed arguments following the
+//! name in the message. Commands are recognized in text messages either when
+//! they start with the name of the command, or when they start with a mention
+//! of the bot, in which case the prefix of the name is optional:
+//!
+//! ```text
+//! !echo Hello
+//! @bot:example.org: echo Hello
+//! Bot: !echo Hello
+//! ```
+//!
+//! The messages sent before the first command was registered are ignored, so
+//! the bot doesn't reply to old commands when it syncs for the first time.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use matrix_sdk::Client;
+//! # async {
+//! # let client: Client = todo!();
+//! client
+//!     .add_command("!echo", |ctx, text: String| async move {
+//!         ctx.reply(text).await
+//!     })
+//!     .set_description("Repeat the given text");
+//!
+//! client
+//!     .add_command("!add", |ctx, (a, b): (i64, i64)| async move {
+//!         ctx.reply((a + b).to_string()).await
+//!     })
+//!     .set_description("Add two integers");
+//!
+//! client.add_command("!help", |ctx, (): ()| async move {
+//!     let help = ctx.room.client().command_help();
+//!     ctx.reply(help).await
+//! });
+//! # anyhow::Ok(()) };
+//! ```
+//!
+//! [event handlers]: crate::event_handler
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock},
+    time::Duration,
+};
+
+use matrix_sdk_base::{SendOutsideWasm, SyncOutsideWasm};
+use matrix_sdk_common::instant::Instant;
+use ruma::{
+    events::room::message::{
+        MessageType, OriginalSyncRoomMessageEvent, Relation, RoomMessageEventContent,
+    },
+    MilliSecondsSinceUnixEpoch, OwnedRoomId, RoomId,
+};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{
+    event_handler::{EventHandlerHandle, EventHandlerResult},
+    Client, Result, Room,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+type CommandFut = Pin<Box<dyn Future<Output = ()> + Send>>;
+#[cfg(target_arch = "wasm32")]
+type CommandFut = Pin<Box<dyn Future<Output = ()>>>;
+
+#[cfg(not(target_arch = "wasm32"))]
+type CommandFn = dyn Fn(CommandContext, &str) -> Result<CommandFut, CommandArgsError> + Send + Sync;
+#[cfg(target_arch = "wasm32")]
+type CommandFn = dyn Fn(CommandContext, &str) -> Result<CommandFut, CommandArgsError>;
+
+/// The arguments of a command, parsed from the text following its name.
+///
+/// This is implemented for:
+///
+/// - `()`, for commands without arguments,
+/// - `String`, for the whole text following the name,
+/// - `Vec<String>`, for the whitespace-separated words following the name,
+/// - tuples of up to 4 types implementing [`FromStr`], for exactly that many
+///   whitespace-separated arguments.
+pub trait CommandArgs: Sized {
+    /// Parse the arguments from the text following the name of the command.
+    fn parse(args: &str) -> Result<Self, CommandArgsError>;
+}
+
+/// An error when parsing the arguments of a command.
+#[derive(Debug, Error)]
+pub enum CommandArgsError {
+    /// The command received the wrong number of arguments.
+    #[error("expected {expected} argument(s), got {got}")]
+    WrongCount {
+        /// The number of arguments of the command.
+        expected: usize,
+        /// The number of arguments that were received.
+        got: usize,
+    },
+
+    /// An argument couldn't be parsed.
+    #[error("invalid argument `{arg}`: {message}")]
+    Invalid {
+        /// The argument that couldn't be parsed.
+        arg: String,
+        /// The parsing error.
+        message: String,
+    },
+}
+
+impl CommandArgs for () {
+    fn parse(args: &str) -> Result<Self, CommandArgsError> {
+        match args.split_whitespace().count() {
+            0 => Ok(()),
+            got => Err(CommandArgsError::WrongCount { expected: 0, got }),
+        }
+    }
+}
+
+impl CommandArgs for String {
+    fn parse(args: &str) -> Result<Self, CommandArgsError> {
+        Ok(args.trim().to_owned())
+    }
+}
+
+impl CommandArgs for Vec<String> {
+    fn parse(args: &str) -> Result<Self, CommandArgsError> {
+        Ok(args.split_whitespace().map(ToOwned::to_owned).collect())
+    }
+}
+
+fn parse_arg<T>(arg: &str) -> Result<T, CommandArgsError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    arg.parse().map_err(|error: T::Err| CommandArgsError::Invalid {
+        arg: arg.to_owned(),
+        message: error.to_string(),
+    })
+}
+
+macro_rules! impl_command_args_for_tuple {
+    ($($ty:ident),+) => {
+        impl<$($ty),+> CommandArgs for ($($ty,)+)
+        where
+            $($ty: FromStr, <$ty as FromStr>::Err: fmt::Display,)+
+        {
+            fn parse(args: &str) -> Result<Self, CommandArgsError> {
+                let args = args.split_whitespace().collect::<Vec<_>>();
+                let expected = [$(stringify!($ty)),+].len();
+
+                if args.len() != expected {
+                    return Err(CommandArgsError::WrongCount { expected, got: args.len() });
+                }
+
+                let mut args = args.into_iter();
+                Ok(($(parse_arg::<$ty>(args.next().unwrap())?,)+))
+            }
+        }
+    };
+}
+
+impl_command_args_for_tuple!(A);
+impl_command_args_for_tuple!(A, B);
+impl_command_args_for_tuple!(A, B, C);
+impl_command_args_for_tuple!(A, B, C, D);
+
+/// The context of an invocation of a command.
+#[derive(Clone, Debug)]
+pub struct CommandContext {
+    /// The room where the command was sent.
+    pub room: Room,
+
+    /// The message that invoked the command.
+    pub event: OriginalSyncRoomMessageEvent,
+}
+
+impl CommandContext {
+    /// Reply to the command with a notice in the room where it was sent.
+    pub async fn reply(&self, body: impl Into<String>) -> Result<()> {
+        self.room.send(RoomMessageEventContent::notice_plain(body)).await?;
+        Ok(())
+    }
+}
+
+/// A handle to a command registered with [`Client::add_command()`].
+#[derive(Debug)]
+pub struct CommandHandle {
+    client: Client,
+    name: String,
+}
+
+impl CommandHandle {
+    /// The name of the command.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set the description of the command, used in the
+    /// [help text][Client::command_help()].
+    pub fn set_description(&self, description: impl Into<String>) {
+        let mut commands = self.client.inner.commands.commands.write().unwrap();
+
+        if let Some(command) = commands.get_mut(&self.name) {
+            command.description = Some(description.into());
+        }
+    }
+
+    /// Unregister the command.
+    ///
+    /// Returns `false` if the command was already unregistered.
+    pub fn remove(self) -> bool {
+        self.client.remove_command(&self.name)
+    }
+}
+
+struct RegisteredCommand {
+    description: Option<String>,
+    handler: Arc<CommandFn>,
+}
+
+/// The commands registered on a client.
+#[derive(Default)]
+pub(crate) struct CommandRegistry {
+    commands: StdRwLock<BTreeMap<String, RegisteredCommand>>,
+    /// The minimum interval between two commands in the same room.
+    throttle: StdRwLock<Option<Duration>>,
+    last_invocations: StdMutex<BTreeMap<OwnedRoomId, Instant>>,
+    /// The event handler dispatching the messages to the commands, registered
+    /// as long as there are commands.
+    dispatcher: StdMutex<Option<EventHandlerHandle>>,
+    /// When the dispatcher was registered. Older messages are ignored.
+    started_at: StdRwLock<Option<MilliSecondsSinceUnixEpoch>>,
+}
+
+impl CommandRegistry {
+    /// Find the command with the given name.
+    ///
+    /// When the bot was mentioned, the prefix of the name of the command is
+    /// optional.
+    fn find(&self, name: &str, mentioned: bool) -> Option<(String, Arc<CommandFn>)> {
+        let commands = self.commands.read().unwrap();
+
+        commands
+            .get_key_value(name)
+            .or_else(|| {
+                mentioned
+                    .then(|| commands.iter().find(|(command, _)| bare_name(command) == name))
+                    .flatten()
+            })
+            .map(|(name, command)| (name.clone(), command.handler.clone()))
+    }
+
+    /// Whether a command can be invoked in the given room now, recording the
+    /// invocation if it can.
+    fn check_throttle(&self, room_id: &RoomId) -> bool {
+        let Some(interval) = *self.throttle.read().unwrap() else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let mut last_invocations = self.last_invocations.lock().unwrap();
+
+        // Forget the rooms where commands can be invoked again.
+        last_invocations.retain(|_, last| now.duration_since(*last) < interval);
+
+        if last_invocations.contains_key(room_id) {
+            return false;
+        }
+
+        last_invocations.insert(room_id.to_owned(), now);
+        true
+    }
+}
+
+/// The name of the command without its prefix, like `echo` for `!echo`.
+fn bare_name(name: &str) -> &str {
+    name.trim_start_matches(|c: char| !c.is_alphanumeric())
+}
+
+/// Strip the given mention of the bot from the start of the body of a message.
+fn strip_mention<'a>(body: &'a str, mention: &str) -> Option<&'a str> {
+    let rest = body.strip_prefix(mention)?;
+    let rest = rest.strip_prefix([':', ',']).unwrap_or(rest);
+
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim_start())
+}
+
+/// Split the body of a message into the name of a command and its arguments.
+///
+/// Returns whether the message started with one of the given mentions of the
+/// bot too.
+fn parse_invocation<'a>(body: &'a str, mentions: &[&str]) -> (&'a str, &'a str, bool) {
+    let body = body.trim_start();
+    let (body, mentioned) = match mentions.iter().find_map(|mention| strip_mention(body, mention)) {
+        Some(body) => (body, true),
+        None => (body, false),
+    };
+    let (name, args) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+
+    (name, args, mentioned)
+}
+
+/// Invoke the command in the given message, if any.
+async fn dispatch(client: Client, room: Room, event: OriginalSyncRoomMessageEvent) {
+    let Some(own_user_id) = client.user_id() else {
+        return;
+    };
+
+    if event.sender == own_user_id {
+        return;
+    }
+
+    let started_at = *client.inner.commands.started_at.read().unwrap();
+    if started_at.is_some_and(|started_at| event.origin_server_ts < started_at) {
+        return;
+    }
+
+    // Bots only react to text messages, not to notices, and not to edits.
+    let MessageType::Text(text) = &event.content.msgtype else {
+        return;
+    };
+    if matches!(event.content.relates_to, Some(Relation::Replacement(_))) {
+        return;
+    }
+
+    let display_name = match room.get_member_no_sync(own_user_id).await {
+        Ok(member) => member.and_then(|member| member.display_name().map(ToOwned::to_owned)),
+        Err(error) => {
+            warn!("Failed to load the own member of the room: {error}");
+            None
+        }
+    };
+    let mut mentions = vec![own_user_id.as_str()];
+    mentions.extend(display_name.as_deref());
+
+    let (name, args, mentioned) = parse_invocation(&text.body, &mentions);
+    let registry = &client.inner.commands;
+
+    let Some((name, handler)) = registry.find(name, mentioned) else {
+        return;
+    };
+
+    if !registry.check_throttle(room.room_id()) {
+        debug!(room_id = ?room.room_id(), name, "Ignoring throttled command");
+        return;
+    }
+
+    let context = CommandContext { room: room.clone(), event: event.clone() };
+
+    match handler(context.clone(), args) {
+        Ok(fut) => fut.await,
+        Err(error) => {
+            debug!(name, "Invalid command arguments: {error}");
+
+            if let Err(error) = context.reply(format!("{name}: {error}")).await {
+                warn!("Failed to reply to invalid command: {error}");
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Register a command of a bot.
+    ///
+    /// The command is invoked by text messages starting with its name, or
+    /// starting with a mention of the bot followed by its name, with or
+    /// without its prefix. The messages of the current user, notices, edits
+    /// and messages sent before the first command was registered are
+    /// ignored. See the [`commands`][crate::commands] module for
+    /// details.
+    ///
+    /// If the arguments of the command can't be parsed, the handler isn't
+    /// called and the bot replies with the parsing error.
+    ///
+    /// Registering a command with the name of an existing command replaces it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the command, including its prefix, like `!echo`.
+    ///
+    /// * `handler` - The function called when the command is invoked, with the
+    ///   context of the invocation and the parsed [`CommandArgs`]. Like for
+    ///   event handlers, it can return a `Result`, in which case errors are
+    ///   logged.
+    pub fn add_command<A, H, Fut>(&self, name: &str, handler: H) -> CommandHandle
+    where
+        A: CommandArgs,
+        H: Fn(CommandContext, A) -> Fut + SendOutsideWasm + SyncOutsideWasm + 'static,
+        Fut: Future + SendOutsideWasm + 'static,
+        Fut::Output: EventHandlerResult,
+    {
+        let command_name = name.to_owned();
+        let handler: Arc<CommandFn> = Arc::new(move |context: CommandContext, args: &str| {
+            let fut = handler(context, A::parse(args)?);
+            let command_name = command_name.clone();
+
+            Ok(Box::pin(async move {
+                fut.await.print_error(Some(&command_name));
+            }) as CommandFut)
+        });
+
+        let registry = &self.inner.commands;
+        registry
+            .commands
+            .write()
+            .unwrap()
+            .insert(name.to_owned(), RegisteredCommand { description: None, handler });
+
+        let mut dispatcher = registry.dispatcher.lock().unwrap();
+        if dispatcher.is_none() {
+            *registry.started_at.write().unwrap() = Some(MilliSecondsSinceUnixEpoch::now());
+            *dispatcher = Some(self.add_event_handler(
+                |event: OriginalSyncRoomMessageEvent, room: Room, client: Client| {
+                    dispatch(client, room, event)
+                },
+            ));
+        }
+
+        CommandHandle { client: self.clone(), name: name.to_owned() }
+    }
+
+    /// Unregister the command with the given name.
+    ///
+    /// Returns `false` if there was no command with this name.
+    pub fn remove_command(&self, name: &str) -> bool {
+        let registry = &self.inner.commands;
+        let mut commands = registry.commands.write().unwrap();
+        let removed = commands.remove(name).is_some();
+
+        if commands.is_empty() {
+            if let Some(handle) = registry.dispatcher.lock().unwrap().take() {
+                self.remove_event_handler(handle);
+            }
+        }
+
+        removed
+    }
+
+    /// Set the minimum interval between two commands in the same room.
+    ///
+    /// The commands sent before the end of the interval are ignored. By
+    /// default, commands are not throttled.
+    pub fn set_command_throttle(&self, interval: Option<Duration>) {
+        *self.inner.commands.throttle.write().unwrap() = interval;
+
+        if interval.is_none() {
+            self.inner.commands.last_invocations.lock().unwrap().clear();
+        }
+    }
+
+    /// Generate the help text of the registered commands.
+    ///
+    /// This lists the names of the commands in alphabetical order, one per
+    /// line, followed by their description, if any.
+    pub fn command_help(&self) -> String {
+        let commands = self.inner.commands.commands.read().unwrap();
+
+        commands
+            .iter()
+            .map(|(name, command)| match &command.description {
+                Some(description) => format!("{name}: {description}"),
+                None => name.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use assert_matches::assert_matches;
+    use matrix_sdk_test::{async_test, JoinedRoomBuilder, SyncResponseBuilder, TimelineTestEvent};
+    use ruma::MilliSecondsSinceUnixEpoch;
+    use serde_json::json;
+
+    use super::{parse_invocation, CommandArgs, CommandArgsError};
+    use crate::test_utils::logged_in_client;
+
+    #[test]
+    fn invocations() {
+        let mentions = ["@example:localhost", "Bot"];
+
+        assert_eq!(
+            parse_invocation("!echo  Hello there", &mentions),
+            ("!echo", " Hello there", false)
+        );
+        assert_eq!(parse_invocation("!help", &mentions), ("!help", "", false));
+        assert_eq!(parse_invocation("Bot: echo Hello", &mentions), ("echo", "Hello", true));
+        assert_eq!(parse_invocation("@example:localhost, !echo", &mentions), ("!echo", "", true));
+        assert_eq!(parse_invocation("Botanist: echo", &mentions), ("Botanist:", "echo", false));
+    }
+
+    #[test]
+    fn args() {
+        assert_eq!(<String as CommandArgs>::parse(" Hello there ").unwrap(), "Hello there");
+        assert_eq!(<Vec<String> as CommandArgs>::parse("a  b c").unwrap(), ["a", "b", "c"]);
+        <() as CommandArgs>::parse("  ").unwrap();
+        assert_eq!(
+            <(i64, String) as CommandArgs>::parse("4 four").unwrap(),
+            (4, "four".to_owned())
+        );
+
+        assert_matches!(
+            <(i64, i64) as CommandArgs>::parse("1"),
+            Err(CommandArgsError::WrongCount { expected: 2, got: 1 })
+        );
+        assert_matches!(
+            <(i64,) as CommandArgs>::parse("one"),
+            Err(CommandArgsError::Invalid { arg, .. }) => {
+                assert_eq!(arg, "one");
+            }
+        );
+    }
+
+    #[async_test]
+    async fn dispatch() {
+        let client = logged_in_client(None).await;
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let handle = client.add_command("!add", {
+            let received = received.clone();
+            move |_ctx, (a, b): (i64, i64)| {
+                let received = received.clone();
+                async move { received.lock().unwrap().push(a + b) }
+            }
+        });
+        handle.set_description("Add two integers");
+        assert_eq!(client.command_help(), "!add: Add two integers");
+
+        let now = MilliSecondsSinceUnixEpoch::now();
+        let message = |event_id: &str, body: &str| {
+            TimelineTestEvent::Custom(json!({
+                "content": { "body": body, "msgtype": "m.text" },
+                "event_id": event_id,
+                "origin_server_ts": now,
+                "sender": "@alice:localhost",
+                "type": "m.room.message",
+            }))
+        };
+        // Commands sent before the bot started are ignored.
+        let stale_message = TimelineTestEvent::Custom(json!({
+            "content": { "body": "!add 5 5", "msgtype": "m.text" },
+            "event_id": "$0",
+            "origin_server_ts": 152037280,
+            "sender": "@alice:localhost",
+            "type": "m.room.message",
+        }));
+        let response = SyncResponseBuilder::default()
+            .add_joined_room(
+                JoinedRoomBuilder::default()
+                    .add_timeline_event(stale_message)
+                    .add_timeline_event(message("$1", "!add 1 2"))
+                    .add_timeline_event(message("$2", "@example:localhost: add 3 4"))
+                    .add_timeline_event(message("$3", "!sub 5 6")),
+            )
+            .build_sync_response();
+        client.process_sync(response).await.unwrap();
+
+        assert_eq!(*received.lock().unwrap(), [3, 7]);
+
+        assert!(handle.remove());
+        assert!(client.command_help().is_empty());
+    }
+}
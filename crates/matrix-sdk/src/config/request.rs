@@ -21,6 +21,15 @@ use matrix_sdk_common::debug::DebugStructExt;
 
 use crate::http_client::DEFAULT_REQUEST_TIMEOUT;
 
+/// The default delay before the first retry of a request.
+const DEFAULT_RETRY_INITIAL_DELAY: Duration = Duration::from_millis(500);
+
+/// The default maximum delay between two retries of a request.
+const DEFAULT_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// The default randomization factor applied to the delays between retries.
+const DEFAULT_RETRY_JITTER: f64 = 0.5;
+
 /// Configuration for requests the `Client` makes.
 ///
 /// This sets how often and for how long a request should be repeated. As well
@@ -28,6 +37,11 @@ use crate::http_client::DEFAULT_REQUEST_TIMEOUT;
 ///
 /// By default requests are retried indefinitely and use no timeout.
 ///
+/// Retries use an exponential backoff, starting at 500ms and growing up to a
+/// delay of 60s between two attempts, each delay being randomized by ±50%.
+/// When the homeserver rate-limits a request, the request is retried after the
+/// delay the homeserver asked for.
+///
 /// # Examples
 ///
 /// ```
@@ -38,12 +52,23 @@ use crate::http_client::DEFAULT_REQUEST_TIMEOUT;
 /// let request_config = RequestConfig::new()
 ///     .disable_retry()
 ///     .timeout(Duration::from_secs(30));
+///
+/// // This retries requests 5 times at most, waiting between 1s and 10s
+/// // between two attempts, without any randomization
+/// let request_config = RequestConfig::new()
+///     .retry_limit(5)
+///     .retry_backoff(Duration::from_secs(1), Duration::from_secs(10))
+///     .retry_jitter(0.0);
 /// ```
 #[derive(Copy, Clone)]
 pub struct RequestConfig {
     pub(crate) timeout: Duration,
     pub(crate) retry_limit: Option<u64>,
     pub(crate) retry_timeout: Option<Duration>,
+    pub(crate) retry_initial_delay: Duration,
+    pub(crate) retry_max_delay: Duration,
+    pub(crate) retry_jitter: f64,
+    pub(crate) retry_on_rate_limit: bool,
     pub(crate) force_auth: bool,
     pub(crate) assert_identity: bool,
 }
@@ -51,12 +76,29 @@ pub struct RequestConfig {
 #[cfg(not(tarpaulin_include))]
 impl Debug for RequestConfig {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Self { timeout, retry_limit, retry_timeout, force_auth, assert_identity } = self;
+        let Self {
+            timeout,
+            retry_limit,
+            retry_timeout,
+            retry_initial_delay,
+            retry_max_delay,
+            retry_jitter,
+            retry_on_rate_limit,
+            force_auth,
+            assert_identity,
+        } = self;
 
         let mut res = fmt.debug_struct("RequestConfig");
         res.field("timeout", timeout)
             .maybe_field("retry_limit", retry_limit)
-            .maybe_field("retry_timeout", retry_timeout);
+            .maybe_field("retry_timeout", retry_timeout)
+            .field("retry_initial_delay", retry_initial_delay)
+            .field("retry_max_delay", retry_max_delay)
+            .field("retry_jitter", retry_jitter);
+
+        if !*retry_on_rate_limit {
+            res.field("retry_on_rate_limit", &false);
+        }
 
         if *force_auth {
             res.field("force_auth", &true);
@@ -75,6 +117,10 @@ impl Default for RequestConfig {
             timeout: DEFAULT_REQUEST_TIMEOUT,
             retry_limit: Default::default(),
             retry_timeout: Default::default(),
+            retry_initial_delay: DEFAULT_RETRY_INITIAL_DELAY,
+            retry_max_delay: DEFAULT_RETRY_MAX_DELAY,
+            retry_jitter: DEFAULT_RETRY_JITTER,
+            retry_on_rate_limit: true,
             force_auth: false,
             assert_identity: false,
         }
@@ -125,6 +171,44 @@ impl RequestConfig {
         self
     }
 
+    /// Set the delays of the exponential backoff between retries.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial_delay` - The delay before the first retry. The default is
+    ///   500ms.
+    ///
+    /// * `max_delay` - The maximum delay between two retries. The default is
+    ///   60s.
+    #[must_use]
+    pub fn retry_backoff(mut self, initial_delay: Duration, max_delay: Duration) -> Self {
+        self.retry_initial_delay = initial_delay;
+        self.retry_max_delay = max_delay.max(initial_delay);
+        self
+    }
+
+    /// Set the randomization factor applied to the delays between retries.
+    ///
+    /// With a factor of `0.5`, which is the default, a delay of 2s becomes a
+    /// random delay between 1s and 3s. Use `0.0` to disable the
+    /// randomization. The factor is clamped between `0.0` and `1.0`.
+    #[must_use]
+    pub fn retry_jitter(mut self, factor: f64) -> Self {
+        self.retry_jitter = factor.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Whether a request that was rate-limited by the homeserver should be
+    /// retried, after the delay the homeserver asked for. The default is
+    /// `true`.
+    ///
+    /// When this is `false`, the rate-limiting error is returned right away.
+    #[must_use]
+    pub fn retry_on_rate_limit(mut self, retry: bool) -> Self {
+        self.retry_on_rate_limit = retry;
+        self
+    }
+
     /// Force sending authorization even if the endpoint does not require it.
     /// Default is only sending authorization if it is required.
     #[must_use]
@@ -166,4 +250,23 @@ mod tests {
         let cfg = RequestConfig::short_retry();
         assert_eq!(cfg.retry_limit, Some(3));
     }
+
+    #[test]
+    fn testing_retry_backoff_settings() {
+        let cfg = RequestConfig::new();
+        assert_eq!(cfg.retry_initial_delay, Duration::from_millis(500));
+        assert_eq!(cfg.retry_max_delay, Duration::from_secs(60));
+        assert_eq!(cfg.retry_jitter, 0.5);
+        assert!(cfg.retry_on_rate_limit);
+
+        let cfg = RequestConfig::new()
+            .retry_backoff(Duration::from_secs(2), Duration::from_secs(1))
+            .retry_jitter(3.0)
+            .retry_on_rate_limit(false);
+        assert_eq!(cfg.retry_initial_delay, Duration::from_secs(2));
+        // The maximum delay can't be lower than the initial delay.
+        assert_eq!(cfg.retry_max_delay, Duration::from_secs(2));
+        assert_eq!(cfg.retry_jitter, 1.0);
+        assert!(!cfg.retry_on_rate_limit);
+    }
 }
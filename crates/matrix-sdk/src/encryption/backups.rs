@@ -0,0 +1,530 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for the server-side [room key backups].
+//!
+//! Once a trusted backup is enabled with [`Backups::enable_from_server()`],
+//! the room keys the client receives or creates are uploaded to the backup on
+//! a background task, after each sync.
+//!
+//! [room key backups]: https://spec.matrix.org/v1.8/client-server-api/#server-side-key-backups
+
+use std::time::Duration;
+
+use eyeball::SharedObservable;
+use futures_core::Stream;
+use matrix_sdk_base::crypto::{
+    backups::MegolmV1BackupKey,
+    olm::{BackedUpRoomKey, ExportedRoomKey},
+    types::RoomKeyBackupInfo,
+};
+use matrix_sdk_common::{executor::spawn, sleep::sleep};
+use ruma::{
+    api::client::{
+        backup::{get_backup_keys_for_session, get_latest_backup_info},
+        error::ErrorKind,
+    },
+    RoomId,
+};
+use tokio::sync::Mutex;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{Client, Error, Result};
+
+/// The maximum number of times the upload of a batch of room keys is retried,
+/// before giving up until the next sync.
+const MAXIMUM_UPLOAD_RETRIES: u32 = 3;
+
+/// The delay before the first retry of a failed upload, doubled for each
+/// following retry.
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The state of the room key backup of the client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum BackupState {
+    /// No backup is enabled, or the client doesn't know yet whether there is
+    /// one.
+    #[default]
+    Unknown,
+    /// The backup is being enabled.
+    Enabling,
+    /// The backup is enabled and all the known room keys are backed up.
+    Enabled,
+    /// Room keys are being uploaded to the backup.
+    Uploading {
+        /// The number of room keys that still need to be backed up.
+        pending: usize,
+    },
+    /// The backup couldn't be enabled, or room keys couldn't be uploaded to
+    /// it.
+    Error,
+}
+
+/// The backup-related state of the client, shared between all the
+/// [`Backups`] instances.
+#[derive(Debug, Default)]
+pub(crate) struct BackupClientState {
+    /// The current state of the backup.
+    state: SharedObservable<BackupState>,
+    /// A lock held by the task uploading room keys, so only one of them runs
+    /// at a time.
+    upload_lock: Mutex<()>,
+}
+
+/// A high-level API to manage the server-side room key backup.
+///
+/// To get this, use [`Encryption::backups()`].
+///
+/// [`Encryption::backups()`]: super::Encryption::backups
+#[derive(Debug, Clone)]
+pub struct Backups {
+    client: Client,
+}
+
+impl Backups {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    fn shared_state(&self) -> &BackupClientState {
+        &self.client.inner.backup_state
+    }
+
+    /// Get the current state of the backup.
+    pub fn state(&self) -> BackupState {
+        self.shared_state().state.get()
+    }
+
+    /// Get a stream of updates to the state of the backup.
+    ///
+    /// The first part of the returned tuple is the current state, and the
+    /// second part is the `Stream` to receive updates.
+    pub fn state_stream(&self) -> (BackupState, impl Stream<Item = BackupState>) {
+        let state = &self.shared_state().state;
+        (state.get(), state.subscribe())
+    }
+
+    /// Whether room keys are being backed up.
+    pub async fn are_enabled(&self) -> bool {
+        let olm = self.client.olm_machine().await;
+        match olm.as_ref() {
+            Some(olm) => olm.backup_machine().enabled().await,
+            None => false,
+        }
+    }
+
+    /// Whether room keys can't be downloaded from the backup because its
+    /// decryption key isn't known.
+    ///
+    /// This is the case when a backup is enabled, but its decryption key
+    /// wasn't retrieved, for example with
+    /// [`Encryption::recover_secrets()`].
+    ///
+    /// [`Encryption::recover_secrets()`]: super::Encryption::recover_secrets
+    pub async fn is_missing_decryption_key(&self) -> Result<bool> {
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+        let backup_keys = olm.backup_machine().get_backup_keys().await?;
+
+        Ok(backup_keys.backup_version.is_some() && backup_keys.decryption_key.is_none())
+    }
+
+    /// Enable the backup that currently exists on the homeserver, if it is
+    /// trusted.
+    ///
+    /// A backup is trusted if it was signed by our own device, our own
+    /// verified cross-signing identity, or one of our verified devices.
+    ///
+    /// Returns `true` if the backup was enabled. Room keys are then backed up
+    /// automatically, after each sync.
+    #[instrument(skip(self))]
+    pub async fn enable_from_server(&self) -> Result<bool> {
+        self.set_state(BackupState::Enabling);
+
+        match self.enable_from_server_helper().await {
+            Ok(enabled) => {
+                if enabled {
+                    self.set_state(BackupState::Enabled);
+                    self.maybe_trigger_backup();
+                } else {
+                    self.set_state(BackupState::Unknown);
+                }
+
+                Ok(enabled)
+            }
+            Err(error) => {
+                self.set_state(BackupState::Error);
+                Err(error)
+            }
+        }
+    }
+
+    async fn enable_from_server_helper(&self) -> Result<bool> {
+        let request = get_latest_backup_info::v3::Request::new();
+
+        let response = match self.client.send(request, None).await {
+            Ok(response) => response,
+            Err(error) if matches!(error.client_api_error_kind(), Some(ErrorKind::NotFound)) => {
+                info!("No backup exists on the homeserver");
+                return Ok(false);
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let backup_info: RoomKeyBackupInfo = response.algorithm.deserialize_as()?;
+
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+        let backup_machine = olm.backup_machine();
+
+        let RoomKeyBackupInfo::MegolmBackupV1Curve25519AesSha2(auth_data) = &backup_info else {
+            warn!("The backup uses an unsupported algorithm");
+            return Ok(false);
+        };
+        let public_key = auth_data.public_key.to_base64();
+
+        if !backup_machine.verify_backup(backup_info, false).await?.trusted() {
+            warn!(version = %response.version, "The backup isn't trusted, not enabling it");
+            return Ok(false);
+        }
+
+        let backup_key = MegolmV1BackupKey::from_base64(&public_key)
+            .expect("The public key of the backup was already decoded");
+        backup_key.set_version(response.version.clone());
+        backup_machine.enable_backup_v1(backup_key).await?;
+        // Remember the version, to be able to download room keys from it.
+        backup_machine.save_decryption_key(None, Some(response.version.clone())).await?;
+
+        info!(version = %response.version, "Enabled the backup");
+
+        Ok(true)
+    }
+
+    /// Disable the backup.
+    ///
+    /// Room keys aren't backed up anymore, and the backup state of the room
+    /// keys is reset.
+    pub async fn disable(&self) -> Result<()> {
+        {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+            olm.backup_machine().disable_backup().await?;
+        }
+
+        self.set_state(BackupState::Unknown);
+
+        Ok(())
+    }
+
+    /// Download the room key with the given session ID from the backup, and
+    /// import it into the crypto store.
+    ///
+    /// Returns `false` if the backup decryption key or the backup version
+    /// aren't known, or if the room key isn't in the backup.
+    #[instrument(skip(self))]
+    pub(crate) async fn download_room_key(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<bool> {
+        let backup_keys = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+            olm.backup_machine().get_backup_keys().await?
+        };
+
+        let (Some(decryption_key), Some(version)) =
+            (backup_keys.decryption_key, backup_keys.backup_version)
+        else {
+            debug!("The backup decryption key or version isn't known, can't download room key");
+            return Ok(false);
+        };
+
+        let request = get_backup_keys_for_session::v3::Request::new(
+            version,
+            room_id.to_owned(),
+            session_id.to_owned(),
+        );
+
+        let response = match self.client.send(request, None).await {
+            Ok(response) => response,
+            Err(error) if matches!(error.client_api_error_kind(), Some(ErrorKind::NotFound)) => {
+                debug!("The room key isn't in the backup");
+                return Ok(false);
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let session_data = response.key_data.deserialize()?.session_data;
+        let decrypted = decryption_key.decrypt_v1(
+            &session_data.ephemeral.encode(),
+            &session_data.mac.encode(),
+            &session_data.ciphertext.encode(),
+        )?;
+        let room_key: BackedUpRoomKey = serde_json::from_str(&decrypted)?;
+        let room_key = ExportedRoomKey::from_backed_up_room_key(room_id.to_owned(), room_key);
+
+        if room_key.session_id != session_id {
+            warn!(
+                backed_up_session_id = %room_key.session_id,
+                "The room key in the backup is for another session, ignoring it"
+            );
+            return Ok(false);
+        }
+
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+        let result = olm.import_room_keys(vec![room_key], true, |_, _| {}).await?;
+
+        info!(imported_count = result.imported_count, "Downloaded room key from the backup");
+
+        Ok(result.imported_count > 0)
+    }
+
+    /// Start uploading the room keys that aren't backed up yet on a background
+    /// task, if the backup is enabled.
+    pub(crate) fn maybe_trigger_backup(&self) {
+        if matches!(self.state(), BackupState::Unknown | BackupState::Enabling) {
+            return;
+        }
+
+        let backups = self.clone();
+
+        spawn(async move {
+            // Another task is already uploading room keys, it will pick the new
+            // ones up too.
+            let Ok(_guard) = backups.shared_state().upload_lock.try_lock() else {
+                return;
+            };
+
+            backups.backup_room_keys_with_retry().await;
+        });
+    }
+
+    async fn backup_room_keys_with_retry(&self) {
+        let mut retries = 0;
+        let mut backoff = INITIAL_RETRY_BACKOFF;
+
+        loop {
+            match self.backup_room_keys().await {
+                Ok(()) => {
+                    self.set_state(BackupState::Enabled);
+                    break;
+                }
+                Err(error) if retries < MAXIMUM_UPLOAD_RETRIES => {
+                    warn!("Failed to back up room keys, retrying in {backoff:?}: {error}");
+
+                    retries += 1;
+                    sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(error) => {
+                    warn!("Failed to back up room keys, giving up: {error}");
+                    self.set_state(BackupState::Error);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Upload the room keys that aren't backed up yet, in batches.
+    async fn backup_room_keys(&self) -> Result<()> {
+        loop {
+            let request = {
+                let olm = self.client.olm_machine().await;
+                let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+                let backup_machine = olm.backup_machine();
+
+                if !backup_machine.enabled().await {
+                    return Ok(());
+                }
+
+                let counts = backup_machine.room_key_counts().await?;
+                let pending = counts.total.saturating_sub(counts.backed_up);
+                self.set_state(BackupState::Uploading { pending });
+
+                backup_machine.backup().await?
+            };
+
+            let Some((request_id, request)) = request else {
+                return Ok(());
+            };
+
+            let response = self.client.send_backup_request(&request).await?;
+            self.client.mark_request_as_sent(&request_id, &response).await?;
+        }
+    }
+
+    fn set_state(&self, state: BackupState) {
+        self.shared_state().state.set_if_not_eq(state);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use assert_matches::assert_matches;
+    use matrix_sdk_base::crypto::{olm::ExportedRoomKey, store::BackupDecryptionKey};
+    use matrix_sdk_test::async_test;
+    use ruma::room_id;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::BackupState;
+    use crate::test_utils::logged_in_client;
+
+    async fn mock_backup_version(server: &MockServer, auth_data: serde_json::Value) {
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/.*/room_keys/version"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "algorithm": "m.megolm_backup.v1.curve25519-aes-sha2",
+                "auth_data": auth_data,
+                "count": 0,
+                "etag": "abcdef",
+                "version": "1",
+            })))
+            .mount(server)
+            .await;
+    }
+
+    #[async_test]
+    async fn test_enable_without_backup() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/.*/room_keys/version"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "No current backup version",
+            })))
+            .mount(&server)
+            .await;
+
+        let backups = client.encryption().backups();
+        assert!(!backups.enable_from_server().await.unwrap());
+        assert_eq!(backups.state(), BackupState::Unknown);
+        assert!(!backups.are_enabled().await);
+    }
+
+    #[async_test]
+    async fn test_enable_untrusted_backup() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let public_key = BackupDecryptionKey::new().unwrap().megolm_v1_public_key().to_base64();
+        mock_backup_version(&server, json!({ "public_key": public_key })).await;
+
+        let backups = client.encryption().backups();
+        assert!(!backups.enable_from_server().await.unwrap());
+        assert_eq!(backups.state(), BackupState::Unknown);
+        assert!(!backups.are_enabled().await);
+    }
+
+    #[async_test]
+    async fn test_enable_trusted_backup() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let public_key = BackupDecryptionKey::new().unwrap().megolm_v1_public_key().to_base64();
+        let auth_data = json!({ "public_key": public_key });
+
+        // Sign the backup with our own device, so it's trusted.
+        let signatures = {
+            let olm = client.olm_machine().await;
+            olm.as_ref().unwrap().sign(&auth_data.to_string()).await
+        };
+        let mut signed_auth_data = auth_data;
+        signed_auth_data["signatures"] = serde_json::to_value(signatures).unwrap();
+
+        mock_backup_version(&server, signed_auth_data).await;
+
+        let backups = client.encryption().backups();
+        let (initial_state, _) = backups.state_stream();
+        assert_eq!(initial_state, BackupState::Unknown);
+
+        assert!(backups.enable_from_server().await.unwrap());
+        assert!(backups.are_enabled().await);
+        assert_matches!(backups.state(), BackupState::Enabled | BackupState::Uploading { .. });
+    }
+
+    #[async_test]
+    async fn test_download_room_key() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let room_id = room_id!("!test:localhost");
+
+        let decryption_key =
+            BackupDecryptionKey::from_base64("Ha9cklU/9NqFo9WKdVfGzmqUL/9wlkdxfEitbSIPVXw")
+                .unwrap();
+        let key_data = json!({
+            "first_message_index": 0,
+            "forwarded_count": 0,
+            "is_verified": false,
+            "session_data": {
+                "ephemeral": "HlLi76oV6wxHz3PCqE/bxJi6yF1HnYz5Dq3T+d/KpRw",
+                "ciphertext": "MuM8E3Yc6TSAvhVGb77rQ++jE6p9dRepx63/3YPD2wACKAppkZHeFrnTH6wJ/HSyrmzo\
+                               7HfwqVl6tKNpfooSTHqUf6x1LHz+h4B/Id5ITO1WYt16AaI40LOnZqTkJZCfSPuE2oxa\
+                               lwEHnCS3biWybutcnrBFPR3LMtaeHvvkb+k3ny9l5ZpsU9G7vCm3XoeYkWfLekWXvDhb\
+                               qWrylXD0+CNUuaQJ/S527TzLd4XKctqVjjO/cCH7q+9utt9WJAfK8LGaWT/mZ3AeWjf5\
+                               kiqOpKKf5Cn4n5SSil5p/pvGYmjnURvZSEeQIzHgvunIBEPtzK/MYEPOXe/P5achNGlC\
+                               x+5N19Ftyp9TFaTFlTWCTi0mpD7ePfCNISrwpozAz9HZc0OhA8+1aSc7rhYFIeAYXFU3\
+                               26NuFIFHI5pvpSxjzPQlOA+mavIKmiRAtjlLw11IVKTxgrdT4N8lXeMr4ndCSmvIkAzF\
+                               Mo1uZA4fzjiAdQJE4/2WeXFNNpvdfoYmX8Zl9CAYjpSO5HvpwkAbk4/iLEH3hDfCVUwD\
+                               fMh05PdGLnxeRpiEFWSMSsJNp+OWAA+5JsF41BoRGrxoXXT+VKqlUDONd+O296Psu8Q+\
+                               d8/S618",
+                "mac": "GtMrurhDTwo"
+            }
+        });
+
+        // Compute the ID of the session that is in the backup.
+        let session_data = &key_data["session_data"];
+        let decrypted = decryption_key
+            .decrypt_v1(
+                session_data["ephemeral"].as_str().unwrap(),
+                session_data["mac"].as_str().unwrap(),
+                session_data["ciphertext"].as_str().unwrap(),
+            )
+            .unwrap();
+        let room_key = ExportedRoomKey::from_backed_up_room_key(
+            room_id.to_owned(),
+            serde_json::from_str(&decrypted).unwrap(),
+        );
+        let session_id = room_key.session_id;
+
+        Mock::given(method("GET"))
+            .and(path_regex(format!(r"^/_matrix/client/.*/room_keys/keys/.*/{session_id}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(key_data))
+            .mount(&server)
+            .await;
+
+        // The room key can't be downloaded without the backup decryption key.
+        let encryption = client.encryption();
+        assert!(!encryption.backup_download_session(room_id, &session_id).await.unwrap());
+
+        {
+            let olm = client.olm_machine().await;
+            olm.as_ref()
+                .unwrap()
+                .backup_machine()
+                .save_decryption_key(Some(decryption_key), Some("1".to_owned()))
+                .await
+                .unwrap();
+        }
+
+        assert!(encryption.backup_download_session(room_id, &session_id).await.unwrap());
+    }
+}
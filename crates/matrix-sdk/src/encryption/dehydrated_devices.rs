@@ -0,0 +1,175 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for [dehydrated devices].
+//!
+//! A dehydrated device is a device that lives on the homeserver, with its
+//! private keys encrypted with a key only known by the user. Other users can
+//! send room keys to it while the user has no active device, and they can be
+//! recovered by rehydrating the device on the next login.
+//!
+//! [dehydrated devices]: https://github.com/matrix-org/matrix-spec-proposals/pull/3814
+
+use ruma::{
+    api::client::{
+        dehydrated_device::{get_dehydrated_device, get_events},
+        error::ErrorKind,
+    },
+    assign, OwnedDeviceId,
+};
+use tracing::{debug, info, instrument};
+
+use crate::{Client, Error, Result};
+
+/// The display name of the dehydrated devices we create.
+const DEHYDRATED_DEVICE_DISPLAY_NAME: &str = "Dehydrated device";
+
+/// A high-level API to manage the dehydrated device of the client owner's
+/// account.
+///
+/// To get this, use [`Encryption::dehydrated_devices()`].
+///
+/// [`Encryption::dehydrated_devices()`]: super::Encryption::dehydrated_devices
+#[derive(Debug, Clone)]
+pub struct DehydratedDevices {
+    client: Client,
+}
+
+impl DehydratedDevices {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Create a new dehydrated device and upload it to the homeserver.
+    ///
+    /// This replaces the existing dehydrated device, if any, so the room keys
+    /// it has received should be recovered with [`Self::rehydrate()`]
+    /// beforehand.
+    ///
+    /// The private cross-signing keys need to be known, since the dehydrated
+    /// device is signed with the self-signing key.
+    ///
+    /// Returns the ID of the new dehydrated device.
+    ///
+    /// # Arguments
+    ///
+    /// * `pickle_key` - The key used to encrypt the private keys of the device.
+    ///   The same key needs to be given to [`Self::rehydrate()`].
+    #[instrument(skip_all)]
+    pub async fn create(&self, pickle_key: &[u8; 32]) -> Result<OwnedDeviceId> {
+        let request = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            olm.dehydrated_devices()
+                .create()
+                .keys_for_upload(DEHYDRATED_DEVICE_DISPLAY_NAME.to_owned(), pickle_key)
+                .await?
+        };
+
+        let response = self.client.send(request, None).await?;
+        info!(device_id = ?response.device_id, "Uploaded a new dehydrated device");
+
+        Ok(response.device_id)
+    }
+
+    /// Rehydrate the dehydrated device of the account, and import the room
+    /// keys it has received.
+    ///
+    /// Once this is done, a new dehydrated device should be created with
+    /// [`Self::create()`], so room keys aren't sent to a device that has
+    /// already been rehydrated.
+    ///
+    /// Returns the number of room keys that were imported, or `None` if the
+    /// account doesn't have a dehydrated device.
+    ///
+    /// # Arguments
+    ///
+    /// * `pickle_key` - The key that was used to create the dehydrated device.
+    #[instrument(skip_all)]
+    pub async fn rehydrate(&self, pickle_key: &[u8; 32]) -> Result<Option<usize>> {
+        let response =
+            match self.client.send(get_dehydrated_device::unstable::Request::new(), None).await {
+                Ok(response) => response,
+                Err(error)
+                    if matches!(error.client_api_error_kind(), Some(ErrorKind::NotFound)) =>
+                {
+                    debug!("The account doesn't have a dehydrated device");
+                    return Ok(None);
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+        let device_id = response.device_id;
+
+        let rehydrated = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            olm.dehydrated_devices().rehydrate(pickle_key, &device_id, response.device_data).await?
+        };
+
+        let mut next_batch = None;
+        let mut room_key_count = 0;
+
+        loop {
+            let request = assign!(get_events::unstable::Request::new(device_id.clone()), {
+                next_batch: next_batch.take(),
+            });
+            let response = self.client.send(request, None).await?;
+
+            if response.events.is_empty() {
+                break;
+            }
+
+            next_batch = response.next_batch;
+            room_key_count += rehydrated.receive_events(response.events).await?.len();
+        }
+
+        info!(?device_id, room_key_count, "Rehydrated the dehydrated device");
+
+        Ok(Some(room_key_count))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use matrix_sdk_test::async_test;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::test_utils::logged_in_client;
+
+    #[async_test]
+    async fn test_rehydrate_without_dehydrated_device() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/.*/dehydrated_device$"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "No dehydrated device found",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let rehydrated = client.encryption().dehydrated_devices().rehydrate(&[0u8; 32]).await;
+        assert_eq!(rehydrated.unwrap(), None);
+    }
+}
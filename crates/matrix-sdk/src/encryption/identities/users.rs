@@ -16,7 +16,7 @@ use std::sync::Arc;
 
 use matrix_sdk_base::{
     crypto::{
-        types::MasterPubkey, OwnUserIdentity as InnerOwnUserIdentity,
+        types::MasterPubkey, CryptoStoreError, OwnUserIdentity as InnerOwnUserIdentity,
         UserIdentity as InnerUserIdentity,
     },
     RoomMemberships,
@@ -345,6 +345,59 @@ impl UserIdentity {
         }
     }
 
+    /// Is this user identity in a verification violation?
+    ///
+    /// This is the case when the identity of the user was verified before,
+    /// but the user reset their cross-signing identity since then and the new
+    /// identity isn't verified. Our own identity never is in a verification
+    /// violation.
+    ///
+    /// The violation can be resolved by verifying the new identity, or by
+    /// calling [`UserIdentity::withdraw_verification()`].
+    pub fn has_verification_violation(&self) -> bool {
+        match &self.inner {
+            UserIdentities::Own(_) => false,
+            UserIdentities::Other(i) => i.inner.has_verification_violation(),
+        }
+    }
+
+    /// Did the master key of this user identity change since it was pinned?
+    ///
+    /// The first master key we see for a user is pinned automatically. When
+    /// it changes, the new one can be accepted with
+    /// [`UserIdentity::pin_current_master_key()`]. Our own identity is never
+    /// in a pin violation.
+    pub fn has_pin_violation(&self) -> bool {
+        match &self.inner {
+            UserIdentities::Own(_) => false,
+            UserIdentities::Other(i) => i.inner.has_pin_violation(),
+        }
+    }
+
+    /// Accept the current master key of this user identity.
+    ///
+    /// This resolves a pin violation, see
+    /// [`UserIdentity::has_pin_violation()`]. This does nothing for our own
+    /// identity.
+    pub async fn pin_current_master_key(&self) -> Result<(), CryptoStoreError> {
+        match &self.inner {
+            UserIdentities::Own(_) => Ok(()),
+            UserIdentities::Other(i) => i.inner.pin_current_master_key().await,
+        }
+    }
+
+    /// Forget that this user identity was verified before.
+    ///
+    /// This resolves a verification violation, see
+    /// [`UserIdentity::has_verification_violation()`]. This does nothing for
+    /// our own identity.
+    pub async fn withdraw_verification(&self) -> Result<(), CryptoStoreError> {
+        match &self.inner {
+            UserIdentities::Own(_) => Ok(()),
+            UserIdentities::Other(i) => i.inner.withdraw_verification().await,
+        }
+    }
+
     /// Get the public part of the Master key of this user identity.
     ///
     /// The public part of the Master key is usually used to uniquely identify
@@ -26,7 +26,7 @@ use std::{
 use eyeball::SharedObservable;
 use futures_util::{
     future::try_join,
-    stream::{self, StreamExt},
+    stream::{self, Stream, StreamExt},
 };
 use matrix_sdk_base::crypto::{
     store::locks::CryptoStoreLockGuard, OlmMachine, OutgoingRequest, RoomMessageRequest,
@@ -52,7 +52,7 @@ use ruma::{
         },
         ImageInfo, MediaSource, ThumbnailInfo,
     },
-    DeviceId, OwnedDeviceId, OwnedUserId, TransactionId, UserId,
+    DeviceId, OwnedDeviceId, OwnedUserId, RoomId, TransactionId, UserId,
 };
 use tokio::sync::RwLockReadGuard;
 use tracing::{debug, instrument, trace, warn};
@@ -60,15 +60,21 @@ use tracing::{debug, instrument, trace, warn};
 use crate::{
     attachment::{AttachmentInfo, Thumbnail},
     encryption::{
+        dehydrated_devices::DehydratedDevices,
         identities::{Device, UserDevices},
+        secret_storage::{SecretStorage, SecretStorageError},
         verification::{SasVerification, Verification, VerificationRequest},
     },
     error::HttpResult,
     Client, Error, Result, Room, TransmissionProgress,
 };
 
+#[cfg(feature = "backups")]
+pub mod backups;
+pub mod dehydrated_devices;
 mod futures;
 pub mod identities;
+pub mod secret_storage;
 pub mod verification;
 
 pub use matrix_sdk_base::crypto::{
@@ -76,6 +82,8 @@ pub use matrix_sdk_base::crypto::{
         SessionCreationError as MegolmSessionCreationError,
         SessionExportError as OlmSessionExportError,
     },
+    store::RoomKeyInfo,
+    types::events::room_key_withheld::WithheldCode,
     vodozemac, CrossSigningStatus, CryptoStoreError, DecryptorError, EventError, KeyExportError,
     LocalTrust, MediaEncryptionInfo, MegolmError, OlmError, RoomKeyImportResult, SecretImportError,
     SessionCreationError, SignatureError, VERSION,
@@ -308,7 +316,7 @@ impl Client {
             "Uploading public encryption keys",
         );
 
-        let response = self.send(request.clone(), None).await?;
+        let response = self.send(request.clone(), self.inner.keys_upload_request_config).await?;
         self.mark_request_as_sent(request_id, &response).await?;
 
         Ok(response)
@@ -448,6 +456,11 @@ impl Client {
             })
             .await;
 
+        // Back up the room keys we might have received or created since the
+        // last time.
+        #[cfg(feature = "backups")]
+        self.encryption().backups().maybe_trigger_backup();
+
         Ok(())
     }
 }
@@ -490,6 +503,100 @@ impl Encryption {
         Some(machine.cross_signing_status().await)
     }
 
+    /// Get the room key backup manager of the client.
+    #[cfg(feature = "backups")]
+    pub fn backups(&self) -> backups::Backups {
+        backups::Backups::new(self.client.clone())
+    }
+
+    /// Download the room key with the given session ID from the key backup.
+    ///
+    /// This can be used when an event couldn't be decrypted, to fetch only
+    /// the missing room key instead of restoring the whole backup. The backup
+    /// decryption key needs to be known, for example after calling
+    /// [`Encryption::recover_secrets()`].
+    ///
+    /// Returns `true` if the room key was found in the backup and imported.
+    #[cfg(feature = "backups")]
+    pub async fn backup_download_session(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<bool> {
+        self.backups().download_room_key(room_id, session_id).await
+    }
+
+    /// Get a stream of the room keys that are received.
+    ///
+    /// The stream yields the room keys that were received via to-device
+    /// messages, downloaded from the key backup or imported, each time they
+    /// are saved in the store. It can be used to retry the decryption of
+    /// events that couldn't be decrypted.
+    ///
+    /// Returns `None` if the client isn't logged in.
+    pub async fn room_keys_received_stream(&self) -> Option<impl Stream<Item = Vec<RoomKeyInfo>>> {
+        let olm = self.client.olm_machine().await;
+        Some(olm.as_ref()?.store().room_keys_received_stream())
+    }
+
+    /// Get the reason why the sender of the room key with the given session ID
+    /// withheld it from us, if they told us so.
+    ///
+    /// This can be used to explain to the user why an event encrypted with
+    /// this room key can't be decrypted.
+    ///
+    /// Returns `None` if the client isn't logged in, or if no
+    /// `m.room_key.withheld` event was received for this room key.
+    pub async fn get_withheld_info(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<WithheldCode>, CryptoStoreError> {
+        let olm = self.client.olm_machine().await;
+        let Some(olm) = olm.as_ref() else { return Ok(None) };
+
+        let event = olm.store().get_withheld_info(room_id, session_id).await?;
+        Ok(event.map(|event| event.content.withheld_code()))
+    }
+
+    /// Get the dehydrated device manager of the client.
+    ///
+    /// Dehydrated devices allow to receive room keys while the user doesn't
+    /// have any active device, and to recover them on the next login.
+    pub fn dehydrated_devices(&self) -> DehydratedDevices {
+        DehydratedDevices::new(self.client.clone())
+    }
+
+    /// Get the secret storage manager of the client.
+    pub fn secret_storage(&self) -> SecretStorage {
+        SecretStorage::new(self.client.clone())
+    }
+
+    /// Recover the secrets stored in the secret storage of the account.
+    ///
+    /// This opens the secret store with the given recovery key, or the
+    /// passphrase it was derived from, and imports the private cross-signing
+    /// keys and the backup decryption key it contains.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// client
+    ///     .encryption()
+    ///     .recover_secrets("EsTc LW2K PGiF wKEA 3As5 g5c4 BXwk")
+    ///     .await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn recover_secrets(&self, recovery_key: &str) -> Result<(), SecretStorageError> {
+        let store = self.secret_storage().open_secret_store(recovery_key).await?;
+        store.import_secrets().await
+    }
+
     /// Get all the tracked users we know about
     ///
     /// Tracked users are users for which we keep the device list of E2EE
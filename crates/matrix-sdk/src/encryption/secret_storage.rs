@@ -0,0 +1,354 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for the [secret storage] of the Matrix specification.
+//!
+//! Secret storage allows to store secrets, like the private cross-signing keys
+//! or the backup decryption key, encrypted in the account data of the user.
+//! The secrets can then be recovered on a new device with a recovery key or a
+//! passphrase.
+//!
+//! [secret storage]: https://spec.matrix.org/v1.8/client-server-api/#storage
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "backups")]
+use matrix_sdk_base::crypto::{
+    backups::DecodeError as BackupDecodeError, store::BackupDecryptionKey,
+};
+use matrix_sdk_base::crypto::{
+    secret_storage::{
+        AesHmacSha2EncryptedData, DecodeError, SecretDecryptionError, SecretStorageKey,
+        SecretStorageKeyEventContent,
+    },
+    store::CrossSigningKeyExport,
+    CryptoStoreError, SecretImportError,
+};
+use ruma::{
+    events::{
+        secret::request::SecretName, AnyGlobalAccountDataEventContent, GlobalAccountDataEventType,
+    },
+    serde::Raw,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, info, instrument};
+
+use crate::{Client, Error};
+
+/// Error type for the secret storage operations.
+#[derive(Debug, Error)]
+pub enum SecretStorageError {
+    /// An error happened while talking to the homeserver or the stores.
+    #[error(transparent)]
+    Sdk(#[from] Error),
+
+    /// An error happened in the crypto store.
+    #[error(transparent)]
+    CryptoStore(#[from] CryptoStoreError),
+
+    /// An account data event couldn't be (de)serialized.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    /// No default secret storage key is set up in the account data.
+    #[error("No default secret storage key is set up")]
+    MissingDefaultKey,
+
+    /// The description of the secret storage key is missing from the account
+    /// data.
+    #[error("The description of the secret storage key {0} is missing")]
+    MissingKeyInfo(String),
+
+    /// The recovery key or passphrase couldn't be used to restore the secret
+    /// storage key.
+    #[error(transparent)]
+    SecretStorageKey(#[from] DecodeError),
+
+    /// A secret couldn't be decrypted.
+    #[error(transparent)]
+    SecretDecryption(#[from] SecretDecryptionError),
+
+    /// A secret couldn't be imported into the crypto store.
+    #[error(transparent)]
+    SecretImport(#[from] SecretImportError),
+
+    /// The backup decryption key found in the secret storage is invalid.
+    #[cfg(feature = "backups")]
+    #[error(transparent)]
+    BackupKey(#[from] BackupDecodeError),
+}
+
+/// The content of an account data event containing a secret.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SecretEventContent {
+    /// The secret encrypted with every key it is stored with, keyed by key ID.
+    encrypted: BTreeMap<String, AesHmacSha2EncryptedData>,
+}
+
+/// The content of the `m.secret_storage.default_key` account data event.
+#[derive(Debug, Serialize, Deserialize)]
+struct DefaultKeyEventContent {
+    /// The ID of the default secret storage key.
+    key: String,
+}
+
+/// A high-level API to manage the secret storage of the client owner's
+/// account.
+///
+/// To get this, use [`Encryption::secret_storage()`].
+///
+/// [`Encryption::secret_storage()`]: super::Encryption::secret_storage
+#[derive(Debug, Clone)]
+pub struct SecretStorage {
+    client: Client,
+}
+
+impl SecretStorage {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Get the ID of the default secret storage key, if one is set up.
+    pub async fn fetch_default_key_id(&self) -> Result<Option<String>, SecretStorageError> {
+        let Some(content) = self
+            .client
+            .account()
+            .fetch_account_data(GlobalAccountDataEventType::SecretStorageDefaultKey)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let content: DefaultKeyEventContent = content.deserialize_as()?;
+        Ok(Some(content.key))
+    }
+
+    /// Create a new secret storage key and make it the default one.
+    ///
+    /// If a `passphrase` is given, the key is derived from it, otherwise a
+    /// random key is created. In both cases the key can be exported as a
+    /// recovery key with [`SecretStore::secret_storage_key()`].
+    ///
+    /// The private cross-signing keys and the backup decryption key that are
+    /// known locally are stored in the new secret store.
+    #[instrument(skip_all)]
+    pub async fn create_secret_store(
+        &self,
+        passphrase: Option<&str>,
+    ) -> Result<SecretStore, SecretStorageError> {
+        let key = match passphrase {
+            Some(passphrase) => SecretStorageKey::new_from_passphrase(passphrase),
+            None => SecretStorageKey::new(),
+        };
+
+        let account = self.client.account();
+
+        account
+            .set_account_data_raw(
+                GlobalAccountDataEventType::from(key.event_type()),
+                Raw::new(&key.event_content())?.cast(),
+            )
+            .await?;
+
+        let default_key = DefaultKeyEventContent { key: key.key_id().to_owned() };
+        account
+            .set_account_data_raw(
+                GlobalAccountDataEventType::SecretStorageDefaultKey,
+                Raw::new(&default_key)?.cast(),
+            )
+            .await?;
+
+        info!(key_id = key.key_id(), "Created a new default secret storage key");
+
+        let store = SecretStore { client: self.client.clone(), key };
+        store.export_secrets().await?;
+
+        Ok(store)
+    }
+
+    /// Open the secret store using the default secret storage key.
+    ///
+    /// The `recovery_key` is either the base58 encoded recovery key, or the
+    /// passphrase the key was derived from.
+    #[instrument(skip_all)]
+    pub async fn open_secret_store(
+        &self,
+        recovery_key: &str,
+    ) -> Result<SecretStore, SecretStorageError> {
+        let key_id =
+            self.fetch_default_key_id().await?.ok_or(SecretStorageError::MissingDefaultKey)?;
+
+        let event_type = GlobalAccountDataEventType::from(format!("m.secret_storage.key.{key_id}"));
+        let content: SecretStorageKeyEventContent = self
+            .client
+            .account()
+            .fetch_account_data(event_type)
+            .await?
+            .ok_or_else(|| SecretStorageError::MissingKeyInfo(key_id.clone()))?
+            .deserialize_as()?;
+
+        let key = SecretStorageKey::from_account_data(recovery_key, key_id, &content)?;
+
+        Ok(SecretStore { client: self.client.clone(), key })
+    }
+}
+
+/// A secret store opened with a secret storage key, used to read and write
+/// secrets.
+#[derive(Debug)]
+pub struct SecretStore {
+    client: Client,
+    key: SecretStorageKey,
+}
+
+impl SecretStore {
+    /// Export the secret storage key as a base58 encoded recovery key.
+    ///
+    /// This should be shown to the user, so they can open the secret store on
+    /// other devices.
+    pub fn secret_storage_key(&self) -> String {
+        self.key.to_base58()
+    }
+
+    /// Get the secret with the given name from the secret store.
+    ///
+    /// Returns `None` if the secret isn't stored, or isn't encrypted with the
+    /// key of this secret store.
+    pub async fn get_secret(
+        &self,
+        secret_name: SecretName,
+    ) -> Result<Option<String>, SecretStorageError> {
+        let event_type = GlobalAccountDataEventType::from(secret_name.as_str());
+
+        let Some(content) = self.client.account().fetch_account_data(event_type).await? else {
+            return Ok(None);
+        };
+
+        let mut content: SecretEventContent = content.deserialize_as()?;
+
+        let Some(data) = content.encrypted.remove(self.key.key_id()) else {
+            debug!(?secret_name, "The secret isn't encrypted with the secret storage key");
+            return Ok(None);
+        };
+
+        Ok(Some(self.key.decrypt(&data, &secret_name)?))
+    }
+
+    /// Store the secret with the given name in the secret store.
+    ///
+    /// This replaces the secret encrypted with the key of this secret store,
+    /// the copies of the secret encrypted with other secret storage keys are
+    /// kept.
+    pub async fn put_secret(
+        &self,
+        secret_name: SecretName,
+        secret: &str,
+    ) -> Result<(), SecretStorageError> {
+        let event_type = GlobalAccountDataEventType::from(secret_name.as_str());
+        let account = self.client.account();
+
+        let mut content = match account.fetch_account_data(event_type.clone()).await? {
+            Some(content) => content.deserialize_as::<SecretEventContent>()?,
+            None => SecretEventContent::default(),
+        };
+        content
+            .encrypted
+            .insert(self.key.key_id().to_owned(), self.key.encrypt(secret, &secret_name));
+
+        account
+            .set_account_data_raw(
+                event_type,
+                Raw::new(&content)?.cast::<AnyGlobalAccountDataEventContent>(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Import the private cross-signing keys and the backup decryption key
+    /// from the secret store into the crypto store.
+    #[instrument(skip_all)]
+    pub async fn import_secrets(&self) -> Result<(), SecretStorageError> {
+        let export = CrossSigningKeyExport {
+            master_key: self.get_secret(SecretName::CrossSigningMasterKey).await?,
+            self_signing_key: self.get_secret(SecretName::CrossSigningSelfSigningKey).await?,
+            user_signing_key: self.get_secret(SecretName::CrossSigningUserSigningKey).await?,
+        };
+        #[cfg(feature = "backups")]
+        let backup_key = self.get_secret(SecretName::RecoveryKey).await?;
+
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        if export.master_key.is_some()
+            || export.self_signing_key.is_some()
+            || export.user_signing_key.is_some()
+        {
+            let status = olm.import_cross_signing_keys(export).await?;
+            info!(?status, "Imported the private cross-signing keys from the secret store");
+        }
+
+        #[cfg(feature = "backups")]
+        if let Some(backup_key) = backup_key {
+            let backup_key = BackupDecryptionKey::from_base64(&backup_key)?;
+            olm.backup_machine().save_decryption_key(Some(backup_key), None).await?;
+            info!("Imported the backup decryption key from the secret store");
+        }
+
+        Ok(())
+    }
+
+    /// Store the private cross-signing keys and the backup decryption key that
+    /// are known locally in the secret store.
+    #[instrument(skip_all)]
+    pub async fn export_secrets(&self) -> Result<(), SecretStorageError> {
+        let cross_signing_keys = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            olm.export_cross_signing_keys().await?
+        };
+
+        if let Some(keys) = &cross_signing_keys {
+            let secrets = [
+                (SecretName::CrossSigningMasterKey, &keys.master_key),
+                (SecretName::CrossSigningSelfSigningKey, &keys.self_signing_key),
+                (SecretName::CrossSigningUserSigningKey, &keys.user_signing_key),
+            ];
+
+            for (secret_name, secret) in secrets {
+                if let Some(secret) = secret {
+                    self.put_secret(secret_name, secret).await?;
+                }
+            }
+        }
+
+        #[cfg(feature = "backups")]
+        {
+            let backup_keys = {
+                let olm = self.client.olm_machine().await;
+                let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+                olm.backup_machine().get_backup_keys().await?
+            };
+
+            if let Some(decryption_key) = &backup_keys.decryption_key {
+                self.put_secret(SecretName::RecoveryKey, &decryption_key.to_base64()).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
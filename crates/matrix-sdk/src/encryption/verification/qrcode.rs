@@ -12,11 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use futures_core::Stream;
 use matrix_sdk_base::crypto::{
     matrix_sdk_qrcode::{qrcode::QrCode, EncodingError},
-    CancelInfo, QrVerification as BaseQrVerification,
+    CancelInfo, QrVerification as BaseQrVerification, QrVerificationState,
 };
-use ruma::UserId;
+use ruma::{events::key::verification::cancel::CancelCode, DeviceId, RoomId, UserId};
 
 use crate::{Client, Result};
 
@@ -48,6 +49,16 @@ impl QrVerification {
         self.inner.has_been_scanned()
     }
 
+    /// Whether we confirmed that the other side scanned the QR code.
+    pub fn has_been_confirmed(&self) -> bool {
+        self.inner.has_been_confirmed()
+    }
+
+    /// Whether we scanned the QR code of the other side and reciprocated it.
+    pub fn reciprocated(&self) -> bool {
+        self.inner.reciprocated()
+    }
+
     /// Did we initiate the verification flow.
     pub fn we_started(&self) -> bool {
         self.inner.we_started()
@@ -65,11 +76,26 @@ impl QrVerification {
         self.inner.other_user_id()
     }
 
+    /// Get the device ID of the other side.
+    pub fn other_device_id(&self) -> &DeviceId {
+        self.inner.other_device_id()
+    }
+
     /// Has the verification been cancelled.
     pub fn is_cancelled(&self) -> bool {
         self.inner.is_cancelled()
     }
 
+    /// Get the unique ID that identifies this QR code verification flow.
+    pub fn flow_id(&self) -> &str {
+        self.inner.flow_id().as_str()
+    }
+
+    /// Get the room id if the verification is happening inside a room.
+    pub fn room_id(&self) -> Option<&RoomId> {
+        self.inner.room_id()
+    }
+
     /// Generate a QR code object that is representing this verification flow.
     ///
     /// The `QrCode` can then be rendered as an image or as an unicode string.
@@ -106,4 +132,73 @@ impl QrVerification {
 
         Ok(())
     }
+
+    /// Abort the verification flow because the other side didn't scan the QR
+    /// code we showed, or scanned a QR code that didn't match.
+    pub async fn mismatch(&self) -> Result<()> {
+        if let Some(request) = self.inner.cancel_with_code(CancelCode::KeyMismatch) {
+            self.client.send_verification_request(request).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Listen for changes in the QR code verification process.
+    ///
+    /// The changes are presented as a stream of [`QrVerificationState`]
+    /// values.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures_util::StreamExt;
+    /// use matrix_sdk::encryption::verification::{
+    ///     QrVerification, QrVerificationState,
+    /// };
+    ///
+    /// # async {
+    /// # let qr: QrVerification = unimplemented!();
+    /// # let user_confirmed = false;
+    /// let mut stream = qr.changes();
+    ///
+    /// while let Some(state) = stream.next().await {
+    ///     match state {
+    ///         QrVerificationState::Scanned => {
+    ///             // Ask the user whether the other device shows that the
+    ///             // scan was successful.
+    ///             if user_confirmed {
+    ///                 qr.confirm().await?;
+    ///             } else {
+    ///                 qr.mismatch().await?;
+    ///             }
+    ///         }
+    ///         QrVerificationState::Done { .. } => {
+    ///             println!("Successfully verified {}", qr.other_user_id());
+    ///             break;
+    ///         }
+    ///         QrVerificationState::Cancelled(cancel_info) => {
+    ///             println!(
+    ///                 "The verification has been cancelled, reason: {}",
+    ///                 cancel_info.reason()
+    ///             );
+    ///             break;
+    ///         }
+    ///         QrVerificationState::Started
+    ///         | QrVerificationState::Confirmed
+    ///         | QrVerificationState::Reciprocated => (),
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn changes(&self) -> impl Stream<Item = QrVerificationState> {
+        self.inner.changes()
+    }
+
+    /// Get the current state the verification process is in.
+    ///
+    /// To listen to changes to the [`QrVerificationState`] use the
+    /// [`QrVerification::changes`] method.
+    pub fn state(&self) -> QrVerificationState {
+        self.inner.state()
+    }
 }
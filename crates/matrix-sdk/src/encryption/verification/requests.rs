@@ -165,7 +165,17 @@ impl VerificationRequest {
         Ok(())
     }
 
-    /// Generate a QR code
+    /// Generate a QR code to be scanned by the other side of this verification
+    /// flow.
+    ///
+    /// Returns `None` if the verification request isn't in the ready state or
+    /// the other side doesn't support scanning QR codes, otherwise a newly
+    /// created `QrVerification` object. Its state changes to
+    /// [`QrVerificationState::Scanned`] once the other side scanned the QR
+    /// code, at which point the scan needs to be confirmed with
+    /// [`QrVerification::confirm()`].
+    ///
+    /// [`QrVerificationState::Scanned`]: super::QrVerificationState::Scanned
     #[cfg(feature = "qrcode")]
     pub async fn generate_qr_code(&self) -> Result<Option<QrVerification>> {
         Ok(self
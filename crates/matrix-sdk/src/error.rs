@@ -16,11 +16,14 @@
 
 use std::{io::Error as IoError, sync::Arc};
 
+#[cfg(feature = "backups")]
+use matrix_sdk_base::crypto::backups::DecryptionError as BackupDecryptionError;
 #[cfg(feature = "qrcode")]
 use matrix_sdk_base::crypto::ScanError;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_base::crypto::{
-    CryptoStoreError, DecryptorError, KeyExportError, MegolmError, OlmError,
+    dehydrated_devices::DehydrationError, CryptoStoreError, DecryptorError, KeyExportError,
+    MegolmError, OlmError,
 };
 use matrix_sdk_base::{Error as SdkBaseError, RoomState, StoreError};
 use reqwest::Error as ReqwestError;
@@ -31,7 +34,7 @@ use ruma::{
     },
     events::tag::InvalidUserTagName,
     push::{InsertPushRuleError, RemovePushRuleError, RuleNotFoundError},
-    IdParseError,
+    IdParseError, OwnedUserId,
 };
 use serde_json::Error as JsonError;
 use thiserror::Error;
@@ -102,6 +105,13 @@ pub enum HttpError {
     #[error(transparent)]
     IntoHttp(#[from] IntoHttpError),
 
+    /// The request would change something visible to other users, but the
+    /// client is in read-only mode.
+    ///
+    /// See [`ClientBuilder::read_only()`](crate::ClientBuilder::read_only).
+    #[error("the request is not allowed because the client is read-only")]
+    ReadOnly,
+
     /// The given request can't be cloned and thus can't be retried.
     #[error("The request cannot be cloned")]
     UnableToCloneRequest,
@@ -157,6 +167,21 @@ impl HttpError {
             _ => None,
         }
     }
+
+    /// Whether this error means that the homeserver couldn't be reached.
+    ///
+    /// This is only the case if the connection to the homeserver failed or
+    /// timed out. Other errors of the HTTP client, like a response that
+    /// couldn't be decoded, mean that the homeserver was reached.
+    pub fn is_network_error(&self) -> bool {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Reqwest(error) => error.is_connect() || error.is_timeout(),
+            #[cfg(target_arch = "wasm32")]
+            Self::Reqwest(error) => error.is_timeout(),
+            _ => false,
+        }
+    }
 }
 
 /// Internal representation of errors.
@@ -215,6 +240,16 @@ pub enum Error {
     #[error(transparent)]
     DecryptorError(#[from] DecryptorError),
 
+    /// A room key downloaded from the key backup couldn't be decrypted.
+    #[cfg(feature = "backups")]
+    #[error(transparent)]
+    BackupDecryption(#[from] BackupDecryptionError),
+
+    /// A dehydrated device couldn't be created or rehydrated.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    Dehydration(#[from] DehydrationError),
+
     /// An error occurred in the state store.
     #[error(transparent)]
     StateStore(#[from] StoreError),
@@ -252,11 +287,38 @@ pub enum Error {
     #[error("wrong room state: {0}")]
     WrongRoomState(WrongRoomState),
 
+    /// The request was not sent because the client is offline, see
+    /// [`Client::network_state()`].
+    ///
+    /// [`Client::network_state()`]: crate::Client::network_state
+    #[error("the request was not sent because the client is offline")]
+    Offline,
+
+    /// The action requires the user to be an invited or joined member of the
+    /// room, but they are not.
+    #[error("{0} is not an invited or joined member of the room")]
+    NotARoomMember(OwnedUserId),
+
     /// The client is in inconsistent state. This happens when we set a room to
     /// a specific type, but then cannot get it in this type.
     #[error("The internal client state is inconsistent.")]
     InconsistentState,
 
+    /// Sending an event of the send queue of a room was aborted.
+    #[error("sending the event was aborted")]
+    SendAborted,
+
+    /// The session callbacks have already been set.
+    #[error("the session callbacks can only be set once")]
+    MultipleSessionCallbacks,
+
+    /// The event was dropped by an event preprocessor, see
+    /// [`Client::add_event_preprocessor()`].
+    ///
+    /// [`Client::add_event_preprocessor()`]: crate::Client::add_event_preprocessor
+    #[error("the event was dropped by an event preprocessor")]
+    EventDropped,
+
     /// An error occurred interacting with the OpenID Connect API.
     #[cfg(feature = "experimental-oidc")]
     #[error(transparent)]
@@ -410,6 +472,10 @@ pub enum ImageError {
     /// The thumbnail size is bigger than the original image.
     #[error("the thumbnail size is bigger than the original image size")]
     ThumbnailBiggerThanOriginal,
+
+    /// The BlurHash of the image could not be computed.
+    #[error("the BlurHash of the image could not be computed")]
+    BlurhashFailed,
 }
 
 /// Errors that can happen when refreshing an access token.
@@ -0,0 +1,960 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The event cache, which keeps the timeline events of the rooms in the state
+//! store.
+//!
+//! If [`ClientBuilder::cache_room_events()`] was used, the timeline events
+//! received via sync are stored, so they can be shown right away after a
+//! restart, without waiting for the homeserver. The places where events are
+//! missing, because a sync response was limited, are remembered as gaps with
+//! the `prev_batch` token that allows to fetch them with the `/messages`
+//! endpoint.
+//!
+//! The cache of a room is accessed with [`Room::event_cache()`]. Paginating
+//! backwards with [`RoomEventCache::paginate_backwards()`] returns the cached
+//! events first, and fills the gaps with the homeserver when they are reached.
+//!
+//! The cached events are also used to compute the [`UnreadCounts`] of the
+//! room, see [`Room::subscribe_to_unread_counts()`].
+//!
+//! [`UnreadCounts`]: crate::sync::UnreadCounts
+//!
+//! [`ClientBuilder::cache_room_events()`]: crate::ClientBuilder::cache_room_events
+//! [history cutoff]: crate::Room::history_cutoff
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use matrix_sdk_base::{
+    deserialized_responses::{SyncTimelineEvent, TimelineEvent},
+    sync::{Timeline, UnreadCounts},
+    StateChanges,
+};
+use ruma::{
+    api::client::receipt::create_receipt::v3::ReceiptType,
+    assign,
+    events::{
+        receipt::ReceiptThread,
+        room::{encrypted, message},
+        AnySyncMessageLikeEvent, AnySyncTimelineEvent, SyncMessageLikeEvent,
+    },
+    uint, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, RoomId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument, trace, warn};
+
+use crate::{
+    room::{Messages, MessagesOptions},
+    Result, Room,
+};
+
+/// The maximum number of events that are kept in the cache of a room.
+///
+/// When there are more events, the oldest chunks are removed and replaced by a
+/// gap, so the removed events can be fetched again from the homeserver.
+const MAX_CACHED_EVENTS: usize = 1000;
+
+/// The maximum number of events in a chunk, that is stored as a whole.
+const CHUNK_CAPACITY: usize = 50;
+
+/// The event caches of the rooms of a client.
+#[derive(Default)]
+pub(crate) struct EventCache {
+    rooms: StdMutex<BTreeMap<OwnedRoomId, Arc<RoomCacheState>>>,
+}
+
+impl EventCache {
+    /// Get the state of the cache of the given room, shared by all the
+    /// [`RoomEventCache`]s of that room.
+    fn room_state(&self, room_id: &RoomId) -> Arc<RoomCacheState> {
+        self.rooms.lock().unwrap().entry(room_id.to_owned()).or_default().clone()
+    }
+}
+
+/// The state of the cache of a room.
+#[derive(Default)]
+struct RoomCacheState {
+    /// The cached events, loaded lazily from the store.
+    events: Mutex<Option<RoomEvents>>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for EventCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventCache").finish_non_exhaustive()
+    }
+}
+
+/// A chunk of the cache of a room.
+#[derive(Debug)]
+enum Chunk {
+    /// Consecutive timeline events, that are stored together.
+    Events { id: u64, events: Vec<SyncTimelineEvent> },
+
+    /// Events that are missing.
+    ///
+    /// They can be fetched with the given token. If it is unknown, because
+    /// the events before the gap were removed from the cache, the token is
+    /// fetched with the first event after the gap.
+    Gap { prev_batch: Option<String> },
+}
+
+/// The position of an event in the cache, as the index of its chunk and its
+/// index in the chunk.
+///
+/// When paginating, it is the position of the oldest event that was returned,
+/// and the index can be the length of the chunk to start after its last event.
+type Position = (usize, usize);
+
+/// The chunks of the cache of a room.
+#[derive(Debug, Default)]
+struct RoomEvents {
+    chunks: Vec<Chunk>,
+    next_chunk_id: u64,
+    /// The chunks of events that changed since the cache was last saved.
+    changed_chunks: BTreeSet<u64>,
+    /// The chunks of events that were removed since the cache was last saved.
+    removed_chunks: BTreeSet<u64>,
+}
+
+impl RoomEvents {
+    /// All the cached events, in chronological order.
+    fn events(&self) -> impl DoubleEndedIterator<Item = &SyncTimelineEvent> + '_ {
+        self.chunks.iter().flat_map(|chunk| match chunk {
+            Chunk::Events { events, .. } => events.as_slice(),
+            Chunk::Gap { .. } => &[][..],
+        })
+    }
+
+    /// The position after the most recent event.
+    fn end(&self) -> Position {
+        (self.chunks.len(), 0)
+    }
+
+    /// Get the position of the given event, if it's cached.
+    fn position(&self, event_id: &EventId) -> Option<Position> {
+        self.chunks.iter().enumerate().rev().find_map(|(chunk_index, chunk)| {
+            let Chunk::Events { events, .. } = chunk else {
+                return None;
+            };
+
+            events
+                .iter()
+                .rposition(|event| event.event_id().as_deref() == Some(event_id))
+                .map(|index| (chunk_index, index))
+        })
+    }
+
+    /// Create a new chunk with the given events, that needs to be saved.
+    fn new_events_chunk(&mut self, events: Vec<SyncTimelineEvent>) -> Chunk {
+        let id = self.next_chunk_id;
+        self.next_chunk_id += 1;
+        self.changed_chunks.insert(id);
+        Chunk::Events { id, events }
+    }
+
+    fn push_gap(&mut self, prev_batch: String) {
+        self.chunks.push(Chunk::Gap { prev_batch: Some(prev_batch) });
+    }
+
+    fn push_event(&mut self, event: SyncTimelineEvent) {
+        if let Some(Chunk::Events { id, events }) = self.chunks.last_mut() {
+            if events.len() < CHUNK_CAPACITY {
+                events.push(event);
+                self.changed_chunks.insert(*id);
+                return;
+            }
+        }
+
+        let chunk = self.new_events_chunk(vec![event]);
+        self.chunks.push(chunk);
+    }
+
+    fn remove_event(&mut self, (chunk_index, index): Position) {
+        let Some(Chunk::Events { id, events }) = self.chunks.get_mut(chunk_index) else {
+            return;
+        };
+
+        events.remove(index);
+
+        if events.is_empty() {
+            self.remove_chunk(chunk_index);
+        } else {
+            self.changed_chunks.insert(*id);
+        }
+    }
+
+    fn remove_chunk(&mut self, chunk_index: usize) -> Chunk {
+        let chunk = self.chunks.remove(chunk_index);
+
+        if let Chunk::Events { id, .. } = &chunk {
+            self.changed_chunks.remove(id);
+            self.removed_chunks.insert(*id);
+        }
+
+        chunk
+    }
+
+    /// Remove the oldest chunks if there are more than [`MAX_CACHED_EVENTS`]
+    /// events.
+    fn trim(&mut self) {
+        let mut count = self.events().count();
+        if count <= MAX_CACHED_EVENTS {
+            return;
+        }
+
+        let mut removed = 0;
+        while count > MAX_CACHED_EVENTS && !self.chunks.is_empty() {
+            if let Chunk::Events { events, .. } = self.remove_chunk(0) {
+                count -= events.len();
+                removed += events.len();
+            }
+        }
+
+        trace!(removed, "Trimming the event cache");
+        self.insert_start_gap();
+    }
+
+    /// Remove the events that are older than the given history cutoff.
+    ///
+    /// Returns whether events were removed.
+    fn remove_events_before(&mut self, cutoff: MilliSecondsSinceUnixEpoch) -> bool {
+        // The events are mostly in chronological order, so there is nothing to
+        // do most of the time.
+        if !self.events().next().is_some_and(|event| is_older_than(event, cutoff)) {
+            return false;
+        }
+
+        let mut removed = 0;
+        for chunk in &mut self.chunks {
+            let Chunk::Events { id, events } = chunk else {
+                continue;
+            };
+
+            let previous_len = events.len();
+            events.retain(|event| !is_older_than(event, cutoff));
+
+            if events.len() != previous_len {
+                removed += previous_len - events.len();
+                self.changed_chunks.insert(*id);
+            }
+        }
+
+        let mut chunk_index = 0;
+        while chunk_index < self.chunks.len() {
+            if matches!(&self.chunks[chunk_index], Chunk::Events { events, .. } if events.is_empty())
+            {
+                self.remove_chunk(chunk_index);
+            } else {
+                chunk_index += 1;
+            }
+        }
+
+        trace!(removed, "Removed events older than the history cutoff from the event cache");
+        self.insert_start_gap();
+
+        true
+    }
+
+    /// Make sure that the oldest events are preceded by a gap, after older
+    /// events were removed.
+    fn insert_start_gap(&mut self) {
+        if matches!(self.chunks.first(), Some(Chunk::Events { .. })) {
+            self.chunks.insert(0, Chunk::Gap { prev_batch: None });
+        }
+    }
+}
+
+/// The layout of the chunks of the cache of a room, as it is stored.
+///
+/// The events of every chunk are stored separately.
+#[derive(Default, Serialize, Deserialize)]
+struct StoredLayout {
+    next_chunk_id: u64,
+    chunks: Vec<StoredChunk>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum StoredChunk {
+    Events { id: u64 },
+    Gap { prev_batch: Option<String> },
+}
+
+/// The result of filling a gap.
+struct FilledGap {
+    /// The events that were added, in reverse chronological order.
+    events: Vec<TimelineEvent>,
+    /// The position of the oldest event that was added.
+    position: Position,
+    /// Whether events older than the history cutoff of the room were reached.
+    reached_history_cutoff: bool,
+}
+
+/// The cache of the timeline events of a room.
+///
+/// To get this, use [`Room::event_cache()`].
+#[derive(Clone)]
+pub struct RoomEventCache {
+    room: Room,
+    state: Arc<RoomCacheState>,
+}
+
+impl RoomEventCache {
+    pub(crate) fn new(room: Room, event_cache: &EventCache) -> Self {
+        let state = event_cache.room_state(room.room_id());
+        Self { room, state }
+    }
+
+    /// Get the most recent cached events of the room, in chronological order.
+    ///
+    /// This doesn't make any request to the homeserver, so it stops at the
+    /// first gap.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of events to return.
+    pub async fn latest_events(&self, limit: usize) -> Result<Vec<SyncTimelineEvent>> {
+        let mut room_events = self.state.events.lock().await;
+        let room_events = self.load_events(&mut room_events).await?;
+
+        let mut events: Vec<_> = room_events
+            .chunks
+            .iter()
+            .rev()
+            .map_while(|chunk| match chunk {
+                Chunk::Events { events, .. } => Some(events),
+                Chunk::Gap { .. } => None,
+            })
+            .flat_map(|events| events.iter().rev())
+            .take(limit)
+            .cloned()
+            .collect();
+        events.reverse();
+
+        Ok(events)
+    }
+
+    /// Get the events before the given one.
+    ///
+    /// The cached events are returned first. When a gap is reached, the
+    /// missing events are fetched from the homeserver and added to the cache.
+    ///
+    /// The `chunk` of the returned [`Messages`] contains the events in reverse
+    /// chronological order, and its `end` is the ID of the oldest event of the
+    /// chunk, to use as `from` for the next call. It is `None` if the start of
+    /// the room, or the [history cutoff] of the room, was reached.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The ID of the event before which to get events, as returned
+    ///   in the `end` of a previous call, or `None` to start from the most
+    ///   recent event.
+    ///
+    /// * `limit` - The maximum number of events to return.
+    ///
+    /// [history cutoff]: Room::history_cutoff
+    #[instrument(skip(self), fields(room_id = ?self.room.room_id()))]
+    pub async fn paginate_backwards(&self, from: Option<&str>, limit: u16) -> Result<Messages> {
+        let history_cutoff = self.room.history_cutoff().await?;
+
+        let mut room_events = self.state.events.lock().await;
+        let room_events = self.load_events(&mut room_events).await?;
+
+        let from_event_id = from.and_then(|from| <&EventId>::try_from(from).ok());
+        let mut position = match from_event_id {
+            Some(event_id) => match room_events.position(event_id) {
+                Some(position) => position,
+                None => {
+                    warn!(?event_id, "Unknown event in the cache, paginating from the end");
+                    room_events.end()
+                }
+            },
+            None => room_events.end(),
+        };
+
+        let mut chunk = Vec::new();
+        let mut reached_history_cutoff = false;
+
+        if room_events.chunks.is_empty() {
+            // Nothing is cached for this room yet, start with the latest
+            // events.
+            let filled = self.fill_gap(room_events, 0, limit, history_cutoff).await?;
+            chunk = filled.events;
+            position = filled.position;
+            reached_history_cutoff = filled.reached_history_cutoff;
+        }
+
+        while chunk.len() < limit.into() && !reached_history_cutoff {
+            let (chunk_index, index) = position;
+
+            if index > 0 {
+                if let Some(Chunk::Events { events, .. }) = room_events.chunks.get(chunk_index) {
+                    chunk.push(timeline_event(&events[index - 1]));
+                }
+                position = (chunk_index, index - 1);
+                continue;
+            }
+
+            // The start of the room was reached.
+            let Some(previous_index) = chunk_index.checked_sub(1) else {
+                break;
+            };
+
+            match &room_events.chunks[previous_index] {
+                Chunk::Events { events, .. } => position = (previous_index, events.len()),
+                // Return the cached events before filling the gap.
+                Chunk::Gap { .. } if !chunk.is_empty() => break,
+                Chunk::Gap { .. } => {
+                    let filled =
+                        self.fill_gap(room_events, previous_index, limit, history_cutoff).await?;
+                    chunk.extend(filled.events);
+                    position = filled.position;
+                    reached_history_cutoff = filled.reached_history_cutoff;
+                }
+            }
+        }
+
+        let end = if reached_history_cutoff || position == (0, 0) {
+            None
+        } else {
+            let (chunk_index, index) = position;
+            match room_events.chunks.get(chunk_index) {
+                Some(Chunk::Events { events, .. }) => {
+                    events.get(index).and_then(SyncTimelineEvent::event_id).map(String::from)
+                }
+                _ => None,
+            }
+            .or_else(|| from.map(ToOwned::to_owned))
+        };
+
+        Ok(Messages { start: from.unwrap_or_default().to_owned(), end, chunk, state: Vec::new() })
+    }
+
+    /// Add the events of the given timeline received via sync to the cache.
+    pub(crate) async fn handle_sync_timeline(&self, timeline: &Timeline) -> Result<()> {
+        if timeline.events.is_empty() {
+            return Ok(());
+        }
+
+        let mut room_events = self.state.events.lock().await;
+        let room_events = self.load_events(&mut room_events).await?;
+
+        // The events before these ones are unknown if the response was limited,
+        // or if nothing is cached yet.
+        if timeline.limited || room_events.chunks.is_empty() {
+            if let Some(prev_batch) = &timeline.prev_batch {
+                room_events.push_gap(prev_batch.clone());
+            }
+        }
+
+        for event in &timeline.events {
+            if let Some(position) =
+                event.event_id().and_then(|event_id| room_events.position(&event_id))
+            {
+                room_events.remove_event(position);
+            }
+            room_events.push_event(event.clone());
+        }
+
+        room_events.trim();
+        self.save_events(room_events).await
+    }
+
+    /// Remove the cached events that are older than the given history cutoff
+    /// of the room.
+    pub(crate) async fn apply_history_cutoff(
+        &self,
+        cutoff: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let mut room_events = self.state.events.lock().await;
+        let room_events = self.load_events(&mut room_events).await?;
+
+        if room_events.remove_events_before(cutoff) {
+            self.save_events(room_events).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Compute the unread counts of the room again, after new events or read
+    /// receipts were received, and persist them in the info of the room.
+    ///
+    /// The events after the latest read receipt of the current user, or after
+    /// the latest event they sent, are unread. In a thread, the receipts and
+    /// the events of the user in that thread are used.
+    pub(crate) async fn update_unread_counts(&self) -> Result<()> {
+        let own_user_id = self.room.own_user_id();
+
+        let mut room_events = self.state.events.lock().await;
+        let room_events = self.load_events(&mut room_events).await?;
+
+        let thread_roots: BTreeSet<_> = room_events.events().filter_map(thread_root).collect();
+        let threads = [ReceiptThread::Unthreaded, ReceiptThread::Main]
+            .into_iter()
+            .chain(thread_roots.into_iter().map(ReceiptThread::Thread));
+
+        // The threads that are read up to the events with a receipt.
+        let mut receipts = BTreeMap::<OwnedEventId, Vec<ReceiptThread>>::new();
+
+        for thread in threads {
+            for receipt_type in [ReceiptType::Read, ReceiptType::ReadPrivate] {
+                let receipt =
+                    self.room.user_receipt(receipt_type, thread.clone(), own_user_id).await?;
+
+                if let Some((event_id, _)) = receipt {
+                    receipts.entry(event_id).or_default().push(thread.clone());
+                }
+            }
+        }
+
+        let mut counts = UnreadCounts::default();
+        let mut is_main_timeline_read = false;
+        let mut read_threads = BTreeSet::new();
+
+        for event in room_events.events().rev() {
+            let receipt_threads =
+                event.event_id().and_then(|event_id| receipts.get(&event_id)).into_iter().flatten();
+            let mut is_everything_read = false;
+
+            for thread in receipt_threads {
+                match thread {
+                    // An unthreaded receipt applies to the threads too.
+                    ReceiptThread::Unthreaded => is_everything_read = true,
+                    ReceiptThread::Main => is_main_timeline_read = true,
+                    ReceiptThread::Thread(root) => {
+                        read_threads.insert(root.clone());
+                    }
+                    _ => {}
+                }
+            }
+
+            if is_everything_read {
+                break;
+            }
+
+            let thread = thread_root(event);
+            let is_read = match &thread {
+                Some(root) => read_threads.contains(root),
+                None => is_main_timeline_read,
+            };
+
+            if is_read {
+                continue;
+            }
+
+            let Ok(deserialized) = event.event.deserialize() else {
+                continue;
+            };
+
+            if deserialized.sender() == own_user_id {
+                match thread {
+                    Some(root) => {
+                        read_threads.insert(root);
+                    }
+                    None => is_main_timeline_read = true,
+                }
+                continue;
+            }
+
+            if is_countable(&deserialized) {
+                counts.num_unread_messages += 1;
+            }
+            if event.push_actions.iter().any(|action| action.should_notify()) {
+                counts.num_unread_notifications += 1;
+            }
+            if event.push_actions.iter().any(|action| action.is_highlight()) {
+                counts.num_unread_mentions += 1;
+            }
+        }
+
+        let _sync_lock = self.room.client.base_client().sync_lock().read().await;
+        let mut room_info = self.room.clone_info();
+
+        if room_info.update_unread_counts(counts) {
+            let mut changes = StateChanges::default();
+            changes.add_room(room_info.clone());
+            self.room.client.store().save_changes(&changes).await?;
+            self.room.update_summary(room_info);
+        }
+
+        Ok(())
+    }
+
+    /// Replace the gap at the given index with the events fetched from the
+    /// homeserver, or insert the latest events if the cache is empty.
+    async fn fill_gap(
+        &self,
+        room_events: &mut RoomEvents,
+        index: usize,
+        limit: u16,
+        history_cutoff: Option<MilliSecondsSinceUnixEpoch>,
+    ) -> Result<FilledGap> {
+        debug!("Filling a gap in the cache");
+
+        let is_gap = matches!(room_events.chunks.get(index), Some(Chunk::Gap { .. }));
+        let prev_batch = match room_events.chunks.get(index) {
+            Some(Chunk::Gap { prev_batch: Some(prev_batch) }) => Some(prev_batch.clone()),
+            Some(Chunk::Gap { prev_batch: None }) => {
+                let prev_batch = self.token_before_chunk(room_events, index + 1).await?;
+
+                if prev_batch.is_none() {
+                    // There is nothing to fetch before the next event.
+                    room_events.chunks.remove(index);
+                    return Ok(FilledGap {
+                        events: Vec::new(),
+                        position: (index, 0),
+                        reached_history_cutoff: false,
+                    });
+                }
+
+                prev_batch
+            }
+            _ => None,
+        };
+
+        let messages = self
+            .room
+            .messages(assign!(MessagesOptions::backward().from(prev_batch.as_deref()), {
+                limit: limit.into(),
+            }))
+            .await?;
+
+        let mut reached_history_cutoff = false;
+        let mut events = Vec::with_capacity(messages.chunk.len());
+
+        for event in messages.chunk.into_iter().rev() {
+            let event = SyncTimelineEvent::from(event);
+
+            if history_cutoff.is_some_and(|cutoff| is_older_than(&event, cutoff)) {
+                trace!("Skipping event older than the history cutoff");
+                reached_history_cutoff = true;
+                continue;
+            }
+
+            if event.event_id().is_some_and(|event_id| room_events.position(&event_id).is_some()) {
+                trace!("Skipping event that is already cached");
+                continue;
+            }
+
+            events.push(event);
+        }
+
+        let added = events.iter().rev().map(timeline_event).collect();
+
+        let mut new_chunks = Vec::new();
+        if let Some(end) = messages.end {
+            // The events older than the history cutoff were skipped, so the
+            // token can't be used anymore if the cutoff changes.
+            let prev_batch = (!reached_history_cutoff).then_some(end);
+            new_chunks.push(Chunk::Gap { prev_batch });
+        }
+
+        let position = (index + new_chunks.len(), 0);
+
+        while !events.is_empty() {
+            let rest = events.split_off(events.len().min(CHUNK_CAPACITY));
+            let chunk = room_events.new_events_chunk(events);
+            new_chunks.push(chunk);
+            events = rest;
+        }
+
+        let range = if is_gap { index..index + 1 } else { index..index };
+        room_events.chunks.splice(range, new_chunks);
+        self.save_events(room_events).await?;
+
+        Ok(FilledGap { events: added, position, reached_history_cutoff })
+    }
+
+    /// Get the token to fetch the events before the first event in the chunks
+    /// starting at the given index.
+    async fn token_before_chunk(
+        &self,
+        room_events: &RoomEvents,
+        chunk_index: usize,
+    ) -> Result<Option<String>> {
+        let next_event_id = room_events.chunks[chunk_index..]
+            .iter()
+            .find_map(|chunk| match chunk {
+                Chunk::Events { events, .. } => events.first(),
+                Chunk::Gap { .. } => None,
+            })
+            .and_then(SyncTimelineEvent::event_id);
+
+        let Some(event_id) = next_event_id else {
+            return Ok(None);
+        };
+
+        Ok(self.room.event_context(&event_id, uint!(0)).await?.prev_batch_token)
+    }
+
+    /// Make sure the events of the cache are loaded from the store.
+    async fn load_events<'a>(
+        &self,
+        room_events: &'a mut Option<RoomEvents>,
+    ) -> Result<&'a mut RoomEvents> {
+        let loaded = match room_events.take() {
+            Some(loaded) => loaded,
+            None => self.load_from_store().await?,
+        };
+
+        Ok(room_events.insert(loaded))
+    }
+
+    async fn load_from_store(&self) -> Result<RoomEvents> {
+        let store = self.room.client.store();
+
+        let Some(bytes) = store.get_custom_value(&self.layout_key()).await? else {
+            return Ok(RoomEvents::default());
+        };
+
+        let layout: StoredLayout = match serde_json::from_slice(&bytes) {
+            Ok(layout) => layout,
+            Err(e) => {
+                warn!("Failed to deserialize the event cache, starting over: {e}");
+                return Ok(RoomEvents::default());
+            }
+        };
+
+        let mut room_events =
+            RoomEvents { next_chunk_id: layout.next_chunk_id, ..Default::default() };
+
+        for chunk in layout.chunks {
+            let chunk = match chunk {
+                StoredChunk::Events { id } => {
+                    let events = match store.get_custom_value(&self.chunk_key(id)).await? {
+                        Some(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                            warn!(id, "Failed to deserialize a chunk of the event cache: {e}");
+                        }),
+                        None => Err(()),
+                    };
+
+                    match events {
+                        Ok(events) => Chunk::Events { id, events },
+                        // The events can be fetched again from the homeserver.
+                        Err(()) => {
+                            room_events.removed_chunks.insert(id);
+                            Chunk::Gap { prev_batch: None }
+                        }
+                    }
+                }
+                StoredChunk::Gap { prev_batch } => Chunk::Gap { prev_batch },
+            };
+
+            room_events.chunks.push(chunk);
+        }
+
+        Ok(room_events)
+    }
+
+    /// Save the chunks that changed, and the new layout of the chunks.
+    async fn save_events(&self, room_events: &mut RoomEvents) -> Result<()> {
+        let store = self.room.client.store();
+
+        for chunk in &room_events.chunks {
+            if let Chunk::Events { id, events } = chunk {
+                if room_events.changed_chunks.contains(id) {
+                    store
+                        .set_custom_value(&self.chunk_key(*id), serde_json::to_vec(events)?)
+                        .await?;
+                }
+            }
+        }
+
+        let layout = StoredLayout {
+            next_chunk_id: room_events.next_chunk_id,
+            chunks: room_events
+                .chunks
+                .iter()
+                .map(|chunk| match chunk {
+                    Chunk::Events { id, .. } => StoredChunk::Events { id: *id },
+                    Chunk::Gap { prev_batch } => {
+                        StoredChunk::Gap { prev_batch: prev_batch.clone() }
+                    }
+                })
+                .collect(),
+        };
+        store.set_custom_value(&self.layout_key(), serde_json::to_vec(&layout)?).await?;
+        room_events.changed_chunks.clear();
+
+        // The removed chunks are not referenced by the layout anymore.
+        for id in std::mem::take(&mut room_events.removed_chunks) {
+            store.remove_custom_value(&self.chunk_key(id)).await?;
+        }
+
+        Ok(())
+    }
+
+    fn layout_key(&self) -> Vec<u8> {
+        format!("event_cache:{}", self.room.room_id()).into_bytes()
+    }
+
+    fn chunk_key(&self, id: u64) -> Vec<u8> {
+        format!("event_cache:{}:{id}", self.room.room_id()).into_bytes()
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for RoomEventCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RoomEventCache").field("room_id", &self.room.room_id()).finish()
+    }
+}
+
+/// Whether the given event is older than the given history cutoff.
+fn is_older_than(event: &SyncTimelineEvent, cutoff: MilliSecondsSinceUnixEpoch) -> bool {
+    event
+        .event
+        .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+        .ok()
+        .flatten()
+        .is_some_and(|timestamp| timestamp < cutoff)
+}
+
+/// The root of the thread of the given event, if it is in a thread.
+///
+/// The relation of encrypted events is not encrypted, so it works before they
+/// are decrypted too.
+fn thread_root(event: &SyncTimelineEvent) -> Option<OwnedEventId> {
+    #[derive(Deserialize)]
+    struct Relation {
+        rel_type: Option<String>,
+        event_id: Option<OwnedEventId>,
+    }
+
+    #[derive(Deserialize)]
+    struct Content {
+        #[serde(rename = "m.relates_to")]
+        relates_to: Option<Relation>,
+    }
+
+    let relation = event.event.get_field::<Content>("content").ok().flatten()?.relates_to?;
+    if relation.rel_type.as_deref() == Some("m.thread") {
+        relation.event_id
+    } else {
+        None
+    }
+}
+
+/// Whether the given event counts as an unread message.
+fn is_countable(event: &AnySyncTimelineEvent) -> bool {
+    let AnySyncTimelineEvent::MessageLike(event) = event else {
+        return false;
+    };
+
+    match event {
+        AnySyncMessageLikeEvent::RoomMessage(SyncMessageLikeEvent::Original(event)) => {
+            !matches!(event.content.relates_to, Some(message::Relation::Replacement(_)))
+        }
+        AnySyncMessageLikeEvent::RoomEncrypted(SyncMessageLikeEvent::Original(event)) => {
+            !matches!(event.content.relates_to, Some(encrypted::Relation::Replacement(_)))
+        }
+        AnySyncMessageLikeEvent::Sticker(SyncMessageLikeEvent::Original(_)) => true,
+        _ => false,
+    }
+}
+
+/// Convert a cached event to the type returned by back-pagination.
+///
+/// The room ID is missing from the event, but it's only meant to be used as a
+/// [`SyncTimelineEvent`].
+fn timeline_event(event: &SyncTimelineEvent) -> TimelineEvent {
+    TimelineEvent {
+        event: event.event.clone().cast(),
+        encryption_info: event.encryption_info.clone(),
+        push_actions: Some(event.push_actions.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
+    use ruma::{event_id, serde::Raw, MilliSecondsSinceUnixEpoch, UInt};
+    use serde_json::json;
+
+    use super::{Chunk, RoomEvents, CHUNK_CAPACITY, MAX_CACHED_EVENTS};
+
+    fn message_event(index: u32) -> SyncTimelineEvent {
+        let event = json!({
+            "content": {
+                "body": "Hello",
+                "msgtype": "m.text",
+            },
+            "event_id": format!("${index}:localhost"),
+            "origin_server_ts": index,
+            "sender": "@alice:localhost",
+            "type": "m.room.message",
+        });
+        SyncTimelineEvent::new(Raw::new(&event).unwrap().cast())
+    }
+
+    #[test]
+    fn events_are_split_in_chunks() {
+        let mut room_events = RoomEvents::default();
+        for index in 0..(CHUNK_CAPACITY + 1) as u32 {
+            room_events.push_event(message_event(index));
+        }
+
+        assert_eq!(room_events.chunks.len(), 2);
+        assert_eq!(room_events.position(event_id!("$50:localhost")), Some((1, 0)));
+        assert_eq!(room_events.changed_chunks.len(), 2);
+    }
+
+    #[test]
+    fn trim_removes_the_oldest_chunks() {
+        let mut room_events = RoomEvents::default();
+        for index in 0..(MAX_CACHED_EVENTS + 10) as u32 {
+            room_events.push_event(message_event(index));
+        }
+        room_events.changed_chunks.clear();
+
+        room_events.trim();
+
+        // The first chunk was removed, and replaced by a gap to fetch it again.
+        assert_eq!(room_events.events().count(), MAX_CACHED_EVENTS + 10 - CHUNK_CAPACITY);
+        assert_matches!(&room_events.chunks[0], Chunk::Gap { prev_batch: None });
+        assert_eq!(room_events.position(event_id!("$0:localhost")), None);
+        assert_eq!(room_events.removed_chunks.len(), 1);
+        assert!(room_events.changed_chunks.is_empty());
+    }
+
+    #[test]
+    fn remove_events_before_history_cutoff() {
+        let mut room_events = RoomEvents::default();
+        for index in 0..10 {
+            room_events.push_event(message_event(index));
+        }
+        room_events.changed_chunks.clear();
+
+        assert!(!room_events.remove_events_before(MilliSecondsSinceUnixEpoch(UInt::MIN)));
+        assert!(room_events.changed_chunks.is_empty());
+
+        assert!(room_events.remove_events_before(MilliSecondsSinceUnixEpoch(5u32.into())));
+
+        let event_ids: Vec<_> =
+            room_events.events().map(|event| event.event_id().unwrap().to_string()).collect();
+        assert_eq!(
+            event_ids,
+            ["$5:localhost", "$6:localhost", "$7:localhost", "$8:localhost", "$9:localhost"]
+        );
+        assert_matches!(&room_events.chunks[0], Chunk::Gap { prev_batch: None });
+        assert_eq!(room_events.changed_chunks.len(), 1);
+    }
+}
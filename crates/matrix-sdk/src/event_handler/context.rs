@@ -44,17 +44,29 @@ impl EventHandlerContext for EventHandlerHandle {
 }
 
 /// This event handler context argument is only applicable to room-specific
-/// events.
+/// events, and to to-device events related to a known room, like room keys.
 ///
 /// Trying to use it in the event handler for another event, for example a
 /// global account data or presence event, will result in the event handler
-/// being skipped and an error getting logged.
+/// being skipped and an error getting logged. Use `Option<Room>` if the room
+/// is not always known.
 impl EventHandlerContext for Room {
     fn from_data(data: &EventHandlerData<'_>) -> Option<Self> {
         data.room.clone()
     }
 }
 
+/// The room of the event, if any.
+///
+/// Contrary to [`Room`], this event handler context argument can be used with
+/// any event, for example with to-device events that might be related to a
+/// room.
+impl EventHandlerContext for Option<Room> {
+    fn from_data(data: &EventHandlerData<'_>) -> Option<Self> {
+        Some(data.room.clone())
+    }
+}
+
 /// The raw JSON form of an event.
 ///
 /// Used as a context argument for event handlers (see
@@ -13,20 +13,26 @@
 // limitations under the License.
 
 use std::{
-    borrow::Borrow,
     collections::{btree_map, BTreeMap},
+    sync::Arc,
 };
 
 use ruma::{OwnedRoomId, RoomId};
 
 use super::{EventHandlerFn, EventHandlerHandle, EventHandlerWrapper, HandlerKind};
 
+/// Handlers by event type.
+///
+/// The event types are interned by the `EventHandlerStore`, and shared with
+/// the handles of the handlers.
+type ByType = BTreeMap<Arc<str>, Vec<EventHandlerWrapper>>;
+
 #[derive(Default)]
 pub(super) struct EventHandlerMaps {
     by_kind: BTreeMap<HandlerKind, Vec<EventHandlerWrapper>>,
-    by_kind_type: BTreeMap<KindTypeWrap, Vec<EventHandlerWrapper>>,
+    by_kind_type: BTreeMap<HandlerKind, ByType>,
     by_kind_roomid: BTreeMap<KindRoomId, Vec<EventHandlerWrapper>>,
-    by_kind_type_roomid: BTreeMap<KindTypeRoomIdWrap, Vec<EventHandlerWrapper>>,
+    by_kind_type_roomid: BTreeMap<KindRoomId, ByType>,
 }
 
 impl EventHandlerMaps {
@@ -37,15 +43,19 @@ impl EventHandlerMaps {
             Key::Kind(key) => {
                 self.by_kind.entry(key).or_default().push(wrapper);
             }
-            Key::KindType(key) => {
-                self.by_kind_type.entry(key).or_default().push(wrapper);
+            Key::KindType(key, ev_type) => {
+                self.by_kind_type.entry(key).or_default().entry(ev_type).or_default().push(wrapper);
             }
             Key::KindRoomId(key) => {
                 self.by_kind_roomid.entry(key).or_default().push(wrapper);
             }
-            Key::KindTypeRoomId(key) => {
-                self.by_kind_type_roomid.entry(key).or_default().push(wrapper)
-            }
+            Key::KindTypeRoomId(key, ev_type) => self
+                .by_kind_type_roomid
+                .entry(key)
+                .or_default()
+                .entry(ev_type)
+                .or_default()
+                .push(wrapper),
         }
     }
 
@@ -55,26 +65,23 @@ impl EventHandlerMaps {
         ev_type: &str,
         room_id: Option<&'a RoomId>,
     ) -> impl Iterator<Item = (EventHandlerHandle, &'a EventHandlerFn)> + 'a {
-        // Use get_key_value instead of just get to be able to access the event_type
-        // from the BTreeMap key as &'static str, required for EventHandlerHandle.
-        let kind_kv = self.by_kind.get_key_value(&ev_kind).map(|(_, handlers)| (None, handlers));
+        // Use get_key_value instead of just get to be able to access the interned
+        // event type from the BTreeMap key, required for EventHandlerHandle.
+        let kind_kv = self.by_kind.get(&ev_kind).map(|handlers| (None, handlers));
         let kind_type_kv = self
             .by_kind_type
-            .get_key_value(&KindType { ev_kind, ev_type })
-            .map(|(key, handlers)| (Some(key.0.ev_type), handlers));
+            .get(&ev_kind)
+            .and_then(|by_type| by_type.get_key_value(ev_type))
+            .map(|(ev_type, handlers)| (Some(ev_type), handlers));
         let maybe_roomid_kvs = room_id
             .map(|r_id| {
-                let room_id = r_id.to_owned();
-                let kind_roomid_kv = self
-                    .by_kind_roomid
-                    .get_key_value(&KindRoomId { ev_kind, room_id })
-                    .map(|(_, handlers)| (None, handlers));
-
-                let room_id = r_id.to_owned();
+                let key = KindRoomId { ev_kind, room_id: r_id.to_owned() };
+                let kind_roomid_kv = self.by_kind_roomid.get(&key).map(|handlers| (None, handlers));
                 let kind_type_roomid_kv = self
                     .by_kind_type_roomid
-                    .get_key_value(&KindTypeRoomId { ev_kind, ev_type, room_id })
-                    .map(|(key, handlers)| (Some(key.0.ev_type), handlers));
+                    .get(&key)
+                    .and_then(|by_type| by_type.get_key_value(ev_type))
+                    .map(|(ev_type, handlers)| (Some(ev_type), handlers));
 
                 [kind_roomid_kv, kind_type_roomid_kv]
             })
@@ -86,7 +93,7 @@ impl EventHandlerMaps {
                 handlers.iter().map(move |wrap| {
                     let handle = EventHandlerHandle {
                         ev_kind,
-                        ev_type,
+                        ev_type: ev_type.cloned(),
                         room_id: room_id.map(ToOwned::to_owned),
                         handler_id: wrap.handler_id,
                     };
@@ -114,34 +121,50 @@ impl EventHandlerMaps {
             }
         }
 
+        fn remove_typed_entry<K: Ord>(
+            map: &mut BTreeMap<K, ByType>,
+            key: K,
+            ev_type: Arc<str>,
+            handler_id: u64,
+        ) {
+            if let btree_map::Entry::Occupied(mut o) = map.entry(key) {
+                let by_type = o.get_mut();
+                remove_entry(by_type, ev_type, handler_id);
+
+                if by_type.is_empty() {
+                    o.remove();
+                }
+            }
+        }
+
         let handler_id = handle.handler_id;
         match Key::new(handle) {
             Key::Kind(key) => {
                 remove_entry(&mut self.by_kind, key, handler_id);
             }
-            Key::KindType(key) => {
-                remove_entry(&mut self.by_kind_type, key, handler_id);
+            Key::KindType(key, ev_type) => {
+                remove_typed_entry(&mut self.by_kind_type, key, ev_type, handler_id);
             }
             Key::KindRoomId(key) => {
                 remove_entry(&mut self.by_kind_roomid, key, handler_id);
             }
-            Key::KindTypeRoomId(key) => {
-                remove_entry(&mut self.by_kind_type_roomid, key, handler_id);
+            Key::KindTypeRoomId(key, ev_type) => {
+                remove_typed_entry(&mut self.by_kind_type_roomid, key, ev_type, handler_id);
             }
         }
     }
 
     #[cfg(test)]
     pub fn len(&self) -> usize {
-        self.by_kind_type.len() + self.by_kind_type_roomid.len()
+        self.by_kind_type.values().chain(self.by_kind_type_roomid.values()).map(ByType::len).sum()
     }
 }
 
 enum Key {
     Kind(HandlerKind),
-    KindType(KindTypeWrap),
+    KindType(HandlerKind, Arc<str>),
     KindRoomId(KindRoomId),
-    KindTypeRoomId(KindTypeRoomIdWrap),
+    KindTypeRoomId(KindRoomId, Arc<str>),
 }
 
 impl Key {
@@ -149,51 +172,17 @@ impl Key {
         let EventHandlerHandle { ev_kind, ev_type, room_id, .. } = handle;
         match (ev_type, room_id) {
             (None, None) => Key::Kind(ev_kind),
-            (Some(ev_type), None) => Key::KindType(KindTypeWrap(KindType { ev_kind, ev_type })),
+            (Some(ev_type), None) => Key::KindType(ev_kind, ev_type),
             (None, Some(room_id)) => Key::KindRoomId(KindRoomId { ev_kind, room_id }),
             (Some(ev_type), Some(room_id)) => {
-                let inner = KindTypeRoomId { ev_kind, ev_type, room_id };
-                Key::KindTypeRoomId(KindTypeRoomIdWrap(inner))
+                Key::KindTypeRoomId(KindRoomId { ev_kind, room_id }, ev_type)
             }
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct KindTypeWrap(KindType<'static>);
-
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct KindType<'a> {
-    ev_kind: HandlerKind,
-    ev_type: &'a str,
-}
-
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 struct KindRoomId {
     ev_kind: HandlerKind,
     room_id: OwnedRoomId,
 }
-
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct KindTypeRoomIdWrap(KindTypeRoomId<'static>);
-
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct KindTypeRoomId<'a> {
-    ev_kind: HandlerKind,
-    ev_type: &'a str,
-    room_id: OwnedRoomId,
-}
-
-// These lifetime-generic impls are what makes it possible to obtain a
-// &'static str event type from get_key_value in call_event_handlers.
-impl<'a> Borrow<KindType<'a>> for KindTypeWrap {
-    fn borrow(&self) -> &KindType<'a> {
-        &self.0
-    }
-}
-
-impl<'a> Borrow<KindTypeRoomId<'a>> for KindTypeRoomIdWrap {
-    fn borrow(&self) -> &KindTypeRoomId<'a> {
-        &self.0
-    }
-}
@@ -35,12 +35,13 @@
 use std::any::TypeId;
 use std::{
     borrow::Cow,
+    collections::BTreeSet,
     fmt,
     future::Future,
     pin::Pin,
     sync::{
         atomic::{AtomicU64, Ordering::SeqCst},
-        RwLock,
+        Arc, RwLock,
     },
 };
 
@@ -50,7 +51,12 @@ use matrix_sdk_base::{
     deserialized_responses::{EncryptionInfo, SyncTimelineEvent},
     SendOutsideWasm, SyncOutsideWasm,
 };
-use ruma::{events::AnySyncStateEvent, push::Action, serde::Raw, OwnedRoomId};
+use ruma::{
+    events::{AnySyncStateEvent, AnySyncTimelineEvent, AnyToDeviceEvent},
+    push::Action,
+    serde::Raw,
+    OwnedRoomId, RoomId,
+};
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::value::RawValue as RawJsonValue;
 use tracing::{debug, error, field::debug, instrument, warn};
@@ -81,6 +87,8 @@ pub(crate) struct EventHandlerStore {
     handlers: RwLock<EventHandlerMaps>,
     context: RwLock<AnyMap>,
     counter: AtomicU64,
+    /// The event types of the registered handlers, shared with their handles.
+    event_types: RwLock<BTreeSet<Arc<str>>>,
 }
 
 impl EventHandlerStore {
@@ -99,6 +107,24 @@ impl EventHandlerStore {
         self.handlers.write().unwrap().remove(handle);
     }
 
+    /// Get a shared copy of the given event type, to use it in the key of a
+    /// handler.
+    ///
+    /// The event types that are not used by any handler or handle anymore are
+    /// dropped at the same time, so only the event types in use are kept.
+    fn intern_event_type(&self, event_type: &str) -> Arc<str> {
+        let mut event_types = self.event_types.write().unwrap();
+        event_types.retain(|event_type| Arc::strong_count(event_type) > 1);
+
+        if let Some(event_type) = event_types.get(event_type) {
+            return event_type.clone();
+        }
+
+        let event_type: Arc<str> = event_type.into();
+        event_types.insert(event_type.clone());
+        event_type
+    }
+
     #[cfg(test)]
     fn len(&self) -> usize {
         self.handlers.read().unwrap().len()
@@ -159,7 +185,7 @@ pub(crate) struct EventHandlerWrapper {
 #[derive(Clone, Debug)]
 pub struct EventHandlerHandle {
     pub(crate) ev_kind: HandlerKind,
-    pub(crate) ev_type: Option<&'static str>,
+    pub(crate) ev_type: Option<Arc<str>>,
     pub(crate) room_id: Option<OwnedRoomId>,
     pub(crate) handler_id: u64,
 }
@@ -290,24 +316,56 @@ impl Client {
         Ev: SyncEvent + DeserializeOwned + Send + 'static,
         H: EventHandler<Ev, Ctx>,
     {
+        let ev_type = Ev::TYPE.map(|ev_type| self.inner.event_handlers.intern_event_type(ev_type));
+        self.add_event_handler_with_key(handler, Ev::KIND, ev_type, room_id)
+    }
+
+    /// Register a handler for the timeline events with the given type, that
+    /// might not be known statically.
+    pub(crate) fn add_raw_event_handler_impl<Ctx, H>(
+        &self,
+        event_type: &str,
+        handler: H,
+        room_id: Option<OwnedRoomId>,
+    ) -> EventHandlerHandle
+    where
+        H: EventHandler<Raw<AnySyncTimelineEvent>, Ctx>,
+    {
+        let ev_type = self.inner.event_handlers.intern_event_type(event_type);
+        self.add_event_handler_with_key(handler, HandlerKind::Timeline, Some(ev_type), room_id)
+    }
+
+    fn add_event_handler_with_key<Ev, Ctx, H>(
+        &self,
+        handler: H,
+        ev_kind: HandlerKind,
+        ev_type: Option<Arc<str>>,
+        room_id: Option<OwnedRoomId>,
+    ) -> EventHandlerHandle
+    where
+        Ev: DeserializeOwned + Send + 'static,
+        H: EventHandler<Ev, Ctx>,
+    {
+        let handler_ev_type = ev_type.clone();
         let handler_fn: Box<EventHandlerFn> = Box::new(move |data| {
             let maybe_fut =
                 serde_json::from_str(data.raw.get()).map(|ev| handler.handle_event(ev, data));
+            let ev_type = handler_ev_type.clone();
 
             Box::pin(async move {
                 match maybe_fut {
                     Ok(Some(fut)) => {
-                        fut.await.print_error(Ev::TYPE);
+                        fut.await.print_error(ev_type.as_deref());
                     }
                     Ok(None) => {
                         error!(
-                            event_type = Ev::TYPE, event_kind = ?Ev::KIND,
+                            event_type = ev_type.as_deref(), event_kind = ?ev_kind,
                             "Event handler has an invalid context argument",
                         );
                     }
                     Err(e) => {
                         warn!(
-                            event_type = Ev::TYPE, event_kind = ?Ev::KIND,
+                            event_type = ev_type.as_deref(), event_kind = ?ev_kind,
                             "Failed to deserialize event, skipping event handler.\n
                              Deserialization error: {e}",
                         );
@@ -317,8 +375,7 @@ impl Client {
         });
 
         let handler_id = self.inner.event_handlers.counter.fetch_add(1, SeqCst);
-        let handle =
-            EventHandlerHandle { ev_kind: Ev::KIND, ev_type: Ev::TYPE, room_id, handler_id };
+        let handle = EventHandlerHandle { ev_kind, ev_type, room_id, handler_id };
 
         self.inner.event_handlers.add_handler(handle.clone(), handler_fn);
 
@@ -345,6 +402,47 @@ impl Client {
         Ok(())
     }
 
+    pub(crate) async fn handle_sync_to_device_events(
+        &self,
+        to_device_events: &[Raw<AnyToDeviceEvent>],
+    ) -> serde_json::Result<()> {
+        #[derive(Deserialize)]
+        struct ToDeviceEventDetails<'a> {
+            #[serde(borrow, rename = "type")]
+            event_type: Cow<'a, str>,
+            #[serde(default)]
+            content: ToDeviceContentDetails<'a>,
+        }
+
+        #[derive(Default, Deserialize)]
+        struct ToDeviceContentDetails<'a> {
+            #[serde(borrow)]
+            room_id: Option<Cow<'a, str>>,
+        }
+
+        for raw_event in to_device_events {
+            let ToDeviceEventDetails { event_type, content } = raw_event.deserialize_as()?;
+
+            // Some to-device events, like room keys, are related to a room.
+            let room = content
+                .room_id
+                .and_then(|room_id| RoomId::parse(&*room_id).ok())
+                .and_then(|room_id| self.get_room(&room_id));
+
+            self.call_event_handlers(
+                room.as_ref(),
+                raw_event.json(),
+                HandlerKind::ToDevice,
+                &event_type,
+                None,
+                &[],
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn handle_sync_state_events(
         &self,
         room: Option<&Room>,
@@ -556,13 +654,15 @@ mod tests {
     };
     use ruma::{
         events::{
+            dummy::ToDeviceDummyEvent,
             room::{
                 member::{OriginalSyncRoomMemberEvent, StrippedRoomMemberEvent},
                 name::OriginalSyncRoomNameEvent,
                 power_levels::OriginalSyncRoomPowerLevelsEvent,
             },
+            room_key::ToDeviceRoomKeyEvent,
             typing::SyncTypingEvent,
-            AnySyncStateEvent,
+            AnySyncStateEvent, AnySyncTimelineEvent,
         },
         room_id,
         serde::Raw,
@@ -833,6 +933,60 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn event_handler_for_event_type() -> crate::Result<()> {
+        let client = logged_in_client(None).await;
+        let counter = Arc::new(AtomicU8::new(0));
+        client.add_event_handler_context(counter.clone());
+        let handle = client.add_raw_event_handler(
+            "org.example.custom",
+            |ev: Raw<AnySyncTimelineEvent>, counter: Ctx<Arc<AtomicU8>>| async move {
+                assert_eq!(ev.get_field::<String>("type").unwrap().unwrap(), "org.example.custom");
+                counter.fetch_add(1, SeqCst);
+            },
+        );
+
+        let custom_event = json!({
+            "content": { "body": "Hello" },
+            "event_id": "$custom:example.org",
+            "origin_server_ts": 152037280,
+            "sender": "@alice:example.org",
+            "type": "org.example.custom",
+        });
+        let mut sync_builder = SyncResponseBuilder::default();
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::default()
+                    .add_timeline_event(TimelineTestEvent::Member)
+                    .add_timeline_event(TimelineTestEvent::Custom(custom_event.clone())),
+            )
+            .build_sync_response();
+        client.process_sync(response).await?;
+
+        assert_eq!(counter.load(SeqCst), 1);
+
+        // The handler can be removed like other handlers.
+        client.remove_event_handler(handle);
+
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::default()
+                    .add_timeline_event(TimelineTestEvent::Custom(custom_event)),
+            )
+            .build_sync_response();
+        client.process_sync(response).await?;
+
+        assert_eq!(counter.load(SeqCst), 1);
+
+        // The event type of the removed handler is not kept.
+        client.add_raw_event_handler("org.example.other", |_: Raw<AnySyncTimelineEvent>| async {});
+        let event_types = client.inner.event_handlers.event_types.read().unwrap();
+        assert!(!event_types.contains("org.example.custom"));
+        assert!(event_types.contains("org.example.other"));
+
+        Ok(())
+    }
+
     #[async_test]
     async fn enum_event_handler() -> crate::Result<()> {
         let client = logged_in_client(None).await;
@@ -854,4 +1008,61 @@ mod tests {
         assert_eq!(counter.load(SeqCst), 1);
         Ok(())
     }
+
+    #[async_test]
+    async fn to_device_event_handler() -> crate::Result<()> {
+        let client = logged_in_client(None).await;
+        let room_id = room_id!("!test:example.org");
+
+        let response = SyncResponseBuilder::default()
+            .add_joined_room(JoinedRoomBuilder::new(room_id))
+            .build_sync_response();
+        client.process_sync(response).await?;
+
+        let room_key_count = Arc::new(AtomicU8::new(0));
+        let dummy_count = Arc::new(AtomicU8::new(0));
+
+        client.add_to_device_event_handler({
+            let room_key_count = room_key_count.clone();
+            move |_ev: ToDeviceRoomKeyEvent, room: Room| {
+                assert_eq!(room.room_id(), room_id);
+                room_key_count.fetch_add(1, SeqCst);
+                future::ready(())
+            }
+        });
+        client.add_to_device_event_handler({
+            let dummy_count = dummy_count.clone();
+            move |_ev: ToDeviceDummyEvent, room: Option<Room>| {
+                assert!(room.is_none());
+                dummy_count.fetch_add(1, SeqCst);
+                future::ready(())
+            }
+        });
+
+        let to_device_events = [
+            Raw::new(&json!({
+                "content": {
+                    "algorithm": "m.megolm.v1.aes-sha2",
+                    "room_id": room_id,
+                    "session_id": "SessId",
+                    "session_key": "SessKey",
+                },
+                "sender": "@alice:example.org",
+                "type": "m.room_key",
+            }))?
+            .cast(),
+            Raw::new(&json!({
+                "content": {},
+                "sender": "@alice:example.org",
+                "type": "m.dummy",
+            }))?
+            .cast(),
+        ];
+        client.handle_sync_to_device_events(&to_device_events).await?;
+
+        assert_eq!(room_key_count.load(SeqCst), 1);
+        assert_eq!(dummy_count.load(SeqCst), 1);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,285 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client-side filtering of the room events received from the server.
+//!
+//! An event preprocessor, added with [`Client::add_event_preprocessor()`], is
+//! called with every room event received in a sync or fetched from the
+//! server, before the event is processed by the client. It can modify the
+//! event, for example to censor some words, or drop it, for example to filter
+//! spam, so that the event is consistent in the store, in the event handlers
+//! and in all the timelines.
+//!
+//! Encrypted events are given to the preprocessors before being decrypted,
+//! and again after being decrypted.
+
+pub use matrix_sdk_base::event_preprocessor::{EventPreprocessorHandle, PreprocessingAction};
+use matrix_sdk_base::{SendOutsideWasm, SyncOutsideWasm};
+use ruma::{api::client::sync::sync_events, events::AnySyncTimelineEvent, serde::Raw, RoomId};
+
+use crate::Client;
+
+impl Client {
+    /// Add a preprocessor called with every room event before it is processed
+    /// by the client.
+    ///
+    /// The preprocessors are called in the order they were added, with the
+    /// timeline events, the state events and the stripped state events of
+    /// invites received in a sync, and with the events fetched with methods
+    /// like [`Room::messages()`] or [`Room::event()`]. Encrypted events are
+    /// given to the preprocessors again once they are decrypted. An event
+    /// dropped by a preprocessor is not given to the next ones.
+    ///
+    /// The preprocessor must not add or remove preprocessors itself.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::event_preprocessor::PreprocessingAction;
+    /// # use url::Url;
+    /// # futures_executor::block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080").unwrap();
+    /// # let client = matrix_sdk::Client::new(homeserver).await.unwrap();
+    ///
+    /// // Drop the events of a spammer.
+    /// client.add_event_preprocessor(|event, _room_id| {
+    ///     if event.get_field::<String>("sender").ok().flatten().as_deref()
+    ///         == Some("@spammer:example.org")
+    ///     {
+    ///         PreprocessingAction::Drop
+    ///     } else {
+    ///         PreprocessingAction::Keep
+    ///     }
+    /// });
+    /// # });
+    /// ```
+    ///
+    /// [`Room::messages()`]: crate::Room::messages
+    /// [`Room::event()`]: crate::Room::event
+    pub fn add_event_preprocessor<F>(&self, preprocessor: F) -> EventPreprocessorHandle
+    where
+        F: Fn(&mut Raw<AnySyncTimelineEvent>, &RoomId) -> PreprocessingAction
+            + SendOutsideWasm
+            + SyncOutsideWasm
+            + 'static,
+    {
+        self.base_client().event_preprocessors().add(preprocessor)
+    }
+
+    /// Remove the event preprocessor associated with the handle.
+    pub fn remove_event_preprocessor(&self, handle: EventPreprocessorHandle) {
+        self.base_client().event_preprocessors().remove(handle);
+    }
+
+    /// Run the event preprocessors on the given event of a room.
+    ///
+    /// Returns `false` if the event must be dropped.
+    pub(crate) fn preprocess_event<T>(&self, room_id: &RoomId, event: &mut Raw<T>) -> bool {
+        self.base_client().event_preprocessors().run(event, room_id)
+    }
+
+    /// Run the event preprocessors on the given events of a room, removing the
+    /// dropped ones.
+    pub(crate) fn preprocess_events<T>(&self, room_id: &RoomId, events: &mut Vec<Raw<T>>) {
+        self.base_client().event_preprocessors().run_all(events, room_id);
+    }
+
+    /// Run the event preprocessors on the room events of the given sync
+    /// response.
+    pub(crate) fn preprocess_sync_response(&self, response: &mut sync_events::v3::Response) {
+        for (room_id, room) in &mut response.rooms.join {
+            self.preprocess_events(room_id, &mut room.state.events);
+            self.preprocess_events(room_id, &mut room.timeline.events);
+        }
+        for (room_id, room) in &mut response.rooms.leave {
+            self.preprocess_events(room_id, &mut room.state.events);
+            self.preprocess_events(room_id, &mut room.timeline.events);
+        }
+        for (room_id, room) in &mut response.rooms.invite {
+            self.preprocess_events(room_id, &mut room.invite_state.events);
+        }
+        for (room_id, room) in &mut response.rooms.knock {
+            self.preprocess_events(room_id, &mut room.knock_state.events);
+        }
+    }
+
+    /// Run the event preprocessors on the room events of the given sliding
+    /// sync response.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub(crate) fn preprocess_sliding_sync_response(
+        &self,
+        response: &mut sync_events::v4::Response,
+    ) {
+        for (room_id, room) in &mut response.rooms {
+            self.preprocess_events(room_id, &mut room.required_state);
+            self.preprocess_events(room_id, &mut room.timeline);
+            if let Some(invite_state) = &mut room.invite_state {
+                self.preprocess_events(room_id, invite_state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use matrix_sdk_test::{
+        async_test, test_json, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder,
+        TimelineTestEvent,
+    };
+    use ruma::{
+        events::{room::message::OriginalSyncRoomMessageEvent, AnySyncTimelineEvent},
+        serde::Raw,
+        RoomId,
+    };
+    use serde_json::{json, Value as JsonValue};
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::PreprocessingAction;
+    use crate::{room::MessagesOptions, test_utils::logged_in_client};
+
+    fn message(sender: &str, body: &str) -> TimelineTestEvent {
+        TimelineTestEvent::Custom(json!({
+            "content": { "body": body, "msgtype": "m.text" },
+            "event_id": format!("${body}:example.org"),
+            "origin_server_ts": 152037280,
+            "sender": sender,
+            "type": "m.room.message",
+        }))
+    }
+
+    /// Drop the events of a spammer.
+    fn drop_spam(event: &mut Raw<AnySyncTimelineEvent>, _room_id: &RoomId) -> PreprocessingAction {
+        if event.get_field::<String>("sender").unwrap().as_deref() == Some("@spammer:example.org") {
+            PreprocessingAction::Drop
+        } else {
+            PreprocessingAction::Keep
+        }
+    }
+
+    /// Censor a word.
+    fn censor(event: &mut Raw<AnySyncTimelineEvent>, _room_id: &RoomId) -> PreprocessingAction {
+        let mut json = event.deserialize_as::<JsonValue>().unwrap();
+        if let Some(JsonValue::String(body)) = json.pointer_mut("/content/body") {
+            *body = body.replace("heck", "h**k");
+        }
+        *event = Raw::new(&json).unwrap().cast();
+        PreprocessingAction::Keep
+    }
+
+    #[async_test]
+    async fn drop_and_modify_events() -> crate::Result<()> {
+        let client = logged_in_client(None).await;
+
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        client.add_event_handler({
+            let bodies = bodies.clone();
+            move |ev: OriginalSyncRoomMessageEvent| {
+                bodies.lock().unwrap().push(ev.content.body().to_owned());
+                async {}
+            }
+        });
+
+        client.add_event_preprocessor(drop_spam);
+        let handle = client.add_event_preprocessor(censor);
+
+        let mut sync_builder = SyncResponseBuilder::default();
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::default()
+                    .add_state_event(StateTestEvent::Custom(json!({
+                        "content": { "topic": "Buy my stuff" },
+                        "event_id": "$topic:example.org",
+                        "origin_server_ts": 152037280,
+                        "sender": "@spammer:example.org",
+                        "state_key": "",
+                        "type": "m.room.topic",
+                    })))
+                    .add_timeline_event(message("@spammer:example.org", "spam"))
+                    .add_timeline_event(message("@alice:example.org", "what-the-heck")),
+            )
+            .build_sync_response();
+        client.process_sync(response).await?;
+
+        assert_eq!(*bodies.lock().unwrap(), ["what-the-h**k"]);
+
+        // The state events are preprocessed too.
+        let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+        assert_eq!(room.topic(), None);
+
+        // Removed preprocessors are not called anymore.
+        client.remove_event_preprocessor(handle);
+
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::default()
+                    .add_timeline_event(message("@alice:example.org", "heck")),
+            )
+            .build_sync_response();
+        client.process_sync(response).await?;
+
+        assert_eq!(*bodies.lock().unwrap(), ["what-the-h**k", "heck"]);
+
+        Ok(())
+    }
+    #[async_test]
+    async fn preprocess_messages() -> crate::Result<()> {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        client.add_event_preprocessor(drop_spam);
+        client.add_event_preprocessor(censor);
+
+        let response = SyncResponseBuilder::default()
+            .add_joined_room(JoinedRoomBuilder::default())
+            .build_sync_response();
+        client.process_sync(response).await?;
+        let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+        let event = |sender: &str, body: &str| {
+            json!({
+                "content": { "body": body, "msgtype": "m.text" },
+                "event_id": format!("${body}:example.org"),
+                "origin_server_ts": 152037280,
+                "room_id": room.room_id(),
+                "sender": sender,
+                "type": "m.room.message",
+            })
+        };
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "start": "start",
+                "end": "end",
+                "chunk": [
+                    event("@alice:example.org", "what-the-heck"),
+                    event("@spammer:example.org", "spam"),
+                ],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let messages = room.messages(MessagesOptions::backward()).await?;
+
+        assert_eq!(messages.chunk.len(), 1);
+        let event = messages.chunk[0].event.deserialize_as::<OriginalSyncRoomMessageEvent>()?;
+        assert_eq!(event.content.body(), "what-the-h**k");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Formatting of the body of outgoing messages.
+//!
+//! The plain text body of a message can be converted to HTML with a
+//! [`MessageFormatter`], set with [`ClientBuilder::message_formatter()`]. With
+//! the `markdown` feature, the [`MarkdownFormatter`] is used by default, and
+//! can be configured, or replaced by a formatter supporting another markup
+//! language.
+//!
+//! [`ClientBuilder::message_formatter()`]: crate::ClientBuilder::message_formatter
+
+use std::fmt::Debug;
+
+#[cfg(feature = "markdown")]
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+use ruma::events::room::message::{
+    EmoteMessageEventContent, FormattedBody, MessageType, NoticeMessageEventContent,
+    RoomMessageEventContent, TextMessageEventContent,
+};
+
+use crate::Client;
+
+/// A converter of the plain text body of outgoing messages to HTML.
+pub trait MessageFormatter: Debug + Send + Sync {
+    /// Convert the given plain text body to HTML.
+    ///
+    /// Returns `None` if the body doesn't contain any markup, in which case
+    /// the message is sent as plain text only.
+    fn format(&self, body: &str) -> Option<String>;
+}
+
+/// The default [`MessageFormatter`], converting [CommonMark] to HTML.
+///
+/// Without any option, the conversion is the same as
+/// [`RoomMessageEventContent::text_markdown()`]: tables and strikethrough are
+/// supported, and single line breaks are kept.
+///
+/// [CommonMark]: https://commonmark.org
+#[cfg(feature = "markdown")]
+#[derive(Clone, Debug)]
+pub struct MarkdownFormatter {
+    images: bool,
+    spoilers: bool,
+}
+
+#[cfg(feature = "markdown")]
+impl MarkdownFormatter {
+    /// Create a new `MarkdownFormatter` with the default options.
+    pub fn new() -> Self {
+        Self { images: true, spoilers: false }
+    }
+
+    /// Whether to convert images, defaults to `true`.
+    ///
+    /// If disabled, only the alternative text of images is kept.
+    pub fn images(mut self, enabled: bool) -> Self {
+        self.images = enabled;
+        self
+    }
+
+    /// Whether to convert text surrounded by `||` to a spoiler, defaults to
+    /// `false`.
+    pub fn spoilers(mut self, enabled: bool) -> Self {
+        self.spoilers = enabled;
+        self
+    }
+}
+
+#[cfg(feature = "markdown")]
+impl Default for MarkdownFormatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "markdown")]
+impl MessageFormatter for MarkdownFormatter {
+    fn format(&self, body: &str) -> Option<String> {
+        const OPTIONS: Options = Options::ENABLE_TABLES.union(Options::ENABLE_STRIKETHROUGH);
+
+        let mut events: Vec<Event<'_>> = Vec::new();
+        for event in Parser::new_ext(body, OPTIONS) {
+            match event {
+                Event::SoftBreak => events.push(Event::HardBreak),
+                Event::Start(Tag::Image(..)) | Event::End(Tag::Image(..)) if !self.images => {}
+                // Merge consecutive text, so spoiler delimiters are found even
+                // if the parser splits the text around them.
+                Event::Text(text) => match events.last_mut() {
+                    Some(Event::Text(last)) => *last = format!("{last}{text}").into(),
+                    _ => events.push(Event::Text(text)),
+                },
+                event => events.push(event),
+            }
+        }
+
+        if self.spoilers {
+            let mut in_code_block = false;
+            events = events
+                .into_iter()
+                .flat_map(|event| {
+                    match &event {
+                        Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                        Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+                        Event::Text(text) if !in_code_block => {
+                            return spoiler_events(text);
+                        }
+                        _ => {}
+                    }
+                    vec![event]
+                })
+                .collect();
+        }
+
+        // Only convert the body if it has markup other than text in
+        // paragraphs.
+        let mut paragraphs = 0;
+        let has_markup = events.iter().any(|event| match event {
+            Event::Text(_) | Event::HardBreak | Event::End(Tag::Paragraph) => false,
+            Event::Start(Tag::Paragraph) => {
+                paragraphs += 1;
+                paragraphs > 1
+            }
+            _ => true,
+        });
+
+        if !has_markup {
+            return None;
+        }
+
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        Some(html)
+    }
+}
+
+/// Split the given text around pairs of `||`, with the text between
+/// them wrapped in a spoiler.
+#[cfg(feature = "markdown")]
+fn spoiler_events<'a>(text: &str) -> Vec<Event<'a>> {
+    let parts: Vec<_> = text.split("||").collect();
+    // An odd number of parts means all delimiters are paired.
+    let paired = if parts.len() % 2 == 1 { parts.len() } else { parts.len() - 1 };
+
+    let mut events = Vec::new();
+    let mut text = String::new();
+    for (index, part) in parts.iter().enumerate() {
+        if index > 0 && index < paired {
+            if !text.is_empty() {
+                events.push(Event::Text(std::mem::take(&mut text).into()));
+            }
+            events.push(Event::Html(CowStr::Borrowed(if index % 2 == 1 {
+                "<span data-mx-spoiler>"
+            } else {
+                "</span>"
+            })));
+        } else if index > 0 {
+            text.push_str("||");
+        }
+        text.push_str(part);
+    }
+    if !text.is_empty() {
+        events.push(Event::Text(text.into()));
+    }
+
+    events
+}
+
+impl Client {
+    /// Convert the given plain text body of a message to HTML, with the
+    /// [`MessageFormatter`] of this client.
+    ///
+    /// Returns `None` if no formatter is set, or if the body doesn't contain
+    /// any markup.
+    pub fn format_body(&self, body: &str) -> Option<FormattedBody> {
+        let formatter = self.inner.message_formatter.as_deref()?;
+        formatter.format(body).map(FormattedBody::html)
+    }
+
+    /// Create a text message with the given body, converted to HTML with the
+    /// [`MessageFormatter`] of this client.
+    ///
+    /// This is equivalent to `RoomMessageEventContent::text_markdown()`,
+    /// except the conversion can be customized with
+    /// [`ClientBuilder::message_formatter()`].
+    ///
+    /// [`ClientBuilder::message_formatter()`]: crate::ClientBuilder::message_formatter
+    pub fn text_markdown(&self, body: impl Into<String>) -> RoomMessageEventContent {
+        self.markdown_content(MessageType::Text(TextMessageEventContent::plain(body)))
+    }
+
+    /// Create a notice with the given body, converted to HTML with the
+    /// [`MessageFormatter`] of this client.
+    ///
+    /// See [`Client::text_markdown()`] for more details.
+    pub fn notice_markdown(&self, body: impl Into<String>) -> RoomMessageEventContent {
+        self.markdown_content(MessageType::Notice(NoticeMessageEventContent::plain(body)))
+    }
+
+    /// Create an emote with the given body, converted to HTML with the
+    /// [`MessageFormatter`] of this client.
+    ///
+    /// See [`Client::text_markdown()`] for more details.
+    pub fn emote_markdown(&self, body: impl Into<String>) -> RoomMessageEventContent {
+        self.markdown_content(MessageType::Emote(EmoteMessageEventContent::plain(body)))
+    }
+
+    /// Add the HTML body converted from the plain text body to the given
+    /// message.
+    fn markdown_content(&self, mut msgtype: MessageType) -> RoomMessageEventContent {
+        let formatted = self.format_body(msgtype.body());
+
+        match &mut msgtype {
+            MessageType::Text(content) => content.formatted = formatted,
+            MessageType::Notice(content) => content.formatted = formatted,
+            MessageType::Emote(content) => content.formatted = formatted,
+            _ => {}
+        }
+
+        RoomMessageEventContent::new(msgtype)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use matrix_sdk_test::async_test;
+    use ruma::events::room::message::MessageType;
+
+    use super::MessageFormatter;
+    use crate::test_utils::test_client_builder;
+
+    #[derive(Debug)]
+    struct ShoutFormatter;
+
+    impl MessageFormatter for ShoutFormatter {
+        fn format(&self, body: &str) -> Option<String> {
+            body.ends_with('!').then(|| format!("<strong>{body}</strong>"))
+        }
+    }
+
+    #[async_test]
+    async fn custom_formatter() {
+        let client =
+            test_client_builder(None).message_formatter(ShoutFormatter).build().await.unwrap();
+
+        let content = client.text_markdown("Hello!");
+        let text = assert_matches!(content.msgtype, MessageType::Text(text) => text);
+        assert_eq!(text.body, "Hello!");
+        assert_eq!(text.formatted.unwrap().body, "<strong>Hello!</strong>");
+
+        let content = client.notice_markdown("Hello");
+        let notice = assert_matches!(content.msgtype, MessageType::Notice(notice) => notice);
+        assert_eq!(notice.body, "Hello");
+        assert_matches!(notice.formatted, None);
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn markdown_formatter() {
+        use super::MarkdownFormatter;
+
+        let formatter = MarkdownFormatter::new();
+        assert_eq!(formatter.format("Hello\nworld"), None);
+        assert_eq!(formatter.format("**Hello**").unwrap(), "<p><strong>Hello</strong></p>\n");
+        assert_eq!(
+            formatter.format("![An image](mxc://localhost/image)").unwrap(),
+            "<p><img src=\"mxc://localhost/image\" alt=\"An image\" /></p>\n"
+        );
+        assert_eq!(formatter.format("A ||secret||"), None);
+
+        let formatter = MarkdownFormatter::new().images(false).spoilers(true);
+        assert_eq!(formatter.format("![An image](mxc://localhost/image)"), None);
+        assert_eq!(
+            formatter.format("A ||secret|| and `||code||` ||").unwrap(),
+            "<p>A <span data-mx-spoiler>secret</span> and <code>||code||</code> ||</p>\n"
+        );
+    }
+}
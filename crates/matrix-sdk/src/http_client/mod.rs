@@ -17,7 +17,7 @@ use std::{
     fmt::Debug,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, RwLock as StdRwLock,
     },
     time::Duration,
 };
@@ -25,16 +25,18 @@ use std::{
 use bytes::{Bytes, BytesMut};
 use bytesize::ByteSize;
 use eyeball::SharedObservable;
+use matrix_sdk_common::instant::Instant;
 use ruma::{
     api::{
         error::{FromHttpResponseError, IntoHttpError},
-        AuthScheme, MatrixVersion, OutgoingRequest, OutgoingRequestAppserviceExt, SendAccessToken,
+        AuthScheme, MatrixVersion, Metadata, OutgoingRequest, OutgoingRequestAppserviceExt,
+        SendAccessToken,
     },
     UserId,
 };
 use tracing::{debug, field::debug, instrument, trace};
 
-use crate::{config::RequestConfig, error::HttpError};
+use crate::{config::RequestConfig, error::HttpError, RumaApiError};
 
 #[cfg(not(target_arch = "wasm32"))]
 mod native;
@@ -46,16 +48,81 @@ pub(crate) use native::HttpSettings;
 
 pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
-#[derive(Clone, Debug)]
+/// A callback called with the information about every request sent to the
+/// homeserver.
+pub(crate) type RequestObserver = Arc<dyn Fn(&RequestInfo) + Send + Sync>;
+
+#[derive(Clone)]
 pub(crate) struct HttpClient {
     pub(crate) inner: reqwest::Client,
     pub(crate) request_config: RequestConfig,
+    /// Whether the requests that change anything visible to other users are
+    /// rejected.
+    pub(crate) read_only: bool,
     next_request_id: Arc<AtomicU64>,
+    request_observers: Arc<StdRwLock<Vec<RequestObserver>>>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl Debug for HttpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpClient")
+            .field("inner", &self.inner)
+            .field("request_config", &self.request_config)
+            .field("read_only", &self.read_only)
+            .finish_non_exhaustive()
+    }
 }
 
 impl HttpClient {
     pub(crate) fn new(inner: reqwest::Client, request_config: RequestConfig) -> Self {
-        HttpClient { inner, request_config, next_request_id: AtomicU64::new(0).into() }
+        HttpClient {
+            inner,
+            request_config,
+            read_only: false,
+            next_request_id: AtomicU64::new(0).into(),
+            request_observers: Default::default(),
+        }
+    }
+
+    pub(crate) fn add_request_observer(&self, observer: RequestObserver) {
+        self.request_observers.write().unwrap().push(observer);
+    }
+
+    /// Call the request observers with the result of an attempt at sending the
+    /// given request.
+    pub(super) fn notify_request_observers<R: OutgoingRequest>(
+        &self,
+        request_id: &str,
+        request: &http::Request<Bytes>,
+        started_at: Instant,
+        result: Result<u16, &HttpError>,
+    ) {
+        // Clone the list, so an observer can add another observer without
+        // deadlocking.
+        let observers = self.request_observers.read().unwrap().clone();
+        if observers.is_empty() {
+            return;
+        }
+
+        let (status, error) = match result {
+            Ok(status) => (Some(status), None),
+            Err(error) => (error_status_code(error), Some(RequestErrorKind::from_error(error))),
+        };
+
+        let info = RequestInfo {
+            request_id: request_id.to_owned(),
+            method: request.method().to_string(),
+            path: sanitized_path(&R::METADATA, request.uri().path()),
+            request_size: request.body().len(),
+            status,
+            duration: started_at.elapsed(),
+            error,
+        };
+
+        for observer in observers {
+            observer(&info);
+        }
     }
 
     fn get_request_id(&self) -> String {
@@ -140,19 +207,24 @@ impl HttpClient {
         HttpError: From<FromHttpResponseError<R::EndpointError>>,
     {
         let config = match config {
-            Some(config) => config,
+            // A client asserting the identity of a user must do it for all of its
+            // requests, not only the ones using the default config.
+            Some(mut config) => {
+                config.assert_identity |= self.request_config.assert_identity;
+                config
+            }
             None => self.request_config,
         };
 
         // Keep some local variables in a separate scope so the compiler doesn't include
         // them in the future type. https://github.com/rust-lang/rust/issues/57478
+        let request_id = self.get_request_id();
         let request = {
-            let request_id = self.get_request_id();
             let span = tracing::Span::current();
 
             // At this point in the code, the config isn't behind an Option anymore, that's
             // why we record it here, instead of in the #[instrument] macro.
-            span.record("config", debug(config)).record("request_id", request_id);
+            span.record("config", debug(config)).record("request_id", request_id.as_str());
 
             // The user ID is only used if we're an app-service. Only log the user_id if
             // it's `Some` and if assert_identity is set.
@@ -165,6 +237,10 @@ impl HttpClient {
                 return Err(HttpError::NotClientRequest);
             }
 
+            if self.read_only && !is_allowed_in_read_only(&R::METADATA) {
+                return Err(HttpError::ReadOnly);
+            }
+
             let request = self.serialize_request(
                 request,
                 config,
@@ -199,7 +275,7 @@ impl HttpClient {
 
         // There's a bunch of state in send_request, factor out a pinned inner
         // future to reduce this size of futures that await this function.
-        match Box::pin(self.send_request::<R>(request, config, send_progress)).await {
+        match Box::pin(self.send_request::<R>(request, &request_id, config, send_progress)).await {
             Ok(response) => {
                 debug!("Got response");
                 Ok(response)
@@ -212,6 +288,151 @@ impl HttpClient {
     }
 }
 
+/// The state of the connectivity to the homeserver, as observed from the
+/// requests sent to it.
+///
+/// Get it with [`Client::network_state()`].
+///
+/// [`Client::network_state()`]: crate::Client::network_state
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NetworkState {
+    /// The last request reached the homeserver, whether it succeeded or not.
+    #[default]
+    Online,
+    /// The last request couldn't reach the homeserver.
+    Offline,
+}
+
+/// Information about an attempt at sending a request to the homeserver, given
+/// to the observers added with [`Client::add_request_observer()`].
+///
+/// It doesn't contain the headers, the query string, the body or the variable
+/// parts of the path of the request, which might contain secrets or personal
+/// data.
+///
+/// [`Client::add_request_observer()`]: crate::Client::add_request_observer
+#[derive(Clone, Debug)]
+pub struct RequestInfo {
+    /// The ID of the request, as it appears in the logs.
+    ///
+    /// All the attempts at sending the same request share the same ID.
+    pub request_id: String,
+    /// The HTTP method of the request.
+    pub method: String,
+    /// The path template of the endpoint of the request, e.g.
+    /// `/_matrix/client/v3/rooms/:room_id/joined_members`.
+    ///
+    /// The variable parts of the path, like user or room IDs, are not included.
+    pub path: String,
+    /// The size of the body of the request, in bytes.
+    pub request_size: usize,
+    /// The HTTP status code of the response, if a response was received.
+    pub status: Option<u16>,
+    /// How long it took to get the response, or the error.
+    pub duration: Duration,
+    /// The kind of error, if the attempt failed.
+    pub error: Option<RequestErrorKind>,
+}
+
+/// The kind of error of a failed attempt at sending a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestErrorKind {
+    /// The homeserver couldn't be reached.
+    Network,
+    /// The homeserver responded with an error.
+    Server,
+    /// The request couldn't be sent or the response couldn't be read for
+    /// another reason.
+    Other,
+}
+
+impl RequestErrorKind {
+    fn from_error(error: &HttpError) -> Self {
+        if error.is_network_error() {
+            Self::Network
+        } else if error.as_ruma_api_error().is_some() {
+            Self::Server
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// The ends of the path templates of the endpoints that are allowed in
+/// read-only mode despite not using the `GET` method, because they don't
+/// change anything visible to other users.
+///
+/// They are matched against the path templates of the endpoints rather than
+/// against the paths of the requests, so a variable part of a path can't be
+/// used to pass for one of them.
+const READ_ONLY_ALLOWED_PATH_SUFFIXES: &[&str] = &[
+    "/login",
+    "/logout",
+    "/logout/all",
+    "/refresh",
+    // Sliding sync uses `POST`.
+    "/sync",
+    "/filter",
+    "/keys/query",
+    "/keys/upload",
+    "/search",
+    "/user_directory/search",
+    "/publicRooms",
+];
+
+/// Whether the endpoint with the given metadata is allowed in read-only mode.
+fn is_allowed_in_read_only(metadata: &Metadata) -> bool {
+    if matches!(metadata.method, http::Method::GET | http::Method::HEAD) {
+        return true;
+    }
+
+    metadata
+        .history
+        .all_paths()
+        .any(|path| READ_ONLY_ALLOWED_PATH_SUFFIXES.iter().any(|suffix| path.ends_with(suffix)))
+}
+
+/// Get the path template of the endpoint of the given metadata that matches the
+/// given request path, e.g. `/_matrix/client/v3/rooms/:room_id/joined_members`.
+///
+/// The variable parts of the path can contain user IDs, room IDs or other
+/// identifiers, so they are not given to the request observers.
+fn sanitized_path(metadata: &Metadata, path: &str) -> String {
+    let is_variable = |segment: &str| segment.starts_with(':') || segment.starts_with('{');
+    let segments: Vec<_> = path.split('/').collect();
+
+    let matches = |template: &&str| {
+        let template_segments: Vec<_> = template.split('/').collect();
+
+        template_segments.len() == segments.len()
+            && template_segments
+                .iter()
+                .zip(&segments)
+                .all(|(template, segment)| is_variable(template) || template == segment)
+    };
+
+    // The path was built from one of the templates, so one should always match.
+    metadata
+        .history
+        .all_paths()
+        .find(matches)
+        .or_else(|| metadata.history.all_paths().next())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// The HTTP status code of the response that resulted in the given error, if
+/// any.
+fn error_status_code(error: &HttpError) -> Option<u16> {
+    let status_code = match error.as_ruma_api_error()? {
+        RumaApiError::ClientApi(e) => e.status_code,
+        RumaApiError::Uiaa(_) => http::StatusCode::UNAUTHORIZED,
+        RumaApiError::Other(e) => e.status_code,
+    };
+
+    Some(status_code.as_u16())
+}
+
 /// Progress of sending or receiving a payload.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct TransmissionProgress {
@@ -221,6 +442,17 @@ pub struct TransmissionProgress {
     pub total: usize,
 }
 
+// Clones all request parts except the extensions which can't be cloned.
+// See also https://github.com/hyperium/http/issues/395
+pub(super) fn clone_request(request: &http::Request<Bytes>) -> http::Request<Bytes> {
+    let mut builder = http::Request::builder()
+        .version(request.version())
+        .method(request.method())
+        .uri(request.uri());
+    *builder.headers_mut().unwrap() = request.headers().clone();
+    builder.body(request.body().clone()).unwrap()
+}
+
 async fn response_to_http_response(
     mut response: reqwest::Response,
 ) -> Result<http::Response<Bytes>, reqwest::Error> {
@@ -23,21 +23,107 @@ use backoff::{future::retry, Error as RetryError, ExponentialBackoff};
 use bytes::Bytes;
 use bytesize::ByteSize;
 use eyeball::SharedObservable;
-use http::header::CONTENT_LENGTH;
+use http::header::{CONTENT_LENGTH, RANGE};
+use matrix_sdk_common::instant::Instant;
 use ruma::api::{
     client::error::{ErrorBody as ClientApiErrorBody, ErrorKind as ClientApiErrorKind},
     error::FromHttpResponseError,
-    IncomingResponse, OutgoingRequest,
+    IncomingResponse, MatrixVersion, OutgoingRequest,
 };
 use tracing::{info, warn};
 
-use super::{response_to_http_response, HttpClient, TransmissionProgress, DEFAULT_REQUEST_TIMEOUT};
+use super::{
+    clone_request, response_to_http_response, HttpClient, TransmissionProgress,
+    DEFAULT_REQUEST_TIMEOUT,
+};
 use crate::{config::RequestConfig, error::HttpError, RumaApiError};
 
 impl HttpClient {
+    /// Send the given request and return the response as soon as its headers
+    /// are received, so its body can be streamed.
+    ///
+    /// If `range_start` is set, only the bytes of the response body starting
+    /// at this offset are requested, with an HTTP `Range` header. The server
+    /// might ignore it and respond with the full body.
+    ///
+    /// Contrary to [`HttpClient::send()`], the request is not retried on
+    /// failure.
+    pub(crate) async fn send_streaming<R>(
+        &self,
+        request: R,
+        config: RequestConfig,
+        homeserver: String,
+        access_token: Option<&str>,
+        server_versions: &[MatrixVersion],
+        range_start: Option<u64>,
+    ) -> Result<reqwest::Response, HttpError>
+    where
+        R: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<R::EndpointError>>,
+    {
+        let mut request = self.serialize_request(
+            request,
+            config,
+            homeserver,
+            access_token,
+            None,
+            server_versions,
+        )?;
+
+        if let Some(range_start) = range_start.filter(|start| *start > 0) {
+            let range = format!("bytes={range_start}-")
+                .try_into()
+                .expect("the range header value should be valid");
+            request.headers_mut().insert(RANGE, range);
+        }
+
+        let request_id = self.get_request_id();
+        let started_at = Instant::now();
+
+        let response = self.execute_streaming::<R>(clone_request(&request), config).await;
+        self.notify_request_observers::<R>(
+            &request_id,
+            &request,
+            started_at,
+            response.as_ref().map(|response| response.status().as_u16()),
+        );
+
+        response
+    }
+
+    /// Send the given request and return the response as soon as its headers
+    /// are received, or the error from the homeserver.
+    async fn execute_streaming<R>(
+        &self,
+        request: http::Request<Bytes>,
+        config: RequestConfig,
+    ) -> Result<reqwest::Response, HttpError>
+    where
+        R: OutgoingRequest,
+        HttpError: From<FromHttpResponseError<R::EndpointError>>,
+    {
+        let mut request = reqwest::Request::try_from(request)?;
+        *request.timeout_mut() = Some(config.timeout);
+
+        let response = self.inner.execute(request).await?;
+
+        if let Some(status_error) = response.error_for_status_ref().err() {
+            // Let ruma deserialize the error from the response body, and fall
+            // back to the error status code if it can't.
+            let response = response_to_http_response(response).await?;
+            return Err(match R::IncomingResponse::try_from_http_response(response) {
+                Err(error) => error.into(),
+                Ok(_) => status_error.into(),
+            });
+        }
+
+        Ok(response)
+    }
+
     pub(super) async fn send_request<R>(
         &self,
         request: http::Request<Bytes>,
+        request_id: &str,
         config: RequestConfig,
         send_progress: SharedObservable<TransmissionProgress>,
     ) -> Result<R::IncomingResponse, HttpError>
@@ -45,8 +131,14 @@ impl HttpClient {
         R: OutgoingRequest + Debug,
         HttpError: From<FromHttpResponseError<R::EndpointError>>,
     {
-        let backoff =
-            ExponentialBackoff { max_elapsed_time: config.retry_timeout, ..Default::default() };
+        let backoff = ExponentialBackoff {
+            current_interval: config.retry_initial_delay,
+            initial_interval: config.retry_initial_delay,
+            max_interval: config.retry_max_delay,
+            randomization_factor: config.retry_jitter,
+            max_elapsed_time: config.retry_timeout,
+            ..Default::default()
+        };
         let retry_count = AtomicU64::new(1);
 
         let send_request = || {
@@ -69,7 +161,7 @@ impl HttpClient {
                                     ClientApiErrorBody::Standard {
                                         kind: ClientApiErrorKind::LimitExceeded { retry_after_ms },
                                         ..
-                                    } => {
+                                    } if config.retry_on_rate_limit => {
                                         return RetryError::Transient {
                                             err,
                                             retry_after: retry_after_ms,
@@ -92,9 +184,19 @@ impl HttpClient {
                     }
                 };
 
-                let response = send_request(&self.inner, &request, config.timeout, send_progress)
-                    .await
-                    .map_err(error_type)?;
+                let started_at = Instant::now();
+
+                let response =
+                    send_request(&self.inner, &request, config.timeout, send_progress).await;
+                if let Err(error) = &response {
+                    self.notify_request_observers::<R>(
+                        request_id,
+                        &request,
+                        started_at,
+                        Err(error),
+                    );
+                }
+                let response = response.map_err(error_type)?;
 
                 let status_code = response.status();
                 let response_size = ByteSize(response.body().len().try_into().unwrap_or(u64::MAX));
@@ -102,8 +204,16 @@ impl HttpClient {
                     .record("status", status_code.as_u16())
                     .record("response_size", response_size.to_string_as(true));
 
-                R::IncomingResponse::try_from_http_response(response)
-                    .map_err(|e| error_type(HttpError::from(e)))
+                let response =
+                    R::IncomingResponse::try_from_http_response(response).map_err(HttpError::from);
+                self.notify_request_observers::<R>(
+                    request_id,
+                    &request,
+                    started_at,
+                    response.as_ref().map(|_| status_code.as_u16()),
+                );
+
+                response.map_err(error_type)
             }
         };
 
@@ -203,17 +313,6 @@ pub(super) async fn send_request(
     Ok(response_to_http_response(response).await?)
 }
 
-// Clones all request parts except the extensions which can't be cloned.
-// See also https://github.com/hyperium/http/issues/395
-fn clone_request(request: &http::Request<Bytes>) -> http::Request<Bytes> {
-    let mut builder = http::Request::builder()
-        .version(request.version())
-        .method(request.method())
-        .uri(request.uri());
-    *builder.headers_mut().unwrap() = request.headers().clone();
-    builder.body(request.body().clone()).unwrap()
-}
-
 struct BytesChunks {
     bytes: Bytes,
     size: usize,
@@ -17,15 +17,17 @@ use std::fmt::Debug;
 use bytes::Bytes;
 use bytesize::ByteSize;
 use eyeball::SharedObservable;
+use matrix_sdk_common::instant::Instant;
 use ruma::api::{error::FromHttpResponseError, IncomingResponse, OutgoingRequest};
 
-use super::{response_to_http_response, HttpClient, TransmissionProgress};
+use super::{clone_request, response_to_http_response, HttpClient, TransmissionProgress};
 use crate::{config::RequestConfig, error::HttpError};
 
 impl HttpClient {
     pub(super) async fn send_request<R>(
         &self,
         request: http::Request<Bytes>,
+        request_id: &str,
         _config: RequestConfig,
         _send_progress: SharedObservable<TransmissionProgress>,
     ) -> Result<R::IncomingResponse, HttpError>
@@ -33,8 +35,13 @@ impl HttpClient {
         R: OutgoingRequest + Debug,
         HttpError: From<FromHttpResponseError<R::EndpointError>>,
     {
-        let request = reqwest::Request::try_from(request)?;
-        let response = response_to_http_response(self.inner.execute(request).await?).await?;
+        let started_at = Instant::now();
+
+        let response = self.execute(&request).await;
+        if let Err(error) = &response {
+            self.notify_request_observers::<R>(request_id, &request, started_at, Err(error));
+        }
+        let response = response?;
 
         let status_code = response.status();
         let response_size = ByteSize(response.body().len().try_into().unwrap_or(u64::MAX));
@@ -42,6 +49,23 @@ impl HttpClient {
             .record("status", status_code.as_u16())
             .record("response_size", response_size.to_string_as(true));
 
-        Ok(R::IncomingResponse::try_from_http_response(response)?)
+        let response =
+            R::IncomingResponse::try_from_http_response(response).map_err(HttpError::from);
+        self.notify_request_observers::<R>(
+            request_id,
+            &request,
+            started_at,
+            response.as_ref().map(|_| status_code.as_u16()),
+        );
+
+        response
+    }
+
+    async fn execute(
+        &self,
+        request: &http::Request<Bytes>,
+    ) -> Result<http::Response<Bytes>, HttpError> {
+        let request = reqwest::Request::try_from(clone_request(request))?;
+        Ok(response_to_http_response(self.inner.execute(request).await?).await?)
     }
 }
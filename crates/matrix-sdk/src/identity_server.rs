@@ -0,0 +1,467 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access to an [identity server], used to invite users by their email
+//! address and to discover users by their third-party identifiers.
+//!
+//! The identity server is configured with [`Client::set_identity_server()`].
+//! Before it can be used, the user must accept its terms of service:
+//!
+//! 1. Get the policies with [`IdentityServerClient::terms()`] and show them to
+//!    the user.
+//! 2. If the user agrees, accept them with
+//!    [`IdentityServerClient::accept_terms()`].
+//!
+//! Requests sent to an identity server whose terms were not accepted fail
+//! with an `M_TERMS_NOT_SIGNED` error.
+//!
+//! [identity server]: https://spec.matrix.org/v1.2/identity-service-api/
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ruma::{
+    api::{
+        client::account::request_openid_token,
+        error::FromHttpResponseError,
+        identity_service::{
+            authentication::{logout, register},
+            invitation::store_invitation,
+            lookup::{get_hash_parameters, lookup_3pid, IdentifierHashingAlgorithm},
+            tos::{accept_terms_of_service, get_terms_of_service},
+        },
+        MatrixVersion, OutgoingRequest,
+    },
+    thirdparty::Medium,
+    OwnedUserId, RoomId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{Client, Error, HttpError, HttpResult, Result};
+
+/// The error code returned by the identity server when the pepper of a lookup
+/// is outdated.
+const INVALID_PEPPER_ERRCODE: &str = "M_INVALID_PEPPER";
+
+/// The key of the session with the identity server in the state store.
+const SESSION_KEY: &[u8] = b"identity_server.session";
+
+/// The parameters to hash the third-party identifiers for a lookup.
+#[derive(Clone, Debug)]
+struct HashDetails {
+    /// The pepper to append to the identifiers before hashing them.
+    pepper: String,
+    /// The hashing algorithms supported by the identity server.
+    algorithms: Vec<IdentifierHashingAlgorithm>,
+}
+
+/// The data of a session with an identity server.
+#[derive(Debug)]
+pub(crate) struct IdentityServerSession {
+    base_url: Url,
+    access_token: String,
+    /// The hash details of the identity server, fetched before the first
+    /// lookup and when the pepper changes.
+    hash_details: Mutex<Option<HashDetails>>,
+}
+
+/// The data of a session with an identity server, as it is persisted in the
+/// store.
+#[derive(Serialize, Deserialize)]
+struct StoredIdentityServerSession {
+    base_url: String,
+    access_token: String,
+}
+
+/// A client for an identity server.
+///
+/// It holds the access token that the homeserver and the identity server
+/// need to look up third-party identifiers on behalf of the user.
+///
+/// Get it with [`Client::set_identity_server()`] or
+/// [`Client::identity_server()`].
+#[derive(Clone)]
+pub struct IdentityServerClient {
+    client: Client,
+    session: Arc<IdentityServerSession>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for IdentityServerClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityServerClient")
+            .field("base_url", &self.session.base_url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A third-party identifier that was found on the identity server.
+#[derive(Clone, Debug)]
+pub struct LookupMatch {
+    /// The type of the third-party identifier.
+    pub medium: Medium,
+    /// The third-party identifier, as it was passed to
+    /// [`IdentityServerClient::lookup()`].
+    pub address: String,
+    /// The ID of the Matrix user the third-party identifier is bound to.
+    pub user_id: OwnedUserId,
+}
+
+impl IdentityServerClient {
+    /// The URL of the identity server.
+    pub fn base_url(&self) -> &Url {
+        &self.session.base_url
+    }
+
+    /// The access token of the user on the identity server.
+    pub fn access_token(&self) -> &str {
+        &self.session.access_token
+    }
+
+    /// The name of the identity server, as expected by the homeserver in the
+    /// `id_server` fields of its requests.
+    ///
+    /// This is the host and port of the identity server, without the scheme.
+    pub fn server_name(&self) -> &str {
+        &self.session.base_url[url::Position::BeforeHost..url::Position::AfterPort]
+    }
+
+    /// Get the terms of service of the identity server.
+    ///
+    /// The user needs to accept them with
+    /// [`IdentityServerClient::accept_terms()`] before the identity server
+    /// can be used.
+    pub async fn terms(&self) -> HttpResult<get_terms_of_service::v2::Response> {
+        let request = get_terms_of_service::v2::Request::new();
+        self.send(request, false).await
+    }
+
+    /// Accept the terms of service of the identity server with the given
+    /// URLs.
+    ///
+    /// The URLs are the ones of the policies returned by
+    /// [`IdentityServerClient::terms()`], in the language chosen by the user.
+    pub async fn accept_terms(&self, urls: Vec<String>) -> HttpResult<()> {
+        let request = accept_terms_of_service::v2::Request::new(urls);
+        self.send(request, true).await?;
+        Ok(())
+    }
+
+    /// Look up the Matrix users bound to the given third-party identifiers.
+    ///
+    /// The identifiers are hashed with the pepper of the identity server
+    /// before being sent, as described in [MSC2134]. The pepper is cached and
+    /// fetched again if the identity server rotated it.
+    ///
+    /// Only the identifiers that are bound to a Matrix user are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `threepids` - The type and address of the third-party identifiers to
+    ///   look up, for example the contacts of the user.
+    ///
+    /// [MSC2134]: https://github.com/matrix-org/matrix-spec-proposals/pull/2134
+    #[instrument(skip_all, fields(count = threepids.len()))]
+    pub async fn lookup(&self, threepids: &[(Medium, String)]) -> HttpResult<Vec<LookupMatch>> {
+        match self.lookup_with_hash_details(threepids).await {
+            Err(error) if is_invalid_pepper(&error) => {
+                debug!("The pepper of the identity server changed, fetching it again");
+                self.session.hash_details.lock().await.take();
+                self.lookup_with_hash_details(threepids).await
+            }
+            result => result,
+        }
+    }
+
+    /// Look up the Matrix user bound to the given email address, if any.
+    ///
+    /// See [`IdentityServerClient::lookup()`] to look up several identifiers
+    /// at once.
+    pub async fn lookup_email(&self, address: &str) -> HttpResult<Option<OwnedUserId>> {
+        let threepids = [(Medium::Email, address.to_owned())];
+        Ok(self.lookup(&threepids).await?.pop().map(|lookup_match| lookup_match.user_id))
+    }
+
+    async fn lookup_with_hash_details(
+        &self,
+        threepids: &[(Medium, String)],
+    ) -> HttpResult<Vec<LookupMatch>> {
+        let hash_details = self.hash_details().await?;
+
+        let algorithm = if hash_details.algorithms.contains(&IdentifierHashingAlgorithm::Sha256) {
+            IdentifierHashingAlgorithm::Sha256
+        } else {
+            IdentifierHashingAlgorithm::None
+        };
+
+        let mut addresses_by_key = BTreeMap::new();
+
+        for (medium, address) in threepids {
+            let normalized_address = match medium {
+                Medium::Email => address.to_lowercase(),
+                _ => address.clone(),
+            };
+            let key = hash_threepid(&algorithm, medium, &normalized_address, &hash_details.pepper);
+            addresses_by_key.insert(key, (medium, address));
+        }
+
+        let request = lookup_3pid::v2::Request::new(
+            algorithm,
+            hash_details.pepper,
+            addresses_by_key.keys().cloned().collect(),
+        );
+        let response = self.send(request, true).await?;
+
+        Ok(response
+            .mappings
+            .into_iter()
+            .filter_map(|(key, user_id)| {
+                let (medium, address) = addresses_by_key.get(&key)?;
+                Some(LookupMatch {
+                    medium: (*medium).clone(),
+                    address: (*address).clone(),
+                    user_id,
+                })
+            })
+            .collect())
+    }
+
+    /// Get the cached hash details, or fetch them from the identity server.
+    async fn hash_details(&self) -> HttpResult<HashDetails> {
+        let mut hash_details = self.session.hash_details.lock().await;
+
+        if let Some(hash_details) = &*hash_details {
+            return Ok(hash_details.clone());
+        }
+
+        let request = get_hash_parameters::v2::Request::new();
+        let response = self.send(request, true).await?;
+        let details =
+            HashDetails { pepper: response.lookup_pepper, algorithms: response.algorithms };
+
+        *hash_details = Some(details.clone());
+
+        Ok(details)
+    }
+
+    /// Ask the identity server to store an invite to the given room for a
+    /// third-party identifier that isn't bound to a Matrix user yet.
+    ///
+    /// The identity server notifies the address of the invite. Usually the
+    /// homeserver does this when using [`Room::invite_by_email()`], which
+    /// should be preferred.
+    ///
+    /// [`Room::invite_by_email()`]: crate::Room::invite_by_email
+    pub async fn store_invitation(
+        &self,
+        medium: Medium,
+        address: &str,
+        room_id: &RoomId,
+    ) -> Result<store_invitation::v2::Response> {
+        let sender = self.client.user_id().ok_or(Error::AuthenticationRequired)?.to_owned();
+        let request = store_invitation::v2::Request::new(
+            medium,
+            address.to_owned(),
+            room_id.to_owned(),
+            sender,
+        );
+        Ok(self.send(request, true).await?)
+    }
+
+    /// Send the given request to the identity server.
+    async fn send<R>(&self, request: R, authenticated: bool) -> HttpResult<R::IncomingResponse>
+    where
+        R: OutgoingRequest + fmt::Debug,
+        HttpError: From<FromHttpResponseError<R::EndpointError>>,
+    {
+        let access_token = authenticated.then_some(self.session.access_token.as_str());
+        send_to_identity_server(&self.client, &self.session.base_url, request, access_token).await
+    }
+}
+
+/// Whether the given error is returned because the pepper of a lookup is
+/// outdated.
+fn is_invalid_pepper(error: &HttpError) -> bool {
+    error.client_api_error_kind().is_some_and(|kind| kind.to_string() == INVALID_PEPPER_ERRCODE)
+}
+
+/// Compute the key of a third-party identifier to look it up with the given
+/// algorithm.
+fn hash_threepid(
+    algorithm: &IdentifierHashingAlgorithm,
+    medium: &Medium,
+    address: &str,
+    pepper: &str,
+) -> String {
+    match algorithm {
+        IdentifierHashingAlgorithm::Sha256 => {
+            let hash = Sha256::digest(format!("{address} {} {pepper}", medium.as_str()));
+            URL_SAFE_NO_PAD.encode(hash)
+        }
+        _ => format!("{address} {}", medium.as_str()),
+    }
+}
+
+/// Send the given request to the identity server at the given URL.
+async fn send_to_identity_server<R>(
+    client: &Client,
+    base_url: &Url,
+    request: R,
+    access_token: Option<&str>,
+) -> HttpResult<R::IncomingResponse>
+where
+    R: OutgoingRequest + fmt::Debug,
+    HttpError: From<FromHttpResponseError<R::EndpointError>>,
+{
+    client
+        .inner
+        .http_client
+        .send(
+            request,
+            None,
+            base_url.as_str().trim_end_matches('/').to_owned(),
+            access_token,
+            None,
+            &[MatrixVersion::V1_0],
+            Default::default(),
+        )
+        .await
+}
+
+impl Client {
+    /// The identity server configured with [`Client::set_identity_server()`],
+    /// if any.
+    ///
+    /// The identity server is persisted in the store, so it is kept when the
+    /// session is restored.
+    pub async fn identity_server(&self) -> Result<Option<IdentityServerClient>> {
+        let mut session = self.inner.identity_server.lock().await;
+        let session = self.load_identity_server_session(&mut session).await?;
+        Ok(session.map(|session| IdentityServerClient { client: self.clone(), session }))
+    }
+
+    /// Get the session with the identity server, loading it from the store
+    /// the first time.
+    async fn load_identity_server_session(
+        &self,
+        session: &mut MutexGuard<'_, Option<Option<Arc<IdentityServerSession>>>>,
+    ) -> Result<Option<Arc<IdentityServerSession>>> {
+        if let Some(session) = &**session {
+            return Ok(session.clone());
+        }
+
+        let loaded = match self.store().get_custom_value(SESSION_KEY).await? {
+            Some(bytes) => {
+                let stored: StoredIdentityServerSession = serde_json::from_slice(&bytes)?;
+                Some(Arc::new(IdentityServerSession {
+                    base_url: Url::parse(&stored.base_url)?,
+                    access_token: stored.access_token,
+                    hash_details: Default::default(),
+                }))
+            }
+            None => None,
+        };
+        **session = Some(loaded.clone());
+
+        Ok(loaded)
+    }
+
+    /// Register with the identity server at the given URL, and use it as the
+    /// identity server of this client.
+    ///
+    /// The identity server advertised by the homeserver, if any, is available
+    /// in [`Client::well_known()`].
+    ///
+    /// This uses an OpenID token of the user to authenticate with the identity
+    /// server, so the user must be logged in.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, ruma::thirdparty::Medium};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let identity_server =
+    ///     client.set_identity_server(Url::parse("https://vector.im")?).await?;
+    ///
+    /// let terms = identity_server.terms().await?;
+    /// // Show the terms to the user, and accept them if they agree.
+    /// identity_server
+    ///     .accept_terms(vec!["https://vector.im/terms".to_owned()])
+    ///     .await?;
+    ///
+    /// let contacts = [(Medium::Email, "alice@example.org".to_owned())];
+    /// for found in identity_server.lookup(&contacts).await? {
+    ///     println!("{} is {}", found.address, found.user_id);
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn set_identity_server(&self, base_url: Url) -> Result<IdentityServerClient> {
+        let user_id = self.user_id().ok_or(Error::AuthenticationRequired)?.to_owned();
+        let openid_token = self.send(request_openid_token::v3::Request::new(user_id), None).await?;
+
+        debug!(%base_url, "Registering with the identity server");
+
+        let request = register::v2::Request::new(
+            openid_token.access_token,
+            openid_token.token_type,
+            openid_token.matrix_server_name,
+            openid_token.expires_in,
+        );
+        let response = send_to_identity_server(self, &base_url, request, None).await?;
+
+        let session = Arc::new(IdentityServerSession {
+            base_url,
+            access_token: response.token,
+            hash_details: Default::default(),
+        });
+
+        let stored = StoredIdentityServerSession {
+            base_url: session.base_url.to_string(),
+            access_token: session.access_token.clone(),
+        };
+
+        let mut current_session = self.inner.identity_server.lock().await;
+        self.store().set_custom_value(SESSION_KEY, serde_json::to_vec(&stored)?).await?;
+        *current_session = Some(Some(session.clone()));
+
+        Ok(IdentityServerClient { client: self.clone(), session })
+    }
+
+    /// Stop using the identity server of this client, if any, and invalidate
+    /// the access token of the user on it.
+    ///
+    /// The identity server is kept if the access token couldn't be
+    /// invalidated.
+    pub async fn unset_identity_server(&self) -> Result<()> {
+        let mut current_session = self.inner.identity_server.lock().await;
+        let Some(session) = self.load_identity_server_session(&mut current_session).await? else {
+            return Ok(());
+        };
+
+        let identity_server = IdentityServerClient { client: self.clone(), session };
+        identity_server.send(logout::v2::Request::new(), true).await?;
+
+        self.store().remove_custom_value(SESSION_KEY).await?;
+        *current_session = Some(None);
+
+        Ok(())
+    }
+}
@@ -23,8 +23,9 @@ pub use matrix_sdk_base::crypto;
 pub use matrix_sdk_base::{
     deserialized_responses,
     store::{DynStateStore, MemoryStore, StateStoreExt},
-    DisplayName, Room as BaseRoom, RoomInfo, RoomMember as BaseRoomMember, RoomMemberships,
-    RoomState, SessionMeta, StateChanges, StateStore, StoreError,
+    ComposerDraft, ComposerDraftType, DisplayName, Room as BaseRoom, RoomInfo,
+    RoomMember as BaseRoomMember, RoomMemberships, RoomState, SessionMeta, StateChanges,
+    StateStore, StoreError,
 };
 pub use matrix_sdk_common::*;
 pub use reqwest;
@@ -33,34 +34,51 @@ mod account;
 pub mod attachment;
 mod authentication;
 mod client;
+pub mod client_manager;
+#[cfg(feature = "bot-commands")]
+pub mod commands;
 pub mod config;
 #[cfg(feature = "e2e-encryption")]
 pub mod encryption;
 mod error;
+pub mod event_cache;
 pub mod event_handler;
+pub mod event_preprocessor;
+pub mod formatting;
 mod http_client;
+pub mod identity_server;
 pub mod matrix_auth;
 pub mod media;
+pub mod mentions;
 pub mod notification_settings;
 #[cfg(feature = "experimental-oidc")]
 pub mod oidc;
+pub mod permalinks;
 pub mod room;
+pub mod room_directory_search;
+pub mod room_preview;
 #[cfg(feature = "experimental-sliding-sync")]
 pub mod sliding_sync;
 pub mod sync;
+mod uiaa;
 #[cfg(feature = "experimental-widgets")]
 pub mod widget;
 
 pub use account::Account;
-pub use authentication::{AuthApi, AuthSession};
-pub use client::{Client, ClientBuildError, ClientBuilder, LoopCtrl, SendRequest, SessionChange};
+pub use authentication::{
+    AuthApi, AuthSession, ReloadSessionCallback, SaveSessionCallback, SessionCallbackError,
+};
+pub use client::{
+    AppState, Client, ClientBuildError, ClientBuilder, ClientWellKnown, LoopCtrl, SendRequest,
+    ServerCapabilities, SessionChange,
+};
 #[cfg(feature = "image-proc")]
 pub use error::ImageError;
 pub use error::{
     Error, HttpError, HttpResult, NotificationSettingsError, RefreshTokenError, Result,
     RumaApiError,
 };
-pub use http_client::TransmissionProgress;
+pub use http_client::{NetworkState, RequestErrorKind, RequestInfo, TransmissionProgress};
 #[cfg(all(feature = "e2e-encryption", feature = "sqlite"))]
 pub use matrix_sdk_sqlite::SqliteCryptoStore;
 pub use media::Media;
@@ -68,9 +86,11 @@ pub use room::Room;
 pub use ruma::{IdParseError, OwnedServerName, ServerName};
 #[cfg(feature = "experimental-sliding-sync")]
 pub use sliding_sync::{
-    RoomListEntry, SlidingSync, SlidingSyncBuilder, SlidingSyncList, SlidingSyncListBuilder,
-    SlidingSyncListLoadingState, SlidingSyncMode, SlidingSyncRoom, UpdateSummary,
+    RoomListEntry, SlidingSync, SlidingSyncBuilder, SlidingSyncExtension, SlidingSyncList,
+    SlidingSyncListBuilder, SlidingSyncListLoadingState, SlidingSyncMode, SlidingSyncRoom,
+    SlidingSyncState, UpdateSummary,
 };
+pub use uiaa::{UiaaFlow, UiaaOutcome};
 
 #[cfg(any(test, feature = "testing"))]
 pub mod test_utils;
@@ -396,6 +396,10 @@ impl MatrixAuth {
     /// refreshed by the first call or a [`RefreshTokenError`] error, if it
     /// failed.
     ///
+    /// If [session callbacks] were set, the session is reloaded first, and
+    /// `Ok(None)` is returned if another process already refreshed the tokens.
+    /// The session is saved after the tokens were refreshed.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -446,6 +450,7 @@ impl MatrixAuth {
     /// [refreshing access tokens]: https://spec.matrix.org/v1.3/client-server-api/#refreshing-access-tokens
     /// [`UnknownToken`]: ruma::api::client::error::ErrorKind::UnknownToken
     /// [restore the session]: Client::restore_session
+    /// [session callbacks]: Client::set_session_callbacks()
     /// [`ClientBuilder::handle_refresh_tokens()`]: crate::ClientBuilder::handle_refresh_tokens
     pub async fn refresh_access_token(
         &self,
@@ -454,6 +459,12 @@ impl MatrixAuth {
         let lock = client.inner.refresh_token_lock.try_lock();
 
         if let Ok(mut guard) = lock {
+            // Another process might have refreshed the tokens already.
+            if client.reload_session_tokens() {
+                *guard = Ok(());
+                return Ok(None);
+            }
+
             let Some(mut session_tokens) = self.session_tokens() else {
                 *guard = Err(RefreshTokenError::RefreshTokenRequired);
                 return Err(RefreshTokenError::RefreshTokenRequired);
@@ -480,6 +491,8 @@ impl MatrixAuth {
                         .session_change_sender
                         .send(SessionChange::TokensRefreshed);
 
+                    client.save_session();
+
                     Ok(Some(res))
                 }
                 Err(error) => {
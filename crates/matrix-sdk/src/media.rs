@@ -17,42 +17,76 @@
 
 #[cfg(feature = "e2e-encryption")]
 use std::io::Read;
-#[cfg(not(target_arch = "wasm32"))]
-use std::path::Path;
 use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fmt,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
+#[cfg(not(target_arch = "wasm32"))]
+use bytes::Bytes;
 use eyeball::SharedObservable;
+#[cfg(not(target_arch = "wasm32"))]
+use eyeball::Subscriber;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_core::Stream;
 use futures_util::future::try_join;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_util::{stream::BoxStream, StreamExt};
+#[cfg(not(target_arch = "wasm32"))]
+use http::StatusCode;
 pub use matrix_sdk_base::media::*;
 use mime::Mime;
 #[cfg(not(target_arch = "wasm32"))]
 use mime2ext;
 use ruma::{
-    api::client::media::{create_content, get_content, get_content_thumbnail},
+    api::client::{
+        error::ErrorKind,
+        media::{
+            create_content, create_content_async, create_mxc_uri, get_content,
+            get_content_thumbnail,
+        },
+    },
     assign,
     events::room::{
         message::{
             self, AudioInfo, FileInfo, FileMessageEventContent, ImageMessageEventContent,
-            MessageType, VideoInfo, VideoMessageEventContent,
+            MessageType, UnstableAmplitude, UnstableAudioDetailsContentBlock,
+            UnstableVoiceContentBlock, VideoInfo, VideoMessageEventContent,
         },
         ImageInfo, MediaSource, ThumbnailInfo,
     },
-    MxcUri,
+    IdParseError, MilliSecondsSinceUnixEpoch, MxcUri, OwnedMxcUri, TransactionId,
 };
+use serde::{Deserialize, Serialize};
 #[cfg(not(target_arch = "wasm32"))]
 use tempfile::{Builder as TempFileBuilder, NamedTempFile, TempDir};
 #[cfg(not(target_arch = "wasm32"))]
 use tokio::{fs::File as TokioFile, io::AsyncWriteExt};
+use tracing::debug;
 
 use crate::{
     attachment::{AttachmentInfo, Thumbnail},
-    Client, Result, SendRequest, TransmissionProgress,
+    Client, HttpError, Result, SendRequest, TransmissionProgress,
 };
 
 /// A conservative upload speed of 1Mbps
 const DEFAULT_UPLOAD_SPEED: u64 = 125_000;
 /// 5 min minimal upload request timeout, used to clamp the request timeout.
 const MIN_UPLOAD_REQUEST_TIMEOUT: Duration = Duration::from_secs(60 * 5);
+/// The minimal size of the files uploaded with [`Media::upload_idempotent()`]
+/// that use a content URI created beforehand.
+const IDEMPOTENT_UPLOAD_MIN_SIZE: usize = 5 * 1024 * 1024;
+/// The prefix of the keys of the pending idempotent uploads in the custom
+/// values of the state store.
+const PENDING_UPLOAD_KEY_PREFIX: &str = "pending_upload:";
+/// 30 min timeout for streaming media downloads, which can be resumed if they
+/// time out.
+#[cfg(not(target_arch = "wasm32"))]
+const MEDIA_STREAM_REQUEST_TIMEOUT: Duration = Duration::from_secs(60 * 30);
 
 /// A high-level API to interact with the media API.
 #[derive(Debug, Clone)]
@@ -82,6 +116,59 @@ impl MediaFileHandle {
     }
 }
 
+/// The content URI created for an idempotent upload, persisted in the state
+/// store.
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingUpload {
+    /// The content URI the media is uploaded to.
+    content_uri: OwnedMxcUri,
+    /// When the content URI expires if the media isn't uploaded.
+    expires_at: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+/// A stream of the chunks of a media file's content.
+///
+/// To get this, use [`Media::get_media_stream()`].
+#[cfg(not(target_arch = "wasm32"))]
+pub struct MediaStream {
+    inner: BoxStream<'static, Result<Bytes>>,
+    progress: SharedObservable<TransmissionProgress>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MediaStream {
+    /// Get a subscriber to observe the progress of the download.
+    ///
+    /// The progress includes the bytes that were skipped when resuming a
+    /// download, if the homeserver didn't need to send them again.
+    pub fn subscribe_to_progress(&self) -> Subscriber<TransmissionProgress> {
+        self.progress.subscribe()
+    }
+
+    /// The current progress of the download.
+    pub fn progress(&self) -> TransmissionProgress {
+        self.progress.get()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Stream for MediaStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl fmt::Debug for MediaStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MediaStream")
+            .field("progress", &self.progress.get())
+            .finish_non_exhaustive()
+    }
+}
+
 /// `IntoFuture` returned by [`Media::upload`].
 pub type SendUploadRequest = SendRequest<create_content::v3::Request>;
 
@@ -118,10 +205,7 @@ impl Media {
     /// # anyhow::Ok(()) };
     /// ```
     pub fn upload(&self, content_type: &Mime, data: Vec<u8>) -> SendUploadRequest {
-        let timeout = std::cmp::max(
-            Duration::from_secs(data.len() as u64 / DEFAULT_UPLOAD_SPEED),
-            MIN_UPLOAD_REQUEST_TIMEOUT,
-        );
+        let timeout = upload_timeout(data.len());
 
         let request = assign!(create_content::v3::Request::new(data), {
             content_type: Some(content_type.essence_str().to_owned()),
@@ -131,6 +215,152 @@ impl Media {
         self.client.send(request, Some(request_config))
     }
 
+    /// Create a content URI to upload media to later, as defined in
+    /// [MSC2246].
+    ///
+    /// The URI can be used in events right away, and the media can be
+    /// uploaded to it with [`Media::upload_to()`] before it expires.
+    ///
+    /// [MSC2246]: https://github.com/matrix-org/matrix-spec-proposals/pull/2246
+    pub async fn create_content_uri(&self) -> Result<create_mxc_uri::v1::Response> {
+        Ok(self.client.send(create_mxc_uri::v1::Request::new(), None).await?)
+    }
+
+    /// Upload some media to a content URI created with
+    /// [`Media::create_content_uri()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `content_uri` - The content URI to upload the media to.
+    ///
+    /// * `content_type` - The type of the media, this will be used as the
+    /// content-type header.
+    ///
+    /// * `data` - The raw bytes of the media.
+    pub fn upload_to(
+        &self,
+        content_uri: &MxcUri,
+        content_type: &Mime,
+        data: Vec<u8>,
+    ) -> Result<SendRequest<create_content_async::v3::Request>> {
+        let (server_name, media_id) = content_uri.parts().map_err(IdParseError::from)?;
+        let timeout = upload_timeout(data.len());
+
+        let request = assign!(
+            create_content_async::v3::Request::new(media_id.to_owned(), server_name.to_owned(), data),
+            { content_type: Some(content_type.essence_str().to_owned()) }
+        );
+
+        let request_config = self.client.request_config().timeout(timeout);
+        Ok(self.client.send(request, Some(request_config)))
+    }
+
+    /// Upload some media so that retrying the upload, even after a restart of
+    /// the application, doesn't create another media on the homeserver.
+    ///
+    /// The content URI is created first and stored in the state store under
+    /// the given `upload_id`. If an upload with the same ID is started again
+    /// before the URI expires, the media is uploaded to the same URI, and if
+    /// the homeserver already has the media, it isn't uploaded again.
+    ///
+    /// The homeserver has no way to tell how much of the media it received
+    /// before an upload was interrupted, so a retry always sends the whole
+    /// media again.
+    ///
+    /// Small files and homeservers that don't support creating content URIs
+    /// use a regular upload.
+    pub(crate) async fn upload_idempotent(
+        &self,
+        upload_id: &TransactionId,
+        content_type: &Mime,
+        data: Vec<u8>,
+        send_progress: SharedObservable<TransmissionProgress>,
+    ) -> Result<OwnedMxcUri> {
+        if data.len() < IDEMPOTENT_UPLOAD_MIN_SIZE {
+            let response = self
+                .upload(content_type, data)
+                .with_send_progress_observable(send_progress)
+                .await?;
+            return Ok(response.content_uri);
+        }
+
+        let key = pending_upload_key(upload_id);
+        let now = MilliSecondsSinceUnixEpoch::now();
+
+        let pending_upload = match self.client.store().get_custom_value(&key).await? {
+            Some(value) => serde_json::from_slice::<PendingUpload>(&value)
+                .ok()
+                .filter(|upload| upload.expires_at.map_or(true, |expires_at| expires_at > now)),
+            None => None,
+        };
+
+        let pending_upload = match pending_upload {
+            Some(upload) => {
+                debug!(content_uri = ?upload.content_uri, "Resuming upload");
+                upload
+            }
+            None => match self.client.send(create_mxc_uri::v1::Request::new(), None).await {
+                Ok(response) => {
+                    let upload = PendingUpload {
+                        content_uri: response.content_uri,
+                        expires_at: response.unused_expires_at,
+                    };
+                    self.client
+                        .store()
+                        .set_custom_value(&key, serde_json::to_vec(&upload)?)
+                        .await?;
+                    upload
+                }
+                Err(error)
+                    if matches!(
+                        error.client_api_error_kind(),
+                        Some(ErrorKind::Unrecognized | ErrorKind::NotFound)
+                    ) =>
+                {
+                    debug!("The homeserver doesn't support creating content URIs");
+                    let response = self
+                        .upload(content_type, data)
+                        .with_send_progress_observable(send_progress)
+                        .await?;
+                    return Ok(response.content_uri);
+                }
+                Err(error) => return Err(error.into()),
+            },
+        };
+
+        let result = self
+            .upload_to(&pending_upload.content_uri, content_type, data)?
+            .with_send_progress_observable(send_progress)
+            .await;
+
+        match result {
+            Ok(_) => {}
+            Err(error)
+                if error.client_api_error_kind() == Some(&ErrorKind::CannotOverwriteMedia) =>
+            {
+                debug!("The media was already uploaded");
+            }
+            // Keep the content URI, the upload can be retried once the
+            // homeserver is reachable again.
+            Err(error @ HttpError::Reqwest(_)) => return Err(error.into()),
+            Err(error) => {
+                self.forget_pending_upload(upload_id).await?;
+                return Err(error.into());
+            }
+        }
+
+        self.forget_pending_upload(upload_id).await?;
+
+        Ok(pending_upload.content_uri)
+    }
+
+    /// Forget the content URI of the idempotent upload with the given ID, if
+    /// any.
+    pub(crate) async fn forget_pending_upload(&self, upload_id: &TransactionId) -> Result<()> {
+        self.client.store().remove_custom_value(&pending_upload_key(upload_id)).await?;
+        Ok(())
+    }
+
     /// Gets a media file by copying it to a temporary location on disk.
     ///
     /// The file won't be encrypted even if it is encrypted on the server.
@@ -275,6 +505,106 @@ impl Media {
         Ok(content)
     }
 
+    /// Get a media file's content as a stream of chunks.
+    ///
+    /// Contrary to [`Media::get_media_content()`], the content is not
+    /// buffered in memory, which is better suited for big files like videos.
+    /// The progress of the download can be observed with
+    /// [`MediaStream::subscribe_to_progress()`]. The media cache is not used.
+    ///
+    /// If the content is encrypted and encryption is enabled, the content will
+    /// be decrypted as it is received. Its integrity can only be checked once
+    /// it was fully downloaded, so if the last item of the stream is an error,
+    /// all the chunks received before must be discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `MediaRequest` of the content.
+    ///
+    /// * `offset` - The number of bytes at the start of the content to skip.
+    ///   This allows to resume an interrupted download: the homeserver is asked
+    ///   to only send the rest of the content, with an HTTP `Range` header. If
+    ///   it doesn't support that, or if the content is encrypted, the whole
+    ///   content is downloaded again and the skipped bytes are not yielded.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn get_media_stream(
+        &self,
+        request: &MediaRequest,
+        offset: u64,
+    ) -> Result<MediaStream> {
+        let config = self.client.request_config().timeout(MEDIA_STREAM_REQUEST_TIMEOUT);
+
+        let (response, encrypted_file) = match &request.source {
+            MediaSource::Encrypted(file) => {
+                let request = get_content::v3::Request::from_url(&file.url)?;
+                let response = self.client.send_streaming(request, Some(config), None).await?;
+                (response, Some(file.clone()))
+            }
+            MediaSource::Plain(uri) => {
+                let response = if let MediaFormat::Thumbnail(size) = &request.format {
+                    let request =
+                        get_content_thumbnail::v3::Request::from_url(uri, size.width, size.height)?;
+                    self.client.send_streaming(request, Some(config), Some(offset)).await?
+                } else {
+                    let request = get_content::v3::Request::from_url(uri)?;
+                    self.client.send_streaming(request, Some(config), Some(offset)).await?
+                };
+                (response, None)
+            }
+        };
+
+        // The homeserver only sends the requested range if it supports it.
+        let received_offset =
+            if response.status() == StatusCode::PARTIAL_CONTENT { offset } else { 0 };
+        let total = response.content_length().map_or(0, |length| received_offset + length);
+
+        let progress = SharedObservable::new(TransmissionProgress {
+            current: received_offset.try_into().unwrap_or(usize::MAX),
+            total: total.try_into().unwrap_or(usize::MAX),
+        });
+
+        #[cfg(feature = "e2e-encryption")]
+        let mut decryptor = encrypted_file
+            .map(|file| {
+                matrix_sdk_base::crypto::AttachmentChunkDecryptor::new(file.as_ref().clone().into())
+            })
+            .transpose()?;
+        #[cfg(not(feature = "e2e-encryption"))]
+        _ = encrypted_file;
+
+        let stream_progress = progress.clone();
+        let stream = async_stream::try_stream! {
+            let mut response = response;
+            let mut to_skip = offset - received_offset;
+
+            while let Some(chunk) = response.chunk().await.map_err(HttpError::from)? {
+                stream_progress.update(|progress| progress.current += chunk.len());
+
+                #[cfg(feature = "e2e-encryption")]
+                let chunk = match &mut decryptor {
+                    Some(decryptor) => {
+                        let mut chunk = chunk.to_vec();
+                        decryptor.decrypt_chunk(&mut chunk);
+                        chunk.into()
+                    }
+                    None => chunk,
+                };
+
+                let chunk = skip_bytes(chunk, &mut to_skip);
+                if !chunk.is_empty() {
+                    yield chunk;
+                }
+            }
+
+            #[cfg(feature = "e2e-encryption")]
+            if let Some(decryptor) = &mut decryptor {
+                decryptor.verify_hash()?;
+            }
+        };
+
+        Ok(MediaStream { inner: Box::pin(stream), progress })
+    }
+
     /// Remove a media file's content from the store.
     ///
     /// # Arguments
@@ -400,6 +730,9 @@ impl Media {
 
     /// Upload the file bytes in `data` and construct an attachment
     /// message with `body`, `content_type`, `info` and `thumbnail`.
+    ///
+    /// If `upload_id` is set, the file is uploaded with
+    /// [`Media::upload_idempotent()`].
     pub(crate) async fn prepare_attachment_message(
         &self,
         body: &str,
@@ -407,22 +740,27 @@ impl Media {
         data: Vec<u8>,
         info: Option<AttachmentInfo>,
         thumbnail: Option<Thumbnail>,
+        upload_id: Option<&TransactionId>,
         send_progress: SharedObservable<TransmissionProgress>,
     ) -> Result<MessageType> {
         let upload_thumbnail = self.upload_thumbnail(thumbnail, send_progress.clone());
 
         let upload_attachment = async move {
-            self.upload(content_type, data)
-                .with_send_progress_observable(send_progress)
-                .await
-                .map_err(crate::Error::from)
+            match upload_id {
+                Some(upload_id) => {
+                    self.upload_idempotent(upload_id, content_type, data, send_progress).await
+                }
+                None => Ok(self
+                    .upload(content_type, data)
+                    .with_send_progress_observable(send_progress)
+                    .await?
+                    .content_uri),
+            }
         };
 
-        let ((thumbnail_source, thumbnail_info), response) =
+        let ((thumbnail_source, thumbnail_info), url) =
             try_join(upload_thumbnail, upload_attachment).await?;
 
-        let url = response.content_uri;
-
         Ok(match content_type.type_() {
             mime::IMAGE => {
                 let info = assign!(info.map(ImageInfo::from).unwrap_or_default(), {
@@ -435,13 +773,34 @@ impl Media {
                 )
             }
             mime::AUDIO => {
+                let voice_details = match &info {
+                    Some(AttachmentInfo::Voice { audio_info, waveform }) => {
+                        let audio_details = audio_info.duration.map(|duration| {
+                            let waveform = waveform
+                                .iter()
+                                .flatten()
+                                .copied()
+                                .map(UnstableAmplitude::new)
+                                .collect();
+                            UnstableAudioDetailsContentBlock::new(duration, waveform)
+                        });
+                        Some(audio_details)
+                    }
+                    _ => None,
+                };
+
                 let info = assign!(info.map(AudioInfo::from).unwrap_or_default(), {
                     mimetype: Some(content_type.as_ref().to_owned()),
                 });
-                MessageType::Audio(
-                    message::AudioMessageEventContent::plain(body.to_owned(), url)
-                        .info(Box::new(info)),
-                )
+                let mut content = message::AudioMessageEventContent::plain(body.to_owned(), url)
+                    .info(Box::new(info));
+
+                if let Some(audio_details) = voice_details {
+                    content.audio = audio_details;
+                    content.voice = Some(UnstableVoiceContentBlock::new());
+                }
+
+                MessageType::Audio(content)
             }
             mime::VIDEO => {
                 let info = assign!(info.map(VideoInfo::from).unwrap_or_default(), {
@@ -492,3 +851,26 @@ impl Media {
         }
     }
 }
+
+/// Skip the given number of bytes at the start of the chunk, and update that
+/// number with the bytes that are left to skip.
+#[cfg(not(target_arch = "wasm32"))]
+fn skip_bytes(mut chunk: Bytes, to_skip: &mut u64) -> Bytes {
+    let skipped = chunk.len().min((*to_skip).try_into().unwrap_or(usize::MAX));
+    *to_skip -= skipped as u64;
+    chunk.split_off(skipped)
+}
+
+/// The timeout of a request uploading media of the given size.
+fn upload_timeout(size: usize) -> Duration {
+    std::cmp::max(
+        Duration::from_secs(size as u64 / DEFAULT_UPLOAD_SPEED),
+        MIN_UPLOAD_REQUEST_TIMEOUT,
+    )
+}
+
+/// The key of the idempotent upload with the given ID in the custom values of
+/// the state store.
+fn pending_upload_key(upload_id: &TransactionId) -> Vec<u8> {
+    format!("{PENDING_UPLOAD_KEY_PREFIX}{upload_id}").into_bytes()
+}
@@ -0,0 +1,407 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of the mentions typed in outgoing messages to pills.
+//!
+//! A [`MentionParser`] finds the mentions of users in the text of a message,
+//! either as `@display name`, as a user ID or as a permalink, and converts
+//! them to links to the users, commonly rendered as pills. The mentioned users
+//! are also listed in the intentional [mentions] of the message.
+//!
+//! To resolve the display names of the members of a room, use
+//! [`Room::mention_parser()`], which also formats the rest of the message with
+//! the [`MessageFormatter`] of the client.
+//!
+//! [mentions]: https://spec.matrix.org/v1.10/client-server-api/#user-and-room-mentions
+//! [`Room::mention_parser()`]: crate::Room::mention_parser
+
+use std::{fmt::Write, sync::Arc};
+
+use ruma::{
+    events::{room::message::RoomMessageEventContent, Mentions},
+    OwnedUserId, UserId,
+};
+
+use crate::{formatting::MessageFormatter, permalinks::PermalinkTarget};
+
+/// The delimiters of the placeholders of the pills in the text given to the
+/// [`MessageFormatter`].
+///
+/// They are in the private use area of Unicode, so markup languages leave them
+/// as is.
+const PILL_START: char = '\u{E000}';
+const PILL_END: char = '\u{E001}';
+
+/// A parser of the mentions in the text of outgoing messages.
+#[derive(Clone, Debug, Default)]
+pub struct MentionParser {
+    /// The users that can be mentioned with their display name, sorted by
+    /// decreasing length of display name.
+    members: Vec<(OwnedUserId, String)>,
+    /// The formatter converting the rest of the message to HTML.
+    formatter: Option<Arc<dyn MessageFormatter>>,
+}
+
+impl MentionParser {
+    /// Create a new `MentionParser` that doesn't know any display name.
+    ///
+    /// Users can still be mentioned with their user ID or a permalink.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new `MentionParser` using the given formatter, if any.
+    pub(crate) fn with_formatter(formatter: Option<Arc<dyn MessageFormatter>>) -> Self {
+        Self { formatter, ..Self::default() }
+    }
+
+    /// Convert the text of the messages to HTML with the given formatter, in
+    /// addition to the mentions.
+    pub fn set_formatter(&mut self, formatter: impl MessageFormatter + 'static) {
+        self.formatter = Some(Arc::new(formatter));
+    }
+
+    /// Allow to mention the given user with `@` followed by the given display
+    /// name.
+    pub fn add_member(&mut self, user_id: OwnedUserId, display_name: impl Into<String>) {
+        let display_name = display_name.into();
+        if display_name.is_empty() {
+            return;
+        }
+
+        // Try the longest display names first, so a display name that is a
+        // prefix of another one doesn't shadow it.
+        let index = self.members.partition_point(|(_, name)| name.len() >= display_name.len());
+        self.members.insert(index, (user_id, display_name));
+    }
+
+    /// Find the mentions in the given text.
+    ///
+    /// Mentions are only recognized at the start of a word, and must be
+    /// followed by the end of a word:
+    ///
+    /// * `@room` mentions the whole room,
+    /// * `@` followed by a user ID or a display name added with
+    ///   [`MentionParser::add_member()`] mentions that user,
+    /// * a `matrix.to` URL or a `matrix:` URI pointing to a user mentions that
+    ///   user.
+    ///
+    /// The text is kept as is in the plain text body of the message. If a
+    /// formatter is set, the HTML body is the text converted by the
+    /// formatter, with the mentioned users converted to links.
+    pub fn parse(&self, text: &str) -> ParsedMessage {
+        // The HTML body if the formatter doesn't find any markup.
+        let mut html = String::with_capacity(text.len());
+        // The text given to the formatter, with placeholders for the pills.
+        let mut template = String::with_capacity(text.len());
+        let mut pills = Vec::new();
+        let mut mentions = Mentions::new();
+        let mut at_word_start = true;
+
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            if at_word_start {
+                if let Some((len, mention)) = self.find_mention(rest) {
+                    match mention {
+                        Mention::Room => {
+                            escape_into(&mut html, &rest[..len]);
+                            template.push_str(&rest[..len]);
+                            mentions.room = true;
+                        }
+                        Mention::User(user_id) => {
+                            let name = self.display_name(&user_id).unwrap_or(user_id.as_str());
+                            let mut pill = String::from("<a href=\"https://matrix.to/#/");
+                            escape_into(&mut pill, user_id.as_str());
+                            pill.push_str("\">");
+                            escape_into(&mut pill, name);
+                            pill.push_str("</a>");
+
+                            html.push_str(&pill);
+                            let _ = write!(template, "{PILL_START}{}{PILL_END}", pills.len());
+                            pills.push(pill);
+                            mentions.user_ids.insert(user_id);
+                        }
+                    }
+
+                    rest = &rest[len..];
+                    at_word_start = false;
+                    continue;
+                }
+            }
+
+            match c {
+                '\n' => html.push_str("<br>"),
+                c => escape_into(&mut html, c.encode_utf8(&mut [0; 4])),
+            }
+            template.push(c);
+            at_word_start = c.is_whitespace();
+            rest = &rest[c.len_utf8()..];
+        }
+
+        let html = self
+            .formatter
+            .as_deref()
+            .and_then(|formatter| formatter.format(&template))
+            .map(|formatted| replace_pills(&formatted, &pills))
+            .or_else(|| (!pills.is_empty()).then_some(html));
+
+        ParsedMessage { body: text.to_owned(), html, mentions }
+    }
+
+    /// Find a mention at the start of the given text.
+    ///
+    /// Returns the length of the mention in the text, and what it mentions.
+    fn find_mention(&self, text: &str) -> Option<(usize, Mention)> {
+        let word = trim_punctuation(text.split(char::is_whitespace).next().unwrap_or_default());
+
+        if word.starts_with("https://matrix.to/#/") || word.starts_with("matrix:") {
+            return match PermalinkTarget::parse(word) {
+                Ok(PermalinkTarget::User(user_id)) => Some((word.len(), Mention::User(user_id))),
+                _ => None,
+            };
+        }
+
+        let name = text.strip_prefix('@')?;
+
+        if word == "@room" {
+            return Some((word.len(), Mention::Room));
+        }
+        if let Ok(user_id) = UserId::parse(word) {
+            return Some((word.len(), Mention::User(user_id)));
+        }
+
+        self.members.iter().find_map(|(user_id, display_name)| {
+            let after = name.strip_prefix(display_name.as_str())?;
+            let at_word_end = after.chars().next().map_or(true, |c| !c.is_alphanumeric());
+            at_word_end.then(|| (display_name.len() + 1, Mention::User(user_id.clone())))
+        })
+    }
+
+    /// The display name of the given user, if known.
+    fn display_name(&self, user_id: &UserId) -> Option<&str> {
+        self.members.iter().find(|(id, _)| id == user_id).map(|(_, name)| name.as_str())
+    }
+}
+
+/// The text of a message, with the mentions it contains.
+///
+/// It can be converted to a text message with
+/// [`RoomMessageEventContent::from`].
+#[derive(Clone, Debug)]
+pub struct ParsedMessage {
+    /// The plain text body of the message.
+    pub body: String,
+
+    /// The HTML body of the message, with the mentioned users converted to
+    /// links.
+    ///
+    /// This is `None` if no user is mentioned and the formatter didn't find
+    /// any markup.
+    pub html: Option<String>,
+
+    /// The intentional mentions of the message.
+    pub mentions: Mentions,
+}
+
+impl From<ParsedMessage> for RoomMessageEventContent {
+    fn from(message: ParsedMessage) -> Self {
+        let content = match message.html {
+            Some(html) => Self::text_html(message.body, html),
+            None => Self::text_plain(message.body),
+        };
+        content.add_mentions(message.mentions)
+    }
+}
+
+/// What a mention mentions.
+enum Mention {
+    Room,
+    User(OwnedUserId),
+}
+
+/// Remove the punctuation that can follow a mention at the end of the given
+/// word.
+fn trim_punctuation(word: &str) -> &str {
+    word.trim_end_matches(|c| matches!(c, '.' | ',' | ';' | ':' | '!' | '?' | ')' | '"' | '\''))
+}
+
+/// Replace the placeholders of the pills in the given HTML by the pills.
+fn replace_pills(formatted: &str, pills: &[String]) -> String {
+    let mut html = String::with_capacity(formatted.len());
+    let mut rest = formatted;
+
+    while let Some(start) = rest.find(PILL_START) {
+        html.push_str(&rest[..start]);
+        rest = &rest[start + PILL_START.len_utf8()..];
+
+        let pill = rest
+            .split_once(PILL_END)
+            .and_then(|(index, after)| Some((pills.get(index.parse::<usize>().ok()?)?, after)));
+        match pill {
+            Some((pill, after)) => {
+                html.push_str(pill);
+                rest = after;
+            }
+            None => html.push(PILL_START),
+        }
+    }
+    html.push_str(rest);
+
+    html
+}
+
+/// Push the given text to the given HTML, escaping the special characters.
+fn escape_into(html: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use ruma::{
+        events::room::message::{MessageType, RoomMessageEventContent},
+        owned_user_id, user_id,
+    };
+
+    use super::MentionParser;
+    use crate::formatting::MessageFormatter;
+
+    #[derive(Debug)]
+    struct QuoteFormatter;
+
+    impl MessageFormatter for QuoteFormatter {
+        fn format(&self, body: &str) -> Option<String> {
+            let quote = body.strip_prefix("> ")?;
+            Some(format!("<blockquote>{quote}</blockquote>"))
+        }
+    }
+
+    fn parser() -> MentionParser {
+        let mut parser = MentionParser::new();
+        parser.add_member(owned_user_id!("@alice:localhost"), "Alice");
+        parser.add_member(owned_user_id!("@alice2:localhost"), "Alice Smith");
+        parser
+    }
+
+    #[test]
+    fn no_mentions() {
+        let message = parser().parse("Hello <Alice> & alice@localhost");
+
+        assert_eq!(message.body, "Hello <Alice> & alice@localhost");
+        assert_eq!(message.html, None);
+        assert!(message.mentions.user_ids.is_empty());
+        assert!(!message.mentions.room);
+    }
+
+    #[test]
+    fn display_names_and_user_ids() {
+        let message = parser().parse("@Alice Smith, @Alice: ping @bob:localhost.\nOk?");
+
+        assert_eq!(
+            message.html.as_deref(),
+            Some(
+                "<a href=\"https://matrix.to/#/@alice2:localhost\">Alice Smith</a>, \
+                 <a href=\"https://matrix.to/#/@alice:localhost\">Alice</a>: ping \
+                 <a href=\"https://matrix.to/#/@bob:localhost\">@bob:localhost</a>.<br>Ok?"
+            )
+        );
+        assert_eq!(
+            message.mentions.user_ids.iter().map(|user_id| user_id.as_str()).collect::<Vec<_>>(),
+            ["@alice2:localhost", "@alice:localhost", "@bob:localhost"]
+        );
+
+        // Display names must be followed by the end of a word.
+        let message = parser().parse("@Alicette");
+        assert_eq!(message.html, None);
+    }
+
+    #[test]
+    fn permalinks_and_room() {
+        let message = parser()
+            .parse("@room look: https://matrix.to/#/@alice:localhost and matrix:u/carol:localhost");
+
+        assert_eq!(
+            message.html.as_deref(),
+            Some(
+                "@room look: <a href=\"https://matrix.to/#/@alice:localhost\">Alice</a> and \
+                 <a href=\"https://matrix.to/#/@carol:localhost\">@carol:localhost</a>"
+            )
+        );
+        assert!(message.mentions.room);
+        assert!(message.mentions.user_ids.contains(user_id!("@carol:localhost")));
+
+        let content = RoomMessageEventContent::from(message);
+        let text = assert_matches!(content.msgtype, MessageType::Text(text) => text);
+        assert_eq!(
+            text.body,
+            "@room look: https://matrix.to/#/@alice:localhost and matrix:u/carol:localhost"
+        );
+        assert!(text.formatted.is_some());
+        assert!(content.mentions.unwrap().room);
+    }
+
+    #[test]
+    fn formatter() {
+        let mut parser = parser();
+        parser.set_formatter(QuoteFormatter);
+
+        let message = parser.parse("> @Alice said hi");
+        assert_eq!(message.body, "> @Alice said hi");
+        assert_eq!(
+            message.html.as_deref(),
+            Some(
+                "<blockquote><a href=\"https://matrix.to/#/@alice:localhost\">Alice</a> said \
+                 hi</blockquote>"
+            )
+        );
+
+        // Without markup, the pills are still converted.
+        let message = parser.parse("@Alice hi");
+        assert_eq!(
+            message.html.as_deref(),
+            Some("<a href=\"https://matrix.to/#/@alice:localhost\">Alice</a> hi")
+        );
+
+        // Without mentions, the markup is still converted.
+        let message = parser.parse("> hi");
+        assert_eq!(message.html.as_deref(), Some("<blockquote>hi</blockquote>"));
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn markdown() {
+        use crate::formatting::MarkdownFormatter;
+
+        let mut parser = parser();
+        parser.set_formatter(MarkdownFormatter::new());
+
+        let message = parser.parse("**Hey** @Alice Smith");
+        assert_eq!(
+            message.html.as_deref(),
+            Some(
+                "<p><strong>Hey</strong> \
+                 <a href=\"https://matrix.to/#/@alice2:localhost\">Alice Smith</a></p>\n"
+            )
+        );
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::sync::Arc;
 
+use futures_core::Stream;
 use ruma::{
     api::client::push::{
         delete_pushrule, set_pushrule, set_pushrule_actions, set_pushrule_enabled,
@@ -10,7 +11,7 @@ use ruma::{
     push::{Action, RuleKind, Ruleset, Tweak},
     RoomId,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
 use self::{command::Command, rule_commands::RuleCommands, rules::Rules};
 
@@ -35,7 +36,7 @@ pub enum RoomNotificationMode {
 }
 
 /// Whether or not a room is encrypted
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum IsEncrypted {
     /// The room is encrypted
     Yes,
@@ -54,7 +55,7 @@ impl From<bool> for IsEncrypted {
 }
 
 /// Whether or not a room is a `one-to-one`
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum IsOneToOne {
     /// A room is a `one-to-one` room if it has exactly two members.
     Yes,
@@ -81,6 +82,8 @@ pub struct NotificationSettings {
     rules: Arc<RwLock<Rules>>,
     /// Event handler for push rules event
     push_rules_event_handler: EventHandlerHandle,
+    /// Sender notifying that the push rules changed.
+    changes_sender: broadcast::Sender<()>,
 }
 
 impl Drop for NotificationSettings {
@@ -98,17 +101,91 @@ impl NotificationSettings {
     /// * `ruleset` - A `Ruleset` containing account's owner push rules
     pub fn new(client: Client, ruleset: Ruleset) -> Self {
         let rules = Arc::new(RwLock::new(Rules::new(ruleset)));
+        let changes_sender = broadcast::Sender::new(100);
 
         // Listen for PushRulesEvent
         let rules_clone = rules.clone();
+        let changes_sender_clone = changes_sender.clone();
         let push_rules_event_handler = client.add_event_handler(move |ev: PushRulesEvent| {
             let rules = rules_clone.to_owned();
+            let changes_sender = changes_sender_clone.to_owned();
             async move {
                 *rules.write().await = Rules::new(ev.content.global);
+                let _ = changes_sender.send(());
             }
         });
 
-        Self { client, rules, push_rules_event_handler }
+        Self { client, rules, push_rules_event_handler, changes_sender }
+    }
+
+    /// Subscribe to changes of the push rules.
+    ///
+    /// A notification is sent every time the push rules are updated, either
+    /// from a sync response or by one of the methods of this type.
+    pub fn subscribe_to_changes(&self) -> broadcast::Receiver<()> {
+        self.changes_sender.subscribe()
+    }
+
+    /// Get the notification mode that applies to a room.
+    ///
+    /// This is the user defined notification mode of the room if there is
+    /// one, otherwise the default notification mode for this kind of room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room
+    /// * `is_encrypted` - `Yes` if the room is encrypted
+    /// * `is_one_to_one` - `Yes` if the room is a direct chat involving two
+    ///   people
+    pub async fn get_room_notification_mode(
+        &self,
+        room_id: &RoomId,
+        is_encrypted: IsEncrypted,
+        is_one_to_one: IsOneToOne,
+    ) -> RoomNotificationMode {
+        self.rules.read().await.get_room_notification_mode(room_id, is_encrypted, is_one_to_one)
+    }
+
+    /// Get a stream of the notification mode that applies to a room.
+    ///
+    /// The current mode is yielded first, then a new mode is yielded every
+    /// time it changes. See [`Self::get_room_notification_mode()`] for the
+    /// meaning of the arguments.
+    pub fn subscribe_to_room_notification_mode(
+        &self,
+        room_id: &RoomId,
+        is_encrypted: IsEncrypted,
+        is_one_to_one: IsOneToOne,
+    ) -> impl Stream<Item = RoomNotificationMode> {
+        // Don't clone `self` here, dropping the clone would remove the event handler
+        // that keeps the rules up-to-date.
+        let rules = self.rules.clone();
+        let room_id = room_id.to_owned();
+        let mut changes = self.subscribe_to_changes();
+
+        async_stream::stream! {
+            let mut mode =
+                rules.read().await.get_room_notification_mode(&room_id, is_encrypted, is_one_to_one);
+            yield mode.clone();
+
+            loop {
+                match changes.recv().await {
+                    Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+
+                let new_mode = rules.read().await.get_room_notification_mode(
+                    &room_id,
+                    is_encrypted,
+                    is_one_to_one,
+                );
+
+                if new_mode != mode {
+                    mode = new_mode;
+                    yield mode.clone();
+                }
+            }
+        }
     }
 
     /// Get the user defined notification mode for a room.
@@ -144,6 +221,62 @@ impl NotificationSettings {
         self.rules.read().await.contains_keyword_rules()
     }
 
+    /// Get the keywords of the enabled keyword rules.
+    pub async fn get_keywords(&self) -> Vec<String> {
+        self.rules.read().await.get_keywords()
+    }
+
+    /// Add a keyword that triggers a notification when it's found in a
+    /// message, in all the rooms that aren't muted.
+    pub async fn add_keyword(&self, keyword: String) -> Result<(), NotificationSettingsError> {
+        if keyword.trim().is_empty() {
+            return Err(NotificationSettingsError::InvalidParameter(
+                "cannot add an empty keyword.".to_owned(),
+            ));
+        }
+
+        let rules = self.rules.read().await.clone();
+
+        // Check that the keyword doesn't already exist.
+        if rules.get_keywords().contains(&keyword) {
+            return Ok(());
+        }
+
+        let mut rule_commands = RuleCommands::new(rules.ruleset.clone());
+
+        if let Some(rule_id) = rules.get_keyword_rule_ids(&keyword).into_iter().next() {
+            // A disabled rule for this keyword exists, enable it.
+            rule_commands.set_rule_enabled(RuleKind::Content, &rule_id, true)?;
+        } else {
+            rule_commands.insert_keyword_rule(keyword)?;
+        }
+
+        self.run_server_commands(&rule_commands).await?;
+        self.apply(rule_commands).await;
+
+        Ok(())
+    }
+
+    /// Remove a keyword, and all the keyword rules matching it.
+    pub async fn remove_keyword(&self, keyword: &str) -> Result<(), NotificationSettingsError> {
+        let rules = self.rules.read().await.clone();
+
+        let rule_ids = rules.get_keyword_rule_ids(keyword);
+        if rule_ids.is_empty() {
+            return Err(NotificationSettingsError::RuleNotFound);
+        }
+
+        let mut rule_commands = RuleCommands::new(rules.ruleset);
+        for rule_id in rule_ids {
+            rule_commands.delete_rule(RuleKind::Content, rule_id)?;
+        }
+
+        self.run_server_commands(&rule_commands).await?;
+        self.apply(rule_commands).await;
+
+        Ok(())
+    }
+
     /// Get whether a push rule is enabled.
     pub async fn is_push_rule_enabled(
         &self,
@@ -167,8 +300,7 @@ impl NotificationSettings {
 
         self.run_server_commands(&rule_commands).await?;
 
-        let rules = &mut *self.rules.write().await;
-        rules.apply(rule_commands);
+        self.apply(rule_commands).await;
 
         Ok(())
     }
@@ -204,8 +336,7 @@ impl NotificationSettings {
 
         self.run_server_commands(&rule_commands).await?;
 
-        let rules = &mut *self.rules.write().await;
-        rules.apply(rule_commands);
+        self.apply(rule_commands).await;
 
         Ok(())
     }
@@ -257,8 +388,7 @@ impl NotificationSettings {
 
         self.run_server_commands(&rule_commands).await?;
 
-        let rules = &mut *self.rules.write().await;
-        rules.apply(rule_commands);
+        self.apply(rule_commands).await;
 
         Ok(())
     }
@@ -282,8 +412,7 @@ impl NotificationSettings {
 
         self.run_server_commands(&rule_commands).await?;
 
-        let rules = &mut *self.rules.write().await;
-        rules.apply(rule_commands);
+        self.apply(rule_commands).await;
 
         Ok(())
     }
@@ -322,6 +451,13 @@ impl NotificationSettings {
         }
     }
 
+    /// Apply the given commands to the local push rules and notify the
+    /// subscribers.
+    async fn apply(&self, rule_commands: RuleCommands) {
+        self.rules.write().await.apply(rule_commands);
+        let _ = self.changes_sender.send(());
+    }
+
     /// Convert commands into requests to the server, and run them.
     async fn run_server_commands(
         &self,
@@ -357,6 +493,14 @@ impl NotificationSettings {
                         .await
                         .map_err(|_| NotificationSettingsError::UnableToAddPushRule)?;
                 }
+                Command::SetKeywordPushRule { scope, keyword: _ } => {
+                    let push_rule = command.to_push_rule()?;
+                    let request = set_pushrule::v3::Request::new(scope.clone(), push_rule);
+                    self.client
+                        .send(request, request_config)
+                        .await
+                        .map_err(|_| NotificationSettingsError::UnableToAddPushRule)?;
+                }
                 Command::SetPushRuleEnabled { scope, kind, rule_id, enabled } => {
                     let request = set_pushrule_enabled::v3::Request::new(
                         scope.clone(),
@@ -391,6 +535,7 @@ impl NotificationSettings {
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use assert_matches::assert_matches;
+    use futures_util::{pin_mut, StreamExt};
     use matrix_sdk_test::{
         async_test,
         notification_settings::{build_ruleset, get_server_default_ruleset},
@@ -967,4 +1112,66 @@ mod tests {
             RoomNotificationMode::MentionsAndKeywordsOnly
         );
     }
+
+    #[async_test]
+    async fn test_add_and_remove_keyword() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        Mock::given(method("PUT")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        Mock::given(method("DELETE")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+
+        let settings = NotificationSettings::new(client, get_server_default_ruleset());
+        assert!(settings.get_keywords().await.is_empty());
+        assert!(!settings.contains_keyword_rules().await);
+
+        settings.add_keyword("cake".to_owned()).await.unwrap();
+        assert_eq!(settings.get_keywords().await, ["cake"]);
+        assert!(settings.contains_keyword_rules().await);
+
+        // Adding the same keyword again doesn't create another rule.
+        settings.add_keyword("cake".to_owned()).await.unwrap();
+        assert_eq!(settings.rules.read().await.get_keyword_rule_ids("cake").len(), 1);
+
+        settings.remove_keyword("cake").await.unwrap();
+        assert!(settings.get_keywords().await.is_empty());
+
+        assert_matches!(
+            settings.remove_keyword("cake").await,
+            Err(NotificationSettingsError::RuleNotFound)
+        );
+        assert_matches!(
+            settings.add_keyword(" ".to_owned()).await,
+            Err(NotificationSettingsError::InvalidParameter(_))
+        );
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_room_notification_mode() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let room_id = get_test_room_id();
+
+        Mock::given(method("PUT")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        Mock::given(method("DELETE")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+
+        let settings = NotificationSettings::new(client, get_server_default_ruleset());
+        let default_mode =
+            settings.get_default_room_notification_mode(IsEncrypted::No, IsOneToOne::No).await;
+
+        let stream =
+            settings.subscribe_to_room_notification_mode(&room_id, IsEncrypted::No, IsOneToOne::No);
+        pin_mut!(stream);
+
+        // The current mode is yielded first.
+        assert_eq!(stream.next().await, Some(default_mode.clone()));
+
+        settings.set_room_notification_mode(&room_id, RoomNotificationMode::Mute).await.unwrap();
+        assert_eq!(stream.next().await, Some(RoomNotificationMode::Mute));
+
+        // Changes that don't affect the room aren't yielded.
+        settings.add_keyword("cake".to_owned()).await.unwrap();
+        settings.delete_user_defined_room_rules(&room_id).await.unwrap();
+        assert_eq!(stream.next().await, Some(default_mode));
+    }
 }
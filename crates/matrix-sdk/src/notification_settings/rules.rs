@@ -123,6 +123,18 @@ impl Rules {
         }
     }
 
+    /// Gets the notification mode that applies to a room, the user defined
+    /// mode if there is one, or the default mode otherwise.
+    pub(crate) fn get_room_notification_mode(
+        &self,
+        room_id: &RoomId,
+        is_encrypted: IsEncrypted,
+        is_one_to_one: IsOneToOne,
+    ) -> RoomNotificationMode {
+        self.get_user_defined_room_notification_mode(room_id)
+            .unwrap_or_else(|| self.get_default_room_notification_mode(is_encrypted, is_one_to_one))
+    }
+
     /// Get all room IDs for which a user-defined rule exists.
     pub(crate) fn get_rooms_with_user_defined_rules(&self, enabled: Option<bool>) -> Vec<String> {
         let test_if_enabled = enabled.is_some();
@@ -214,6 +226,28 @@ impl Rules {
         self.ruleset.content.iter().any(|r| !r.default && r.enabled)
     }
 
+    /// Get the keywords of the enabled user defined keyword rules.
+    pub(crate) fn get_keywords(&self) -> Vec<String> {
+        // Keyword rules are user defined `Content` rules.
+        self.ruleset
+            .content
+            .iter()
+            .filter(|r| !r.default && r.enabled)
+            .map(|r| r.pattern.clone())
+            .collect()
+    }
+
+    /// Get the IDs of the user defined keyword rules matching the given
+    /// keyword.
+    pub(crate) fn get_keyword_rule_ids(&self, keyword: &str) -> Vec<String> {
+        self.ruleset
+            .content
+            .iter()
+            .filter(|r| !r.default && r.pattern == keyword)
+            .map(|r| r.rule_id.clone())
+            .collect()
+    }
+
     /// Get whether a rule is enabled.
     pub(crate) fn is_enabled(
         &self,
@@ -241,7 +275,9 @@ impl Rules {
                 Command::DeletePushRule { scope: _, kind, rule_id } => {
                     _ = self.ruleset.remove(kind, rule_id);
                 }
-                Command::SetRoomPushRule { .. } | Command::SetOverridePushRule { .. } => {
+                Command::SetRoomPushRule { .. }
+                | Command::SetOverridePushRule { .. }
+                | Command::SetKeywordPushRule { .. } => {
                     if let Ok(push_rule) = command.to_push_rule() {
                         _ = self.ruleset.insert(push_rule, None, None);
                     }
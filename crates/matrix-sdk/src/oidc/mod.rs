@@ -348,7 +348,7 @@ impl Oidc {
     }
 
     /// Set the current session tokens.
-    fn set_session_tokens(&self, session_tokens: SessionTokens) {
+    pub(crate) fn set_session_tokens(&self, session_tokens: SessionTokens) {
         if let Some(auth_data) = self.client.inner.auth_data.get() {
             let Some(data) = auth_data.as_oidc() else {
                 panic!("Cannot call OpenID Connect API after logging in with another API");
@@ -907,6 +907,11 @@ impl Oidc {
     /// will return `Ok(None)` if the token was refreshed by the first call
     /// or the same [`RefreshTokenError`], if it failed.
     ///
+    /// If [session callbacks] were set, the session is reloaded first, and
+    /// `Ok(None)` is returned if another process already refreshed the tokens.
+    /// The session is saved after the tokens were refreshed.
+    ///
+    /// [session callbacks]: Client::set_session_callbacks()
     /// [`ClientBuilder::handle_refresh_tokens()`]: crate::ClientBuilder::handle_refresh_tokens()
     pub async fn refresh_access_token(
         &self,
@@ -915,6 +920,12 @@ impl Oidc {
         let lock = client.inner.refresh_token_lock.try_lock();
 
         if let Ok(mut guard) = lock {
+            // Another process might have refreshed the tokens already.
+            if client.reload_session_tokens() {
+                *guard = Ok(());
+                return Ok(None);
+            }
+
             let Some(session_tokens) = self.session_tokens() else {
                 let error = RefreshTokenError::RefreshTokenRequired;
                 *guard = Err(error.clone());
@@ -934,6 +945,7 @@ impl Oidc {
                         .inner
                         .session_change_sender
                         .send(SessionChange::TokensRefreshed);
+                    client.save_session();
                     Ok(Some(response))
                 }
                 Err(error) => {
@@ -0,0 +1,224 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Building and parsing permalinks, as `matrix.to` URLs or `matrix:` URIs.
+//!
+//! A [`PermalinkTarget`] can be parsed from any of both formats with
+//! [`PermalinkTarget::parse()`], and can be turned into any of both formats
+//! with [`PermalinkTarget::matrix_to_uri()`] and
+//! [`PermalinkTarget::matrix_uri()`].
+//!
+//! To get the target of a permalink to a room or an event in a room, with the
+//! servers to use to route the room ID chosen from the members of the room,
+//! use [`Room::permalink_target()`] and [`Room::event_permalink_target()`].
+//!
+//! [`Room::permalink_target()`]: crate::Room::permalink_target
+//! [`Room::event_permalink_target()`]: crate::Room::event_permalink_target
+
+use ruma::{
+    matrix_uri::MatrixId, IdParseError, MatrixToUri, MatrixUri, OwnedEventId, OwnedRoomAliasId,
+    OwnedRoomId, OwnedRoomOrAliasId, OwnedServerName, OwnedUserId, RoomAliasId, RoomId,
+};
+use thiserror::Error;
+
+/// The scheme of `matrix:` URIs.
+const MATRIX_URI_SCHEME: &str = "matrix:";
+
+/// The entity a permalink points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PermalinkTarget {
+    /// A room, with its ID.
+    Room {
+        /// The ID of the room.
+        room_id: OwnedRoomId,
+        /// Servers that should know the room, to be able to join it.
+        via: Vec<OwnedServerName>,
+    },
+
+    /// A room, with one of its aliases.
+    RoomAlias(OwnedRoomAliasId),
+
+    /// An event in a room.
+    Event {
+        /// The ID or an alias of the room of the event.
+        room: OwnedRoomOrAliasId,
+        /// The ID of the event.
+        event_id: OwnedEventId,
+        /// Servers that should know the room, to be able to join it.
+        via: Vec<OwnedServerName>,
+    },
+
+    /// A user.
+    User(OwnedUserId),
+}
+
+impl PermalinkTarget {
+    /// Parse a permalink, either as a `matrix.to` URL or as a `matrix:` URI.
+    pub fn parse(uri: &str) -> Result<Self, PermalinkParseError> {
+        let (id, via) = if uri.starts_with(MATRIX_URI_SCHEME) {
+            let uri = MatrixUri::parse(uri)?;
+            (uri.id().clone(), uri.via().to_owned())
+        } else {
+            let uri = MatrixToUri::parse(uri)?;
+            (uri.id().clone(), uri.via().to_owned())
+        };
+
+        Ok(match id {
+            MatrixId::Room(room_id) => Self::Room { room_id, via },
+            MatrixId::RoomAlias(alias) => Self::RoomAlias(alias),
+            MatrixId::Event(room, event_id) => Self::Event { room, event_id, via },
+            MatrixId::User(user_id) => Self::User(user_id),
+            _ => return Err(PermalinkParseError::UnsupportedTarget),
+        })
+    }
+
+    /// Get a `matrix.to` URL pointing to this target.
+    pub fn matrix_to_uri(&self) -> MatrixToUri {
+        match self {
+            Self::Room { room_id, via } => room_id.matrix_to_uri_via(via.clone()),
+            Self::RoomAlias(alias) => alias.matrix_to_uri(),
+            Self::Event { room, event_id, via } => match split_room_or_alias(room) {
+                Ok(room_id) => room_id.matrix_to_event_uri_via(event_id.clone(), via.clone()),
+                Err(alias) => alias.matrix_to_event_uri(event_id.clone()),
+            },
+            Self::User(user_id) => user_id.matrix_to_uri(),
+        }
+    }
+
+    /// Get a `matrix:` URI pointing to this target.
+    ///
+    /// # Arguments
+    ///
+    /// * `join` - Whether the user should join the room, for a permalink to a
+    ///   room. This is ignored for other targets.
+    pub fn matrix_uri(&self, join: bool) -> MatrixUri {
+        match self {
+            Self::Room { room_id, via } => room_id.matrix_uri_via(via.clone(), join),
+            Self::RoomAlias(alias) => alias.matrix_uri(join),
+            Self::Event { room, event_id, via } => match split_room_or_alias(room) {
+                Ok(room_id) => room_id.matrix_event_uri_via(event_id.clone(), via.clone()),
+                Err(alias) => alias.matrix_event_uri(event_id.clone()),
+            },
+            Self::User(user_id) => user_id.matrix_uri(false),
+        }
+    }
+}
+
+/// Get either the room ID or the room alias behind the given identifier.
+fn split_room_or_alias(room: &OwnedRoomOrAliasId) -> Result<&RoomId, &RoomAliasId> {
+    <&RoomId>::try_from(&**room)
+}
+
+/// An error encountered when parsing a permalink.
+#[derive(Debug, Error)]
+pub enum PermalinkParseError {
+    /// The permalink is not a valid `matrix.to` URL or `matrix:` URI.
+    #[error(transparent)]
+    InvalidUri(#[from] IdParseError),
+
+    /// The permalink points to an entity that isn't supported.
+    #[error("the permalink points to an unsupported target")]
+    UnsupportedTarget,
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use ruma::{event_id, owned_server_name, room_alias_id, room_id, user_id};
+
+    use super::{PermalinkParseError, PermalinkTarget};
+
+    #[test]
+    fn test_parse_matrix_to_uri() {
+        let target = PermalinkTarget::parse(
+            "https://matrix.to/#/!room:localhost/$event:localhost?via=localhost&via=example.org",
+        )
+        .unwrap();
+        assert_eq!(
+            target,
+            PermalinkTarget::Event {
+                room: room_id!("!room:localhost").to_owned().into(),
+                event_id: event_id!("$event:localhost").to_owned(),
+                via: vec![owned_server_name!("localhost"), owned_server_name!("example.org")],
+            }
+        );
+
+        let target = PermalinkTarget::parse("https://matrix.to/#/@alice:localhost").unwrap();
+        assert_eq!(target, PermalinkTarget::User(user_id!("@alice:localhost").to_owned()));
+    }
+
+    #[test]
+    fn test_parse_matrix_uri() {
+        let target = PermalinkTarget::parse("matrix:roomid/room:localhost?via=localhost").unwrap();
+        assert_eq!(
+            target,
+            PermalinkTarget::Room {
+                room_id: room_id!("!room:localhost").to_owned(),
+                via: vec![owned_server_name!("localhost")],
+            }
+        );
+
+        let target = PermalinkTarget::parse("matrix:r/alias:localhost").unwrap();
+        assert_eq!(
+            target,
+            PermalinkTarget::RoomAlias(room_alias_id!("#alias:localhost").to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_permalink() {
+        assert_matches!(
+            PermalinkTarget::parse("https://example.org/#/@alice:localhost"),
+            Err(PermalinkParseError::InvalidUri(_))
+        );
+        assert_matches!(
+            PermalinkTarget::parse("matrix:u/alice"),
+            Err(PermalinkParseError::InvalidUri(_))
+        );
+    }
+
+    #[test]
+    fn test_build_permalinks() {
+        let target = PermalinkTarget::Room {
+            room_id: room_id!("!room:localhost").to_owned(),
+            via: vec![owned_server_name!("localhost")],
+        };
+        assert_eq!(
+            target.matrix_to_uri().to_string(),
+            "https://matrix.to/#/!room:localhost?via=localhost"
+        );
+        assert_eq!(
+            target.matrix_uri(true).to_string(),
+            "matrix:roomid/room:localhost?via=localhost&action=join"
+        );
+
+        let target = PermalinkTarget::Event {
+            room: room_alias_id!("#alias:localhost").to_owned().into(),
+            event_id: event_id!("$event:localhost").to_owned(),
+            via: vec![],
+        };
+        assert_eq!(
+            target.matrix_to_uri().to_string(),
+            "https://matrix.to/#/%23alias:localhost/$event:localhost"
+        );
+        assert_eq!(
+            target.matrix_uri(false).to_string(),
+            "matrix:r/alias:localhost/e/event:localhost"
+        );
+
+        let target = PermalinkTarget::User(user_id!("@alice:localhost").to_owned());
+        assert_eq!(target.matrix_to_uri().to_string(), "https://matrix.to/#/@alice:localhost");
+        assert_eq!(target.matrix_uri(false).to_string(), "matrix:u/alice:localhost");
+    }
+}
@@ -8,13 +8,18 @@ use std::{
 use eyeball::SharedObservable;
 use mime::Mime;
 use ruma::api::client::message::send_message_event;
+#[cfg(feature = "image-proc")]
+use tracing::debug;
 use tracing::{Instrument, Span};
 
 use super::Room;
 use crate::{attachment::AttachmentConfig, Result, TransmissionProgress};
 #[cfg(feature = "image-proc")]
 use crate::{
-    attachment::{generate_image_thumbnail, Thumbnail},
+    attachment::{
+        generate_blurhash, generate_image_thumbnail, AttachmentInfo, BaseImageInfo, BaseVideoInfo,
+        Thumbnail,
+    },
     error::ImageError,
 };
 
@@ -71,71 +76,100 @@ impl<'a> IntoFuture for SendAttachment<'a> {
     fn into_future(self) -> Self::IntoFuture {
         let Self { room, body, content_type, data, config, tracing_span, send_progress } = self;
         let fut = async move {
-            if config.thumbnail.is_some() {
-                room.prepare_and_send_attachment(body, content_type, data, config, send_progress)
-                    .await
+            #[cfg(feature = "image-proc")]
+            let (data, config) = if config.generate_thumbnail {
+                generate_media_previews(content_type.clone(), data, config).await?
             } else {
-                #[cfg(not(feature = "image-proc"))]
-                let thumbnail = None;
-
-                #[cfg(feature = "image-proc")]
-                let data_slot;
-                #[cfg(feature = "image-proc")]
-                let (data, thumbnail) = if config.generate_thumbnail {
-                    let content_type = content_type.clone();
-                    let make_thumbnail = move |data| {
-                        let res = generate_image_thumbnail(
-                            &content_type,
-                            Cursor::new(&data),
-                            config.thumbnail_size,
-                        );
-                        (data, res)
-                    };
-
-                    #[cfg(not(target_arch = "wasm32"))]
-                    let (data, res) = tokio::task::spawn_blocking(move || make_thumbnail(data))
-                        .await
-                        .expect("Task join error");
-
-                    #[cfg(target_arch = "wasm32")]
-                    let (data, res) = make_thumbnail(data);
-
-                    let thumbnail = match res {
-                        Ok((thumbnail_data, thumbnail_info)) => {
-                            data_slot = thumbnail_data;
-                            Some(Thumbnail {
-                                data: data_slot,
-                                content_type: mime::IMAGE_JPEG,
-                                info: Some(thumbnail_info),
-                            })
-                        }
-                        Err(
-                            ImageError::ThumbnailBiggerThanOriginal
-                            | ImageError::FormatNotSupported,
-                        ) => None,
-                        Err(error) => return Err(error.into()),
-                    };
-
-                    (data, thumbnail)
-                } else {
-                    (data, None)
-                };
-
-                let config = AttachmentConfig {
-                    txn_id: config.txn_id,
-                    info: config.info,
-                    thumbnail,
-                    #[cfg(feature = "image-proc")]
-                    generate_thumbnail: false,
-                    #[cfg(feature = "image-proc")]
-                    thumbnail_size: None,
-                };
-
-                room.prepare_and_send_attachment(body, content_type, data, config, send_progress)
-                    .await
-            }
+                (data, config)
+            };
+
+            room.prepare_and_send_attachment(body, content_type, data, config, send_progress).await
         };
 
         Box::pin(fut.instrument(tracing_span))
     }
 }
+
+/// Generate the thumbnail and the BlurHash of the given media, as requested
+/// with [`AttachmentConfig::generate_thumbnail()`].
+///
+/// Unsupported media are sent without them.
+#[cfg(feature = "image-proc")]
+async fn generate_media_previews(
+    content_type: Mime,
+    data: Vec<u8>,
+    mut config: AttachmentConfig,
+) -> Result<(Vec<u8>, AttachmentConfig)> {
+    let generate = move || {
+        if content_type.type_() == mime::IMAGE && config.thumbnail.is_none() {
+            match generate_image_thumbnail(&content_type, Cursor::new(&data), config.thumbnail_size)
+            {
+                Ok((thumbnail_data, thumbnail_info)) => {
+                    // The thumbnail is encoded in the same format as the image.
+                    config.thumbnail = Some(Thumbnail {
+                        data: thumbnail_data,
+                        content_type: content_type.clone(),
+                        info: Some(thumbnail_info),
+                    });
+                }
+                Err(ImageError::ThumbnailBiggerThanOriginal | ImageError::FormatNotSupported) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        let blurhash = match (&config.thumbnail, content_type.type_()) {
+            (Some(thumbnail), _) => {
+                generate_blurhash(&thumbnail.content_type, Cursor::new(&thumbnail.data))
+            }
+            (None, mime::IMAGE) => generate_blurhash(&content_type, Cursor::new(&data)),
+            (None, _) => Err(ImageError::FormatNotSupported),
+        };
+
+        match blurhash {
+            Ok(blurhash) => set_blurhash(&mut config.info, content_type.type_(), blurhash),
+            Err(error) => debug!("Could not compute the BlurHash of the attachment: {error}"),
+        }
+
+        Ok((data, config))
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let result = tokio::task::spawn_blocking(generate).await.expect("Task join error");
+
+    #[cfg(target_arch = "wasm32")]
+    let result = generate();
+
+    Ok(result?)
+}
+
+/// Set the BlurHash of the given attachment info, if it doesn't have one yet.
+#[cfg(feature = "image-proc")]
+fn set_blurhash(info: &mut Option<AttachmentInfo>, media_type: mime::Name<'_>, blurhash: String) {
+    match info {
+        Some(AttachmentInfo::Image(info)) => {
+            info.blurhash.get_or_insert(blurhash);
+        }
+        Some(AttachmentInfo::Video(info)) => {
+            info.blurhash.get_or_insert(blurhash);
+        }
+        Some(_) => {}
+        None if media_type == mime::IMAGE => {
+            *info = Some(AttachmentInfo::Image(BaseImageInfo {
+                height: None,
+                width: None,
+                size: None,
+                blurhash: Some(blurhash),
+            }));
+        }
+        None if media_type == mime::VIDEO => {
+            *info = Some(AttachmentInfo::Video(BaseVideoInfo {
+                duration: None,
+                height: None,
+                width: None,
+                size: None,
+                blurhash: Some(blurhash),
+            }));
+        }
+        None => {}
+    }
+}
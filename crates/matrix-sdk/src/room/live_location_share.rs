@@ -0,0 +1,89 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Live location sharing, as defined in [MSC3489] and [MSC3672].
+//!
+//! A live location share is started with an `m.beacon_info` state event, whose
+//! state key is the ID of the user sharing their location. The locations are
+//! then sent as `m.beacon` events that reference it, until the share times
+//! out or is stopped by replacing the state event with a non-live one.
+//!
+//! [MSC3489]: https://github.com/matrix-org/matrix-spec-proposals/pull/3489
+//! [MSC3672]: https://github.com/matrix-org/matrix-spec-proposals/pull/3672
+
+use ruma::{
+    events::{beacon::BeaconEventContent, beacon_info::BeaconInfoEventContent},
+    EventId, OwnedEventId,
+};
+use tracing::instrument;
+
+use super::Room;
+use crate::Result;
+
+/// A live location share of the own user in a room.
+///
+/// To get this, use [`Room::start_live_location_share()`].
+#[derive(Debug, Clone)]
+pub struct LiveLocationShare {
+    room: Room,
+    beacon_info: BeaconInfoEventContent,
+    beacon_info_event_id: OwnedEventId,
+}
+
+impl LiveLocationShare {
+    pub(super) fn new(
+        room: Room,
+        beacon_info: BeaconInfoEventContent,
+        beacon_info_event_id: OwnedEventId,
+    ) -> Self {
+        Self { room, beacon_info, beacon_info_event_id }
+    }
+
+    /// The ID of the `m.beacon_info` event that started this share.
+    pub fn beacon_info_event_id(&self) -> &EventId {
+        &self.beacon_info_event_id
+    }
+
+    /// Whether this share is still live, i.e. it was not stopped and it has
+    /// not timed out yet.
+    pub fn is_live(&self) -> bool {
+        self.beacon_info.is_live()
+    }
+
+    /// Share the current location of the user.
+    ///
+    /// Returns the ID of the `m.beacon` event that was sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `geo_uri` - The location, as a `geo:` URI as defined in [RFC 5870].
+    ///
+    /// [RFC 5870]: https://datatracker.ietf.org/doc/html/rfc5870
+    #[instrument(skip_all, fields(room_id = ?self.room.room_id()))]
+    pub async fn update_location(&self, geo_uri: String) -> Result<OwnedEventId> {
+        let content = BeaconEventContent::new(self.beacon_info_event_id.clone(), geo_uri, None);
+        let response = self.room.send(content, None).await?;
+
+        Ok(response.event_id)
+    }
+
+    /// Stop this share, before it times out.
+    #[instrument(skip_all, fields(room_id = ?self.room.room_id()))]
+    pub async fn stop(mut self) -> Result<()> {
+        self.beacon_info.live = false;
+        self.room.send_state_event_for_key(self.room.own_user_id(), self.beacon_info).await?;
+
+        Ok(())
+    }
+}
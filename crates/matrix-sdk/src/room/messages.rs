@@ -138,3 +138,29 @@ pub struct Messages {
     /// A list of state events relevant to showing the `chunk`.
     pub state: Vec<Raw<AnyStateEvent>>,
 }
+
+/// The result of a [`Room::event_context`][super::Room::event_context] call.
+///
+/// In short, this is a possibly decrypted version of the response of a
+/// `rooms/{roomId}/context/{eventId}` api call.
+#[derive(Debug)]
+pub struct EventContext {
+    /// The requested event, if the homeserver returned it.
+    pub event: Option<TimelineEvent>,
+
+    /// The events before the requested event, in reverse-chronological order.
+    pub events_before: Vec<TimelineEvent>,
+
+    /// The events after the requested event, in chronological order.
+    pub events_after: Vec<TimelineEvent>,
+
+    /// A token to paginate backwards from the first event of
+    /// `events_before`.
+    pub prev_batch_token: Option<String>,
+
+    /// A token to paginate forwards from the last event of `events_after`.
+    pub next_batch_token: Option<String>,
+
+    /// A list of state events relevant to showing the events.
+    pub state: Vec<Raw<AnyStateEvent>>,
+}
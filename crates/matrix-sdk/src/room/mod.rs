@@ -2,7 +2,11 @@
 
 use std::{borrow::Borrow, collections::BTreeMap, ops::Deref, sync::Arc, time::Duration};
 
-use eyeball::SharedObservable;
+use eyeball::{SharedObservable, Subscriber};
+use futures_core::Stream;
+#[cfg(feature = "e2e-encryption")]
+use futures_util::future::try_join_all;
+use futures_util::{stream, StreamExt};
 use matrix_sdk_base::{
     deserialized_responses::{
         MembersResponse, RawAnySyncOrStrippedState, RawSyncOrStrippedState, SyncOrStrippedState,
@@ -10,39 +14,52 @@ use matrix_sdk_base::{
     },
     instant::Instant,
     store::StateStoreExt,
-    RoomMemberships, StateChanges,
+    sync::UnreadCounts,
+    ComposerDraft, RoomInfo, RoomMemberships, StateChanges, StateStoreDataKey, StateStoreDataValue,
 };
 use matrix_sdk_common::timeout::timeout;
 use mime::Mime;
 #[cfg(feature = "e2e-encryption")]
 use ruma::events::{
-    room::encrypted::OriginalSyncRoomEncryptedEvent, AnySyncMessageLikeEvent, AnySyncTimelineEvent,
-    SyncMessageLikeEvent,
+    room::encrypted::OriginalSyncRoomEncryptedEvent, AnySyncMessageLikeEvent, SyncMessageLikeEvent,
 };
 use ruma::{
-    api::client::{
-        config::set_global_account_data,
-        context,
-        error::ErrorKind,
-        filter::LazyLoadOptions,
-        membership::{
-            ban_user, forget_room, get_member_events,
-            invite_user::{self, v3::InvitationRecipient},
-            join_room_by_id, kick_user, leave_room, Invite3pid,
+    api::{
+        client::{
+            config::{set_global_account_data, set_room_account_data},
+            context,
+            error::ErrorKind,
+            filter::{LazyLoadOptions, RoomEventFilter},
+            membership::{
+                ban_user, forget_room, get_member_events,
+                invite_user::{self, v3::InvitationRecipient},
+                join_room_by_id, kick_user, leave_room, Invite3pid, Invite3pidInit,
+            },
+            message::send_message_event,
+            read_marker::set_read_marker,
+            receipt::create_receipt,
+            redact::redact_event,
+            relations::{
+                get_relating_events, get_relating_events_with_rel_type,
+                get_relating_events_with_rel_type_and_event_type,
+            },
+            room::{get_room_event, report_content, upgrade_room},
+            state::{get_state_events_for_key, send_state_event},
+            tag::{create_tag, delete_tag},
+            typing::create_typing_event::{self, v3::Typing},
         },
-        message::send_message_event,
-        read_marker::set_read_marker,
-        receipt::create_receipt,
-        redact::redact_event,
-        room::get_room_event,
-        state::{get_state_events_for_key, send_state_event},
-        tag::{create_tag, delete_tag},
-        typing::create_typing_event::{self, v3::Typing},
+        Direction,
     },
     assign,
     events::{
+        beacon_info::BeaconInfoEventContent,
         direct::DirectEventContent,
+        policy::rule::{
+            room::PolicyRuleRoomEventContent, server::PolicyRuleServerEventContent,
+            user::PolicyRuleUserEventContent, PolicyRuleEventContent, Recommendation,
+        },
         receipt::{Receipt, ReceiptThread, ReceiptType},
+        relation::RelationType,
         room::{
             avatar::{self, RoomAvatarEventContent},
             encryption::RoomEncryptionEventContent,
@@ -51,19 +68,24 @@ use ruma::{
             name::RoomNameEventContent,
             power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent},
             server_acl::RoomServerAclEventContent,
+            tombstone::RoomTombstoneEventContent,
             topic::RoomTopicEventContent,
             MediaSource,
         },
         tag::{TagInfo, TagName},
-        AnyRoomAccountDataEvent, AnyStateEvent, EmptyStateKey, MessageLikeEventContent,
-        MessageLikeEventType, RedactContent, RedactedStateEventContent, RoomAccountDataEvent,
+        typing::SyncTypingEvent,
+        AnyMessageLikeEvent, AnyRoomAccountDataEvent, AnyStateEvent, AnySyncTimelineEvent,
+        AnyTimelineEvent, EmptyStateKey, MessageLikeEventContent, MessageLikeEventType,
+        RedactContent, RedactedStateEventContent, RoomAccountDataEvent,
         RoomAccountDataEventContent, RoomAccountDataEventType, StateEventContent, StateEventType,
         StaticEventContent, StaticStateEventContent,
     },
     push::{Action, PushConditionRoomCtx},
     serde::Raw,
-    uint, EventId, Int, MatrixToUri, MatrixUri, MxcUri, OwnedEventId, OwnedServerName,
-    OwnedTransactionId, OwnedUserId, TransactionId, UInt, UserId,
+    thirdparty::Medium,
+    uint, EventId, Int, MatrixToUri, MatrixUri, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId,
+    OwnedMxcUri, OwnedRoomId, OwnedServerName, OwnedTransactionId, OwnedUserId, RoomVersionId,
+    TransactionId, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
 use thiserror::Error;
@@ -73,20 +95,39 @@ use tracing::{debug, instrument, warn};
 use crate::{
     attachment::AttachmentConfig,
     error::WrongRoomState,
+    event_cache::RoomEventCache,
     event_handler::{EventHandler, EventHandlerHandle, SyncEvent},
+    identity_server::IdentityServerClient,
     media::{MediaFormat, MediaRequest},
+    mentions::MentionParser,
+    permalinks::PermalinkTarget,
     sync::RoomUpdate,
-    BaseRoom, Client, Error, HttpError, HttpResult, Result, RoomState, TransmissionProgress,
+    BaseRoom, Client, Error, HttpError, HttpResult, NetworkState, Result, RoomState,
+    TransmissionProgress,
 };
 
 mod futures;
+mod live_location_share;
 mod member;
 mod messages;
+mod policy_list;
+mod relations;
+pub(crate) mod retention;
+pub(crate) mod search;
+pub(crate) mod send_queue;
 
 pub use self::{
     futures::SendAttachment,
+    live_location_share::LiveLocationShare,
     member::RoomMember,
-    messages::{Messages, MessagesOptions},
+    messages::{EventContext, Messages, MessagesOptions},
+    policy_list::{PolicyListSubscription, PolicyRule, PolicyRuleKind},
+    relations::{Relations, RelationsOptions},
+    retention::RetentionPolicy,
+    search::{SearchOptions, SearchResult, SearchResults},
+    send_queue::{
+        DependentRequest, QueuedDependentRequest, QueuedEvent, QueuedEventContent, RoomSendQueue,
+    },
 };
 
 /// A struct containing methods that are common for Joined, Invited and Left
@@ -121,12 +162,16 @@ impl Room {
 
     /// Leave this room.
     ///
-    /// Only invited and joined rooms can be left.
+    /// Only invited, knocked and joined rooms can be left. Leaving a knocked
+    /// room withdraws the request to join it.
     #[doc(alias = "reject_invitation")]
     pub async fn leave(&self) -> Result<()> {
         let state = self.state();
         if state == RoomState::Left {
-            return Err(Error::WrongRoomState(WrongRoomState::new("Joined or Invited", state)));
+            return Err(Error::WrongRoomState(WrongRoomState::new(
+                "Joined, Invited or Knocked",
+                state,
+            )));
         }
 
         let request = leave_room::v3::Request::new(self.inner.room_id().to_owned());
@@ -242,33 +287,54 @@ impl Room {
     /// ```
     #[instrument(skip_all, fields(room_id = ?self.inner.room_id(), ?options))]
     pub async fn messages(&self, options: MessagesOptions) -> Result<Messages> {
-        let room_id = self.inner.room_id();
-        let request = options.into_request(room_id);
-        let http_response = self.client.send(request, None).await?;
+        let request = options.into_request(self.inner.room_id());
+        let mut http_response = self.client.send(request, None).await?;
+        self.client.preprocess_events(self.room_id(), &mut http_response.state);
 
-        #[allow(unused_mut)]
-        let mut response = Messages {
+        Ok(Messages {
             start: http_response.start,
             end: http_response.end,
-            #[cfg(not(feature = "e2e-encryption"))]
-            chunk: http_response.chunk.into_iter().map(TimelineEvent::new).collect(),
-            #[cfg(feature = "e2e-encryption")]
-            chunk: Vec::with_capacity(http_response.chunk.len()),
+            chunk: self.to_timeline_events(http_response.chunk).await?,
             state: http_response.state,
-        };
+        })
+    }
+
+    /// Run the event preprocessors on the given events, try to decrypt them,
+    /// if they are encrypted, and compute their push actions.
+    ///
+    /// The events dropped by the preprocessors are removed.
+    async fn to_timeline_events(
+        &self,
+        mut events: Vec<Raw<AnyTimelineEvent>>,
+    ) -> Result<Vec<TimelineEvent>> {
+        self.client.preprocess_events(self.room_id(), &mut events);
+
+        #[cfg(not(feature = "e2e-encryption"))]
+        let mut timeline_events: Vec<_> = events.into_iter().map(TimelineEvent::new).collect();
+
+        #[cfg(feature = "e2e-encryption")]
+        let mut timeline_events = Vec::with_capacity(events.len());
 
         #[cfg(feature = "e2e-encryption")]
         {
+            let room_id = self.inner.room_id();
             let machine = self.client.olm_machine().await;
             if let Some(machine) = machine.as_ref() {
-                for event in http_response.chunk {
+                for event in events {
                     let decrypted_event = if let Ok(AnySyncTimelineEvent::MessageLike(
                         AnySyncMessageLikeEvent::RoomEncrypted(SyncMessageLikeEvent::Original(_)),
                     )) = event.deserialize_as::<AnySyncTimelineEvent>()
                     {
-                        if let Ok(event) =
-                            machine.decrypt_room_event(event.cast_ref(), room_id).await
+                        let settings = self.client.base_client().decryption_settings();
+
+                        if let Ok(mut event) = machine
+                            .decrypt_room_event_with_settings(event.cast_ref(), room_id, settings)
+                            .await
                         {
+                            if !self.client.preprocess_event(room_id, &mut event.event) {
+                                continue;
+                            }
+
                             event
                         } else {
                             TimelineEvent::new(event)
@@ -277,23 +343,78 @@ impl Room {
                         TimelineEvent::new(event)
                     };
 
-                    response.chunk.push(decrypted_event);
+                    timeline_events.push(decrypted_event);
                 }
             } else {
-                response.chunk.extend(http_response.chunk.into_iter().map(TimelineEvent::new));
+                timeline_events.extend(events.into_iter().map(TimelineEvent::new));
             }
         }
 
         if let Some(push_context) = self.push_context().await? {
             let push_rules = self.client().account().push_rules().await?;
 
-            for event in &mut response.chunk {
+            for event in &mut timeline_events {
                 event.push_actions =
                     Some(push_rules.get_actions(&event.event, &push_context).to_owned());
             }
         }
 
-        Ok(response)
+        Ok(timeline_events)
+    }
+
+    /// Get the cache of the timeline events of this room.
+    ///
+    /// Returns `None` if [`ClientBuilder::cache_room_events()`] wasn't used.
+    ///
+    /// [`ClientBuilder::cache_room_events()`]: crate::ClientBuilder::cache_room_events
+    pub fn event_cache(&self) -> Option<RoomEventCache> {
+        let event_cache = self.client.inner.event_cache.as_ref()?;
+        Some(RoomEventCache::new(self.clone(), event_cache))
+    }
+
+    /// Get a stream of the unread counts of this room, computed by the SDK
+    /// from the cached events and the read receipts of the current user.
+    ///
+    /// A new value is yielded every time the counts change, when a sync
+    /// brings new events or read receipts. The counts are only computed if
+    /// the event cache is enabled with [`ClientBuilder::cache_room_events()`].
+    /// Use [`BaseRoom::unread_counts()`] to get the current value.
+    ///
+    /// [`ClientBuilder::cache_room_events()`]: crate::ClientBuilder::cache_room_events
+    /// [`BaseRoom::unread_counts()`]: crate::BaseRoom::unread_counts
+    pub fn subscribe_to_unread_counts(&self) -> impl Stream<Item = UnreadCounts> {
+        self.subscribe_to_room_info_field(RoomInfo::unread_counts)
+    }
+
+    /// Search the messages of this room for the given query.
+    ///
+    /// A message matches the query if it contains all the words of the query,
+    /// ignoring case.
+    ///
+    /// In encrypted rooms, the homeserver can't search the messages, so the
+    /// search is done in the local search index, which only contains the
+    /// messages received via sync while
+    /// [`ClientBuilder::index_encrypted_messages()`] was enabled. In other
+    /// rooms, the search is done by the homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The words to search for.
+    ///
+    /// * `options` - Options for the search, like pagination.
+    ///
+    /// [`ClientBuilder::index_encrypted_messages()`]: crate::ClientBuilder::index_encrypted_messages
+    #[instrument(skip_all, fields(room_id = ?self.inner.room_id(), ?options))]
+    pub async fn search_messages(
+        &self,
+        query: &str,
+        options: SearchOptions,
+    ) -> Result<SearchResults> {
+        if self.is_encrypted().await? {
+            search::search_local(self, query, options).await
+        } else {
+            search::search_server(self, query, options).await
+        }
     }
 
     /// Register a handler for events of a specific type, within this room.
@@ -313,6 +434,19 @@ impl Room {
         self.client.add_room_event_handler(self.room_id(), handler)
     }
 
+    /// Register a handler for timeline events with the given type, received
+    /// as raw JSON, within this room.
+    ///
+    /// This method works the same way as [`Client::add_raw_event_handler`],
+    /// except that the handler will only be called for events within this
+    /// room.
+    pub fn add_raw_event_handler<Ctx, H>(&self, event_type: &str, handler: H) -> EventHandlerHandle
+    where
+        H: EventHandler<Raw<AnySyncTimelineEvent>, Ctx>,
+    {
+        self.client.add_raw_event_handler_impl(event_type, handler, Some(self.room_id().to_owned()))
+    }
+
     /// Subscribe to all updates for this room.
     ///
     /// The returned receiver will receive a new message for each sync response
@@ -322,18 +456,27 @@ impl Room {
     }
 
     /// Fetch the event with the given `EventId` in this room.
+    ///
+    /// Returns [`Error::EventDropped`] if the event is dropped by an event
+    /// preprocessor.
     pub async fn event(&self, event_id: &EventId) -> Result<TimelineEvent> {
         let request =
             get_room_event::v3::Request::new(self.room_id().to_owned(), event_id.to_owned());
-        let event = self.client.send(request, None).await?.event;
+        let mut event = self.client.send(request, None).await?.event;
+
+        if !self.client.preprocess_event(self.room_id(), &mut event) {
+            return Err(Error::EventDropped);
+        }
 
         #[cfg(feature = "e2e-encryption")]
         if let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomEncrypted(
             SyncMessageLikeEvent::Original(_),
         ))) = event.deserialize_as::<AnySyncTimelineEvent>()
         {
-            if let Ok(event) = self.decrypt_event(event.cast_ref()).await {
-                return Ok(event);
+            match self.decrypt_event(event.cast_ref()).await {
+                Ok(event) => return Ok(event),
+                Err(Error::EventDropped) => return Err(Error::EventDropped),
+                Err(_) => {}
             }
         }
 
@@ -344,6 +487,8 @@ impl Room {
 
     /// Fetch the event with the given `EventId` in this room, using the
     /// `/context` endpoint to get more information.
+    ///
+    /// Returns `None` if the event is dropped by an event preprocessor.
     pub async fn event_with_context(
         &self,
         event_id: &EventId,
@@ -359,19 +504,27 @@ impl Room {
                 LazyLoadOptions::Enabled { include_redundant_members: false };
         }
 
-        let response = self.client.send(request, None).await?;
+        let mut response = self.client.send(request, None).await?;
 
-        let Some(event) = response.event else {
+        let Some(mut event) = response.event else {
             return Ok(None);
         };
 
+        if !self.client.preprocess_event(self.room_id(), &mut event) {
+            return Ok(None);
+        }
+
+        self.client.preprocess_events(self.room_id(), &mut response.state);
+
         #[cfg(feature = "e2e-encryption")]
         if let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomEncrypted(
             SyncMessageLikeEvent::Original(_),
         ))) = event.deserialize_as::<AnySyncTimelineEvent>()
         {
-            if let Ok(event) = self.decrypt_event(event.cast_ref()).await {
-                return Ok(Some((event, response.state)));
+            match self.decrypt_event(event.cast_ref()).await {
+                Ok(event) => return Ok(Some((event, response.state))),
+                Err(Error::EventDropped) => return Ok(None),
+                Err(_) => {}
             }
         }
 
@@ -380,6 +533,130 @@ impl Room {
         Ok(Some((TimelineEvent { event, encryption_info: None, push_actions }, response.state)))
     }
 
+    /// Fetch the event with the given `EventId` in this room, along with the
+    /// events around it, using the `/context` endpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event to fetch.
+    ///
+    /// * `context_size` - The maximum number of events to return around the
+    ///   event, split between the events before and after it by the homeserver.
+    #[instrument(skip(self), fields(room_id = ?self.inner.room_id()))]
+    pub async fn event_context(
+        &self,
+        event_id: &EventId,
+        context_size: UInt,
+    ) -> Result<EventContext> {
+        let request = assign!(
+            context::get_context::v3::Request::new(self.room_id().to_owned(), event_id.to_owned()),
+            { limit: context_size }
+        );
+
+        let mut response = self.client.send(request, None).await?;
+        self.client.preprocess_events(self.room_id(), &mut response.state);
+
+        let event = match response.event {
+            Some(event) => self.to_timeline_events(vec![event]).await?.pop(),
+            None => None,
+        };
+
+        Ok(EventContext {
+            event,
+            events_before: self.to_timeline_events(response.events_before).await?,
+            events_after: self.to_timeline_events(response.events_after).await?,
+            prev_batch_token: response.start,
+            next_batch_token: response.end,
+            state: response.state,
+        })
+    }
+
+    /// Get the events that relate to the event with the given `EventId` in
+    /// this room, using the `/relations` endpoint.
+    ///
+    /// This allows to fetch the replies of a thread, the edits of a message or
+    /// its reactions, for example. The events are decrypted if possible.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event to get the relations of.
+    ///
+    /// * `options` - The options for the request, like the type of relations to
+    ///   get and the token to paginate from.
+    #[instrument(skip(self), fields(room_id = ?self.inner.room_id()))]
+    pub async fn relations(
+        &self,
+        event_id: &EventId,
+        options: RelationsOptions,
+    ) -> Result<Relations> {
+        let RelationsOptions { from, dir, limit, rel_type, event_type, recurse } = options;
+        let room_id = self.room_id().to_owned();
+        let event_id = event_id.to_owned();
+
+        let (chunk, next_batch, prev_batch, recursion_depth) = match (rel_type, event_type) {
+            (Some(rel_type), Some(event_type)) => {
+                let request = assign!(
+                    get_relating_events_with_rel_type_and_event_type::v1::Request::new(
+                        room_id, event_id, rel_type, event_type,
+                    ),
+                    { from, dir, limit, recurse }
+                );
+                let response = self.client.send(request, None).await?;
+                (response.chunk, response.next_batch, response.prev_batch, response.recursion_depth)
+            }
+            (Some(rel_type), None) => {
+                let request = assign!(
+                    get_relating_events_with_rel_type::v1::Request::new(room_id, event_id, rel_type),
+                    { from, dir, limit, recurse }
+                );
+                let response = self.client.send(request, None).await?;
+                (response.chunk, response.next_batch, response.prev_batch, response.recursion_depth)
+            }
+            (None, _) => {
+                let request = assign!(
+                    get_relating_events::v1::Request::new(room_id, event_id),
+                    { from, dir, limit, recurse }
+                );
+                let response = self.client.send(request, None).await?;
+                (response.chunk, response.next_batch, response.prev_batch, response.recursion_depth)
+            }
+        };
+
+        Ok(Relations {
+            chunk: self.to_timeline_events(chunk.into_iter().map(Raw::cast).collect()).await?,
+            next_batch_token: next_batch,
+            prev_batch_token: prev_batch,
+            recursion_depth,
+        })
+    }
+
+    /// Get all the edits of the event with the given `EventId` in this room,
+    /// from the oldest to the most recent.
+    ///
+    /// The events with an `m.replace` relation to the event are fetched with
+    /// [`Self::relations()`] until there are no more of them. They are not
+    /// validated, so it is up to the caller to check, for example, that they
+    /// were sent by the sender of the original event.
+    #[instrument(skip(self), fields(room_id = ?self.inner.room_id()))]
+    pub async fn event_edits(&self, event_id: &EventId) -> Result<Vec<TimelineEvent>> {
+        let mut options = assign!(RelationsOptions::with_rel_type(RelationType::Replacement), {
+            dir: Direction::Forward,
+        });
+        let mut edits = Vec::new();
+
+        loop {
+            let relations = self.relations(event_id, options.clone()).await?;
+            edits.extend(relations.chunk);
+
+            match relations.next_batch_token {
+                Some(token) => options.from = Some(token),
+                None => break,
+            }
+        }
+
+        Ok(edits)
+    }
+
     pub(crate) async fn request_members(&self) -> Result<Option<MembersResponse>> {
         let mut map = self.client.inner.members_request_locks.lock().await;
 
@@ -631,6 +908,31 @@ impl Room {
             .collect())
     }
 
+    /// Get a [`MentionParser`] that can mention the active members of this
+    /// room with their display name.
+    ///
+    /// The rest of the messages is converted to HTML with the
+    /// [`MessageFormatter`] of the client.
+    ///
+    /// The members whose display name is ambiguous can only be mentioned with
+    /// their user ID.
+    ///
+    /// *Note*: This method will fetch the members from the homeserver if the
+    /// member list isn't synchronized due to member lazy loading.
+    ///
+    /// [`MessageFormatter`]: crate::formatting::MessageFormatter
+    pub async fn mention_parser(&self) -> Result<MentionParser> {
+        let mut parser = MentionParser::with_formatter(self.client.inner.message_formatter.clone());
+
+        for member in self.members(RoomMemberships::ACTIVE).await? {
+            if let Some(display_name) = member.display_name().filter(|_| !member.name_ambiguous()) {
+                parser.add_member(member.user_id().to_owned(), display_name);
+            }
+        }
+
+        Ok(parser)
+    }
+
     /// Get all state events of a given type in this room.
     pub async fn get_state_events(
         &self,
@@ -819,6 +1121,66 @@ impl Room {
         Ok(self.account_data(C::TYPE.into()).await?.map(Raw::cast))
     }
 
+    /// Get a stream of the content of an account data event of
+    /// statically-known type in this room.
+    ///
+    /// A new value is yielded every time an event of this type is received for
+    /// this room in a sync response. Use [`Room::account_data_static()`] to get
+    /// the current value.
+    pub fn subscribe_to_account_data<C>(&self) -> impl Stream<Item = C>
+    where
+        C: StaticEventContent + RoomAccountDataEventContent + Send + 'static,
+        RoomAccountDataEvent<C>: DeserializeOwned,
+    {
+        let (sender, receiver) = async_channel::unbounded();
+
+        let handle = self.add_event_handler(move |event: RoomAccountDataEvent<C>| {
+            let sender = sender.clone();
+            async move {
+                // The receiver is only dropped with the stream, which also
+                // removes this event handler.
+                let _ = sender.send(event.content).await;
+            }
+        });
+
+        self.client.event_handler_stream(handle, receiver)
+    }
+
+    /// Set the given account data event for this room.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async {
+    /// # let room: matrix_sdk::Room = todo!();
+    /// use matrix_sdk::ruma::{
+    ///     event_id, events::fully_read::FullyReadEventContent,
+    /// };
+    ///
+    /// let content =
+    ///     FullyReadEventContent::new(event_id!("$event:localhost").to_owned());
+    /// room.set_account_data(content).await?;
+    /// # anyhow::Ok(())
+    /// # };
+    /// ```
+    pub async fn set_account_data<C>(
+        &self,
+        content: C,
+    ) -> Result<set_room_account_data::v3::Response>
+    where
+        C: RoomAccountDataEventContent,
+    {
+        let own_user = self.client.user_id().ok_or(HttpError::AuthenticationRequired)?;
+
+        let request = set_room_account_data::v3::Request::new(
+            own_user.to_owned(),
+            self.room_id().to_owned(),
+            &content,
+        )?;
+
+        Ok(self.client.send(request, None).await?)
+    }
+
     /// Check if all members of this room are verified and all their devices are
     /// verified.
     ///
@@ -901,6 +1263,104 @@ impl Room {
         self.client.send(request, None).await
     }
 
+    /// Add or remove the `m.favourite` tag of this room.
+    ///
+    /// Adding the `m.favourite` tag removes the `m.lowpriority` tag, since a
+    /// room can't be both.
+    ///
+    /// The change is reflected in [`Room::is_favourite()`] once the tags are
+    /// received via sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `is_favourite` - Whether the room should be tagged as a favourite.
+    ///
+    /// * `tag_order` - The order of the room among the favourites, between 0
+    ///   and 1.
+    pub async fn set_is_favourite(&self, is_favourite: bool, tag_order: Option<f64>) -> Result<()> {
+        if is_favourite {
+            let tag_info = assign!(TagInfo::new(), { order: tag_order });
+            self.set_tag(TagName::Favorite, tag_info).await?;
+
+            if self.is_low_priority() {
+                self.remove_tag(TagName::LowPriority).await?;
+            }
+        } else {
+            self.remove_tag(TagName::Favorite).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Add or remove the `m.lowpriority` tag of this room.
+    ///
+    /// Adding the `m.lowpriority` tag removes the `m.favourite` tag, since a
+    /// room can't be both.
+    ///
+    /// The change is reflected in [`Room::is_low_priority()`] once the tags
+    /// are received via sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `is_low_priority` - Whether the room should be tagged as low priority.
+    ///
+    /// * `tag_order` - The order of the room among the low priority rooms,
+    ///   between 0 and 1.
+    pub async fn set_is_low_priority(
+        &self,
+        is_low_priority: bool,
+        tag_order: Option<f64>,
+    ) -> Result<()> {
+        if is_low_priority {
+            let tag_info = assign!(TagInfo::new(), { order: tag_order });
+            self.set_tag(TagName::LowPriority, tag_info).await?;
+
+            if self.is_favourite() {
+                self.remove_tag(TagName::Favorite).await?;
+            }
+        } else {
+            self.remove_tag(TagName::LowPriority).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Get a stream of whether this room is tagged as a favourite.
+    ///
+    /// A new value is yielded every time the `m.favourite` tag is added or
+    /// removed. Use [`Room::is_favourite()`] to get the current value.
+    pub fn subscribe_to_is_favourite(&self) -> impl Stream<Item = bool> {
+        self.subscribe_to_room_info_field(RoomInfo::is_favourite)
+    }
+
+    /// Get a stream of whether this room is tagged as low priority.
+    ///
+    /// A new value is yielded every time the `m.lowpriority` tag is added or
+    /// removed. Use [`Room::is_low_priority()`] to get the current value.
+    pub fn subscribe_to_is_low_priority(&self) -> impl Stream<Item = bool> {
+        self.subscribe_to_room_info_field(RoomInfo::is_low_priority)
+    }
+
+    /// Get a stream of the changes of a field of the `RoomInfo`.
+    fn subscribe_to_room_info_field<T>(&self, field: fn(&RoomInfo) -> T) -> impl Stream<Item = T>
+    where
+        T: Copy + PartialEq,
+    {
+        let mut room_info = self.subscribe_info();
+        let mut value = field(&room_info.get());
+
+        async_stream::stream! {
+            while let Some(info) = room_info.next().await {
+                let new_value = field(&info);
+
+                if new_value != value {
+                    value = new_value;
+                    yield value;
+                }
+            }
+        }
+    }
+
     /// Sets whether this room is a DM.
     ///
     /// When setting this room as DM, it will be marked as DM for all active
@@ -954,7 +1414,8 @@ impl Room {
     /// # Arguments
     /// * `event` - The room event to be decrypted.
     ///
-    /// Returns the decrypted event.
+    /// Returns the decrypted event, after running the event preprocessors on
+    /// it, or [`Error::EventDropped`] if they dropped it.
     #[cfg(feature = "e2e-encryption")]
     pub async fn decrypt_event(
         &self,
@@ -962,8 +1423,17 @@ impl Room {
     ) -> Result<TimelineEvent> {
         let machine = self.client.olm_machine().await;
         if let Some(machine) = machine.as_ref() {
-            let mut event =
-                machine.decrypt_room_event(event.cast_ref(), self.inner.room_id()).await?;
+            let mut event = machine
+                .decrypt_room_event_with_settings(
+                    event.cast_ref(),
+                    self.inner.room_id(),
+                    self.client.base_client().decryption_settings(),
+                )
+                .await?;
+
+            if !self.client.preprocess_event(self.room_id(), &mut event.event) {
+                return Err(Error::EventDropped);
+            }
 
             event.push_actions = self.event_push_actions(&event.event).await?;
 
@@ -973,6 +1443,107 @@ impl Room {
         }
     }
 
+    /// Get the members of this room whose identity changed in a way that needs
+    /// the attention of the user, and a stream of updates of that list.
+    ///
+    /// A member is included if their identity has a verification violation,
+    /// i.e. it was verified before but isn't anymore, or a pin violation, i.e.
+    /// its master key changed since it was pinned. See
+    /// [`UserIdentity::has_verification_violation()`] and
+    /// [`UserIdentity::has_pin_violation()`].
+    ///
+    /// The list is updated every time user identities are updated in the
+    /// crypto store. Only the users whose identity changed are checked again,
+    /// and the stream only yields a new list if it changed.
+    ///
+    /// [`UserIdentity::has_verification_violation()`]: crate::encryption::identities::UserIdentity::has_verification_violation
+    /// [`UserIdentity::has_pin_violation()`]: crate::encryption::identities::UserIdentity::has_pin_violation
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn subscribe_to_identity_violations(
+        &self,
+    ) -> Result<(Vec<OwnedUserId>, impl Stream<Item = Vec<OwnedUserId>>)> {
+        let identities_changed = {
+            let olm = self.client.olm_machine().await;
+            olm.as_ref().ok_or(Error::NoOlmMachine)?.store().identities_changed_stream()
+        };
+        let mut user_ids = self.members_with_identity_violations().await?;
+        let current = user_ids.clone();
+
+        let room = self.clone();
+        let stream = async_stream::stream! {
+            for await changed_user_ids in identities_changed {
+                match room.update_identity_violations(&mut user_ids, changed_user_ids).await {
+                    Ok(true) => yield user_ids.clone(),
+                    Ok(false) => {}
+                    Err(error) => warn!(?error, "Couldn't update the identity violations"),
+                }
+            }
+        };
+
+        Ok((current, stream))
+    }
+
+    /// Get the members of this room whose identity has a verification or pin
+    /// violation.
+    #[cfg(feature = "e2e-encryption")]
+    async fn members_with_identity_violations(&self) -> Result<Vec<OwnedUserId>> {
+        let mut user_ids = Vec::new();
+
+        for member in self.members_no_sync(RoomMemberships::ACTIVE).await? {
+            if self.has_identity_violation(member.user_id()).await? {
+                user_ids.push(member.user_id().to_owned());
+            }
+        }
+
+        Ok(user_ids)
+    }
+
+    /// Update the list of members with an identity violation after the
+    /// identities of the given users changed.
+    ///
+    /// Users that are not active members of this room are ignored, so this
+    /// doesn't do anything for rooms that don't share any user with the
+    /// change. Returns whether the list changed.
+    #[cfg(feature = "e2e-encryption")]
+    async fn update_identity_violations(
+        &self,
+        user_ids: &mut Vec<OwnedUserId>,
+        changed_user_ids: Vec<OwnedUserId>,
+    ) -> Result<bool> {
+        let mut changed = false;
+
+        for user_id in changed_user_ids {
+            let is_member = self
+                .get_member_no_sync(&user_id)
+                .await?
+                .is_some_and(|member| RoomMemberships::ACTIVE.matches(member.membership()));
+            let has_violation = is_member && self.has_identity_violation(&user_id).await?;
+
+            match (user_ids.iter().position(|id| *id == user_id), has_violation) {
+                (None, true) => {
+                    user_ids.push(user_id);
+                    changed = true;
+                }
+                (Some(index), false) => {
+                    user_ids.remove(index);
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Whether the identity of the given user has a verification or pin
+    /// violation.
+    #[cfg(feature = "e2e-encryption")]
+    async fn has_identity_violation(&self, user_id: &UserId) -> Result<bool> {
+        Ok(self.client.encryption().get_user_identity(user_id).await?.is_some_and(|identity| {
+            identity.has_verification_violation() || identity.has_pin_violation()
+        }))
+    }
+
     /// Ban the user with `UserId` from this room.
     ///
     /// # Arguments
@@ -1013,15 +1584,76 @@ impl Room {
     /// # Arguments
     ///
     /// * `user_id` - The `UserId` of the user to invite to the room.
+    ///
+    /// If the room is encrypted and its history is shared with new members,
+    /// the room keys that were created while the history was shared are sent
+    /// to the devices of the invited user, so they can read the history of
+    /// the room once they join it.
     #[instrument(skip_all)]
     pub async fn invite_user_by_id(&self, user_id: &UserId) -> Result<()> {
         let recipient = InvitationRecipient::UserId { user_id: user_id.to_owned() };
         let request = invite_user::v3::Request::new(self.room_id().to_owned(), recipient);
         self.client.send(request, None).await?;
 
+        // The invite went through, failing to share the room keys shouldn't be
+        // reported as a failure to invite the user.
+        #[cfg(feature = "e2e-encryption")]
+        if matches!(
+            self.history_visibility(),
+            HistoryVisibility::Shared | HistoryVisibility::WorldReadable
+        ) {
+            match self.is_encrypted().await {
+                Ok(true) => {
+                    if let Err(e) = self.share_history_with_member(user_id).await {
+                        warn!(error = ?e, "Couldn't share the room key history with the invited user");
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    warn!(error = ?e, "Couldn't check if the room is encrypted after an invite");
+                }
+            }
+        }
+
         Ok(())
     }
 
+    /// The members of this room that knocked on it and are waiting for a
+    /// moderator to approve or deny their request to join.
+    pub async fn knock_requests(&self) -> Result<Vec<RoomMember>> {
+        self.members(RoomMemberships::KNOCK).await
+    }
+
+    /// Approve the request of the given user to join this room, after they
+    /// knocked on it.
+    ///
+    /// This invites the user to the room, so it requires the permission to
+    /// invite users.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The `UserId` of the user that knocked on the room.
+    #[instrument(skip_all)]
+    pub async fn approve_knock(&self, user_id: &UserId) -> Result<()> {
+        self.invite_user_by_id(user_id).await
+    }
+
+    /// Deny the request of the given user to join this room, after they
+    /// knocked on it.
+    ///
+    /// This kicks the user from the room, so it requires the permission to
+    /// kick users.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The `UserId` of the user that knocked on the room.
+    ///
+    /// * `reason` - Optional reason why the request is denied.
+    #[instrument(skip_all)]
+    pub async fn deny_knock(&self, user_id: &UserId, reason: Option<&str>) -> Result<()> {
+        self.kick_user(user_id, reason).await
+    }
+
     /// Invite the specified user by third party id to this room.
     ///
     /// # Arguments
@@ -1036,6 +1668,36 @@ impl Room {
         Ok(())
     }
 
+    /// Invite a user to this room by their email address.
+    ///
+    /// The homeserver looks up the email address on the identity server. If
+    /// it is bound to a Matrix user, this user is invited directly. Otherwise
+    /// an `m.room.third_party_invite` event is sent to the room, and the
+    /// identity server sends an email to the address, so the invite is
+    /// received once the address is bound to a Matrix user.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The email address of the user to invite to the room.
+    ///
+    /// * `identity_server` - The identity server to use to look up the email
+    ///   address. Its terms of service need to be accepted.
+    #[instrument(skip_all)]
+    pub async fn invite_by_email(
+        &self,
+        address: &str,
+        identity_server: &IdentityServerClient,
+    ) -> Result<()> {
+        let invite_id = Invite3pidInit {
+            id_server: identity_server.server_name().to_owned(),
+            id_access_token: identity_server.access_token().to_owned(),
+            medium: Medium::Email,
+            address: address.to_owned(),
+        };
+
+        self.invite_user_by_3pid(invite_id.into()).await
+    }
+
     /// Activate typing notice for this room.
     ///
     /// The typing notice remains active for 4s. It can be deactivate at any
@@ -1044,6 +1706,9 @@ impl Room {
     /// called on every key stroke, since it will do nothing while typing is
     /// active.
     ///
+    /// The typing notice is not sent if the client is offline, and
+    /// [`Error::Offline`] is returned. See [`Client::network_state()`].
+    ///
     /// # Arguments
     ///
     /// * `typing` - Whether the user is typing or has stopped typing.
@@ -1073,6 +1738,11 @@ impl Room {
     pub async fn typing_notice(&self, typing: bool) -> Result<()> {
         self.ensure_room_joined()?;
 
+        if self.client.network_state() == NetworkState::Offline {
+            debug!("The client is offline, not sending the typing notice");
+            return Err(Error::Offline);
+        }
+
         // Only send a request to the homeserver if the old timeout has elapsed
         // or the typing notice changed state within the
         // TYPING_NOTICE_TIMEOUT
@@ -1121,6 +1791,62 @@ impl Room {
         Ok(())
     }
 
+    /// Get a stream of the members that are currently typing in this room.
+    ///
+    /// A new list is yielded every time a typing notification is received for
+    /// this room in a sync response. The homeserver takes care of removing
+    /// users whose typing notice timed out, so an empty list means that nobody
+    /// is typing anymore.
+    ///
+    /// The list never contains the own user, and users that aren't known
+    /// members of the room are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async {
+    /// # let room: matrix_sdk::Room = todo!();
+    /// use futures_util::StreamExt;
+    ///
+    /// let typing = room.subscribe_to_typing();
+    /// futures_util::pin_mut!(typing);
+    ///
+    /// while let Some(members) = typing.next().await {
+    ///     let names: Vec<_> =
+    ///         members.iter().map(|member| member.name()).collect();
+    ///     println!("Typing: {}", names.join(", "));
+    /// }
+    /// # anyhow::Ok(())
+    /// # };
+    /// ```
+    pub fn subscribe_to_typing(&self) -> impl Stream<Item = Vec<RoomMember>> {
+        let (sender, receiver) = async_channel::unbounded();
+
+        let handle = self.add_event_handler(move |event: SyncTypingEvent, room: Room| {
+            let sender = sender.clone();
+            async move {
+                let own_user_id = room.own_user_id();
+                let mut members = Vec::with_capacity(event.content.user_ids.len());
+
+                for user_id in event.content.user_ids.iter().filter(|id| *id != own_user_id) {
+                    match room.get_member_no_sync(user_id).await {
+                        Ok(Some(member)) => members.push(member),
+                        Ok(None) => debug!(?user_id, "Typing user isn't a known room member"),
+                        Err(error) => {
+                            warn!(?user_id, ?error, "Couldn't load the typing room member")
+                        }
+                    }
+                }
+
+                // The receiver is only dropped with the stream, which also
+                // removes this event handler.
+                let _ = sender.send(members).await;
+            }
+        });
+
+        self.client.event_handler_stream(handle, receiver)
+    }
+
     /// Send a request to set a single receipt.
     ///
     /// # Arguments
@@ -1134,6 +1860,11 @@ impl Room {
     ///   [`ReceiptType::FullyRead`][create_receipt::v3::ReceiptType::FullyRead].
     ///
     /// * `event_id` - The `EventId` of the event to set the receipt on.
+    ///
+    /// If the client is offline (see [`Client::network_state()`]), the receipt
+    /// is not sent and [`Error::Offline`] is returned. While the app is in the
+    /// background, the receipt is sent with the next batch of receipts, see
+    /// [`Client::set_app_state()`].
     #[instrument(skip_all)]
     pub async fn send_single_receipt(
         &self,
@@ -1141,6 +1872,22 @@ impl Room {
         thread: ReceiptThread,
         event_id: OwnedEventId,
     ) -> Result<()> {
+        if self.client.network_state() == NetworkState::Offline {
+            debug!("The client is offline, not sending the receipt");
+            return Err(Error::Offline);
+        }
+
+        if self.client.should_delay_receipts() {
+            debug!("The app is in the background, delaying the receipt");
+            let sent = self.client.inner.pending_receipts.lock().unwrap().add(
+                self.room_id(),
+                receipt_type,
+                thread,
+                event_id,
+            );
+            return sent.wait().await;
+        }
+
         let mut request =
             create_receipt::v3::Request::new(self.room_id().to_owned(), receipt_type, event_id);
         request.thread = thread;
@@ -1155,13 +1902,34 @@ impl Room {
     ///
     /// * `receipts` - The `Receipts` to send.
     ///
-    /// If `receipts` is empty, this is a no-op.
+    /// If `receipts` is empty, this is a no-op. If the client is offline (see
+    /// [`Client::network_state()`]), the receipts are not sent and
+    /// [`Error::Offline`] is returned. While the app is in the background, the
+    /// receipts are sent with the next batch of receipts, see
+    /// [`Client::set_app_state()`].
     #[instrument(skip_all)]
     pub async fn send_multiple_receipts(&self, receipts: Receipts) -> Result<()> {
         if receipts.is_empty() {
             return Ok(());
         }
 
+        if self.client.network_state() == NetworkState::Offline {
+            debug!("The client is offline, not sending the receipts");
+            return Err(Error::Offline);
+        }
+
+        if self.client.should_delay_receipts() {
+            debug!("The app is in the background, delaying the receipts");
+            let sent = self
+                .client
+                .inner
+                .pending_receipts
+                .lock()
+                .unwrap()
+                .add_multiple(self.room_id(), receipts);
+            return sent.wait().await;
+        }
+
         let Receipts { fully_read, public_read_receipt, private_read_receipt } = receipts;
         let request = assign!(set_read_marker::v3::Request::new(self.room_id().to_owned()), {
             fully_read,
@@ -1310,6 +2078,167 @@ impl Room {
         Ok(())
     }
 
+    /// Share the room keys that were created while the history of the room
+    /// was shared with the given user, who was just invited to the room or
+    /// joined it.
+    ///
+    /// Only the room keys that were created while the history visibility of
+    /// the room was `shared` or `world_readable` are shared, so the user can
+    /// read the messages that were sent while they were allowed to see them.
+    /// This is done automatically by [`Room::invite_user_by_id()`], but can be
+    /// useful for moderators or bots when a user joins the room by other
+    /// means.
+    ///
+    /// Only the devices of the user that are not blacklisted receive the room
+    /// keys.
+    ///
+    /// Returns [`Error::NotARoomMember`] if the user is not an invited or
+    /// joined member of the room.
+    #[cfg(feature = "e2e-encryption")]
+    #[instrument(skip_all, fields(user_id = ?user_id))]
+    pub async fn share_history_with(&self, user_id: &UserId) -> Result<()> {
+        let is_member = self
+            .get_member(user_id)
+            .await?
+            .is_some_and(|member| RoomMemberships::ACTIVE.matches(member.membership()));
+
+        if !is_member {
+            return Err(Error::NotARoomMember(user_id.to_owned()));
+        }
+
+        self.share_history_with_member(user_id).await
+    }
+
+    /// Share the room key history with the given user, who is known to be an
+    /// invited or joined member of the room.
+    #[cfg(feature = "e2e-encryption")]
+    async fn share_history_with_member(&self, user_id: &UserId) -> Result<()> {
+        if !self.is_encrypted().await? {
+            return Ok(());
+        }
+
+        let has_shareable_room_keys = !self
+            .client
+            .olm_machine()
+            .await
+            .as_ref()
+            .ok_or(Error::NoOlmMachine)?
+            .shareable_room_key_history(self.room_id())
+            .await?
+            .is_empty();
+
+        if !has_shareable_room_keys {
+            debug!("No room keys with a shared history to share");
+            return Ok(());
+        }
+
+        // Make sure that we know about the devices of the invited user and that
+        // we have Olm sessions with them before encrypting the room keys.
+        self.client
+            .olm_machine()
+            .await
+            .as_ref()
+            .ok_or(Error::NoOlmMachine)?
+            .update_tracked_users([user_id])
+            .await?;
+        self.client.send_outgoing_requests().await?;
+        self.client.claim_one_time_keys(std::iter::once(user_id)).await?;
+
+        let requests = self
+            .client
+            .olm_machine()
+            .await
+            .as_ref()
+            .ok_or(Error::NoOlmMachine)?
+            .share_room_key_history(self.room_id(), user_id)
+            .await?;
+
+        try_join_all(requests.iter().map(|request| async move {
+            let response = self.client.send_to_device(request).await?;
+            self.client.mark_request_as_sent(&request.txn_id, &response).await?;
+            Ok::<_, Error>(())
+        }))
+        .await?;
+
+        Ok(())
+    }
+
+    /// Upgrade this room to a new room version.
+    ///
+    /// The homeserver creates a new room with the given version, and closes
+    /// this room with a `m.room.tombstone` event pointing to the new room.
+    ///
+    /// Returns the ID of the new room.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_version` - The version of the new room.
+    #[instrument(skip_all)]
+    pub async fn upgrade(&self, new_version: RoomVersionId) -> Result<OwnedRoomId> {
+        let request = upgrade_room::v3::Request::new(self.room_id().to_owned(), new_version);
+        let response = self.client.send(request, None).await?;
+
+        Ok(response.replacement_room)
+    }
+
+    /// Join the room that replaced this room, if this room was upgraded.
+    ///
+    /// The tags and the user defined notification mode of this room are copied
+    /// to the new room. Failing to copy them isn't considered an error, since
+    /// the new room was joined.
+    ///
+    /// Returns the new room, or `None` if this room doesn't have a
+    /// `m.room.tombstone` event.
+    #[instrument(skip_all, fields(room_id = ?self.room_id()))]
+    pub async fn follow_upgrade(&self) -> Result<Option<Room>> {
+        let Some(tombstone) = self.tombstone() else {
+            return Ok(None);
+        };
+
+        let new_room = match self.client.get_room(&tombstone.replacement_room) {
+            Some(room) if room.state() == RoomState::Joined => room,
+            _ => {
+                // The server of the user that upgraded the room is in the new
+                // room, so it can be used to join it.
+                let via: Vec<OwnedServerName> = self
+                    .get_state_event_static::<RoomTombstoneEventContent>()
+                    .await?
+                    .and_then(|event| event.deserialize().ok())
+                    .map(|event| event.sender().server_name().to_owned())
+                    .into_iter()
+                    .collect();
+
+                self.client
+                    .join_room_by_id_or_alias((&*tombstone.replacement_room).into(), &via)
+                    .await?
+            }
+        };
+
+        match self.tags().await {
+            Ok(tags) => {
+                for (tag, tag_info) in tags.into_iter().flatten() {
+                    if let Err(error) = new_room.set_tag(tag, tag_info).await {
+                        warn!(?error, "Couldn't copy a tag to the new room");
+                    }
+                }
+            }
+            Err(error) => warn!(?error, "Couldn't load the tags of the room"),
+        }
+
+        let notification_settings = self.client.notification_settings().await;
+        if let Some(mode) =
+            notification_settings.get_user_defined_room_notification_mode(self.room_id()).await
+        {
+            if let Err(error) =
+                notification_settings.set_room_notification_mode(new_room.room_id(), mode).await
+            {
+                warn!(?error, "Couldn't copy the notification mode to the new room");
+            }
+        }
+
+        Ok(Some(new_room))
+    }
+
     /// Wait for the room to be fully synced.
     ///
     /// This method makes sure the room that was returned when joining a room
@@ -1327,6 +2256,25 @@ impl Room {
         }
     }
 
+    /// Get the persistent queue of the events waiting to be sent to this room.
+    ///
+    /// The queue is loaded from the state store the first time it is
+    /// requested, and is then shared by all the `Room`s with the same ID.
+    pub async fn send_queue(&self) -> Result<RoomSendQueue> {
+        let mut send_queues = self.client.inner.send_queues.lock().await;
+
+        let state = match send_queues.get(self.room_id()) {
+            Some(state) => state.clone(),
+            None => {
+                let state = RoomSendQueue::load(self).await?;
+                send_queues.insert(self.room_id().to_owned(), state.clone());
+                state
+            }
+        };
+
+        Ok(RoomSendQueue::new(self.clone(), state))
+    }
+
     /// Send a room message to this room.
     ///
     /// Returns the parsed response from the server.
@@ -1694,6 +2642,7 @@ impl Room {
                     data,
                     config.info,
                     config.thumbnail,
+                    config.txn_id.as_deref(),
                     send_progress,
                 )
                 .await?
@@ -1709,6 +2658,7 @@ impl Room {
                 data,
                 config.info,
                 config.thumbnail,
+                config.txn_id.as_deref(),
                 send_progress,
             )
             .await?;
@@ -1751,17 +2701,40 @@ impl Room {
     }
 
     /// Sets the name of this room.
+    ///
+    /// The name of the room is updated locally right away, and restored if
+    /// sending the state event fails.
     pub async fn set_name(&self, name: Option<String>) -> Result<send_state_event::v3::Response> {
-        self.send_state_event(RoomNameEventContent::new(name)).await
+        self.send_state_event_with_local_echo(
+            RoomNameEventContent::new(name.clone()),
+            LocalInfoUpdate::Name(name),
+        )
+        .await
+    }
+
+    /// Sets a new topic for this room.
+    ///
+    /// The topic of the room is updated locally right away, and restored if
+    /// sending the state event fails.
+    pub async fn set_topic(&self, topic: &str) -> Result<send_state_event::v3::Response> {
+        self.send_state_event_with_local_echo(
+            RoomTopicEventContent::new(topic.to_owned()),
+            LocalInfoUpdate::Topic(Some(topic.to_owned())),
+        )
+        .await
     }
 
     /// Sets a new topic for this room.
+    #[deprecated = "Use set_topic instead"]
     pub async fn set_room_topic(&self, topic: &str) -> Result<send_state_event::v3::Response> {
-        self.send_state_event(RoomTopicEventContent::new(topic.into())).await
+        self.set_topic(topic).await
     }
 
     /// Sets the new avatar url for this room.
     ///
+    /// The avatar of the room is updated locally right away, and restored if
+    /// sending the state event fails.
+    ///
     /// # Arguments
     /// * `avatar_url` - The owned matrix uri that represents the avatar
     /// * `info` - The optional image info that can be provided for the avatar
@@ -1776,16 +2749,30 @@ impl Room {
         room_avatar_event.url = Some(url.to_owned());
         room_avatar_event.info = info.map(Box::new);
 
-        self.send_state_event(room_avatar_event).await
+        let update = LocalInfoUpdate::Avatar {
+            url: room_avatar_event.url.clone(),
+            info: room_avatar_event.info.clone(),
+        };
+        self.send_state_event_with_local_echo(room_avatar_event, update).await
     }
 
     /// Removes the avatar from the room
+    ///
+    /// The avatar of the room is removed locally right away, and restored if
+    /// sending the state event fails.
     pub async fn remove_avatar(&self) -> Result<send_state_event::v3::Response> {
-        self.send_state_event(RoomAvatarEventContent::new()).await
+        self.send_state_event_with_local_echo(
+            RoomAvatarEventContent::new(),
+            LocalInfoUpdate::Avatar { url: None, info: None },
+        )
+        .await
     }
 
     /// Uploads a new avatar for this room.
     ///
+    /// The avatar of the room is updated locally as soon as it is uploaded,
+    /// see [`Room::set_avatar_url()`].
+    ///
     /// # Arguments
     /// * `mime` - The mime type describing the data
     /// * `data` - The data representation of the avatar
@@ -1807,6 +2794,52 @@ impl Room {
         self.set_avatar_url(&upload_response.content_uri, Some(info)).await
     }
 
+    /// Send a state event with an empty state key and apply the given update
+    /// to the local info of the room right away, so it is visible before the
+    /// event comes back via sync.
+    ///
+    /// If sending the event fails, the previous state is restored, unless the
+    /// info was changed again in the meantime.
+    async fn send_state_event_with_local_echo(
+        &self,
+        content: impl StateEventContent<StateKey = EmptyStateKey>,
+        update: LocalInfoUpdate,
+    ) -> Result<send_state_event::v3::Response> {
+        let previous = {
+            let _sync_lock = self.client.base_client().sync_lock().read().await;
+
+            let previous = self.clone_info();
+            let mut room_info = previous.clone();
+            match &update {
+                LocalInfoUpdate::Name(Some(name)) => room_info.update_name(name.clone()),
+                LocalInfoUpdate::Name(None) => room_info.remove_name(),
+                LocalInfoUpdate::Topic(topic) => room_info.update_topic(topic.clone()),
+                LocalInfoUpdate::Avatar { url, info } => {
+                    room_info.update_avatar(url.clone(), info.clone());
+                }
+            }
+            self.update_summary(room_info);
+
+            previous
+        };
+
+        let result = self.send_state_event(content).await;
+
+        if result.is_err() {
+            let _sync_lock = self.client.base_client().sync_lock().read().await;
+
+            if update.is_current(self) {
+                debug!("Sending the state event failed, rolling back the local update");
+
+                let mut room_info = self.clone_info();
+                room_info.restore_state(&update.event_type(), &previous);
+                self.update_summary(room_info);
+            }
+        }
+
+        result
+    }
+
     /// Send a state event with an empty state key to the homeserver.
     ///
     /// For state events with a non-empty state key, see
@@ -1915,6 +2948,43 @@ impl Room {
         Ok(response)
     }
 
+    /// Start sharing the live location of the own user in this room.
+    ///
+    /// This sends an `m.beacon_info` state event. The location must then be
+    /// shared regularly with [`LiveLocationShare::update_location()`], until
+    /// the share times out or is stopped with [`LiveLocationShare::stop()`].
+    ///
+    /// Starting a new share replaces the previous one of the own user in this
+    /// room, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - How long the location will be shared.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # async {
+    /// # let room: matrix_sdk::Room = todo!();
+    /// let share =
+    ///     room.start_live_location_share(Duration::from_secs(15 * 60)).await?;
+    ///
+    /// share.update_location("geo:51.5008,0.1247;u=35".to_owned()).await?;
+    ///
+    /// // Later, if the user doesn't want to share their location anymore.
+    /// share.stop().await?;
+    /// # anyhow::Ok(())
+    /// # };
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn start_live_location_share(&self, duration: Duration) -> Result<LiveLocationShare> {
+        let content = BeaconInfoEventContent::new(None, duration, true, None);
+        let response = self.send_state_event_for_key(self.own_user_id(), content.clone()).await?;
+
+        Ok(LiveLocationShare::new(self.clone(), content, response.event_id))
+    }
+
     /// Send a raw room state event to the homeserver.
     ///
     /// Returns the parsed response from the server.
@@ -2018,6 +3088,279 @@ impl Room {
         self.client.send(request, None).await
     }
 
+    /// Redact the message-like events sent by the given user in this room
+    /// since the given time.
+    ///
+    /// The history of the room is paginated backwards from its most recent
+    /// event, until the events are older than `since`. The redactions of the
+    /// events of every page are sent concurrently, but only a few at a time,
+    /// and if the homeserver rate-limits them, they are retried after the
+    /// delay it asked for.
+    ///
+    /// State events are not redacted because it would change the state of the
+    /// room, like the membership of the user. Events that were already
+    /// redacted are skipped.
+    ///
+    /// If the redaction of an event fails, the other events are still
+    /// redacted, and the failure is collected in the returned
+    /// [`RedactedEvents`]. If the history of the room can't be paginated
+    /// further, the pagination error is returned with the events that were
+    /// redacted until then.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user whose events should be redacted.
+    ///
+    /// * `since` - The time of the oldest event to redact.
+    ///
+    /// * `reason` - The reason for the redactions.
+    #[instrument(skip(self, reason), fields(room_id = ?self.room_id()))]
+    pub async fn redact_events_from(
+        &self,
+        user_id: &UserId,
+        since: MilliSecondsSinceUnixEpoch,
+        reason: Option<&str>,
+    ) -> RedactedEvents {
+        const MAX_CONCURRENT_REQUESTS: usize = 5;
+
+        let filter =
+            assign!(RoomEventFilter::default(), { senders: Some(vec![user_id.to_owned()]) });
+
+        let mut outcome = RedactedEvents::default();
+        let mut from = None;
+
+        loop {
+            let options = assign!(MessagesOptions::backward().from(from.as_deref()), {
+                limit: uint!(100),
+                filter: filter.clone(),
+            });
+            let messages = match self.messages(options).await {
+                Ok(messages) => messages,
+                Err(error) => {
+                    warn!("Failed to paginate the history of the room: {error}");
+                    outcome.pagination_error = Some(error);
+                    return outcome;
+                }
+            };
+
+            let mut to_redact = Vec::new();
+            let mut reached_since = false;
+
+            for event in messages.chunk {
+                let event = match event.event.deserialize() {
+                    Ok(event) => event,
+                    Err(error) => {
+                        warn!("Skipping event that failed to deserialize: {error}");
+                        continue;
+                    }
+                };
+
+                if event.origin_server_ts() < since {
+                    reached_since = true;
+                    break;
+                }
+
+                // Don't rely on the homeserver applying the filter.
+                if event.sender() != user_id {
+                    continue;
+                }
+
+                let AnyTimelineEvent::MessageLike(event) = event else {
+                    continue;
+                };
+                if event.original_content().is_none()
+                    || matches!(event, AnyMessageLikeEvent::RoomRedaction(_))
+                {
+                    continue;
+                }
+
+                to_redact.push(event.event_id().to_owned());
+            }
+
+            let mut redactions = stream::iter(to_redact)
+                .map(|event_id| async move {
+                    debug!(?event_id, "Redacting event");
+                    let request = assign!(
+                        redact_event::v3::Request::new(
+                            self.room_id().to_owned(),
+                            event_id.clone(),
+                            TransactionId::new(),
+                        ),
+                        { reason: reason.map(ToOwned::to_owned) }
+                    );
+                    let result = self.client.send(request, None).await;
+
+                    (event_id, result)
+                })
+                .buffered(MAX_CONCURRENT_REQUESTS);
+
+            while let Some((event_id, result)) = redactions.next().await {
+                match result {
+                    Ok(_) => outcome.redacted.push(event_id),
+                    Err(error) => {
+                        warn!(?event_id, "Failed to redact event: {error}");
+                        outcome.failed.push((event_id, error.into()));
+                    }
+                }
+            }
+
+            match messages.end {
+                Some(end) if !reached_since => from = Some(end),
+                _ => return outcome,
+            }
+        }
+    }
+
+    /// Ban the user from this room, and redact all the message-like events
+    /// they sent in it.
+    ///
+    /// This is a shorthand for [`Room::ban_user()`] followed by
+    /// [`Room::redact_events_from()`] since the beginning of the history of
+    /// the room, see those methods for details.
+    ///
+    /// Returns an error if the user couldn't be banned, and the outcome of the
+    /// redactions otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user to ban.
+    ///
+    /// * `reason` - The reason for the ban and the redactions.
+    #[instrument(skip(self, reason), fields(room_id = ?self.room_id()))]
+    pub async fn ban_and_purge(
+        &self,
+        user_id: &UserId,
+        reason: Option<&str>,
+    ) -> Result<RedactedEvents> {
+        self.ban_user(user_id, reason).await?;
+        Ok(self.redact_events_from(user_id, MilliSecondsSinceUnixEpoch(uint!(0)), reason).await)
+    }
+
+    /// Get the moderation policy rules of this room, if it is a policy list.
+    ///
+    /// To be notified of the changes of the rules of policy lists, use
+    /// [`Client::subscribe_to_policy_lists()`].
+    pub async fn policy_rules(&self) -> Result<Vec<PolicyRule>> {
+        let mut rules = Vec::new();
+
+        for kind in [PolicyRuleKind::User, PolicyRuleKind::Room, PolicyRuleKind::Server] {
+            for event_type in kind.event_types() {
+                for event in self.get_state_events(event_type).await? {
+                    if let RawAnySyncOrStrippedState::Sync(event) = event {
+                        rules.extend(PolicyRule::from_raw_event(self.room_id(), &event));
+                    }
+                }
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// Add a rule to this moderation policy list.
+    ///
+    /// The rule replaces any rule of the same kind for the same entity in this
+    /// room.
+    ///
+    /// Returns the ID of the state event of the rule.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of entity the rule applies to.
+    ///
+    /// * `entity` - The glob of the entities the rule applies to, where `*`
+    ///   matches any number of characters, and `?` exactly one character.
+    ///
+    /// * `recommendation` - The action recommended against the entities.
+    ///
+    /// * `reason` - The reason for the rule.
+    #[instrument(skip(self, reason), fields(room_id = ?self.room_id()))]
+    pub async fn add_policy_rule(
+        &self,
+        kind: PolicyRuleKind,
+        entity: &str,
+        recommendation: Recommendation,
+        reason: &str,
+    ) -> Result<OwnedEventId> {
+        let state_key = format!("rule:{entity}");
+        let content =
+            PolicyRuleEventContent::new(entity.to_owned(), recommendation, reason.to_owned());
+
+        let response = match kind {
+            PolicyRuleKind::User => {
+                self.send_state_event_for_key(&state_key, PolicyRuleUserEventContent(content))
+                    .await?
+            }
+            PolicyRuleKind::Room => {
+                self.send_state_event_for_key(&state_key, PolicyRuleRoomEventContent(content))
+                    .await?
+            }
+            PolicyRuleKind::Server => {
+                self.send_state_event_for_key(&state_key, PolicyRuleServerEventContent(content))
+                    .await?
+            }
+        };
+
+        Ok(response.event_id)
+    }
+
+    /// Remove a rule from this moderation policy list.
+    ///
+    /// This replaces the state event of the rule with an empty one.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of entity the rule applies to.
+    ///
+    /// * `state_key` - The state key of the event of the rule, as found in
+    ///   [`PolicyRule::state_key`].
+    #[instrument(skip(self), fields(room_id = ?self.room_id()))]
+    pub async fn remove_policy_rule(&self, kind: PolicyRuleKind, state_key: &str) -> Result<()> {
+        self.send_state_event_raw(serde_json::json!({}), &kind.event_type().to_string(), state_key)
+            .await?;
+        Ok(())
+    }
+
+    /// Report an event from this room to the homeserver administrators.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event to report.
+    ///
+    /// * `score` - The score to rate this content as, where -100 is most
+    ///   offensive and 0 is inoffensive.
+    ///
+    /// * `reason` - The reason for the event being reported.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::ruma::{event_id, int};
+    ///
+    /// # async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let mut client = matrix_sdk::Client::new(homeserver).await?;
+    /// # let room_id = matrix_sdk::ruma::room_id!("!test:localhost");
+    /// #
+    /// if let Some(room) = client.get_room(&room_id) {
+    ///     let event_id = event_id!("$xxxxxx:example.org");
+    ///     let reason = Some("Spam".to_owned());
+    ///     room.report_content(event_id.to_owned(), Some(int!(-100)), reason)
+    ///         .await?;
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip_all)]
+    pub async fn report_content(
+        &self,
+        event_id: OwnedEventId,
+        score: Option<Int>,
+        reason: Option<String>,
+    ) -> HttpResult<report_content::v3::Response> {
+        let request =
+            report_content::v3::Request::new(self.room_id().to_owned(), event_id, score, reason);
+        self.client.send(request, None).await
+    }
+
     /// Returns true if the user with the given user_id is able to redact
     /// messages in the room.
     ///
@@ -2082,6 +3425,63 @@ impl Room {
         Ok(self.get_room_power_levels().await?.user_can_trigger_room_notification(user_id))
     }
 
+    /// Get the [retention policy] of this room, if any.
+    ///
+    /// [retention policy]: https://github.com/matrix-org/matrix-spec-proposals/pull/1763
+    pub async fn retention_policy(&self) -> Result<Option<RetentionPolicy>> {
+        let Some(raw) =
+            self.get_state_event(StateEventType::from(retention::RETENTION_EVENT_TYPE), "").await?
+        else {
+            return Ok(None);
+        };
+
+        let content = match raw {
+            RawAnySyncOrStrippedState::Sync(raw) => {
+                raw.get_field::<retention::RetentionEventContent>("content")
+            }
+            RawAnySyncOrStrippedState::Stripped(raw) => raw.get_field("content"),
+        };
+
+        match content {
+            Ok(content) => Ok(content.map(Into::into)),
+            Err(error) => {
+                warn!("Failed to deserialize the retention policy: {error}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Get how long the events of this room should be kept locally at most, if
+    /// there is a limit.
+    ///
+    /// This is the smallest of the maximum lifetime in the
+    /// [retention policy](Self::retention_policy) of this room and
+    /// [`Client::max_local_history_age()`].
+    pub async fn max_history_age(&self) -> Result<Option<Duration>> {
+        let max_lifetime = self.retention_policy().await?.and_then(|policy| policy.max_lifetime);
+        let max_local_age = self.client.max_local_history_age();
+
+        Ok(match (max_lifetime, max_local_age) {
+            (Some(max_lifetime), Some(max_local_age)) => Some(max_lifetime.min(max_local_age)),
+            (max_lifetime, max_local_age) => max_lifetime.or(max_local_age),
+        })
+    }
+
+    /// Get the timestamp before which the events of this room should not be
+    /// kept, if there is a [maximum history age](Self::max_history_age).
+    ///
+    /// The cutoff is updated after every sync, and the events that are older
+    /// are removed from the event cache.
+    pub async fn history_cutoff(&self) -> Result<Option<MilliSecondsSinceUnixEpoch>> {
+        self.client.inner.history_cutoffs.update(self, false).await
+    }
+
+    /// Subscribe to the changes of the [history cutoff](Self::history_cutoff)
+    /// of this room.
+    pub fn subscribe_to_history_cutoff(&self) -> Subscriber<Option<MilliSecondsSinceUnixEpoch>> {
+        self.client.inner.history_cutoffs.subscribe(self.room_id())
+    }
+
     /// Get a list of servers that should know this room.
     ///
     /// Uses the synced members of the room and the suggested [routing
@@ -2141,84 +3541,129 @@ impl Room {
             .collect())
     }
 
-    /// Get a `matrix.to` permalink to this room.
+    /// Get the target of a permalink to this room.
     ///
     /// If this room has an alias, we use it. Otherwise, we try to use the
     /// synced members in the room for [routing] the room ID.
     ///
     /// [routing]: https://spec.matrix.org/v1.3/appendices/#routing
-    pub async fn matrix_to_permalink(&self) -> Result<MatrixToUri> {
+    pub async fn permalink_target(&self) -> Result<PermalinkTarget> {
         if let Some(alias) = self.canonical_alias().or_else(|| self.alt_aliases().pop()) {
-            return Ok(alias.matrix_to_uri());
+            return Ok(PermalinkTarget::RoomAlias(alias));
         }
 
         let via = self.route().await?;
-        Ok(self.room_id().matrix_to_uri_via(via))
+        Ok(PermalinkTarget::Room { room_id: self.room_id().to_owned(), via })
     }
 
-    /// Get a `matrix:` permalink to this room.
+    /// Get the target of a permalink to an event in this room.
     ///
-    /// If this room has an alias, we use it. Otherwise, we try to use the
-    /// synced members in the room for [routing] the room ID.
+    /// We try to use the synced members in the room for [routing] the room ID.
+    ///
+    /// *Note*: This method does not check if the given event ID is actually
+    /// part of this room. It needs to be checked before calling this method
+    /// otherwise the permalink won't work.
     ///
     /// # Arguments
     ///
-    /// * `join` - Whether the user should join the room.
+    /// * `event_id` - The ID of the event.
     ///
     /// [routing]: https://spec.matrix.org/v1.3/appendices/#routing
-    pub async fn matrix_permalink(&self, join: bool) -> Result<MatrixUri> {
-        if let Some(alias) = self.canonical_alias().or_else(|| self.alt_aliases().pop()) {
-            return Ok(alias.matrix_uri(join));
-        }
-
+    pub async fn event_permalink_target(
+        &self,
+        event_id: impl Into<OwnedEventId>,
+    ) -> Result<PermalinkTarget> {
+        // Don't use the alias because an event is tied to a room ID, but an
+        // alias might point to another room, e.g. after a room upgrade.
         let via = self.route().await?;
-        Ok(self.room_id().matrix_uri_via(via, join))
+        Ok(PermalinkTarget::Event {
+            room: self.room_id().to_owned().into(),
+            event_id: event_id.into(),
+            via,
+        })
     }
 
-    /// Get a `matrix.to` permalink to an event in this room.
+    /// Get a `matrix.to` permalink to this room.
     ///
-    /// We try to use the synced members in the room for [routing] the room ID.
+    /// See [`Room::permalink_target()`] for how the permalink is built.
+    pub async fn matrix_to_permalink(&self) -> Result<MatrixToUri> {
+        Ok(self.permalink_target().await?.matrix_to_uri())
+    }
+
+    /// Get a `matrix:` permalink to this room.
     ///
-    /// *Note*: This method does not check if the given event ID is actually
-    /// part of this room. It needs to be checked before calling this method
-    /// otherwise the permalink won't work.
+    /// See [`Room::permalink_target()`] for how the permalink is built.
     ///
     /// # Arguments
     ///
-    /// * `event_id` - The ID of the event.
+    /// * `join` - Whether the user should join the room.
+    pub async fn matrix_permalink(&self, join: bool) -> Result<MatrixUri> {
+        Ok(self.permalink_target().await?.matrix_uri(join))
+    }
+
+    /// Get a `matrix.to` permalink to an event in this room.
     ///
-    /// [routing]: https://spec.matrix.org/v1.3/appendices/#routing
+    /// See [`Room::event_permalink_target()`] for how the permalink is built.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_id` - The ID of the event.
     pub async fn matrix_to_event_permalink(
         &self,
         event_id: impl Into<OwnedEventId>,
     ) -> Result<MatrixToUri> {
-        // Don't use the alias because an event is tied to a room ID, but an
-        // alias might point to another room, e.g. after a room upgrade.
-        let via = self.route().await?;
-        Ok(self.room_id().matrix_to_event_uri_via(event_id, via))
+        Ok(self.event_permalink_target(event_id).await?.matrix_to_uri())
     }
 
     /// Get a `matrix:` permalink to an event in this room.
     ///
-    /// We try to use the synced members in the room for [routing] the room ID.
-    ///
-    /// *Note*: This method does not check if the given event ID is actually
-    /// part of this room. It needs to be checked before calling this method
-    /// otherwise the permalink won't work.
+    /// See [`Room::event_permalink_target()`] for how the permalink is built.
     ///
     /// # Arguments
     ///
     /// * `event_id` - The ID of the event.
-    ///
-    /// [routing]: https://spec.matrix.org/v1.3/appendices/#routing
     pub async fn matrix_event_permalink(
         &self,
         event_id: impl Into<OwnedEventId>,
     ) -> Result<MatrixUri> {
-        // Don't use the alias because an event is tied to a room ID, but an
-        // alias might point to another room, e.g. after a room upgrade.
-        let via = self.route().await?;
-        Ok(self.room_id().matrix_event_uri_via(event_id, via))
+        Ok(self.event_permalink_target(event_id).await?.matrix_uri(false))
+    }
+
+    /// Store the given [`ComposerDraft`] in the state store, so it can be
+    /// restored later with [`Room::load_composer_draft()`], e.g. after a
+    /// restart.
+    ///
+    /// This replaces the previous draft of this room, if any.
+    pub async fn save_composer_draft(&self, draft: ComposerDraft) -> Result<()> {
+        self.client
+            .store()
+            .set_kv_data(
+                StateStoreDataKey::ComposerDraft(self.room_id()),
+                StateStoreDataValue::ComposerDraft(draft),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Retrieve the [`ComposerDraft`] stored in the state store for this room,
+    /// if any.
+    pub async fn load_composer_draft(&self) -> Result<Option<ComposerDraft>> {
+        let data = self
+            .client
+            .store()
+            .get_kv_data(StateStoreDataKey::ComposerDraft(self.room_id()))
+            .await?;
+        Ok(data.map(|v| v.into_composer_draft().expect("Session data is not a composer draft")))
+    }
+
+    /// Remove the [`ComposerDraft`] stored in the state store for this room,
+    /// e.g. after the message was sent.
+    pub async fn clear_composer_draft(&self) -> Result<()> {
+        self.client
+            .store()
+            .remove_kv_data(StateStoreDataKey::ComposerDraft(self.room_id()))
+            .await?;
+        Ok(())
     }
 
     /// Get the latest receipt of a user in this room.
@@ -2370,6 +3815,21 @@ pub struct Invite {
     pub inviter: Option<RoomMember>,
 }
 
+/// The outcome of [`Room::redact_events_from()`].
+#[derive(Debug, Default)]
+pub struct RedactedEvents {
+    /// The IDs of the events that were redacted.
+    pub redacted: Vec<OwnedEventId>,
+    /// The IDs of the events that couldn't be redacted, with the error of
+    /// their redaction.
+    pub failed: Vec<(OwnedEventId, Error)>,
+    /// The error that stopped the pagination of the history of the room, if
+    /// any.
+    ///
+    /// When it is set, older events of the user might not have been redacted.
+    pub pagination_error: Option<Error>,
+}
+
 #[derive(Error, Debug)]
 enum InvitationError {
     #[error("No membership event found")]
@@ -2433,6 +3893,35 @@ impl Receipts {
     }
 }
 
+/// An update of the local info of a room, applied before the corresponding
+/// state event is received.
+#[derive(Debug)]
+enum LocalInfoUpdate {
+    Name(Option<String>),
+    Topic(Option<String>),
+    Avatar { url: Option<OwnedMxcUri>, info: Option<Box<avatar::ImageInfo>> },
+}
+
+impl LocalInfoUpdate {
+    /// The type of the state event that is updated.
+    fn event_type(&self) -> StateEventType {
+        match self {
+            Self::Name(_) => StateEventType::RoomName,
+            Self::Topic(_) => StateEventType::RoomTopic,
+            Self::Avatar { .. } => StateEventType::RoomAvatar,
+        }
+    }
+
+    /// Whether the given room still has the value of this update.
+    fn is_current(&self, room: &Room) -> bool {
+        match self {
+            Self::Name(name) => room.name() == *name,
+            Self::Topic(topic) => room.topic() == *topic,
+            Self::Avatar { url, .. } => room.avatar_url() == *url,
+        }
+    }
+}
+
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use matrix_sdk_base::SessionMeta;
@@ -0,0 +1,337 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Moderation policy lists, as defined in the [spec].
+//!
+//! A policy list is a room whose `m.policy.rule.user`, `m.policy.rule.room`
+//! and `m.policy.rule.server` state events are rules recommending an action,
+//! usually a ban, against the users, rooms or servers matching a glob. A rule
+//! is removed by replacing its state event with an empty one.
+//!
+//! The rules with the legacy `m.room.rule.*` and `org.matrix.mjolnir.rule.*`
+//! event types are supported too.
+//!
+//! [spec]: https://spec.matrix.org/v1.10/client-server-api/#moderation-policy-lists
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock as StdRwLock},
+};
+
+use matrix_sdk_base::deserialized_responses::RawAnySyncOrStrippedState;
+use ruma::{
+    events::{
+        policy::rule::{PolicyRuleEventContent, Recommendation},
+        AnySyncStateEvent, StateEventType,
+    },
+    serde::Raw,
+    OwnedRoomId, RoomId, ServerName, UserId,
+};
+use tracing::debug;
+
+use crate::{event_handler::EventHandlerDropGuard, Client, Result};
+
+/// The kind of entity a policy rule applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PolicyRuleKind {
+    /// The rule applies to users, with an `m.policy.rule.user` event.
+    User,
+    /// The rule applies to rooms, with an `m.policy.rule.room` event.
+    Room,
+    /// The rule applies to servers, with an `m.policy.rule.server` event.
+    Server,
+}
+
+impl PolicyRuleKind {
+    /// The type of the state events of the rules of this kind.
+    pub fn event_type(self) -> StateEventType {
+        match self {
+            Self::User => StateEventType::PolicyRuleUser,
+            Self::Room => StateEventType::PolicyRuleRoom,
+            Self::Server => StateEventType::PolicyRuleServer,
+        }
+    }
+
+    /// The types of the state events of the rules of this kind, including the
+    /// legacy ones.
+    pub(crate) fn event_types(self) -> Vec<StateEventType> {
+        let legacy_types = match self {
+            Self::User => ["m.room.rule.user", "org.matrix.mjolnir.rule.user"],
+            Self::Room => ["m.room.rule.room", "org.matrix.mjolnir.rule.room"],
+            Self::Server => ["m.room.rule.server", "org.matrix.mjolnir.rule.server"],
+        };
+
+        let mut event_types = vec![self.event_type()];
+        for event_type in legacy_types.map(StateEventType::from) {
+            if !event_types.contains(&event_type) {
+                event_types.push(event_type);
+            }
+        }
+        event_types
+    }
+
+    fn from_event_type(event_type: &StateEventType) -> Option<Self> {
+        match event_type.to_string().as_str() {
+            "m.policy.rule.user" | "m.room.rule.user" | "org.matrix.mjolnir.rule.user" => {
+                Some(Self::User)
+            }
+            "m.policy.rule.room" | "m.room.rule.room" | "org.matrix.mjolnir.rule.room" => {
+                Some(Self::Room)
+            }
+            "m.policy.rule.server" | "m.room.rule.server" | "org.matrix.mjolnir.rule.server" => {
+                Some(Self::Server)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A rule of a moderation policy list.
+#[derive(Clone, Debug)]
+pub struct PolicyRule {
+    /// The ID of the policy list the rule is in.
+    pub room_id: OwnedRoomId,
+
+    /// The kind of entity the rule applies to.
+    pub kind: PolicyRuleKind,
+
+    /// The state key of the event of the rule, which identifies it in the
+    /// policy list.
+    pub state_key: String,
+
+    /// The glob of the entities the rule applies to.
+    ///
+    /// `*` matches any number of characters, and `?` matches exactly one
+    /// character.
+    pub entity: String,
+
+    /// The action recommended against the entities the rule applies to.
+    pub recommendation: Recommendation,
+
+    /// The reason for the rule.
+    pub reason: String,
+}
+
+impl PolicyRule {
+    /// Parse the rule defined by the given state event.
+    ///
+    /// Returns `None` if the event isn't a policy rule event, or if it removes
+    /// the rule.
+    pub(crate) fn from_raw_event(room_id: &RoomId, event: &Raw<AnySyncStateEvent>) -> Option<Self> {
+        let event_type = event.get_field::<StateEventType>("type").ok()??;
+        let kind = PolicyRuleKind::from_event_type(&event_type)?;
+        let state_key = event.get_field::<String>("state_key").ok()??;
+
+        // Removed rules have an empty content.
+        let content = event.get_field::<PolicyRuleEventContent>("content").ok()??;
+
+        Some(Self {
+            room_id: room_id.to_owned(),
+            kind,
+            state_key,
+            entity: content.entity,
+            recommendation: content.recommendation,
+            reason: content.reason,
+        })
+    }
+
+    /// Whether the given entity matches the glob of this rule.
+    pub fn matches(&self, entity: &str) -> bool {
+        glob_matches(&self.entity, entity)
+    }
+}
+
+/// Whether the given text matches the given glob, with `*` and `?` wildcards.
+///
+/// This runs in `O(glob.len() * text.len())`, since globs come from the state
+/// of rooms that might not be trusted.
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut g, mut t) = (0, 0);
+    // The position in the glob after the last `*`, and the position in the
+    // text from which it was tried to match the rest of the glob.
+    let mut last_star = None;
+
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                last_star = Some((g + 1, t));
+                g += 1;
+            }
+            Some('?') => {
+                g += 1;
+                t += 1;
+            }
+            Some(&c) if c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            // Let the last `*` match one more character, and try again.
+            _ => match &mut last_star {
+                Some((star_g, star_t)) => {
+                    *star_t += 1;
+                    g = *star_g;
+                    t = *star_t;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// The key of a rule: a policy list can only have one rule of each kind with
+/// the same state key.
+type RuleKey = (OwnedRoomId, PolicyRuleKind, String);
+
+/// The rules of a set of moderation policy lists, kept up to date with the
+/// state events received in the sync.
+///
+/// To get this, use [`Client::subscribe_to_policy_lists()`].
+#[derive(Debug)]
+pub struct PolicyListSubscription {
+    rules: Arc<StdRwLock<BTreeMap<RuleKey, PolicyRule>>>,
+    _event_handler_guards: Vec<EventHandlerDropGuard>,
+}
+
+impl PolicyListSubscription {
+    pub(crate) async fn new(client: &Client, room_ids: &[&RoomId]) -> Result<Self> {
+        let rules = Arc::new(StdRwLock::new(BTreeMap::new()));
+        let mut event_handler_guards = Vec::with_capacity(room_ids.len());
+
+        for &room_id in room_ids {
+            for kind in [PolicyRuleKind::User, PolicyRuleKind::Room, PolicyRuleKind::Server] {
+                for event_type in kind.event_types() {
+                    let events = client.store().get_state_events(room_id, event_type).await?;
+
+                    for event in events {
+                        if let RawAnySyncOrStrippedState::Sync(event) = event {
+                            update_rules(&rules, room_id, &event);
+                        }
+                    }
+                }
+            }
+
+            let handle = client.add_room_event_handler(room_id, {
+                let rules = rules.clone();
+                let room_id = room_id.to_owned();
+
+                move |event: Raw<AnySyncStateEvent>| {
+                    update_rules(&rules, &room_id, &event);
+                    async {}
+                }
+            });
+            event_handler_guards.push(client.event_handler_drop_guard(handle));
+        }
+
+        Ok(Self { rules, _event_handler_guards: event_handler_guards })
+    }
+
+    /// The current rules of the policy lists.
+    pub fn rules(&self) -> Vec<PolicyRule> {
+        self.rules.read().unwrap().values().cloned().collect()
+    }
+
+    /// Get the first rule that matches the given user, if any.
+    ///
+    /// The rules on the server of the user also apply to them.
+    pub fn match_user(&self, user_id: &UserId) -> Option<PolicyRule> {
+        self.find_rule(PolicyRuleKind::User, user_id.as_str())
+            .or_else(|| self.match_server(user_id.server_name()))
+    }
+
+    /// Get the first rule that matches the given room, if any.
+    pub fn match_room(&self, room_id: &RoomId) -> Option<PolicyRule> {
+        self.find_rule(PolicyRuleKind::Room, room_id.as_str())
+    }
+
+    /// Get the first rule that matches the given server, if any.
+    pub fn match_server(&self, server_name: &ServerName) -> Option<PolicyRule> {
+        self.find_rule(PolicyRuleKind::Server, server_name.as_str())
+    }
+
+    fn find_rule(&self, kind: PolicyRuleKind, entity: &str) -> Option<PolicyRule> {
+        self.rules
+            .read()
+            .unwrap()
+            .values()
+            .find(|rule| rule.kind == kind && rule.matches(entity))
+            .cloned()
+    }
+}
+
+/// Update the rules with the given state event of the given policy list.
+fn update_rules(
+    rules: &StdRwLock<BTreeMap<RuleKey, PolicyRule>>,
+    room_id: &RoomId,
+    event: &Raw<AnySyncStateEvent>,
+) {
+    let Some(kind) = event
+        .get_field::<StateEventType>("type")
+        .ok()
+        .flatten()
+        .and_then(|event_type| PolicyRuleKind::from_event_type(&event_type))
+    else {
+        return;
+    };
+    let Ok(Some(state_key)) = event.get_field::<String>("state_key") else {
+        return;
+    };
+
+    let key = (room_id.to_owned(), kind, state_key);
+    let mut rules = rules.write().unwrap();
+
+    if let Some(rule) = PolicyRule::from_raw_event(room_id, event) {
+        debug!(?room_id, state_key = ?key.2, "Updating policy rule");
+        rules.insert(key, rule);
+    } else if rules.remove(&key).is_some() {
+        debug!(?room_id, state_key = ?key.2, "Removing policy rule");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_matches;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("@spammer:example.org", "@spammer:example.org"));
+        assert!(!glob_matches("@spammer:example.org", "@spammer:example.com"));
+        assert!(glob_matches("*:example.org", "@spammer:example.org"));
+        assert!(glob_matches("*.example.org", "matrix.example.org"));
+        assert!(!glob_matches("*.example.org", "example.org"));
+        assert!(glob_matches("@spammer?:*", "@spammer1:example.org"));
+        assert!(!glob_matches("@spammer?:*", "@spammer:example.org"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "axxbyybzzc"));
+        assert!(!glob_matches("a*b*c", "axxbyybzz"));
+        assert!(glob_matches("**a", "a"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_character() {
+        assert!(glob_matches("@sp?mmer:example.org", "@spämmer:example.org"));
+        assert!(!glob_matches("@sp??mmer:example.org", "@spämmer:example.org"));
+    }
+
+    #[test]
+    fn glob_with_many_stars_is_fast() {
+        let glob = format!("{}b", "*a".repeat(50));
+        let text = "a".repeat(1000);
+        assert!(!glob_matches(&glob, &text));
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use matrix_sdk_common::deserialized_responses::TimelineEvent;
+use ruma::{
+    api::Direction,
+    events::{relation::RelationType, TimelineEventType},
+    UInt,
+};
+
+/// Options for [`relations`][super::Room::relations].
+///
+/// See that method and
+/// <https://spec.matrix.org/v1.8/client-server-api/#get_matrixclientv1roomsroomidrelationseventid>
+/// for details.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RelationsOptions {
+    /// The token to start returning events from, as returned in the
+    /// `next_batch_token` or `prev_batch_token` of a previous call.
+    ///
+    /// If it isn't provided, the homeserver returns the most recent or the
+    /// oldest relations, depending on `dir`.
+    pub from: Option<String>,
+
+    /// The direction to return events in.
+    ///
+    /// Default: `Backward`.
+    pub dir: Direction,
+
+    /// The maximum number of events to return.
+    ///
+    /// If it isn't provided, the homeserver chooses a default.
+    pub limit: Option<UInt>,
+
+    /// Only return the events with this type of relation.
+    pub rel_type: Option<RelationType>,
+
+    /// Only return the events of this type.
+    ///
+    /// Ignored if `rel_type` isn't set, as the homeserver only allows to
+    /// filter by event type together with the type of relation.
+    pub event_type: Option<TimelineEventType>,
+
+    /// Whether to also return the events that relate indirectly to the event,
+    /// i.e. the relations of its relations.
+    ///
+    /// Default: `false`.
+    pub recurse: bool,
+}
+
+impl RelationsOptions {
+    /// Creates `RelationsOptions` that return all the events that relate
+    /// directly to an event, from the most recent one.
+    pub fn new() -> Self {
+        Self {
+            from: None,
+            dir: Direction::Backward,
+            limit: None,
+            rel_type: None,
+            event_type: None,
+            recurse: false,
+        }
+    }
+
+    /// Creates `RelationsOptions` that only return the events with the given
+    /// type of relation.
+    pub fn with_rel_type(rel_type: RelationType) -> Self {
+        Self { rel_type: Some(rel_type), ..Self::new() }
+    }
+
+    /// Creates a new `RelationsOptions` from `self` with the `from` field set
+    /// to the given value.
+    ///
+    /// Since the field is public, you can also assign to it directly. This
+    /// method merely acts as a shorthand for that, because it is very
+    /// common to set this field.
+    pub fn from<'a>(self, from: impl Into<Option<&'a str>>) -> Self {
+        Self { from: from.into().map(ToOwned::to_owned), ..self }
+    }
+}
+
+impl Default for RelationsOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The result of a [`relations`][super::Room::relations] call.
+///
+/// In short, this is a possibly decrypted version of the response of a
+/// `rooms/{roomId}/relations/{eventId}` api call.
+#[derive(Debug)]
+pub struct Relations {
+    /// The events that relate to the event, in the requested direction.
+    pub chunk: Vec<TimelineEvent>,
+
+    /// A token to get the next batch of events, in the same direction.
+    ///
+    /// If it is `None`, there are no more events to get.
+    pub next_batch_token: Option<String>,
+
+    /// A token to get the previous batch of events, in the opposite
+    /// direction.
+    pub prev_batch_token: Option<String>,
+
+    /// How deep the homeserver recursed, if `recurse` was set.
+    pub recursion_depth: Option<UInt>,
+}
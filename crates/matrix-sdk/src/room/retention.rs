@@ -0,0 +1,126 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Message retention policies, as defined in [MSC1763].
+//!
+//! The retention policy of a room is set with an `m.room.retention` state
+//! event, and tells clients how long they should keep the events of the room.
+//!
+//! [MSC1763]: https://github.com/matrix-org/matrix-spec-proposals/pull/1763
+
+use std::{collections::BTreeMap, sync::Mutex as StdMutex, time::Duration};
+
+use eyeball::{SharedObservable, Subscriber};
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedRoomId, RoomId, UInt};
+use serde::Deserialize;
+
+use crate::{Result, Room};
+
+/// The type of the state event holding the retention policy of a room.
+pub(crate) const RETENTION_EVENT_TYPE: &str = "m.room.retention";
+
+/// The retention policy of a room.
+///
+/// To get this, use [`Room::retention_policy()`].
+///
+/// [`Room::retention_policy()`]: super::Room::retention_policy
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// How long the events of the room should be kept at most, if any.
+    pub max_lifetime: Option<Duration>,
+    /// How long the events of the room should be kept at least, if any.
+    pub min_lifetime: Option<Duration>,
+}
+
+/// The content of an `m.room.retention` state event.
+#[derive(Deserialize)]
+pub(super) struct RetentionEventContent {
+    #[serde(default)]
+    max_lifetime: Option<UInt>,
+    #[serde(default)]
+    min_lifetime: Option<UInt>,
+}
+
+impl From<RetentionEventContent> for RetentionPolicy {
+    fn from(content: RetentionEventContent) -> Self {
+        let to_duration = |ms: UInt| Duration::from_millis(ms.into());
+        Self {
+            max_lifetime: content.max_lifetime.map(to_duration),
+            min_lifetime: content.min_lifetime.map(to_duration),
+        }
+    }
+}
+
+/// The history cutoffs of the rooms of a client.
+///
+/// They are computed again after every sync, so the events that expired in the
+/// meantime can be removed.
+#[derive(Default)]
+pub(crate) struct HistoryCutoffs {
+    rooms: StdMutex<BTreeMap<OwnedRoomId, RoomHistoryCutoff>>,
+}
+
+#[derive(Default)]
+struct RoomHistoryCutoff {
+    /// The maximum age of the events of the room, if it was loaded already.
+    max_history_age: Option<Option<Duration>>,
+    /// The timestamp before which events should not be kept.
+    cutoff: SharedObservable<Option<MilliSecondsSinceUnixEpoch>>,
+}
+
+impl HistoryCutoffs {
+    /// Compute the history cutoff of the given room from the current time, and
+    /// notify the subscribers if it changed.
+    ///
+    /// The maximum history age of the room is only loaded from the store the
+    /// first time, or if `policy_changed` is `true`.
+    pub(crate) async fn update(
+        &self,
+        room: &Room,
+        policy_changed: bool,
+    ) -> Result<Option<MilliSecondsSinceUnixEpoch>> {
+        let cached = if policy_changed {
+            None
+        } else {
+            self.rooms.lock().unwrap().get(room.room_id()).and_then(|room| room.max_history_age)
+        };
+
+        let max_history_age = match cached {
+            Some(max_history_age) => max_history_age,
+            None => room.max_history_age().await?,
+        };
+        let cutoff = max_history_age.map(cutoff_for_age);
+
+        let mut rooms = self.rooms.lock().unwrap();
+        let entry = rooms.entry(room.room_id().to_owned()).or_default();
+        entry.max_history_age = Some(max_history_age);
+        entry.cutoff.set_if_not_eq(cutoff);
+
+        Ok(cutoff)
+    }
+
+    /// Subscribe to the changes of the history cutoff of the given room.
+    pub(crate) fn subscribe(
+        &self,
+        room_id: &RoomId,
+    ) -> Subscriber<Option<MilliSecondsSinceUnixEpoch>> {
+        self.rooms.lock().unwrap().entry(room_id.to_owned()).or_default().cutoff.subscribe()
+    }
+}
+
+/// The timestamp before which events are older than the given age.
+fn cutoff_for_age(age: Duration) -> MilliSecondsSinceUnixEpoch {
+    let age = UInt::new_saturating(age.as_millis().try_into().unwrap_or(u64::MAX));
+    MilliSecondsSinceUnixEpoch(MilliSecondsSinceUnixEpoch::now().0.saturating_sub(age))
+}
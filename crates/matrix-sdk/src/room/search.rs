@@ -0,0 +1,792 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Search of the messages of a room.
+//!
+//! The messages of encrypted rooms can't be searched by the homeserver, so
+//! they are indexed locally as they are received via sync and decrypted, if
+//! [`ClientBuilder::index_encrypted_messages()`] was used. The messages of
+//! unencrypted rooms are searched with the homeserver's `/search` endpoint.
+//!
+//! [`ClientBuilder::index_encrypted_messages()`]: crate::ClientBuilder::index_encrypted_messages
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Range,
+};
+
+use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
+use ruma::{
+    api::client::search::search_events::v3::{Categories, Criteria, Request},
+    assign,
+    events::{
+        room::message::{MessageType, Relation},
+        AnyMessageLikeEvent, AnySyncMessageLikeEvent, AnySyncTimelineEvent, AnyTimelineEvent,
+        MessageLikeEvent, SyncMessageLikeEvent,
+    },
+    MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, RoomId, RoomVersionId,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument, warn};
+
+use super::Room;
+use crate::Result;
+
+/// Options for [`search_messages`][super::Room::search_messages].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SearchOptions {
+    /// The token to continue the search from.
+    ///
+    /// This token can be obtained from the `next_batch` field of the
+    /// [`SearchResults`] of a previous search with the same query.
+    pub from: Option<String>,
+
+    /// The maximum number of results to return.
+    ///
+    /// This is only a hint for searches done by the homeserver.
+    ///
+    /// Default: 10.
+    pub limit: usize,
+}
+
+impl SearchOptions {
+    /// Creates `SearchOptions` with the default parameters.
+    pub fn new() -> Self {
+        Self { from: None, limit: 10 }
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The result of a [`search_messages`][super::Room::search_messages] call.
+#[derive(Clone, Debug)]
+pub struct SearchResults {
+    /// The messages matching the query, from the most recent to the oldest.
+    pub results: Vec<SearchResult>,
+
+    /// The token to get the next batch of results, if there are more.
+    pub next_batch: Option<String>,
+}
+
+/// A message matching a search query.
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    /// The ID of the event of the message.
+    pub event_id: OwnedEventId,
+
+    /// The sender of the message.
+    pub sender: OwnedUserId,
+
+    /// The timestamp of the message.
+    pub origin_server_ts: MilliSecondsSinceUnixEpoch,
+
+    /// The plain text body of the message.
+    pub body: String,
+
+    /// The byte ranges of the parts of `body` that match the query.
+    pub highlights: Vec<Range<usize>>,
+}
+
+/// The maximum number of messages in a chunk of the local search index.
+const CHUNK_SIZE: usize = 250;
+
+/// The maximum number of chunks in the local search index of a room.
+///
+/// When the index grows beyond that, its oldest chunk is dropped.
+const MAX_CHUNKS: usize = 40;
+
+/// The chunks of the local search index of a room.
+///
+/// The index is split in chunks stored under their own key, so adding a
+/// message only rewrites the most recent chunk.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexManifest {
+    /// The IDs of the chunks, from the oldest to the most recent.
+    chunks: VecDeque<u64>,
+    /// The ID of the next chunk to create.
+    next_chunk_id: u64,
+}
+
+/// A message stored in the local search index of a room.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct IndexedMessage {
+    event_id: OwnedEventId,
+    sender: OwnedUserId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    body: String,
+}
+
+/// A change to the local search index found in a timeline event.
+enum IndexUpdate {
+    /// A new message to index.
+    Add(IndexedMessage),
+    /// A new body for the message with the given event ID.
+    Edit(OwnedEventId, String),
+    /// The message with the given event ID was redacted.
+    Redact(OwnedEventId),
+}
+
+impl IndexUpdate {
+    fn from_sync_event(event: &SyncTimelineEvent, room_version: &RoomVersionId) -> Option<Self> {
+        let deserialized = event.event.deserialize().ok()?;
+        let AnySyncTimelineEvent::MessageLike(message_like) = deserialized else {
+            return None;
+        };
+
+        let ev = match message_like {
+            AnySyncMessageLikeEvent::RoomRedaction(ev) => {
+                return ev.redacts(room_version).map(|redacts| Self::Redact(redacts.to_owned()));
+            }
+            AnySyncMessageLikeEvent::RoomMessage(SyncMessageLikeEvent::Original(ev)) => ev,
+            _ => return None,
+        };
+
+        // Only index the messages that the homeserver can't search.
+        if event.encryption_info.is_none() {
+            return None;
+        }
+
+        if let Some(Relation::Replacement(replacement)) = ev.content.relates_to {
+            let body = searchable_body(&replacement.new_content.msgtype)?;
+            return Some(Self::Edit(replacement.event_id, body));
+        }
+
+        Some(Self::Add(IndexedMessage {
+            event_id: ev.event_id,
+            sender: ev.sender,
+            origin_server_ts: ev.origin_server_ts,
+            body: searchable_body(&ev.content.msgtype)?,
+        }))
+    }
+}
+
+/// Get the body of a message, if it's a type of message that can be searched.
+fn searchable_body(msgtype: &MessageType) -> Option<String> {
+    match msgtype {
+        MessageType::Text(_)
+        | MessageType::Notice(_)
+        | MessageType::Emote(_)
+        | MessageType::File(_)
+        | MessageType::Image(_)
+        | MessageType::Video(_)
+        | MessageType::Audio(_) => Some(msgtype.body().to_owned()),
+        _ => None,
+    }
+}
+
+fn manifest_key(room_id: &RoomId) -> Vec<u8> {
+    format!("search_index:{room_id}").into_bytes()
+}
+
+fn chunk_key(room_id: &RoomId, chunk_id: u64) -> Vec<u8> {
+    format!("search_index:{room_id}:{chunk_id}").into_bytes()
+}
+
+async fn load_manifest(room: &Room) -> Result<IndexManifest> {
+    let Some(bytes) = room.client.store().get_custom_value(&manifest_key(room.room_id())).await?
+    else {
+        return Ok(IndexManifest::default());
+    };
+
+    match serde_json::from_slice(&bytes) {
+        Ok(manifest) => Ok(manifest),
+        Err(e) => {
+            warn!("Failed to deserialize the search index, starting over: {e}");
+            Ok(IndexManifest::default())
+        }
+    }
+}
+
+async fn save_manifest(room: &Room, manifest: &IndexManifest) -> Result<()> {
+    let bytes = serde_json::to_vec(manifest)?;
+    room.client.store().set_custom_value(&manifest_key(room.room_id()), bytes).await?;
+    Ok(())
+}
+
+async fn load_chunk(room: &Room, chunk_id: u64) -> Result<Vec<IndexedMessage>> {
+    let key = chunk_key(room.room_id(), chunk_id);
+    let Some(bytes) = room.client.store().get_custom_value(&key).await? else {
+        return Ok(Vec::new());
+    };
+
+    match serde_json::from_slice(&bytes) {
+        Ok(chunk) => Ok(chunk),
+        Err(e) => {
+            warn!(chunk_id, "Failed to deserialize a chunk of the search index, dropping it: {e}");
+            Ok(Vec::new())
+        }
+    }
+}
+
+async fn save_chunk(room: &Room, chunk_id: u64, chunk: &[IndexedMessage]) -> Result<()> {
+    let bytes = serde_json::to_vec(chunk)?;
+    room.client.store().set_custom_value(&chunk_key(room.room_id(), chunk_id), bytes).await?;
+    Ok(())
+}
+
+/// Add the decrypted messages among the given events to the local search index
+/// of the room, and apply the edits and redactions of the indexed messages.
+#[instrument(skip_all, fields(room_id = ?room.room_id()))]
+pub(crate) async fn index_timeline_events(room: &Room, events: &[SyncTimelineEvent]) -> Result<()> {
+    let room_version = room.clone_info().room_version().cloned().unwrap_or(RoomVersionId::V1);
+    let mut new_messages: Vec<IndexedMessage> = Vec::new();
+    // The edits and redactions of messages of previous responses, by event ID.
+    // A `None` body means that the message was redacted.
+    let mut changes: BTreeMap<OwnedEventId, Option<String>> = BTreeMap::new();
+
+    for event in events {
+        match IndexUpdate::from_sync_event(event, &room_version) {
+            Some(IndexUpdate::Add(message)) => new_messages.push(message),
+            Some(IndexUpdate::Edit(event_id, body)) => {
+                if let Some(message) = new_messages.iter_mut().find(|m| m.event_id == event_id) {
+                    message.body = body;
+                } else if let Some(Some(edited_body)) = changes.get_mut(&event_id) {
+                    *edited_body = body;
+                } else {
+                    changes.entry(event_id).or_insert(Some(body));
+                }
+            }
+            Some(IndexUpdate::Redact(event_id)) => {
+                new_messages.retain(|m| m.event_id != event_id);
+                changes.insert(event_id, None);
+            }
+            None => {}
+        }
+    }
+
+    if new_messages.is_empty() && changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut manifest = load_manifest(room).await?;
+
+    // Apply the changes to the indexed messages, from the most recent chunk,
+    // and only rewrite the chunks that changed.
+    for &chunk_id in manifest.chunks.iter().rev() {
+        if changes.is_empty() {
+            break;
+        }
+
+        let mut chunk = load_chunk(room, chunk_id).await?;
+        let previous_len = chunk.len();
+        let mut changed = false;
+
+        chunk.retain_mut(|message| match changes.remove(&message.event_id) {
+            Some(Some(body)) => {
+                message.body = body;
+                changed = true;
+                true
+            }
+            Some(None) => false,
+            None => true,
+        });
+
+        if changed || chunk.len() != previous_len {
+            save_chunk(room, chunk_id, &chunk).await?;
+        }
+    }
+
+    if new_messages.is_empty() {
+        return Ok(());
+    }
+
+    debug!(new_messages = new_messages.len(), "Updating the search index");
+
+    // Fill the most recent chunk, and create new ones as needed.
+    let mut last_chunk = match manifest.chunks.back() {
+        Some(&chunk_id) => (chunk_id, load_chunk(room, chunk_id).await?),
+        None => {
+            let chunk_id = manifest.next_chunk_id;
+            manifest.next_chunk_id += 1;
+            manifest.chunks.push_back(chunk_id);
+            (chunk_id, Vec::new())
+        }
+    };
+
+    for message in new_messages {
+        if last_chunk.1.iter().any(|m| m.event_id == message.event_id) {
+            continue;
+        }
+
+        if last_chunk.1.len() >= CHUNK_SIZE {
+            save_chunk(room, last_chunk.0, &last_chunk.1).await?;
+
+            let chunk_id = manifest.next_chunk_id;
+            manifest.next_chunk_id += 1;
+            manifest.chunks.push_back(chunk_id);
+            last_chunk = (chunk_id, Vec::new());
+        }
+
+        last_chunk.1.push(message);
+    }
+
+    save_chunk(room, last_chunk.0, &last_chunk.1).await?;
+
+    // Drop the oldest chunks if the index is too big.
+    while manifest.chunks.len() > MAX_CHUNKS {
+        if let Some(chunk_id) = manifest.chunks.pop_front() {
+            debug!(chunk_id, "Dropping the oldest chunk of the search index");
+            room.client.store().remove_custom_value(&chunk_key(room.room_id(), chunk_id)).await?;
+        }
+    }
+
+    save_manifest(room, &manifest).await
+}
+
+/// Search the messages in the local search index of the room.
+pub(super) async fn search_local(
+    room: &Room,
+    query: &str,
+    options: SearchOptions,
+) -> Result<SearchResults> {
+    let terms = search_terms(query);
+    let offset = options.from.as_deref().and_then(|from| from.parse().ok()).unwrap_or(0);
+    let manifest = load_manifest(room).await?;
+
+    // Look at the chunks from the most recent one, and stop as soon as we know
+    // whether there is a next batch.
+    let mut matches = Vec::new();
+    for &chunk_id in manifest.chunks.iter().rev() {
+        let mut chunk = load_chunk(room, chunk_id).await?;
+        chunk.sort_by(|a, b| b.origin_server_ts.cmp(&a.origin_server_ts));
+
+        matches.extend(chunk.into_iter().filter_map(|message| {
+            let highlights = highlights(&message.body, &terms)?;
+            Some(SearchResult {
+                event_id: message.event_id,
+                sender: message.sender,
+                origin_server_ts: message.origin_server_ts,
+                body: message.body,
+                highlights,
+            })
+        }));
+
+        if matches.len() > offset + options.limit {
+            break;
+        }
+    }
+
+    let mut matches = matches.into_iter();
+    let results: Vec<_> = matches.by_ref().skip(offset).take(options.limit).collect();
+    let next_batch = matches.next().is_some().then(|| (offset + results.len()).to_string());
+
+    Ok(SearchResults { results, next_batch })
+}
+
+/// Search the messages of the room with the homeserver's `/search` endpoint.
+pub(super) async fn search_server(
+    room: &Room,
+    query: &str,
+    options: SearchOptions,
+) -> Result<SearchResults> {
+    let mut criteria = Criteria::new(query.to_owned());
+    criteria.filter.rooms = Some(vec![room.room_id().to_owned()]);
+    criteria.filter.limit = u32::try_from(options.limit).ok().map(Into::into);
+
+    let request = assign!(Request::new(assign!(Categories::new(), {
+        room_events: Some(criteria),
+    })), {
+        next_batch: options.from,
+    });
+
+    let response = room.client.send(request, None).await?;
+    let room_events = response.search_categories.room_events;
+
+    let mut terms = search_terms(query);
+    terms.extend(room_events.highlights.iter().map(|h| h.to_lowercase()));
+
+    let results = room_events
+        .results
+        .into_iter()
+        .filter_map(|result| {
+            let event = result.result?.deserialize().ok()?;
+            let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(ev),
+            )) = event
+            else {
+                return None;
+            };
+
+            let body = searchable_body(&ev.content.msgtype)?;
+            let highlights = highlights(&body, &terms).unwrap_or_default();
+            Some(SearchResult {
+                event_id: ev.event_id,
+                sender: ev.sender,
+                origin_server_ts: ev.origin_server_ts,
+                body,
+                highlights,
+            })
+        })
+        .collect();
+
+    Ok(SearchResults { results, next_batch: room_events.next_batch })
+}
+
+/// Split the query in lowercase terms.
+fn search_terms(query: &str) -> Vec<String> {
+    query.split_whitespace().map(str::to_lowercase).collect()
+}
+
+/// Get the ranges of the occurrences of the terms in the body.
+///
+/// Returns `None` if one of the terms doesn't occur in the body, or if there
+/// are no terms.
+fn highlights(body: &str, terms: &[String]) -> Option<Vec<Range<usize>>> {
+    if terms.is_empty() {
+        return None;
+    }
+
+    let lowercase_body = body.to_lowercase();
+    let mut ranges = Vec::new();
+
+    for term in terms {
+        let term_ranges: Vec<_> = lowercase_body
+            .match_indices(term.as_str())
+            .map(|(start, matched)| start..start + matched.len())
+            .collect();
+
+        if term_ranges.is_empty() {
+            return None;
+        }
+
+        ranges.extend(term_ranges);
+    }
+
+    // The ranges are only meaningful if the lowercase body has the same byte
+    // offsets as the original one.
+    if lowercase_body.len() != body.len() {
+        return Some(Vec::new());
+    }
+
+    ranges.sort_by_key(|r| r.start);
+    ranges.dedup();
+
+    Some(ranges)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use matrix_sdk_base::deserialized_responses::{
+        AlgorithmInfo, EncryptionInfo, SyncTimelineEvent, VerificationState,
+    };
+    use matrix_sdk_test::{
+        async_test, test_json, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder,
+    };
+    use ruma::{serde::Raw, user_id};
+    use serde_json::{json, Value as JsonValue};
+    use wiremock::{
+        matchers::{body_partial_json, method, path, path_regex, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{
+        chunk_key, highlights, index_timeline_events, load_chunk, load_manifest, search_terms,
+        SearchOptions, SearchResults, CHUNK_SIZE, MAX_CHUNKS,
+    };
+    use crate::{test_utils::logged_in_client, Client, Room};
+
+    /// Get the default room of the test sync responses, encrypted or not.
+    async fn room(server: &MockServer, encrypted: bool) -> (Client, Room) {
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let mut joined_room = JoinedRoomBuilder::default();
+        if encrypted {
+            joined_room = joined_room.add_state_event(StateTestEvent::Encryption);
+        } else {
+            Mock::given(method("GET"))
+                .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.*room.*encryption.?"))
+                .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                    "errcode": "M_NOT_FOUND",
+                    "error": "Event not found.",
+                })))
+                .mount(server)
+                .await;
+        }
+
+        let response =
+            SyncResponseBuilder::default().add_joined_room(joined_room).build_sync_response();
+        client.base_client().receive_sync_response(response).await.unwrap();
+
+        let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+        (client, room)
+    }
+
+    /// A decrypted event with the given JSON.
+    fn decrypted(json: JsonValue) -> SyncTimelineEvent {
+        let mut event = SyncTimelineEvent::new(Raw::new(&json).unwrap().cast());
+        event.encryption_info = Some(EncryptionInfo {
+            sender: user_id!("@alice:localhost").to_owned(),
+            sender_device: None,
+            algorithm_info: AlgorithmInfo::MegolmV1AesSha2 {
+                curve25519_key: "curve25519_key".to_owned(),
+                sender_claimed_keys: BTreeMap::new(),
+            },
+            verification_state: VerificationState::Verified,
+        });
+        event
+    }
+
+    /// A decrypted text message.
+    fn message(event_id: &str, ts: u64, body: &str) -> SyncTimelineEvent {
+        decrypted(json!({
+            "content": { "body": body, "msgtype": "m.text" },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": "@alice:localhost",
+            "type": "m.room.message",
+        }))
+    }
+
+    fn event_ids(results: &SearchResults) -> Vec<&str> {
+        results.results.iter().map(|result| result.event_id.as_str()).collect()
+    }
+
+    #[test]
+    fn highlights_all_terms() {
+        let terms = search_terms("Hello world");
+        let body = "hello, World! Hello again.";
+
+        assert_eq!(highlights(body, &terms), Some(vec![0..5, 7..12, 14..19]));
+    }
+
+    #[test]
+    fn highlights_missing_term() {
+        let terms = search_terms("hello moon");
+
+        assert_eq!(highlights("hello world", &terms), None);
+        assert_eq!(highlights("hello world", &[]), None);
+    }
+
+    #[async_test]
+    async fn test_edits_and_redactions() {
+        let server = MockServer::start().await;
+        let (_client, room) = room(&server, true).await;
+
+        let events = [message("$hello", 1, "hello world"), message("$moon", 2, "hello moon")];
+        index_timeline_events(&room, &events).await.unwrap();
+
+        let results = room.search_messages("hello", SearchOptions::new()).await.unwrap();
+        assert_eq!(event_ids(&results), ["$moon", "$hello"]);
+
+        // Edit and redact the messages of the previous batch.
+        let events = [
+            decrypted(json!({
+                "content": {
+                    "body": "* goodbye world",
+                    "msgtype": "m.text",
+                    "m.new_content": { "body": "goodbye world", "msgtype": "m.text" },
+                    "m.relates_to": { "rel_type": "m.replace", "event_id": "$hello" },
+                },
+                "event_id": "$edit",
+                "origin_server_ts": 3,
+                "sender": "@alice:localhost",
+                "type": "m.room.message",
+            })),
+            SyncTimelineEvent::new(
+                Raw::new(&json!({
+                    "content": { "redacts": "$moon" },
+                    "event_id": "$redaction",
+                    "origin_server_ts": 4,
+                    "redacts": "$moon",
+                    "sender": "@alice:localhost",
+                    "type": "m.room.redaction",
+                }))
+                .unwrap()
+                .cast(),
+            ),
+        ];
+        index_timeline_events(&room, &events).await.unwrap();
+
+        // The edit replaced the indexed text, and isn't indexed itself.
+        let results = room.search_messages("hello", SearchOptions::new()).await.unwrap();
+        assert!(results.results.is_empty());
+
+        let results = room.search_messages("goodbye", SearchOptions::new()).await.unwrap();
+        assert_eq!(event_ids(&results), ["$hello"]);
+        assert_eq!(results.results[0].body, "goodbye world");
+        assert_eq!(results.results[0].highlights, [0..7]);
+
+        // The redacted message was removed from the index.
+        let results = room.search_messages("moon", SearchOptions::new()).await.unwrap();
+        assert!(results.results.is_empty());
+    }
+
+    #[async_test]
+    async fn test_unencrypted_messages_are_not_indexed() {
+        let server = MockServer::start().await;
+        let (_client, room) = room(&server, true).await;
+
+        let event = SyncTimelineEvent::new(
+            Raw::new(&json!({
+                "content": { "body": "hello world", "msgtype": "m.text" },
+                "event_id": "$hello",
+                "origin_server_ts": 1,
+                "sender": "@alice:localhost",
+                "type": "m.room.message",
+            }))
+            .unwrap()
+            .cast(),
+        );
+        index_timeline_events(&room, &[event]).await.unwrap();
+
+        assert!(load_manifest(&room).await.unwrap().chunks.is_empty());
+    }
+
+    #[async_test]
+    async fn test_chunk_rollover_and_eviction() {
+        let server = MockServer::start().await;
+        let (client, room) = room(&server, true).await;
+
+        let messages = |range: std::ops::Range<usize>| -> Vec<_> {
+            range.map(|i| message(&format!("${i}"), i as u64, &format!("message <{i}>"))).collect()
+        };
+
+        // A new chunk is created once the last one is full.
+        index_timeline_events(&room, &messages(0..CHUNK_SIZE + 1)).await.unwrap();
+
+        let manifest = load_manifest(&room).await.unwrap();
+        assert_eq!(manifest.chunks, [0, 1]);
+        assert_eq!(load_chunk(&room, 0).await.unwrap().len(), CHUNK_SIZE);
+        assert_eq!(load_chunk(&room, 1).await.unwrap().len(), 1);
+
+        // The oldest chunk is dropped once there are too many.
+        let total = CHUNK_SIZE * MAX_CHUNKS + 1;
+        index_timeline_events(&room, &messages(CHUNK_SIZE + 1..total)).await.unwrap();
+
+        let manifest = load_manifest(&room).await.unwrap();
+        assert_eq!(manifest.chunks.len(), MAX_CHUNKS);
+        assert_eq!(manifest.chunks.front(), Some(&1));
+        assert_eq!(manifest.next_chunk_id, MAX_CHUNKS as u64 + 1);
+        assert!(client
+            .store()
+            .get_custom_value(&chunk_key(room.room_id(), 0))
+            .await
+            .unwrap()
+            .is_none());
+
+        let results = room.search_messages("<0>", SearchOptions::new()).await.unwrap();
+        assert!(results.results.is_empty());
+
+        let query = format!("<{CHUNK_SIZE}>");
+        let results = room.search_messages(&query, SearchOptions::new()).await.unwrap();
+        assert_eq!(event_ids(&results), [format!("${CHUNK_SIZE}")]);
+    }
+
+    #[async_test]
+    async fn test_search_local_pagination() {
+        let server = MockServer::start().await;
+        let (_client, room) = room(&server, true).await;
+
+        let events: Vec<_> =
+            (0..5).map(|i| message(&format!("${i}"), i, &format!("message {i}"))).collect();
+        index_timeline_events(&room, &events).await.unwrap();
+
+        let mut options = SearchOptions::new();
+        options.limit = 2;
+
+        let results = room.search_messages("message", options.clone()).await.unwrap();
+        assert_eq!(event_ids(&results), ["$4", "$3"]);
+        assert_eq!(results.next_batch.as_deref(), Some("2"));
+
+        options.from = results.next_batch;
+        let results = room.search_messages("message", options.clone()).await.unwrap();
+        assert_eq!(event_ids(&results), ["$2", "$1"]);
+        assert_eq!(results.next_batch.as_deref(), Some("4"));
+
+        options.from = results.next_batch;
+        let results = room.search_messages("message", options).await.unwrap();
+        assert_eq!(event_ids(&results), ["$0"]);
+        assert_eq!(results.next_batch, None);
+    }
+
+    #[async_test]
+    async fn test_search_server() {
+        let server = MockServer::start().await;
+        let (_client, room) = room(&server, false).await;
+        let room_id = room.room_id();
+
+        let result = |event_id: &str, body: &str| {
+            json!({
+                "rank": 1.0,
+                "result": {
+                    "content": { "body": body, "msgtype": "m.text" },
+                    "event_id": event_id,
+                    "origin_server_ts": 1,
+                    "room_id": room_id,
+                    "sender": "@alice:localhost",
+                    "type": "m.room.message",
+                },
+            })
+        };
+
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/r0/search"))
+            .and(query_param("next_batch", "next"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "search_categories": {
+                    "room_events": {
+                        "count": 2,
+                        "highlights": [],
+                        "results": [result("$older", "Hello world")],
+                    },
+                },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/r0/search"))
+            .and(body_partial_json(json!({
+                "search_categories": {
+                    "room_events": {
+                        "search_term": "hello",
+                        "filter": { "rooms": [room_id] },
+                    },
+                },
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "search_categories": {
+                    "room_events": {
+                        "count": 2,
+                        "highlights": ["hello"],
+                        "next_batch": "next",
+                        "results": [result("$newer", "hello, hello")],
+                    },
+                },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let results = room.search_messages("hello", SearchOptions::new()).await.unwrap();
+        assert_eq!(event_ids(&results), ["$newer"]);
+        assert_eq!(results.results[0].body, "hello, hello");
+        assert_eq!(results.results[0].highlights, [0..5, 7..12]);
+        assert_eq!(results.next_batch.as_deref(), Some("next"));
+
+        let mut options = SearchOptions::new();
+        options.from = results.next_batch;
+        let results = room.search_messages("hello", options).await.unwrap();
+        assert_eq!(event_ids(&results), ["$older"]);
+        assert_eq!(results.next_batch, None);
+    }
+}
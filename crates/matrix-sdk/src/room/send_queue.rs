@@ -0,0 +1,596 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A persistent queue of the events waiting to be sent to a room.
+//!
+//! Events are written to the state store as soon as they are queued and are
+//! only removed once they were sent, or once sending them failed for a reason
+//! that retrying won't fix. This allows clients to restore the events that
+//! were composed while offline, or that were still in flight, after a restart.
+//!
+//! Each event is stored separately, next to the list of the transaction IDs of
+//! the queue, so changing an event doesn't rewrite the whole queue.
+//!
+//! Requests that target an event of the queue, like edits and redactions, are
+//! kept in a separate list, because they can only be sent once the event they
+//! target was sent.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use eyeball_im::{ObservableVector, Vector, VectorSubscriber};
+use futures_util::{
+    future::{select, AbortHandle, Abortable},
+    pin_mut,
+};
+use matrix_sdk_common::sleep::sleep;
+use ruma::{
+    events::{
+        room::message::RoomMessageEventContentWithoutRelation, AnyMessageLikeEventContent,
+        MessageLikeEventContent, MessageLikeEventType,
+    },
+    serde::Raw,
+    EventId, OwnedEventId, OwnedTransactionId, RoomId, TransactionId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument, warn};
+
+use crate::{Error, NetworkState, Result, Room};
+
+/// The prefix of the keys of the send queues in the custom values of the state
+/// store.
+const SEND_QUEUE_KEY_PREFIX: &str = "send_queue:";
+
+/// The prefix of the keys of the dependent requests of the send queues in the
+/// custom values of the state store.
+const DEPENDENTS_KEY_PREFIX: &str = "send_queue_dependents:";
+
+/// The delay before the first retry of an event that couldn't be sent because
+/// of a connectivity issue.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The maximum delay between two retries of an event that couldn't be sent
+/// because of a connectivity issue.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// The maximum number of times an event is retried because of a connectivity
+/// issue, before it is marked as failed.
+const MAX_CONNECTIVITY_RETRIES: u32 = 10;
+
+/// An event waiting in the send queue of a room.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedEvent {
+    /// The transaction ID of the event.
+    pub transaction_id: OwnedTransactionId,
+    /// The content to send.
+    pub content: QueuedEventContent,
+    /// Whether sending the event failed because the homeserver couldn't be
+    /// reached, even after retrying.
+    ///
+    /// The event stays in the queue so it can be sent again later.
+    #[serde(default)]
+    pub sending_failed: bool,
+}
+
+impl QueuedEvent {
+    fn new(transaction_id: OwnedTransactionId, content: QueuedEventContent) -> Self {
+        Self { transaction_id, content, sending_failed: false }
+    }
+}
+
+/// The content of a [`QueuedEvent`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuedEventContent {
+    /// A message-like event that is sent as-is.
+    Event {
+        /// The type of the event.
+        event_type: MessageLikeEventType,
+        /// The content of the event.
+        content: Raw<AnyMessageLikeEventContent>,
+    },
+    /// A file that needs to be uploaded before sending the event referencing
+    /// it.
+    Attachment {
+        /// The path of the file to upload.
+        path: String,
+        /// The mime type of the file.
+        mime_type: String,
+    },
+}
+
+/// A request waiting in the send queue of a room, that targets an event of the
+/// queue and can only be sent once that event was sent, because it needs its
+/// event ID.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedDependentRequest {
+    /// The transaction ID of the request.
+    pub transaction_id: OwnedTransactionId,
+    /// The transaction ID of the event targeted by the request.
+    pub parent_transaction_id: OwnedTransactionId,
+    /// The ID of the event targeted by the request, once it was sent.
+    pub parent_event_id: Option<OwnedEventId>,
+    /// The request to send.
+    pub request: DependentRequest,
+}
+
+/// A request that targets an event which might not have been sent yet.
+///
+/// See [`QueuedDependentRequest`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DependentRequest {
+    /// Replace the content of the event.
+    Edit {
+        /// The new content of the event.
+        new_content: RoomMessageEventContentWithoutRelation,
+    },
+    /// Redact the event.
+    Redact {
+        /// The reason of the redaction.
+        reason: Option<String>,
+    },
+    /// React to the event.
+    React {
+        /// The key of the reaction, usually an emoji.
+        key: String,
+    },
+}
+
+/// The state of a send queue, shared between all the [`RoomSendQueue`]s of the
+/// same room.
+pub(crate) type SharedSendQueue = Arc<SendQueueState>;
+
+#[derive(Debug, Default)]
+pub(crate) struct SendQueueState {
+    /// The events of the queue.
+    events: Mutex<ObservableVector<QueuedEvent>>,
+    /// The requests targeting events of the queue.
+    dependents: Mutex<Vec<QueuedDependentRequest>>,
+    /// The handles to abort the events that are being sent, by transaction ID.
+    in_flight: StdMutex<BTreeMap<OwnedTransactionId, AbortHandle>>,
+}
+
+/// A persistent and observable queue of the events waiting to be sent to a
+/// room.
+///
+/// Get one with [`Room::send_queue()`].
+#[derive(Clone, Debug)]
+pub struct RoomSendQueue {
+    room: Room,
+    state: SharedSendQueue,
+}
+
+impl RoomSendQueue {
+    /// Load the send queue of the given room from the state store.
+    pub(crate) async fn load(room: &Room) -> Result<SharedSendQueue> {
+        let store = room.client.store();
+        let transaction_ids: Vec<OwnedTransactionId> =
+            match store.get_custom_value(&index_key(room.room_id())).await? {
+                Some(value) => serde_json::from_slice(&value)?,
+                None => Vec::new(),
+            };
+
+        let mut events = Vector::new();
+        for transaction_id in transaction_ids {
+            match store.get_custom_value(&event_key(room.room_id(), &transaction_id)).await? {
+                Some(value) => events.push_back(serde_json::from_slice(&value)?),
+                None => warn!(txn_id = ?transaction_id, "Queued event is missing from the store"),
+            }
+        }
+
+        let mut queue = ObservableVector::new();
+        queue.append(events);
+
+        let dependents = match store.get_custom_value(&dependents_key(room.room_id())).await? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => Vec::new(),
+        };
+
+        Ok(Arc::new(SendQueueState {
+            events: Mutex::new(queue),
+            dependents: Mutex::new(dependents),
+            in_flight: Default::default(),
+        }))
+    }
+
+    pub(crate) fn new(room: Room, state: SharedSendQueue) -> Self {
+        Self { room, state }
+    }
+
+    /// The events currently waiting in the queue, in the order they were
+    /// added.
+    pub async fn events(&self) -> Vector<QueuedEvent> {
+        Vector::clone(&*self.state.events.lock().await)
+    }
+
+    /// Get the events currently waiting in the queue and a stream of updates
+    /// to them.
+    pub async fn subscribe(&self) -> (Vector<QueuedEvent>, VectorSubscriber<QueuedEvent>) {
+        let events = self.state.events.lock().await;
+        (Vector::clone(&events), ObservableVector::subscribe(&events))
+    }
+
+    /// Add a message-like event at the end of the queue.
+    ///
+    /// This doesn't send the event, use [`RoomSendQueue::send_with_retry()`]
+    /// for that.
+    pub async fn push_event(
+        &self,
+        transaction_id: OwnedTransactionId,
+        content: &AnyMessageLikeEventContent,
+    ) -> Result<()> {
+        let content = QueuedEventContent::Event {
+            event_type: content.event_type(),
+            content: Raw::new(content)?,
+        };
+        self.push(QueuedEvent::new(transaction_id, content)).await
+    }
+
+    /// Add an attachment at the end of the queue.
+    ///
+    /// This doesn't upload the file, it only records it so the upload can be
+    /// restarted after the application was restarted.
+    pub async fn push_attachment(
+        &self,
+        transaction_id: OwnedTransactionId,
+        path: String,
+        mime_type: &mime::Mime,
+    ) -> Result<()> {
+        let content = QueuedEventContent::Attachment { path, mime_type: mime_type.to_string() };
+        self.push(QueuedEvent::new(transaction_id, content)).await
+    }
+
+    /// The requests targeting events of the queue, in the order they were
+    /// added.
+    pub async fn dependent_requests(&self) -> Vec<QueuedDependentRequest> {
+        self.state.dependents.lock().await.clone()
+    }
+
+    /// Add a request targeting the event with the given parent transaction
+    /// ID.
+    ///
+    /// The request isn't part of the events of the queue: it should be sent
+    /// once, after its parent event was sent, and then removed with
+    /// [`RoomSendQueue::remove_dependent()`]. Use
+    /// [`RoomSendQueue::resolve_dependents()`] once the parent event was sent
+    /// to record its ID.
+    pub async fn push_dependent(
+        &self,
+        transaction_id: OwnedTransactionId,
+        parent_transaction_id: OwnedTransactionId,
+        request: DependentRequest,
+    ) -> Result<()> {
+        let mut dependents = self.state.dependents.lock().await;
+
+        if dependents.iter().any(|d| d.transaction_id == transaction_id) {
+            debug!(txn_id = ?transaction_id, "Request is already queued");
+            return Ok(());
+        }
+
+        dependents.push(QueuedDependentRequest {
+            transaction_id,
+            parent_transaction_id,
+            parent_event_id: None,
+            request,
+        });
+        self.save_dependents(&dependents).await
+    }
+
+    /// Record the event ID of the event with the given transaction ID in the
+    /// requests that target it, so they can be sent even if the application
+    /// is restarted before.
+    pub async fn resolve_dependents(
+        &self,
+        parent_transaction_id: &TransactionId,
+        parent_event_id: &EventId,
+    ) -> Result<()> {
+        let mut dependents = self.state.dependents.lock().await;
+        let mut has_changed = false;
+
+        for dependent in dependents.iter_mut() {
+            if dependent.parent_transaction_id == parent_transaction_id {
+                dependent.parent_event_id = Some(parent_event_id.to_owned());
+                has_changed = true;
+            }
+        }
+
+        if has_changed {
+            self.save_dependents(&dependents).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the request targeting an event with the given transaction ID.
+    ///
+    /// Returns whether the request was found.
+    pub async fn remove_dependent(&self, transaction_id: &TransactionId) -> Result<bool> {
+        let mut dependents = self.state.dependents.lock().await;
+
+        let Some(index) = dependents.iter().position(|d| d.transaction_id == transaction_id) else {
+            return Ok(false);
+        };
+
+        dependents.remove(index);
+        self.save_dependents(&dependents).await?;
+
+        Ok(true)
+    }
+
+    async fn push(&self, event: QueuedEvent) -> Result<()> {
+        let mut events = self.state.events.lock().await;
+
+        if events.iter().any(|e| e.transaction_id == event.transaction_id) {
+            debug!(txn_id = ?event.transaction_id, "Event is already queued");
+            return Ok(());
+        }
+
+        // Save the event before adding it to the index, so the index never
+        // references a missing event.
+        self.save_event(&event).await?;
+        events.push_back(event);
+        self.save_index(&events).await
+    }
+
+    /// Replace the content of the message-like event with the given
+    /// transaction ID, keeping its place in the queue.
+    ///
+    /// Returns whether the event was found.
+    pub async fn replace_event(
+        &self,
+        transaction_id: &TransactionId,
+        content: &AnyMessageLikeEventContent,
+    ) -> Result<bool> {
+        let mut events = self.state.events.lock().await;
+
+        let Some(index) = events.iter().position(|e| {
+            e.transaction_id == transaction_id
+                && matches!(e.content, QueuedEventContent::Event { .. })
+        }) else {
+            return Ok(false);
+        };
+
+        let content = QueuedEventContent::Event {
+            event_type: content.event_type(),
+            content: Raw::new(content)?,
+        };
+        let event = QueuedEvent::new(transaction_id.to_owned(), content);
+        self.save_event(&event).await?;
+        events.set(index, event);
+
+        Ok(true)
+    }
+
+    /// Remove the event with the given transaction ID from the queue.
+    ///
+    /// Returns whether the event was found.
+    pub async fn remove(&self, transaction_id: &TransactionId) -> Result<bool> {
+        let mut events = self.state.events.lock().await;
+
+        let Some(index) = events.iter().position(|e| e.transaction_id == transaction_id) else {
+            return Ok(false);
+        };
+
+        let event = events.remove(index);
+        self.save_index(&events).await?;
+        self.room
+            .client
+            .store()
+            .remove_custom_value(&event_key(self.room.room_id(), transaction_id))
+            .await?;
+
+        if matches!(event.content, QueuedEventContent::Attachment { .. }) {
+            self.room.client.media().forget_pending_upload(transaction_id).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Abort sending the event with the given transaction ID, and remove it
+    /// from the queue.
+    ///
+    /// If the event is being sent with [`RoomSendQueue::send_with_retry()`],
+    /// it stops waiting for the response and returns
+    /// [`Error::SendAborted`]. Note that if the request already reached the
+    /// homeserver, the event might have been sent anyway.
+    ///
+    /// Returns whether the event was found in the queue.
+    pub async fn abort(&self, transaction_id: &TransactionId) -> Result<bool> {
+        if let Some(abort_handle) = self.state.in_flight.lock().unwrap().remove(transaction_id) {
+            debug!(txn_id = ?transaction_id, "Aborting sending of event");
+            abort_handle.abort();
+        }
+
+        self.remove(transaction_id).await
+    }
+
+    /// Run the given request to send the event with the given transaction ID,
+    /// and remove the event from the queue once it's done.
+    ///
+    /// If the request fails because the homeserver couldn't be reached, it is
+    /// retried with an exponential backoff until connectivity returns, or as
+    /// soon as another request reaches the homeserver. After
+    /// `MAX_CONNECTIVITY_RETRIES` attempts, the error is returned and the
+    /// event is kept in the queue, marked as
+    /// [`sending_failed`](QueuedEvent::sending_failed). Other errors are
+    /// returned as-is, and the event is removed from the queue as retrying
+    /// wouldn't help.
+    ///
+    /// Sending can be stopped with [`RoomSendQueue::abort()`].
+    #[instrument(skip(self, request), fields(room_id = ?self.room.room_id()))]
+    pub async fn send_with_retry<T, F, Fut>(
+        &self,
+        transaction_id: &TransactionId,
+        mut request: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        if let Err(error) = self.set_sending_failed(transaction_id, false).await {
+            warn!("Failed to update event in the send queue: {error}");
+        }
+
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        self.state.in_flight.lock().unwrap().insert(transaction_id.to_owned(), abort_handle);
+
+        let send = async {
+            let mut delay = INITIAL_RETRY_DELAY;
+            let mut retries = 0;
+
+            loop {
+                match request().await {
+                    Err(error)
+                        if is_connectivity_error(&error) && retries < MAX_CONNECTIVITY_RETRIES =>
+                    {
+                        debug!("Couldn't reach the homeserver, retrying in {delay:?}: {error}");
+                        self.wait_before_retry(delay).await;
+                        delay = (delay * 2).min(MAX_RETRY_DELAY);
+                        retries += 1;
+                    }
+                    result => break result,
+                }
+            }
+        };
+        let result = Abortable::new(send, abort_registration).await;
+
+        self.state.in_flight.lock().unwrap().remove(transaction_id);
+
+        let result = match result {
+            Ok(Err(error)) if is_connectivity_error(&error) => {
+                warn!("Couldn't reach the homeserver, giving up: {error}");
+                if let Err(error) = self.set_sending_failed(transaction_id, true).await {
+                    warn!("Failed to mark event as failed in the send queue: {error}");
+                }
+                return Err(error);
+            }
+            Ok(result) => result,
+            // The event was already removed from the queue.
+            Err(_) => return Err(Error::SendAborted),
+        };
+
+        if let Err(error) = self.remove(transaction_id).await {
+            warn!("Failed to remove event from the send queue: {error}");
+        }
+
+        result
+    }
+
+    /// Set whether sending the event with the given transaction ID failed.
+    async fn set_sending_failed(
+        &self,
+        transaction_id: &TransactionId,
+        sending_failed: bool,
+    ) -> Result<()> {
+        let mut events = self.state.events.lock().await;
+
+        let Some(index) = events.iter().position(|e| e.transaction_id == transaction_id) else {
+            return Ok(());
+        };
+        if events[index].sending_failed == sending_failed {
+            return Ok(());
+        }
+
+        let event = QueuedEvent { sending_failed, ..events[index].clone() };
+        self.save_event(&event).await?;
+        events.set(index, event);
+
+        Ok(())
+    }
+
+    /// Wait for the given delay, or until the client is back online if that
+    /// happens first.
+    async fn wait_before_retry(&self, delay: Duration) {
+        let mut network_state = self.room.client.subscribe_to_network_state();
+
+        let back_online = async move {
+            while let Some(state) = network_state.next().await {
+                if state == NetworkState::Online {
+                    break;
+                }
+            }
+        };
+
+        pin_mut!(back_online);
+        let timeout = sleep(delay);
+        pin_mut!(timeout);
+
+        select(back_online, timeout).await;
+    }
+
+    /// Save the given event in the state store.
+    async fn save_event(&self, event: &QueuedEvent) -> Result<()> {
+        let key = event_key(self.room.room_id(), &event.transaction_id);
+        self.room.client.store().set_custom_value(&key, serde_json::to_vec(event)?).await?;
+        Ok(())
+    }
+
+    /// Save the given dependent requests in the state store.
+    async fn save_dependents(&self, dependents: &[QueuedDependentRequest]) -> Result<()> {
+        let key = dependents_key(self.room.room_id());
+        let store = self.room.client.store();
+
+        if dependents.is_empty() {
+            store.remove_custom_value(&key).await?;
+        } else {
+            store.set_custom_value(&key, serde_json::to_vec(dependents)?).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Save the order of the given events in the state store.
+    async fn save_index(&self, events: &ObservableVector<QueuedEvent>) -> Result<()> {
+        let key = index_key(self.room.room_id());
+        let store = self.room.client.store();
+
+        if events.is_empty() {
+            store.remove_custom_value(&key).await?;
+        } else {
+            let transaction_ids: Vec<_> = events.iter().map(|e| &e.transaction_id).collect();
+            store.set_custom_value(&key, serde_json::to_vec(&transaction_ids)?).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The key of the transaction IDs of the send queue of the given room in the
+/// custom values of the state store.
+fn index_key(room_id: &RoomId) -> Vec<u8> {
+    format!("{SEND_QUEUE_KEY_PREFIX}{room_id}").into_bytes()
+}
+
+/// The key of the queued event with the given transaction ID in the custom
+/// values of the state store.
+fn event_key(room_id: &RoomId, transaction_id: &TransactionId) -> Vec<u8> {
+    format!("{SEND_QUEUE_KEY_PREFIX}{room_id}:{transaction_id}").into_bytes()
+}
+
+/// The key of the dependent requests of the send queue of the given room in
+/// the custom values of the state store.
+fn dependents_key(room_id: &RoomId) -> Vec<u8> {
+    format!("{DEPENDENTS_KEY_PREFIX}{room_id}").into_bytes()
+}
+
+/// Whether the given error means that the homeserver couldn't be reached.
+fn is_connectivity_error(error: &Error) -> bool {
+    matches!(error, Error::Http(error) if error.is_network_error())
+}
@@ -0,0 +1,209 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Types to search the public rooms directory of a homeserver, one page at a
+//! time.
+//!
+//! See [`Client::public_room_search()`] to get started.
+
+use eyeball_im::{ObservableVector, Vector, VectorSubscriber};
+use matrix_sdk_base::RoomState;
+use ruma::{
+    api::client::directory::get_public_rooms_filtered,
+    directory::{Filter, PublicRoomJoinRule, PublicRoomsChunk},
+    OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, OwnedServerName, ServerName,
+};
+use tracing::debug;
+
+use crate::{Client, HttpResult};
+
+/// The number of rooms requested for every page, by default.
+const DEFAULT_BATCH_SIZE: u32 = 20;
+
+/// The description of a room found in the public rooms directory.
+#[derive(Clone, Debug)]
+pub struct RoomDescription {
+    /// The ID of the room.
+    pub room_id: OwnedRoomId,
+    /// The name of the room, if any.
+    pub name: Option<String>,
+    /// The topic of the room, if any.
+    pub topic: Option<String>,
+    /// The canonical alias of the room, if any.
+    pub alias: Option<OwnedRoomAliasId>,
+    /// The URL of the avatar of the room, if any.
+    pub avatar_url: Option<OwnedMxcUri>,
+    /// The number of members that joined the room.
+    pub joined_members: u64,
+    /// Whether the history of the room can be read without joining it.
+    pub is_world_readable: bool,
+    /// Whether guests can join the room.
+    pub guest_can_join: bool,
+    /// The rule to join the room.
+    pub join_rule: PublicRoomJoinRule,
+    /// The membership of the current user in the room, if the client knows
+    /// the room.
+    pub membership: Option<RoomState>,
+}
+
+impl RoomDescription {
+    fn new(chunk: PublicRoomsChunk, client: &Client) -> Self {
+        let membership = client.get_room(&chunk.room_id).map(|room| room.state());
+
+        Self {
+            room_id: chunk.room_id,
+            name: chunk.name,
+            topic: chunk.topic,
+            alias: chunk.canonical_alias,
+            avatar_url: chunk.avatar_url,
+            joined_members: chunk.num_joined_members.into(),
+            is_world_readable: chunk.world_readable,
+            guest_can_join: chunk.guest_can_join,
+            join_rule: chunk.join_rule,
+            membership,
+        }
+    }
+
+    /// Whether the current user can join the room without being invited.
+    pub fn can_join_directly(&self) -> bool {
+        self.join_rule == PublicRoomJoinRule::Public
+    }
+}
+
+/// A search in the public rooms directory of a homeserver.
+///
+/// The results are fetched one page at a time with
+/// [`RoomDirectorySearch::next_page()`], and accumulated in a list that can be
+/// observed with [`RoomDirectorySearch::results()`].
+///
+/// Get it with [`Client::public_room_search()`].
+#[derive(Debug)]
+pub struct RoomDirectorySearch {
+    client: Client,
+    server: Option<OwnedServerName>,
+    filter: Filter,
+    batch_size: u32,
+    /// The token to fetch the next page, if any.
+    next_token: Option<String>,
+    /// Whether the last page was reached.
+    is_at_last_page: bool,
+    results: ObservableVector<RoomDescription>,
+}
+
+impl RoomDirectorySearch {
+    pub(crate) fn new(client: Client, server: Option<&ServerName>, filter: Filter) -> Self {
+        Self {
+            client,
+            server: server.map(ToOwned::to_owned),
+            filter,
+            batch_size: DEFAULT_BATCH_SIZE,
+            next_token: None,
+            is_at_last_page: false,
+            results: ObservableVector::new(),
+        }
+    }
+
+    /// Set the number of rooms to request for every page.
+    ///
+    /// Defaults to 20.
+    pub fn batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Fetch the next page of results and append them to the results.
+    ///
+    /// Does nothing if the last page was already reached.
+    pub async fn next_page(&mut self) -> HttpResult<()> {
+        if self.is_at_last_page {
+            return Ok(());
+        }
+
+        let mut request = get_public_rooms_filtered::v3::Request::new();
+        request.server = self.server.clone();
+        request.filter = self.filter.clone();
+        request.limit = Some(self.batch_size.into());
+        request.since = self.next_token.clone();
+
+        let response = self.client.public_rooms_filtered(request).await?;
+
+        debug!(count = response.chunk.len(), "Received a page of public rooms");
+
+        let rooms = response
+            .chunk
+            .into_iter()
+            .map(|chunk| RoomDescription::new(chunk, &self.client))
+            .collect();
+
+        self.next_token = response.next_batch;
+        self.is_at_last_page = self.next_token.is_none();
+        self.results.append(rooms);
+
+        Ok(())
+    }
+
+    /// Whether the last page of results was reached.
+    pub fn is_at_last_page(&self) -> bool {
+        self.is_at_last_page
+    }
+
+    /// Get the results found so far and a stream of updates to them.
+    pub fn results(&self) -> (Vector<RoomDescription>, VectorSubscriber<RoomDescription>) {
+        (Vector::clone(&self.results), ObservableVector::subscribe(&self.results))
+    }
+}
+
+impl Client {
+    /// Search the public rooms directory of a homeserver.
+    ///
+    /// The search doesn't send any request until
+    /// [`RoomDirectorySearch::next_page()`] is called, and the pagination
+    /// tokens are handled by the returned [`RoomDirectorySearch`].
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The name of the server to search the directory of, if
+    ///   `None` the homeserver of the client is used.
+    ///
+    /// * `filter` - The filter to apply to the rooms of the directory.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, ruma::directory::Filter};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let mut filter = Filter::new();
+    /// filter.generic_search_term = Some("rust".to_owned());
+    ///
+    /// let mut search = client.public_room_search(None, filter);
+    /// search.next_page().await?;
+    ///
+    /// let (rooms, _stream) = search.results();
+    ///
+    /// for room in rooms {
+    ///     println!("Found room {:?}", room.name);
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn public_room_search(
+        &self,
+        server: Option<&ServerName>,
+        filter: Filter,
+    ) -> RoomDirectorySearch {
+        RoomDirectorySearch::new(self.clone(), server, filter)
+    }
+}
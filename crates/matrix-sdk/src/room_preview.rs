@@ -0,0 +1,299 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Preview of a room, to show its details before joining it.
+//!
+//! See [`Client::get_room_preview()`] to get started.
+
+use std::collections::BTreeMap;
+
+use matrix_sdk_base::{Room as BaseRoom, RoomState};
+use ruma::{
+    api::{
+        client::{
+            filter::LazyLoadOptions,
+            message::get_message_events,
+            space::{get_hierarchy, SpaceHierarchyRoomsChunk},
+        },
+        Direction,
+    },
+    events::{
+        room::{history_visibility::HistoryVisibility, member::MembershipState},
+        AnyMessageLikeEvent, AnyStateEvent, AnyTimelineEvent,
+    },
+    room::RoomType,
+    serde::Raw,
+    space::SpaceRoomJoinRule,
+    uint, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, RoomId, RoomOrAliasId,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{Client, Result, Room};
+
+/// The details of a room, that can be shown before joining it.
+#[derive(Clone, Debug)]
+pub struct RoomPreview {
+    /// The ID of the room.
+    pub room_id: OwnedRoomId,
+    /// The canonical alias of the room, if any.
+    pub canonical_alias: Option<OwnedRoomAliasId>,
+    /// The name of the room, if any.
+    pub name: Option<String>,
+    /// The topic of the room, if any.
+    pub topic: Option<String>,
+    /// The URL of the avatar of the room, if any.
+    pub avatar_url: Option<OwnedMxcUri>,
+    /// The number of members that joined the room.
+    pub num_joined_members: u64,
+    /// The type of the room, if any. Spaces have a [`RoomType::Space`].
+    pub room_type: Option<RoomType>,
+    /// The rule to join the room.
+    pub join_rule: SpaceRoomJoinRule,
+    /// Whether the history of the room can be read without joining it.
+    pub is_world_readable: bool,
+    /// Whether the room is encrypted, or `None` if it couldn't be determined.
+    pub is_encrypted: Option<bool>,
+    /// The membership of the current user in the room, if the client knows
+    /// the room.
+    pub state: Option<RoomState>,
+}
+
+impl RoomPreview {
+    /// Create a preview from the data the client has about a known room.
+    fn from_known(room: &Room) -> Self {
+        let base_room: &BaseRoom = room;
+
+        Self {
+            room_id: room.room_id().to_owned(),
+            canonical_alias: room.canonical_alias(),
+            name: room.name(),
+            topic: room.topic(),
+            avatar_url: room.avatar_url(),
+            num_joined_members: room.joined_members_count(),
+            room_type: room.create_content().and_then(|content| content.room_type),
+            join_rule: SpaceRoomJoinRule::from(room.join_rule().as_str()),
+            is_world_readable: room.history_visibility() == HistoryVisibility::WorldReadable,
+            is_encrypted: room.is_encryption_state_synced().then(|| base_room.is_encrypted()),
+            state: Some(room.state()),
+        }
+    }
+
+    /// Create a preview from the chunk of the room in a `/hierarchy`
+    /// response.
+    fn from_hierarchy(chunk: SpaceHierarchyRoomsChunk, state: Option<RoomState>) -> Self {
+        Self {
+            room_id: chunk.room_id,
+            canonical_alias: chunk.canonical_alias,
+            name: chunk.name,
+            topic: chunk.topic,
+            avatar_url: chunk.avatar_url,
+            num_joined_members: chunk.num_joined_members.into(),
+            room_type: chunk.room_type,
+            join_rule: chunk.join_rule,
+            is_world_readable: chunk.world_readable,
+            is_encrypted: None,
+            state,
+        }
+    }
+
+    /// Create a preview from the events of a world-readable room that could be
+    /// peeked into with the `/messages` endpoint.
+    ///
+    /// Only the state events in the returned page are known, so the details
+    /// that were not changed recently might be missing.
+    fn from_peeked_events(
+        room_id: OwnedRoomId,
+        response: get_message_events::v3::Response,
+        state: Option<RoomState>,
+    ) -> Self {
+        let mut preview = Self {
+            room_id,
+            canonical_alias: None,
+            name: None,
+            topic: None,
+            avatar_url: None,
+            num_joined_members: 0,
+            room_type: None,
+            join_rule: SpaceRoomJoinRule::Invite,
+            // The homeserver only allows to peek into world-readable rooms.
+            is_world_readable: true,
+            is_encrypted: None,
+            state,
+        };
+        let mut memberships = BTreeMap::new();
+
+        // The timeline is in reverse chronological order, so go through it from the
+        // oldest event to let the latest state win.
+        let state_events =
+            response.state.into_iter().map(Raw::cast).chain(response.chunk.into_iter().rev());
+
+        for raw_event in state_events {
+            let event = match raw_event.deserialize() {
+                Ok(AnyTimelineEvent::State(event)) => event,
+                Ok(AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomEncrypted(_))) => {
+                    preview.is_encrypted = Some(true);
+                    continue;
+                }
+                Ok(AnyTimelineEvent::MessageLike(_)) => continue,
+                Err(error) => {
+                    warn!("Couldn't deserialize an event of the room preview: {error}");
+                    continue;
+                }
+            };
+
+            match &event {
+                AnyStateEvent::RoomCanonicalAlias(event) => {
+                    preview.canonical_alias =
+                        event.as_original().and_then(|event| event.content.alias.clone());
+                }
+                AnyStateEvent::RoomName(event) => {
+                    preview.name = event.as_original().and_then(|event| {
+                        event
+                            .content
+                            .name
+                            .as_ref()
+                            .map(|name| AsRef::<str>::as_ref(name).to_owned())
+                    });
+                }
+                AnyStateEvent::RoomTopic(event) => {
+                    preview.topic = event.as_original().map(|event| event.content.topic.clone());
+                }
+                AnyStateEvent::RoomAvatar(event) => {
+                    preview.avatar_url =
+                        event.as_original().and_then(|event| event.content.url.clone());
+                }
+                AnyStateEvent::RoomCreate(event) => {
+                    preview.room_type =
+                        event.as_original().and_then(|event| event.content.room_type.clone());
+                }
+                AnyStateEvent::RoomJoinRules(event) => {
+                    if let Some(event) = event.as_original() {
+                        preview.join_rule =
+                            SpaceRoomJoinRule::from(event.content.join_rule.as_str());
+                    }
+                }
+                AnyStateEvent::RoomEncryption(_) => {
+                    preview.is_encrypted = Some(true);
+                }
+                AnyStateEvent::RoomMember(event) => {
+                    let is_joined = event
+                        .as_original()
+                        .is_some_and(|event| event.content.membership == MembershipState::Join);
+                    memberships.insert(event.state_key().to_owned(), is_joined);
+                }
+                _ => {}
+            }
+        }
+
+        preview.num_joined_members =
+            memberships.values().filter(|is_joined| **is_joined).count() as u64;
+
+        preview
+    }
+}
+
+impl Client {
+    /// Get the details of a room before joining it, to show them in a dialog
+    /// to join the room for example.
+    ///
+    /// If the current user is in the room, the details known by the client
+    /// are used. Otherwise they are fetched from the `/hierarchy` endpoint,
+    /// which works for public rooms and rooms that the user can join, or by
+    /// peeking into the latest events of the room with the `/messages`
+    /// endpoint if it is world-readable. If the details can't be fetched but
+    /// the client knows the room, for example because the user is invited,
+    /// the details known by the client are used.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id_or_alias` - The `RoomId` or `RoomAliasId` of the room to
+    ///   preview.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, ruma::room_alias_id};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let preview = client
+    ///     .get_room_preview(room_alias_id!("#rust:matrix.org").into())
+    ///     .await?;
+    ///
+    /// println!("{:?} has {} members", preview.name, preview.num_joined_members);
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn get_room_preview(&self, room_id_or_alias: &RoomOrAliasId) -> Result<RoomPreview> {
+        let room_id = match <&RoomId>::try_from(room_id_or_alias) {
+            Ok(room_id) => room_id.to_owned(),
+            Err(room_alias) => self.resolve_room_alias(room_alias).await?.room_id,
+        };
+
+        let room = self.get_room(&room_id);
+
+        if let Some(room) = room.as_ref().filter(|room| room.state() == RoomState::Joined) {
+            return Ok(RoomPreview::from_known(room));
+        }
+
+        let state = room.as_ref().map(|room| room.state());
+
+        let error = match self.get_room_preview_from_hierarchy(&room_id, state).await {
+            Ok(Some(preview)) => return Ok(preview),
+            Ok(None) => None,
+            Err(error) => {
+                debug!("Couldn't get the room preview from the hierarchy: {error}");
+                Some(error)
+            }
+        };
+
+        // Peek into the latest events of the room, with the members who sent them.
+        let mut request =
+            get_message_events::v3::Request::new(room_id.clone(), Direction::Backward);
+        request.limit = uint!(50);
+        request.filter.lazy_load_options =
+            LazyLoadOptions::Enabled { include_redundant_members: false };
+        let error = match self.send(request, None).await {
+            Ok(response) => return Ok(RoomPreview::from_peeked_events(room_id, response, state)),
+            Err(peek_error) => {
+                debug!("Couldn't peek into the room for the room preview: {peek_error}");
+                error.unwrap_or_else(|| peek_error.into())
+            }
+        };
+
+        match room {
+            Some(room) => Ok(RoomPreview::from_known(&room)),
+            None => Err(error),
+        }
+    }
+
+    async fn get_room_preview_from_hierarchy(
+        &self,
+        room_id: &RoomId,
+        state: Option<RoomState>,
+    ) -> Result<Option<RoomPreview>> {
+        let mut request = get_hierarchy::v1::Request::new(room_id.to_owned());
+        request.max_depth = Some(uint!(0));
+        request.limit = Some(uint!(1));
+
+        let response = self.send(request, None).await?;
+
+        Ok(response
+            .rooms
+            .into_iter()
+            .find(|chunk| *chunk.room_id == *room_id)
+            .map(|chunk| RoomPreview::from_hierarchy(chunk, state)))
+    }
+}
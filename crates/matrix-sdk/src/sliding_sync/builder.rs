@@ -19,8 +19,8 @@ use url::Url;
 use super::{
     cache::{format_storage_key_prefix, restore_sliding_sync_state},
     sticky_parameters::SlidingSyncStickyManager,
-    Error, SlidingSync, SlidingSyncInner, SlidingSyncListBuilder, SlidingSyncPositionMarkers,
-    SlidingSyncRoom,
+    Error, SlidingSync, SlidingSyncExtension, SlidingSyncInner, SlidingSyncListBuilder,
+    SlidingSyncPositionMarkers, SlidingSyncRoom,
 };
 use crate::{sliding_sync::SlidingSyncStickyParameters, Client, Result};
 
@@ -137,6 +137,37 @@ impl SlidingSyncBuilder {
         self
     }
 
+    /// Enable the given extension, keeping the rest of its configuration.
+    pub fn with_extension(mut self, extension: SlidingSyncExtension) -> Self {
+        extension.set_enabled_in(self.extensions.get_or_insert_with(Default::default), true);
+        self
+    }
+
+    /// Disable the given extension, keeping the rest of its configuration.
+    ///
+    /// Unlike the `without_*_extension` methods, this explicitly disables the
+    /// extension, so it isn't enabled by [`Self::with_all_extensions`].
+    pub fn without_extension(mut self, extension: SlidingSyncExtension) -> Self {
+        extension.set_enabled_in(self.extensions.get_or_insert_with(Default::default), false);
+        self
+    }
+
+    /// Restrict the given extension to the rooms of the given lists.
+    ///
+    /// By default, an extension applies to the rooms of all the lists. This
+    /// only applies to the account data, receipts and typing extensions.
+    pub fn with_extension_lists(
+        mut self,
+        extension: SlidingSyncExtension,
+        lists: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        extension.set_lists_in(
+            self.extensions.get_or_insert_with(Default::default),
+            Some(lists.into_iter().map(Into::into).collect()),
+        );
+        self
+    }
+
     /// Set the E2EE extension configuration.
     pub fn with_e2ee_extension(mut self, e2ee: E2EEConfig) -> Self {
         self.extensions.get_or_insert_with(Default::default).e2ee = e2ee;
@@ -283,6 +314,7 @@ impl SlidingSyncBuilder {
             room_unsubscriptions: Default::default(),
 
             internal_channel: internal_channel_sender,
+            state: Default::default(),
 
             poll_timeout: self.poll_timeout,
             network_timeout: self.network_timeout,
@@ -55,6 +55,7 @@ impl SlidingSyncResponseProcessor {
 
         self.to_device_events =
             self.client.base_client().process_sliding_sync_e2ee(extensions).await?;
+        self.client.refresh_own_devices_if_changed(&extensions.e2ee.device_lists.changed);
         Ok(())
     }
 
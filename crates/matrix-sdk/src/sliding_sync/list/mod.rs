@@ -129,6 +129,18 @@ impl SlidingSyncList {
         self.inner.sticky.write().unwrap().data_mut().set_timeline_limit(timeline);
     }
 
+    /// Get the sort order of the rooms.
+    pub fn sort(&self) -> Vec<String> {
+        self.inner.sticky.read().unwrap().data().sort().to_vec()
+    }
+
+    /// Set the sort order of the rooms.
+    ///
+    /// The new sort order is sent to the server with the next request.
+    pub fn set_sort(&self, sort: Vec<String>) {
+        self.inner.sticky.write().unwrap().data_mut().set_sort(sort);
+    }
+
     /// Get the current room list.
     pub fn room_list<R>(&self) -> Vec<R>
     where
@@ -258,6 +270,16 @@ impl SlidingSyncList {
     pub fn invalidate_sticky_data(&self) {
         let _ = self.inner.sticky.write().unwrap().data_mut();
     }
+
+    /// Reset the list after the Sliding Sync session has expired.
+    ///
+    /// The request generator starts over, and the sticky parameters are
+    /// invalidated so that they are sent again. The room list and the maximum
+    /// number of rooms are kept; they will be updated by the next response.
+    pub(super) fn reset(&self) {
+        self.inner.reset();
+        self.invalidate_sticky_data();
+    }
 }
 
 #[cfg(any(test, feature = "testing"))]
@@ -351,6 +373,18 @@ impl SlidingSyncListInner {
         }
     }
 
+    /// Reset the request generator, and move the state back to
+    /// [`SlidingSyncListLoadingState::PartiallyLoaded`] if it was loaded.
+    fn reset(&self) {
+        self.request_generator.write().unwrap().reset();
+
+        let mut state = self.state.write().unwrap();
+
+        if **state == SlidingSyncListLoadingState::FullyLoaded {
+            Observable::set(&mut state, SlidingSyncListLoadingState::PartiallyLoaded);
+        }
+    }
+
     /// Update the state to the next request, and return it.
     fn next_request(&self, txn_id: &mut LazyTransactionId) -> Result<v4::SyncRequestList, Error> {
         let ranges = {
@@ -982,6 +1016,22 @@ mod tests {
         assert_eq!(list.inner.sticky.read().unwrap().data().timeline_limit(), None);
     }
 
+    #[test]
+    fn test_sliding_sync_list_sort() {
+        let (sender, _receiver) = channel(1);
+
+        let list = SlidingSyncList::builder("foo")
+            .sync_mode(SlidingSyncMode::new_selective().add_range(0..=1))
+            .sort(vec!["by_recency".to_owned(), "by_name".to_owned()])
+            .build(sender);
+
+        assert_eq!(list.sort(), vec!["by_recency".to_owned(), "by_name".to_owned()]);
+
+        list.set_sort(vec!["by_name".to_owned()]);
+        assert_eq!(list.sort(), vec!["by_name".to_owned()]);
+        assert_eq!(list.inner.sticky.read().unwrap().data().sort(), ["by_name".to_owned()]);
+    }
+
     #[test]
     fn test_sliding_sync_get_room_id() {
         let (sender, _receiver) = channel(1);
@@ -115,6 +115,34 @@ impl SlidingSyncListRequestGenerator {
         }
     }
 
+    /// Reset the request generator, so that the next requests start loading
+    /// the list from the beginning again.
+    ///
+    /// Selective generators keep their ranges, as they are defined by the user.
+    pub(super) fn reset(&mut self) {
+        match &mut self.kind {
+            SlidingSyncListRequestGeneratorKind::Paging {
+                number_of_fetched_rooms,
+                fully_loaded,
+                requested_end,
+                ..
+            }
+            | SlidingSyncListRequestGeneratorKind::Growing {
+                number_of_fetched_rooms,
+                fully_loaded,
+                requested_end,
+                ..
+            } => {
+                self.ranges.clear();
+                *number_of_fetched_rooms = 0;
+                *fully_loaded = false;
+                *requested_end = None;
+            }
+
+            SlidingSyncListRequestGeneratorKind::Selective => {}
+        }
+    }
+
     /// Return a view on the ranges requested by this generator.
     ///
     /// For generators in the selective mode, this is the initial set of ranges.
@@ -49,6 +49,14 @@ impl SlidingSyncListStickyParameters {
     pub(super) fn set_timeline_limit(&mut self, timeline: Option<Bound>) {
         self.timeline_limit = timeline;
     }
+
+    pub(super) fn sort(&self) -> &[String] {
+        &self.sort
+    }
+
+    pub(super) fn set_sort(&mut self, sort: Vec<String>) {
+        self.sort = sort;
+    }
 }
 
 impl StickyData for SlidingSyncListStickyParameters {
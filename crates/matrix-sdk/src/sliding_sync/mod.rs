@@ -33,8 +33,9 @@ use std::{
 };
 
 use async_stream::stream;
+use eyeball::Observable;
 use futures_core::stream::Stream;
-use matrix_sdk_common::{ring_buffer::RingBuffer, timer};
+use matrix_sdk_common::{ring_buffer::RingBuffer, sleep::sleep, timer};
 use ruma::{
     api::client::{
         error::ErrorKind,
@@ -59,6 +60,85 @@ use self::{
 };
 use crate::{config::RequestConfig, Client, Result};
 
+/// The number of times the sync loop tries to recover from an expired session
+/// before giving up and returning the error.
+const MAXIMUM_RECOVERY_ATTEMPTS: u32 = 3;
+
+/// The delay before the first recovery attempt. It doubles with every
+/// consecutive attempt.
+const INITIAL_RECOVERY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// An extension of the Sliding Sync protocol that can be enabled or disabled
+/// on a [`SlidingSync`] instance.
+///
+/// See [`SlidingSyncBuilder::with_extension`] and
+/// [`SlidingSync::set_extension_enabled`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SlidingSyncExtension {
+    /// The to-device extension, to receive to-device events.
+    ///
+    /// The token of the last received batch is persisted in the crypto store,
+    /// so that receiving to-device events resumes where it left off after a
+    /// restart.
+    ToDevice,
+
+    /// The end-to-end encryption extension, to receive device list changes
+    /// and one-time key counts.
+    E2ee,
+
+    /// The account data extension, to receive global and room account data.
+    AccountData,
+
+    /// The receipts extension, to receive read receipts.
+    Receipts,
+
+    /// The typing extension, to receive typing notifications.
+    Typing,
+}
+
+/// Expand to a borrow of the `enabled` field of the given extension in an
+/// [`ExtensionsConfig`], with the given kind of borrow, i.e. `&` or `&mut`.
+macro_rules! extension_enabled {
+    ($extension:expr, [$($borrow:tt)+] $config:expr) => {
+        match $extension {
+            SlidingSyncExtension::ToDevice => $($borrow)+ $config.to_device.enabled,
+            SlidingSyncExtension::E2ee => $($borrow)+ $config.e2ee.enabled,
+            SlidingSyncExtension::AccountData => $($borrow)+ $config.account_data.enabled,
+            SlidingSyncExtension::Receipts => $($borrow)+ $config.receipts.enabled,
+            SlidingSyncExtension::Typing => $($borrow)+ $config.typing.enabled,
+        }
+    };
+}
+
+impl SlidingSyncExtension {
+    /// Whether this extension is enabled in the given configuration.
+    fn is_enabled_in(self, config: &ExtensionsConfig) -> bool {
+        *extension_enabled!(self, [&] config) == Some(true)
+    }
+
+    /// Enable or disable this extension in the given configuration.
+    pub(super) fn set_enabled_in(self, config: &mut ExtensionsConfig, enabled: bool) {
+        *extension_enabled!(self, [&mut] config) = Some(enabled);
+    }
+
+    /// Restrict this extension to the rooms of the given lists in the given
+    /// configuration, or apply it to the rooms of all the lists if `lists` is
+    /// `None`.
+    ///
+    /// This has no effect for the to-device and E2EE extensions, which are not
+    /// about rooms.
+    pub(super) fn set_lists_in(self, config: &mut ExtensionsConfig, lists: Option<Vec<String>>) {
+        match self {
+            Self::ToDevice | Self::E2ee => {
+                warn!(extension = ?self, "The extension can't be restricted to some lists");
+            }
+            Self::AccountData => config.account_data.lists = lists,
+            Self::Receipts => config.receipts.lists = lists,
+            Self::Typing => config.typing.lists = lists,
+        }
+    }
+}
+
 /// The Sliding Sync instance.
 ///
 /// It is OK to clone this type as much as you need: cloning it is cheap.
@@ -123,6 +203,9 @@ pub(super) struct SlidingSyncInner {
     /// Internal channel used to pass messages between Sliding Sync and other
     /// types.
     internal_channel: Sender<SlidingSyncInternalMessage>,
+
+    /// The state of the sync loop.
+    state: StdRwLock<Observable<SlidingSyncState>>,
 }
 
 impl SlidingSync {
@@ -139,6 +222,23 @@ impl SlidingSync {
         SlidingSyncBuilder::new(id, client)
     }
 
+    /// Get the current state of the sync loop.
+    pub fn state(&self) -> SlidingSyncState {
+        self.inner.state.read().unwrap().clone()
+    }
+
+    /// Get a stream of the state updates of the sync loop.
+    ///
+    /// The first part of the returned tuple is the current state, and the
+    /// second part is the `Stream` to receive updates.
+    pub fn state_stream(&self) -> (SlidingSyncState, impl Stream<Item = SlidingSyncState>) {
+        let read_lock = self.inner.state.read().unwrap();
+        let previous_value = (*read_lock).clone();
+        let subscriber = Observable::subscribe(&read_lock);
+
+        (previous_value, subscriber)
+    }
+
     /// Subscribe to a given room.
     ///
     /// If the associated `Room` exists, it will be marked as
@@ -308,6 +408,8 @@ impl SlidingSync {
             compute_limited(&known_rooms, &mut sliding_sync_response.rooms);
         }
 
+        self.inner.client.preprocess_sliding_sync_response(&mut sliding_sync_response);
+
         // Transform a Sliding Sync Response to a `SyncResponse`.
         //
         // We may not need the `sync_response` in the future (once `SyncResponse` will
@@ -317,8 +419,16 @@ impl SlidingSync {
 
         let mut response_processor = SlidingSyncResponseProcessor::new(self.inner.client.clone());
 
+        if self.is_extension_enabled(SlidingSyncExtension::ToDevice)
+            && sliding_sync_response.extensions.to_device.is_none()
+        {
+            debug!("The to-device extension is enabled, but the response doesn't contain it");
+        }
+
+        // The to-device events and their token are handled along with the e2ee data, so
+        // that the token is persisted in the crypto store and survives restarts.
         #[cfg(feature = "e2e-encryption")]
-        if self.is_e2ee_enabled() {
+        if self.is_e2ee_enabled() || self.is_extension_enabled(SlidingSyncExtension::ToDevice) {
             response_processor.handle_encryption(&sliding_sync_response.extensions).await?
         }
 
@@ -467,12 +577,14 @@ impl SlidingSync {
 
         // Collect other data.
         let room_unsubscriptions = self.inner.room_unsubscriptions.read().unwrap().clone();
+        // Poll for longer while the app is in the background.
+        let poll_timeout = self.inner.client.sync_timeout(self.inner.poll_timeout);
 
         let mut request = assign!(v4::Request::new(), {
             conn_id: Some(self.inner.id.clone()),
             pos,
             delta_token,
-            timeout: Some(self.inner.poll_timeout),
+            timeout: Some(poll_timeout),
             lists: requests_lists,
             unsubscribe_rooms: room_unsubscriptions.iter().cloned().collect(),
         });
@@ -482,7 +594,7 @@ impl SlidingSync {
 
             sticky_params.maybe_apply(&mut request, txn_id);
 
-            sticky_params.data().extensions.to_device.enabled == Some(true)
+            SlidingSyncExtension::ToDevice.is_enabled_in(&sticky_params.data().extensions)
         };
 
         // Set the to_device token if the extension is enabled.
@@ -512,16 +624,40 @@ impl SlidingSync {
             request,
             // Configure long-polling. We need some time for the long-poll itself,
             // and extra time for the network delays.
-            RequestConfig::default().timeout(self.inner.poll_timeout + self.inner.network_timeout),
+            RequestConfig::default().timeout(poll_timeout + self.inner.network_timeout),
             room_unsubscriptions,
             position_guard,
         ))
     }
 
+    /// Is the given extension enabled for this sliding sync instance?
+    pub fn is_extension_enabled(&self, extension: SlidingSyncExtension) -> bool {
+        extension.is_enabled_in(&self.inner.sticky.read().unwrap().data().extensions)
+    }
+
+    /// Enable or disable the given extension for this sliding sync instance.
+    ///
+    /// The change is sent to the server with the next request, keeping the
+    /// rest of the configuration of the extension untouched.
+    pub fn set_extension_enabled(&self, extension: SlidingSyncExtension, enabled: bool) {
+        let mut sticky = self.inner.sticky.write().unwrap();
+        extension.set_enabled_in(&mut sticky.data_mut().extensions, enabled);
+    }
+
+    /// Restrict the given extension to the rooms of the given lists, or apply
+    /// it to the rooms of all the lists if `lists` is `None`.
+    ///
+    /// This only applies to the account data, receipts and typing extensions.
+    /// The change is sent to the server with the next request.
+    pub fn set_extension_lists(&self, extension: SlidingSyncExtension, lists: Option<Vec<String>>) {
+        let mut sticky = self.inner.sticky.write().unwrap();
+        extension.set_lists_in(&mut sticky.data_mut().extensions, lists);
+    }
+
     /// Is the e2ee extension enabled for this sliding sync instance?
     #[cfg(feature = "e2e-encryption")]
     fn is_e2ee_enabled(&self) -> bool {
-        self.inner.sticky.read().unwrap().data().extensions.e2ee.enabled == Some(true)
+        self.is_extension_enabled(SlidingSyncExtension::E2ee)
     }
 
     #[cfg(not(feature = "e2e-encryption"))]
@@ -666,6 +802,12 @@ impl SlidingSync {
     /// This function returns `Ok(…)` if everything went well, otherwise it will
     /// return `Err(…)`. An `Err` will _always_ lead to the `Stream`
     /// termination.
+    ///
+    /// If the server reports that the session has expired (`M_UNKNOWN_POS`),
+    /// the session is reset, as well as the lists and the extensions, and the
+    /// sync loop restarts automatically after a short backoff; the state is
+    /// then [`SlidingSyncState::Recovering`]. The error is only returned if
+    /// the session keeps expiring after a few attempts.
     #[allow(unknown_lints, clippy::let_with_type_underscore)] // triggered by instrument macro
     #[instrument(name = "sync_stream", skip_all, fields(conn_id = self.inner.id, with_e2ee = self.is_e2ee_enabled()))]
     pub fn sync(&self) -> impl Stream<Item = Result<UpdateSummary, crate::Error>> + '_ {
@@ -675,6 +817,10 @@ impl SlidingSync {
         let mut internal_channel_receiver = self.inner.internal_channel.subscribe();
 
         stream! {
+            // Mark the sync loop as terminated when the stream ends or is dropped.
+            let _state_guard = SyncLoopStateGuard::new(&self.inner);
+            let mut recovery_attempts = 0;
+
             loop {
                 sync_span.in_scope(|| {
                     debug!("Sync stream is running");
@@ -704,6 +850,9 @@ impl SlidingSync {
                     update_summary = self.sync_once().instrument(sync_span.clone()) => {
                         match update_summary {
                             Ok(updates) => {
+                                recovery_attempts = 0;
+                                self.inner.set_state(SlidingSyncState::Running);
+
                                 yield Ok(updates);
                             }
 
@@ -712,6 +861,26 @@ impl SlidingSync {
                                 continue;
                             }
 
+                            // The Sliding Sync session has expired. Let's reset it and restart the
+                            // sync loop, unless it keeps expiring.
+                            Err(error) if error.client_api_error_kind() == Some(&ErrorKind::UnknownPos)
+                                && recovery_attempts < MAXIMUM_RECOVERY_ATTEMPTS => {
+                                let backoff = INITIAL_RECOVERY_BACKOFF * 2u32.pow(recovery_attempts);
+                                recovery_attempts += 1;
+
+                                sync_span.in_scope(|| {
+                                    warn!(?backoff, "Session expired; restarting the sync loop");
+                                });
+
+                                self.expire_session().instrument(sync_span.clone()).await;
+                                self.inner.lists.read().await.values().for_each(|list| list.reset());
+                                self.inner.set_state(SlidingSyncState::Recovering { backoff });
+
+                                sleep(backoff).await;
+
+                                continue;
+                            }
+
                             // Here, errors we **cannot** ignore, and that must stop the sync loop.
                             Err(error) => {
                                 if error.client_api_error_kind() == Some(&ErrorKind::UnknownPos) {
@@ -778,6 +947,11 @@ impl SlidingSync {
 }
 
 impl SlidingSyncInner {
+    /// Update the state of the sync loop.
+    fn set_state(&self, state: SlidingSyncState) {
+        Observable::set_if_not_eq(&mut self.state.write().unwrap(), state);
+    }
+
     /// Send a message over the internal channel.
     #[instrument]
     fn internal_channel_send(&self, message: SlidingSyncInternalMessage) -> Result<(), Error> {
@@ -793,6 +967,44 @@ impl SlidingSyncInner {
     }
 }
 
+/// The state of the sync loop of a [`SlidingSync`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SlidingSyncState {
+    /// The sync loop isn't running, either because it hasn't been started yet
+    /// or because it has stopped.
+    #[default]
+    Terminated,
+
+    /// The sync loop is running.
+    Running,
+
+    /// The session has expired and has been reset; the sync loop will restart
+    /// after the given backoff.
+    Recovering {
+        /// The delay before the next request.
+        backoff: Duration,
+    },
+}
+
+/// Marks the sync loop as running when it's created, and as terminated when
+/// it's dropped.
+struct SyncLoopStateGuard<'a> {
+    inner: &'a SlidingSyncInner,
+}
+
+impl<'a> SyncLoopStateGuard<'a> {
+    fn new(inner: &'a SlidingSyncInner) -> Self {
+        inner.set_state(SlidingSyncState::Running);
+        Self { inner }
+    }
+}
+
+impl Drop for SyncLoopStateGuard<'_> {
+    fn drop(&mut self) {
+        self.inner.set_state(SlidingSyncState::Terminated);
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 enum SlidingSyncInternalMessage {
     /// Instruct the sync loop to stop.
@@ -998,7 +1210,10 @@ mod tests {
     };
 
     use assert_matches::assert_matches;
-    use futures_util::{future::join_all, pin_mut, StreamExt};
+    use futures_util::{
+        future::{join, join_all},
+        pin_mut, StreamExt,
+    };
     use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
     use matrix_sdk_test::async_test;
     use ruma::{
@@ -1018,8 +1233,9 @@ mod tests {
     use super::{
         compute_limited,
         sticky_parameters::{LazyTransactionId, SlidingSyncStickyManager},
-        FrozenSlidingSync, SlidingSync, SlidingSyncList, SlidingSyncListBuilder, SlidingSyncMode,
-        SlidingSyncRoom, SlidingSyncStickyParameters,
+        FrozenSlidingSync, SlidingSync, SlidingSyncExtension, SlidingSyncList,
+        SlidingSyncListBuilder, SlidingSyncMode, SlidingSyncRoom, SlidingSyncState,
+        SlidingSyncStickyParameters, INITIAL_RECOVERY_BACKOFF,
     };
     use crate::{test_utils::logged_in_client, Result};
 
@@ -1304,6 +1520,64 @@ mod tests {
         assert_eq!(request.extensions.account_data.enabled, Some(true));
     }
 
+    #[async_test]
+    async fn test_typed_extensions() -> Result<()> {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let sync = client
+            .sliding_sync("test-slidingsync")?
+            .add_list(SlidingSyncList::builder("new_list"))
+            .with_extension(SlidingSyncExtension::ToDevice)
+            .with_extension(SlidingSyncExtension::Typing)
+            .without_extension(SlidingSyncExtension::Receipts)
+            .with_extension_lists(SlidingSyncExtension::Typing, ["new_list"])
+            .with_all_extensions()
+            .build()
+            .await?;
+
+        assert!(sync.is_extension_enabled(SlidingSyncExtension::ToDevice));
+        assert!(sync.is_extension_enabled(SlidingSyncExtension::E2ee));
+        assert!(sync.is_extension_enabled(SlidingSyncExtension::AccountData));
+        assert!(sync.is_extension_enabled(SlidingSyncExtension::Typing));
+        // The receipts extension has been explicitly disabled.
+        assert!(!sync.is_extension_enabled(SlidingSyncExtension::Receipts));
+
+        let (request, _, _, _) = sync
+            .generate_sync_request(&mut LazyTransactionId::from_owned(TransactionId::new()))
+            .await?;
+
+        assert_eq!(request.extensions.to_device.enabled, Some(true));
+        assert_eq!(request.extensions.typing.enabled, Some(true));
+        assert_eq!(request.extensions.typing.lists, Some(vec!["new_list".to_owned()]));
+        assert_eq!(request.extensions.receipts.enabled, Some(false));
+        assert_eq!(request.extensions.receipts.lists, None);
+
+        // Disabling an extension at runtime invalidates the sticky parameters.
+        sync.set_extension_enabled(SlidingSyncExtension::Typing, false);
+        assert!(!sync.is_extension_enabled(SlidingSyncExtension::Typing));
+        assert!(sync.inner.sticky.read().unwrap().is_invalidated());
+
+        let (request, _, _, _) = sync
+            .generate_sync_request(&mut LazyTransactionId::from_owned(TransactionId::new()))
+            .await?;
+
+        assert_eq!(request.extensions.typing.enabled, Some(false));
+        assert_eq!(request.extensions.to_device.enabled, Some(true));
+
+        // Applying an extension to all the lists again.
+        sync.set_extension_lists(SlidingSyncExtension::Typing, None);
+        assert!(sync.inner.sticky.read().unwrap().is_invalidated());
+
+        let (request, _, _, _) = sync
+            .generate_sync_request(&mut LazyTransactionId::from_owned(TransactionId::new()))
+            .await?;
+
+        assert_eq!(request.extensions.typing.lists, None);
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_sticky_extensions_plus_since() -> Result<()> {
         let server = MockServer::start().await;
@@ -1549,7 +1823,8 @@ mod tests {
         // Stop responding with successful requests!
         //
         // When responding with M_UNKNOWN_POS, that regenerates the sticky parameters,
-        // so they're reset. It also resets the `pos`.
+        // so they're reset. It also resets the `pos`. The sync loop tries to recover a
+        // few times before giving up.
         {
             let _mock_guard = Mock::given(SlidingSyncMatcher)
                 .respond_with(ResponseTemplate::new(400).set_body_json(json!({
@@ -1578,11 +1853,103 @@ mod tests {
 
             // `sync` has been stopped.
             assert!(sync.next().await.is_none());
+            assert_eq!(sliding_sync.state(), SlidingSyncState::Terminated);
         }
 
         Ok(())
     }
 
+    #[async_test]
+    async fn test_unknown_pos_restarts_sync_loop() -> Result<()> {
+        let (server, sliding_sync) = new_sliding_sync(vec![
+            SlidingSyncList::builder("foo").sync_mode(SlidingSyncMode::new_growing(10))
+        ])
+        .await?;
+
+        assert_eq!(sliding_sync.state(), SlidingSyncState::Terminated);
+
+        let (_, state_stream) = sliding_sync.state_stream();
+        pin_mut!(state_stream);
+
+        let sync = sliding_sync.sync();
+        pin_mut!(sync);
+
+        {
+            let _mock_guard = Mock::given(SlidingSyncMatcher)
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "pos": "0",
+                    "lists": {
+                        "foo": {
+                            "count": 20,
+                            "ops": [],
+                        },
+                    },
+                    "rooms": {},
+                })))
+                .mount_as_scoped(&server)
+                .await;
+
+            assert_matches!(sync.next().await, Some(Ok(_)));
+            assert_eq!(state_stream.next().await, Some(SlidingSyncState::Running));
+            assert_eq!(sliding_sync.inner.position.lock().await.pos, Some("0".to_owned()));
+
+            // The list has grown.
+            let (request, _, _, _) =
+                sliding_sync.generate_sync_request(&mut LazyTransactionId::new()).await?;
+            assert_eq!(request.lists["foo"].ranges, vec![(uint!(0), uint!(19))]);
+        }
+
+        // The session expires once, then the server responds normally again.
+        {
+            let _expired_mock_guard = Mock::given(SlidingSyncMatcher)
+                .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                    "error": "foo",
+                    "errcode": "M_UNKNOWN_POS",
+                })))
+                .up_to_n_times(1)
+                .mount_as_scoped(&server)
+                .await;
+
+            let _mock_guard = Mock::given(SlidingSyncMatcher)
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "pos": "a",
+                    "lists": {
+                        "foo": {
+                            "count": 20,
+                            "ops": [],
+                        },
+                    },
+                    "rooms": {},
+                })))
+                .mount_as_scoped(&server)
+                .await;
+
+            // The error isn't returned, the sync loop has recovered.
+            let (next, state) = join(sync.next(), state_stream.next()).await;
+            assert_matches!(next, Some(Ok(_)));
+            assert_eq!(
+                state,
+                Some(SlidingSyncState::Recovering { backoff: INITIAL_RECOVERY_BACKOFF })
+            );
+            assert_eq!(state_stream.next().await, Some(SlidingSyncState::Running));
+            assert_eq!(sliding_sync.inner.position.lock().await.pos, Some("a".to_owned()));
+
+            // The request sent after the recovery started loading the list from the
+            // beginning again, without any `pos`.
+            let requests = server.received_requests().await.unwrap();
+            let request = requests.last().unwrap();
+            assert!(request.url.query_pairs().all(|(key, _)| key != "pos"));
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            assert_eq!(body["lists"]["foo"]["ranges"], json!([[0, 9]]));
+        }
+
+        // Dropping the stream terminates the sync loop.
+        drop(sync);
+        assert_eq!(sliding_sync.state(), SlidingSyncState::Terminated);
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_stop_sync_loop() -> Result<()> {
         let (_server, sliding_sync) = new_sliding_sync(vec![SlidingSyncList::builder("foo")
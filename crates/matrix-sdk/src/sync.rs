@@ -15,7 +15,7 @@
 //! The SDK's representation of the result of a `/sync` request.
 
 use std::{
-    collections::{btree_map, BTreeMap},
+    collections::{btree_map, BTreeMap, BTreeSet},
     fmt,
     time::Duration,
 };
@@ -24,22 +24,30 @@ use eyeball::Observable;
 pub use matrix_sdk_base::sync::*;
 use matrix_sdk_base::{
     debug::{DebugInvitedRoom, DebugListOfRawEventsNoId, DebugNotificationMap},
-    deserialized_responses::AmbiguityChanges,
+    deserialized_responses::{AmbiguityChanges, SyncTimelineEvent},
     instant::Instant,
     sync::SyncResponse as BaseSyncResponse,
 };
+use matrix_sdk_common::executor::spawn;
 use ruma::{
     api::client::{
         push::get_notifications::v3::Notification,
         sync::sync_events::{self, v3::InvitedRoom},
     },
-    events::{presence::PresenceEvent, AnyGlobalAccountDataEvent, AnyToDeviceEvent},
+    events::{
+        presence::PresenceEvent, AnyGlobalAccountDataEvent, AnySyncEphemeralRoomEvent,
+        AnySyncStateEvent, AnyToDeviceEvent, EphemeralRoomEventType, StateEventType,
+    },
     serde::Raw,
     OwnedRoomId, RoomId,
 };
 use tracing::{debug, error, warn};
 
-use crate::{event_handler::HandlerKind, Client, Result, Room};
+use crate::{
+    event_handler::HandlerKind,
+    room::{retention::RETENTION_EVENT_TYPE, search},
+    Client, Result, Room,
+};
 
 /// The processed response of a `/sync` request.
 #[derive(Clone, Default)]
@@ -123,6 +131,23 @@ pub enum RoomUpdate {
     },
 }
 
+/// An upgrade of a joined room, announced by a `m.room.tombstone` event.
+#[derive(Clone, Debug)]
+pub struct RoomUpgrade {
+    /// The ID of the room that was upgraded.
+    pub old_room_id: OwnedRoomId,
+    /// The ID of the room that replaces the old room.
+    pub new_room_id: OwnedRoomId,
+    /// The message explaining the upgrade to the users.
+    pub body: String,
+    /// The new room, if it was joined automatically.
+    ///
+    /// See [`ClientBuilder::follow_room_upgrades()`].
+    ///
+    /// [`ClientBuilder::follow_room_upgrades()`]: crate::ClientBuilder::follow_room_upgrades
+    pub new_room: Option<Room>,
+}
+
 impl fmt::Debug for RoomUpdate {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -146,10 +171,13 @@ impl fmt::Debug for RoomUpdate {
 impl Client {
     pub(crate) async fn process_sync(
         &self,
-        response: sync_events::v3::Response,
+        mut response: sync_events::v3::Response,
     ) -> Result<BaseSyncResponse> {
+        self.preprocess_sync_response(&mut response);
+        let changed_devices = response.device_lists.changed.clone();
         let response = Box::pin(self.base_client().receive_sync_response(response)).await?;
         self.handle_sync_response(&response).await?;
+        self.refresh_own_devices_if_changed(&changed_devices);
         Ok(response)
     }
 
@@ -167,7 +195,9 @@ impl Client {
         let now = Instant::now();
         self.handle_sync_events(HandlerKind::GlobalAccountData, None, account_data).await?;
         self.handle_sync_events(HandlerKind::Presence, None, presence).await?;
-        self.handle_sync_events(HandlerKind::ToDevice, None, to_device).await?;
+        self.handle_sync_to_device_events(to_device).await?;
+
+        let mut retention_changed = BTreeSet::new();
 
         for (room_id, room_info) in &rooms.join {
             if room_info.timeline.limited {
@@ -187,6 +217,37 @@ impl Client {
             let JoinedRoom { unread_notifications: _, timeline, state, account_data, ephemeral } =
                 room_info;
 
+            if contains_retention_event(state, &timeline.events) {
+                retention_changed.insert(room_id.clone());
+            }
+
+            // A tombstone in the state section was received before the events of
+            // this sync response, so only the new ones are reported.
+            if contains_tombstone(&timeline.events) {
+                self.handle_room_upgrade(&room);
+            }
+
+            if self.inner.index_encrypted_messages {
+                if let Err(e) = search::index_timeline_events(&room, &timeline.events).await {
+                    error!(?room_id, "Failed to index the timeline events: {e}");
+                }
+            }
+
+            if let Some(event_cache) = room.event_cache() {
+                if let Err(e) = event_cache.handle_sync_timeline(timeline).await {
+                    error!(?room_id, "Failed to add the timeline events to the cache: {e}");
+                }
+
+                // The read receipts of the response were already stored, so
+                // they are taken into account. The counts can only change with
+                // new events or new receipts.
+                if !timeline.events.is_empty() || contains_receipt(ephemeral) {
+                    if let Err(e) = event_cache.update_unread_counts().await {
+                        error!(?room_id, "Failed to update the unread counts: {e}");
+                    }
+                }
+            }
+
             let room = Some(&room);
             self.handle_sync_events(HandlerKind::RoomAccountData, room, account_data).await?;
             self.handle_sync_state_events(room, state).await?;
@@ -213,6 +274,22 @@ impl Client {
 
             let LeftRoom { timeline, state, account_data } = room_info;
 
+            if contains_retention_event(state, &timeline.events) {
+                retention_changed.insert(room_id.clone());
+            }
+
+            if self.inner.index_encrypted_messages {
+                if let Err(e) = search::index_timeline_events(&room, &timeline.events).await {
+                    error!(?room_id, "Failed to index the timeline events: {e}");
+                }
+            }
+
+            if let Some(event_cache) = room.event_cache() {
+                if let Err(e) = event_cache.handle_sync_timeline(timeline).await {
+                    error!(?room_id, "Failed to add the timeline events to the cache: {e}");
+                }
+            }
+
             let room = Some(&room);
             self.handle_sync_events(HandlerKind::RoomAccountData, room, account_data).await?;
             self.handle_sync_state_events(room, state).await?;
@@ -236,6 +313,8 @@ impl Client {
 
         debug!("Ran event handlers in {:?}", now.elapsed());
 
+        self.update_history_cutoffs(&retention_changed).await;
+
         let now = Instant::now();
 
         // Construct notification event handler futures
@@ -264,6 +343,68 @@ impl Client {
         Ok(())
     }
 
+    /// Report the upgrade of the given room, and join the new room if
+    /// [`ClientBuilder::follow_room_upgrades()`] was used.
+    ///
+    /// [`ClientBuilder::follow_room_upgrades()`]: crate::ClientBuilder::follow_room_upgrades
+    fn handle_room_upgrade(&self, room: &Room) {
+        let Some(tombstone) = room.tombstone() else {
+            return;
+        };
+
+        let room = room.clone();
+        let follow = self.inner.follow_room_upgrades;
+        let sender = self.inner.room_upgrade_sender.clone();
+
+        // Don't block the sync processing while joining the new room.
+        spawn(async move {
+            let new_room = if follow {
+                match room.follow_upgrade().await {
+                    Ok(new_room) => new_room,
+                    Err(error) => {
+                        warn!(room_id = ?room.room_id(), ?error, "Couldn't join the upgraded room");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            _ = sender.send(RoomUpgrade {
+                old_room_id: room.room_id().to_owned(),
+                new_room_id: tombstone.replacement_room,
+                body: tombstone.body,
+                new_room,
+            });
+        });
+    }
+
+    /// Compute the history cutoffs of the rooms again, since time has passed
+    /// since the previous sync, and remove the events that are older from the
+    /// event cache.
+    ///
+    /// The retention policy of the rooms in `retention_changed` is loaded
+    /// again.
+    async fn update_history_cutoffs(&self, retention_changed: &BTreeSet<OwnedRoomId>) {
+        for room in self.joined_rooms().into_iter().chain(self.left_rooms()) {
+            let policy_changed = retention_changed.contains(room.room_id());
+
+            let cutoff = match self.inner.history_cutoffs.update(&room, policy_changed).await {
+                Ok(cutoff) => cutoff,
+                Err(error) => {
+                    warn!(room_id = ?room.room_id(), "Failed to update the history cutoff: {error}");
+                    continue;
+                }
+            };
+
+            if let Some((cutoff, event_cache)) = cutoff.zip(room.event_cache()) {
+                if let Err(error) = event_cache.apply_history_cutoff(cutoff).await {
+                    warn!(room_id = ?room.room_id(), "Failed to apply the history cutoff: {error}");
+                }
+            }
+        }
+    }
+
     fn send_room_update(&self, room_id: &RoomId, make_msg: impl FnOnce() -> RoomUpdate) {
         if let btree_map::Entry::Occupied(entry) =
             self.inner.room_update_channels.lock().unwrap().entry(room_id.to_owned())
@@ -325,3 +466,32 @@ impl Client {
         }
     }
 }
+
+/// Whether the given timeline events contain a `m.room.tombstone` event.
+fn contains_tombstone(timeline: &[SyncTimelineEvent]) -> bool {
+    timeline.iter().any(|event| has_type(&event.event, &StateEventType::RoomTombstone))
+}
+
+/// Whether the given ephemeral events contain a `m.receipt` event.
+fn contains_receipt(ephemeral: &[Raw<AnySyncEphemeralRoomEvent>]) -> bool {
+    ephemeral.iter().any(|event| {
+        event.get_field::<EphemeralRoomEventType>("type").ok().flatten()
+            == Some(EphemeralRoomEventType::Receipt)
+    })
+}
+
+/// Whether the given state or timeline events contain a `m.room.retention`
+/// event.
+fn contains_retention_event(
+    state: &[Raw<AnySyncStateEvent>],
+    timeline: &[SyncTimelineEvent],
+) -> bool {
+    let event_type = StateEventType::from(RETENTION_EVENT_TYPE);
+    state.iter().any(|event| has_type(event, &event_type))
+        || timeline.iter().any(|event| has_type(&event.event, &event_type))
+}
+
+/// Whether the given event has the given type.
+fn has_type<T>(event: &Raw<T>, event_type: &StateEventType) -> bool {
+    event.get_field::<StateEventType>("type").ok().flatten().as_ref() == Some(event_type)
+}
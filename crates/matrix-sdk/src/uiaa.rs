@@ -0,0 +1,255 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers to go through the [User-Interactive Authentication API][uiaa].
+//!
+//! [uiaa]: https://spec.matrix.org/v1.2/client-server-api/#user-interactive-authentication-api
+
+use std::{collections::BTreeMap, fmt, future::Future};
+
+use ruma::{
+    api::client::{
+        error::StandardErrorBody,
+        uiaa::{
+            AuthData, AuthFlow, AuthType, Dummy, Password, RegistrationToken, Token, UiaaInfo,
+            UserIdentifier,
+        },
+    },
+    OwnedUserId, TransactionId,
+};
+use serde_json::Value as JsonValue;
+
+use crate::Result;
+
+#[cfg(not(target_arch = "wasm32"))]
+type ResendRequest<T> = Box<
+    dyn Fn(Option<AuthData>) -> futures_core::future::BoxFuture<'static, Result<T>> + Send + Sync,
+>;
+#[cfg(target_arch = "wasm32")]
+type ResendRequest<T> =
+    Box<dyn Fn(Option<AuthData>) -> futures_core::future::LocalBoxFuture<'static, Result<T>>>;
+
+/// The result of sending a request that might require User-Interactive
+/// Authentication.
+#[derive(Debug)]
+pub enum UiaaOutcome<T> {
+    /// The request succeeded without requiring authentication.
+    Done(T),
+    /// The homeserver requires authentication, which can be provided with the
+    /// [`UiaaFlow`].
+    AuthRequired(UiaaFlow<T>),
+}
+
+/// A request that requires to go through the [User-Interactive Authentication
+/// API][uiaa].
+///
+/// It exposes the stages offered by the homeserver and allows to submit the
+/// data of the stages one by one, until the request succeeds.
+///
+/// Get it with [`Client::send_with_uiaa()`].
+///
+/// [uiaa]: https://spec.matrix.org/v1.2/client-server-api/#user-interactive-authentication-api
+/// [`Client::send_with_uiaa()`]: crate::Client::send_with_uiaa
+pub struct UiaaFlow<T> {
+    /// The latest information returned by the homeserver.
+    info: UiaaInfo,
+    /// The ID of the user, to identify them in the password stage.
+    user_id: Option<OwnedUserId>,
+    /// Send the request again with the given authentication data.
+    send: ResendRequest<T>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl<T> fmt::Debug for UiaaFlow<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UiaaFlow")
+            .field("info", &self.info)
+            .field("user_id", &self.user_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> UiaaFlow<T> {
+    /// Send the request a first time without authentication data.
+    ///
+    /// If the homeserver responds with a UIAA error, a flow is returned to go
+    /// through the authentication. Any other error is returned as is.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn start<F, Fut>(
+        user_id: Option<OwnedUserId>,
+        send: F,
+    ) -> Result<UiaaOutcome<T>>
+    where
+        F: Fn(Option<AuthData>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+    {
+        Self::from_boxed(user_id, Box::new(move |auth_data| Box::pin(send(auth_data)))).await
+    }
+
+    /// Send the request a first time without authentication data.
+    ///
+    /// If the homeserver responds with a UIAA error, a flow is returned to go
+    /// through the authentication. Any other error is returned as is.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) async fn start<F, Fut>(
+        user_id: Option<OwnedUserId>,
+        send: F,
+    ) -> Result<UiaaOutcome<T>>
+    where
+        F: Fn(Option<AuthData>) -> Fut + 'static,
+        Fut: Future<Output = Result<T>> + 'static,
+    {
+        Self::from_boxed(user_id, Box::new(move |auth_data| Box::pin(send(auth_data)))).await
+    }
+
+    async fn from_boxed(
+        user_id: Option<OwnedUserId>,
+        send: ResendRequest<T>,
+    ) -> Result<UiaaOutcome<T>> {
+        let error = match send(None).await {
+            Ok(response) => return Ok(UiaaOutcome::Done(response)),
+            Err(error) => error,
+        };
+
+        match error.as_uiaa_response() {
+            Some(info) => {
+                let info = info.clone();
+                Ok(UiaaOutcome::AuthRequired(Self { info, user_id, send }))
+            }
+            None => Err(error),
+        }
+    }
+
+    /// The flows offered by the homeserver to authenticate.
+    ///
+    /// Each flow is a list of stages that need to be completed in order.
+    pub fn flows(&self) -> &[AuthFlow] {
+        &self.info.flows
+    }
+
+    /// The stages that were already completed.
+    pub fn completed(&self) -> &[AuthType] {
+        &self.info.completed
+    }
+
+    /// The stages that can be submitted next, in the flows that are compatible
+    /// with the stages that were already completed.
+    pub fn next_stages(&self) -> Vec<AuthType> {
+        let completed = self.completed();
+        let mut stages = Vec::new();
+
+        for flow in &self.info.flows {
+            if !flow.stages.starts_with(completed) {
+                continue;
+            }
+
+            if let Some(stage) = flow.stages.get(completed.len()) {
+                if !stages.contains(stage) {
+                    stages.push(stage.clone());
+                }
+            }
+        }
+
+        stages
+    }
+
+    /// The parameters provided by the homeserver for the given stage, if any.
+    ///
+    /// For example, the `m.login.terms` stage has the policies that the user
+    /// needs to accept.
+    pub fn params(&self, stage: &AuthType) -> Option<JsonValue> {
+        let mut params =
+            serde_json::from_str::<BTreeMap<String, JsonValue>>(self.info.params.get()).ok()?;
+        params.remove(stage.as_ref())
+    }
+
+    /// The ID of the UIAA session, to set in the authentication data passed to
+    /// [`UiaaFlow::submit()`].
+    pub fn session(&self) -> Option<&str> {
+        self.info.session.as_deref()
+    }
+
+    /// The error returned by the homeserver for the last submitted stage, if
+    /// it failed.
+    pub fn auth_error(&self) -> Option<&StandardErrorBody> {
+        self.info.auth_error.as_ref()
+    }
+
+    /// Submit the data of a stage.
+    ///
+    /// The session of the authentication data should be set to
+    /// [`UiaaFlow::session()`].
+    ///
+    /// Returns the response of the request if the authentication is complete,
+    /// or `None` if other stages need to be completed, or if the stage failed.
+    /// In the latter case, [`UiaaFlow::auth_error()`] contains the error
+    /// returned by the homeserver, and the stage can be submitted again.
+    pub async fn submit(&mut self, auth_data: AuthData) -> Result<Option<T>> {
+        match (self.send)(Some(auth_data)).await {
+            Ok(response) => Ok(Some(response)),
+            Err(error) => match error.as_uiaa_response() {
+                Some(info) => {
+                    self.info = info.clone();
+                    Ok(None)
+                }
+                None => Err(error),
+            },
+        }
+    }
+
+    /// Submit the `m.login.password` stage with the password of the user.
+    ///
+    /// See [`UiaaFlow::submit()`] for the returned value.
+    pub async fn submit_password(&mut self, password: &str) -> Result<Option<T>> {
+        let user_id = self.user_id.as_ref().ok_or(crate::Error::AuthenticationRequired)?;
+        let mut password = Password::new(
+            UserIdentifier::UserIdOrLocalpart(user_id.to_string()),
+            password.to_owned(),
+        );
+        password.session = self.info.session.clone();
+
+        self.submit(AuthData::Password(password)).await
+    }
+
+    /// Submit the `m.login.token` stage with a login token.
+    ///
+    /// See [`UiaaFlow::submit()`] for the returned value.
+    pub async fn submit_token(&mut self, token: &str) -> Result<Option<T>> {
+        let mut token = Token::new(token.to_owned(), TransactionId::new());
+        token.session = self.info.session.clone();
+
+        self.submit(AuthData::Token(token)).await
+    }
+
+    /// Submit the `m.login.registration_token` stage with a registration
+    /// token.
+    ///
+    /// See [`UiaaFlow::submit()`] for the returned value.
+    pub async fn submit_registration_token(&mut self, token: &str) -> Result<Option<T>> {
+        let mut token = RegistrationToken::new(token.to_owned());
+        token.session = self.info.session.clone();
+
+        self.submit(AuthData::RegistrationToken(token)).await
+    }
+
+    /// Submit the `m.login.dummy` stage.
+    ///
+    /// See [`UiaaFlow::submit()`] for the returned value.
+    pub async fn submit_dummy(&mut self) -> Result<Option<T>> {
+        let mut dummy = Dummy::new();
+        dummy.session = self.info.session.clone();
+
+        self.submit(AuthData::Dummy(dummy)).await
+    }
+}
@@ -0,0 +1,258 @@
+//! Handling of the messages received from a widget.
+
+use ruma::{
+    events::{AnyTimelineEvent, MessageLikeEventType, StateEventType},
+    serde::Raw,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, warn};
+
+use super::{PendingRequest, WidgetClient};
+use crate::widget::{
+    messages::{
+        from_widget, openid::OpenIdResponse, to_widget, Api, CapabilitiesResponse, Message,
+        ReadEventsRequest, ReadEventsResponse, SendEventRequest, SendEventResponse,
+        StateKeySelector, SUPPORTED_API_VERSIONS,
+    },
+    CapabilitiesProvider, Permissions,
+};
+
+/// The default number of message-like events returned by `read_events`.
+const DEFAULT_READ_EVENTS_LIMIT: u32 = 50;
+
+impl<T: CapabilitiesProvider> WidgetClient<T> {
+    /// Handle a message received from the widget.
+    pub(super) async fn handle_message(&mut self, message: Message) {
+        if message.widget_id != self.info.id {
+            warn!(widget_id = message.widget_id, "Received a message for another widget");
+            return;
+        }
+
+        match (message.api, message.response.is_some()) {
+            (Api::FromWidget, false) => self.handle_request(message).await,
+            (Api::ToWidget, true) => self.handle_response(message).await,
+            (api, _) => {
+                warn!(?api, action = message.action, "Received an unexpected message");
+            }
+        }
+    }
+
+    /// Handle a request from the widget.
+    async fn handle_request(&mut self, request: Message) {
+        debug!(action = request.action, "Handling a request from the widget");
+
+        let response = match request.action.as_str() {
+            from_widget::SUPPORTED_API_VERSIONS => {
+                request.into_response(json!({ "supported_versions": SUPPORTED_API_VERSIONS }))
+            }
+            from_widget::CONTENT_LOADED => {
+                self.send(request.into_response(json!({}))).await;
+
+                if self.info.init_on_load && self.permissions.is_none() {
+                    self.request_capabilities().await;
+                }
+                return;
+            }
+            from_widget::SEND_EVENT => self.send_event(request).await,
+            from_widget::READ_EVENTS => self.read_events(request).await,
+            from_widget::GET_OPENID => {
+                let response = self.get_openid().await;
+                request.into_response(json!(response))
+            }
+            from_widget::REQUEST_CAPABILITIES => {
+                let capabilities =
+                    serde_json::from_value::<CapabilitiesResponse>(request.data.clone())
+                        .map(|data| data.capabilities)
+                        .unwrap_or_default();
+                self.send(request.into_response(json!({}))).await;
+
+                self.negotiate_permissions(capabilities).await;
+                return;
+            }
+            _ => request.into_error_response("Unsupported action"),
+        };
+
+        self.send(response).await;
+    }
+
+    /// Handle a response of the widget to one of our requests.
+    async fn handle_response(&mut self, response: Message) {
+        let Some(pending) = self.take_pending_request(&response.request_id) else {
+            warn!(request_id = response.request_id, "Received a response to an unknown request");
+            return;
+        };
+
+        match pending {
+            PendingRequest::Capabilities => {
+                let capabilities = response
+                    .response
+                    .and_then(|r| serde_json::from_value::<CapabilitiesResponse>(r).ok())
+                    .map(|r| r.capabilities)
+                    .unwrap_or_default();
+                self.negotiate_permissions(capabilities).await;
+            }
+            PendingRequest::NotifyCapabilities | PendingRequest::SendEvent => {}
+        }
+    }
+
+    /// Forward an event received in the room to the widget, if it is allowed
+    /// to read it.
+    pub(super) async fn handle_matrix_event(&mut self, event: Raw<AnyTimelineEvent>) {
+        let Some(permissions) = &self.permissions else {
+            return;
+        };
+        if !can_read(permissions, &event) {
+            return;
+        }
+
+        let request =
+            Message::to_widget_request(&self.info.id, to_widget::SEND_EVENT, json!(event));
+        self.send_request(request, PendingRequest::SendEvent).await;
+    }
+
+    /// Handle a `get_openid` request, if the embedder allows the widget to get
+    /// OpenID credentials.
+    ///
+    /// The embedder is only asked once, and its decision is remembered until
+    /// the widget is disconnected.
+    async fn get_openid(&mut self) -> OpenIdResponse {
+        let allowed = match self.openid_allowed {
+            Some(allowed) => allowed,
+            None => {
+                let allowed = self.capabilities_provider.acquire_openid(&self.info.id).await;
+                self.openid_allowed = Some(allowed);
+                allowed
+            }
+        };
+
+        if !allowed {
+            debug!("The widget is not allowed to get OpenID credentials");
+            return OpenIdResponse::Blocked;
+        }
+
+        match self.driver.openid_credentials(&self.info.id).await {
+            Ok(credentials) => OpenIdResponse::Allowed(credentials),
+            Err(e) => {
+                warn!("Failed to get OpenID credentials for the widget: {e}");
+                OpenIdResponse::Blocked
+            }
+        }
+    }
+
+    /// Handle a `send_event` request, if the widget has the permission to send
+    /// the event.
+    async fn send_event(&self, request: Message) -> Message {
+        let data = match serde_json::from_value::<SendEventRequest>(request.data.clone()) {
+            Ok(data) => data,
+            Err(e) => return request.into_error_response(format!("Invalid request: {e}")),
+        };
+
+        let Some(permissions) = &self.permissions else {
+            return request.into_error_response("Capabilities have not been negotiated yet");
+        };
+
+        let allowed = match &data.state_key {
+            Some(state_key) => permissions
+                .can_send_state(&StateEventType::from(data.event_type.as_str()), state_key),
+            None => {
+                let msgtype = data.content.get("msgtype").and_then(|m| m.as_str());
+                permissions.can_send_message_like(
+                    &MessageLikeEventType::from(data.event_type.as_str()),
+                    msgtype,
+                )
+            }
+        };
+
+        if !allowed {
+            return request.into_error_response(format!(
+                "Missing capability to send an event of type {}",
+                data.event_type
+            ));
+        }
+
+        let SendEventRequest { event_type, state_key, content } = data;
+        match self.driver.send_event(&event_type, state_key.as_deref(), content).await {
+            Ok(event_id) => {
+                let room_id = self.driver.room_id().to_owned();
+                request.into_response(json!(SendEventResponse { room_id, event_id }))
+            }
+            Err(e) => request.into_error_response(format!("Failed to send the event: {e}")),
+        }
+    }
+
+    /// Handle a `read_events` request, returning only the events that the
+    /// widget has the permission to read.
+    async fn read_events(&self, request: Message) -> Message {
+        let data = match serde_json::from_value::<ReadEventsRequest>(request.data.clone()) {
+            Ok(data) => data,
+            Err(e) => return request.into_error_response(format!("Invalid request: {e}")),
+        };
+
+        let Some(permissions) = &self.permissions else {
+            return request.into_error_response("Capabilities have not been negotiated yet");
+        };
+
+        if !permissions.can_read_type(&data.event_type, data.state_key.is_some()) {
+            return request.into_error_response(format!(
+                "Missing capability to read events of type {}",
+                data.event_type
+            ));
+        }
+
+        let event_type = data.event_type.as_str();
+        let events = match &data.state_key {
+            Some(selector) => {
+                let state_key = match selector {
+                    StateKeySelector::Key(state_key) => Some(state_key.as_str()),
+                    StateKeySelector::Any(_) => None,
+                };
+                self.driver.read_state_events(event_type.into(), state_key).await
+            }
+            None => {
+                let limit = data.limit.unwrap_or(DEFAULT_READ_EVENTS_LIMIT);
+                self.driver.read_message_like_events(event_type.into(), limit).await
+            }
+        };
+
+        match events {
+            Ok(events) => {
+                let events = events.into_iter().filter(|ev| can_read(permissions, ev)).collect();
+                request.into_response(json!(ReadEventsResponse { events }))
+            }
+            Err(e) => request.into_error_response(format!("Failed to read the events: {e}")),
+        }
+    }
+}
+
+/// The fields of an event that are needed to check the read permissions.
+#[derive(Deserialize)]
+struct EventMetadata {
+    #[serde(rename = "type")]
+    event_type: String,
+    state_key: Option<String>,
+    #[serde(default)]
+    content: EventContentMetadata,
+}
+
+#[derive(Default, Deserialize)]
+struct EventContentMetadata {
+    msgtype: Option<String>,
+}
+
+/// Whether the given permissions allow to read the given event.
+fn can_read(permissions: &Permissions, event: &Raw<AnyTimelineEvent>) -> bool {
+    let Ok(metadata) = event.deserialize_as::<EventMetadata>() else {
+        return false;
+    };
+
+    match &metadata.state_key {
+        Some(state_key) => {
+            permissions.can_read_state(&metadata.event_type.as_str().into(), state_key)
+        }
+        None => permissions.can_read_message_like(
+            &metadata.event_type.as_str().into(),
+            metadata.content.msgtype.as_deref(),
+        ),
+    }
+}
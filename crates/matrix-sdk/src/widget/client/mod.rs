@@ -0,0 +1,626 @@
+//! The client side of the widget API: a state machine that handles the
+//! messages sent by a widget, and the requests sent to it.
+
+use std::{collections::HashMap, time::Duration};
+
+use async_channel::{Receiver, Sender};
+use futures_util::{future, pin_mut, stream, StreamExt};
+use matrix_sdk_common::instant::Instant;
+use ruma::{events::AnyTimelineEvent, serde::Raw};
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+use super::{
+    matrix::MatrixDriver,
+    messages::{to_widget, Message, NotifyCapabilitiesRequest},
+    CapabilitiesProvider, CapabilitiesRequest, Info, Permissions,
+};
+
+mod handler;
+
+/// How long a request sent to the widget waits for a response, before it is
+/// forgotten.
+const PENDING_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request sent to the widget, that is waiting for a response.
+#[derive(Debug)]
+enum PendingRequest {
+    /// The widget was asked for the capabilities it wants.
+    Capabilities,
+    /// The widget was notified of the approved capabilities.
+    NotifyCapabilities,
+    /// An event of the room was forwarded to the widget.
+    SendEvent,
+}
+
+/// Something that the widget API has to react to.
+enum Incoming {
+    /// A raw message from the widget.
+    Widget(String),
+    /// An event received in the room.
+    Matrix(Raw<AnyTimelineEvent>),
+    /// The widget is disconnected.
+    Disconnected,
+}
+
+/// The state of the widget API for a given widget.
+pub(super) struct WidgetClient<T> {
+    info: Info,
+    to_widget: Sender<String>,
+    driver: MatrixDriver,
+    capabilities_provider: T,
+    /// The permissions approved for the widget, once they were negotiated.
+    permissions: Option<Permissions>,
+    /// Whether the widget is allowed to get OpenID credentials, once the
+    /// embedder was asked.
+    openid_allowed: Option<bool>,
+    /// The requests sent to the widget that didn't get a response yet, by
+    /// request ID, with the time when they were sent.
+    pending_requests: HashMap<String, (PendingRequest, Instant)>,
+}
+
+impl<T: CapabilitiesProvider> WidgetClient<T> {
+    pub(super) fn new(
+        info: Info,
+        to_widget: Sender<String>,
+        driver: MatrixDriver,
+        capabilities_provider: T,
+    ) -> Self {
+        Self {
+            info,
+            to_widget,
+            driver,
+            capabilities_provider,
+            permissions: None,
+            openid_allowed: None,
+            pending_requests: HashMap::new(),
+        }
+    }
+
+    /// Handle the messages from the widget, and forward it the events of the
+    /// room, until it is disconnected.
+    pub(super) async fn run(mut self, from_widget: Receiver<String>) {
+        if !self.info.init_on_load {
+            self.request_capabilities().await;
+        }
+
+        let (events, _drop_guard) = self.driver.events();
+        let incoming = stream::select(
+            from_widget
+                .map(Incoming::Widget)
+                .chain(stream::once(future::ready(Incoming::Disconnected))),
+            events.map(Incoming::Matrix),
+        );
+        pin_mut!(incoming);
+
+        while let Some(incoming) = incoming.next().await {
+            match incoming {
+                Incoming::Widget(raw) => match serde_json::from_str::<Message>(&raw) {
+                    Ok(message) => self.handle_message(message).await,
+                    Err(e) => warn!("Failed to deserialize a message from the widget: {e}"),
+                },
+                Incoming::Matrix(event) => self.handle_matrix_event(event).await,
+                Incoming::Disconnected => break,
+            }
+
+            if self.to_widget.is_closed() {
+                break;
+            }
+        }
+
+        info!(widget_id = self.info.id, "Widget disconnected");
+    }
+
+    /// Send a message to the widget.
+    async fn send(&self, message: Message) {
+        let raw = match serde_json::to_string(&message) {
+            Ok(raw) => raw,
+            Err(e) => {
+                warn!("Failed to serialize a message to the widget: {e}");
+                return;
+            }
+        };
+
+        if self.to_widget.send(raw).await.is_err() {
+            debug!("Can't send a message to the widget, it is disconnected");
+        }
+    }
+
+    /// Send a request to the widget, and remember it to handle its response.
+    ///
+    /// The requests that didn't get a response after
+    /// `PENDING_REQUEST_TIMEOUT` are forgotten, so a widget that never
+    /// responds doesn't make them pile up.
+    async fn send_request(&mut self, message: Message, pending: PendingRequest) {
+        self.pending_requests.retain(|request_id, (_, sent_at)| {
+            let expired = sent_at.elapsed() > PENDING_REQUEST_TIMEOUT;
+            if expired {
+                debug!(request_id, "The widget didn't respond to a request in time");
+            }
+            !expired
+        });
+
+        self.pending_requests.insert(message.request_id.clone(), (pending, Instant::now()));
+        self.send(message).await;
+    }
+
+    /// Take the request sent to the widget with the given ID, if it is still
+    /// waiting for a response.
+    fn take_pending_request(&mut self, request_id: &str) -> Option<PendingRequest> {
+        let (pending, sent_at) = self.pending_requests.remove(request_id)?;
+        (sent_at.elapsed() <= PENDING_REQUEST_TIMEOUT).then_some(pending)
+    }
+
+    /// Ask the widget for the capabilities it wants.
+    async fn request_capabilities(&mut self) {
+        let request = Message::to_widget_request(&self.info.id, to_widget::CAPABILITIES, json!({}));
+        self.send_request(request, PendingRequest::Capabilities).await;
+    }
+
+    /// Ask the embedder which of the requested permissions to approve, and
+    /// notify the widget of the result.
+    ///
+    /// The embedder can't approve more than what was requested. If permissions
+    /// were already approved for the widget, this is a renegotiation and the
+    /// newly approved permissions are added to them.
+    async fn negotiate_permissions(&mut self, requested: Vec<String>) {
+        let requested_permissions = Permissions::from_capabilities(&requested);
+        let request = CapabilitiesRequest {
+            widget_id: self.info.id.clone(),
+            requested: requested_permissions.clone(),
+            approved: self.permissions.clone(),
+        };
+        let mut approved = self.capabilities_provider.acquire_capabilities(request).await;
+        approved.restrict_to(&requested_permissions);
+
+        let approved = match self.permissions.take() {
+            Some(mut permissions) => {
+                permissions.extend(approved);
+                permissions
+            }
+            None => approved,
+        };
+
+        let data = NotifyCapabilitiesRequest { requested, approved: approved.to_capabilities() };
+        self.permissions = Some(approved);
+
+        let request =
+            Message::to_widget_request(&self.info.id, to_widget::NOTIFY_CAPABILITIES, json!(data));
+        self.send_request(request, PendingRequest::NotifyCapabilities).await;
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::time::Duration;
+
+    use async_channel::{Receiver, Sender};
+    use async_trait::async_trait;
+    use matrix_sdk_common::instant::Instant;
+    use matrix_sdk_test::{async_test, test_json, JoinedRoomBuilder, SyncResponseBuilder};
+    use ruma::TransactionId;
+    use serde_json::{json, Value as JsonValue};
+    use tokio::time::timeout;
+    use wiremock::{
+        http::Method,
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{PendingRequest, WidgetClient, PENDING_REQUEST_TIMEOUT};
+    use crate::{
+        room::Room,
+        test_utils::logged_in_client,
+        widget::{
+            matrix::{MatrixDriver, OPENID_TOKEN_REFRESH_MARGIN},
+            messages::{to_widget, Api, Message},
+            CapabilitiesProvider, CapabilitiesRequest, Info, Permissions, PermissionsProvider,
+        },
+    };
+
+    const WIDGET_ID: &str = "test-widget";
+
+    /// Approves the permissions requested by the widget, and its requests of
+    /// OpenID credentials.
+    struct TestProvider;
+
+    #[async_trait]
+    impl CapabilitiesProvider for TestProvider {
+        async fn acquire_capabilities(&self, request: CapabilitiesRequest) -> Permissions {
+            request.requested
+        }
+
+        async fn acquire_openid(&self, _widget_id: &str) -> bool {
+            true
+        }
+    }
+
+    /// Approves the permissions requested by the widget, and nothing else.
+    struct TestPermissionsProvider;
+
+    #[async_trait]
+    impl PermissionsProvider for TestPermissionsProvider {
+        async fn acquire_permissions(&self, permissions: Permissions) -> Permissions {
+            permissions
+        }
+    }
+
+    /// Approves the permissions requested by the widget, and more.
+    struct OverGrantingProvider;
+
+    #[async_trait]
+    impl CapabilitiesProvider for OverGrantingProvider {
+        async fn acquire_capabilities(&self, request: CapabilitiesRequest) -> Permissions {
+            let mut approved = request.requested;
+            approved.extend(Permissions::from_capabilities(&[
+                "org.matrix.msc2762.send.event:m.reaction".to_owned(),
+                "org.matrix.msc2762.send.state_event:m.room.name".to_owned(),
+            ]));
+            approved
+        }
+    }
+
+    /// The side of the widget, exchanging messages with a running
+    /// [`WidgetClient`].
+    struct TestWidget {
+        to_client: Sender<String>,
+        from_client: Receiver<String>,
+    }
+
+    impl TestWidget {
+        fn start(room: Room, provider: impl CapabilitiesProvider) -> Self {
+            let (to_client, from_widget) = async_channel::unbounded();
+            let (to_widget, from_client) = async_channel::unbounded();
+
+            let info = Info { id: WIDGET_ID.to_owned(), init_on_load: false };
+            let client = WidgetClient::new(info, to_widget, MatrixDriver::new(room), provider);
+            tokio::spawn(client.run(from_widget));
+
+            Self { to_client, from_client }
+        }
+
+        /// Receive the next message from the client.
+        async fn recv(&self) -> Message {
+            let raw = timeout(Duration::from_secs(1), self.from_client.recv())
+                .await
+                .expect("the client should send a message")
+                .unwrap();
+            serde_json::from_str(&raw).unwrap()
+        }
+
+        async fn send(&self, message: JsonValue) {
+            self.to_client.send(message.to_string()).await.unwrap();
+        }
+
+        /// Send a request to the client, and return the data of its response.
+        async fn request(&self, action: &str, data: JsonValue) -> JsonValue {
+            let request_id = TransactionId::new().to_string();
+            self.send(json!({
+                "api": "fromWidget",
+                "widgetId": WIDGET_ID,
+                "requestId": request_id,
+                "action": action,
+                "data": data,
+            }))
+            .await;
+
+            let response = self.recv().await;
+            assert_eq!(response.api, Api::FromWidget);
+            assert_eq!(response.request_id, request_id);
+            response.response.expect("the client should respond to the request")
+        }
+
+        /// Respond to a request of the client.
+        async fn respond(&self, request: Message, response: JsonValue) {
+            self.send(json!(request.into_response(response))).await;
+        }
+
+        /// Answer the capabilities request of the client with the given
+        /// capabilities, and return the approved ones.
+        async fn negotiate(&self, capabilities: &[&str]) -> JsonValue {
+            let request = self.recv().await;
+            assert_eq!(request.action, to_widget::CAPABILITIES);
+            self.respond(request, json!({ "capabilities": capabilities })).await;
+
+            let notify = self.recv().await;
+            assert_eq!(notify.action, to_widget::NOTIFY_CAPABILITIES);
+            let approved = notify.data["approved"].clone();
+            self.respond(notify, json!({})).await;
+
+            approved
+        }
+    }
+
+    async fn joined_room(server: &MockServer) -> Room {
+        let client = logged_in_client(Some(server.uri())).await;
+        let response = SyncResponseBuilder::default()
+            .add_joined_room(JoinedRoomBuilder::default())
+            .build_sync_response();
+        client.base_client().receive_sync_response(response).await.unwrap();
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.*room.*encryption.?"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "Event not found.",
+            })))
+            .mount(server)
+            .await;
+
+        client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap()
+    }
+
+    fn mock_openid_token(access_token: &str, expires_in: Duration) -> Mock {
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/r0/user/.*/openid/request_token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "matrix_server_name": "example.com",
+                "expires_in": expires_in.as_secs(),
+            })))
+    }
+
+    #[async_test]
+    async fn test_send_event() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m.room.message/.*"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "event_id": "$h29iv0s8:example.com",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let widget = TestWidget::start(room, TestProvider);
+        let approved = widget.negotiate(&["org.matrix.msc2762.send.event:m.room.message"]).await;
+        assert_eq!(approved, json!(["org.matrix.msc2762.send.event:m.room.message"]));
+
+        let response = widget
+            .request(
+                "send_event",
+                json!({
+                    "type": "m.room.message",
+                    "content": { "msgtype": "m.text", "body": "Hello" },
+                }),
+            )
+            .await;
+        assert_eq!(
+            response,
+            json!({
+                "room_id": *test_json::DEFAULT_SYNC_ROOM_ID,
+                "event_id": "$h29iv0s8:example.com",
+            })
+        );
+    }
+
+    #[async_test]
+    async fn test_send_event_without_capability() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "event_id": "$h29iv0s8:example.com",
+            })))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let widget = TestWidget::start(room, TestProvider);
+        widget.negotiate(&["org.matrix.msc2762.receive.event:m.room.message"]).await;
+
+        let response = widget
+            .request(
+                "send_event",
+                json!({
+                    "type": "m.room.message",
+                    "content": { "msgtype": "m.text", "body": "Hello" },
+                }),
+            )
+            .await;
+        let message = response["error"]["message"].as_str().unwrap();
+        assert!(message.contains("Missing capability"), "unexpected error: {message}");
+    }
+
+    #[async_test]
+    async fn test_over_granted_permissions() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "event_id": "$h29iv0s8:example.com",
+            })))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        // Only the requested permissions are approved.
+        let widget = TestWidget::start(room, OverGrantingProvider);
+        let approved = widget.negotiate(&["org.matrix.msc2762.receive.event:m.room.message"]).await;
+        assert_eq!(approved, json!(["org.matrix.msc2762.receive.event:m.room.message"]));
+
+        let response = widget
+            .request(
+                "send_event",
+                json!({
+                    "type": "m.reaction",
+                    "content": {
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": "$h29iv0s8:example.com",
+                            "key": "👍",
+                        },
+                    },
+                }),
+            )
+            .await;
+        let message = response["error"]["message"].as_str().unwrap();
+        assert!(message.contains("Missing capability"), "unexpected error: {message}");
+    }
+
+    #[async_test]
+    async fn test_renegotiate_permissions() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        let widget = TestWidget::start(room, OverGrantingProvider);
+        widget.negotiate(&["org.matrix.msc2762.receive.event:m.room.message"]).await;
+
+        let response = widget
+            .request(
+                "org.matrix.msc2974.request_capabilities",
+                json!({ "capabilities": ["org.matrix.msc2762.send.event:m.reaction"] }),
+            )
+            .await;
+        assert_eq!(response, json!({}));
+
+        // The newly approved permissions are added to the previous ones, but
+        // nothing more.
+        let notify = widget.recv().await;
+        assert_eq!(notify.action, to_widget::NOTIFY_CAPABILITIES);
+        assert_eq!(notify.data["requested"], json!(["org.matrix.msc2762.send.event:m.reaction"]));
+        assert_eq!(
+            notify.data["approved"],
+            json!([
+                "org.matrix.msc2762.send.event:m.reaction",
+                "org.matrix.msc2762.receive.event:m.room.message",
+            ])
+        );
+    }
+
+    #[async_test]
+    async fn test_get_openid() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        // The token is only requested once.
+        mock_openid_token("token", Duration::from_secs(3600)).expect(1).mount(&server).await;
+
+        let widget = TestWidget::start(room, TestProvider);
+        widget.negotiate(&[]).await;
+
+        for _ in 0..2 {
+            let response = widget.request("get_openid", json!({})).await;
+            assert_eq!(response["state"], "allowed");
+            assert_eq!(response["access_token"], "token");
+            assert_eq!(response["token_type"], "Bearer");
+            assert_eq!(response["matrix_server_name"], "example.com");
+            assert!(response["expires_in"].as_u64().unwrap() <= 3600);
+        }
+    }
+
+    #[async_test]
+    async fn test_get_openid_blocked() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        mock_openid_token("token", Duration::from_secs(3600)).expect(0).mount(&server).await;
+
+        let widget = TestWidget::start(room, TestPermissionsProvider);
+        widget.negotiate(&[]).await;
+
+        let response = widget.request("get_openid", json!({})).await;
+        assert_eq!(response, json!({ "state": "blocked" }));
+    }
+
+    #[async_test]
+    async fn test_get_openid_refresh() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        // The first token is about to expire, so it is refreshed right away.
+        mock_openid_token("first", OPENID_TOKEN_REFRESH_MARGIN + Duration::from_secs(1))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        mock_openid_token("second", Duration::from_secs(3600)).expect(1).mount(&server).await;
+
+        let widget = TestWidget::start(room, TestProvider);
+        widget.negotiate(&[]).await;
+
+        let response = widget.request("get_openid", json!({})).await;
+        assert_eq!(response["access_token"], "first");
+
+        // The token is refreshed without waiting for the widget to ask for it.
+        timeout(Duration::from_secs(5), async {
+            // The requests of the tokens are the only POST requests.
+            while server
+                .received_requests()
+                .await
+                .unwrap()
+                .iter()
+                .filter(|r| r.method == Method::Post)
+                .count()
+                < 2
+            {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("the token should be refreshed");
+
+        let response = widget.request("get_openid", json!({})).await;
+        assert_eq!(response["access_token"], "second");
+    }
+
+    #[async_test]
+    async fn test_response_to_unknown_request() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        let widget = TestWidget::start(room, TestProvider);
+        widget.negotiate(&[]).await;
+
+        // A response to a request that the client never sent is ignored…
+        widget
+            .send(json!({
+                "api": "toWidget",
+                "widgetId": WIDGET_ID,
+                "requestId": "unknown",
+                "action": "capabilities",
+                "data": {},
+                "response": { "capabilities": ["org.matrix.msc2762.send.event:m.reaction"] },
+            }))
+            .await;
+
+        // … so the next message of the client is the response to the next
+        // request.
+        let response = widget.request("supported_api_versions", json!({})).await;
+        assert!(response["supported_versions"].is_array());
+        assert!(widget.from_client.is_empty());
+    }
+
+    #[async_test]
+    async fn test_pending_requests_expire() {
+        let server = MockServer::start().await;
+        let room = joined_room(&server).await;
+
+        let (to_widget, from_client) = async_channel::unbounded();
+        let info = Info { id: WIDGET_ID.to_owned(), init_on_load: false };
+        let mut client = WidgetClient::new(info, to_widget, MatrixDriver::new(room), TestProvider);
+
+        let expired_at =
+            Instant::now().checked_sub(PENDING_REQUEST_TIMEOUT + Duration::from_secs(1)).unwrap();
+
+        // A response that arrives too late is ignored.
+        client.pending_requests.insert("late".to_owned(), (PendingRequest::SendEvent, expired_at));
+        assert!(client.take_pending_request("late").is_none());
+
+        // A request that never gets a response is forgotten when the next
+        // request is sent.
+        client.pending_requests.insert("lost".to_owned(), (PendingRequest::SendEvent, expired_at));
+        client.request_capabilities().await;
+        assert_eq!(client.pending_requests.len(), 1);
+
+        let request: Message = serde_json::from_str(&from_client.recv().await.unwrap()).unwrap();
+        assert!(client.take_pending_request(&request.request_id).is_some());
+    }
+}
@@ -0,0 +1,97 @@
+//! Helpers to embed [Element Call] as a widget.
+//!
+//! [Element Call]: https://github.com/element-hq/element-call
+
+use ruma::{
+    events::{MessageLikeEventType, StateEventType},
+    DeviceId, UserId,
+};
+
+use super::{EventFilter, Permissions, WidgetSettings};
+
+/// The type of the state events describing the membership of a call.
+const CALL_MEMBER_EVENT_TYPE: &str = "org.matrix.msc3401.call.member";
+/// The type of the events used to share the encryption keys of a call.
+const CALL_ENCRYPTION_KEYS_EVENT_TYPE: &str = "io.element.call.encryption_keys";
+
+/// Options to embed Element Call as a widget.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ElementCallOptions {
+    /// The URL of the Element Call instance, like
+    /// `https://call.element.io`.
+    pub element_call_url: String,
+    /// The identifier of the widget.
+    pub widget_id: String,
+    /// Whether Element Call should hide its header, when the embedding
+    /// application provides its own.
+    pub hide_header: bool,
+    /// Whether Element Call should be preloaded, without joining the call.
+    pub preload: bool,
+    /// Whether Element Call should skip its lobby and join the call
+    /// directly.
+    pub skip_lobby: bool,
+}
+
+impl ElementCallOptions {
+    /// Create options for the given Element Call instance and widget ID.
+    pub fn new(element_call_url: String, widget_id: String) -> Self {
+        Self { element_call_url, widget_id, hide_header: false, preload: false, skip_lobby: false }
+    }
+}
+
+impl WidgetSettings {
+    /// Create the settings of an Element Call widget.
+    ///
+    /// The generated URL passes the user, room and device of the client, as
+    /// well as its language and theme, to Element Call.
+    pub fn new_element_call(options: ElementCallOptions) -> Self {
+        let ElementCallOptions { element_call_url, widget_id, hide_header, preload, skip_lobby } =
+            options;
+
+        let raw_url = format!(
+            "{}/room#?embed=true\
+             &widgetId=$org.matrix.msc2873.widget_id\
+             &userId=$matrix_user_id\
+             &deviceId=$org.matrix.msc2873.matrix_device_id\
+             &roomId=$matrix_room_id\
+             &lang=$org.matrix.msc2873.client_language\
+             &theme=$org.matrix.msc2873.client_theme\
+             &hideHeader={hide_header}\
+             &preload={preload}\
+             &skipLobby={skip_lobby}",
+            element_call_url.trim_end_matches('/'),
+        );
+
+        // Element Call only starts using the widget API once it is loaded.
+        Self::new(widget_id, true, raw_url)
+    }
+}
+
+/// The permissions that Element Call needs, to approve them without prompting
+/// the user.
+pub fn element_call_permissions(own_user_id: &UserId, own_device_id: &DeviceId) -> Permissions {
+    let call_member = StateEventType::from(CALL_MEMBER_EVENT_TYPE);
+    let encryption_keys = MessageLikeEventType::from(CALL_ENCRYPTION_KEYS_EVENT_TYPE);
+
+    Permissions {
+        read: vec![
+            EventFilter::State { event_type: call_member.clone(), state_key: None },
+            EventFilter::State { event_type: StateEventType::RoomMember, state_key: None },
+            EventFilter::State { event_type: StateEventType::RoomEncryption, state_key: None },
+            EventFilter::State { event_type: StateEventType::RoomCreate, state_key: None },
+            EventFilter::MessageLike { event_type: encryption_keys.clone(), msgtype: None },
+        ],
+        send: vec![
+            EventFilter::State {
+                event_type: call_member.clone(),
+                state_key: Some(own_user_id.to_string()),
+            },
+            EventFilter::State {
+                event_type: call_member,
+                state_key: Some(format!("_{own_user_id}_{own_device_id}")),
+            },
+            EventFilter::MessageLike { event_type: encryption_keys, msgtype: None },
+        ],
+    }
+}
@@ -0,0 +1,303 @@
+//! The access of a widget to the Matrix room it is attached to.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_channel::Receiver;
+use futures_util::future::{AbortHandle, Abortable};
+use matrix_sdk_base::deserialized_responses::RawAnySyncOrStrippedState;
+use matrix_sdk_common::{executor::spawn, instant::Instant, sleep::sleep};
+use ruma::{
+    api::client::account::request_openid_token,
+    events::{AnySyncTimelineEvent, AnyTimelineEvent, MessageLikeEventType, StateEventType},
+    serde::Raw,
+    OwnedEventId, OwnedServerName, OwnedUserId, RoomId, UInt, UserId,
+};
+use serde_json::{value::RawValue as RawJsonValue, Value as JsonValue};
+use tracing::warn;
+
+use super::messages::openid::OpenIdCredentials;
+use crate::{
+    event_handler::EventHandlerDropGuard,
+    room::{MessagesOptions, Room},
+    Client, Result,
+};
+
+/// How long before its expiration an OpenID token is refreshed.
+pub(super) const OPENID_TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// How long to wait before trying again to refresh an OpenID token, after it
+/// failed.
+const OPENID_TOKEN_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The OpenID tokens that were requested for widgets, by widget ID.
+///
+/// It is shared by all the widgets of a client, so a widget that is reloaded
+/// gets the same token until it expires.
+pub(crate) type OpenIdTokenCache = Mutex<HashMap<String, CachedOpenIdToken>>;
+
+/// Performs the Matrix requests on behalf of a widget, in a given room.
+#[derive(Debug)]
+pub(crate) struct MatrixDriver {
+    room: Room,
+    /// The task refreshing the OpenID token of the widget before it expires,
+    /// once the widget got one.
+    openid_refresh: Mutex<Option<AbortHandle>>,
+}
+
+impl MatrixDriver {
+    pub(crate) fn new(room: Room) -> Self {
+        Self { room, openid_refresh: Mutex::new(None) }
+    }
+
+    pub(crate) fn room_id(&self) -> &RoomId {
+        self.room.room_id()
+    }
+
+    /// Send an event to the room, as a state event if a `state_key` is given,
+    /// or as a message-like event otherwise.
+    pub(crate) async fn send_event(
+        &self,
+        event_type: &str,
+        state_key: Option<&str>,
+        content: JsonValue,
+    ) -> Result<OwnedEventId> {
+        let event_id = match state_key {
+            Some(state_key) => {
+                self.room.send_state_event_raw(content, event_type, state_key).await?.event_id
+            }
+            None => self.room.send_raw(content, event_type, None).await?.event_id,
+        };
+
+        Ok(event_id)
+    }
+
+    /// Read the latest message-like events of the given type in the room.
+    ///
+    /// In encrypted rooms, the homeserver only knows that events are
+    /// encrypted, so they are requested too and filtered once decrypted.
+    pub(crate) async fn read_message_like_events(
+        &self,
+        event_type: MessageLikeEventType,
+        limit: u32,
+    ) -> Result<Vec<Raw<AnyTimelineEvent>>> {
+        let mut options = MessagesOptions::backward();
+        options.limit = UInt::from(limit);
+        options.filter.types =
+            Some(vec![event_type.to_string(), MessageLikeEventType::RoomEncrypted.to_string()]);
+
+        let messages = self.room.messages(options).await?;
+        Ok(messages
+            .chunk
+            .into_iter()
+            .filter(|ev| {
+                ev.event.get_field::<MessageLikeEventType>("type").ok().flatten().as_ref()
+                    == Some(&event_type)
+            })
+            .map(|ev| ev.event.cast())
+            .collect())
+    }
+
+    /// Read the state events of the given type in the room, optionally
+    /// restricted to a single state key.
+    pub(crate) async fn read_state_events(
+        &self,
+        event_type: StateEventType,
+        state_key: Option<&str>,
+    ) -> Result<Vec<Raw<AnyTimelineEvent>>> {
+        let events = match state_key {
+            Some(state_key) => {
+                self.room.get_state_event(event_type, state_key).await?.into_iter().collect()
+            }
+            None => self.room.get_state_events(event_type).await?,
+        };
+
+        Ok(events
+            .into_iter()
+            .filter_map(|ev| match ev {
+                RawAnySyncOrStrippedState::Sync(ev) => {
+                    attach_room_id(ev.cast_ref(), self.room_id()).ok()
+                }
+                RawAnySyncOrStrippedState::Stripped(_) => None,
+            })
+            .collect())
+    }
+
+    /// Get OpenID credentials for the given widget.
+    ///
+    /// A token that was previously requested for the same widget is reused
+    /// until it is about to expire. From then on, the token is refreshed
+    /// shortly before it expires, until the widget is disconnected.
+    pub(crate) async fn openid_credentials(&self, widget_id: &str) -> Result<OpenIdCredentials> {
+        let client = self.room.client();
+        let cached = client
+            .inner
+            .widget_openid_tokens
+            .lock()
+            .unwrap()
+            .get(widget_id)
+            .and_then(|t| t.credentials());
+
+        let credentials = match cached {
+            Some(credentials) => credentials,
+            None => {
+                let credentials =
+                    fetch_openid_token(&client, self.room.own_user_id(), widget_id).await?;
+
+                // The previous refresh task gave up, start a new one.
+                if let Some(abort_handle) = self.openid_refresh.lock().unwrap().take() {
+                    abort_handle.abort();
+                }
+
+                credentials
+            }
+        };
+
+        let mut openid_refresh = self.openid_refresh.lock().unwrap();
+        if openid_refresh.is_none() {
+            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+            let refresh = refresh_openid_token(
+                client,
+                self.room.own_user_id().to_owned(),
+                widget_id.to_owned(),
+            );
+            spawn(Abortable::new(refresh, abort_registration));
+            *openid_refresh = Some(abort_handle);
+        }
+
+        Ok(credentials)
+    }
+
+    /// Start receiving the events of the room as they arrive in sync.
+    ///
+    /// The events are received until the returned drop guard is dropped.
+    pub(crate) fn events(&self) -> (Receiver<Raw<AnyTimelineEvent>>, EventHandlerDropGuard) {
+        let (tx, rx) = async_channel::unbounded();
+        let room_id = self.room_id().to_owned();
+        let client = self.room.client();
+
+        let handle =
+            client.add_room_event_handler(self.room_id(), move |raw: Raw<AnySyncTimelineEvent>| {
+                let tx = tx.clone();
+                let event = attach_room_id(&raw, &room_id);
+                async move {
+                    if let Ok(event) = event {
+                        let _ = tx.send(event).await;
+                    }
+                }
+            });
+
+        (rx, client.event_handler_drop_guard(handle))
+    }
+}
+
+impl Drop for MatrixDriver {
+    fn drop(&mut self) {
+        if let Some(abort_handle) = self.openid_refresh.get_mut().unwrap().take() {
+            abort_handle.abort();
+        }
+    }
+}
+
+/// Request a new OpenID token for the given widget, and cache it.
+async fn fetch_openid_token(
+    client: &Client,
+    user_id: &UserId,
+    widget_id: &str,
+) -> Result<OpenIdCredentials> {
+    let request = request_openid_token::v3::Request::new(user_id.to_owned());
+    let response = client.send(request, None).await?;
+
+    let token = CachedOpenIdToken {
+        access_token: response.access_token,
+        token_type: response.token_type.to_string(),
+        matrix_server_name: response.matrix_server_name,
+        expires_at: Instant::now() + response.expires_in,
+    };
+    let credentials = token.credentials_unchecked();
+    client.inner.widget_openid_tokens.lock().unwrap().insert(widget_id.to_owned(), token);
+
+    Ok(credentials)
+}
+
+/// Refresh the cached OpenID token of the given widget shortly before it
+/// expires, over and over.
+///
+/// If the token can't be refreshed before it expires, it is removed from the
+/// cache and this stops.
+async fn refresh_openid_token(client: Client, user_id: OwnedUserId, widget_id: String) {
+    loop {
+        let expires_in = client
+            .inner
+            .widget_openid_tokens
+            .lock()
+            .unwrap()
+            .get(&widget_id)
+            .map(|t| t.expires_at.saturating_duration_since(Instant::now()));
+        let Some(expires_in) = expires_in else {
+            return;
+        };
+
+        sleep(expires_in.saturating_sub(OPENID_TOKEN_REFRESH_MARGIN)).await;
+
+        if let Err(error) = fetch_openid_token(&client, &user_id, &widget_id).await {
+            warn!(widget_id, "Failed to refresh the OpenID token of the widget: {error}");
+
+            let expired = {
+                let mut cache = client.inner.widget_openid_tokens.lock().unwrap();
+                let expired =
+                    cache.get(&widget_id).map_or(true, |t| t.expires_at <= Instant::now());
+                if expired {
+                    cache.remove(&widget_id);
+                }
+                expired
+            };
+            if expired {
+                return;
+            }
+
+            sleep(OPENID_TOKEN_RETRY_DELAY).await;
+        }
+    }
+}
+
+/// An OpenID token that was requested for a widget.
+#[derive(Debug)]
+pub(crate) struct CachedOpenIdToken {
+    access_token: String,
+    token_type: String,
+    matrix_server_name: OwnedServerName,
+    expires_at: Instant,
+}
+
+impl CachedOpenIdToken {
+    /// The credentials of this token, if it is not about to expire.
+    fn credentials(&self) -> Option<OpenIdCredentials> {
+        let expires_in = self.expires_at.checked_duration_since(Instant::now())?;
+        (expires_in > OPENID_TOKEN_REFRESH_MARGIN).then(|| self.credentials_unchecked())
+    }
+
+    fn credentials_unchecked(&self) -> OpenIdCredentials {
+        let expires_in = self.expires_at.saturating_duration_since(Instant::now());
+        OpenIdCredentials {
+            access_token: self.access_token.clone(),
+            expires_in: expires_in.as_secs(),
+            matrix_server_name: self.matrix_server_name.clone(),
+            token_type: self.token_type.clone(),
+        }
+    }
+}
+
+/// Add the `room_id` field to an event received in sync, as expected by a
+/// widget.
+fn attach_room_id(
+    raw: &Raw<AnySyncTimelineEvent>,
+    room_id: &RoomId,
+) -> serde_json::Result<Raw<AnyTimelineEvent>> {
+    let mut event = raw.deserialize_as::<BTreeMap<String, Box<RawJsonValue>>>()?;
+    event.insert("room_id".to_owned(), serde_json::value::to_raw_value(room_id)?);
+    Ok(Raw::new(&event)?.cast())
+}
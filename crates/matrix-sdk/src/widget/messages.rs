@@ -0,0 +1,165 @@
+//! Messages exchanged with a widget, as defined by the widget API.
+
+use ruma::{events::AnyTimelineEvent, serde::Raw, OwnedEventId, OwnedRoomId, TransactionId};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+/// Actions of requests sent by a widget.
+pub(super) mod from_widget {
+    pub(crate) const SUPPORTED_API_VERSIONS: &str = "supported_api_versions";
+    pub(crate) const CONTENT_LOADED: &str = "content_loaded";
+    pub(crate) const SEND_EVENT: &str = "send_event";
+    pub(crate) const READ_EVENTS: &str = "org.matrix.msc2876.read_events";
+    pub(crate) const REQUEST_CAPABILITIES: &str = "org.matrix.msc2974.request_capabilities";
+    pub(crate) const GET_OPENID: &str = "get_openid";
+}
+
+/// Actions of requests sent to a widget.
+pub(super) mod to_widget {
+    pub(crate) const CAPABILITIES: &str = "capabilities";
+    pub(crate) const NOTIFY_CAPABILITIES: &str = "notify_capabilities";
+    pub(crate) const SEND_EVENT: &str = "send_event";
+}
+
+/// The versions of the widget API supported by the client.
+pub(super) const SUPPORTED_API_VERSIONS: &[&str] =
+    &["0.0.1", "0.0.2", "org.matrix.msc2762", "org.matrix.msc2871", "org.matrix.msc2974"];
+
+/// The direction of a request, from the point of view of the widget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(super) enum Api {
+    /// A request sent by the widget to the client.
+    #[serde(rename = "fromWidget")]
+    FromWidget,
+    /// A request sent by the client to the widget.
+    #[serde(rename = "toWidget")]
+    ToWidget,
+}
+
+/// A message exchanged with a widget.
+///
+/// A response is the request it answers to, with the `response` field set.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Message {
+    pub(super) api: Api,
+    pub(super) widget_id: String,
+    pub(super) request_id: String,
+    pub(super) action: String,
+    #[serde(default)]
+    pub(super) data: JsonValue,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) response: Option<JsonValue>,
+}
+
+impl Message {
+    /// Create a new request to send to the widget.
+    pub(super) fn to_widget_request(widget_id: &str, action: &str, data: JsonValue) -> Self {
+        Self {
+            api: Api::ToWidget,
+            widget_id: widget_id.to_owned(),
+            request_id: TransactionId::new().to_string(),
+            action: action.to_owned(),
+            data,
+            response: None,
+        }
+    }
+
+    /// Turn this request into a response with the given data.
+    pub(super) fn into_response(mut self, response: JsonValue) -> Self {
+        self.response = Some(response);
+        self
+    }
+
+    /// Turn this request into an error response with the given message.
+    pub(super) fn into_error_response(self, message: impl Into<String>) -> Self {
+        let message = message.into();
+        self.into_response(json!({ "error": { "message": message } }))
+    }
+}
+
+/// The data of a `send_event` request.
+#[derive(Debug, Deserialize)]
+pub(super) struct SendEventRequest {
+    #[serde(rename = "type")]
+    pub(super) event_type: String,
+    pub(super) state_key: Option<String>,
+    #[serde(default)]
+    pub(super) content: JsonValue,
+}
+
+/// The data of a `send_event` response.
+#[derive(Debug, Serialize)]
+pub(super) struct SendEventResponse {
+    pub(super) room_id: OwnedRoomId,
+    pub(super) event_id: OwnedEventId,
+}
+
+/// The data of a `read_events` request.
+#[derive(Debug, Deserialize)]
+pub(super) struct ReadEventsRequest {
+    #[serde(rename = "type")]
+    pub(super) event_type: String,
+    /// Set for state events, either to a state key or to `true` for any state
+    /// key.
+    pub(super) state_key: Option<StateKeySelector>,
+    pub(super) limit: Option<u32>,
+}
+
+/// The state keys of the state events to read.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum StateKeySelector {
+    /// A single state key.
+    Key(String),
+    /// Any state key.
+    Any(bool),
+}
+
+/// The data of a `read_events` response.
+#[derive(Debug, Serialize)]
+pub(super) struct ReadEventsResponse {
+    pub(super) events: Vec<Raw<AnyTimelineEvent>>,
+}
+
+/// The data of a `capabilities` response, or of a `request_capabilities`
+/// request.
+#[derive(Debug, Deserialize)]
+pub(super) struct CapabilitiesResponse {
+    #[serde(default)]
+    pub(super) capabilities: Vec<String>,
+}
+
+/// The data of a `notify_capabilities` request.
+#[derive(Debug, Serialize)]
+pub(super) struct NotifyCapabilitiesRequest {
+    pub(super) requested: Vec<String>,
+    pub(super) approved: Vec<String>,
+}
+
+/// Types of the OpenID flow, that allows a widget to verify the identity of
+/// the user.
+pub(super) mod openid {
+    use ruma::OwnedServerName;
+    use serde::Serialize;
+
+    /// The data of a `get_openid` response.
+    #[derive(Debug, Serialize)]
+    #[serde(tag = "state", rename_all = "lowercase")]
+    pub(crate) enum OpenIdResponse {
+        /// The widget is allowed to get OpenID credentials.
+        Allowed(OpenIdCredentials),
+        /// The widget is not allowed to get OpenID credentials.
+        Blocked,
+    }
+
+    /// OpenID credentials for a widget.
+    #[derive(Clone, Debug, Serialize)]
+    pub(crate) struct OpenIdCredentials {
+        pub(crate) access_token: String,
+        /// The number of seconds before the token expires.
+        pub(crate) expires_in: u64,
+        pub(crate) matrix_server_name: OwnedServerName,
+        pub(crate) token_type: String,
+    }
+}
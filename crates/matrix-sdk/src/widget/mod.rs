@@ -2,11 +2,23 @@
 
 use async_channel::{Receiver, Sender};
 
+pub(crate) use self::matrix::OpenIdTokenCache;
+use self::{client::WidgetClient, matrix::MatrixDriver};
 use crate::room::Room as JoinedRoom;
 
+mod client;
+pub mod element_call;
+mod matrix;
+mod messages;
 mod permissions;
+mod settings;
 
-pub use self::permissions::{EventFilter, Permissions, PermissionsProvider};
+pub use self::{
+    permissions::{
+        CapabilitiesProvider, CapabilitiesRequest, EventFilter, Permissions, PermissionsProvider,
+    },
+    settings::{ClientProperties, WidgetSettings},
+};
 
 /// Describes a widget.
 #[derive(Debug)]
@@ -41,12 +53,14 @@ pub struct Comm {
 /// Starts a client widget API state machine for a given `widget` in a given
 /// joined `room`. The function returns once the widget is disconnected or any
 /// terminal error occurs.
-///
-/// Not implemented yet, currently always panics.
 pub async fn run_widget_api(
-    _room: JoinedRoom,
-    _widget: Widget,
-    _permissions_provider: impl PermissionsProvider,
+    room: JoinedRoom,
+    widget: Widget,
+    capabilities_provider: impl CapabilitiesProvider,
 ) -> Result<(), ()> {
-    Err(())
+    let Widget { info, comm } = widget;
+    let client = WidgetClient::new(info, comm.to, MatrixDriver::new(room), capabilities_provider);
+    client.run(comm.from).await;
+
+    Ok(())
 }
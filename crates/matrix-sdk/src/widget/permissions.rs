@@ -5,6 +5,11 @@ use async_trait::async_trait;
 
 use crate::ruma::events::{MessageLikeEventType, StateEventType};
 
+const SEND_EVENT: &str = "org.matrix.msc2762.send.event:";
+const READ_EVENT: &str = "org.matrix.msc2762.receive.event:";
+const SEND_STATE: &str = "org.matrix.msc2762.send.state_event:";
+const READ_STATE: &str = "org.matrix.msc2762.receive.state_event:";
+
 /// Must be implemented by a component that provides functionality of deciding
 /// whether a widget is allowed to use certain capabilities (typically by
 /// providing a prompt to the user).
@@ -16,8 +21,52 @@ pub trait PermissionsProvider: Send + Sync + 'static {
     async fn acquire_permissions(&self, permissions: Permissions) -> Permissions;
 }
 
+/// A request of capabilities by a widget, to be approved by the embedding
+/// application.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CapabilitiesRequest {
+    /// The identifier of the widget.
+    pub widget_id: String,
+    /// The permissions requested by the widget.
+    pub requested: Permissions,
+    /// The permissions that were already approved for the widget, if it is
+    /// renegotiating its capabilities.
+    pub approved: Option<Permissions>,
+}
+
+/// Must be implemented by a component that decides which of the capabilities
+/// requested by a widget are approved, including when the widget renegotiates
+/// its capabilities.
+///
+/// It is implemented for any [`PermissionsProvider`], denying the requests of
+/// OpenID credentials.
+#[async_trait]
+pub trait CapabilitiesProvider: Send + Sync + 'static {
+    /// Receives a request for capabilities and returns the permissions that
+    /// are approved for the widget, usually a subset of the requested ones.
+    async fn acquire_capabilities(&self, request: CapabilitiesRequest) -> Permissions;
+
+    /// Receives a request of the widget with the given ID to get OpenID
+    /// credentials, to verify the identity of the user, and returns whether
+    /// it is allowed (usually by prompting the user).
+    ///
+    /// By default, the request is denied.
+    async fn acquire_openid(&self, widget_id: &str) -> bool {
+        let _ = widget_id;
+        false
+    }
+}
+
+#[async_trait]
+impl<T: PermissionsProvider> CapabilitiesProvider for T {
+    async fn acquire_capabilities(&self, request: CapabilitiesRequest) -> Permissions {
+        self.acquire_permissions(request.requested).await
+    }
+}
+
 /// Permissions that a widget can request from a client.
-#[derive(Debug)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Permissions {
     /// Types of the messages that a widget wants to be able to fetch.
     pub read: Vec<EventFilter>,
@@ -25,8 +74,121 @@ pub struct Permissions {
     pub send: Vec<EventFilter>,
 }
 
+impl Permissions {
+    /// Parse the permissions from the capabilities requested by a widget.
+    ///
+    /// Capabilities that are not related to reading or sending events are
+    /// ignored.
+    pub(crate) fn from_capabilities(capabilities: &[String]) -> Self {
+        let mut permissions = Self::default();
+
+        for capability in capabilities {
+            if let Some(filter) = capability.strip_prefix(SEND_EVENT) {
+                permissions.send.push(EventFilter::message_like_from_capability(filter));
+            } else if let Some(filter) = capability.strip_prefix(READ_EVENT) {
+                permissions.read.push(EventFilter::message_like_from_capability(filter));
+            } else if let Some(filter) = capability.strip_prefix(SEND_STATE) {
+                permissions.send.push(EventFilter::state_from_capability(filter));
+            } else if let Some(filter) = capability.strip_prefix(READ_STATE) {
+                permissions.read.push(EventFilter::state_from_capability(filter));
+            }
+        }
+
+        permissions
+    }
+
+    /// Convert the permissions to the capabilities to send to a widget.
+    pub(crate) fn to_capabilities(&self) -> Vec<String> {
+        let send = self.send.iter().map(|filter| match filter {
+            EventFilter::MessageLike { .. } => {
+                format!("{SEND_EVENT}{}", filter.capability_suffix())
+            }
+            EventFilter::State { .. } => format!("{SEND_STATE}{}", filter.capability_suffix()),
+        });
+        let read = self.read.iter().map(|filter| match filter {
+            EventFilter::MessageLike { .. } => {
+                format!("{READ_EVENT}{}", filter.capability_suffix())
+            }
+            EventFilter::State { .. } => format!("{READ_STATE}{}", filter.capability_suffix()),
+        });
+
+        send.chain(read).collect()
+    }
+
+    /// Add the given permissions to these ones, ignoring the ones that are
+    /// already present.
+    pub(crate) fn extend(&mut self, other: Permissions) {
+        for filter in other.read {
+            if !self.read.contains(&filter) {
+                self.read.push(filter);
+            }
+        }
+        for filter in other.send {
+            if !self.send.contains(&filter) {
+                self.send.push(filter);
+            }
+        }
+    }
+
+    /// Remove the permissions that are not covered by the given ones.
+    ///
+    /// A permission is covered if it is one of the given ones, or a narrower
+    /// version of one of them, e.g. `m.room.message#m.text` is covered by
+    /// `m.room.message`.
+    pub(crate) fn restrict_to(&mut self, allowed: &Permissions) {
+        self.read.retain(|filter| allowed.read.iter().any(|allowed| filter.is_within(allowed)));
+        self.send.retain(|filter| allowed.send.iter().any(|allowed| filter.is_within(allowed)));
+    }
+
+    /// Whether the widget is allowed to send a message-like event of the given
+    /// type, with the given `msgtype` for `m.room.message` events.
+    pub(crate) fn can_send_message_like(
+        &self,
+        event_type: &MessageLikeEventType,
+        msgtype: Option<&str>,
+    ) -> bool {
+        self.send.iter().any(|filter| filter.matches_message_like(event_type, msgtype))
+    }
+
+    /// Whether the widget is allowed to send a state event of the given type
+    /// and state key.
+    pub(crate) fn can_send_state(&self, event_type: &StateEventType, state_key: &str) -> bool {
+        self.send.iter().any(|filter| filter.matches_state(event_type, state_key))
+    }
+
+    /// Whether the widget is allowed to read at least some events of the given
+    /// type, state events if `is_state` is set, or message-like events
+    /// otherwise.
+    pub(crate) fn can_read_type(&self, event_type: &str, is_state: bool) -> bool {
+        self.read.iter().any(|filter| match filter {
+            EventFilter::MessageLike { event_type: filter_type, .. } => {
+                !is_state && filter_type.to_string() == event_type
+            }
+            EventFilter::State { event_type: filter_type, .. } => {
+                is_state && filter_type.to_string() == event_type
+            }
+        })
+    }
+
+    /// Whether the widget is allowed to read a message-like event of the given
+    /// type, with the given `msgtype` for `m.room.message` events.
+    pub(crate) fn can_read_message_like(
+        &self,
+        event_type: &MessageLikeEventType,
+        msgtype: Option<&str>,
+    ) -> bool {
+        self.read.iter().any(|filter| filter.matches_message_like(event_type, msgtype))
+    }
+
+    /// Whether the widget is allowed to read a state event of the given type
+    /// and state key.
+    pub(crate) fn can_read_state(&self, event_type: &StateEventType, state_key: &str) -> bool {
+        self.read.iter().any(|filter| filter.matches_state(event_type, state_key))
+    }
+}
+
 /// Different kinds of filters that could be applied to the timeline events.
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum EventFilter {
     /// Message-like events.
     MessageLike {
@@ -44,3 +206,184 @@ pub enum EventFilter {
         state_key: Option<String>,
     },
 }
+
+impl EventFilter {
+    fn message_like_from_capability(filter: &str) -> Self {
+        match filter.split_once('#') {
+            Some((event_type, msgtype)) => Self::MessageLike {
+                event_type: event_type.into(),
+                msgtype: Some(msgtype.to_owned()),
+            },
+            None => Self::MessageLike { event_type: filter.into(), msgtype: None },
+        }
+    }
+
+    fn state_from_capability(filter: &str) -> Self {
+        match filter.split_once('#') {
+            Some((event_type, state_key)) => {
+                Self::State { event_type: event_type.into(), state_key: Some(state_key.to_owned()) }
+            }
+            None => Self::State { event_type: filter.into(), state_key: None },
+        }
+    }
+
+    fn capability_suffix(&self) -> String {
+        match self {
+            Self::MessageLike { event_type, msgtype: Some(msgtype) } => {
+                format!("{event_type}#{msgtype}")
+            }
+            Self::MessageLike { event_type, msgtype: None } => event_type.to_string(),
+            Self::State { event_type, state_key: Some(state_key) } => {
+                format!("{event_type}#{state_key}")
+            }
+            Self::State { event_type, state_key: None } => event_type.to_string(),
+        }
+    }
+
+    /// Whether all the events matched by this filter are also matched by the
+    /// given one.
+    fn is_within(&self, other: &EventFilter) -> bool {
+        match (self, other) {
+            (
+                Self::MessageLike { event_type, msgtype },
+                Self::MessageLike { event_type: other_type, msgtype: other_msgtype },
+            ) => event_type == other_type && (other_msgtype.is_none() || msgtype == other_msgtype),
+            (
+                Self::State { event_type, state_key },
+                Self::State { event_type: other_type, state_key: other_state_key },
+            ) => {
+                event_type == other_type
+                    && (other_state_key.is_none() || state_key == other_state_key)
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn matches_message_like(
+        &self,
+        event_type: &MessageLikeEventType,
+        msgtype: Option<&str>,
+    ) -> bool {
+        match self {
+            Self::MessageLike { event_type: filter_type, msgtype: filter_msgtype } => {
+                filter_type == event_type
+                    && filter_msgtype.as_ref().map_or(true, |f| Some(f.as_str()) == msgtype)
+            }
+            Self::State { .. } => false,
+        }
+    }
+
+    pub(crate) fn matches_state(&self, event_type: &StateEventType, state_key: &str) -> bool {
+        match self {
+            Self::State { event_type: filter_type, state_key: filter_state_key } => {
+                filter_type == event_type
+                    && filter_state_key.as_ref().map_or(true, |f| f == state_key)
+            }
+            Self::MessageLike { .. } => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::events::{MessageLikeEventType, StateEventType};
+
+    use super::Permissions;
+
+    #[test]
+    fn capabilities_roundtrip() {
+        let capabilities = vec![
+            "org.matrix.msc2762.send.event:m.room.message#m.text".to_owned(),
+            "org.matrix.msc2762.send.state_event:org.matrix.msc3401.call.member#@alice:b.c"
+                .to_owned(),
+            "org.matrix.msc2762.receive.event:m.reaction".to_owned(),
+            "org.matrix.msc2762.receive.state_event:m.room.member".to_owned(),
+            "m.always_on_screen".to_owned(),
+        ];
+
+        let permissions = Permissions::from_capabilities(&capabilities);
+        assert_eq!(permissions.send.len(), 2);
+        assert_eq!(permissions.read.len(), 2);
+        assert_eq!(permissions.to_capabilities(), capabilities[..4]);
+    }
+
+    #[test]
+    fn send_permissions() {
+        let permissions = Permissions::from_capabilities(&[
+            "org.matrix.msc2762.send.event:m.room.message#m.text".to_owned(),
+            "org.matrix.msc2762.send.state_event:org.matrix.msc3401.call.member#@alice:b.c"
+                .to_owned(),
+        ]);
+
+        let message = MessageLikeEventType::RoomMessage;
+        assert!(permissions.can_send_message_like(&message, Some("m.text")));
+        assert!(!permissions.can_send_message_like(&message, Some("m.notice")));
+        assert!(!permissions.can_send_message_like(&MessageLikeEventType::Reaction, None));
+
+        let call_member = StateEventType::from("org.matrix.msc3401.call.member");
+        assert!(permissions.can_send_state(&call_member, "@alice:b.c"));
+        assert!(!permissions.can_send_state(&call_member, "@bob:b.c"));
+    }
+
+    #[test]
+    fn read_permissions() {
+        let permissions = Permissions::from_capabilities(&[
+            "org.matrix.msc2762.receive.event:m.room.message#m.text".to_owned(),
+            "org.matrix.msc2762.receive.state_event:m.room.member".to_owned(),
+        ]);
+
+        assert!(permissions.can_read_type("m.room.message", false));
+        assert!(!permissions.can_read_type("m.room.message", true));
+        assert!(permissions.can_read_type("m.room.member", true));
+
+        let message = MessageLikeEventType::RoomMessage;
+        assert!(permissions.can_read_message_like(&message, Some("m.text")));
+        assert!(!permissions.can_read_message_like(&message, Some("m.image")));
+        assert!(!permissions.can_send_message_like(&message, Some("m.text")));
+
+        assert!(permissions.can_read_state(&StateEventType::RoomMember, "@alice:b.c"));
+        assert!(!permissions.can_read_state(&StateEventType::RoomName, ""));
+    }
+
+    #[test]
+    fn extend_permissions() {
+        let mut permissions = Permissions::from_capabilities(&[
+            "org.matrix.msc2762.receive.event:m.reaction".to_owned(),
+        ]);
+        permissions.extend(Permissions::from_capabilities(&[
+            "org.matrix.msc2762.receive.event:m.reaction".to_owned(),
+            "org.matrix.msc2762.send.event:m.reaction".to_owned(),
+        ]));
+
+        assert_eq!(
+            permissions.to_capabilities(),
+            [
+                "org.matrix.msc2762.send.event:m.reaction",
+                "org.matrix.msc2762.receive.event:m.reaction",
+            ]
+        );
+    }
+
+    #[test]
+    fn restrict_permissions() {
+        let mut permissions = Permissions::from_capabilities(&[
+            "org.matrix.msc2762.send.event:m.room.message#m.text".to_owned(),
+            "org.matrix.msc2762.send.event:m.reaction".to_owned(),
+            "org.matrix.msc2762.receive.state_event:m.room.member#@alice:b.c".to_owned(),
+            "org.matrix.msc2762.receive.state_event:m.room.name".to_owned(),
+        ]);
+        permissions.restrict_to(&Permissions::from_capabilities(&[
+            "org.matrix.msc2762.send.event:m.room.message".to_owned(),
+            "org.matrix.msc2762.receive.state_event:m.room.member".to_owned(),
+            "org.matrix.msc2762.receive.event:m.room.name".to_owned(),
+        ]));
+
+        assert_eq!(
+            permissions.to_capabilities(),
+            [
+                "org.matrix.msc2762.send.event:m.room.message#m.text",
+                "org.matrix.msc2762.receive.state_event:m.room.member#@alice:b.c",
+            ]
+        );
+    }
+}
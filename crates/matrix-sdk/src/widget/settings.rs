@@ -0,0 +1,131 @@
+//! Settings of a widget, and generation of the URL to load it.
+
+use url::{form_urlencoded::byte_serialize, Url};
+
+use crate::room::Room;
+
+/// Settings of a widget.
+#[derive(Clone, Debug)]
+pub struct WidgetSettings {
+    id: String,
+    init_on_load: bool,
+    raw_url: String,
+}
+
+impl WidgetSettings {
+    /// Create the settings of a widget.
+    ///
+    /// The `raw_url` can contain placeholders, like `$matrix_user_id`, that
+    /// are replaced when generating the URL with [`Self::generate_url`].
+    pub fn new(id: String, init_on_load: bool, raw_url: String) -> Self {
+        Self { id, init_on_load, raw_url }
+    }
+
+    /// The identifier of the widget.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether the widget API should be initialized when the widget sends the
+    /// `content_loaded` message, rather than when it is attached.
+    pub fn init_on_load(&self) -> bool {
+        self.init_on_load
+    }
+
+    /// The URL of the widget, with its placeholders.
+    pub fn raw_url(&self) -> &str {
+        &self.raw_url
+    }
+
+    /// Generate the URL to load the widget in the given room, by replacing
+    /// the placeholders of the raw URL.
+    pub fn generate_url(
+        &self,
+        room: &Room,
+        props: &ClientProperties,
+    ) -> Result<Url, url::ParseError> {
+        let values = UrlValues {
+            widget_id: &self.id,
+            user_id: room.own_user_id().as_str(),
+            room_id: room.room_id().as_str(),
+            device_id: room.client().device_id().map(|d| d.as_str()).unwrap_or_default(),
+            client_id: &props.client_id,
+            language: props.language.as_deref().unwrap_or_default(),
+            theme: props.theme.as_deref().unwrap_or_default(),
+        };
+
+        Url::parse(&replace_placeholders(&self.raw_url, &values))
+    }
+}
+
+/// Properties of the client embedding a widget, that can be passed to the
+/// widget in its URL.
+#[derive(Clone, Debug, Default)]
+pub struct ClientProperties {
+    /// The identifier of the client, like `io.element.elementx`.
+    pub client_id: String,
+    /// The language of the client, as a BCP 47 tag.
+    pub language: Option<String>,
+    /// The theme of the client, like `light` or `dark`.
+    pub theme: Option<String>,
+}
+
+/// The values of the placeholders in the URL of a widget.
+struct UrlValues<'a> {
+    widget_id: &'a str,
+    user_id: &'a str,
+    room_id: &'a str,
+    device_id: &'a str,
+    client_id: &'a str,
+    language: &'a str,
+    theme: &'a str,
+}
+
+/// Replace the placeholders in the given URL with their URL-encoded values.
+fn replace_placeholders(raw_url: &str, values: &UrlValues<'_>) -> String {
+    let placeholders = [
+        ("$org.matrix.msc2873.widget_id", values.widget_id),
+        ("$org.matrix.msc2873.matrix_device_id", values.device_id),
+        ("$org.matrix.msc2873.client_id", values.client_id),
+        ("$org.matrix.msc2873.client_language", values.language),
+        ("$org.matrix.msc2873.client_theme", values.theme),
+        ("$matrix_user_id", values.user_id),
+        ("$matrix_room_id", values.room_id),
+    ];
+
+    placeholders.into_iter().fold(raw_url.to_owned(), |url, (placeholder, value)| {
+        url.replace(placeholder, &byte_serialize(value.as_bytes()).collect::<String>())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{replace_placeholders, UrlValues};
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let values = UrlValues {
+            widget_id: "w1",
+            user_id: "@alice:example.org",
+            room_id: "!room:example.org",
+            device_id: "ABCDEF",
+            client_id: "io.element.elementx",
+            language: "en-US",
+            theme: "dark",
+        };
+
+        let url = replace_placeholders(
+            "https://call.example.org/room#?widgetId=$org.matrix.msc2873.widget_id\
+             &userId=$matrix_user_id&roomId=$matrix_room_id\
+             &deviceId=$org.matrix.msc2873.matrix_device_id\
+             &lang=$org.matrix.msc2873.client_language&theme=$org.matrix.msc2873.client_theme",
+            &values,
+        );
+
+        assert_eq!(
+            url,
+            "https://call.example.org/room#?widgetId=w1&userId=%40alice%3Aexample.org\
+             &roomId=%21room%3Aexample.org&deviceId=ABCDEF&lang=en-US&theme=dark"
+        );
+    }
+}
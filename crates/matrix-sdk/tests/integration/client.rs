@@ -1,35 +1,62 @@
-use std::{collections::BTreeMap, time::Duration};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
-use futures_util::FutureExt;
+use eyeball_im::VectorDiff;
+use futures_util::{pin_mut, FutureExt, StreamExt};
 use matrix_sdk::{
-    config::SyncSettings,
+    config::{RequestConfig, StoreConfig, SyncSettings},
+    matrix_auth::{Session, SessionTokens},
     media::{MediaFormat, MediaRequest, MediaThumbnailSize},
     sync::RoomUpdate,
+    Client, Error, HttpError, NetworkState, RequestErrorKind, UiaaOutcome,
+};
+use matrix_sdk_base::{store::MemoryStore, RoomState, SessionMeta};
+use matrix_sdk_test::{
+    async_test, test_json, GlobalAccountDataTestEvent, JoinedRoomBuilder, SyncResponseBuilder,
+    TimelineTestEvent,
 };
-use matrix_sdk_base::RoomState;
-use matrix_sdk_test::{async_test, test_json};
 use ruma::{
-    api::client::{
-        directory::{
-            get_public_rooms,
-            get_public_rooms_filtered::{self, v3::Request as PublicRoomsFilterRequest},
+    api::{
+        client::{
+            account::whoami,
+            directory::{
+                get_public_rooms,
+                get_public_rooms_filtered::{self, v3::Request as PublicRoomsFilterRequest},
+            },
+            media::get_content_thumbnail::v3::Method,
+            push::PusherKind,
+            receipt::create_receipt::v3::ReceiptType,
+            uiaa,
         },
-        media::get_content_thumbnail::v3::Method,
-        uiaa,
+        MatrixVersion,
     },
     assign, device_id,
     directory::Filter,
-    events::room::{message::ImageMessageEventContent, ImageInfo, MediaSource},
-    mxc_uri, room_id, uint, user_id,
+    event_id,
+    events::{
+        direct::DirectEventContent,
+        receipt::ReceiptThread,
+        room::{message::ImageMessageEventContent, ImageInfo, MediaSource},
+    },
+    mxc_uri,
+    push::PushFormat,
+    room_alias_id, room_id,
+    space::SpaceRoomJoinRule,
+    thirdparty, uint, user_id,
 };
 use serde_json::json;
 use wiremock::{
-    matchers::{header, method, path, path_regex},
+    matchers::{body_partial_json, header, method, path, path_regex, query_param},
     Mock, ResponseTemplate,
 };
 
-use crate::{logged_in_client, mock_sync, no_retry_test_client};
+use crate::{
+    logged_in_client, mock_sync, no_retry_test_client, synced_client, test_client_builder,
+};
 
 #[async_test]
 async fn sync() {
@@ -57,6 +84,307 @@ async fn devices() {
     client.devices().await.unwrap();
 }
 
+#[async_test]
+async fn own_devices_observable() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/devices"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::DEVICES))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path("/_matrix/client/r0/devices/BNYQQWUMXO"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .mount(&server)
+        .await;
+
+    let subscriber = client.subscribe_to_own_devices();
+    assert!(subscriber.get().is_none());
+
+    client.devices().await.unwrap();
+    let devices = subscriber.get().unwrap();
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].last_seen_ip.as_deref(), Some("-"));
+    assert!(devices[0].last_seen_ts.is_some());
+
+    client.rename_device(device_id!("BNYQQWUMXO"), "Renamed").await.unwrap();
+    let devices = subscriber.get().unwrap();
+    assert_eq!(devices[0].display_name.as_deref(), Some("Renamed"));
+
+    client.delete_devices(&[device_id!("LEBKSEUSNR").to_owned()], None).await.unwrap();
+    let devices = subscriber.get().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].device_id.as_str(), "BNYQQWUMXO");
+}
+
+#[async_test]
+async fn own_devices_refreshed_in_background_on_sync() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/devices"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::DEVICES))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut subscriber = client.subscribe_to_own_devices();
+    client.devices().await.unwrap();
+    assert_eq!(subscriber.next().await.unwrap().unwrap().len(), 2);
+
+    // The sync doesn't wait for the devices, so they are only returned after a
+    // delay.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/devices"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "devices": [test_json::DEVICES["devices"][0]] }))
+                .set_delay(Duration::from_millis(500)),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut sync_response = (*test_json::SYNC).clone();
+    sync_response["device_lists"]["changed"] = json!(["@example:localhost"]);
+    mock_sync(&server, sync_response, None).await;
+
+    client.sync_once(SyncSettings::new()).await.unwrap();
+    assert_eq!(subscriber.get().unwrap().len(), 2);
+
+    let devices = tokio::time::timeout(Duration::from_secs(2), subscriber.next())
+        .await
+        .expect("our own devices should have been fetched again")
+        .unwrap()
+        .unwrap();
+    assert_eq!(devices.len(), 1);
+}
+
+#[async_test]
+async fn knock() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!knock:example.org");
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"/knock/"))
+        .and(body_partial_json(json!({ "reason": "Let me in" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "room_id": room_id })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.knock(room_id.into(), Some("Let me in".to_owned()), &[]).await.unwrap();
+
+    assert_eq!(room.state(), RoomState::Knocked);
+    assert_eq!(client.get_room(room_id).unwrap().state(), RoomState::Knocked);
+}
+
+#[async_test]
+async fn room_preview_from_hierarchy() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"/directory/room/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "room_id": "!preview:example.org",
+            "servers": ["example.org"],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"/rooms/.*/hierarchy"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "rooms": [{
+                "room_id": "!preview:example.org",
+                "canonical_alias": "#preview:example.org",
+                "name": "Preview",
+                "topic": "A room to preview",
+                "num_joined_members": 12,
+                "world_readable": false,
+                "guest_can_join": false,
+                "join_rule": "knock",
+                "children_state": [],
+            }],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let preview =
+        client.get_room_preview(room_alias_id!("#preview:example.org").into()).await.unwrap();
+
+    assert_eq!(preview.room_id.as_str(), "!preview:example.org");
+    assert_eq!(preview.name.as_deref(), Some("Preview"));
+    assert_eq!(preview.topic.as_deref(), Some("A room to preview"));
+    assert_eq!(preview.num_joined_members, 12);
+    assert_eq!(preview.join_rule, SpaceRoomJoinRule::Knock);
+    assert!(!preview.is_world_readable);
+    assert!(preview.is_encrypted.is_none());
+    assert!(preview.state.is_none());
+}
+
+#[async_test]
+async fn room_preview_from_peeking() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"/rooms/.*/hierarchy"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Unknown room",
+        })))
+        .mount(&server)
+        .await;
+
+    // The timeline is in reverse chronological order, the latest name wins.
+    Mock::given(method("GET"))
+        .and(path_regex(r"/rooms/.*/messages$"))
+        .and(query_param("dir", "b"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t392-516_47314_0_7_1_1_1_11444_1",
+            "chunk": [
+                {
+                    "content": { "name": "Peeked" },
+                    "event_id": "$name",
+                    "origin_server_ts": 5,
+                    "room_id": "!peek:example.org",
+                    "sender": "@bob:example.org",
+                    "state_key": "",
+                    "type": "m.room.name",
+                },
+                {
+                    "content": { "body": "Hello", "msgtype": "m.text" },
+                    "event_id": "$message",
+                    "origin_server_ts": 4,
+                    "room_id": "!peek:example.org",
+                    "sender": "@bob:example.org",
+                    "type": "m.room.message",
+                },
+                {
+                    "content": { "join_rule": "public" },
+                    "event_id": "$join_rules",
+                    "origin_server_ts": 3,
+                    "room_id": "!peek:example.org",
+                    "sender": "@bob:example.org",
+                    "state_key": "",
+                    "type": "m.room.join_rules",
+                },
+                {
+                    "content": { "algorithm": "m.megolm.v1.aes-sha2" },
+                    "event_id": "$encryption",
+                    "origin_server_ts": 2,
+                    "room_id": "!peek:example.org",
+                    "sender": "@bob:example.org",
+                    "state_key": "",
+                    "type": "m.room.encryption",
+                },
+                {
+                    "content": { "name": "Old name" },
+                    "event_id": "$old_name",
+                    "origin_server_ts": 1,
+                    "room_id": "!peek:example.org",
+                    "sender": "@bob:example.org",
+                    "state_key": "",
+                    "type": "m.room.name",
+                },
+            ],
+            "state": [
+                {
+                    "content": { "membership": "join" },
+                    "event_id": "$member",
+                    "origin_server_ts": 0,
+                    "room_id": "!peek:example.org",
+                    "sender": "@bob:example.org",
+                    "state_key": "@bob:example.org",
+                    "type": "m.room.member",
+                },
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let preview = client.get_room_preview(room_id!("!peek:example.org").into()).await.unwrap();
+
+    assert_eq!(preview.name.as_deref(), Some("Peeked"));
+    assert_eq!(preview.num_joined_members, 1);
+    assert_eq!(preview.join_rule, SpaceRoomJoinRule::Public);
+    assert!(preview.is_world_readable);
+    assert_eq!(preview.is_encrypted, Some(true));
+}
+
+#[async_test]
+async fn public_room_search() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/publicRooms"))
+        .and(body_partial_json(json!({ "since": "p2" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [{
+                "room_id": "!second:example.org",
+                "num_joined_members": 3,
+                "world_readable": false,
+                "guest_can_join": false,
+                "join_rule": "knock",
+            }],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/publicRooms"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [{
+                "room_id": "!first:example.org",
+                "name": "First room",
+                "num_joined_members": 42,
+                "world_readable": true,
+                "guest_can_join": false,
+            }],
+            "next_batch": "p2",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut search = client.public_room_search(None, Filter::new());
+    let (rooms, stream) = search.results();
+    assert!(rooms.is_empty());
+    pin_mut!(stream);
+
+    search.next_page().await.unwrap();
+    assert!(!search.is_at_last_page());
+    let diff = stream.next().await.unwrap();
+    let rooms = assert_matches!(diff, VectorDiff::Append { values } => values);
+    assert_eq!(rooms[0].room_id.as_str(), "!first:example.org");
+    assert_eq!(rooms[0].name.as_deref(), Some("First room"));
+    assert_eq!(rooms[0].joined_members, 42);
+    assert!(rooms[0].can_join_directly());
+    assert!(rooms[0].membership.is_none());
+
+    search.next_page().await.unwrap();
+    assert!(search.is_at_last_page());
+    let (rooms, _) = search.results();
+    assert_eq!(rooms.len(), 2);
+    assert!(!rooms[1].can_join_directly());
+
+    // Nothing is requested once the last page is reached.
+    search.next_page().await.unwrap();
+}
+
 #[async_test]
 async fn delete_devices() {
     let (client, server) = no_retry_test_client().await;
@@ -123,6 +451,111 @@ async fn delete_devices() {
     }
 }
 
+#[async_test]
+async fn delete_devices_with_uiaa_flow() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .and(body_partial_json(json!({
+            "auth": {
+                "type": "m.login.password",
+                "password": "wordpass",
+                "session": "vBslorikviAjxzYBASOBGfPp",
+            }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/delete_devices"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [
+                { "stages": ["m.login.password"] },
+                { "stages": ["m.login.email.identity", "m.login.dummy"] },
+            ],
+            "params": {
+                "m.login.terms": { "policies": {} },
+            },
+            "session": "vBslorikviAjxzYBASOBGfPp"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let devices = vec![device_id!("DEVICEID").to_owned()];
+    let uiaa_client = client.clone();
+
+    let outcome = client
+        .send_with_uiaa(move |auth_data| {
+            let client = uiaa_client.clone();
+            let devices = devices.clone();
+            async move { Ok(client.delete_devices(&devices, auth_data).await?) }
+        })
+        .await;
+    let mut flow = assert_matches!(outcome, Ok(UiaaOutcome::AuthRequired(flow)) => flow);
+    assert_eq!(flow.session(), Some("vBslorikviAjxzYBASOBGfPp"));
+    assert_eq!(flow.next_stages(), [uiaa::AuthType::Password, uiaa::AuthType::EmailIdentity]);
+    assert!(flow.params(&uiaa::AuthType::Terms).is_some());
+    assert!(flow.params(&uiaa::AuthType::Password).is_none());
+
+    assert_matches!(flow.submit_password("wordpass").await, Ok(Some(_)));
+}
+
+#[async_test]
+async fn deactivate_and_erase_with_uiaa_flow() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/account/deactivate"))
+        .and(body_partial_json(json!({
+            "erase": true,
+            "auth": {
+                "type": "m.login.password",
+                "identifier": {
+                    "type": "m.id.user",
+                    "user": "@example:localhost",
+                },
+                "password": "wordpass",
+                "session": "dxuOyDkQmlRqzyYwPDOXiBBj",
+            }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id_server_unbind_result": "success",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/account/deactivate"))
+        .and(body_partial_json(json!({ "erase": true })))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [
+                { "stages": ["m.login.password"] },
+            ],
+            "params": {},
+            "session": "dxuOyDkQmlRqzyYwPDOXiBBj"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let account = client.account();
+    let outcome = client
+        .send_with_uiaa(move |auth_data| {
+            let account = account.clone();
+            async move { account.deactivate_and_erase(None, auth_data).await }
+        })
+        .await;
+    let mut flow = assert_matches!(outcome, Ok(UiaaOutcome::AuthRequired(flow)) => flow);
+    assert_eq!(flow.next_stages(), [uiaa::AuthType::Password]);
+
+    assert_matches!(flow.submit_password("wordpass").await, Ok(Some(_)));
+}
+
 #[async_test]
 async fn resolve_room_alias() {
     let (client, server) = no_retry_test_client().await;
@@ -292,6 +725,75 @@ async fn get_media_content() {
     client.media().get_media_content(&request, false).await.unwrap();
 }
 
+#[async_test]
+async fn get_media_stream() {
+    let (client, server) = logged_in_client().await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Some very interesting text."))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let stream = client.media().get_media_stream(&request, 0).await.unwrap();
+    let chunks: Vec<_> = stream.map(Result::unwrap).collect().await;
+    assert_eq!(chunks.concat(), b"Some very interesting text.");
+}
+
+#[async_test]
+async fn get_media_stream_resume() {
+    let (client, server) = logged_in_client().await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .and(header("range", "bytes=10-"))
+        .respond_with(ResponseTemplate::new(206).set_body_string("interesting text."))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let stream = client.media().get_media_stream(&request, 10).await.unwrap();
+    let progress = stream.progress();
+    assert_eq!(progress.current, 10);
+    assert_eq!(progress.total, 27);
+
+    let chunks: Vec<_> = stream.map(Result::unwrap).collect().await;
+    assert_eq!(chunks.concat(), b"interesting text.");
+}
+
+#[async_test]
+async fn get_media_stream_resume_without_range_support() {
+    let (client, server) = logged_in_client().await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    // The homeserver ignores the range and sends the whole content.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Some very interesting text."))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let stream = client.media().get_media_stream(&request, 10).await.unwrap();
+    let chunks: Vec<_> = stream.map(Result::unwrap).collect().await;
+    assert_eq!(chunks.concat(), b"interesting text.");
+}
+
 #[async_test]
 async fn get_media_file() {
     let (client, server) = logged_in_client().await;
@@ -430,3 +932,725 @@ async fn request_encryption_event_before_sending() {
         "Both attempts to find out if the room is encrypted should return the same result."
     );
 }
+
+#[async_test]
+async fn subscribe_to_account_data() {
+    let (client, server) = logged_in_client().await;
+
+    let stream = client.account().subscribe_to_account_data::<DirectEventContent>();
+    pin_mut!(stream);
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_global_account_data_event(GlobalAccountDataTestEvent::Direct);
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    client.sync_once(sync_settings).await.unwrap();
+
+    let content = stream.next().now_or_never().flatten().unwrap();
+    let direct_rooms = &content[user_id!("@invited:localhost")];
+    assert_eq!(direct_rooms.as_slice(), [room_id!("!SVkFJHzfwvuaIEawgC:localhost").to_owned()]);
+}
+
+#[async_test]
+async fn pushers() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/pushers"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "pushers": [{
+                "app_display_name": "Example",
+                "app_id": "org.example.app",
+                "data": {
+                    "format": "event_id_only",
+                    "url": "https://push.example.org/_matrix/push/v1/notify",
+                },
+                "device_display_name": "My phone",
+                "kind": "http",
+                "lang": "en",
+                "pushkey": "device-push-token",
+            }],
+        })))
+        .mount(&server)
+        .await;
+
+    let pushers = client.pushers().await.unwrap();
+    assert_eq!(pushers.len(), 1);
+    assert_eq!(pushers[0].ids.pushkey, "device-push-token");
+    assert_matches!(&pushers[0].kind, PusherKind::Http(data) => {
+        assert_eq!(data.url, "https://push.example.org/_matrix/push/v1/notify");
+        assert_eq!(data.format, Some(PushFormat::EventIdOnly));
+    });
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/pushers/set"))
+        .and(body_partial_json(json!({
+            "app_id": "org.example.app",
+            "kind": null,
+            "pushkey": "device-push-token",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.delete_pusher(pushers[0].ids.clone()).await.unwrap();
+}
+
+#[async_test]
+async fn network_state() {
+    let (client, server) = synced_client().await;
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let mut network_state = client.subscribe_to_network_state();
+    assert_eq!(client.network_state(), NetworkState::Online);
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/account/whoami"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::WHOAMI))
+        .mount(&server)
+        .await;
+
+    client.whoami().await.unwrap();
+    assert_eq!(client.network_state(), NetworkState::Online);
+    assert!(network_state.next().now_or_never().is_none());
+
+    // A request that times out means that the homeserver can't be reached.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/account/whoami"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&*test_json::WHOAMI)
+                .set_delay(Duration::from_secs(5)),
+        )
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let config = RequestConfig::new().disable_retry().timeout(Duration::from_millis(100));
+    client.send(whoami::v3::Request::new(), Some(config)).await.unwrap_err();
+    assert_eq!(client.network_state(), NetworkState::Offline);
+    assert_eq!(network_state.next().now_or_never(), Some(Some(NetworkState::Offline)));
+
+    // Receipts and typing notices are not sent while offline.
+    let event_id = event_id!("$event").to_owned();
+    assert_matches!(
+        room.send_single_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, event_id).await,
+        Err(Error::Offline)
+    );
+    assert_matches!(room.typing_notice(true).await, Err(Error::Offline));
+
+    // The next successful request means that the client is back online.
+    client.whoami().await.unwrap();
+    assert_eq!(client.network_state(), NetworkState::Online);
+    assert_eq!(network_state.next().now_or_never(), Some(Some(NetworkState::Online)));
+
+    // Once the server is gone, the homeserver can't be reached anymore.
+    drop(server);
+
+    client.whoami().await.unwrap_err();
+    assert_eq!(client.network_state(), NetworkState::Offline);
+    assert_eq!(network_state.next().now_or_never(), Some(Some(NetworkState::Offline)));
+}
+
+#[async_test]
+async fn network_state_is_probed_while_offline() {
+    let (client, server) = logged_in_client().await;
+    let mut network_state = client.subscribe_to_network_state();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/account/whoami"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&*test_json::WHOAMI)
+                .set_delay(Duration::from_secs(5)),
+        )
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::VERSIONS))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let config = RequestConfig::new().disable_retry().timeout(Duration::from_millis(100));
+    client.send(whoami::v3::Request::new(), Some(config)).await.unwrap_err();
+    assert_eq!(client.network_state(), NetworkState::Offline);
+    assert_eq!(network_state.next().now_or_never(), Some(Some(NetworkState::Offline)));
+
+    // No other request is sent, but the client finds out that the homeserver
+    // can be reached again.
+    let state = tokio::time::timeout(Duration::from_secs(5), network_state.next())
+        .await
+        .expect("the client should be back online");
+    assert_eq!(state, Some(NetworkState::Online));
+    assert_eq!(client.network_state(), NetworkState::Online);
+}
+
+#[async_test]
+async fn request_observer() {
+    let (client, server) = logged_in_client().await;
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    client.add_request_observer({
+        let requests = requests.clone();
+        move |info| requests.lock().unwrap().push(info.clone())
+    });
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/account/whoami"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::WHOAMI))
+        .mount(&server)
+        .await;
+
+    client.whoami().await.unwrap();
+
+    {
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "GET");
+        assert!(requests[0].path.ends_with("/account/whoami"));
+        assert_eq!(requests[0].status, Some(200));
+        assert_eq!(requests[0].error, None);
+    }
+
+    // There's no mock for this endpoint, so the mock server returns a 404.
+    client.get_profile(user_id!("@alice:localhost")).await.unwrap_err();
+
+    {
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].status, Some(404));
+        assert_eq!(requests[1].error, Some(RequestErrorKind::Server));
+        assert_ne!(requests[0].request_id, requests[1].request_id);
+        // The user ID is not part of the path given to the observers.
+        assert!(requests[1].path.ends_with("/profile/:user_id"));
+    }
+
+    // Streaming requests are observed too.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/file"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"Some text".to_vec()))
+        .mount(&server)
+        .await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/file").to_owned()),
+        format: MediaFormat::File,
+    };
+    client.media().get_media_stream(&request, 0).await.unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[2].status, Some(200));
+    assert!(requests[2].path.ends_with("/download/:server_name/:media_id"));
+}
+
+#[async_test]
+async fn request_observer_adding_observer() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/account/whoami"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::WHOAMI))
+        .mount(&server)
+        .await;
+
+    // An observer can add another observer without deadlocking.
+    let observed = Arc::new(Mutex::new(0));
+    client.add_request_observer({
+        let client = client.clone();
+        let observed = observed.clone();
+        move |_| {
+            let observed = observed.clone();
+            client.add_request_observer(move |_| *observed.lock().unwrap() += 1);
+        }
+    });
+
+    client.whoami().await.unwrap();
+    assert_eq!(*observed.lock().unwrap(), 0);
+
+    client.whoami().await.unwrap();
+    assert_eq!(*observed.lock().unwrap(), 1);
+}
+
+#[async_test]
+async fn server_capabilities() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["r0.6.1", "v1.1"],
+            "unstable_features": {
+                "org.matrix.msc3440.stable": true,
+                "org.matrix.msc2965": false,
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/capabilities"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "capabilities": {
+                "m.change_password": { "enabled": false },
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/media/.*/config"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "m.upload.size": 50_000_000,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let capabilities = client.server_capabilities().await.unwrap();
+    assert!(capabilities.supports_version(MatrixVersion::V1_1));
+    assert!(!capabilities.supports_version(MatrixVersion::V1_2));
+    assert!(capabilities.supports_msc(3440));
+    assert!(capabilities.supports_unstable_feature("org.matrix.msc3440.stable"));
+    assert!(!capabilities.supports_msc(2965));
+    assert!(!capabilities.can_change_password());
+    assert_eq!(capabilities.max_upload_size(), Some(50_000_000));
+
+    // The capabilities are cached, the endpoints are only called once.
+    let capabilities = client.server_capabilities().await.unwrap();
+    assert!(capabilities.supports_msc(3440));
+}
+
+#[async_test]
+async fn identity_server_lookup_refreshes_pepper() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/openid/request_token$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "OpenIdToken",
+            "token_type": "Bearer",
+            "matrix_server_name": "localhost",
+            "expires_in": 3600,
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/identity/v2/account/register"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "token": "IdToken" })))
+        .mount(&server)
+        .await;
+
+    assert!(client.identity_server().await.unwrap().is_none());
+    client.set_identity_server(server.uri().parse().unwrap()).await.unwrap();
+    let identity_server = client.identity_server().await.unwrap().unwrap();
+    assert_eq!(identity_server.access_token(), "IdToken");
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/identity/v2/hash_details"))
+        .and(header("authorization", "Bearer IdToken"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "lookup_pepper": "old_pepper",
+            "algorithms": ["none", "sha256"],
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/identity/v2/hash_details"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "lookup_pepper": "new_pepper",
+            "algorithms": ["none", "sha256"],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/identity/v2/lookup"))
+        .and(body_partial_json(json!({ "pepper": "old_pepper" })))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "errcode": "M_INVALID_PEPPER",
+            "error": "Unknown or invalid pepper - has it been rotated?",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/identity/v2/lookup"))
+        .and(body_partial_json(json!({ "pepper": "new_pepper", "algorithm": "sha256" })))
+        .respond_with(|request: &wiremock::Request| {
+            let body: serde_json::Value = request.body_json().unwrap();
+            let addresses = body["addresses"].as_array().unwrap();
+            assert_eq!(addresses.len(), 2);
+            let alice_key = addresses[0].as_str().unwrap();
+            assert!(!alice_key.contains("example.org"));
+
+            ResponseTemplate::new(200).set_body_json(json!({
+                "mappings": { alice_key: "@alice:localhost" },
+            }))
+        })
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let threepids = [
+        (thirdparty::Medium::Email, "alice@example.org".to_owned()),
+        (thirdparty::Medium::Email, "bob@example.org".to_owned()),
+    ];
+    let matches = identity_server.lookup(&threepids).await.unwrap();
+
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].user_id, "@alice:localhost");
+    assert!(matches[0].address.ends_with("@example.org"));
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/identity/v2/logout"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.unset_identity_server().await.unwrap();
+    assert!(client.identity_server().await.unwrap().is_none());
+}
+
+#[async_test]
+async fn identity_server_is_persisted() {
+    let (builder, server) = test_client_builder().await;
+    let store = Arc::new(MemoryStore::new());
+    let session = Session {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: SessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+
+    let client = builder
+        .store_config(StoreConfig::new().state_store(store.clone()))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    client.restore_session(session.clone()).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/openid/request_token$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "OpenIdToken",
+            "token_type": "Bearer",
+            "matrix_server_name": "localhost",
+            "expires_in": 3600,
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/identity/v2/account/register"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "token": "IdToken" })))
+        .mount(&server)
+        .await;
+
+    client.set_identity_server(server.uri().parse().unwrap()).await.unwrap();
+
+    // The identity server is loaded from the store by a new client.
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .store_config(StoreConfig::new().state_store(store))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    client.restore_session(session).await.unwrap();
+
+    let identity_server = client.identity_server().await.unwrap().unwrap();
+    assert_eq!(identity_server.access_token(), "IdToken");
+
+    // The identity server is kept if the logout fails.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/identity/v2/logout"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.unset_identity_server().await.unwrap_err();
+    assert!(client.identity_server().await.unwrap().is_some());
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/identity/v2/logout"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.unset_identity_server().await.unwrap();
+    assert!(client.identity_server().await.unwrap().is_none());
+    assert!(client.store().get_custom_value(b"identity_server.session").await.unwrap().is_none());
+}
+
+#[async_test]
+async fn find_or_create_dm_creates_an_encrypted_dm() {
+    let (client, server) = logged_in_client().await;
+    let user_id = user_id!("@bob:localhost");
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Account data not found",
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/createRoom"))
+        .and(body_partial_json(json!({
+            "invite": [user_id],
+            "is_direct": true,
+            "initial_state": [{
+                "type": "m.room.encryption",
+                "state_key": "",
+                "content": { "algorithm": "m.megolm.v1.aes-sha2" },
+            }],
+        })))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "room_id": "!dm:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$"))
+        .and(body_partial_json(json!({ "@bob:localhost": ["!dm:localhost"] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.find_or_create_dm(user_id).await.unwrap();
+    assert_eq!(room.room_id(), room_id!("!dm:localhost"));
+}
+
+#[async_test]
+async fn find_or_create_dm_finds_a_dm_that_was_not_synced() {
+    let (client, server) = logged_in_client().await;
+    let user_id = user_id!("@bob:localhost");
+
+    // The most recent DM was left by Bob, so the previous one is used.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "@bob:localhost": ["!dm:localhost", "!left:localhost"],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.member/.*example"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "membership": "join" })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*left.*/state/m.room.member/.*bob"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "membership": "leave" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*dm.*/state/m.room.member/.*bob"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "membership": "invite" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/createRoom"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "room_id": "!new:localhost" })),
+        )
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let room = client.find_or_create_dm(user_id).await.unwrap();
+    assert_eq!(room.room_id(), room_id!("!dm:localhost"));
+    assert_eq!(room.state(), RoomState::Joined);
+}
+
+#[async_test]
+async fn find_or_create_dm_fails_if_the_dm_is_not_marked() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Account data not found",
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/createRoom"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "room_id": "!dm:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.find_or_create_dm(user_id!("@bob:localhost")).await.unwrap_err();
+}
+
+#[async_test]
+async fn mark_all_as_read() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .cache_room_events()
+        .build()
+        .await
+        .unwrap();
+
+    let session = Session {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: SessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    client.restore_session(session).await.unwrap();
+
+    let room_id = room_id!("!test:localhost");
+    let empty_room_id = room_id!("!empty:localhost");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder
+        .add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+            TimelineTestEvent::Custom(json!({
+                "content": { "body": "Latest", "msgtype": "m.text" },
+                "event_id": "$latest:localhost",
+                "origin_server_ts": 152037280,
+                "sender": "@alice:localhost",
+                "type": "m.room.message",
+            })),
+        ))
+        .add_joined_room(JoinedRoomBuilder::new(empty_room_id));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // The latest events are found in the event cache.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/read_markers$"))
+        .and(body_partial_json(json!({
+            "m.fully_read": "$latest:localhost",
+            "m.read": "$latest:localhost",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room_ids = [
+        room_id.to_owned(),
+        // Rooms without known events are skipped.
+        empty_room_id.to_owned(),
+        // Unknown rooms are ignored.
+        room_id!("!unknown:localhost").to_owned(),
+    ];
+    let errors = client.mark_all_as_read(&room_ids).await;
+    assert!(errors.is_empty());
+
+    let room = client.get_room(room_id).unwrap();
+    assert_eq!(room.unread_notification_counts().notification_count, 0);
+    assert_eq!(room.unread_counts().num_unread_messages, 0);
+}
+
+#[async_test]
+async fn read_only() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .read_only()
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    assert!(client.is_read_only());
+
+    let session = Session {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: SessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    client.restore_session(session).await.unwrap();
+
+    // Syncing is allowed.
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // Sending anything to the room isn't.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/(send|typing)/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let error = room.typing_notice(true).await.unwrap_err();
+    assert_matches!(error, Error::Http(HttpError::ReadOnly));
+
+    let error = room
+        .send_raw(json!({ "body": "Hello", "msgtype": "m.text" }), "m.room.message", None)
+        .await
+        .unwrap_err();
+    assert_matches!(error, Error::Http(HttpError::ReadOnly));
+
+    // The state key can't be used to pass for an allowed endpoint.
+    let error = room.send_state_event_raw(json!({}), "m.room.topic", "login").await.unwrap_err();
+    assert_matches!(error, Error::Http(HttpError::ReadOnly));
+}
@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use assert_matches::assert_matches;
+use futures_util::StreamExt;
+use matrix_sdk::{
+    client_manager::{ClientManager, ClientManagerError, SyncPriority},
+    config::RequestConfig,
+    matrix_auth::{Session, SessionTokens},
+    Client,
+};
+use matrix_sdk_base::SessionMeta;
+use matrix_sdk_test::{async_test, JoinedRoomBuilder, SyncResponseBuilder};
+use ruma::{device_id, room_id, user_id, UserId};
+use serde_json::json;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{no_retry_test_client, test_client_builder};
+
+/// Create a client for the given user, with a homeserver returning a sync
+/// response with the given number of unread notifications.
+async fn client_with_unread_count(user_id: &UserId, unread_count: u64) -> (Client, MockServer) {
+    let (builder, server) = test_client_builder().await;
+    let client =
+        builder.request_config(RequestConfig::new().disable_retry()).build().await.unwrap();
+
+    let session = Session {
+        meta: SessionMeta {
+            user_id: user_id.to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: SessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    client.restore_session(session).await.unwrap();
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id!("!room:localhost")).set_unread_notifications_count(
+            json!({ "highlight_count": 0, "notification_count": unread_count }),
+        ),
+    );
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(sync_builder.build_json_sync_response()),
+        )
+        .mount(&server)
+        .await;
+
+    (client, server)
+}
+
+#[async_test]
+async fn add_and_remove_clients() {
+    let manager = ClientManager::new();
+
+    let (client, _server) = no_retry_test_client().await;
+    assert_matches!(
+        manager.add_client(client, SyncPriority::Foreground),
+        Err(ClientManagerError::NotLoggedIn)
+    );
+
+    let alice = user_id!("@alice:localhost");
+    let (client, _alice_server) = client_with_unread_count(alice, 0).await;
+    manager.add_client(client.clone(), SyncPriority::Foreground).unwrap();
+    assert_matches!(
+        manager.add_client(client, SyncPriority::Background),
+        Err(ClientManagerError::AlreadyAdded(user_id)) => {
+            assert_eq!(user_id, alice);
+        }
+    );
+
+    assert_eq!(manager.clients().len(), 1);
+    assert!(manager.client(alice).is_some());
+    assert!(manager.set_priority(alice, SyncPriority::Background));
+
+    assert!(manager.remove_client(alice).is_some());
+    assert!(manager.client(alice).is_none());
+    assert!(!manager.set_priority(alice, SyncPriority::Foreground));
+}
+
+#[async_test]
+async fn total_unread_count() {
+    let manager = ClientManager::new();
+    manager.set_sync_stagger(Duration::from_millis(10));
+
+    let alice = user_id!("@alice:localhost");
+    let (client, _alice_server) = client_with_unread_count(alice, 3).await;
+    manager.add_client(client, SyncPriority::Foreground).unwrap();
+
+    let bob = user_id!("@bob:localhost");
+    let (client, _bob_server) = client_with_unread_count(bob, 4).await;
+    manager.add_client(client, SyncPriority::Background).unwrap();
+
+    let mut total_unread_count = manager.subscribe_to_total_unread_count();
+    manager.start_sync();
+    assert!(manager.is_syncing());
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while total_unread_count.next().await != Some(7) {}
+    })
+    .await
+    .expect("the unread counts of both accounts should be merged");
+
+    assert_eq!(manager.unread_count(alice), Some(3));
+    assert_eq!(manager.unread_count(bob), Some(4));
+
+    // Removing an account removes its unread count.
+    manager.remove_client(bob);
+    assert_eq!(manager.total_unread_count(), 3);
+
+    manager.stop_sync();
+    assert!(!manager.is_syncing());
+}
+
+#[async_test]
+async fn stop_syncing_logged_out_account() {
+    let manager = ClientManager::new();
+
+    let alice = user_id!("@alice:localhost");
+    let (client, server) = client_with_unread_count(alice, 0).await;
+    server.reset().await;
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "errcode": "M_UNKNOWN_TOKEN",
+            "error": "Invalid access token",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    manager.add_client(client, SyncPriority::Foreground).unwrap();
+
+    let mut logged_out_accounts = manager.subscribe_to_logged_out_accounts();
+    manager.start_sync();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while logged_out_accounts.next().await.is_some_and(|accounts| accounts.is_empty()) {}
+    })
+    .await
+    .expect("the account should be reported as logged out");
+    assert_eq!(manager.logged_out_accounts(), [alice.to_owned()]);
+
+    // Removing the account forgets it.
+    manager.remove_client(alice);
+    assert!(manager.logged_out_accounts().is_empty());
+}
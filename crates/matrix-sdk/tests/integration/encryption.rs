@@ -0,0 +1,422 @@
+use std::{
+    io::{Cursor, Read},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use assert_matches::assert_matches;
+use futures_util::{pin_mut, FutureExt, StreamExt};
+use matrix_sdk::{
+    config::SyncSettings,
+    crypto::{AttachmentEncryptor, OlmMachine, OutgoingRequests},
+    encryption::secret_storage::SecretStorageError,
+    media::{MediaFormat, MediaRequest},
+};
+use matrix_sdk_test::{
+    async_test, test_json, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder,
+};
+use ruma::{
+    device_id,
+    events::{
+        room::{message::RoomMessageEventContent, EncryptedFileInit, MediaSource},
+        secret::request::SecretName,
+    },
+    mxc_uri, room_id, user_id, DeviceId, UserId,
+};
+use serde_json::json;
+use tokio::time::timeout;
+use wiremock::{
+    http::Method,
+    matchers::{method, path, path_regex},
+    Mock, Request, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync};
+
+#[async_test]
+async fn secret_storage_roundtrip() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/.*/user/.*/account_data/.*"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .mount(&server)
+        .await;
+
+    let secret_storage = client.encryption().secret_storage();
+    let store = secret_storage.create_secret_store(None).await.unwrap();
+
+    // The secret is already stored with another secret storage key.
+    let other_key_data = json!({
+        "iv": "gH2iNpiETFhApvW6/FFEJQ",
+        "ciphertext": "+Sl7qBRSIMV6T3lUOPAO6Kp/S+e0yN7m",
+        "mac": "9Q5yhWnkcJAd1tGJ+SDlsW1AzmIwmU+MAfhXNKJHZGk",
+    });
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/user/.*/account_data/org.example.secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "encrypted": {
+                "other_key": other_key_data,
+            },
+        })))
+        .mount(&server)
+        .await;
+
+    let secret_name = SecretName::from("org.example.secret");
+    store.put_secret(secret_name.clone(), "It's a secret to everybody").await.unwrap();
+
+    let uploads: Vec<_> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.method == Method::Put)
+        .collect();
+
+    // The copy of the secret encrypted with the other key is kept.
+    let secret_upload = uploads
+        .iter()
+        .find(|request| request.url.path().ends_with("/account_data/org.example.secret"))
+        .unwrap();
+    let body: serde_json::Value = secret_upload.body_json().unwrap();
+    assert_eq!(body["encrypted"]["other_key"], other_key_data);
+    assert_eq!(body["encrypted"].as_object().unwrap().len(), 2);
+
+    // Serve the account data that was uploaded.
+    server.reset().await;
+    for request in uploads {
+        let body: serde_json::Value = request.body_json().unwrap();
+        Mock::given(method("GET"))
+            .and(path(request.url.path()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(&server)
+            .await;
+    }
+
+    let recovery_key = store.secret_storage_key();
+    let store = secret_storage.open_secret_store(&recovery_key).await.unwrap();
+
+    let secret = store.get_secret(secret_name).await.unwrap();
+    assert_eq!(secret.as_deref(), Some("It's a secret to everybody"));
+
+    // Secrets that aren't stored aren't found.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/user/.*/account_data/m.megolm_backup.v1"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Account data not found",
+        })))
+        .mount(&server)
+        .await;
+    assert_matches!(store.get_secret(SecretName::RecoveryKey).await, Ok(None));
+
+    // The secret store can't be opened with another key.
+    let result = secret_storage.open_secret_store("It's not the right key").await;
+    assert_matches!(result, Err(SecretStorageError::SecretStorageKey(_)));
+}
+
+#[async_test]
+async fn test_invite_shares_room_key_history() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!test:localhost");
+    let bob_id = user_id!("@bob:localhost");
+    let bob_device_id = device_id!("BOBDEVICE");
+
+    // Get the device keys and a one-time key of Bob's device.
+    let bob = OlmMachine::new(bob_id, bob_device_id).await;
+    let bob_keys = bob
+        .outgoing_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find_map(|request| match request.request() {
+            OutgoingRequests::KeysUpload(request) => Some(request.clone()),
+            _ => None,
+        })
+        .unwrap();
+    let (one_time_key_id, one_time_key) = bob_keys.one_time_keys.into_iter().next().unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/keys/upload"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::KEYS_UPLOAD))
+        .mount(&server)
+        .await;
+
+    // Only Bob has devices.
+    let bob_device_keys = json!({
+        bob_id.as_str(): {
+            bob_device_id.as_str(): bob_keys.device_keys.unwrap(),
+        },
+    });
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/keys/query"))
+        .respond_with(move |request: &Request| {
+            let body: serde_json::Value = request.body_json().unwrap();
+            let device_keys = if body["device_keys"].get(bob_id.as_str()).is_some() {
+                bob_device_keys.clone()
+            } else {
+                json!({})
+            };
+            ResponseTemplate::new(200).set_body_json(json!({ "device_keys": device_keys }))
+        })
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/keys/claim"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "one_time_keys": {
+                bob_id.as_str(): {
+                    bob_device_id.as_str(): {
+                        one_time_key_id.as_str(): one_time_key,
+                    },
+                },
+            },
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/sendToDevice/m.room.encrypted/.*"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::MEMBERS))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m.room.encrypted/.*"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/invite"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // An encrypted room where the history is visible to anyone.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Member)
+            .add_state_event(StateTestEvent::Encryption)
+            .add_state_event(StateTestEvent::HistoryVisibility),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // Sending a message creates a room key with a shared history.
+    let room = client.get_room(room_id).unwrap();
+    room.send(RoomMessageEventContent::text_plain("Hello"), None).await.unwrap();
+
+    let to_device_requests = |requests: Vec<Request>| {
+        requests
+            .into_iter()
+            .filter(|request| request.url.path().contains("/sendToDevice/"))
+            .collect::<Vec<_>>()
+    };
+    let previous_count = to_device_requests(server.received_requests().await.unwrap()).len();
+
+    room.invite_user_by_id(bob_id).await.unwrap();
+
+    // The room key was sent to Bob's device, in a single request.
+    let requests = to_device_requests(server.received_requests().await.unwrap());
+    assert_eq!(requests.len(), previous_count + 1);
+    let body: serde_json::Value = requests.last().unwrap().body_json().unwrap();
+    let messages = body["messages"].as_object().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[bob_id.as_str()].get(bob_device_id.as_str()).is_some());
+}
+
+#[async_test]
+async fn get_encrypted_media_stream() {
+    let (client, server) = logged_in_client().await;
+
+    let mut cursor = Cursor::new(b"Some very secret text.".to_vec());
+    let mut encryptor = AttachmentEncryptor::new(&mut cursor);
+    let mut encrypted = Vec::new();
+    encryptor.read_to_end(&mut encrypted).unwrap();
+    let info = encryptor.finish();
+
+    let file = EncryptedFileInit {
+        url: mxc_uri!("mxc://localhost/secretfile").to_owned(),
+        key: info.key,
+        iv: info.iv,
+        hashes: info.hashes,
+        v: info.version,
+    };
+    let request = MediaRequest {
+        source: MediaSource::Encrypted(Box::new(file.into())),
+        format: MediaFormat::File,
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/secretfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(encrypted.clone()))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    let stream = client.media().get_media_stream(&request, 7).await.unwrap();
+    let chunks: Vec<_> = stream.map(Result::unwrap).collect().await;
+    assert_eq!(chunks.concat(), b"very secret text.");
+
+    // The content was tampered with.
+    encrypted[0] ^= 1;
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/secretfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(encrypted))
+        .mount(&server)
+        .await;
+
+    let stream = client.media().get_media_stream(&request, 0).await.unwrap();
+    let items: Vec<_> = stream.collect().await;
+    assert!(items.last().unwrap().is_err());
+}
+
+/// Get the public cross-signing keys of a new identity of the given user, in
+/// the format of a `/keys/query` response.
+async fn new_cross_signing_keys(user_id: &UserId, device_id: &DeviceId) -> serde_json::Value {
+    let machine = OlmMachine::new(user_id, device_id).await;
+    let (request, _) = machine.bootstrap_cross_signing(false).await.unwrap();
+
+    json!({
+        "master_keys": { user_id.as_str(): request.master_key },
+        "self_signing_keys": { user_id.as_str(): request.self_signing_key },
+    })
+}
+
+#[async_test]
+async fn test_identity_violations_are_updated_in_rooms_of_the_user() {
+    let (client, server) = logged_in_client().await;
+    let room_with_bob_id = room_id!("!bob:localhost");
+    let other_room_id = room_id!("!other:localhost");
+    let bob_id = user_id!("@bob:localhost");
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/keys/upload"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::KEYS_UPLOAD))
+        .mount(&server)
+        .await;
+
+    let bob_keys =
+        Arc::new(Mutex::new(new_cross_signing_keys(bob_id, device_id!("BOBDEVICE")).await));
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/keys/query"))
+        .respond_with({
+            let bob_keys = bob_keys.clone();
+            move |request: &Request| {
+                let body: serde_json::Value = request.body_json().unwrap();
+                let mut response = json!({ "device_keys": {} });
+                if body["device_keys"].get(bob_id.as_str()).is_some() {
+                    let bob_keys = bob_keys.lock().unwrap();
+                    response["master_keys"] = bob_keys["master_keys"].clone();
+                    response["self_signing_keys"] = bob_keys["self_signing_keys"].clone();
+                }
+                ResponseTemplate::new(200).set_body_json(response)
+            }
+        })
+        .mount(&server)
+        .await;
+
+    // Bob is only a member of one of the encrypted rooms.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder
+        .add_joined_room(
+            JoinedRoomBuilder::new(room_with_bob_id)
+                .add_state_event(StateTestEvent::Member)
+                .add_state_event(StateTestEvent::Encryption)
+                .add_state_event(StateTestEvent::Custom(json!({
+                    "content": { "membership": "join" },
+                    "event_id": "$bob_join",
+                    "origin_server_ts": 151800140,
+                    "sender": bob_id,
+                    "state_key": bob_id,
+                    "type": "m.room.member",
+                }))),
+        )
+        .add_joined_room(
+            JoinedRoomBuilder::new(other_room_id)
+                .add_state_event(StateTestEvent::Member)
+                .add_state_event(StateTestEvent::Encryption),
+        );
+    let sync_response = ev_builder.build_json_sync_response();
+    let sync_token = sync_response["next_batch"].as_str().unwrap().to_owned();
+    mock_sync(&server, sync_response, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // The first identity of Bob is pinned, so there is no violation.
+    let identity = client.encryption().get_user_identity(bob_id).await.unwrap().unwrap();
+    assert!(!identity.has_pin_violation());
+
+    let room_with_bob = client.get_room(room_with_bob_id).unwrap();
+    let (violations, room_with_bob_stream) =
+        room_with_bob.subscribe_to_identity_violations().await.unwrap();
+    pin_mut!(room_with_bob_stream);
+    assert!(violations.is_empty());
+
+    let other_room = client.get_room(other_room_id).unwrap();
+    let (violations, other_room_stream) =
+        other_room.subscribe_to_identity_violations().await.unwrap();
+    pin_mut!(other_room_stream);
+    assert!(violations.is_empty());
+
+    // Bob resets his identity.
+    *bob_keys.lock().unwrap() = new_cross_signing_keys(bob_id, device_id!("BOBDEVICE2")).await;
+    let mut sync_response = ev_builder.build_json_sync_response();
+    sync_response["device_lists"]["changed"] = json!([bob_id]);
+    mock_sync(&server, sync_response, Some(sync_token.clone())).await;
+    client.sync_once(SyncSettings::default().token(sync_token)).await.unwrap();
+
+    // Only the room where Bob is a member is updated.
+    let violations =
+        timeout(Duration::from_secs(1), room_with_bob_stream.next()).await.unwrap().unwrap();
+    assert_eq!(violations, vec![bob_id.to_owned()]);
+    assert!(other_room_stream.next().now_or_never().is_none());
+
+    // Accepting the new identity resolves the violation.
+    let identity = client.encryption().get_user_identity(bob_id).await.unwrap().unwrap();
+    identity.pin_current_master_key().await.unwrap();
+    let violations =
+        timeout(Duration::from_secs(1), room_with_bob_stream.next()).await.unwrap().unwrap();
+    assert!(violations.is_empty());
+    assert!(other_room_stream.next().now_or_never().is_none());
+}
+
+#[async_test]
+async fn test_share_history_with_non_member() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!test:localhost");
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::MEMBERS))
+        .mount(&server)
+        .await;
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Member)
+            .add_state_event(StateTestEvent::Encryption)
+            .add_state_event(StateTestEvent::HistoryVisibility),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // Bob is neither invited nor joined, so the room keys are not shared with
+    // him.
+    let room = client.get_room(room_id).unwrap();
+    let bob_id = user_id!("@bob:localhost");
+    assert_matches!(
+        room.share_history_with(bob_id).await,
+        Err(matrix_sdk::Error::NotARoomMember(user_id)) if user_id == bob_id
+    );
+}
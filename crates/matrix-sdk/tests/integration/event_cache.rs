@@ -0,0 +1,212 @@
+use futures_util::{pin_mut, StreamExt};
+use matrix_sdk::{
+    config::{RequestConfig, SyncSettings},
+    deserialized_responses::{SyncTimelineEvent, TimelineEvent},
+    matrix_auth::{Session, SessionTokens},
+    sync::UnreadCounts,
+    Client,
+};
+use matrix_sdk_base::SessionMeta;
+use matrix_sdk_test::{
+    async_test, EphemeralTestEvent, JoinedRoomBuilder, SyncResponseBuilder, TimelineTestEvent,
+};
+use ruma::{device_id, room_id, user_id};
+use serde_json::{json, Value as JsonValue};
+use wiremock::{
+    matchers::{method, path_regex, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync, test_client_builder};
+
+fn cached_event_ids(events: &[SyncTimelineEvent]) -> Vec<String> {
+    events.iter().map(|event| event.event_id().unwrap().to_string()).collect()
+}
+
+fn paginated_event_ids(events: &[TimelineEvent]) -> Vec<String> {
+    events.iter().map(|event| event.event.get_field("event_id").unwrap().unwrap()).collect()
+}
+
+async fn client_with_event_cache() -> (Client, MockServer) {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .cache_room_events()
+        .build()
+        .await
+        .unwrap();
+
+    let session = Session {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: SessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    client.restore_session(session).await.unwrap();
+
+    (client, server)
+}
+
+fn message_event(event_id: &str, body: &str) -> JsonValue {
+    json!({
+        "content": {
+            "body": body,
+            "msgtype": "m.text",
+        },
+        "event_id": event_id,
+        "origin_server_ts": 152037280,
+        "sender": "@alice:localhost",
+        "type": "m.room.message",
+    })
+}
+
+#[async_test]
+async fn test_event_cache_disabled_by_default() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!test:localhost");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(room_id).unwrap();
+    assert!(room.event_cache().is_none());
+}
+
+#[async_test]
+async fn test_event_cache_paginate_backwards() {
+    let (client, server) = client_with_event_cache().await;
+    let room_id = room_id!("!test:localhost");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(message_event("$b", "B")))
+            .add_timeline_event(TimelineTestEvent::Custom(message_event("$c", "C")))
+            .set_timeline_limited()
+            .set_timeline_prev_batch("prev_batch".to_owned()),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(room_id).unwrap();
+    let event_cache = room.event_cache().unwrap();
+
+    // The synced events are cached.
+    let latest_events = event_cache.latest_events(10).await.unwrap();
+    assert_eq!(cached_event_ids(&latest_events), ["$b", "$c"]);
+
+    // The cached events are returned first, without requests to the homeserver.
+    let messages = event_cache.paginate_backwards(None, 10).await.unwrap();
+    assert_eq!(paginated_event_ids(&messages.chunk), ["$c", "$b"]);
+    assert_eq!(messages.end.as_deref(), Some("$b"));
+
+    // Then the gap is filled with the homeserver.
+    let mut older_event = message_event("$a", "A");
+    older_event["room_id"] = room_id.as_str().into();
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("from", "prev_batch"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "prev_batch",
+            "chunk": [older_event],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let messages = event_cache.paginate_backwards(messages.end.as_deref(), 10).await.unwrap();
+    assert_eq!(paginated_event_ids(&messages.chunk), ["$a"]);
+    assert_eq!(messages.end, None);
+
+    // The fetched events are cached too.
+    let latest_events = event_cache.latest_events(10).await.unwrap();
+    assert_eq!(cached_event_ids(&latest_events), ["$a", "$b", "$c"]);
+}
+
+#[async_test]
+async fn test_event_cache_unread_counts() {
+    let (client, server) = client_with_event_cache().await;
+    let room_id = room_id!("!test:localhost");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(message_event("$a", "A")))
+            .add_timeline_event(TimelineTestEvent::Custom(message_event("$b", "B")))
+            .add_timeline_event(TimelineTestEvent::Custom(message_event("$c", "C"))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let sync_token = client.sync_once(SyncSettings::new()).await.unwrap().next_batch;
+
+    let room = client.get_room(room_id).unwrap();
+    assert_eq!(room.unread_counts().num_unread_messages, 3);
+
+    let unread_counts = room.subscribe_to_unread_counts();
+    pin_mut!(unread_counts);
+
+    // A read receipt of the current user marks the previous events as read.
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_ephemeral_event(
+        EphemeralTestEvent::Custom(json!({
+            "content": {
+                "$b": {
+                    "m.read": {
+                        "@example:localhost": { "ts": 152037290 },
+                    },
+                },
+            },
+            "type": "m.receipt",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    let sync_token =
+        client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap().next_batch;
+
+    assert_eq!(unread_counts.next().await.unwrap().num_unread_messages, 1);
+
+    // So does a message sent by the current user.
+    let mut own_event = message_event("$d", "D");
+    own_event["sender"] = "@example:localhost".into();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id).add_timeline_event(TimelineTestEvent::Custom(own_event)),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    let sync_token =
+        client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap().next_batch;
+
+    assert_eq!(unread_counts.next().await.unwrap(), UnreadCounts::default());
+
+    // A message in a thread is unread until a receipt is sent in that thread.
+    let mut thread_event = message_event("$e", "E");
+    thread_event["content"]["m.relates_to"] = json!({ "rel_type": "m.thread", "event_id": "$c" });
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(thread_event))
+            .add_timeline_event(TimelineTestEvent::Custom(message_event("$f", "F"))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    let sync_token =
+        client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap().next_batch;
+
+    assert_eq!(unread_counts.next().await.unwrap().num_unread_messages, 2);
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_ephemeral_event(
+        EphemeralTestEvent::Custom(json!({
+            "content": {
+                "$e": {
+                    "m.read": {
+                        "@example:localhost": { "ts": 152037291, "thread_id": "$c" },
+                    },
+                },
+            },
+            "type": "m.receipt",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap();
+
+    assert_eq!(unread_counts.next().await.unwrap().num_unread_messages, 1);
+    assert_eq!(room.unread_counts().num_unread_messages, 1);
+}
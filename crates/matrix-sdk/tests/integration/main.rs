@@ -16,6 +16,10 @@ use wiremock::{
 };
 
 mod client;
+mod client_manager;
+#[cfg(feature = "e2e-encryption")]
+mod encryption;
+mod event_cache;
 mod matrix_auth;
 mod refresh_token;
 mod room;
@@ -1,4 +1,7 @@
-use std::time::Duration;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use futures_util::StreamExt;
@@ -6,7 +9,7 @@ use matrix_sdk::{
     config::RequestConfig,
     executor::spawn,
     matrix_auth::{Session, SessionTokens},
-    HttpError, RefreshTokenError,
+    Client, Error, HttpError, RefreshTokenError,
 };
 use matrix_sdk_base::SessionMeta;
 use matrix_sdk_test::{async_test, test_json};
@@ -303,6 +306,139 @@ async fn refresh_token_handled_success() {
     changed_join_handle.await.unwrap();
 }
 
+#[async_test]
+async fn refresh_token_handled_save_session() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .server_versions([MatrixVersion::V1_3])
+        .handle_refresh_tokens()
+        .build()
+        .await
+        .unwrap();
+    client.matrix_auth().restore_session(session()).await.unwrap();
+
+    let saved_access_token = Arc::new(Mutex::new(None));
+    client
+        .set_session_callbacks(
+            Box::new(|_| Ok(session().into())),
+            Box::new({
+                let saved_access_token = saved_access_token.clone();
+                move |client: Client| {
+                    *saved_access_token.lock().unwrap() = client.access_token();
+                    Ok(())
+                }
+            }),
+        )
+        .unwrap();
+
+    // The callbacks can only be set once.
+    assert_matches!(
+        client.set_session_callbacks(Box::new(|_| Ok(session().into())), Box::new(|_| Ok(()))),
+        Err(Error::MultipleSessionCallbacks)
+    );
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/v3/refresh"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::REFRESH_TOKEN))
+        .expect(1)
+        .named("`POST /refresh`")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v3/account/whoami"))
+        .and(header(http::header::AUTHORIZATION, "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(401).set_body_json(&*test_json::UNKNOWN_TOKEN_SOFT_LOGOUT),
+        )
+        .expect(1)
+        .named("`GET /whoami` wrong token")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v3/account/whoami"))
+        .and(header(http::header::AUTHORIZATION, "Bearer 5678"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::WHOAMI))
+        .expect(1)
+        .named("`GET /whoami` good token")
+        .mount(&server)
+        .await;
+
+    client.whoami().await.unwrap();
+
+    // The refreshed session was saved.
+    assert_eq!(saved_access_token.lock().unwrap().as_deref(), Some("5678"));
+}
+
+#[async_test]
+async fn refresh_token_handled_reload_session() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .server_versions([MatrixVersion::V1_3])
+        .handle_refresh_tokens()
+        .build()
+        .await
+        .unwrap();
+    client.matrix_auth().restore_session(session()).await.unwrap();
+
+    // Another process has already refreshed the tokens.
+    let mut reloaded_session = session();
+    reloaded_session.tokens =
+        SessionTokens { access_token: "5678".to_owned(), refresh_token: Some("efgh".to_owned()) };
+
+    let save_count = Arc::new(Mutex::new(0));
+    client
+        .set_session_callbacks(
+            Box::new(move |_| Ok(reloaded_session.clone().into())),
+            Box::new({
+                let save_count = save_count.clone();
+                move |_| {
+                    *save_count.lock().unwrap() += 1;
+                    Ok(())
+                }
+            }),
+        )
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/v3/refresh"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::REFRESH_TOKEN))
+        .expect(0)
+        .named("`POST /refresh`")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v3/account/whoami"))
+        .and(header(http::header::AUTHORIZATION, "Bearer 1234"))
+        .respond_with(
+            ResponseTemplate::new(401).set_body_json(&*test_json::UNKNOWN_TOKEN_SOFT_LOGOUT),
+        )
+        .expect(1)
+        .named("`GET /whoami` wrong token")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/v3/account/whoami"))
+        .and(header(http::header::AUTHORIZATION, "Bearer 5678"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::WHOAMI))
+        .expect(1)
+        .named("`GET /whoami` good token")
+        .mount(&server)
+        .await;
+
+    client.whoami().await.unwrap();
+
+    // The reloaded tokens are used, and there is no need to save them.
+    let tokens = client.matrix_auth().session_tokens().unwrap();
+    assert_eq!(tokens.refresh_token.as_deref(), Some("efgh"));
+    assert_eq!(*save_count.lock().unwrap(), 0);
+}
+
 #[async_test]
 async fn refresh_token_handled_failure() {
     let (builder, server) = test_client_builder().await;
@@ -1,7 +1,10 @@
 use std::time::Duration;
 
 use assert_matches::assert_matches;
-use matrix_sdk::{config::SyncSettings, room::RoomMember, DisplayName, RoomMemberships};
+use matrix_sdk::{
+    config::SyncSettings, room::RoomMember, ComposerDraft, ComposerDraftType, DisplayName,
+    RoomMemberships,
+};
 use matrix_sdk_test::{
     async_test, bulk_room_members, test_json, JoinedRoomBuilder, StateTestEvent,
     SyncResponseBuilder, TimelineTestEvent,
@@ -12,7 +15,7 @@ use ruma::{
         room::member::MembershipState, AnyStateEvent, AnySyncStateEvent, AnyTimelineEvent,
         StateEventType,
     },
-    room_id,
+    room_id, uint, MilliSecondsSinceUnixEpoch,
 };
 use serde_json::json;
 use wiremock::{
@@ -556,3 +559,69 @@ async fn event() {
     assert!(push_actions.iter().any(|a| a.is_highlight()));
     assert!(push_actions.iter().any(|a| a.should_notify()));
 }
+
+#[async_test]
+async fn composer_draft() {
+    let (client, server) = logged_in_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    assert_eq!(room.load_composer_draft().await.unwrap(), None);
+
+    let draft = ComposerDraft {
+        plain_text: "Hello, world!".to_owned(),
+        html_text: None,
+        draft_type: ComposerDraftType::Edit { event_id: event_id!("$edited").to_owned() },
+    };
+    room.save_composer_draft(draft.clone()).await.unwrap();
+    assert_eq!(room.load_composer_draft().await.unwrap(), Some(draft));
+
+    room.clear_composer_draft().await.unwrap();
+    assert_eq!(room.load_composer_draft().await.unwrap(), None);
+}
+
+#[async_test]
+async fn history_cutoff() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!test:localhost");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let cutoff = room.subscribe_to_history_cutoff();
+    assert_eq!(cutoff.get(), None);
+
+    // The retention policy of the room is received.
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
+        StateTestEvent::Custom(json!({
+            "content": {
+                "max_lifetime": 3_600_000,
+            },
+            "event_id": "$retention",
+            "origin_server_ts": 151800140,
+            "sender": "@example:localhost",
+            "state_key": "",
+            "type": "m.room.retention",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+    server.reset().await;
+
+    let first_cutoff = cutoff.get().unwrap();
+    assert!(first_cutoff.get() <= MilliSecondsSinceUnixEpoch::now().get() - uint!(3_600_000));
+
+    // The cutoff moves forward with every sync, even without new events.
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    assert!(cutoff.get().unwrap() > first_cutoff);
+    assert!(room.history_cutoff().await.unwrap().is_some());
+}
@@ -1,25 +1,41 @@
-use std::time::Duration;
+use std::{
+    future,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
 
-use futures_util::future::join_all;
+use assert_matches::assert_matches;
+use futures_util::{future::join_all, pin_mut, StreamExt};
 use matrix_sdk::{
     attachment::{
-        AttachmentConfig, AttachmentInfo, BaseImageInfo, BaseThumbnailInfo, BaseVideoInfo,
-        Thumbnail,
+        AttachmentConfig, AttachmentInfo, BaseAudioInfo, BaseImageInfo, BaseThumbnailInfo,
+        BaseVideoInfo, Thumbnail,
     },
     config::SyncSettings,
-    room::Receipts,
+    executor::spawn,
+    reqwest,
+    room::{DependentRequest, PolicyRuleKind, QueuedEventContent, Receipts, RelationsOptions},
+    AppState, HttpError,
 };
 use matrix_sdk_base::RoomState;
-use matrix_sdk_test::{async_test, test_json};
+use matrix_sdk_test::{
+    async_test, test_json, EphemeralTestEvent, JoinedRoomBuilder, RoomAccountDataTestEvent,
+    StateTestEvent, SyncResponseBuilder, TimelineTestEvent,
+};
 use ruma::{
     api::client::{membership::Invite3pidInit, receipt::create_receipt::v3::ReceiptType},
     assign, event_id,
-    events::{receipt::ReceiptThread, room::message::RoomMessageEventContent},
-    mxc_uri, thirdparty, uint, user_id, TransactionId,
+    events::{
+        policy::rule::Recommendation, receipt::ReceiptThread, relation::RelationType,
+        room::message::RoomMessageEventContent, TimelineEventType,
+    },
+    int, mxc_uri, room_id, thirdparty, uint, user_id, MilliSecondsSinceUnixEpoch, TransactionId,
 };
 use serde_json::json;
+use tokio::{sync::oneshot, task::yield_now};
+use url::Url;
 use wiremock::{
-    matchers::{body_json, body_partial_json, header, method, path, path_regex},
+    matchers::{body_json, body_partial_json, header, method, path, path_regex, query_param},
     Mock, ResponseTemplate,
 };
 
@@ -80,6 +96,58 @@ async fn invite_user_by_3pid() {
     .unwrap();
 }
 
+#[async_test]
+async fn invite_by_email() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/openid/request_token$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "OpenIdToken",
+            "token_type": "Bearer",
+            "matrix_server_name": "localhost",
+            "expires_in": 3600,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/identity/v2/account/register"))
+        .and(body_partial_json(json!({ "access_token": "OpenIdToken" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "token": "IdToken" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let identity_server_url = Url::parse(&server.uri()).unwrap();
+    let identity_server = client.set_identity_server(identity_server_url.clone()).await.unwrap();
+    assert_eq!(identity_server.access_token(), "IdToken");
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/invite$"))
+        .and(body_partial_json(json!({
+            "id_server": format!(
+                "{}:{}",
+                identity_server_url.host_str().unwrap(),
+                identity_server_url.port().unwrap()
+            ),
+            "id_access_token": "IdToken",
+            "medium": "email",
+            "address": "alice@example.org",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    room.invite_by_email("alice@example.org", &identity_server).await.unwrap();
+}
+
 #[async_test]
 async fn leave_room() -> Result<(), anyhow::Error> {
     let (client, server) = logged_in_client().await;
@@ -199,6 +267,56 @@ async fn send_multiple_receipts() {
     room.send_multiple_receipts(receipts).await.unwrap();
 }
 
+#[async_test]
+async fn delay_receipts_in_background() {
+    let (client, server) = logged_in_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let _response = client.sync_once(SyncSettings::new()).await.unwrap();
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    client.set_app_state(AppState::Background);
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/receipt/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    // The receipts are kept until the app is back in the foreground.
+    let tasks: Vec<_> = [event_id!("$1:example.org"), event_id!("$2:example.org")]
+        .into_iter()
+        .map(|event_id| {
+            let room = room.clone();
+            spawn(async move {
+                room.send_single_receipt(
+                    ReceiptType::Read,
+                    ReceiptThread::Unthreaded,
+                    event_id.to_owned(),
+                )
+                .await
+            })
+        })
+        .collect();
+    yield_now().await;
+    assert!(tasks.iter().all(|task| !task.is_finished()));
+
+    // Only the latest receipt is sent, and both calls return once it's sent.
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/read_markers$"))
+        .and(body_json(json!({ "m.read": "$2:example.org" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.set_app_state(AppState::Foreground);
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+}
+
 #[async_test]
 async fn typing_notice() {
     let (client, server) = logged_in_client().await;
@@ -500,6 +618,167 @@ async fn room_attachment_send_info_thumbnail() {
     assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
 }
 
+#[async_test]
+async fn room_attachment_send_voice() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "msgtype": "m.audio",
+            "info": {
+                "mimetype": "audio/ogg",
+                "duration": 3600,
+            },
+            "org.matrix.msc1767.audio": {
+                "duration": 3600,
+                "waveform": [0, 512, 1024],
+            },
+            "org.matrix.msc3245.voice": {},
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(header("content-type", "audio/ogg"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let config = AttachmentConfig::new().info(AttachmentInfo::Voice {
+        audio_info: BaseAudioInfo { duration: Some(Duration::from_millis(3600)), size: None },
+        waveform: Some(vec![0, 512, 1024]),
+    });
+
+    let response = room
+        .send_attachment(
+            "voice.ogg",
+            &"audio/ogg".parse().unwrap(),
+            b"Hello world".to_vec(),
+            config,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+#[async_test]
+async fn room_attachment_send_idempotent() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/media/.*/create$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/media/.*/upload/example.com/AQwafuaFswefuhsfAFAgsw$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(header("content-type", "video/mp4"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "url": "mxc://example.com/AQwafuaFswefuhsfAFAgsw",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let config = AttachmentConfig::new().txn_id(&TransactionId::new());
+    let data = vec![0; 10 * 1024 * 1024];
+
+    let response = room
+        .send_attachment("video.mp4", &"video/mp4".parse().unwrap(), data, config)
+        .await
+        .unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+#[async_test]
+async fn room_attachment_send_idempotent_already_uploaded() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/media/.*/create$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .mount(&server)
+        .await;
+
+    // The media was uploaded by a previous attempt.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/media/.*/upload/example.com/AQwafuaFswefuhsfAFAgsw$"))
+        .respond_with(ResponseTemplate::new(409).set_body_json(json!({
+            "errcode": "M_CANNOT_OVERWRITE_MEDIA",
+            "error": "Media already uploaded",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_partial_json(json!({
+            "url": "mxc://example.com/AQwafuaFswefuhsfAFAgsw",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    let config = AttachmentConfig::new().txn_id(&TransactionId::new());
+    let data = vec![0; 10 * 1024 * 1024];
+
+    room.send_attachment("video.mp4", &"video/mp4".parse().unwrap(), data, config).await.unwrap();
+}
+
 #[async_test]
 async fn room_redact() {
     let (client, server) = synced_client().await;
@@ -522,6 +801,163 @@ async fn room_redact() {
     assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
 }
 
+#[async_test]
+async fn ban_and_purge() {
+    let (client, server) = synced_client().await;
+
+    let spammer = user_id!("@spammer:localhost");
+    let message = |event_id: &str, ts: u64| {
+        json!({
+            "content": { "body": "Spam", "msgtype": "m.text" },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": spammer,
+            "type": "m.room.message",
+        })
+    };
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/ban$"))
+        .and(body_partial_json(json!({ "user_id": spammer })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("from", "prev"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [message("$second", 2000), message("$first", 1000)],
+            "start": "prev",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                message("$fourth", 4000),
+                // State events are not redacted.
+                {
+                    "content": { "membership": "join" },
+                    "event_id": "$member",
+                    "origin_server_ts": 3500,
+                    "sender": spammer,
+                    "state_key": spammer,
+                    "type": "m.room.member",
+                },
+                // Events that were already redacted are skipped.
+                {
+                    "content": {},
+                    "event_id": "$third",
+                    "origin_server_ts": 3000,
+                    "sender": spammer,
+                    "type": "m.room.message",
+                    "unsigned": {
+                        "redacted_because": {
+                            "content": {},
+                            "event_id": "$redaction",
+                            "origin_server_ts": 3100,
+                            "redacts": "$third",
+                            "sender": "@example:localhost",
+                            "type": "m.room.redaction",
+                        },
+                    },
+                },
+            ],
+            "start": "latest",
+            "end": "prev",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/.*?/.*?"))
+        .and(body_json(json!({ "reason": "Spam" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let outcome = room.ban_and_purge(spammer, Some("Spam")).await.unwrap();
+    assert_eq!(outcome.redacted, ["$fourth", "$second", "$first"]);
+    assert!(outcome.failed.is_empty());
+    assert_matches!(outcome.pagination_error, None);
+}
+
+#[async_test]
+async fn redact_events_from_keeps_going_after_failures() {
+    let (client, server) = synced_client().await;
+
+    let spammer = user_id!("@spammer:localhost");
+    let message = |event_id: &str, ts: u64| {
+        json!({
+            "content": { "body": "Spam", "msgtype": "m.text" },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": spammer,
+            "type": "m.room.message",
+        })
+    };
+
+    // The second page can't be loaded.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("from", "prev"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_NOT_FOUND",
+            "error": "Unknown token",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [message("$third", 3000), message("$second", 2000), message("$first", 1000)],
+            "start": "latest",
+            "end": "prev",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The redaction of the second event fails.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/%24second/.*?"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "Not allowed",
+        })))
+        .expect(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/.*?/.*?"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let outcome =
+        room.redact_events_from(spammer, MilliSecondsSinceUnixEpoch(uint!(0)), None).await;
+
+    // The events that were redacted before and after the failure are returned.
+    assert_eq!(outcome.redacted, ["$third", "$first"]);
+    assert_eq!(outcome.failed.len(), 1);
+    assert_eq!(outcome.failed[0].0, "$second");
+    assert_matches!(outcome.pagination_error, Some(_));
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn fetch_members_deduplication() {
@@ -590,3 +1026,649 @@ async fn set_name() {
 
     room.set_name(Some(name.to_owned())).await.unwrap();
 }
+
+#[async_test]
+async fn send_queue() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new();
+    client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let send_queue = room.send_queue().await.unwrap();
+    assert!(send_queue.events().await.is_empty());
+
+    let content = RoomMessageEventContent::text_plain("Hello").into();
+    send_queue.push_event("first".into(), &content).await.unwrap();
+    send_queue
+        .push_attachment("second".into(), "/tmp/image.jpg".to_owned(), &mime::IMAGE_JPEG)
+        .await
+        .unwrap();
+    // Events with the same transaction ID are only queued once.
+    send_queue.push_event("first".into(), &content).await.unwrap();
+
+    // The queue is shared by all the instances of the room.
+    let events = room.send_queue().await.unwrap().events().await;
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].transaction_id, "first");
+    assert_matches!(&events[0].content, QueuedEventContent::Event { event_type, .. } => {
+        assert_eq!(event_type.to_string(), "m.room.message");
+    });
+    assert_eq!(events[1].transaction_id, "second");
+    assert_matches!(&events[1].content, QueuedEventContent::Attachment { path, mime_type } => {
+        assert_eq!(path, "/tmp/image.jpg");
+        assert_eq!(mime_type, "image/jpeg");
+    });
+
+    // Replacing the content of an event keeps its place in the queue.
+    let new_content = RoomMessageEventContent::text_plain("Hello, world").into();
+    assert!(send_queue.replace_event("first".into(), &new_content).await.unwrap());
+    assert!(!send_queue.replace_event("second".into(), &new_content).await.unwrap());
+    let events = send_queue.events().await;
+    assert_eq!(events[0].transaction_id, "first");
+    assert_matches!(&events[0].content, QueuedEventContent::Event { content, .. } => {
+        assert!(content.json().get().contains("Hello, world"));
+    });
+
+    assert!(send_queue.remove("first".into()).await.unwrap());
+    assert!(!send_queue.remove("first".into()).await.unwrap());
+    assert_eq!(send_queue.events().await.len(), 1);
+}
+
+#[async_test]
+async fn send_queue_dependent_requests() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new();
+    client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let send_queue = room.send_queue().await.unwrap();
+
+    let content = RoomMessageEventContent::text_plain("Hello").into();
+    send_queue.push_event("first".into(), &content).await.unwrap();
+
+    let request = DependentRequest::Redact { reason: None };
+    send_queue.push_dependent("second".into(), "first".into(), request.clone()).await.unwrap();
+    // Requests with the same transaction ID are only queued once.
+    send_queue.push_dependent("second".into(), "first".into(), request).await.unwrap();
+
+    // The request isn't one of the events of the queue.
+    assert_eq!(send_queue.events().await.len(), 1);
+    let dependents = send_queue.dependent_requests().await;
+    assert_eq!(dependents.len(), 1);
+    assert_eq!(dependents[0].transaction_id, "second");
+    assert_eq!(dependents[0].parent_transaction_id, "first");
+    assert_eq!(dependents[0].parent_event_id, None);
+
+    // Skip the delays between the retries.
+    tokio::time::pause();
+
+    // The request is kept while its parent event fails to be sent.
+    let result = send_queue
+        .send_with_retry("first".into(), || async {
+            // A request that can't be built is reported like a connectivity error.
+            let error = reqwest::Client::new().get("http://").send().await.unwrap_err();
+            Err::<(), _>(HttpError::Reqwest(error).into())
+        })
+        .await;
+    assert_matches!(result, Err(matrix_sdk::Error::Http(HttpError::Reqwest(_))));
+    assert_eq!(send_queue.dependent_requests().await.len(), 1);
+
+    // And after it was sent, until it is removed explicitly.
+    send_queue.send_with_retry("first".into(), || async { Ok(()) }).await.unwrap();
+    assert!(send_queue.events().await.is_empty());
+
+    send_queue.resolve_dependents("first".into(), event_id!("$first")).await.unwrap();
+    let dependents = room.send_queue().await.unwrap().dependent_requests().await;
+    assert_eq!(dependents.len(), 1);
+    assert_eq!(dependents[0].parent_event_id.as_deref(), Some(event_id!("$first")));
+    assert_matches!(dependents[0].request, DependentRequest::Redact { reason: None });
+
+    assert!(send_queue.remove_dependent("second".into()).await.unwrap());
+    assert!(!send_queue.remove_dependent("second".into()).await.unwrap());
+    assert!(send_queue.dependent_requests().await.is_empty());
+}
+
+#[async_test]
+async fn send_queue_abort() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new();
+    client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let send_queue = room.send_queue().await.unwrap();
+
+    let content = RoomMessageEventContent::text_plain("Hello").into();
+    send_queue.push_event("first".into(), &content).await.unwrap();
+
+    let (started_sender, started_receiver) = oneshot::channel();
+    let sending = spawn({
+        let send_queue = send_queue.clone();
+        let mut started_sender = Some(started_sender);
+        async move {
+            send_queue
+                .send_with_retry("first".into(), move || {
+                    if let Some(started_sender) = started_sender.take() {
+                        started_sender.send(()).unwrap();
+                    }
+                    future::pending::<matrix_sdk::Result<()>>()
+                })
+                .await
+        }
+    });
+    started_receiver.await.unwrap();
+
+    assert!(send_queue.abort("first".into()).await.unwrap());
+    assert_matches!(sending.await.unwrap(), Err(matrix_sdk::Error::SendAborted));
+    assert!(send_queue.events().await.is_empty());
+}
+
+#[async_test]
+async fn send_queue_marks_event_failed_after_connectivity_retries() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new();
+    client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let send_queue = room.send_queue().await.unwrap();
+
+    let content = RoomMessageEventContent::text_plain("Hello").into();
+    send_queue.push_event("first".into(), &content).await.unwrap();
+
+    // Skip the delays between the retries.
+    tokio::time::pause();
+
+    let attempts = AtomicU32::new(0);
+    let attempts_ref = &attempts;
+    let result = send_queue
+        .send_with_retry("first".into(), move || async move {
+            attempts_ref.fetch_add(1, Ordering::SeqCst);
+            // A request that can't be built is reported like a connectivity error.
+            let error = reqwest::Client::new().get("http://").send().await.unwrap_err();
+            Err::<(), _>(HttpError::Reqwest(error).into())
+        })
+        .await;
+
+    assert_matches!(result, Err(matrix_sdk::Error::Http(HttpError::Reqwest(_))));
+    assert_eq!(attempts.load(Ordering::SeqCst), 11);
+
+    // The event is kept in the queue so it can be sent later.
+    let events = send_queue.events().await;
+    assert_eq!(events.len(), 1);
+    assert!(events[0].sending_failed);
+}
+
+#[async_test]
+async fn report_content() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new();
+    client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+    let event_id = event_id!("$xxxxxx:example.org");
+    let reason = "I am offended";
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/report/.*$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({
+            "score": -100,
+            "reason": reason,
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    room.report_content(event_id.to_owned(), Some(int!(-100)), Some(reason.to_owned()))
+        .await
+        .unwrap();
+}
+
+#[async_test]
+async fn room_upgrade() {
+    let (client, server) = logged_in_client().await;
+    let mut upgrades = client.subscribe_to_room_upgrades();
+
+    let tombstone = |replacement_room: &str| {
+        json!({
+            "content": {
+                "body": "This room has been replaced",
+                "replacement_room": replacement_room,
+            },
+            "event_id": format!("$tombstone{replacement_room}"),
+            "origin_server_ts": 151957878,
+            "sender": "@example:localhost",
+            "state_key": "",
+            "type": "m.room.tombstone",
+        })
+    };
+
+    // A room that was upgraded before it was synced isn't announced.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id!("!older:localhost"))
+            .add_state_event(StateTestEvent::Custom(tombstone("!old:localhost"))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+    server.reset().await;
+
+    let room_id = room_id!("!old:localhost");
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(tombstone("!new:localhost"))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    // The upgrade is announced from a background task.
+    let upgrade = upgrades.recv().await.unwrap();
+    assert_eq!(upgrade.old_room_id, room_id);
+    assert_eq!(upgrade.new_room_id, "!new:localhost");
+    assert_eq!(upgrade.body, "This room has been replaced");
+    assert!(upgrade.new_room.is_none());
+}
+
+#[async_test]
+async fn subscribe_to_typing() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!typing:localhost");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
+        StateTestEvent::Custom(json!({
+            "content": {
+                "displayname": "Alice",
+                "membership": "join",
+            },
+            "event_id": "$alice_join",
+            "origin_server_ts": 151800140,
+            "sender": "@alice:localhost",
+            "state_key": "@alice:localhost",
+            "type": "m.room.member",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let sync_token = client.sync_once(SyncSettings::new()).await.unwrap().next_batch;
+
+    let room = client.get_room(room_id).unwrap();
+    let typing = room.subscribe_to_typing();
+    pin_mut!(typing);
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_ephemeral_event(
+        EphemeralTestEvent::Custom(json!({
+            "content": {
+                "user_ids": ["@alice:localhost", "@example:localhost"],
+            },
+            "type": "m.typing",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    let sync_token =
+        client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap().next_batch;
+
+    // The own user is filtered out.
+    let members = typing.next().await.unwrap();
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].user_id(), "@alice:localhost");
+    assert_eq!(members[0].display_name(), Some("Alice"));
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_ephemeral_event(
+        EphemeralTestEvent::Custom(json!({
+            "content": {
+                "user_ids": [],
+            },
+            "type": "m.typing",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap();
+
+    assert!(typing.next().await.unwrap().is_empty());
+}
+
+#[async_test]
+async fn favourite_and_low_priority_tags() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!tags:localhost");
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let sync_token = client.sync_once(SyncSettings::new()).await.unwrap().next_batch;
+
+    let room = client.get_room(room_id).unwrap();
+    assert!(!room.is_favourite());
+    assert!(!room.is_low_priority());
+
+    let is_favourite = room.subscribe_to_is_favourite();
+    pin_mut!(is_favourite);
+
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_account_data(
+        RoomAccountDataTestEvent::Custom(json!({
+            "content": {
+                "tags": {
+                    "m.favourite": { "order": 0.5 },
+                },
+            },
+            "type": "m.tag",
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap();
+
+    assert!(is_favourite.next().await.unwrap());
+    assert!(room.is_favourite());
+    assert!(!room.is_low_priority());
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/rooms/.*/tags/m.lowpriority$"))
+        .and(body_json(json!({ "order": 0.1 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("DELETE"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/rooms/.*/tags/m.favourite$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    room.set_is_low_priority(true, Some(0.1)).await.unwrap();
+}
+
+#[async_test]
+async fn live_location_share() {
+    let (client, server) = synced_client().await;
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    mock_encryption_state(&server, false).await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/org.matrix.msc3672.beacon_info/.*"))
+        .and(body_partial_json(json!({ "live": true, "timeout": 60000 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let share = room.start_live_location_share(Duration::from_secs(60)).await.unwrap();
+    assert_eq!(share.beacon_info_event_id(), event_id!("$h29iv0s8:example.com"));
+    assert!(share.is_live());
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/org.matrix.msc3672.beacon/"))
+        .and(body_partial_json(json!({
+            "m.relates_to": {
+                "rel_type": "m.reference",
+                "event_id": "$h29iv0s8:example.com",
+            },
+            "org.matrix.msc3488.location": {
+                "uri": "geo:51.5008,0.1247;u=35",
+            },
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    share.update_location("geo:51.5008,0.1247;u=35".to_owned()).await.unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/org.matrix.msc3672.beacon_info/.*"))
+        .and(body_partial_json(json!({ "live": false })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    share.stop().await.unwrap();
+}
+
+#[async_test]
+async fn legacy_policy_rules() {
+    let (client, server) = logged_in_client().await;
+
+    let room_id = room_id!("!bans:localhost");
+    let subscription = client.subscribe_to_policy_lists(&[room_id]).await.unwrap();
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "entity": "@spammer:example.org",
+                    "recommendation": "m.ban",
+                    "reason": "spam",
+                },
+                "event_id": "$rule_user",
+                "origin_server_ts": 151957878,
+                "sender": "@example:localhost",
+                "state_key": "rule:@spammer:example.org",
+                "type": "m.room.rule.user",
+            })))
+            .add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "entity": "!spam*:example.org",
+                    "recommendation": "m.ban",
+                    "reason": "spam room",
+                },
+                "event_id": "$rule_room",
+                "origin_server_ts": 151957878,
+                "sender": "@example:localhost",
+                "state_key": "rule:!spam*:example.org",
+                "type": "org.matrix.mjolnir.rule.room",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(room_id).unwrap();
+    assert_eq!(room.policy_rules().await.unwrap().len(), 2);
+    assert_eq!(subscription.rules().len(), 2);
+
+    let rule = subscription.match_user(user_id!("@spammer:example.org")).unwrap();
+    assert_eq!(rule.kind, PolicyRuleKind::User);
+    let rule = subscription.match_room(room_id!("!spammy:example.org")).unwrap();
+    assert_eq!(rule.kind, PolicyRuleKind::Room);
+    assert_eq!(rule.reason, "spam room");
+
+    // The rules are also loaded from the store.
+    let subscription = client.subscribe_to_policy_lists(&[room_id]).await.unwrap();
+    assert_eq!(subscription.rules().len(), 2);
+}
+
+#[async_test]
+async fn policy_lists() {
+    let (client, server) = logged_in_client().await;
+
+    let room_id = room_id!("!bans:localhost");
+    let subscription = client.subscribe_to_policy_lists(&[room_id]).await.unwrap();
+    assert!(subscription.rules().is_empty());
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "entity": "@spammer:example.org",
+                    "recommendation": "m.ban",
+                    "reason": "spam",
+                },
+                "event_id": "$rule_user",
+                "origin_server_ts": 151957878,
+                "sender": "@example:localhost",
+                "state_key": "rule:@spammer:example.org",
+                "type": "m.policy.rule.user",
+            })))
+            .add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "entity": "*.evil.org",
+                    "recommendation": "m.ban",
+                    "reason": "spam server",
+                },
+                "event_id": "$rule_server",
+                "origin_server_ts": 151957878,
+                "sender": "@example:localhost",
+                "state_key": "rule:*.evil.org",
+                "type": "m.policy.rule.server",
+            }))),
+    );
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let sync_token = client.sync_once(SyncSettings::new()).await.unwrap().next_batch;
+
+    let room = client.get_room(room_id).unwrap();
+    assert_eq!(room.policy_rules().await.unwrap().len(), 2);
+    assert_eq!(subscription.rules().len(), 2);
+
+    let rule = subscription.match_user(user_id!("@spammer:example.org")).unwrap();
+    assert_eq!(rule.kind, PolicyRuleKind::User);
+    assert_eq!(rule.recommendation, Recommendation::Ban);
+    assert_eq!(rule.reason, "spam");
+
+    // The rules on the server of a user also apply to them.
+    let rule = subscription.match_user(user_id!("@someone:matrix.evil.org")).unwrap();
+    assert_eq!(rule.kind, PolicyRuleKind::Server);
+    assert_eq!(rule.reason, "spam server");
+
+    assert!(subscription.match_user(user_id!("@alice:example.org")).is_none());
+    assert!(subscription.match_room(room_id!("!room:example.org")).is_none());
+
+    // Removing the rule in the sync removes it from the subscription.
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
+        StateTestEvent::Custom(json!({
+            "content": {},
+            "event_id": "$rule_user_removed",
+            "origin_server_ts": 151957879,
+            "sender": "@example:localhost",
+            "state_key": "rule:@spammer:example.org",
+            "type": "m.policy.rule.user",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), Some(sync_token.clone())).await;
+    client.sync_once(SyncSettings::new().token(sync_token)).await.unwrap();
+
+    assert!(subscription.match_user(user_id!("@spammer:example.org")).is_none());
+    assert_eq!(subscription.rules().len(), 1);
+    assert_eq!(room.policy_rules().await.unwrap().len(), 1);
+
+    // Adding and removing rules sends the corresponding state events.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.policy.rule.room/rule:.*"))
+        .and(body_json(json!({
+            "entity": "!room:example.org",
+            "recommendation": "m.ban",
+            "reason": "abuse",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let event_id = room
+        .add_policy_rule(PolicyRuleKind::Room, "!room:example.org", Recommendation::Ban, "abuse")
+        .await
+        .unwrap();
+    assert_eq!(event_id, event_id!("$h29iv0s8:example.com"));
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.policy.rule.server/rule:.*"))
+        .and(body_json(json!({})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    room.remove_policy_rule(PolicyRuleKind::Server, "rule:*.evil.org").await.unwrap();
+}
+
+#[async_test]
+async fn set_name_and_topic_with_local_echo() {
+    let (client, server) = synced_client().await;
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.name/$"))
+        .and(body_json(json!({ "name": "New name" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    room.set_name(Some("New name".to_owned())).await.unwrap();
+
+    // The name is updated without waiting for the event to come back via sync.
+    assert_eq!(room.name().as_deref(), Some("New name"));
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.topic/$"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You don't have permission to change the topic",
+        })))
+        .mount(&server)
+        .await;
+
+    let topic = room.topic();
+    room.set_topic("New topic").await.unwrap_err();
+
+    // The topic is restored after the request failed.
+    assert_eq!(room.topic(), topic);
+}
+
+#[async_test]
+async fn relations() {
+    let (client, server) = synced_client().await;
+    let room = client.get_room(&test_json::DEFAULT_SYNC_ROOM_ID).unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/v1/rooms/.*/relations/\$root/m.annotation/m.reaction$"))
+        .and(query_param("from", "prev_token"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [{
+                "content": {
+                    "m.relates_to": {
+                        "event_id": "$root",
+                        "key": "👍",
+                        "rel_type": "m.annotation",
+                    },
+                },
+                "event_id": "$reaction",
+                "origin_server_ts": 152037280,
+                "room_id": *test_json::DEFAULT_SYNC_ROOM_ID,
+                "sender": "@alice:example.org",
+                "type": "m.reaction",
+            }],
+            "next_batch": "next_token",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = assign!(RelationsOptions::with_rel_type(RelationType::Annotation), {
+        event_type: Some(TimelineEventType::Reaction),
+    })
+    .from("prev_token");
+    let relations = room.relations(event_id!("$root"), options).await.unwrap();
+
+    assert_eq!(relations.chunk.len(), 1);
+    assert_eq!(relations.chunk[0].event_id().as_deref(), Some(event_id!("$reaction")));
+    assert_eq!(relations.next_batch_token.as_deref(), Some("next_token"));
+    assert_eq!(relations.prev_batch_token, None);
+}